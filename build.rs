@@ -0,0 +1,64 @@
+//! Generates the instruction table (instructions::DESCRIPTORS) from src/instructions.tbl.
+use std::fmt::Write;
+use std::{env, fs, path::Path};
+
+const TABLE: &str = "src/instructions.tbl";
+const MODES: [&str; 6] = ["imm", "dir", "idx", "ext", "inh", "rel"];
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", TABLE);
+    let text = fs::read_to_string(TABLE).expect("can't read the instruction table");
+    let mut out = String::from("pub const DESCRIPTORS: &[Descriptor] = &[\n");
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let row = parse_row(line).unwrap_or_else(|e| panic!("{} line {}: {}", TABLE, i + 1, e));
+        out.push_str(&row);
+    }
+    out.push_str("];\n");
+    let path = Path::new(&env::var("OUT_DIR").unwrap()).join("descriptors.rs");
+    fs::write(path, out).expect("can't write the instruction table");
+}
+
+/// Turns a line of the table into a Descriptor
+fn parse_row(line: &str) -> Result<String, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [name, eval, reg, pbt, ot, flags, modes @ ..] = fields.as_slice() else {
+        return Err("expected mnemonic, eval, reg, post-byte, operand, flags and modes".to_string());
+    };
+    if !matches!(*pbt, "NA" | "TransferExchange" | "PushPull") {
+        return Err(format!("unknown post-byte type {}", pbt));
+    }
+    if !matches!(*ot, "None" | "Mode" | "Push" | "Exch") {
+        return Err(format!("unknown operand type {}", ot));
+    }
+    let valid_flag = |(c, f): (char, char)| c == f || "01?-".contains(c);
+    if flags.len() != 5 || !flags.chars().zip("HNZVC".chars()).all(valid_flag) {
+        return Err(format!("flags should look like HNZVC or -NZ0- (not {})", flags));
+    }
+    if modes.is_empty() {
+        return Err(format!("{} has no addressing modes", name));
+    }
+    let mut md = String::new();
+    for mode in modes {
+        let parts: Vec<&str> = mode.split(':').collect();
+        let [am, op, clk, sz] = parts.as_slice() else {
+            return Err(format!("expected mode:opcode:cycles:bytes (not {})", mode));
+        };
+        let am = MODES.iter().position(|m| m == am).ok_or(format!("unknown addressing mode {}", am))?;
+        let op = u16::from_str_radix(op, 16).map_err(|_| format!("bad opcode {}", op))?;
+        let clk: u8 = clk.parse().map_err(|_| format!("bad cycle count {}", clk))?;
+        let sz: u16 = sz.parse().map_err(|_| format!("bad size {}", sz))?;
+        let op_size = if op > 0xff { 2 } else { 1 };
+        if sz < op_size {
+            return Err(format!("{} {:X} is shorter than its opcode", name, op));
+        }
+        write!(md, "M{{op:0x{:X},clk:{},sz:{},am:{}}},", op, clk, sz, am).unwrap();
+    }
+    Ok(format!(
+        " Descriptor{{name:\"{}\", eval:__{}, reg: Name::{}, pbt: PBT::{}, ot:OT::{}, flags:\"{}\", md:&[{}]}},\n",
+        name, eval, reg, pbt, ot, flags, md
+    ))
+}
@@ -1,7 +1,9 @@
 use super::*;
+use crate::core::CPU_HZ;
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::io::prelude::*;
-use std::net::TcpListener;
+use std::net::{TcpListener, TcpStream};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -10,63 +12,263 @@ use std::time::Duration;
 // status register bits
 const RDRF: u8 = 0b00000001; // receive data register full
 const TDRE: u8 = 0b00000010; // transmit data register empty
+const OVRN: u8 = 0b00000100; // overrun (a received byte was lost)
+const IRQ: u8 = 0b10000000; // interrupt request
+// control register bits
+const RIE: u8 = 0b10000000; // receive interrupt enable
+const SBR: u8 = 0b00001111; // selected baud rate (0 is the external clock, i.e. as fast as TCP delivers)
+const WL: u8 = 0b01100000; // word length (0 is 8 bits, 3 is 5 bits)
+/// The baud rates the 6551's control register selects (the first is the external clock)
+const BAUD_RATES: [u32; 16] = [0, 50, 75, 110, 135, 150, 300, 600, 1200, 1800, 2400, 3600, 4800, 7200, 9600, 19200];
+// sent by an instance started with --acia-connect so that the listening instance passes bytes through untouched
+const NULL_MODEM_HELLO: &[u8] = b"\0COCO-NULL-MODEM\0";
+// how long a connection that has started with part of NULL_MODEM_HELLO waits for the rest
+const HELLO_WAIT: Duration = Duration::from_secs(1);
+// the ports tried (in order) when --acia-port isn't given
+const FIRST_PORT: u16 = 6809;
+const PORTS_TRIED: u16 = 16;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// How the ACIA keeps the host from sending faster than the program reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FlowControl {
+    /// bytes that arrive while the receive FIFO is full are lost (an overrun)
+    None,
+    /// the host holds its bytes while the receive FIFO is full
+    RtsCts,
+    /// XOFF goes to the host when the receive FIFO is 3/4 full and XON when it's down to 1/4;
+    /// XOFF and XON from the host pause and resume transmission
+    XonXoff,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Stats {
+    received: u64,
+    sent: u64,
+    overruns: u64, // received bytes lost because the receive FIFO was full
+    lost: u64,     // bytes written while the transmit FIFO was full
+    rx_high: usize,
+    tx_high: usize,
+}
+
+/// The receive and transmit FIFOs and the flow control between them and the host
+struct Fifos {
+    flow: FlowControl,
+    cycles_per_char: Option<u64>, // the time it takes to move one character at the selected baud rate
+    rx: VecDeque<u8>,
+    rx_depth: usize,
+    next_rx: u64, // the clock cycle at which the next character can arrive
+    tx: VecDeque<u8>,
+    tx_depth: usize,
+    next_tx: u64,        // the clock cycle at which the next character can go
+    xoff_sent: bool,     // we've asked the host to stop sending
+    xoff_received: bool, // the host has asked us to stop sending
+    overrun: bool,
+    stats: Stats,
+}
+impl Fifos {
+    fn new(flow: FlowControl, cycles_per_char: Option<u64>, rx_depth: usize, tx_depth: usize) -> Self {
+        Fifos {
+            flow,
+            cycles_per_char,
+            rx: VecDeque::new(),
+            rx_depth: rx_depth.max(1),
+            next_rx: 0,
+            tx: VecDeque::new(),
+            tx_depth: tx_depth.max(1),
+            next_tx: 0,
+            xoff_sent: false,
+            xoff_received: false,
+            overrun: false,
+            stats: Stats::default(),
+        }
+    }
+    /// Takes the bytes that have arrived from the host by the given cycle. With a baud rate
+    /// selected they arrive a character time apart however quickly TCP delivers them. send is for XOFF.
+    fn receive(&mut self, cycle: u64, mut incoming: impl FnMut() -> Option<u8>, mut send: impl FnMut(u8)) {
+        loop {
+            let full = self.rx.len() >= self.rx_depth;
+            let held = match self.flow {
+                FlowControl::None => false,
+                FlowControl::RtsCts => full,
+                FlowControl::XonXoff => self.xoff_sent,
+            };
+            if self.cycles_per_char.is_some() && self.next_rx > cycle {
+                return;
+            }
+            let Some(byte) = (!held).then(&mut incoming).flatten() else {
+                // the line has been idle until now
+                self.next_rx = self.next_rx.max(cycle);
+                return;
+            };
+            self.next_rx += self.cycles_per_char.unwrap_or(0);
+            if self.flow == FlowControl::XonXoff && (byte == XON || byte == XOFF) {
+                self.xoff_received = byte == XOFF;
+                continue;
+            }
+            if full {
+                self.overrun = true;
+                self.stats.overruns += 1;
+                continue;
+            }
+            self.rx.push_back(byte);
+            self.stats.received += 1;
+            self.stats.rx_high = self.stats.rx_high.max(self.rx.len());
+            if self.flow == FlowControl::XonXoff && self.rx.len() >= (self.rx_depth * 3 / 4).max(1) {
+                send(XOFF);
+                self.xoff_sent = true;
+            }
+        }
+    }
+    /// Takes the next received byte for the program (clearing an overrun)
+    fn read(&mut self, mut send: impl FnMut(u8)) -> Option<u8> {
+        let byte = self.rx.pop_front()?;
+        self.overrun = false;
+        if self.xoff_sent && self.rx.len() <= self.rx_depth / 4 {
+            send(XON);
+            self.xoff_sent = false;
+        }
+        Some(byte)
+    }
+    /// true if the program can write another byte
+    fn can_write(&self, connected: bool) -> bool { connected && !self.xoff_received && self.tx.len() < self.tx_depth }
+    /// Queues a byte written by the program
+    fn write(&mut self, byte: u8, cycle: u64) {
+        if self.tx.len() >= self.tx_depth {
+            self.stats.lost += 1;
+            return;
+        }
+        if self.tx.is_empty() {
+            self.next_tx = self.next_tx.max(cycle);
+        }
+        self.tx.push_back(byte);
+        self.stats.tx_high = self.stats.tx_high.max(self.tx.len());
+    }
+    /// Sends the bytes that have gone out by the given cycle
+    fn transmit(&mut self, cycle: u64, connected: bool, mut send: impl FnMut(u8)) {
+        while connected && !self.xoff_received && self.next_tx <= cycle {
+            let Some(byte) = self.tx.pop_front() else {
+                return;
+            };
+            send(byte);
+            self.stats.sent += 1;
+            self.next_tx += self.cycles_per_char.unwrap_or(0);
+        }
+    }
+}
 
 pub struct Acia {
     pub addr: u16,
     txout: Sender<u8>,
     rxin: Receiver<u8>,
-    recv_cache: RefCell<Option<u8>>,
+    fifos: RefCell<Fifos>,
     tty_count: Arc<Mutex<i32>>,
+    control: u8,
 }
 
 impl Acia {
     pub fn control_register_address(&self) -> u16 { self.addr }
+    /// The control register (for snapshots)
+    pub fn control(&self) -> u8 { self.control }
+    pub fn set_control(&mut self, control: u8) {
+        self.control = control;
+        self.fifos.get_mut().cycles_per_char = cycles_per_char(control);
+    }
     pub fn status_register_address(&self) -> u16 { self.addr }
     pub fn data_register_address(&self) -> u16 { self.addr + 1 }
     pub fn owns_address(&self, addr: u16) -> bool { addr == self.addr || addr == (self.addr + 1) }
-    pub fn write(&mut self, addr: u16, byte: u8) -> Result<(), Error> {
+    fn connected(&self) -> bool { *self.tty_count.lock().unwrap() > 0 }
+    /// Moves bytes between the FIFOs and the connection as far as the given cycle
+    fn update(&self, cycle: u64) {
+        let mut fifos = self.fifos.borrow_mut();
+        fifos.receive(cycle, || self.rxin.try_recv().ok(), |b| _ = self.txout.send(b));
+        fifos.transmit(cycle, self.connected(), |b| _ = self.txout.send(b));
+    }
+    pub fn write(&mut self, addr: u16, byte: u8, cycle: u64) -> Result<(), Error> {
         if addr == self.control_register_address() {
-            // ignore control register writes
+            // the receive interrupt enable bit, the baud rate and the word length
+            acia_dbg!("ACIA control {:02X}", byte);
+            self.set_control(byte);
             return Ok(());
         } else if addr == self.data_register_address() {
-            // ignore error here
-            _ = self.txout.send(byte);
+            self.fifos.get_mut().write(byte, cycle);
+            self.update(cycle);
         }
         Ok(())
     }
-    pub fn read(&self, addr: u16) -> Result<u8, Error> {
+    /// Returns true if the ACIA is requesting an interrupt (received data is waiting and the
+    /// program has enabled receive interrupts). The line stays asserted until the data is read.
+    /// Called at every hsync, which also keeps bytes moving.
+    pub fn irq(&self, cycle: u64) -> bool {
+        self.update(cycle);
+        self.control & RIE != 0 && !self.fifos.borrow().rx.is_empty()
+    }
+    pub fn read(&self, addr: u16, cycle: u64) -> Result<u8, Error> {
+        self.update(cycle);
         let mut flags = 0u8;
         if addr == self.status_register_address() {
+            let fifos = self.fifos.borrow();
             // if there is some data ready to read then set the RDRF bit
-            let pending_data = self.recv_cache.borrow().or_else(|| self.rxin.try_recv().ok());
-            if pending_data.is_some() {
-                acia_dbg!("ACIA status - pending data {:02X}", pending_data.unwrap());
-                *self.recv_cache.borrow_mut() = pending_data;
+            if let Some(data) = fifos.rx.front() {
+                acia_dbg!("ACIA status - pending data {:02X}", data);
                 flags |= RDRF;
+                if self.control & RIE != 0 {
+                    flags |= IRQ;
+                }
+            }
+            if fifos.overrun {
+                flags |= OVRN;
             }
-            // if we have a TTY connected then set the TDRE flag
-            let ttyc = self.tty_count.lock().unwrap();
-            if *ttyc > 0 {
+            // the program can write when a TTY is connected and there's room to send
+            if fifos.can_write(self.connected()) {
                 flags |= TDRE;
             }
             Ok(flags)
         } else if addr == self.data_register_address() {
-            // try to get a byte from our cache or from the comms thread
-            let pending_data = self.recv_cache.borrow().or_else(|| self.rxin.try_recv().ok());
-            if let Some(pending_data) = pending_data {
-                *self.recv_cache.borrow_mut() = self.rxin.try_recv().ok();
-                let byte = pending_data;
-                acia_dbg!("ACIA read {:02X}", byte);
-                Ok(byte)
-            } else {
+            let byte = self.fifos.borrow_mut().read(|b| _ = self.txout.send(b));
+            match byte {
+                Some(byte) => {
+                    acia_dbg!("ACIA read {:02X}", byte);
+                    // the next byte may already be waiting
+                    self.update(cycle);
+                    Ok(byte)
+                }
                 // user read the data register when there was no data available.
                 // result is undefined? just return a 0?
-                Ok(0)
+                None => Ok(0),
             }
         } else {
             panic!("invalid ACIA read address")
         }
     }
+    /// Describes the FIFOs and the traffic through them (for the debugger's acia command)
+    pub fn stats(&self) -> Vec<String> {
+        let fifos = self.fifos.borrow();
+        let stats = fifos.stats;
+        vec![
+            format!("flow control: {:?}{}", fifos.flow, if fifos.xoff_sent { " (XOFF sent)" } else { "" }),
+            format!(
+                "receive:  {} of {} waiting, high-water mark {}, {} bytes, {} overruns",
+                fifos.rx.len(),
+                fifos.rx_depth,
+                stats.rx_high,
+                stats.received,
+                stats.overruns
+            ),
+            format!(
+                "transmit: {} of {} waiting, high-water mark {}, {} bytes, {} lost{}",
+                fifos.tx.len(),
+                fifos.tx_depth,
+                stats.tx_high,
+                stats.sent,
+                stats.lost,
+                if fifos.xoff_received { " (paused by XOFF)" } else { "" }
+            ),
+        ]
+    }
+    /// Resets the counts and high-water marks
+    pub fn clear_stats(&self) { self.fifos.borrow_mut().stats = Stats::default() }
 }
 
 impl Acia {
@@ -74,82 +276,38 @@ impl Acia {
         let (txout, rxout): (Sender<u8>, Receiver<u8>) = channel();
         let (txin, rxin): (Sender<u8>, Receiver<u8>) = channel();
         let tty_count = Arc::new(Mutex::new(0));
-        const MSEC_10: Duration = Duration::from_millis(10);
+        let map = charmap::CharMap::from_args()?;
 
         let thread_tty_count = Arc::clone(&tty_count);
         let _handle = Some(thread::spawn(move || -> Result<(), Error> {
-            let listener = TcpListener::bind(format!("127.0.0.1:{}", config::ARGS.acia_port))
-                .map_err(|e| Error::new(ErrorKind::General, None, e.to_string().as_str()))?;
-            info!(
-                "ACIA instantiated at address {:04X}, listening at {}",
-                addr,
-                listener.local_addr().unwrap()
-            );
-            while let Ok((mut stream, client_addr)) = listener.accept() {
-                info!("ACIA accepted connection from {}", client_addr);
-                _ = stream.set_nodelay(true);
-                _ = stream.set_read_timeout(Some(MSEC_10));
-                _ = stream.set_write_timeout(Some(MSEC_10));
-                let mut in_buf = [0u8; 256];
-                let mut out_buf = [0u8; 3];
-                {
-                    let mut ttyc = thread_tty_count.lock().unwrap();
-                    *ttyc += 1;
-                }
-                'io_loop: loop {
-                    // read any input from client
-                    let mut r = stream.read(&mut in_buf);
-                    if let Err(e) = r {
-                        if e.kind() != std::io::ErrorKind::WouldBlock && e.kind() != std::io::ErrorKind::TimedOut {
-                            acia_dbg!(red!("ACIA TCP read error: {}"), e);
-                            break;
-                        }
-                    } else {
-                        let size = r.unwrap();
-                        if size == 0 {
-                            // connection closed
-                            break;
-                        }
-                        // forward input to Core
-                        #[allow(clippy::needless_range_loop)]
-                        for i in 0..size {
-                            let b: u8 = match in_buf[i] {
-                                0x41..=0x5a if config::ARGS.acia_case => in_buf[i] + 0x20,
-                                0x61..=0x7a if config::ARGS.acia_case => in_buf[i] - 0x20,
-                                0x7f => 8, // delete --> backspace
-                                _ => in_buf[i],
-                            };
-                            _ = txin.send(b);
-                            acia_dbg!(green!("ACIA recv {:02X}"), in_buf[i]);
-                        }
-                    }
-                    // get any output from Core
-                    while let Ok(byte) = rxout.try_recv() {
-                        // forward output to the client
-                        out_buf[0] = byte;
-                        if byte == 8 {
-                            out_buf[1] = 0x20;
-                            out_buf[2] = 8;
-                            r = stream.write(&out_buf[..3]);
-                            acia_dbg!(yellow!("ACIA send 0x08, 0x20, 0x08"));
-                        } else {
-                            acia_dbg!(yellow!("ACIA send {:02X}"), byte);
-                            r = stream.write(&out_buf[..1]);
-                        }
-                        if let Err(e) = r {
-                            if e.kind() != std::io::ErrorKind::WouldBlock {
-                                acia_dbg!(red!("ACIA TCP write error: {}"), e);
-                                break 'io_loop;
+            if let Some(peer) = config::ARGS.acia_connect.as_ref() {
+                // null-modem mode: keep (re)connecting to the other emulator
+                info!("ACIA instantiated at address {:04X}, connecting to {}", addr, peer);
+                loop {
+                    match TcpStream::connect(peer) {
+                        Ok(mut stream) => {
+                            info!("ACIA connected to {}", peer);
+                            if stream.write_all(NULL_MODEM_HELLO).is_ok() {
+                                serve(stream, true, map.clone(), &txin, &rxout, &thread_tty_count);
                             }
+                            info!("ACIA connection to {} closed", peer);
                         }
-                        _ = stream.flush();
+                        Err(_) => thread::sleep(Duration::from_secs(1)),
                     }
                 }
-                {
-                    let mut ttyc = thread_tty_count.lock().unwrap();
-                    *ttyc -= 1;
-                }
-
+            }
+            let listener = match config::ARGS.acia_port {
+                Some(port) => listen(port..=port)?,
+                None => listen(FIRST_PORT..=FIRST_PORT + PORTS_TRIED - 1)?,
+            };
+            let local = listener.local_addr().unwrap();
+            info!("ACIA instantiated at address {:04X}, listening at {}", addr, local);
+            if let Some(path) = config::ARGS.port_file.as_ref() {
+                std::fs::write(path, format!("acia {}\n", local))?;
+            }
+            while let Ok((stream, client_addr)) = listener.accept() {
+                info!("ACIA accepted connection from {}", client_addr);
+                serve(stream, false, map.clone(), &txin, &rxout, &thread_tty_count);
                 acia_dbg!(yellow!("ACIA TCP connection terminated. Listening at {}..."), addr);
             }
             Ok(())
@@ -158,8 +316,234 @@ impl Acia {
             addr,
             txout,
             rxin,
-            recv_cache: RefCell::new(None),
+            fifos: RefCell::new(Fifos::new(
+                config::ARGS.acia_flow,
+                cycles_per_char(0),
+                config::ARGS.acia_rx_fifo,
+                config::ARGS.acia_tx_fifo,
+            )),
             tty_count,
+            control: 0,
         })
     }
 }
+
+/// The cycles one character takes at the baud rate and word length the control register selects
+/// (with a start and a stop bit and no parity), or None for the external clock
+fn cycles_per_char(control: u8) -> Option<u64> {
+    let baud = BAUD_RATES[(control & SBR) as usize];
+    let bits = 10 - ((control & WL) >> 5) as u64;
+    (baud > 0).then(|| CPU_HZ * bits / baud as u64)
+}
+
+/// Listens at the first of the ports that's free
+fn listen(ports: std::ops::RangeInclusive<u16>) -> Result<TcpListener, Error> {
+    let (first, last) = (*ports.start(), *ports.end());
+    for port in ports {
+        match TcpListener::bind(("127.0.0.1", port)) {
+            Ok(listener) => return Ok(listener),
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && port < last => {}
+            Err(e) if first == last => return Err(general_err!("can't listen at port {}: {}", port, e)),
+            Err(e) => return Err(general_err!("can't listen at any port from {} to {}: {}", first, last, e)),
+        }
+    }
+    unreachable!()
+}
+
+/// What the bytes at the start of a connection turned out to be
+#[derive(Debug, PartialEq)]
+enum Greeting {
+    /// they're the start of NULL_MODEM_HELLO (which can arrive split across reads)
+    Partial,
+    /// the whole of NULL_MODEM_HELLO, followed by these bytes
+    Peer(Vec<u8>),
+    /// not NULL_MODEM_HELLO, so all of them are input from a terminal
+    Terminal,
+}
+fn greeting(received: &[u8]) -> Greeting {
+    let n = received.len().min(NULL_MODEM_HELLO.len());
+    if received[..n] != NULL_MODEM_HELLO[..n] {
+        Greeting::Terminal
+    } else if n < NULL_MODEM_HELLO.len() {
+        Greeting::Partial
+    } else {
+        Greeting::Peer(received[n..].to_vec())
+    }
+}
+
+/// Passes bytes between a TCP connection and the Core until the connection closes.
+/// A terminal's bytes are translated by the character map (see charmap.rs) but a null-modem
+/// peer (another instance of the emulator, which identifies itself by sending NULL_MODEM_HELLO
+/// first) gets every byte exactly as it was sent. Nothing is passed on until the start of the
+/// connection is known not to be (or to be all of) NULL_MODEM_HELLO.
+fn serve(
+    mut stream: TcpStream, mut raw: bool, mut map: charmap::CharMap, txin: &Sender<u8>, rxout: &Receiver<u8>,
+    tty_count: &Mutex<i32>,
+) {
+    const MSEC_10: Duration = Duration::from_millis(10);
+    _ = stream.set_nodelay(true);
+    _ = stream.set_read_timeout(Some(MSEC_10));
+    _ = stream.set_write_timeout(Some(MSEC_10));
+    let mut in_buf = [0u8; 256];
+    let mut out_buf = Vec::new();
+    // the start of the connection, held until it's clear whether it's NULL_MODEM_HELLO
+    let mut hello = (!raw).then(Vec::new);
+    let connected = Instant::now();
+    *tty_count.lock().unwrap() += 1;
+    loop {
+        // read any input from client
+        let mut r = stream.read(&mut in_buf);
+        let mut input = Vec::new();
+        if let Err(e) = r {
+            if e.kind() != std::io::ErrorKind::WouldBlock && e.kind() != std::io::ErrorKind::TimedOut {
+                acia_dbg!(red!("ACIA TCP read error: {}"), e);
+                break;
+            }
+        } else {
+            let size = r.unwrap();
+            if size == 0 {
+                // connection closed
+                break;
+            }
+            input.extend_from_slice(&in_buf[..size]);
+        }
+        if let Some(mut received) = hello.take() {
+            received.append(&mut input);
+            match greeting(&received) {
+                // a terminal that happens to start with a NUL gets its bytes through in the end
+                Greeting::Partial if connected.elapsed() < HELLO_WAIT => hello = Some(received),
+                Greeting::Partial | Greeting::Terminal => input = received,
+                Greeting::Peer(rest) => {
+                    info!("ACIA peer is another emulator (null-modem mode)");
+                    raw = true;
+                    input = rest;
+                }
+            }
+        }
+        // forward input to Core
+        for &byte in &input {
+            acia_dbg!(green!("ACIA recv {:02X}"), byte);
+            let Some(b) = (if raw { Some(byte) } else { map.inbound(byte) }) else {
+                continue;
+            };
+            _ = txin.send(b);
+            if config::ARGS.acia_echo && !raw {
+                // show the host what it sent as if the CoCo had printed it
+                map.outbound(b, &mut out_buf);
+            }
+        }
+        // get any output from Core
+        while let Ok(byte) = rxout.try_recv() {
+            if raw {
+                out_buf.push(byte);
+            } else {
+                map.outbound(byte, &mut out_buf);
+            }
+        }
+        // forward output to the client
+        if !out_buf.is_empty() {
+            acia_dbg!(yellow!("ACIA send {:02X?}"), out_buf);
+            r = stream.write_all(&out_buf).map(|_| out_buf.len());
+            out_buf.clear();
+            if let Err(e) = r {
+                if e.kind() != std::io::ErrorKind::WouldBlock {
+                    acia_dbg!(red!("ACIA TCP write error: {}"), e);
+                    break;
+                }
+            }
+            _ = stream.flush();
+        }
+    }
+    *tty_count.lock().unwrap() -= 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the receive side with bytes from the host, returning what went back to it
+    fn receive(fifos: &mut Fifos, cycle: u64, host: &mut VecDeque<u8>) -> Vec<u8> {
+        let mut sent = Vec::new();
+        fifos.receive(cycle, || host.pop_front(), |b| sent.push(b));
+        sent
+    }
+
+    #[test]
+    fn free_port() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        assert!(listen(port..=port).is_err());
+        if let Some(next) = port.checked_add(1) {
+            // (unless another program happens to have the next port)
+            if let Ok(listener) = listen(port..=next) {
+                assert_eq!(listener.local_addr().unwrap().port(), next);
+            }
+        }
+    }
+
+    #[test]
+    fn fifos_and_flow_control() {
+        // without flow control a paste overruns a 4-byte FIFO
+        let mut fifos = Fifos::new(FlowControl::None, None, 4, 1);
+        let mut host: VecDeque<u8> = (b'A'..=b'J').collect();
+        receive(&mut fifos, 0, &mut host);
+        assert_eq!((fifos.rx.len(), fifos.stats.overruns, fifos.overrun), (4, 6, true));
+        assert_eq!(fifos.read(|_| {}), Some(b'A'));
+        assert!(!fifos.overrun);
+
+        // RTS/CTS holds the rest until there's room, paced at 100 cycles a character
+        let mut fifos = Fifos::new(FlowControl::RtsCts, Some(100), 4, 1);
+        let mut host: VecDeque<u8> = (b'A'..=b'J').collect();
+        receive(&mut fifos, 250, &mut host);
+        assert_eq!(fifos.rx.len(), 3);
+        receive(&mut fifos, 1000, &mut host);
+        assert_eq!((fifos.rx.len(), host.len(), fifos.stats.rx_high), (4, 6, 4));
+        let mut read = Vec::new();
+        for i in 0..10 {
+            receive(&mut fifos, 1000 + i * 100, &mut host);
+            read.extend(fifos.read(|_| {}));
+        }
+        assert_eq!(read, b"ABCDEFGHIJ");
+        assert_eq!(fifos.stats.overruns, 0);
+
+        // XON/XOFF asks the host to stop at 3/4 full and start again at 1/4
+        let mut fifos = Fifos::new(FlowControl::XonXoff, None, 8, 2);
+        let mut host: VecDeque<u8> = (b'A'..=b'J').collect();
+        assert_eq!(receive(&mut fifos, 0, &mut host), [XOFF]);
+        assert_eq!(fifos.rx.len(), 6);
+        let mut sent = Vec::new();
+        for _ in 0..4 {
+            fifos.read(|b| sent.push(b));
+        }
+        assert_eq!(sent, [XON]);
+        // and XOFF from the host pauses transmission
+        host = VecDeque::from([XOFF]);
+        receive(&mut fifos, 0, &mut host);
+        fifos.write(b'x', 0);
+        assert!(!fifos.can_write(true));
+        fifos.transmit(0, true, |_| panic!("sent while paused"));
+        host.extend([XON]);
+        receive(&mut fifos, 0, &mut host);
+        let mut sent = Vec::new();
+        fifos.transmit(0, true, |b| sent.push(b));
+        assert_eq!((sent, fifos.can_write(true)), (vec![b'x'], true));
+    }
+
+    #[test]
+    fn null_modem_hello() {
+        let (head, tail) = NULL_MODEM_HELLO.split_at(5);
+        assert_eq!(greeting(head), Greeting::Partial);
+        assert_eq!(greeting(&[head, tail, b"hi"].concat()), Greeting::Peer(b"hi".to_vec()));
+        assert_eq!(greeting(b"\0hi"), Greeting::Terminal);
+        assert_eq!(greeting(b"hi"), Greeting::Terminal);
+    }
+
+    #[test]
+    fn baud_rate() {
+        assert_eq!(cycles_per_char(RIE), None);
+        // 1200 baud, 8 bits
+        assert_eq!(cycles_per_char(0x08), Some(CPU_HZ * 10 / 1200));
+        // 19200 baud, 7 bits (with interrupts on)
+        assert_eq!(cycles_per_char(RIE | 0x20 | 0x0f), Some(CPU_HZ * 9 / 19200));
+    }
+}
@@ -17,13 +17,90 @@
 //! This module along with parse.rs provide most of the work required to translate
 //! from assembly language to machine code.
 use super::obj::*;
+use super::expr::ValueNode;
 use super::parse::{OperandDescriptor, Parser};
 use super::test::TestCriterion;
 use super::*;
 
 use regex::Regex;
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+/// the deepest INCLUDEs can nest (which also stops a file that includes itself)
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// the fewest lines worth handing to a lexer thread of their own
+const LEX_CHUNK: usize = 4096;
+
+/// a statement's label, operation and operand (the operand runs to the end of the line)
+type Fields<'a> = (Option<&'a str>, Option<&'a str>, Option<&'a str>);
+
+/// Where the assembler reads source files from: the program itself and the files it INCLUDEs.
+/// Included files are read in parallel, hence Sync.
+pub trait SourceFs: Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+}
+
+/// Source files on the host's filesystem
+pub struct HostFs;
+impl SourceFs for HostFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> { std::fs::read(path) }
+}
+
+/// Source files held in memory (looked up by path, e.g. "lib/defs.asm"), for tools built on
+/// the library
+#[derive(Default)]
+pub struct MemoryFs(HashMap<PathBuf, Vec<u8>>);
+impl MemoryFs {
+    pub fn new() -> Self { MemoryFs::default() }
+    pub fn add(&mut self, path: impl Into<PathBuf>, src: impl Into<Vec<u8>>) { self.0.insert(path.into(), src.into()); }
+}
+impl SourceFs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.0.get(path).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+}
+
+/// Records how long each phase of assembly took (reported with --timing)
+struct PhaseTimes {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(String, Duration)>,
+}
+impl PhaseTimes {
+    fn new() -> Self {
+        let now = Instant::now();
+        PhaseTimes {
+            start: now,
+            last: now,
+            phases: Vec::new(),
+        }
+    }
+    /// Ends the current phase
+    fn mark(&mut self, phase: &str) {
+        let now = Instant::now();
+        self.phases.push((phase.to_string(), now - self.last));
+        self.last = now;
+    }
+    fn report(&self, lines: usize) {
+        if !config::ARGS.timing {
+            return;
+        }
+        for (phase, d) in &self.phases {
+            info!("{:>16}: {:8.3} ms", phase, d.as_secs_f64() * 1000.0);
+        }
+        let total = self.start.elapsed().as_secs_f64();
+        info!(
+            "{:>16}: {:8.3} ms ({} lines, {:.0} lines/sec)",
+            "total",
+            total * 1000.0,
+            lines,
+            lines as f64 / total.max(1e-9)
+        );
+    }
+}
 
 /// The container for our assembler methods.
 pub struct Assembler {
@@ -32,11 +109,18 @@ pub struct Assembler {
     re_comment_or_blank_line: Regex, // matches a line that is blank or only contains a comment
     re_statement: Regex, // matches a generic assembly statement line ([label] operation [operand [comment]])
     re_macro_args: Regex, // matches a comma delimited list of parameters for a macro
+    fs: Box<dyn SourceFs>, // where source files are read from
+    quiet: bool,           // true to keep the progress messages off stdout
 }
 impl Assembler {
-    pub fn new() -> Assembler {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Assembler { Assembler::with_fs(Box::new(HostFs)) }
+    /// Creates an assembler that reads source files from fs instead of the host's filesystem
+    pub fn with_fs(fs: Box<dyn SourceFs>) -> Assembler {
         instructions::init();
         Assembler {
+            fs,
+            quiet: false,
             parser: Parser::new(),
             re_result_line: Regex::new(r"^;![ \t]*([^\s]+)[ \t]*=[ \t]*([^\s]+)[ \t]*$").unwrap(),
             re_comment_or_blank_line: Regex::new(r"^(?:[ \t]*[*;].*)|^[ \t]*$").unwrap(),
@@ -50,21 +134,48 @@ impl Assembler {
 
     /// Load an assembly language program using the supplied iterable container of program lines.
     /// All macros are expanded during this process. The success result contains a Program object
-    /// that contains all the source lines but that has not been built.
+    /// that contains all the source lines but that has not been built. (The assemble_* methods
+    /// lex and load separately to time each.)
     pub fn load_program<I, T>(&self, src: I) -> Result<Program, Error>
     where
         I: IntoIterator<Item = T>,
         T: Into<String>,
     {
+        let src: Vec<String> = src.into_iter().map(Into::into).collect();
+        let fields = self.lex(&src);
+        self.load_fields(&src, fields)
+    }
+
+    /// Splits each source line into its fields. A line's fields don't depend on any other line
+    /// (unlike macro expansion) so a large program is split into chunks that are lexed in parallel.
+    fn lex<'a>(&self, src: &'a [String]) -> Vec<Fields<'a>> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = src.len().div_ceil(threads).max(LEX_CHUNK);
+        if src.len() <= chunk {
+            return src.iter().map(|line| self.split_statement(line)).collect();
+        }
+        thread::scope(|s| {
+            let lexers: Vec<_> = src
+                .chunks(chunk)
+                .map(|lines| s.spawn(move || lines.iter().map(|line| self.split_statement(line)).collect::<Vec<_>>()))
+                .collect();
+            lexers.into_iter().flat_map(|lexer| lexer.join().unwrap()).collect()
+        })
+    }
+
+    /// Builds the program's lines from the source and the fields lexed from it, expanding macros
+    /// along the way
+    #[allow(clippy::unnecessary_unwrap)]
+    fn load_fields(&self, src: &[String], fields: Vec<Fields>) -> Result<Program, Error> {
         let mut macros = HashMap::new();
         let mut mo: Option<Macro> = None;
-        let mut src_line_num = 0usize;
+        let mut names = Interner::default();
         let mut prog_lines = Vec::new();
         let add_line = |pls: &mut Vec<ProgramLine>,
                         src_line_num: usize,
                         src: String,
-                        label: Option<String>,
-                        operation: Option<String>,
+                        label: Option<Rc<str>>,
+                        operation: Option<Rc<str>>,
                         operand: Option<String>| {
             let pl = ProgramLine {
                 src_line_num,
@@ -79,19 +190,12 @@ impl Assembler {
             pls.push(pl);
         };
         // read each line of the program and process any macro definitions and expansions along the way
-        for line in src {
-            src_line_num += 1;
-            let line = line.into();
-            let mut label = None;
-            let mut operation = None;
-            let mut operand = None;
-            if let Some(statement) = self.re_statement.captures(line.as_str()) {
-                // the source line looks like a statement; try to extract the fields
-                label = statement.get(1).map(|s| s.as_str().to_string());
-                // operations (including macro names!) are case insensitive
-                operation = statement.get(2).map(|m| m.as_str().to_ascii_uppercase());
-                operand = statement.get(3).map(|s| s.as_str().to_string());
-            }
+        for (i, (line, (label, operation, operand))) in src.iter().zip(fields).enumerate() {
+            let src_line_num = i + 1;
+            let label = label.map(|s| names.intern(s));
+            // operations (including macro names!) are case insensitive
+            let operation = operation.map(|s| names.intern(&s.to_ascii_uppercase()));
+            let operand = operand.map(String::from);
             if operation.as_deref() == Some(".MACRO") {
                 // found a ".macro" (begin macro defn) statement
                 if mo.is_some() {
@@ -108,7 +212,7 @@ impl Assembler {
                     }
                     // create a new Macro object and hold it in the mo Option
                     mo = Some(Macro::new(&name));
-                    add_line(&mut prog_lines, src_line_num, format!("; {}", &line), None, None, None);
+                    add_line(&mut prog_lines, src_line_num, format!("; {}", line), None, None, None);
                     continue;
                 }
                 // no name found for this macro
@@ -122,19 +226,19 @@ impl Assembler {
                 } else {
                     return Err(syntax_err_line!(src_line_num, "invalid macro end"));
                 }
-                add_line(&mut prog_lines, src_line_num, format!("; {}", &line), None, None, None);
+                add_line(&mut prog_lines, src_line_num, format!("; {}", line), None, None, None);
                 continue;
             }
             if let Some(mut m) = mo {
                 // we're in a macro definition; add this line to the macro
-                m.add_line(&line)
+                m.add_line(line)
                     .map_err(|e| line_err!(prog_lines.len(), e.kind, e.msg))?;
                 // also add this line as a comment in the program
-                add_line(&mut prog_lines, src_line_num, format!("; {}", &line), None, None, None);
+                add_line(&mut prog_lines, src_line_num, format!("; {}", line), None, None, None);
                 mo = Some(m);
                 continue;
             }
-            if let Some(m) = operation.as_ref().and_then(|s| macros.get(s)) {
+            if let Some(m) = operation.as_deref().and_then(|s| macros.get(s)) {
                 // there is a macro to expand on this line
                 if label.is_some() {
                     // there is also a label on this line; preserve it (on its own line) before expanding the macro
@@ -174,16 +278,13 @@ impl Assembler {
                     Vec::new()
                 };
                 // expand the macro and add the resulting lines to the program
-                m.hydrate_instance(args)?.into_iter().for_each(|s| {
-                    let (a, b, c) = self.re_statement.captures(&s).map_or((None, None, None), |c| {
-                        (
-                            c.get(1).map(|m| m.as_str().to_string()),
-                            c.get(2).map(|m| m.as_str().to_ascii_uppercase()),
-                            c.get(3).map(|m| m.as_str().to_string()),
-                        )
-                    });
+                for s in m.hydrate_instance(args)? {
+                    let (a, b, c) = self.split_statement(&s);
+                    let a = a.map(|s| names.intern(s));
+                    let b = b.map(|s| names.intern(&s.to_ascii_uppercase()));
+                    let c = c.map(String::from);
                     add_line(&mut prog_lines, src_line_num, s, a, b, c);
-                });
+                }
                 continue;
             }
             // the line doesn't include a macro instance, so just add it as a potential statement
@@ -204,31 +305,136 @@ impl Assembler {
         Ok(Program::new(prog_lines))
     }
 
+    /// Splits a statement into its label, operation and operand (the operand runs to the end of
+    /// the line, so it includes any comment)
+    pub(crate) fn split_statement<'a>(&self, line: &'a str) -> Fields<'a> {
+        self.re_statement.captures(line).map_or((None, None, None), |c| {
+            (c.get(1).map(|m| m.as_str()), c.get(2).map(|m| m.as_str()), c.get(3).map(|m| m.as_str()))
+        })
+    }
+    /// Returns the name of the file that an INCLUDE line names ("" if it's missing) or None if
+    /// the line isn't an INCLUDE
+    pub(crate) fn include_name<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let (_, operation, operand) = self.split_statement(line);
+        if !operation?.eq_ignore_ascii_case("INCLUDE") {
+            return None;
+        }
+        let operand = operand.unwrap_or("");
+        let name = match operand.strip_prefix('"') {
+            Some(quoted) => quoted.split('"').next(),
+            None => operand.split_whitespace().next(),
+        };
+        Some(name.unwrap_or(""))
+    }
+    /// Returns this assembler without its progress messages (for when stdout is being used for
+    /// something else)
+    pub fn quiet(self) -> Assembler { Assembler { quiet: true, ..self } }
+    fn progress(&self, msg: &str) {
+        if !self.quiet {
+            println!("{}", msg);
+        }
+    }
+    /// Replaces each INCLUDE line (e.g. `include "defs.asm"`) with the lines of the file it
+    /// names, which is found relative to dir. Line numbers in errors count the included lines.
+    /// The files a file includes are read (and their own INCLUDEs expanded) in parallel.
+    pub(crate) fn expand_includes(&self, src: Vec<String>, dir: &Path, depth: usize) -> Result<Vec<String>, Error> {
+        let mut includes = Vec::new();
+        for (i, line) in src.iter().enumerate() {
+            let Some(name) = self.include_name(line) else { continue };
+            if name.is_empty() {
+                return Err(syntax_err_line!(i + 1, "missing file name for INCLUDE"));
+            }
+            if depth == MAX_INCLUDE_DEPTH {
+                return Err(syntax_err_line!(i + 1, "INCLUDEs are nested too deeply"));
+            }
+            includes.push((i, dir.join(name)));
+        }
+        let include = |(i, path): &(usize, PathBuf)| -> Result<Vec<String>, Error> {
+            let text = self.fs.read(path);
+            let text = text.map_err(|e| general_err!("line {}: can't include {}: {}", i + 1, path.display(), e))?;
+            let included = String::from_utf8_lossy(&text).lines().map(String::from).collect();
+            self.expand_includes(included, path.parent().unwrap_or(Path::new("")), depth + 1)
+        };
+        let mut included = match includes.len() {
+            0 => return Ok(src),
+            1 => vec![include(&includes[0])],
+            _ => thread::scope(|s| {
+                let readers: Vec<_> = includes.iter().map(|inc| s.spawn(move || include(inc))).collect();
+                readers.into_iter().map(|reader| reader.join().unwrap()).collect()
+            }),
+        }
+        .into_iter();
+        let mut lines = Vec::with_capacity(src.len());
+        let mut next = includes.iter().map(|(i, _)| *i).peekable();
+        for (i, line) in src.into_iter().enumerate() {
+            if next.next_if_eq(&i).is_none() {
+                lines.push(line);
+                continue;
+            }
+            lines.push(format!("; {}", line));
+            lines.extend(included.next().unwrap()?);
+        }
+        Ok(lines)
+    }
+
     /// Attempt to load and build an assembly language program from a file with the given path.
     pub fn assemble_from_file(&self, path: &Path) -> Result<Program, Error> {
-        let src = io::BufReader::new(File::open(path)?)
-            .lines()
-            .collect::<Result<Vec<String>, io::Error>>()?;
-        let mut program = self.load_program(src)?;
-        self.assemble_program(&mut program)?;
-        if config::ARGS.write_files {
+        let mut times = PhaseTimes::new();
+        let text = self.fs.read(path)?;
+        let src = String::from_utf8_lossy(&text).lines().map(String::from).collect();
+        let src = self.expand_includes(src, path.parent().unwrap_or(Path::new("")), 0)?;
+        times.mark("read");
+        let fields = self.lex(&src);
+        times.mark("lex");
+        let mut program = self.load_fields(&src, fields)?;
+        times.mark("load");
+        self.assemble_program(&mut program, &mut times)?;
+        times.report(program.lines.len());
+        // compare first, since the old listing may be the one about to be overwritten
+        if let Some(old) = config::ARGS.diff_lst.as_ref().filter(|_| !self.quiet) {
+            program.diff_listing(old)?;
+        }
+        if config::ARGS.write_files && !config::kiosk() {
             _ = program.write_output_files(path);
         }
         Ok(program)
     }
 
+    /// Attempt to load and build an assembly language program from the given lines of source.
+    pub fn assemble_lines<I, T>(&self, src: I) -> Result<Program, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut times = PhaseTimes::new();
+        let src = self.expand_includes(src.into_iter().map(Into::into).collect(), Path::new(""), 0)?;
+        let fields = self.lex(&src);
+        times.mark("lex");
+        let mut program = self.load_fields(&src, fields)?;
+        times.mark("load");
+        self.assemble_program(&mut program, &mut times)?;
+        times.report(program.lines.len());
+        Ok(program)
+    }
+
+    /// Attempt to load and build an assembly language program from source code in a string.
+    pub fn assemble_str(&self, src: &str) -> Result<Program, Error> { self.assemble_lines(src.lines()) }
+
     /// Performs the full build process to create a machine code program from the
     /// assembly language in the given Program object.
     ///
-    fn assemble_program(&self, program: &mut Program) -> Result<(), Error> {
-        println!("Pre-processing...");
+    fn assemble_program(&self, program: &mut Program, times: &mut PhaseTimes) -> Result<(), Error> {
+        self.progress("Pre-processing...");
         self.pre_build(program)?;
+        times.mark("pre-build");
         let mut pass_count = 0;
-        println!("Building...");
+        self.progress("Building...");
         loop {
             pass_count += 1;
-            println!("Build pass {}...", pass_count);
-            if self.build(program)? == 0 {
+            self.progress(&format!("Build pass {}...", pass_count));
+            let changes = self.build(program)?;
+            times.mark(&format!("build pass {}", pass_count));
+            if changes == 0 {
                 break;
             }
             // is the proper build pass limit actually 2 rather than 3?
@@ -241,11 +447,12 @@ impl Assembler {
                 ));
             }
         }
-        println!("Post-processing...");
+        self.progress("Post-processing...");
         self.post_build(program)?;
-        println!("Build complete.");
+        times.mark("post-build");
+        self.progress("Build complete.");
         if config::ARGS.list {
-            program.write_listing(&mut io::stdout())?;
+            program.page_listing()?;
         }
         Ok(())
     }
@@ -263,7 +470,14 @@ impl Assembler {
             // Does the line contain an operation (or assembler directive)?
             if line.operation.is_some() {
                 // parse the operation and potentially create the corresponding binary object
-                self.process_op_line(&mut program.segs, &mut program.labels, line, program.dp_dirty)?;
+                self.process_op_line(
+                    &mut program.segs,
+                    &mut program.labels,
+                    &mut program.stack_nodes,
+                    &mut program.entry_node,
+                    line,
+                    program.dp_dirty,
+                )?;
                 // get any/all object address and size info
                 if let Some(obj) = line.obj.as_ref() {
                     // check to see if this object has a static address assignment
@@ -302,8 +516,8 @@ impl Assembler {
                     // nothing to do; move on to the next line
                     return Ok(());
                 }
-                // theoretically, we shouldn't be able to reach this point
-                unreachable!()
+                // the line is not a statement, a test criterion or a comment
+                return Err(syntax_err!("unrecognized statement"));
             }
             Ok(())
         };
@@ -376,9 +590,21 @@ impl Assembler {
         changes += program.labels.eval_all_nodes()?;
         Ok(changes)
     }
-    /// Perform final phase of the build process. For now, this only entails parsing
-    /// any test criteria that the program contains.
+    /// Perform final phase of the build process. This entails parsing any test criteria
+    /// that the program contains and evaluating the bounds given by a STACK directive and the
+    /// entry point given by an END directive.
     fn post_build(&self, program: &mut Program) -> Result<(), Error> {
+        if let Some((low, high)) = program.stack_nodes.as_ref() {
+            let low = low.eval(&program.labels, 0, false)?.u16();
+            let high = high.eval(&program.labels, 0, false)?.u16();
+            if low >= high {
+                return Err(syntax_err!("STACK low address must be below the high address"));
+            }
+            program.stack = Some((low, high));
+        }
+        if let Some(entry) = program.entry_node.as_ref() {
+            program.entry = Some(entry.eval(&program.labels, 0, false)?.u16());
+        }
         for tc in &mut program.results {
             // Each TestCriterion must be parsed AFTER build is complete so that all labels can be resolved.
             if let Err(e) = self.parser.parse_test_criterion(tc, &program.labels) {
@@ -392,10 +618,11 @@ impl Assembler {
     /// Otherwise, an Error is returned. On success, an ObjectProducer for the operation
     /// is added to the provided ProgramLine.
     fn process_op_line(
-        &self, segs: &mut ProgramSegments, labels: &mut ProgramLabels, line: &mut ProgramLine, dp_dirty: bool,
+        &self, segs: &mut ProgramSegments, labels: &mut ProgramLabels, stack: &mut Option<(ValueNode, ValueNode)>,
+        entry: &mut Option<ValueNode>, line: &mut ProgramLine, dp_dirty: bool,
     ) -> Result<(), Error> {
         // first see if this is actually an assembler directive
-        if self.process_directive_line(segs, labels, line)? {
+        if self.process_directive_line(segs, labels, stack, entry, line)? {
             // the line contains an assembler directive and it was processed without error
             return Ok(());
         }
@@ -403,7 +630,9 @@ impl Assembler {
         // using ok_or_else to avoid executing the format! every time this next line is executed.
         let desc = instructions::name_to_descriptor(line.get_operation())
             .ok_or_else(|| syntax_err!(format!("Invalid operation: \"{}\"", line.get_operation())))?;
-        let od = if line.operand.is_none() || desc.is_inherent() {
+        // a comment isn't an operand (EXIT has both inherent and immediate forms, so this matters)
+        let operand = line.operand.as_deref().filter(|o| !o.starts_with(';'));
+        let od = if operand.is_none() || desc.is_inherent() {
             // the instruction uses only inherent addressing or there is no operand
             OperandDescriptor::new()
         } else {
@@ -423,7 +652,8 @@ impl Assembler {
     ///  - ```Err(Error)``` line is a directive but is invalid
     ///
     fn process_directive_line(
-        &self, segs: &mut ProgramSegments, labels: &mut ProgramLabels, line: &mut ProgramLine,
+        &self, segs: &mut ProgramSegments, labels: &mut ProgramLabels, stack: &mut Option<(ValueNode, ValueNode)>,
+        entry: &mut Option<ValueNode>, line: &mut ProgramLine,
     ) -> Result<bool, Error> {
         match line.get_operation() {
             "ORG" => {
@@ -510,14 +740,83 @@ impl Assembler {
                 let node = self.parser.str_to_value_node(line.get_operand())?;
                 line.obj = Some(Box::new(Rmb::new(node)));
             }
+            "STACK" => {
+                // STACK <low>,<high> sets the bounds within which the system stack pointer must stay
+                let bounds: Vec<&str> = line.get_operand().split(',').collect();
+                if bounds.len() != 2 {
+                    return Err(syntax_err!("STACK requires a low and a high address"));
+                }
+                let low = self.parser.str_to_value_node(bounds[0])?;
+                let high = self.parser.str_to_value_node(bounds[1])?;
+                stack.replace((low, high));
+            }
             "END" => {
+                // END [<start>] optionally gives the program's entry point
                 if line.operand.is_some() {
-                    return Err(syntax_err!("invalid operand for END"));
+                    let node = self.parser.str_to_value_node(line.get_operand())?;
+                    entry.replace(node);
                 }
-                // do nothing...
             }
             _ => return Ok(false),
         }
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn includes() {
+        let mut fs = MemoryFs::new();
+        fs.add("main.asm", " org $3000\n include \"lib/defs.asm\" ; constants\n lda #value\n");
+        fs.add("lib/defs.asm", "value equ $41\n include more.asm\n");
+        fs.add("lib/more.asm", " nop\n");
+        fs.add("self.asm", " include self.asm\n");
+        fs.add("both.asm", " include lib/defs.asm\n include lib/more.asm\n fcb value\n");
+        let asm = Assembler::with_fs(Box::new(fs));
+        let program = asm.assemble_from_file(Path::new("main.asm")).unwrap();
+        let mut core = Core::headless().unwrap();
+        core.load_program(&program, None).unwrap();
+        assert_eq!(core.raw_ram[0x3000..0x3003], [0x12, 0x86, 0x41]);
+        assert!(asm.assemble_str(" org $3000\n include lib/more.asm").is_ok());
+        // sibling INCLUDEs are read in parallel but land in order
+        let program = asm.assemble_str(" org $3000\n include both.asm").unwrap();
+        let srcs: Vec<_> = program.lines.iter().map(|l| l.src.trim()).collect();
+        let expected = [";  include lib/defs.asm", "value equ $41", ";  include more.asm", "nop"];
+        assert_eq!(srcs[2..6], expected);
+        assert_eq!(srcs[6..], [";  include lib/more.asm", "nop", "fcb value"]);
+        assert!(asm.assemble_str(" include self.asm").unwrap_err().msg.contains("nested too deeply"));
+        assert!(asm.assemble_str(" include nowhere.asm").is_err());
+        assert!(asm.assemble_str(" include").is_err());
+    }
+
+    #[test]
+    fn parallel_lexing() {
+        // enough lines for several lexer threads, with a label defined in one chunk and used in the last
+        let mut src = vec![" org $3000".to_string(), "start nop".to_string()];
+        src.extend((0..3 * LEX_CHUNK).map(|i| format!(" fcb {}", i % 256)));
+        src.push(" lda start".to_string());
+        let asm = Assembler::new().quiet();
+        let program = asm.assemble_lines(src).unwrap();
+        let last = program.lines.last().unwrap();
+        assert_eq!(last.addr, 0x3001 + 3 * LEX_CHUNK as u16);
+        assert_eq!(last.get_operand(), "start");
+        // names are interned: every FCB line shares one copy of the operation
+        let ops: Vec<_> = program.lines.iter().filter_map(|l| l.operation.as_ref()).collect();
+        assert!(Rc::ptr_eq(ops[2], ops[3]) && Rc::ptr_eq(ops[2], ops[3 * LEX_CHUNK + 1]));
+    }
+
+    #[test]
+    fn exit_forms() {
+        let asm = Assembler::new().quiet();
+        let program = asm.assemble_str(" org $3000\n exit ; done\n exit #3 ; done\n exit\n").unwrap();
+        let mut core = Core::headless().unwrap();
+        core.load_program(&program, None).unwrap();
+        assert_eq!(core.raw_ram[0x3000..0x3007], [0x11, 0x11, 0x11, 0x12, 0x03, 0x11, 0x11]);
+        // the default coco.yaml loads hello.asm, which ends with "exit ; terminate the emulator"
+        let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("hello.asm");
+        asm.assemble_from_file(&hello).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+//! Types text into the emulated keyboard after boot.
+//!
+//! Text comes from the --type option and from POKE scripts (--script). A POKE script is a
+//! text file of BASIC-style lines such as the type-in patches found in magazines:
+//! ```text
+//! ' comments start with ' or #
+//! POKE 65495,0        (applied directly to memory; numbers may be decimal, &H or $ hex)
+//! EXEC &H3F00         (any other line is typed and followed by ENTER)
+//! WAIT "READY"        (waits until the text screen shows READY before going on)
+//! ```
+//! Typing starts shortly after the ROM first scans the keyboard, which is a good sign that
+//! BASIC is waiting at its prompt. Each key is held for a few frames and then released so that
+//! the ROM's keyboard routine sees every key press.
+use super::*;
+
+const START_DELAY_FRAMES: u32 = 30; // frames to wait after the first keyboard scan
+const KEY_DOWN_FRAMES: u32 = 2;
+const KEY_UP_FRAMES: u32 = 2;
+const ENTER_FRAMES: u32 = 10; // extra time for BASIC to execute a line after ENTER
+const ENTER: char = '\r';
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    Poke(u16, u8),
+    Type(String),
+    Wait(String),
+}
+
+/// Parses a number in BASIC (decimal, &H hex, &O octal) or assembler ($ hex) notation
+fn parse_number(s: &str) -> Option<u32> {
+    let s = s.trim().to_ascii_uppercase();
+    if let Some(hex) = s.strip_prefix("&H").or_else(|| s.strip_prefix('$')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(oct) = s.strip_prefix("&O").or_else(|| s.strip_prefix('&')) {
+        u32::from_str_radix(oct, 8).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Parses the lines of a POKE script
+pub fn parse_script(text: &str) -> Result<Vec<Step>, Error> {
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('\'') || line.starts_with('#') {
+            continue;
+        }
+        let upper = line.to_ascii_uppercase();
+        if let Some(args) = upper.strip_prefix("POKE") {
+            let values: Vec<Option<u32>> = args.split(',').map(parse_number).collect();
+            match values[..] {
+                [Some(addr), Some(byte)] if addr <= 0xffff && byte <= 0xff => steps.push(Step::Poke(addr as u16, byte as u8)),
+                _ => return Err(general_err!("line {}: expected POKE <address>,<byte>", i + 1)),
+            }
+        } else if upper.starts_with("WAIT") {
+            match line[4..].trim().strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(text) if !text.is_empty() => steps.push(Step::Wait(text.to_string())),
+                _ => return Err(general_err!("line {}: expected WAIT \"<text>\"", i + 1)),
+            }
+        } else {
+            steps.push(Step::Type(line.to_string()));
+        }
+    }
+    Ok(steps)
+}
+
+pub struct AutoType {
+    steps: VecDeque<Step>,
+    chars: VecDeque<char>,
+    started: bool,
+    key_down: bool,
+    wait: u32,
+}
+impl AutoType {
+    /// Collects the text and scripts given on the command line (if any)
+    pub fn from_args() -> Result<Option<Self>, Error> {
+        let mut steps = Vec::new();
+        if let Some(path) = config::ARGS.script.as_ref() {
+            let text = std::fs::read_to_string(path)?;
+            steps.extend(parse_script(&text).map_err(|e| general_err!("{}: {}", path.display(), e))?);
+        }
+        if let Some(text) = config::ARGS.type_text.as_ref() {
+            steps.extend(text.split("\\n").map(|s| Step::Type(s.to_string())));
+        }
+        if steps.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(AutoType::new(steps)))
+    }
+    pub fn new(steps: Vec<Step>) -> Self {
+        AutoType { steps: steps.into(), chars: VecDeque::new(), started: false, key_down: false, wait: 0 }
+    }
+    /// Adds a step after the ones still waiting
+    pub fn push(&mut self, step: Step) { self.steps.push_back(step) }
+}
+
+impl Core {
+    /// Advances typing by one frame. Called on each vsync while there is text left to type.
+    pub fn autotype_vsync(&mut self) -> Result<(), Error> {
+        // the texts being waited for that are on the screen now
+        let shown: Vec<String> = self.autotype.as_ref().map_or(Vec::new(), |at| {
+            at.steps
+                .iter()
+                .filter_map(|s| match s {
+                    Step::Wait(text) if self.screen_shows(text) => Some(text.clone()),
+                    _ => None,
+                })
+                .collect()
+        });
+        let at = match self.autotype.as_mut() {
+            Some(at) => at,
+            None => return Ok(()),
+        };
+        let mut pia0 = self.lock_stats.lock(lockstats::Device::Pia0, &self.pia0);
+        if !at.started {
+            if pia0.keyboard_scanned() {
+                at.started = true;
+                at.wait = START_DELAY_FRAMES;
+            }
+            return Ok(());
+        }
+        if at.wait > 0 {
+            at.wait -= 1;
+            return Ok(());
+        }
+        if at.key_down {
+            pia0.set_typed_keys(&[]);
+            at.key_down = false;
+            at.wait = KEY_UP_FRAMES;
+            return Ok(());
+        }
+        let mut pokes = Vec::new();
+        while at.chars.is_empty() {
+            match at.steps.pop_front() {
+                Some(Step::Poke(addr, byte)) => pokes.push((addr, byte)),
+                Some(Step::Type(line)) => {
+                    at.chars.extend(line.chars());
+                    at.chars.push_back(ENTER);
+                }
+                Some(Step::Wait(text)) if shown.contains(&text) => {}
+                Some(step @ Step::Wait(_)) => {
+                    at.steps.push_front(step);
+                    break;
+                }
+                None => break,
+            }
+        }
+        if let Some(c) = at.chars.pop_front() {
+            match pia::char_keys(c) {
+                Some(keys) => {
+                    pia0.set_typed_keys(&keys);
+                    at.key_down = true;
+                    at.wait = KEY_DOWN_FRAMES + if c == ENTER { ENTER_FRAMES } else { 0 };
+                }
+                None => warn!("can't type '{}'", c.escape_default()),
+            }
+        } else if at.steps.is_empty() {
+            info!("finished typing");
+            self.autotype = None;
+        }
+        drop(pia0);
+        for (addr, byte) in pokes {
+            verbose_println!("POKE {:04X},{:02X}", addr, byte);
+            self._write_u8(memory::AccessType::Generic, addr, byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poke_script() -> Result<(), Error> {
+        let steps = parse_script("' speed up\nPOKE 65495,0\n\npoke &H3F00, $12\nEXEC &H3F00\nwait \"OK\"\n")?;
+        assert_eq!(
+            steps,
+            vec![
+                Step::Poke(0xffd7, 0),
+                Step::Poke(0x3f00, 0x12),
+                Step::Type("EXEC &H3F00".to_string()),
+                Step::Wait("OK".to_string())
+            ]
+        );
+        assert!(parse_script("POKE 70000,1").is_err());
+        assert!(parse_script("WAIT READY").is_err());
+        assert_eq!(pia::char_keys('a'), Some(vec![(0, 1)]));
+        assert_eq!(pia::char_keys('"'), Some(vec![pia::SHIFT, (4, 2)]));
+        assert_eq!(pia::char_keys('?'), Some(vec![pia::SHIFT, (5, 7)]));
+        Ok(())
+    }
+}
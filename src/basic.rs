@@ -0,0 +1,515 @@
+//! Color BASIC programs in text form (.bas files).
+//!
+//! A .bas file given with --load is checked and tokenized the way BASIC would crunch each line
+//! as it's typed (with the keywords of Color, Extended and Disk BASIC). Once BASIC is waiting at
+//! its prompt the program is put in memory as if it had been typed in, so RUN (typed, say,
+//! with --type RUN) starts it.
+//!
+//! Type-in listings are checked before anything runs and every problem is reported with the
+//! line of the file it's on:
+//! - lines without a line number, line numbers over 63999 and lines out of order
+//! - statements that don't start with a keyword and aren't assignments (e.g., PRNT)
+//! - with --basic-checksum, DATA lines whose last value doesn't match the others. Magazine
+//!   listings often end each DATA line with the sum of its values (`sum`), the sum modulo 256
+//!   (`sum256`) or their exclusive or (`xor`); add `-hex` when the values are hex without &H.
+//!
+//! With --watch the .bas file is checked for changes twice a second. When it's saved it's
+//! tokenized and put in memory again in place of the old program (which also clears BASIC's
+//! variables, just as editing a line does) and, with --watch-run, RUN is typed. Nothing else
+//! about the machine changes. A version with problems is reported and left out of memory.
+use super::*;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// the tokens for statements and operators, starting at $80
+#[rustfmt::skip]
+static STATEMENTS: &[&str] = &[
+    // Color BASIC
+    "FOR", "GO", "REM", "'", "ELSE", "IF", "DATA", "PRINT", "ON", "INPUT", "END", "NEXT", "DIM", "READ", "RUN",
+    "RESTORE", "RETURN", "STOP", "POKE", "CONT", "LIST", "CLEAR", "NEW", "CLOAD", "CSAVE", "OPEN", "CLOSE", "LLIST",
+    "SET", "RESET", "CLS", "MOTOR", "SOUND", "AUDIO", "EXEC", "SKIPF", "TAB(", "TO", "SUB", "THEN", "NOT", "STEP",
+    "OFF", "+", "-", "*", "/", "^", "AND", "OR", ">", "=", "<",
+    // Extended BASIC
+    "DEL", "EDIT", "TRON", "TROFF", "DEF", "LET", "LINE", "PCLS", "PSET", "PRESET", "SCREEN", "PCLEAR", "COLOR",
+    "CIRCLE", "PAINT", "GET", "PUT", "DRAW", "PCOPY", "PMODE", "PLAY", "DLOAD", "RENUM", "FN", "USING",
+    // Disk BASIC
+    "DIR", "DRIVE", "FIELD", "FILES", "KILL", "LOAD", "LSET", "MERGE", "RENAME", "RSET", "SAVE", "WRITE", "VERIFY",
+    "UNLOAD", "DSKINI", "BACKUP", "COPY", "DSKI$", "DSKO$", "DOS",
+];
+/// the tokens for functions, which follow $FF and start at $80
+#[rustfmt::skip]
+static FUNCTIONS: &[&str] = &[
+    // Color BASIC
+    "SGN", "INT", "ABS", "USR", "RND", "SIN", "PEEK", "LEN", "STR$", "VAL", "ASC", "CHR$", "EOF", "JOYSTK", "LEFT$",
+    "RIGHT$", "MID$", "POINT", "INKEY$", "MEM",
+    // Extended BASIC
+    "ATN", "COS", "TAN", "EXP", "FIX", "LOG", "POS", "SQR", "HEX$", "VARPTR", "INSTR", "TIMER", "PPOINT", "STRING$",
+    // Disk BASIC
+    "CVN", "FREE", "LOC", "LOF", "MKN$", "AS",
+];
+const REM: u8 = 0x82;
+const REM_QUOTE: u8 = 0x83;
+const ELSE: u8 = 0x84;
+const DATA: u8 = 0x86;
+const PRINT: u8 = 0x87;
+const THEN: u8 = 0xa7;
+const EQUALS: u8 = 0xb3;
+const FUNCTION: u8 = 0xff;
+const MID: u8 = 0x90;
+const MAX_LINE_NUMBER: u16 = 63999;
+// BASIC's pointers to the start of the program, its variables, its arrays, the end of the
+// arrays and the bottom of string space
+const TXTTAB: usize = 0x19;
+const VARTAB: usize = 0x1b;
+const ARYTAB: usize = 0x1d;
+const ARYEND: usize = 0x1f;
+const FRETOP: usize = 0x21;
+/// how often (in frames) a watched file is checked for changes
+const WATCH_FRAMES: u32 = 30;
+
+/// How the last value on each DATA line checks the others
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Checksum {
+    Sum,
+    Sum256,
+    Xor,
+    SumHex,
+    Sum256Hex,
+    XorHex,
+}
+impl Checksum {
+    fn hex(&self) -> bool { matches!(self, Checksum::SumHex | Checksum::Sum256Hex | Checksum::XorHex) }
+    fn compute(&self, values: &[u32]) -> u32 {
+        match self {
+            Checksum::Sum | Checksum::SumHex => values.iter().sum(),
+            Checksum::Sum256 | Checksum::Sum256Hex => values.iter().sum::<u32>() % 256,
+            Checksum::Xor | Checksum::XorHex => values.iter().fold(0, |x, v| x ^ v),
+        }
+    }
+    /// Parses a DATA value (decimal or &H hex, or bare hex for the -hex schemes)
+    fn value(&self, item: &str) -> Option<u32> {
+        let item = item.trim().trim_matches('"');
+        match item.strip_prefix("&H") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None if self.hex() => u32::from_str_radix(item, 16).ok(),
+            None => item.parse().ok(),
+        }
+    }
+}
+
+/// Returns the token for the keyword text starts with (if any) and the keyword's length
+fn keyword(text: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let starts = |k: &&str| text.len() >= k.len() && text[..k.len()].eq_ignore_ascii_case(k.as_bytes());
+    if let Some(i) = STATEMENTS.iter().position(starts) {
+        return Some((vec![0x80 + i as u8], STATEMENTS[i].len()));
+    }
+    let i = FUNCTIONS.iter().position(starts)?;
+    Some((vec![FUNCTION, 0x80 + i as u8], FUNCTIONS[i].len()))
+}
+
+/// Crunches the text of a line (after its number) into tokens the way BASIC does when the line
+/// is typed. Letters outside strings, DATA and remarks are made upper case.
+pub fn tokenize(text: &str) -> Vec<u8> {
+    let text = text.as_bytes();
+    let mut tokens = Vec::new();
+    let (mut i, mut quoted, mut data) = (0, false, false);
+    while i < text.len() {
+        let c = text[i];
+        if c == b'"' {
+            quoted = !quoted;
+        }
+        if quoted || c == b'"' || (data && c != b':') {
+            tokens.push(c);
+            i += 1;
+            continue;
+        }
+        data = false;
+        let Some((token, len)) = keyword(&text[i..]).or_else(|| (c == b'?').then(|| (vec![PRINT], 1))) else {
+            tokens.push(c.to_ascii_uppercase());
+            i += 1;
+            continue;
+        };
+        // ' and ELSE are stored as the end of a statement followed by the token
+        if matches!(token[..], [REM_QUOTE] | [ELSE]) && tokens.last() != Some(&b':') {
+            tokens.push(b':');
+        }
+        tokens.extend(&token);
+        i += len;
+        match token[..] {
+            [REM] | [REM_QUOTE] => {
+                tokens.extend(&text[i..]);
+                break;
+            }
+            [DATA] => data = true,
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// Turns tokens back into text (as LIST would show them)
+pub fn detokenize(tokens: &[u8]) -> String {
+    let mut text = String::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i..] {
+            [b':', REM_QUOTE | ELSE, ..] => {}
+            [FUNCTION, t, ..] if (0x80..0x80 + FUNCTIONS.len() as u8).contains(&t) => {
+                text.push_str(FUNCTIONS[t as usize - 0x80]);
+                i += 1;
+            }
+            [t, ..] if (0x80..0x80 + STATEMENTS.len() as u8).contains(&t) => {
+                text.push_str(STATEMENTS[t as usize - 0x80])
+            }
+            [c, ..] => text.push(c as char),
+            [] => unreachable!(),
+        }
+        i += 1;
+    }
+    text
+}
+
+/// Returns true if a statement can start with token t
+fn starts_statement(t: u8) -> bool { matches!(t, 0x80..=0xa3 | 0xb5..=0xcb | 0xce..=0xe1) }
+
+/// Returns true if a statement is an assignment (e.g., A$(I+1)="X")
+fn is_assignment(statement: &[u8]) -> bool {
+    let mut i = statement.iter().take_while(|c| c.is_ascii_alphanumeric()).count();
+    if statement.get(i) == Some(&b'$') {
+        i += 1;
+    }
+    if statement.get(i) == Some(&b'(') {
+        let mut depth = 0;
+        while i < statement.len() {
+            match statement[i] {
+                b'(' => depth += 1,
+                b')' => depth -= 1,
+                _ => {}
+            }
+            i += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+    statement[i..].iter().find(|&&c| c != b' ') == Some(&EQUALS)
+}
+
+/// Checks that every statement in a line starts with a keyword or is an assignment. Returns the
+/// first one that doesn't.
+fn check_statements(tokens: &[u8]) -> Result<(), String> {
+    // where the next statement starts and whether it follows THEN or ELSE
+    let mut start = Some((0, false));
+    let mut i = 0;
+    while i <= tokens.len() {
+        if let Some((s, after_then)) = start.take() {
+            // the statement starts at the first character that isn't a space
+            let s = s + tokens[s..].iter().take_while(|&&c| c == b' ').count();
+            let statement = &tokens[s..];
+            let known = match statement {
+                [] | [b':', ..] => true,
+                [FUNCTION, MID, ..] => true,
+                [t, ..] if *t >= 0x80 => starts_statement(*t),
+                // a line number after THEN or ELSE
+                [c, ..] if c.is_ascii_digit() => after_then,
+                [c, ..] if c.is_ascii_alphabetic() => is_assignment(statement),
+                _ => false,
+            };
+            if !known {
+                let end = statement.iter().position(|&c| c == b':').unwrap_or(statement.len());
+                return Err(format!("unknown statement: {}", detokenize(&statement[..end]).trim_end()));
+            }
+            i = s;
+        }
+        match tokens.get(i) {
+            Some(b'"') => i += tokens[i + 1..].iter().position(|&c| c == b'"').map_or(tokens.len(), |n| n + 1),
+            Some(&REM) | Some(&REM_QUOTE) => break,
+            Some(&DATA) => {
+                while i + 1 < tokens.len() && tokens[i + 1] != b':' {
+                    if tokens[i + 1] == b'"' {
+                        i += tokens[i + 2..].iter().position(|&c| c == b'"').map_or(tokens.len(), |n| n + 1);
+                    }
+                    i += 1;
+                }
+            }
+            Some(b':') => start = Some((i + 1, false)),
+            Some(&THEN) | Some(&ELSE) => start = Some((i + 1, true)),
+            Some(&FUNCTION) => i += 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// Returns the items of the DATA statements in a line
+fn data_items(tokens: &[u8]) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut i = 0;
+    let mut quoted = false;
+    let mut item: Option<String> = None;
+    while i < tokens.len() {
+        let c = tokens[i];
+        if c == b'"' {
+            quoted = !quoted;
+        }
+        match (&mut item, c) {
+            (None, DATA) if !quoted => item = Some(String::new()),
+            (None, REM | REM_QUOTE) if !quoted => break,
+            (Some(_), b',' | b':') if !quoted => {
+                items.push(item.take().unwrap());
+                if c == b',' {
+                    item = Some(String::new());
+                }
+            }
+            (Some(s), _) => s.push(c as char),
+            _ => {}
+        }
+        i += 1;
+    }
+    items.extend(item);
+    items
+}
+
+/// A problem with a listing, on the given line of the file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub line: usize,
+    pub msg: String,
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "line {}: {}", self.line, self.msg) }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub number: u16,
+    pub tokens: Vec<u8>,
+}
+
+/// A tokenized BASIC program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub lines: Vec<Line>,
+}
+impl Program {
+    /// Tokenizes a listing and checks it for mistakes
+    pub fn parse(text: &str, checksum: Option<Checksum>) -> (Self, Vec<Problem>) {
+        let mut program = Program::default();
+        let mut problems = Vec::new();
+        for (i, text) in text.lines().enumerate() {
+            let mut problem = |msg: String| problems.push(Problem { line: i + 1, msg });
+            let text = text.trim_end_matches('\r');
+            if text.trim().is_empty() {
+                continue;
+            }
+            if let Some(c) = text.chars().find(|c| !c.is_ascii() || c.is_ascii_control()) {
+                problem(format!("'{}' can't be typed on a CoCo", c.escape_default()));
+                continue;
+            }
+            let text = text.trim_start();
+            let digits = text.chars().take_while(char::is_ascii_digit).count();
+            let number = match text[..digits].parse::<u32>() {
+                Ok(n) if n <= MAX_LINE_NUMBER as u32 => n as u16,
+                Ok(n) => {
+                    problem(format!("line number {} is too large ({} at most)", n, MAX_LINE_NUMBER));
+                    continue;
+                }
+                Err(_) => {
+                    problem("missing line number".to_string());
+                    continue;
+                }
+            };
+            if let Some(prev) = program.lines.last().map(|l| l.number).filter(|&prev| prev >= number) {
+                problem(format!("line {} comes after line {}", number, prev));
+            }
+            // BASIC drops the space after the line number
+            let rest = &text[digits..];
+            let tokens = tokenize(rest.strip_prefix(' ').unwrap_or(rest));
+            if let Err(e) = check_statements(&tokens) {
+                problem(format!("{} (in line {})", e, number));
+            }
+            if let Some(checksum) = checksum {
+                let items = data_items(&tokens);
+                let values: Vec<Option<u32>> = items.iter().map(|item| checksum.value(item)).collect();
+                if let Some(j) = values.iter().position(Option::is_none) {
+                    problem(format!("DATA value '{}' in line {} isn't a number", items[j].trim(), number));
+                } else if let [values @ .., Some(sum)] = &values[..] {
+                    let values: Vec<u32> = values.iter().flatten().copied().collect();
+                    let computed = checksum.compute(&values);
+                    // a line with a single value has nothing to check
+                    if !values.is_empty() && computed != *sum {
+                        problem(format!(
+                            "DATA checksum in line {} is {} but the values give {} (check for typos)",
+                            number, sum, computed
+                        ));
+                    }
+                }
+            }
+            program.lines.retain(|l| l.number != number);
+            program.lines.push(Line { number, tokens });
+        }
+        program.lines.sort_by_key(|l| l.number);
+        (program, problems)
+    }
+    /// Reads, tokenizes and checks a .bas file
+    pub fn from_file(path: &Path, checksum: Option<Checksum>) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)?;
+        let (program, problems) = Program::parse(&text, checksum);
+        if !problems.is_empty() {
+            let list: Vec<String> = problems.iter().map(|p| format!("{}:{}", path.display(), p)).collect();
+            return Err(general_err!("{} problem(s) in the listing:\n{}", problems.len(), list.join("\n")));
+        }
+        Ok(program)
+    }
+    /// Returns the program as it would be in memory at addr (each line is linked to the next and
+    /// the program ends with a null link)
+    pub fn bytes(&self, addr: u16) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for line in &self.lines {
+            let next = addr as usize + bytes.len() + line.tokens.len() + 5;
+            bytes.extend((next as u16).to_be_bytes());
+            bytes.extend(line.number.to_be_bytes());
+            bytes.extend(&line.tokens);
+            bytes.push(0);
+        }
+        bytes.extend([0, 0]);
+        bytes
+    }
+}
+
+/// A .bas file that's reloaded when it changes
+#[derive(Debug)]
+pub struct Watch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    countdown: u32,
+}
+impl Watch {
+    /// Watches the .bas file given with --load (if --watch was given)
+    pub fn from_args() -> Option<Self> {
+        if !config::ARGS.watch {
+            return None;
+        }
+        let is_bas = |p: &&PathBuf| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("bas"));
+        let Some(path) = config::ARGS.load.iter().rfind(is_bas) else {
+            warn!("--watch needs a .bas file given with --load");
+            return None;
+        };
+        info!("watching {} for changes", path.display());
+        Some(Watch { path: path.clone(), modified: Watch::modified(path), countdown: WATCH_FRAMES })
+    }
+    fn modified(path: &Path) -> Option<SystemTime> { std::fs::metadata(path).and_then(|m| m.modified()).ok() }
+    /// Returns true if the file has changed since it was last checked (which is done on every
+    /// WATCH_FRAMES'th call)
+    fn changed(&mut self) -> bool {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+        self.countdown = WATCH_FRAMES;
+        let modified = Watch::modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        modified.is_some()
+    }
+}
+
+impl Core {
+    /// Tokenizes a watched .bas file again (after it's changed) so that it replaces the program
+    /// in memory
+    fn reload_basic(&mut self) {
+        let path = self.basic_watch.as_ref().unwrap().path.clone();
+        match Program::from_file(&path, config::ARGS.basic_checksum) {
+            Ok(program) => {
+                info!("{} changed; reloading it", path.display());
+                self.basic = Some(program);
+                if config::ARGS.watch_run {
+                    let step = autotype::Step::Type("RUN".to_string());
+                    self.autotype.get_or_insert_with(|| autotype::AutoType::new(vec![])).push(step);
+                }
+            }
+            Err(e) => warn!("{} changed but can't be reloaded: {}", path.display(), e),
+        }
+    }
+    /// Puts a BASIC program loaded with --load into memory once BASIC is waiting at its prompt
+    /// and reloads it when it changes (called on each vsync)
+    pub fn basic_vsync(&mut self) -> Result<(), Error> {
+        if self.basic_watch.as_mut().is_some_and(|w| w.changed()) {
+            self.reload_basic();
+        }
+        if self.basic.is_none() || !self.lock_pia0().keyboard_scanned() {
+            return Ok(());
+        }
+        let program = self.basic.take().unwrap();
+        let word = |ram: &[u8], addr: usize| u16::from_be_bytes([ram[addr], ram[addr + 1]]);
+        let start = word(self.raw_ram, TXTTAB);
+        let bytes = program.bytes(start);
+        let end = start as usize + bytes.len();
+        if start == 0 || end > word(self.raw_ram, FRETOP) as usize {
+            return Err(general_err!("the BASIC program ({} bytes) doesn't fit in memory", bytes.len()));
+        }
+        self.raw_ram[start as usize..end].copy_from_slice(&bytes);
+        // there are no variables yet
+        for ptr in [VARTAB, ARYTAB, ARYEND] {
+            self.raw_ram[ptr..ptr + 2].copy_from_slice(&(end as u16).to_be_bytes());
+        }
+        info!("BASIC program ({} lines) loaded at {:04X}", program.lines.len(), start);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let tokens = tokenize("print \"hi\":goto 10'done");
+        assert_eq!(tokens, b"\x87 \"hi\":\x81\xa5 10:\x83done");
+        assert_eq!(detokenize(&tokens), "PRINT \"hi\":GOTO 10'done");
+        assert_eq!(tokenize("IFA=BTHEN?X ELSE20"), b"\x85A\xb3B\xa7\x87X :\x8420");
+        assert_eq!(tokenize("A$=MID$(B$,2):DATA a,\"b:c\":REM x=1"), b"A$\xb3\xff\x90(B$,2):\x86 a,\"b:c\":\x82 x=1");
+        assert_eq!(detokenize(b"\xff\x9f"), "TIMER");
+    }
+
+    #[test]
+    fn lint() {
+        let (program, problems) = Program::parse("10 CLS\n\n 20 X(1)=2: PRNT X\n15 A=1\n70000 END\nEND\n", None);
+        let lines: Vec<usize> = problems.iter().map(|p| p.line).collect();
+        assert_eq!(lines, vec![3, 4, 5, 6]);
+        assert_eq!(problems[0].msg, "unknown statement: PRNT X (in line 20)");
+        assert_eq!(program.lines.iter().map(|l| l.number).collect::<Vec<_>>(), vec![10, 15, 20]);
+        let (_, problems) = Program::parse("10 IF A THEN 20 ELSE PRINT\n20 MID$(A$,1)=\"X\":ON X GOSUB 10\n", None);
+        assert!(problems.is_empty(), "{:?}", problems);
+        let listing = "10 DATA 1,2,3,6\n20 DATA 255,2,1\n30 DATA &HFF, 1,&H100\n40 DATA 1,X\n";
+        let (_, problems) = Program::parse(listing, Some(Checksum::Sum));
+        assert_eq!(problems.iter().map(|p| p.line).collect::<Vec<_>>(), vec![2, 4]);
+        let (_, problems) = Program::parse("10 DATA FF,02,FD:REM\n", Some(Checksum::XorHex));
+        assert!(problems.is_empty(), "{:?}", problems);
+        let (_, problems) = Program::parse("10 DATA 200,100,44\n", Some(Checksum::Sum256));
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn watch() {
+        let path = std::env::temp_dir().join(format!("coco-watch-{}.bas", std::process::id()));
+        std::fs::write(&path, "10 END\n").unwrap();
+        let mut watch = Watch { path: path.clone(), modified: Watch::modified(&path), countdown: 0 };
+        assert!(!watch.changed());
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        // the file isn't checked again until WATCH_FRAMES frames have passed
+        assert!((0..WATCH_FRAMES).all(|_| !watch.changed()));
+        assert!(watch.changed() && !watch.changed());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn memory() {
+        let (program, _) = Program::parse("10 END\n20 CLS\n", None);
+        assert_eq!(program.bytes(0x2601), vec![0x26, 0x07, 0, 10, 0x8a, 0, 0x26, 0x0d, 0, 20, 0x9e, 0, 0, 0]);
+    }
+}
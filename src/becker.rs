@@ -0,0 +1,79 @@
+//! Becker port with a built-in DriveWire responder.
+//!
+//! The Becker port is a simple two-register interface (status at $FF41, data at $FF42)
+//! that DriveWire-aware ROMs and drivers use to talk to a DriveWire server. Rather than
+//! relaying the traffic to an external server, the emulator answers the DriveWire
+//! protocol itself. Currently only the printing operations are implemented; print
+//! jobs are handed to the virtual printer (see printer.rs).
+use super::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+pub const STATUS_ADDR: u16 = 0xff41;
+pub const DATA_ADDR: u16 = 0xff42;
+const STATUS_READY: u8 = 0b00000010; // there is data to be read from the data register
+
+// DriveWire operation codes
+const OP_RESET1: u8 = 0xfe;
+const OP_RESET2: u8 = 0xff;
+const OP_RESET3: u8 = 0xf8;
+const OP_INIT: u8 = 0x49;
+const OP_TERM: u8 = 0x54;
+const OP_DWINIT: u8 = 0x5a;
+const OP_PRINT: u8 = 0x50;
+const OP_PRINTFLUSH: u8 = 0x46;
+
+pub struct Becker {
+    printer: printer::Printer,
+    pending_op: Option<u8>,          // operation waiting for its operand byte
+    response: RefCell<VecDeque<u8>>, // bytes waiting to be read by the guest
+}
+impl Becker {
+    pub fn new(printer: printer::Printer) -> Self {
+        info!("Becker port enabled at {:04X}", STATUS_ADDR);
+        Becker {
+            printer,
+            pending_op: None,
+            response: RefCell::new(VecDeque::new()),
+        }
+    }
+    pub fn owns_address(&self, addr: u16) -> bool { addr == STATUS_ADDR || addr == DATA_ADDR }
+    pub fn read(&self, addr: u16) -> u8 {
+        let mut response = self.response.borrow_mut();
+        if addr == STATUS_ADDR {
+            if response.is_empty() { 0 } else { STATUS_READY }
+        } else {
+            response.pop_front().unwrap_or(0)
+        }
+    }
+    pub fn write(&mut self, addr: u16, byte: u8) -> Result<(), Error> {
+        if addr != DATA_ADDR {
+            return Ok(());
+        }
+        if let Some(op) = self.pending_op.take() {
+            match op {
+                OP_PRINT => self.printer.print(byte),
+                OP_DWINIT => {
+                    // the guest told us its driver version; reply that the server has no extended features
+                    self.response.borrow_mut().push_back(0);
+                }
+                _ => unreachable!(),
+            }
+            return Ok(());
+        }
+        match byte {
+            OP_PRINT | OP_DWINIT => self.pending_op = Some(byte),
+            OP_PRINTFLUSH => _ = self.printer.flush()?,
+            OP_RESET1 | OP_RESET2 | OP_RESET3 | OP_INIT | OP_TERM => {}
+            _ => {
+                verbose_println!("unsupported DriveWire operation {:02X}", byte);
+            }
+        }
+        Ok(())
+    }
+    /// Writes out any partial print job. Called when the emulator exits.
+    pub fn close(&mut self) -> Result<(), Error> {
+        self.printer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Opcode-level microbenchmark (--bench-opcode).
+//!
+//! Each instruction form (an opcode together with its addressing mode) is placed in memory with
+//! a synthetic operand and executed over and over on a headless core. Before every execution
+//! the registers are put back the way they started, so branches, jumps, returns and pulls all
+//! run the same path each time. The result is the host time per instruction, broken down into
+//! the decode (prep), eval and commit phases that --perf reports for a whole run.
+use super::*;
+use instructions::{AddressingMode, Flavor, OperandType};
+use std::time::Duration;
+
+/// the address where each instruction is placed
+const ORIGIN: u16 = 0x1000;
+/// number of timed executions of each instruction form
+const ITERATIONS: u32 = 200_000;
+/// number of untimed executions before timing starts
+const WARMUP: u32 = 1_000;
+
+/// Returns every instruction form whose mnemonic matches (ignoring case) or all of them for "all"
+fn forms(which: &str) -> Vec<&'static Flavor> {
+    (0..0x300u16)
+        .map(|i| match i >> 8 {
+            0 => i,
+            1 => 0x1000 | (i & 0xff),
+            _ => 0x1100 | (i & 0xff),
+        })
+        .filter_map(instructions::opcode_to_flavor)
+        .filter(|f| which.eq_ignore_ascii_case("all") || f.desc.name.eq_ignore_ascii_case(which))
+        .collect()
+}
+
+/// Returns the bytes of an instruction form with an operand that keeps every access in plain RAM
+fn encode(flavor: &Flavor) -> Vec<u8> {
+    let mut bytes = vec![0u8; 2];
+    let op_size = flavor.detail.op_as_u8u16().get_as_bytes(&mut bytes);
+    bytes.truncate(op_size);
+    let operand: &[u8] = match (&flavor.desc.ot, flavor.mode) {
+        (OperandType::Exch, _) => &[0x89],                 // A,B
+        (OperandType::Push, _) => &[0x06],                 // A,B
+        (_, AddressingMode::Indexed) => &[0x84],           // ,X
+        (_, AddressingMode::Direct) => &[0x20],            // $0020 (DP = 0)
+        (_, AddressingMode::Extended) => &[0x20, 0x00],    // $2000
+        (_, AddressingMode::Immediate) => &[0x12, 0x34],   // truncated for 8-bit operands
+        _ => &[0x00, 0x00],                                // relative branches go to the next instruction
+    };
+    let len = flavor.detail.sz as usize - op_size;
+    bytes.extend(operand.iter().copied().chain(std::iter::repeat(0)).take(len));
+    bytes
+}
+
+/// Runs the benchmark for the given mnemonic (or "all") and prints a row for each instruction form
+pub fn run(which: &str) -> Result<(), Error> {
+    let mut core = Core::headless()?;
+    let forms = forms(which);
+    if forms.is_empty() {
+        return Err(general_err!("unknown instruction \"{}\"", which));
+    }
+    let start = registers::Set {
+        pc: ORIGIN,
+        s: 0x7000,
+        u: 0x6000,
+        x: 0x2000,
+        y: 0x2000,
+        ..Default::default()
+    };
+    println!(
+        "{:<6} {:<6} {:<10} {:>9} {:>9} {:>9} {:>9}",
+        "Opcode", "Inst", "Mode", "ns/inst", "decode", "eval", "commit"
+    );
+    println!("{}", "-".repeat(65));
+    let (mut total, mut timed) = (Duration::ZERO, 0u32);
+    for flavor in forms {
+        let bytes = encode(flavor);
+        core.raw_ram[ORIGIN as usize..ORIGIN as usize + bytes.len()].copy_from_slice(&bytes);
+        let mut bench = |count: u32| -> Result<Duration, Error> {
+            core.prep_time = Duration::ZERO;
+            core.eval_time = Duration::ZERO;
+            core.commit_time = Duration::ZERO;
+            let began = Instant::now();
+            for _ in 0..count {
+                core.reg = start;
+                core.exec_next(true)?;
+            }
+            Ok(began.elapsed())
+        };
+        let result = bench(WARMUP).and_then(|_| bench(ITERATIONS));
+        let ns = |d: Duration| d.as_secs_f64() * 1e9 / ITERATIONS as f64;
+        let opcode = format!("{:X}", flavor.detail.op);
+        let mode = format!("{:?}", flavor.mode);
+        match result {
+            Ok(elapsed) => {
+                println!(
+                    "{:<6} {:<6} {:<10} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+                    opcode,
+                    flavor.desc.name,
+                    mode,
+                    ns(elapsed),
+                    ns(core.prep_time),
+                    ns(core.eval_time),
+                    ns(core.commit_time)
+                );
+                total += elapsed;
+                timed += 1;
+            }
+            Err(e) => println!("{:<6} {:<6} {:<10} failed: {}", opcode, flavor.desc.name, mode, e),
+        }
+    }
+    if timed > 1 {
+        println!("{}", "-".repeat(65));
+        println!(
+            "{:<6} {:<17} {:>9.1}",
+            "",
+            format!("average of {}", timed),
+            total.as_secs_f64() * 1e9 / (ITERATIONS as f64 * timed as f64)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        instructions::init();
+        let encoded = |name: &str| forms(name).iter().map(|f| encode(f)).collect::<Vec<_>>();
+        assert_eq!(
+            encoded("lda"),
+            vec![vec![0x86, 0x12], vec![0x96, 0x20], vec![0xa6, 0x84], vec![0xb6, 0x20, 0x00]]
+        );
+        assert_eq!(encoded("LDY")[0], vec![0x10, 0x8e, 0x12, 0x34]);
+        assert_eq!(encoded("tfr"), vec![vec![0x1f, 0x89]]);
+        assert_eq!(encoded("pshs"), vec![vec![0x34, 0x06]]);
+        assert_eq!(encoded("lbra"), vec![vec![0x16, 0x00, 0x00]]);
+        assert!(forms("bogus").is_empty());
+        // every form has exactly the size its descriptor says it has
+        assert!(forms("all").iter().all(|f| encode(f).len() == f.detail.sz as usize));
+    }
+}
@@ -0,0 +1,248 @@
+//! The cartridge slot.
+//!
+//! Anything plugged into the slot implements the Cartridge trait. The slot decodes two windows:
+//! ```text
+//! C000-FEFF  CTS (normally the cartridge's ROM)
+//! FF40-FF5F  SCS (the cartridge's I/O registers, e.g., a disk controller)
+//! ```
+//! A cartridge can also pull the CART line (which raises a FIRQ through PIA1), either once
+//! when it's plugged in (see autostart) or from its timer callback, and can keep state that
+//! outlives the session (e.g., battery-backed RAM). The state is saved next to the cartridge
+//! file with a .sav extension.
+//!
+//! The devices that are turned on with options (the timer, WordPak, MIDI Pak, tablet, RAM disk
+//! and disk controller) are cartridges too. They're plugged in alongside whatever is in the slot,
+//! as they would be in a Multi-Pak, and can't be pulled. Their registers can sit anywhere (see
+//! owns_address) and they can save a chunk of their own in snapshots.
+use super::*;
+use std::path::PathBuf;
+
+pub const ROM_START: u16 = 0xc000;
+pub const ROM_END: u16 = 0xfeff;
+pub const IO_START: u16 = 0xff40;
+pub const IO_END: u16 = 0xff5f;
+
+/// Returns true if addr is decoded by the cartridge slot
+#[inline(always)]
+pub fn in_slot(addr: u16) -> bool { (ROM_START..=ROM_END).contains(&addr) || (IO_START..=IO_END).contains(&addr) }
+
+/// The interrupt line a cartridge pulls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    /// CART, which raises a FIRQ through PIA1
+    Cart,
+    Irq,
+}
+
+pub trait Cartridge {
+    /// A short description (shown by the debugger's cart command)
+    fn name(&self) -> String;
+    /// Called when the cartridge is pushed into the slot. rom is the CTS window of memory;
+    /// cartridges whose ROM doesn't bank switch can copy it there and let memory handle reads.
+    fn plug(&mut self, _rom: &mut [u8]) {}
+    /// Called when the cartridge is pulled. Nothing drives the bus so the window reads as 0xFF.
+    fn unplug(&mut self, rom: &mut [u8]) { rom.fill(0xff) }
+    /// Returns true if the cartridge pulls the CART line when it's plugged in (as program paks do)
+    fn autostart(&self) -> bool { false }
+    /// Returns true if addr is one of the cartridge's I/O registers (which the I/O log shows)
+    fn owns_address(&self, _addr: u16) -> bool { false }
+    /// Handles a read in either window (or at any address for a device plugged in alongside the
+    /// slot). None means the cartridge doesn't drive the bus at addr and the read is satisfied
+    /// from memory.
+    fn read(&self, _addr: u16, _cycle: u64) -> Option<u8> { None }
+    /// Handles a write (where read does). Returns false if the cartridge ignored it.
+    fn write(&mut self, _addr: u16, _byte: u8, _cycle: u64) -> Result<bool, Error> { Ok(false) }
+    /// Called when the machine is reset
+    fn reset(&mut self) {}
+    /// Called on every hsync with the current clock cycle. Returns the line the cartridge pulls (if any).
+    fn tick(&mut self, _cycle: u64) -> Option<Line> { None }
+    /// Returns the cycle the cartridge holds the CPU halted until (if it's halting it)
+    fn halted_until(&self) -> Option<u64> { None }
+    /// Called after every instruction (for cartridges that need better timing than tick's).
+    /// Returns true to start an NMI.
+    fn step(&mut self, _cycle: u64) -> bool { false }
+    /// Returns the state that should persist between sessions (None if the cartridge has none)
+    fn save_state(&self) -> Option<Vec<u8>> { None }
+    /// Restores state returned by save_state in an earlier session
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), Error> { Ok(()) }
+    /// Returns the error load_state would return for state (so that a snapshot can be checked
+    /// before anything is changed)
+    fn check_state(&self, _state: &[u8]) -> Result<(), Error> { Ok(()) }
+    /// The tag of the cartridge's snapshot chunk
+    fn tag(&self) -> [u8; 4] { *b"CART" }
+    /// Returns the cartridge's part of a snapshot (None if it has nothing to save). Times are
+    /// saved relative to cycle, the clock when the snapshot is taken.
+    fn snapshot(&self, _cycle: u64) -> Option<Vec<u8>> { None }
+    /// Returns an error if state isn't something restore can use
+    fn check_snapshot(&self, _state: &[u8]) -> Result<(), Error> { Ok(()) }
+    /// Puts the cartridge back in a state returned by snapshot (and passed by check_snapshot)
+    fn restore(&mut self, _state: &[u8], _cycle: u64) {}
+    /// Called when coco exits (to write anything the cartridge keeps in a file)
+    fn close(&mut self) -> Result<(), Error> { Ok(()) }
+}
+
+/// A plain program pak: a ROM image at C000 that autostarts
+pub struct RomCart {
+    name: String,
+    image: Vec<u8>,
+}
+impl RomCart {
+    pub fn new(name: &str, image: Vec<u8>) -> Self {
+        RomCart {
+            name: name.to_string(),
+            image,
+        }
+    }
+}
+impl Cartridge for RomCart {
+    fn name(&self) -> String { format!("{} byte ROM cartridge ({})", self.image.len(), self.name) }
+    fn plug(&mut self, rom: &mut [u8]) {
+        let n = self.image.len().min(rom.len());
+        rom[..n].copy_from_slice(&self.image[..n]);
+    }
+    fn unplug(&mut self, rom: &mut [u8]) {
+        let n = self.image.len().min(rom.len());
+        rom[..n].fill(0xff);
+    }
+    fn autostart(&self) -> bool { true }
+}
+
+impl Core {
+    /// Returns the cartridges that are plugged in: the devices first and then whatever is in the slot
+    pub fn cartridges(&self) -> impl Iterator<Item = &dyn Cartridge> {
+        let slot = self.cart.as_deref().filter(|_| self.cart_inserted);
+        self.paks.iter().map(|p| p.as_ref()).chain(slot)
+    }
+    /// Returns the cartridges that are plugged in (see cartridges)
+    pub fn cartridges_mut(&mut self) -> impl Iterator<Item = &mut (dyn Cartridge + 'static)> {
+        let slot = self.cart.as_deref_mut().filter(|_| self.cart_inserted);
+        self.paks.iter_mut().map(|p| p.as_mut()).chain(slot)
+    }
+
+    /// Called after each instruction: skips ahead while a cartridge halts the CPU and asserts the
+    /// NMI a cartridge starts
+    pub fn cartridge_step(&mut self) {
+        let mut nmi = false;
+        let slot = self.cart.as_deref_mut().filter(|_| self.cart_inserted);
+        for cart in self.paks.iter_mut().map(|p| p.as_mut()).chain(slot) {
+            if let Some(until) = cart.halted_until() {
+                self.clock_cycles = self.clock_cycles.max(until);
+            }
+            nmi |= cart.step(self.clock_cycles);
+        }
+        if nmi {
+            self.assert_nmi();
+        }
+    }
+    /// Called on every hsync: pulls the CART line for the cartridges that pull it and returns true
+    /// if one pulls IRQ
+    pub fn cartridge_tick(&mut self) -> bool {
+        let (mut cart_line, mut irq) = (false, false);
+        let cycle = self.clock_cycles;
+        for cart in self.cartridges_mut() {
+            match cart.tick(cycle) {
+                Some(Line::Cart) => cart_line = true,
+                Some(Line::Irq) => irq = true,
+                None => {}
+            }
+        }
+        self.cart_pending |= cart_line;
+        irq
+    }
+
+    /// Returns the CTS window of memory
+    fn cart_window(&mut self) -> &mut [u8] { &mut self.raw_ram[ROM_START as usize..=ROM_END as usize] }
+
+    /// simulates the presence of a cartridge (aka "program pak")
+    /// by loading a binary file at address 0xC000 and plugging it into the slot.
+    pub fn load_cart(&mut self, cart_path: &Path) -> Result<usize, Error> {
+        let size = self.load_bin(cart_path, ROM_START)?;
+        let start = ROM_START as usize;
+        let image = self.raw_ram[start..start + size].to_vec();
+        let name = cart_path.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string());
+        self.plug_cart(Box::new(RomCart::new(&name, image)), Some(cart_path.with_extension("sav")))?;
+        self.cheats.open_for(cart_path);
+        Ok(size)
+    }
+
+    /// puts a cartridge in the slot (replacing any that was there) and restores its saved state
+    pub fn plug_cart(&mut self, mut cart: Box<dyn Cartridge>, state_path: Option<PathBuf>) -> Result<(), Error> {
+        // only stateful cartridges look for a saved state
+        if let Some(path) = state_path.as_ref().filter(|p| p.exists() && cart.save_state().is_some()) {
+            cart.load_state(&std::fs::read(path)?)?;
+            verbose_println!("loaded cartridge state from {}", path.display());
+        }
+        cart.plug(self.cart_window());
+        self.cart_pending = cart.autostart();
+        self.cart = Some(cart);
+        self.cart_state_path = state_path;
+        self.cart_inserted = true;
+        Ok(())
+    }
+
+    /// simulates pulling the cartridge out of the running machine.
+    /// The cartridge's address space reads as 0xFF (nothing drives the bus) and the CART line goes quiet.
+    pub fn pull_cart(&mut self) {
+        if let Some(mut cart) = self.cart.take().filter(|_| self.cart_inserted) {
+            cart.unplug(self.cart_window());
+            self.cart = Some(cart);
+            self.cart_inserted = false;
+            self.cart_pending = false;
+            info!("{}", tr!("cartridge pulled"));
+        }
+    }
+
+    /// simulates pushing the most recently loaded cartridge back in. The CART line raises a FIRQ
+    /// as it does at power-on and, with --cart-reset, the machine is reset as well.
+    /// Returns true if the machine was reset.
+    pub fn insert_cart(&mut self) -> Result<bool, Error> {
+        let mut cart = match self.cart.take() {
+            Some(cart) if !self.cart_inserted => cart,
+            Some(cart) => {
+                self.cart = Some(cart);
+                return Ok(false);
+            }
+            None => {
+                warn!("{}", tr!("no cartridge has been loaded (use --cart or the debugger's cart command)"));
+                return Ok(false);
+            }
+        };
+        cart.plug(self.cart_window());
+        self.cart_pending = cart.autostart();
+        self.cart = Some(cart);
+        self.cart_inserted = true;
+        info!("{}", tr!("cartridge inserted"));
+        if config::ARGS.cart_reset {
+            self.warm_reset()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// saves the cartridge's state (if it has any) so the next session can restore it
+    pub fn save_cart_state(&self) -> Result<(), Error> {
+        if let (Some(state), Some(path)) = (self.cart.as_ref().and_then(|c| c.save_state()), self.cart_state_path.as_ref()) {
+            std::fs::write(path, state)?;
+            info!("cartridge state saved to {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_cart() {
+        assert!(in_slot(0xc000) && in_slot(0xfeff) && in_slot(0xff40) && in_slot(0xff5f));
+        assert!(!in_slot(0xbfff) && !in_slot(0xff00) && !in_slot(0xff60));
+        let mut window = [0u8; 8];
+        let mut cart = RomCart::new("test.rom", vec![1, 2, 3, 4]);
+        cart.plug(&mut window);
+        assert_eq!(window, [1, 2, 3, 4, 0, 0, 0, 0]);
+        cart.unplug(&mut window);
+        assert_eq!(window, [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        assert!(cart.autostart() && cart.read(0xc000, 0).is_none() && cart.save_state().is_none());
+    }
+}
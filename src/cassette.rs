@@ -0,0 +1,398 @@
+//! The cassette recorder, playing and recording .CAS and .WAV files (--cassette).
+//!
+//! The tape is wired the way the CoCo's cassette port is: the relay that switches the motor is
+//! PIA1's CA2 ($FF21 bit 3), what the tape plays is read as PIA1 PA0 ($FF20 bit 0) and what's
+//! recorded is the DAC's output. Nothing is patched in the ROM, so CLOAD, CLOADM, CSAVE,
+//! CSAVEM and programs with their own tape routines all work the same way.
+//!
+//! The tape only moves while the motor is on. Playing a .CAS file turns each of its bits into
+//! one cycle of a square wave (2400 Hz for a 1 and 1200 Hz for a 0, see tape.rs), timed by the
+//! emulated clock. A .WAV file (a recording of a real tape, say) is played as it is: PA0 is set
+//! while the signal is above zero, like the CoCo's comparator, with some hysteresis so that
+//! noise near zero isn't read as cycles.
+//!
+//! Recording watches the DAC. Once it's putting out cassette cycles (the time it spends above
+//! the middle of its range is half a 1200 or 2400 Hz cycle) what it puts out is recorded at the
+//! tape's position, overwriting what was there: each half cycle becomes a bit of a .CAS file,
+//! or the DAC's level is sampled into a .WAV file. The file is saved when the motor stops.
+use super::*;
+use crate::core::CPU_HZ;
+use std::path::PathBuf;
+
+const ONE_CYCLES: u64 = CPU_HZ / 2400; // the length of a 1 bit
+const ZERO_CYCLES: u64 = CPU_HZ / 1200; // the length of a 0 bit
+/// half cycles longer than this are 0s
+const HALF_THRESHOLD: u64 = (ONE_CYCLES + ZERO_CYCLES) / 4;
+/// the DAC's middle value (see pia.rs)
+const DAC_MID: u8 = 32;
+/// the sample rate of a blank .WAV tape
+const WAV_RATE: u32 = 44100;
+/// how far past zero a .WAV file's signal has to go to switch PA0 (a fraction of its peak)
+const HYSTERESIS: f32 = 0.1;
+
+/// What's on the tape
+enum Media {
+    /// the bytes of a .CAS file
+    Cas(Vec<u8>),
+    /// the samples (from -1 to 1) of a .WAV file and their rate
+    Wav(Vec<f32>, u32),
+}
+
+pub struct Cassette {
+    path: PathBuf,
+    media: Media,
+    motor: bool,
+    pos: usize,        // the position of the tape (in bits of a .CAS file or samples of a .WAV file)
+    pos_start: u64,    // the cycle at which the tape was at pos
+    level: f32,        // how far past zero a .WAV file's signal has to go to switch PA0
+    playing: bool,     // the .WAV file's signal is above zero (with hysteresis)
+    dac: u8,           // the DAC's value when it was last written
+    rise: Option<u64>, // the cycle at which the DAC last went above its middle value
+    shift: u8,         // recorded bits that don't make a byte yet
+    shift_count: u8,   // the number of bits in shift
+    recorded: usize,   // the next sample of a .WAV file to record
+    recording: bool,   // cassette cycles have been recorded since the motor started
+    dirty: bool,       // what was recorded hasn't been saved yet
+}
+impl Cassette {
+    /// Puts the tape in a .CAS or .WAV file in the recorder; a file that doesn't exist is a
+    /// blank tape
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        let wav = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+        let media = match std::fs::read(path) {
+            Ok(bytes) if wav => {
+                let (samples, rate) = tape::read_wav(&bytes).map_err(|e| general_err!("{}: {}", path.display(), e))?;
+                Media::Wav(samples, rate)
+            }
+            Ok(bytes) => Media::Cas(bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && wav => Media::Wav(Vec::new(), WAV_RATE),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Media::Cas(Vec::new()),
+            Err(e) => return Err(general_err!("failed to read {}: {}", path.display(), e)),
+        };
+        let level = match &media {
+            Media::Wav(samples, _) => samples.iter().fold(0f32, |peak, s| peak.max(s.abs())) * HYSTERESIS,
+            Media::Cas(_) => 0.0,
+        };
+        Ok(Cassette {
+            path: path.to_path_buf(),
+            media,
+            motor: false,
+            pos: 0,
+            pos_start: 0,
+            level,
+            playing: false,
+            dac: 0,
+            rise: None,
+            shift: 0,
+            shift_count: 0,
+            recorded: 0,
+            recording: false,
+            dirty: false,
+        })
+    }
+    /// Loads the tape given on the command line (if any)
+    pub fn from_args() -> Result<Option<Self>, Error> {
+        config::ARGS.cassette.as_deref().map(Cassette::new).transpose()
+    }
+    /// Returns the position of the tape and its length (in bytes of a .CAS file or seconds of
+    /// a .WAV file)
+    pub fn position(&self) -> (f64, f64) {
+        match &self.media {
+            Media::Cas(data) => ((self.pos / 8) as f64, data.len() as f64),
+            Media::Wav(samples, rate) => (self.pos as f64 / *rate as f64, samples.len() as f64 / *rate as f64),
+        }
+    }
+    /// Rewinds the tape to the beginning
+    pub fn rewind(&mut self, now: u64) {
+        self.pos = 0;
+        self.pos_start = now;
+        (self.shift_count, self.recording) = (0, false);
+    }
+    /// Returns the state of the recorder at cycle now for a snapshot: the motor, the DAC, the
+    /// position of the tape (4 bytes) and the path of the tape
+    pub fn save_state(&mut self, now: u64) -> Vec<u8> {
+        self.advance(now);
+        let pos = if self.motor { self.sample_at(now) } else { self.pos };
+        let mut state = vec![self.motor as u8, self.dac];
+        state.extend((pos as u32).to_be_bytes());
+        state.extend(self.path.to_string_lossy().as_bytes());
+        state
+    }
+    /// Puts the recorder in a state saved by save_state at cycle now. Returns a warning if the
+    /// state was saved with a different tape.
+    pub fn load_state(&mut self, state: &[u8], now: u64) -> Option<String> {
+        let len = match &self.media {
+            Media::Cas(data) => data.len() * 8,
+            Media::Wav(samples, _) => samples.len(),
+        };
+        (self.motor, self.dac) = (state[0] != 0, state[1]);
+        self.pos = (u32::from_be_bytes(state[2..6].try_into().unwrap()) as usize).min(len);
+        self.pos_start = now;
+        (self.rise, self.shift_count, self.recording) = (None, 0, false);
+        let path = String::from_utf8_lossy(&state[6..]);
+        let other = path != self.path.to_string_lossy();
+        other.then(|| format!("the cassette held {:?} when the snapshot was saved", path))
+    }
+    /// Returns the length (in cycles) of a bit of a .CAS file
+    fn bit_cycles(data: &[u8], bit: usize) -> u64 {
+        if data[bit / 8] & (1 << (bit % 8)) != 0 {
+            ONE_CYCLES
+        } else {
+            ZERO_CYCLES
+        }
+    }
+    /// Returns the sample of a .WAV file that's under the head at cycle now
+    fn sample_at(&self, now: u64) -> usize {
+        match self.media {
+            Media::Wav(_, rate) if self.motor => {
+                let samples = now.saturating_sub(self.pos_start) as u128 * rate as u128 / CPU_HZ as u128;
+                self.pos + samples as usize
+            }
+            _ => self.pos,
+        }
+    }
+    /// Moves the tape on to where it is at cycle now (if the motor is running and it's playing)
+    fn advance(&mut self, now: u64) {
+        if !self.motor || self.recording {
+            return;
+        }
+        match &self.media {
+            Media::Cas(data) => {
+                while self.pos < data.len() * 8 && now - self.pos_start >= Cassette::bit_cycles(data, self.pos) {
+                    self.pos_start += Cassette::bit_cycles(data, self.pos);
+                    self.pos += 1;
+                }
+                if self.pos >= data.len() * 8 {
+                    // past the end of the tape there's nothing to play
+                    self.pos_start = now;
+                }
+            }
+            // the position of a .WAV tape is worked out from the time (see sample_at)
+            Media::Wav(..) => {}
+        }
+    }
+    /// Returns what the tape is playing at cycle now. Each bit of a .CAS file is high for the
+    /// first half of its cycle and low for the second.
+    pub fn input(&mut self, now: u64) -> bool {
+        if !self.motor {
+            return false;
+        }
+        self.advance(now);
+        match &self.media {
+            Media::Cas(data) => {
+                self.pos < data.len() * 8 && now - self.pos_start < Cassette::bit_cycles(data, self.pos) / 2
+            }
+            Media::Wav(samples, _) => {
+                match samples.get(self.sample_at(now)) {
+                    Some(&s) if s > self.level => self.playing = true,
+                    Some(&s) if s < -self.level => self.playing = false,
+                    Some(_) => {}
+                    None => self.playing = false,
+                }
+                self.playing
+            }
+        }
+    }
+    /// Updates the recorder with the state of the relay and the DAC at cycle now (called when
+    /// either of them is written)
+    pub fn update(&mut self, motor: bool, dac: u8, now: u64) -> Result<(), Error> {
+        if motor != self.motor {
+            self.advance(now);
+            self.record_level(now);
+            self.pos = self.sample_at(now);
+            self.motor = motor;
+            self.pos_start = now;
+            (self.rise, self.shift_count, self.recording) = (None, 0, false);
+            verbose_println!("cassette motor {}: {}", if motor { "on" } else { "off" }, self);
+            if !motor {
+                self.save()?;
+            }
+        }
+        let (high, was_high) = (dac >= DAC_MID, self.dac >= DAC_MID);
+        if self.motor && high != was_high {
+            if high {
+                self.rise = Some(now);
+            } else if let Some(rise) = self.rise.take() {
+                // anything longer than a 0 is the DAC being used for something else
+                let half = now - rise;
+                if half < ZERO_CYCLES {
+                    self.record(half < HALF_THRESHOLD, rise);
+                }
+            }
+        }
+        self.record_level(now);
+        self.dac = dac;
+        Ok(())
+    }
+    /// Adds a half cycle that started at cycle rise to the recording
+    fn record(&mut self, bit: bool, rise: u64) {
+        if !self.recording {
+            self.advance(rise);
+            match self.media {
+                // recording starts at the next byte boundary
+                Media::Cas(_) => self.pos = self.pos.div_ceil(8) * 8,
+                // or with the half cycle that was just recorded
+                Media::Wav(..) => {
+                    (self.pos, self.pos_start) = (self.sample_at(rise), rise);
+                    self.recorded = self.pos;
+                }
+            }
+            self.recording = true;
+        }
+        let Media::Cas(data) = &mut self.media else {
+            return;
+        };
+        // bytes are recorded least significant bit first
+        self.shift = self.shift >> 1 | (bit as u8) << 7;
+        self.shift_count += 1;
+        if self.shift_count == 8 {
+            let byte = self.pos / 8;
+            if byte < data.len() {
+                data[byte] = self.shift;
+            } else {
+                data.resize(byte, 0);
+                data.push(self.shift);
+            }
+            (self.pos, self.shift_count) = (self.pos + 8, 0);
+            self.dirty = true;
+        }
+    }
+    /// Records the DAC's level from the last time it was written up to cycle now (on a .WAV tape)
+    fn record_level(&mut self, now: u64) {
+        if !self.motor || !self.recording {
+            return;
+        }
+        let (start, end, level) = (self.recorded, self.sample_at(now), (self.dac as f32 - 31.5) / 32.0);
+        if let Media::Wav(samples, _) = &mut self.media {
+            samples.resize(samples.len().max(end), 0.0);
+            samples[start.min(end)..end].fill(level);
+            (self.recorded, self.dirty) = (end, true);
+        }
+    }
+    /// Writes the tape back to its file if anything was recorded
+    pub fn save(&mut self) -> Result<(), Error> {
+        if self.dirty {
+            let bytes = match &self.media {
+                Media::Cas(data) => data.clone(),
+                Media::Wav(samples, rate) => tape::wav(samples, *rate),
+            };
+            let path = self.path.display();
+            std::fs::write(&self.path, bytes).map_err(|e| general_err!("failed to save {}: {}", path, e))?;
+            info!("saved the tape to {}", path);
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for Cassette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ((pos, len), motor) = (self.position(), if self.motor { "on" } else { "off" });
+        match self.media {
+            Media::Cas(_) => write!(f, "{} at byte {} of {}, motor {}", self.path.display(), pos, len, motor),
+            Media::Wav(..) => write!(f, "{} at {:.1}s of {:.1}s, motor {}", self.path.display(), pos, len, motor),
+        }
+    }
+}
+
+impl Core {
+    /// Lets the recorder know that PIA1's side A was written
+    pub fn cassette_update(&self) -> Result<(), Error> {
+        if let Some(cassette) = self.cassette.as_ref() {
+            let pia1 = self.lock_pia1();
+            cassette.borrow_mut().update(pia1.cassette_motor(), pia1.dac(), self.clock_cycles)?;
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the bytes the tape plays (measuring each half cycle the way the ROM does)
+    fn play(tape: &mut Cassette, start: u64, bits: usize) -> Vec<u8> {
+        let mut played = Vec::new();
+        let (mut was_high, mut rise) = (false, start);
+        for t in start..start + bits as u64 * ZERO_CYCLES {
+            let high = tape.input(t);
+            if high && !was_high {
+                rise = t;
+            } else if !high && was_high {
+                played.push(t - rise < HALF_THRESHOLD);
+            }
+            was_high = high;
+        }
+        played.chunks_exact(8).map(|bits| bits.iter().rev().fold(0u8, |b, &bit| b << 1 | bit as u8)).collect()
+    }
+
+    /// Records bytes the way the ROM does, as cycles that start halfway up the DAC's range, and
+    /// returns the cycle after the last one
+    fn record(tape: &mut Cassette, mut now: u64, bytes: &[u8]) -> Result<u64, Error> {
+        for byte in bytes {
+            for i in 0..8 {
+                let cycles = if byte & (1 << i) != 0 { ONE_CYCLES } else { ZERO_CYCLES };
+                tape.update(true, 48, now)?;
+                tape.update(true, 16, now + cycles / 2)?;
+                now += cycles;
+            }
+        }
+        Ok(now)
+    }
+
+    #[test]
+    fn wav_tapes() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("coco-cassette-{}.wav", std::process::id()));
+        let bytes = [0x55, 0x55, 0x3c, 0x01, 0x80];
+        // a recording of a real tape (with a little noise)
+        let noise = [0.05, -0.05].iter().cycle();
+        let noisy: Vec<f32> = tape::encode(&bytes, 22050).iter().zip(noise).map(|(s, n)| s + n).collect();
+        std::fs::write(&path, tape::wav(&noisy, 22050))?;
+        let mut tape = Cassette::new(&path)?;
+        tape.update(true, 32, 0)?;
+        assert_eq!(play(&mut tape, 0, 40), bytes);
+        tape.update(false, 32, 40 * ZERO_CYCLES)?;
+
+        // recording over it from the start
+        tape.rewind(0);
+        tape.update(true, 32, 0)?;
+        // (an end of file block)
+        let end = record(&mut tape, 100, &[0x55, 0x55, 0x3c, 0xff, 0x00, 0xff, 0x55])?;
+        tape.update(false, 32, end)?;
+        let (samples, rate) = tape::read_wav(&std::fs::read(&path)?)?;
+        assert_eq!(rate, 22050);
+        let decoded = tape::decode(&samples, rate, 1800.0, 0.1);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!((decoded[0].kind, decoded[0].data.len(), &decoded[0].error), (0xff, 0, &None));
+        assert!(tape.to_string().ends_with("s, motor off"));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn record_and_play() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("coco-cassette-{}.cas", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut tape = Cassette::new(&path)?;
+        tape.update(true, 0, 1000)?;
+        let now = record(&mut tape, 1000, &[0x55, 0x3c])?;
+        tape.update(false, 32, now)?;
+        assert_eq!(std::fs::read(&path)?, [0x55, 0x3c]);
+        assert_eq!(tape.position(), (2.0, 2.0));
+
+        // playing it back gives the same cycles
+        tape.rewind(now);
+        tape.update(true, 32, now)?;
+        assert_eq!(play(&mut tape, now, 16), [0x55, 0x3c]);
+        assert!(!tape.input(now + 20 * ZERO_CYCLES));
+
+        // the relay is CA2 and the tape is read through PA0
+        let mut core = Core::headless().unwrap();
+        core.cassette = Some(std::cell::RefCell::new(Cassette::new(&path)?));
+        core._write_u8(memory::AccessType::Generic, 0xff21, 0x3c)?;
+        assert!(core.pia1.lock().unwrap().cassette_motor());
+        assert_eq!(core._read_u8(memory::AccessType::Generic, 0xff20, None)? & 1, 1);
+        core.clock_cycles += ONE_CYCLES / 2;
+        assert_eq!(core._read_u8(memory::AccessType::Generic, 0xff20, None)? & 1, 0);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Translation of the ACIA's byte stream for terminals and host-side services.
+//!
+//! Bytes from the host ("in") and bytes from the CoCo ("out") each pass through a 256 entry
+//! table. By default DEL is turned into backspace on the way in and --acia-case swaps the case
+//! of letters on the way in. A --acia-charmap file can replace any entry with lines like:
+//! ```text
+//! # direction  from  to      (bytes are hex)
+//! in           7F    08
+//! out          5E    18
+//! ```
+//! --acia-7bit clears bit 7 in both directions and --acia-crlf translates line endings (the
+//! CoCo ends lines with CR; the host's CR LF or LF becomes CR and the CoCo's CR becomes CR LF).
+//! None of this applies to a null-modem peer (see acia.rs), which gets every byte untouched.
+use super::*;
+
+#[derive(Clone)]
+pub struct CharMap {
+    input: [u8; 256],
+    output: [u8; 256],
+    crlf: bool,
+    strip: bool,
+    last_cr: bool, // the previous input byte was CR (so a following LF is dropped)
+}
+impl Default for CharMap {
+    fn default() -> Self {
+        let mut input: [u8; 256] = std::array::from_fn(|i| i as u8);
+        input[0x7f] = 8; // delete --> backspace
+        CharMap {
+            input,
+            output: std::array::from_fn(|i| i as u8),
+            crlf: false,
+            strip: false,
+            last_cr: false,
+        }
+    }
+}
+impl CharMap {
+    pub fn from_args() -> Result<Self, Error> {
+        let mut map = CharMap {
+            crlf: config::ARGS.acia_crlf,
+            strip: config::ARGS.acia_7bit,
+            ..Default::default()
+        };
+        if config::ARGS.acia_case {
+            for c in b'A'..=b'Z' {
+                map.input[c as usize] = c + 0x20;
+                map.input[(c + 0x20) as usize] = c;
+            }
+        }
+        if let Some(path) = config::ARGS.acia_charmap.as_ref() {
+            let text = std::fs::read_to_string(path)?;
+            map.parse(&text).map_err(|e| general_err!("{}: {}", path.display(), e))?;
+        }
+        Ok(map)
+    }
+    /// Applies the entries in a character map file on top of the current tables
+    pub fn parse(&mut self, text: &str) -> Result<(), Error> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let byte = |s: &str| u8::from_str_radix(s.trim_start_matches("0x").trim_start_matches('$'), 16).ok();
+            match (fields.first().map(|d| d.to_ascii_lowercase()).as_deref(), &fields[1..]) {
+                (Some("in"), [from, to]) if byte(from).is_some() && byte(to).is_some() => {
+                    self.input[byte(from).unwrap() as usize] = byte(to).unwrap()
+                }
+                (Some("out"), [from, to]) if byte(from).is_some() && byte(to).is_some() => {
+                    self.output[byte(from).unwrap() as usize] = byte(to).unwrap()
+                }
+                _ => return Err(general_err!("line {}: expected in|out <from> <to>", i + 1)),
+            }
+        }
+        Ok(())
+    }
+    /// Translates a byte from the host. Returns None if the byte should be dropped.
+    pub fn inbound(&mut self, byte: u8) -> Option<u8> {
+        let byte = if self.strip { byte & 0x7f } else { byte };
+        if self.crlf {
+            let after_cr = std::mem::replace(&mut self.last_cr, byte == b'\r');
+            match byte {
+                b'\n' if after_cr => return None,
+                b'\n' => return Some(b'\r'),
+                _ => {}
+            }
+        }
+        Some(self.input[byte as usize])
+    }
+    /// Translates a byte from the CoCo, appending the result to out
+    pub fn outbound(&self, byte: u8, out: &mut Vec<u8>) {
+        let byte = self.output[byte as usize];
+        let byte = if self.strip { byte & 0x7f } else { byte };
+        match byte {
+            // erase the character on the terminal
+            8 => out.extend_from_slice(&[8, 0x20, 8]),
+            b'\r' if self.crlf => out.extend_from_slice(b"\r\n"),
+            _ => out.push(byte),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate() {
+        let mut map = CharMap {
+            crlf: true,
+            strip: true,
+            ..Default::default()
+        };
+        map.parse("# comment\nin 41 42\nout 0x5e $18\n").unwrap();
+        assert!(map.parse("sideways 41 42").is_err());
+        let input: Vec<u8> = b"A\r\nb\n\x7f\xc1".iter().filter_map(|&b| map.inbound(b)).collect();
+        assert_eq!(input, b"B\rb\r\x08B");
+        let mut out = Vec::new();
+        for b in b"^\r\x08" {
+            map.outbound(*b, &mut out);
+        }
+        assert_eq!(out, b"\x18\r\n\x08 \x08");
+    }
+}
@@ -0,0 +1,234 @@
+//! Cheats: searching RAM for values and freezing them.
+//!
+//! The debugger's `cheat search` command narrows down the RAM addresses that might hold a value
+//! (the number of lives, say) the same way game trainers do: start with every address and keep
+//! the ones that equal a value, went up or down, or changed or stayed the same since the last
+//! search. Once the right address is found `cheat add` freezes it: the value is written back
+//! at every vsync so the program can never change it.
+//!
+//! Cheats are saved to and loaded from a file named after the program or cartridge with the
+//! extension .cht (or the file given with --cheats), one cheat per line:
+//! ```text
+//! # address value name
+//! 0152 09 lives
+//! 0160 0000 timer
+//! ```
+//! The address and value are hex; a value of more than two digits is a 16-bit word.
+use super::*;
+use std::path::{Path, PathBuf};
+
+/// the most search results that are listed
+pub const MAX_LISTED: usize = 20;
+
+/// How a search narrows down its candidates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Eq(u8),
+    Ne(u8),
+    Gt(u8),
+    Lt(u8),
+    Changed,
+    Same,
+    Up,
+    Down,
+}
+impl Filter {
+    /// Parses a filter (e.g., "= 3" or "changed")
+    pub fn parse(op: &str, val: Option<u8>) -> Option<Self> {
+        Some(match (op, val) {
+            ("=", Some(v)) => Filter::Eq(v),
+            ("!=", Some(v)) => Filter::Ne(v),
+            (">", Some(v)) => Filter::Gt(v),
+            ("<", Some(v)) => Filter::Lt(v),
+            ("changed", None) => Filter::Changed,
+            ("same", None) => Filter::Same,
+            ("up", None) => Filter::Up,
+            ("down", None) => Filter::Down,
+            _ => return None,
+        })
+    }
+    /// Returns true if an address that held old and now holds new is still a candidate
+    pub fn keeps(&self, old: u8, new: u8) -> bool {
+        match *self {
+            Filter::Eq(v) => new == v,
+            Filter::Ne(v) => new != v,
+            Filter::Gt(v) => new > v,
+            Filter::Lt(v) => new < v,
+            Filter::Changed => new != old,
+            Filter::Same => new == old,
+            Filter::Up => new > old,
+            Filter::Down => new < old,
+        }
+    }
+}
+
+/// A search of RAM in progress
+#[derive(Debug)]
+pub struct Search {
+    /// the contents of RAM at the last search
+    ram: Vec<u8>,
+    /// and at the search before that
+    prev: Vec<u8>,
+    /// the addresses that still match
+    pub candidates: Vec<u16>,
+}
+impl Search {
+    /// Starts a search with every address in ram as a candidate
+    pub fn new(ram: &[u8]) -> Self {
+        Search { ram: ram.to_vec(), prev: ram.to_vec(), candidates: (0..ram.len()).map(|a| a as u16).collect() }
+    }
+    /// Keeps the candidates that pass the filter and returns how many are left
+    pub fn filter(&mut self, ram: &[u8], filter: Filter) -> usize {
+        self.prev = std::mem::replace(&mut self.ram, ram.to_vec());
+        let prev = &self.prev;
+        self.candidates.retain(|&a| filter.keeps(prev[a as usize], ram[a as usize]));
+        self.candidates.len()
+    }
+    /// Returns the value an address had before the last search
+    pub fn prev(&self, addr: u16) -> u8 { self.prev[addr as usize] }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub addr: u16,
+    pub val: u8u16,
+    pub name: String,
+}
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.val {
+            u8u16::u8(v) => write!(f, "{:04X} {:02X}", self.addr, v)?,
+            u8u16::u16(v) => write!(f, "{:04X} {:04X}", self.addr, v)?,
+        }
+        if !self.name.is_empty() {
+            write!(f, " {}", self.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a cheat file
+pub fn parse(text: &str) -> Result<Vec<Cheat>, Error> {
+    let mut cheats = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let cheat = match fields.as_slice() {
+            [] => continue,
+            [addr, val, name @ ..] => {
+                let addr = u16::from_str_radix(addr, 16);
+                let val = match val.len() {
+                    1 | 2 => u8::from_str_radix(val, 16).map(u8u16::u8),
+                    _ => u16::from_str_radix(val, 16).map(u8u16::u16),
+                };
+                match (addr, val) {
+                    (Ok(addr), Ok(val)) => Cheat { addr, val, name: name.join(" ") },
+                    _ => return Err(general_err!("line {}: bad address or value", i + 1)),
+                }
+            }
+            _ => return Err(general_err!("line {}: expected \"<address> <value> [<name>]\"", i + 1)),
+        };
+        cheats.push(cheat);
+    }
+    Ok(cheats)
+}
+
+#[derive(Debug, Default)]
+pub struct Cheats {
+    pub cheats: Vec<Cheat>,
+    pub search: Option<Search>,
+    /// the file the cheats are saved to
+    pub path: Option<PathBuf>,
+}
+impl Cheats {
+    /// Creates the cheats, loading the file given with --cheats (if any)
+    pub fn from_args() -> Self {
+        let mut cheats = Cheats::default();
+        if let Some(path) = config::ARGS.cheats.as_ref() {
+            cheats.open(path);
+        }
+        cheats
+    }
+    /// Loads the cheats in path (if it exists) and saves them there from now on
+    pub fn open(&mut self, path: &Path) {
+        if path.exists() {
+            match self.load(path) {
+                Ok(n) => info!("loaded {} cheat(s) from {}", n, path.display()),
+                Err(e) => warn!("failed to load cheats from {}: {}", path.display(), e),
+            }
+        }
+        self.path = Some(path.to_path_buf());
+    }
+    /// Uses the cheat file that goes with a program or cartridge (unless --cheats was given)
+    pub fn open_for(&mut self, program: &Path) {
+        if config::ARGS.cheats.is_none() {
+            self.open(&program.with_extension("cht"));
+        }
+    }
+    /// Adds the cheats in a file. Returns how many there were.
+    pub fn load(&mut self, path: &Path) -> Result<usize, Error> {
+        let cheats = parse(&std::fs::read_to_string(path)?)?;
+        let n = cheats.len();
+        self.cheats.extend(cheats);
+        Ok(n)
+    }
+    /// Saves the cheats to path (or the file they were loaded from)
+    pub fn save(&mut self, path: Option<&Path>) -> Result<PathBuf, Error> {
+        if config::kiosk() {
+            return Err(general_err!("cheats can't be saved in kiosk mode"));
+        }
+        let path = path.map(Path::to_path_buf).or_else(|| self.path.clone());
+        let path = path.ok_or_else(|| general_err!("no cheat file (give one to save to)"))?;
+        let text: String = self.cheats.iter().map(|c| format!("{}\n", c)).collect();
+        std::fs::write(&path, text)?;
+        self.path = Some(path.clone());
+        Ok(path)
+    }
+}
+
+impl Core {
+    /// Writes the value of every cheat to memory (called at each vsync)
+    pub fn apply_cheats(&mut self) -> Result<(), Error> {
+        for i in 0..self.cheats.cheats.len() {
+            let Cheat { addr, val, .. } = self.cheats.cheats[i];
+            self._write_u8u16(memory::AccessType::System, addr, val)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search() {
+        let mut ram = vec![0u8; 8];
+        ram[2] = 3;
+        ram[5] = 3;
+        let mut search = Search::new(&ram);
+        assert_eq!(search.filter(&ram, Filter::parse("=", Some(3)).unwrap()), 2);
+        ram[2] = 2;
+        ram[5] = 4;
+        assert_eq!(search.filter(&ram, Filter::parse("changed", None).unwrap()), 2);
+        ram[5] = 3;
+        assert_eq!(search.filter(&ram, Filter::Down), 1);
+        assert_eq!((search.candidates[0], search.prev(5)), (5, 4));
+        assert!(Filter::parse("=", None).is_none() && Filter::parse("up", Some(1)).is_none());
+        assert!(Filter::Gt(2).keeps(0, 3) && Filter::Lt(2).keeps(0, 1) && Filter::Ne(2).keeps(2, 1));
+        assert!(Filter::Same.keeps(7, 7) && Filter::Up.keeps(1, 2) && !Filter::Up.keeps(2, 2));
+    }
+
+    #[test]
+    fn files() {
+        let cheats = parse("# lives\n0152 09 lives left\n\n0160 0000 timer # frozen\n").unwrap();
+        assert_eq!(cheats[0], Cheat { addr: 0x152, val: u8u16::u8(9), name: "lives left".to_string() });
+        assert_eq!(cheats[1].val, u8u16::u16(0));
+        assert_eq!(cheats[1].to_string(), "0160 0000 timer");
+        assert_eq!(Cheat { name: String::new(), ..cheats[1].clone() }.to_string(), "0160 0000");
+        assert_eq!(parse(&cheats[0].to_string()).unwrap()[0], cheats[0]);
+        for bad in ["0152", "xyz 09", "0152 zz", "10000 00"] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
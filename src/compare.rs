@@ -0,0 +1,208 @@
+//! Comparison against a trace from another emulator.
+//!
+//! With --compare-trace the registers are checked after every instruction against a trace of
+//! the same ROM or program made by MAME or XRoar, and the emulator stops at the first
+//! instruction where they differ. That's the fastest way to find a bug in the CPU emulation.
+//!
+//! Each line of the trace that has an instruction address (four hex digits followed by ':' as
+//! MAME writes them or by '|' as XRoar does) is one instruction. Registers are given on the
+//! same line as `name=value` in hex, e.g., with MAME's tracelog:
+//! ```text
+//! trace cpu.log,maincpu,,{tracelog "A=%02X B=%02X X=%04X Y=%04X U=%04X S=%04X DP=%02X CC=%02X ",a,b,x,y,u,s,dp,cc}
+//! ```
+//! Only the registers on a line are compared. MAME shows them before the instruction runs and
+//! XRoar after it runs. Comparison starts at the first line whose address is the address of the
+//! first instruction coco executes, and the emulator exits when the trace runs out. A trace that
+//! never gets to that address (so nothing was compared) is an error.
+use super::*;
+use registers::Name;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::path::Path;
+
+/// the number of matching trace lines shown before a divergence
+const CONTEXT: usize = 8;
+
+/// An instruction in the trace
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record {
+    pub pc: u16,
+    /// true if the registers are the state after the instruction ran (XRoar)
+    pub after: bool,
+    pub regs: Vec<(Name, u16)>,
+}
+impl Record {
+    /// Parses a line of the trace (None if it isn't an instruction)
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut addr = None;
+        let mut regs = Vec::new();
+        for token in line.split_whitespace() {
+            let token = token.trim_end_matches(',');
+            if let Some((name, val)) = token.split_once('=') {
+                let name = Name::from_str(name);
+                let val = u16::from_str_radix(val.trim_start_matches('$'), 16);
+                if let (false, Ok(val)) = (name == Name::Z, val) {
+                    regs.push((name, val));
+                }
+            } else if addr.is_none() && token.len() == 5 && token.ends_with([':', '|']) {
+                addr = u16::from_str_radix(&token[..4], 16).ok().map(|pc| (pc, token.ends_with('|')));
+            }
+        }
+        addr.map(|(pc, after)| Record { pc, after, regs })
+    }
+    /// Returns the registers that don't match (name, trace's value, coco's value)
+    pub fn mismatches(&self, regs: &registers::Set) -> Vec<(Name, u16, u16)> {
+        self.regs
+            .iter()
+            .map(|&(name, val)| (name, val, regs.get_register(name).u16()))
+            .filter(|(_, val, actual)| val != actual)
+            .collect()
+    }
+}
+
+pub struct TraceCompare {
+    lines: Lines<BufReader<File>>,
+    line_num: usize,
+    /// the most recent lines that matched
+    recent: VecDeque<String>,
+    started: bool,
+    count: u64,
+}
+impl TraceCompare {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        info!("comparing execution with the trace in {}", path.display());
+        Ok(TraceCompare {
+            lines: BufReader::new(File::open(path)?).lines(),
+            line_num: 0,
+            recent: VecDeque::with_capacity(CONTEXT),
+            started: false,
+            count: 0,
+        })
+    }
+    /// Opens the trace named by --compare-trace (if any)
+    pub fn from_args() -> Result<Option<Self>, Error> {
+        config::ARGS.compare_trace.as_deref().map(TraceCompare::open).transpose()
+    }
+    /// Returns the next instruction in the trace (and the line it's on)
+    fn next(&mut self) -> Result<Option<(Record, String)>, Error> {
+        for line in self.lines.by_ref() {
+            let line = line?;
+            self.line_num += 1;
+            if let Some(record) = Record::parse(&line) {
+                return Ok(Some((record, line)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Core {
+    /// Checks the instruction that just ran at pc (with registers before beforehand) against
+    /// the next instruction in the trace
+    pub fn compare_trace(&mut self, pc: u16, before: &registers::Set) -> Result<(), Error> {
+        let Some(cmp) = self.trace_compare.as_mut() else {
+            return Ok(());
+        };
+        let next = loop {
+            match cmp.next()? {
+                // skip ahead to where coco started
+                Some((record, _)) if !cmp.started && record.pc != pc => continue,
+                next => break next,
+            }
+        };
+        let Some((record, line)) = next else {
+            let count = cmp.count;
+            self.trace_compare = None;
+            if count == 0 {
+                let msg = format!("the trace never gets to {:04X} (where coco started) so nothing was compared", pc);
+                return Err(runtime_err!(Some(self.reg), "{}", msg));
+            }
+            info!("reached the end of the trace after {} matching instructions", count);
+            return Err(Error::new(ErrorKind::Exit, None, "trace comparison complete"));
+        };
+        if !cmp.started {
+            info!("trace comparison starts at line {}", cmp.line_num);
+            cmp.started = true;
+        }
+        let regs = if record.after { &self.reg } else { before };
+        let mut problems: Vec<String> = record
+            .mismatches(regs)
+            .iter()
+            .map(|&(name, val, actual)| {
+                let w = registers::reg_size(name) as usize * 2;
+                format!("{} is {:0w$X} (trace has {:0w$X})", name.to_str(), actual, val, w = w)
+            })
+            .collect();
+        if record.pc != pc {
+            problems.insert(0, format!("PC is {:04X} (trace has {:04X})", pc, record.pc));
+        }
+        if problems.is_empty() {
+            if cmp.recent.len() == CONTEXT {
+                cmp.recent.pop_front();
+            }
+            cmp.recent.push_back(line);
+            cmp.count += 1;
+            return Ok(());
+        }
+        println!("The last {} matching lines of the trace:", cmp.recent.len());
+        for recent in &cmp.recent {
+            println!("  {}", recent);
+        }
+        println!("Line {} of the trace:\n  {}", cmp.line_num, line);
+        println!("coco before: {}\ncoco after:  {}", before, self.reg);
+        let msg = format!(
+            "diverged from the trace at line {} after {} matching instructions: {}",
+            cmp.line_num,
+            cmp.count,
+            problems.join(", ")
+        );
+        // stop comparing so that execution can go on in the debugger
+        self.trace_compare = None;
+        Err(runtime_err!(Some(self.reg), "{}", msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() {
+        use Name::*;
+        let mame = Record::parse("A=12 B=00 X=0400 Y=0000 U=0000 S=7F36 DP=00 CC=D4 E03F: sta   $0400,x").unwrap();
+        assert_eq!(
+            mame,
+            Record {
+                pc: 0xe03f,
+                after: false,
+                regs: vec![(A, 0x12), (B, 0), (X, 0x400), (Y, 0), (U, 0), (S, 0x7f36), (DP, 0), (CC, 0xd4)]
+            }
+        );
+        let xroar = Record::parse("e03f| a789      STA    ,X+        cc=d4 a=12 b=00 dp=00 x=0401 y=0000").unwrap();
+        assert_eq!((xroar.pc, xroar.after, xroar.regs.len()), (0xe03f, true, 6));
+        assert_eq!(Record::parse("8000: nop").unwrap().regs, vec![]);
+        assert!(Record::parse("   (loops for 13 instructions)").is_none());
+        assert!(Record::parse("E03F sta $0400 A=12").is_none());
+        let mut regs = registers::Set { a: 0x12, x: 0x401, cc: Default::default(), ..Default::default() };
+        regs.cc.set_from_byte(0xd4);
+        assert_eq!(xroar.mismatches(&regs), vec![]);
+        regs.a = 0x13;
+        assert_eq!(xroar.mismatches(&regs), vec![(A, 0x12, 0x13)]);
+    }
+
+    #[test]
+    fn end_of_trace() {
+        let path = std::env::temp_dir().join(format!("coco-compare-{}.log", std::process::id()));
+        let mut core = Core::headless().unwrap();
+        let before = core.reg;
+        // a trace that never gets to where coco started checked nothing
+        std::fs::write(&path, "8000: nop\n8001: nop\n").unwrap();
+        core.trace_compare = Some(TraceCompare::open(&path).unwrap());
+        assert_eq!(core.compare_trace(0x4000, &before).unwrap_err().kind, ErrorKind::Runtime);
+        // one that matches and then runs out is a normal exit
+        core.trace_compare = Some(TraceCompare::open(&path).unwrap());
+        core.compare_trace(0x8001, &before).unwrap();
+        assert_eq!(core.compare_trace(0x8002, &before).unwrap_err().kind, ErrorKind::Exit);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,17 +1,51 @@
 #![allow(unused)]
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use clap::Parser;
+use crate::acia::FlowControl;
+use crate::basic::Checksum;
+use crate::convert::Format;
+use crate::disk::ExitPolicy;
+use crate::host::Trap;
+use crate::loadmap::OverlapPolicy;
+use crate::messages;
+use crate::pia::RepeatMode;
+use crate::printer::PrintFormat;
+use crate::recent::Recent;
+use crate::wordpak::Output;
+use crate::rng::RamPattern;
+use crate::tracefile::TraceFormat;
+use crate::warp::{Accuracy, VideoStandard};
+use clap::{Parser, Subcommand};
 use clap_num::maybe_hex;
-use lazy_static::lazy_static;
 use serde::Deserialize;
 
 #[derive(Parser, Debug)]
-#[command(author,version,about,long_about=None)]
+#[command(author,version,about,long_about=None,args_override_self=true)]
 pub struct Args {
-    /// Assembly (.asm, .s) or Hex (.hex) file to assemble/run/debug
+    #[command(subcommand)]
+    pub utility: Option<Utility>,
+
+    /// Assembly (.asm, .s), Hex (.hex), S-record (.s19, .srec), Disk BASIC (.bin), cassette (.cas) or
+    /// BASIC (.bas) file to assemble/run/debug (repeat, or separate paths with ':' (';' on Windows), to load
+    /// several files in order)
     #[arg(long)]
-    pub load: Option<PathBuf>,
+    pub load: Vec<PathBuf>,
+
+    /// Check that the last value on each DATA line of a .bas file matches the others
+    #[arg(long, value_enum)]
+    pub basic_checksum: Option<Checksum>,
+
+    /// Reload the .bas file given with --load whenever it's saved
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Type RUN after reloading a watched .bas file
+    #[arg(long, requires = "watch")]
+    pub watch_run: bool,
 
     /// Enable ACIA emulation
     #[arg(long)]
@@ -21,9 +55,48 @@ pub struct Args {
     #[arg(long,value_parser=maybe_hex::<u16>, default_value_t=0xffd0_u16)]
     pub acia_addr: u16,
 
-    /// TCP port on which to expose ACIA
-    #[arg(long, default_value_t = 6809_u16)]
-    pub acia_port: u16,
+    /// TCP port on which to expose ACIA (default is the first free port from 6809, so that several
+    /// instances can run at once)
+    #[arg(long)]
+    pub acia_port: Option<u16>,
+
+    /// Write the address the ACIA listens at to this file once it's listening (for scripts that
+    /// start several instances)
+    #[arg(long, value_name = "FILE")]
+    pub port_file: Option<PathBuf>,
+
+    /// Connect the ACIA to another instance of the emulator at host:port (null modem) instead of listening
+    #[arg(long)]
+    pub acia_connect: Option<String>,
+
+    /// Bytes the ACIA can hold for the program to read (1 is the 6551's data register)
+    #[arg(long, default_value_t = 1)]
+    pub acia_rx_fifo: usize,
+
+    /// Bytes the program can write to the ACIA before it has to wait for them to go out
+    #[arg(long, default_value_t = 1)]
+    pub acia_tx_fifo: usize,
+
+    /// How the ACIA stops the host from sending while its receive FIFO is full
+    #[arg(long, value_enum, default_value_t = FlowControl::RtsCts)]
+    pub acia_flow: FlowControl,
+
+    /// Put a .cas or .wav file in the cassette recorder for CLOAD, CLOADM, CSAVE and CSAVEM (a
+    /// file that doesn't exist is a blank tape)
+    #[arg(long, value_name = "FILE")]
+    pub cassette: Option<PathBuf>,
+
+    /// Enable the Becker port (DriveWire) at $FF41-$FF42
+    #[arg(long)]
+    pub becker: bool,
+
+    /// Directory in which DriveWire print jobs are saved
+    #[arg(long, default_value_os_t=PathBuf::from("."))]
+    pub print_dir: PathBuf,
+
+    /// File format for DriveWire print jobs
+    #[arg(long, value_enum, default_value_t=PrintFormat::Text)]
+    pub print_format: PrintFormat,
 
     /// Print ACIA debug information
     #[arg(long)]
@@ -33,6 +106,27 @@ pub struct Args {
     #[arg(long)]
     pub acia_case: bool,
 
+    /// Translate line endings on the ACIA (host CR LF or LF -> CR; CoCo CR -> CR LF)
+    #[arg(long)]
+    pub acia_crlf: bool,
+
+    /// Echo bytes received by the ACIA back to the host
+    #[arg(long)]
+    pub acia_echo: bool,
+
+    /// Clear bit 7 of every byte passing through the ACIA
+    #[arg(long = "acia-7bit")]
+    pub acia_7bit: bool,
+
+    /// File of byte translations for the ACIA ("in|out <from> <to>" per line, in hex)
+    #[arg(long)]
+    pub acia_charmap: Option<PathBuf>,
+
+    /// Time each form of an instruction (or every instruction) in a tight loop, report the host
+    /// time per instruction and exit
+    #[arg(long, value_name = "MNEMONIC|all")]
+    pub bench_opcode: Option<String>,
+
     /// Break into the debugger before running the program (only if debugger enabled)
     #[arg(short, long)]
     pub break_start: bool,
@@ -45,26 +139,202 @@ pub struct Args {
     #[arg(long)]
     pub cart: Option<PathBuf>,
 
+    /// Kiosk mode for demos: no debugger, no file writes, no hotkeys but reset, and the program
+    /// restarts if it crashes, exits or sits idle
+    #[arg(long)]
+    pub kiosk: bool,
+
+    /// Seconds without keyboard or mouse input after which kiosk mode restarts the program (0 = never)
+    #[arg(long, default_value_t = 300)]
+    pub kiosk_idle: u64,
+
+    /// File of cheats (values frozen in RAM) to load and save instead of <program>.cht
+    #[arg(long)]
+    pub cheats: Option<PathBuf>,
+
+    /// Keep this many snapshots for rewinding with F9 (30 if no number is given)
+    #[arg(long, value_name = "SNAPSHOTS", num_args = 0..=1, default_missing_value = "30")]
+    pub rewind: Option<usize>,
+
+    /// Seconds of emulated time between rewind snapshots
+    #[arg(long, value_name = "SECONDS", default_value_t = 1.0, requires = "rewind")]
+    pub rewind_interval: f64,
+
+    /// Start in the state saved in a snapshot file (with the debugger's state save command)
+    #[arg(long, value_name = "FILE", visible_alias = "load-state")]
+    pub state: Option<PathBuf>,
+
+    /// Save the machine's state to a snapshot file when coco exits
+    #[arg(long, value_name = "FILE")]
+    pub save_state: Option<PathBuf>,
+
+    /// Reset the machine when a cartridge is inserted at runtime (F11 or the debugger's cart command)
+    #[arg(long)]
+    pub cart_reset: bool,
+
+    /// Compare the registers after each instruction with a trace made by MAME or XRoar and stop
+    /// at the first difference
+    #[arg(long)]
+    pub compare_trace: Option<PathBuf>,
+
+    /// Record instruction coverage and write an lcov tracefile to this path at exit
+    #[arg(long)]
+    pub coverage: Option<PathBuf>,
+
     /// Run with debugger enabled
     #[arg(short, long)]
     pub debug: bool,
 
+    /// Disk image (or host directory) to mount (repeat to mount images in drives 0-3 in order)
+    #[arg(long)]
+    pub disk: Vec<PathBuf>,
+
+    /// Disk image (or host directory) to mount in drive 0
+    #[arg(long, conflicts_with = "disk")]
+    pub disk0: Option<PathBuf>,
+
+    /// Disk image (or host directory) to mount in drive 1
+    #[arg(long, conflicts_with = "disk")]
+    pub disk1: Option<PathBuf>,
+
+    /// Emulate the FD-502 disk controller even if no disk is mounted (it is whenever one is)
+    #[arg(long)]
+    pub fdc: bool,
+
+    /// Host directory to present as a Disk BASIC diskette (repeatable; mounted after --disk images)
+    #[arg(long)]
+    pub disk_dir: Vec<PathBuf>,
+
+    /// Write protect the given drive (repeatable)
+    #[arg(long)]
+    pub disk_wp: Vec<usize>,
+
+    /// What to do with unsaved disk image changes on exit
+    #[arg(long, value_enum, default_value_t=ExitPolicy::Ask)]
+    pub disk_exit: ExitPolicy,
+
+    /// Script of debugger commands to run when the debugger is first entered
+    #[arg(long)]
+    pub debug_init: Option<PathBuf>,
+
+    /// File in which debugger command history is kept (default is ~/.coco_history)
+    #[arg(long)]
+    pub debug_history: Option<PathBuf>,
+
+    /// File in which the recently used programs, cartridges and disks are listed (default is
+    /// ~/.coco_recent)
+    #[arg(long)]
+    pub recent_file: Option<PathBuf>,
+
+    /// Neither offer the recently used files at startup nor add to the list
+    #[arg(long)]
+    pub no_recent: bool,
+
+    /// Take symbols such as " : and + from the characters the host's keyboard layout types
+    /// rather than from the positions of the keys (for non-US keyboards)
+    #[arg(long)]
+    pub typed_symbols: bool,
+
+    /// How far the joystick moves for the mouse's motion while the mouse is captured (F7): 1.0
+    /// moves it across its whole range for a window's width
+    #[arg(long, default_value_t = 1.0)]
+    pub mouse_sensitivity: f32,
+
+    /// What a key held down on the host does: follow the host's auto-repeat, hold the key down
+    /// steadily, or release and press it again after --key-repeat-delay at --key-repeat-rate
+    #[arg(long, value_enum, default_value_t = RepeatMode::Host)]
+    pub key_repeat: RepeatMode,
+
+    /// Milliseconds a key is held before it starts to repeat (with --key-repeat synth)
+    #[arg(long, default_value_t = 500)]
+    pub key_repeat_delay: u64,
+
+    /// Repeats per second once a key has started to repeat (with --key-repeat synth)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..=50))]
+    pub key_repeat_rate: u64,
+
     /// The number of instructions to keep in the execution history when debugging
     #[arg(long, default_value_t = 100)]
     pub history: usize,
 
+    /// Let programs request services from the host (printing, time, exit status, files) with a
+    /// software interrupt (SWI2 if none is given) instead of taking the interrupt
+    #[arg(long, value_enum, value_name = "TRAP", num_args = 0..=1, default_missing_value = "swi2")]
+    pub host_services: Option<Trap>,
+
+    /// Directory in which files written by host services are created
+    #[arg(long, default_value = ".")]
+    pub host_dir: PathBuf,
+
+    /// What to do when a ROM, cartridge or program is loaded on top of one loaded earlier (default is warn)
+    #[arg(long, value_enum)]
+    pub load_overlap: Option<OverlapPolicy>,
+
+    /// Keep a log of the last N I/O accesses (see the debugger's iolog command)
+    #[arg(long)]
+    pub io_log: Option<usize>,
+
+    /// Act on reads or writes of an I/O address, e.g. --spy "ff22 w log {value:b}" (see the README)
+    #[arg(long, value_name = "SPEC")]
+    pub spy: Vec<String>,
+
     /// If there is a program listing then dump it to stdout
     #[arg(short, long)]
     pub list: bool,
 
+    /// Compare each assembled program with an older listing (.lst) and show which bytes changed
+    #[arg(long, value_name = "OLD_LST")]
+    pub diff_lst: Option<PathBuf>,
+
     /// Disable automatic branch->long_branch conversion
     #[arg(long)]
     pub lbr_disable: bool,
 
-    /// Limits the clock speed in MHz (default is unlimited)
+    /// The clock speed in MHz (default is the CoCo's 0.89)
     #[arg(short, long)]
     pub mhz: Option<f32>,
 
+    /// Assert each hsync a random number of cycles (up to this many) into its scan line
+    #[arg(long, default_value_t = 0)]
+    pub sync_jitter: u64,
+
+    /// The video standard: NTSC (262 lines and 60 fields a second) or PAL (312 lines and 50)
+    #[arg(long, value_enum, default_value_t = VideoStandard::Ntsc)]
+    pub video: VideoStandard,
+
+    /// Model every documented corner of the PIAs (IRQ flag rules, C2 output modes, DDR) instead of the fast approximation
+    #[arg(long)]
+    pub pia_strict: bool,
+
+    /// Trade speed for fidelity: fast paces the CPU once a field, accurate never skips frames and has strict PIAs
+    #[arg(long, value_enum, default_value_t = Accuracy::Balanced)]
+    pub accuracy: Accuracy,
+
+    /// Start in warp mode: run flat out with hsync and vsync timed by the emulated clock (F10 toggles)
+    #[arg(long)]
+    pub warp: bool,
+
+    /// Write the program listing straight to stdout rather than paging it
+    #[arg(long)]
+    pub no_pager: bool,
+
+    /// ROM file to load at $A000 instead of coco.yaml's load_rom
+    #[arg(long, value_name = "FILE")]
+    pub rom: Option<PathBuf>,
+
+    /// Load coco's built-in monitor at $A000 instead of coco.yaml's load_rom (even with a program
+    /// to load)
+    #[arg(long, conflicts_with = "rom")]
+    pub monitor: bool,
+
+    /// File of ROM entry points to use along with the built-in ones (see src/romdb.txt)
+    #[arg(long)]
+    pub rom_db: Option<PathBuf>,
+
+    /// Log each call into a recognized ROM routine (or only the comma-separated routines named)
+    #[arg(long, value_name = "NAMES", num_args = 0..=1, default_missing_value = "")]
+    pub rom_calls: Option<String>,
+
     /// No automatic loading of symbols
     #[arg(short, long)]
     pub no_auto_sym: bool,
@@ -77,22 +347,152 @@ pub struct Args {
     #[arg(long)]
     pub perf: bool,
 
+    /// Enable a RAM disk cartridge of the given size in KB
+    #[arg(long)]
+    pub ramdisk: Option<usize>,
+
+    /// Base address of the RAM disk registers (hex ok with '0x')
+    #[arg(long,value_parser=maybe_hex::<u16>, default_value_t=0xff50_u16)]
+    pub ramdisk_addr: u16,
+
+    /// File from which RAM disk contents are loaded at startup and saved at exit
+    #[arg(long)]
+    pub ramdisk_file: Option<PathBuf>,
+
+    /// Enable a programmable timer cartridge (an interrupt source independent of video timing)
+    #[arg(long)]
+    pub timer: bool,
+
+    /// Base address of the timer cartridge's registers (hex ok with '0x')
+    #[arg(long,value_parser=maybe_hex::<u16>, default_value_t=0xff54_u16)]
+    pub timer_addr: u16,
+
+    /// Plug in a MIDI Pak that sends to this file (a .mid file captures a standard MIDI file;
+    /// anything else, e.g. a host MIDI port like /dev/snd/midiC1D0, gets the raw bytes)
+    #[arg(long, value_name = "FILE")]
+    pub midi: Option<PathBuf>,
+
+    /// Base address of the MIDI Pak's ACIA (hex ok with '0x')
+    #[arg(long,value_parser=maybe_hex::<u16>, default_value_t=0xff6e_u16)]
+    pub midi_addr: u16,
+
+    /// Plug in a graphics tablet (an X-Pad-like pad with the mouse as its pen)
+    #[arg(long)]
+    pub tablet: bool,
+
+    /// Base address of the tablet's registers (hex ok with '0x')
+    #[arg(long,value_parser=maybe_hex::<u16>, default_value_t=0xff60_u16)]
+    pub tablet_addr: u16,
+
+    /// Plug in a WordPak 80-column card shown in its own window (or in the main window in place of the
+    /// VDG once a program has set the card up)
+    #[arg(long, value_enum, value_name = "DISPLAY", num_args = 0..=1, default_missing_value = "window")]
+    pub wordpak: Option<Output>,
+
+    /// Base address of the WordPak's registers (hex ok with '0x')
+    #[arg(long,value_parser=maybe_hex::<u16>, default_value_t=0xff98_u16)]
+    pub wordpak_addr: u16,
+
+    /// Describe the machine (settings, ROMs, devices, audio and video) for a bug report and exit;
+    /// the description is printed or, if a file is given, written to it as JSON
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "")]
+    pub dump_machine_info: Option<String>,
+
     /// Set the top RAM address
     #[arg(long,value_parser=maybe_hex::<u16>, default_value_t=0x7fff_u16)]
     pub ram_top: u16,
 
+    /// How RAM is filled at power-on
+    #[arg(long, value_enum, default_value_t=RamPattern::Zeros)]
+    pub ram_pattern: RamPattern,
+
+    /// Seed for everything random (e.g., --ram-pattern random) so that runs can be repeated
+    #[arg(long)]
+    pub seed: Option<u64>,
+
     /// Override the reset vector
     #[arg(long,value_parser=maybe_hex::<u16>)]
     pub reset_vector: Option<u16>,
 
+    /// POKE script to run after boot (POKE lines are applied directly; other lines are typed)
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+
+    /// Fault if the system stack grows into the program (or is pulled up into it)
+    #[arg(long)]
+    pub stack_guard: bool,
+
+    /// Report loops that make no progress for this many million cycles (10 if no value is given)
+    #[arg(long, value_name = "MCYCLES", num_args = 0..=1, default_missing_value = "10")]
+    pub hang_detect: Option<f64>,
+
+    /// Addresses or symbols of intentional wait loops that --hang-detect shouldn't report
+    #[arg(long, value_name = "ADDR", value_delimiter = ',', requires = "hang_detect")]
+    pub hang_allow: Vec<String>,
+
+    /// Stop (or enter the debugger) when --hang-detect finds a hung program
+    #[arg(long, requires = "hang_detect")]
+    pub hang_break: bool,
+
+    /// Exit with a report if the emulator's core or UI stops making progress for this many seconds
+    /// (10 if no value is given)
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "10", value_parser = positive_secs)]
+    pub watchdog: Option<f64>,
+
+    /// Lowest allowed value of the system stack pointer (requires --stack-high)
+    #[arg(long,value_parser=maybe_hex::<u16>, requires="stack_high")]
+    pub stack_low: Option<u16>,
+
+    /// Highest allowed value of the system stack pointer (requires --stack-low)
+    #[arg(long,value_parser=maybe_hex::<u16>, requires="stack_low")]
+    pub stack_high: Option<u16>,
+
+    /// Report how long each phase of assembling a program took
+    #[arg(long)]
+    pub timing: bool,
+
+    /// Run the program on two cores in lockstep (one taking the plain path wherever there's a
+    /// faster one) for up to this many instructions, stopping at the first difference
+    #[arg(long, value_name = "INSTRUCTIONS")]
+    pub lockstep: Option<u64>,
+
     /// Set the duration in seconds for which the program should run
     #[arg(short, long)]
     pub time: Option<f32>,
 
+    /// End the run when the program counter reaches this address or symbol (see the README)
+    #[arg(long, value_name = "ADDR")]
+    pub until_pc: Vec<String>,
+
+    /// End the run when a byte of memory holds a value, e.g. --until-mem 3100=ff
+    #[arg(long, value_name = "ADDR=VALUE")]
+    pub until_mem: Vec<String>,
+
+    /// End the run when the text screen shows this text
+    #[arg(long, value_name = "TEXT")]
+    pub until_screen: Vec<String>,
+
+    /// End the run after this many frames (60 a second)
+    #[arg(long, value_name = "FRAMES")]
+    pub until_frames: Option<u64>,
+
+    /// Text to type after boot; each line (separated by \n) is followed by ENTER
+    #[arg(long = "type")]
+    pub type_text: Option<String>,
+
     /// Trace each machine instruction as it is executed
     #[arg(long)]
     pub trace: bool,
 
+    /// Write a trace of instructions, interrupts and device register writes to this file
+    /// for use in waveform (VCD) or trace (Perfetto) viewers
+    #[arg(long)]
+    pub trace_export: Option<PathBuf>,
+
+    /// Format of the --trace-export file (default is VCD for .vcd files and Perfetto otherwise)
+    #[arg(long, value_enum)]
+    pub trace_format: Option<TraceFormat>,
+
     /// Enable verbose output
     #[arg(short, long)]
     pub verbose: bool,
@@ -110,6 +510,62 @@ pub struct Args {
     pub config_file: Option<ConfigFile>,
 }
 
+/// Tools that work on files without running the emulator
+#[derive(Subcommand, Debug)]
+pub enum Utility {
+    /// Convert a .cas file to a .wav file or decode a .wav file (e.g. a digitized tape) to a .cas file
+    Tapeconv {
+        /// The .cas or .wav file to convert
+        input: PathBuf,
+        /// The .wav or .cas file to write
+        output: PathBuf,
+        /// Sample rate of the .wav file that's written
+        #[arg(long, default_value_t = 44100)]
+        rate: u32,
+        /// Cycles above this frequency (Hz) are 1s when decoding
+        #[arg(long, default_value_t = 1800.0)]
+        threshold: f64,
+        /// How far past zero (a fraction of the peak level) the signal has to swing for a crossing to count
+        #[arg(long, default_value_t = 0.1)]
+        hysteresis: f32,
+    },
+    /// Convert machine code between raw binary, Intel HEX, S-record, Disk BASIC (.bin) and cassette (.cas) files
+    Convert {
+        /// The file to convert
+        input: PathBuf,
+        /// The file to write (in the format its extension implies unless --to is given)
+        output: PathBuf,
+        /// The input's format (instead of the one its extension implies)
+        #[arg(long, value_enum)]
+        from: Option<Format>,
+        /// The output's format (instead of the one its extension implies)
+        #[arg(long, value_enum)]
+        to: Option<Format>,
+        /// Where raw input goes in memory (hex ok with '0x')
+        #[arg(long, value_parser=maybe_hex::<u16>)]
+        org: Option<u16>,
+        /// The exec address to write (instead of the input's; hex ok with '0x')
+        #[arg(long, value_parser=maybe_hex::<u16>)]
+        exec: Option<u16>,
+    },
+    /// List, extract or add files on a Disk BASIC (RS-DOS) disk image
+    Dsk {
+        #[command(subcommand)]
+        command: DskCommand,
+    },
+    /// Run a Language Server Protocol server for 6809 assembly on stdin and stdout (for editors)
+    Lsp,
+}
+#[derive(Subcommand, Debug)]
+pub enum DskCommand {
+    /// List the files on an image
+    Ls { image: PathBuf },
+    /// Copy a file from an image (to a host file with the same name unless <OUTPUT> is given)
+    Get { image: PathBuf, name: String, output: Option<PathBuf> },
+    /// Copy a host file onto an image (with the same name unless <NAME> is given), replacing any file with that name
+    Put { image: PathBuf, file: PathBuf, name: Option<String> },
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RomSpec {
     pub path: PathBuf,
@@ -120,28 +576,199 @@ pub struct ConfigFile {
     // files containing binary data to load into ROM
     pub load_rom: Option<Vec<RomSpec>>,
     pub load_code: Option<Vec<LoadCode>>,
+    // what to do when loads overlap (overridden by --load-overlap)
+    pub load_overlap: Option<OverlapPolicy>,
+    // files to write into disk sectors before running
+    pub disk_sectors: Option<Vec<SectorSpec>>,
+    // files that disk sectors must match when the program exits
+    pub disk_expect: Option<Vec<SectorSpec>>,
+    // patches applied to memory after everything else has been loaded
+    pub patches: Option<Vec<PatchSpec>>,
+    // settings for particular programs, cartridges and disks
+    pub programs: Option<Vec<ProgramSpec>>,
+    // translations of the messages shown to the user (see messages.rs)
+    pub messages: Option<HashMap<String, String>>,
+    // spies on I/O addresses, written like --spy (see spy.rs)
+    pub spies: Option<Vec<String>>,
+}
+impl ConfigFile {
+    /// Returns the options of every program section that matches one of the files
+    pub fn program_args(&self, files: &[&PathBuf]) -> Vec<String> {
+        let Some(programs) = self.programs.as_ref() else {
+            return Vec::new();
+        };
+        let mut args = Vec::new();
+        for file in files {
+            // only read the file if it might be matched by its checksum
+            let crc = programs.iter().any(|p| p.crc32.is_some()).then(|| std::fs::read(file).ok()).flatten();
+            let crc = crc.map(|data| crate::romdb::crc32(&data));
+            for program in programs.iter().filter(|p| p.matches(file, crc)) {
+                info!("using the settings for {}", program.name.as_deref().unwrap_or(&file.display().to_string()));
+                args.extend(program.args.split_whitespace().map(String::from));
+            }
+        }
+        args
+    }
+}
+/// Settings for a program, cartridge or disk that's matched by its path (or the end of it)
+/// or by the CRC32 of its contents
+#[derive(Debug, Deserialize)]
+pub struct ProgramSpec {
+    pub name: Option<String>,
+    pub path: Option<PathBuf>,
+    pub crc32: Option<u32>,
+    // command line options used whenever the program is loaded (the command line itself wins)
+    pub args: String,
+}
+impl ProgramSpec {
+    /// Returns true if these settings are for the file at path (whose CRC32 is crc)
+    pub fn matches(&self, path: &Path, crc: Option<u32>) -> bool {
+        self.path.as_ref().is_some_and(|p| path.ends_with(p)) || self.crc32.is_some_and(|c| crc == Some(c))
+    }
+}
+/// Bytes to write at addr, or an IPS file whose offsets are relative to addr
+#[derive(Debug, Deserialize)]
+pub struct PatchSpec {
+    pub addr: u16,
+    pub bytes: Option<Vec<u8>>,
+    pub ips: Option<PathBuf>,
+}
+/// A file's worth of sectors on a mounted disk, starting at track/sector (sectors are 1-based)
+#[derive(Debug, Deserialize)]
+pub struct SectorSpec {
+    pub drive: usize,
+    pub track: usize,
+    pub sector: usize,
+    pub path: PathBuf,
 }
 #[derive(Debug, Deserialize)]
 pub struct LoadCode {
     pub path: PathBuf,
 }
-lazy_static! {
-    pub static ref ARGS: Args = if cfg!(test) {
-        // manually set parameters for running tests
-        Args::parse_from(["test", "test", "--run"])
-    } else {
-        let mut args = Args::parse();
-        let s = std::fs::read_to_string(&args.config_file_path)
-            .unwrap_or_else(|_| {
-                warn!("Failed to open config file \"{}\"", &args.config_file_path.display());
-                String::default()
-            });
-        args.config_file = Some(serde_yaml::from_str(&s).unwrap());
+impl Args {
+    /// Parses a command line, splitting each --load value into its paths (see std::env::split_paths)
+    fn from_argv<I: IntoIterator<Item = T>, T: Into<OsString> + Clone>(argv: I) -> Args {
+        let mut args = Args::parse_from(argv);
+        args.load = args.load.iter().flat_map(std::env::split_paths).collect();
         args
-    };
+    }
+    /// The disk images given with --disk0, --disk1 or --disk (in drive order)
+    pub fn disk_images(&self) -> impl DoubleEndedIterator<Item = &PathBuf> {
+        self.disk0.iter().chain(self.disk1.iter()).chain(self.disk.iter())
+    }
+    /// True when the command line asks for no program, cartridge, disk, tape or snapshot
+    fn nothing_to_load(&self) -> bool {
+        self.load.is_empty()
+            && self.cart.is_none()
+            && self.disk_images().next().is_none()
+            && self.cassette.is_none()
+            && self.state.is_none()
+    }
+}
+/// Parses a number of seconds that must be more than zero
+fn positive_secs(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs.is_finite() => Ok(secs),
+        _ => Err(format!("{} isn't a positive number of seconds", s)),
+    }
 }
 
-pub fn init() {}
-pub fn auto_load_syms() -> bool { !ARGS.no_auto_sym && ARGS.debug }
-pub fn debug() -> bool { ARGS.debug }
-pub fn help_humans() -> bool { ARGS.debug || ARGS.trace }
+/// The settled command line (see init); tests and fuzz targets, which never call init, get the defaults
+pub static ARGS: Settled = Settled(OnceLock::new());
+pub struct Settled(OnceLock<Args>);
+impl Deref for Settled {
+    type Target = Args;
+    fn deref(&self) -> &Args { self.0.get_or_init(|| Args::from_argv(["coco"])) }
+}
+
+/// Parses the command line, reads the config file and settles ARGS. With nothing to load this
+/// offers the recently used files first, which waits on the terminal, so it's up to run to call
+/// it rather than whatever happens to use ARGS first.
+pub fn init() {
+    let mut argv: Vec<OsString> = std::env::args_os().collect();
+    let mut args = Args::from_argv(&argv);
+    if args.utility.is_some() {
+        // the utilities don't use the config file
+        _ = ARGS.0.set(args);
+        return;
+    }
+    let s = std::fs::read_to_string(&args.config_file_path).unwrap_or_else(|_| {
+        warn!("Failed to open config file \"{}\"", &args.config_file_path.display());
+        String::default()
+    });
+    let config: ConfigFile = serde_yaml::from_str(&s).unwrap();
+    messages::init(config.messages.clone().unwrap_or_default());
+    // with nothing to load offer the recently used files
+    if args.nothing_to_load() && !args.kiosk && !args.no_recent && args.bench_opcode.is_none() {
+        let recent = Recent::path(args.recent_file.as_ref()).map(|path| Recent::open(&path));
+        if let Some(entry) = recent.and_then(|recent| recent.choose()) {
+            argv.extend([OsString::from(entry.kind.option()), entry.path.into_os_string()]);
+            args = Args::from_argv(&argv);
+        }
+    }
+    let files: Vec<&PathBuf> = args.load.iter().chain(&args.cart).chain(args.disk_images()).collect();
+    let program_args = config.program_args(&files);
+    if !program_args.is_empty() {
+        // the program's settings go before the command line's so that the command line wins
+        argv.splice(1..1, program_args.into_iter().map(OsString::from));
+        args = Args::from_argv(argv);
+    }
+    args.config_file = Some(config);
+    _ = ARGS.0.set(args);
+}
+pub fn auto_load_syms() -> bool { !ARGS.no_auto_sym && debug() }
+pub fn debug() -> bool { ARGS.debug && !ARGS.kiosk }
+pub fn kiosk() -> bool { ARGS.kiosk }
+/// True when --rom or --monitor takes the place of the config file's ROMs
+pub fn roms_replaced() -> bool { ARGS.rom.is_some() || ARGS.monitor }
+pub fn overlap_policy() -> OverlapPolicy {
+    ARGS.load_overlap
+        .or_else(|| ARGS.config_file.as_ref().and_then(|c| c.load_overlap))
+        .unwrap_or(OverlapPolicy::Warn)
+}
+pub fn pia_strict() -> bool { ARGS.pia_strict || ARGS.accuracy.strict_pias() }
+pub fn help_humans() -> bool { debug() || ARGS.trace }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programs() {
+        let yaml = "programs:\n  - name: Game\n    path: games/game.ccc\n    args: --pia-strict --mhz 2\n  \
+                    - crc32: 0xCBF43926\n    args: --warp\n";
+        let config: ConfigFile = serde_yaml::from_str(yaml).unwrap();
+        let programs = config.programs.as_ref().unwrap();
+        assert!(programs[0].matches(Path::new("/roms/games/game.ccc"), None));
+        assert!(!programs[0].matches(Path::new("/roms/othergame.ccc"), None));
+        assert!(programs[1].matches(Path::new("x"), Some(0xcbf43926)));
+        assert!(!programs[1].matches(Path::new("x"), None));
+        let path = std::env::temp_dir().join(format!("coco-config-{}.bin", std::process::id()));
+        std::fs::write(&path, b"123456789").unwrap();
+        let game = PathBuf::from("games/game.ccc");
+        assert_eq!(config.program_args(&[&game, &path]), vec!["--pia-strict", "--mhz", "2", "--warp"]);
+        std::fs::remove_file(path).unwrap();
+        // the command line comes after the overrides and wins
+        let args = Args::try_parse_from(["coco", "--warp", "--mhz", "2", "--warp", "--mhz", "3"]).unwrap();
+        assert_eq!(args.mhz, Some(3.0));
+        // a Windows path keeps its drive letter
+        // --load splits its value like PATH, which keeps a Windows drive letter and a comma in a name
+        let first = if cfg!(windows) { "C:\\games\\a,b.bin" } else { "/games/a,b.bin" };
+        let list = std::env::join_paths([first, "b.bas"]).unwrap();
+        let args = Args::from_argv([OsString::from("coco"), "--load".into(), list, "--load".into(), "c.asm".into()]);
+        assert_eq!(args.load, [first, "b.bas", "c.asm"].map(PathBuf::from));
+        // a tape or a snapshot is something to load, so the recent files aren't offered
+        assert!(Args::parse_from(["coco"]).nothing_to_load());
+        assert!(!Args::parse_from(["coco", "--cassette", "t.cas"]).nothing_to_load());
+        assert!(!Args::parse_from(["coco", "--load-state", "s.snap"]).nothing_to_load());
+        // --disk fills drives 0, 1, ... so it can't be mixed with the explicit drive slots
+        let args = Args::parse_from(["coco", "--disk1", "b.dsk", "--disk0", "a.dsk"]);
+        let disks: Vec<PathBuf> = args.disk_images().cloned().collect();
+        assert_eq!(disks, ["a.dsk", "b.dsk"].map(PathBuf::from));
+        assert!(Args::try_parse_from(["coco", "--disk", "a.dsk", "--disk1", "b.dsk"]).is_err());
+        // the watchdog needs some time to wait
+        assert_eq!(Args::parse_from(["coco", "--watchdog"]).watchdog, Some(10.0));
+        assert!(Args::try_parse_from(["coco", "--watchdog=-1"]).is_err());
+        assert!(Args::try_parse_from(["coco", "--watchdog", "0"]).is_err());
+    }
+}
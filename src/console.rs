@@ -0,0 +1,140 @@
+//! A text console on the VDG's 32x16 text screen.
+//!
+//! VirtualTextConsole writes text straight into video RAM (any RAM handle, at any offset) with
+//! a cursor that wraps at the end of a line and scrolls the screen at the bottom. It puts
+//! dm-test's messages on the emulated screen, and it's only built into tests (dm_test.rs and,
+//! for its own unit test, the library's). Text goes through fmt::Write so `write!` and `writeln!`
+//! work with it.
+use super::*;
+use std::sync::{Arc, RwLock};
+use vdg::{BLOCK_COLS, BLOCK_ROWS};
+
+/// How characters are stored in video RAM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// the VDG's own codes, as BASIC stores them (lower case shows as inverse upper case)
+    Vdg,
+    /// plain ASCII (for a VDG that interprets characters as ASCII)
+    Ascii,
+}
+impl Encoding {
+    fn encode(self, ch: char) -> u8 {
+        let ch = if ch.is_ascii() && !ch.is_ascii_control() { ch as u8 } else { b'?' };
+        match (self, ch) {
+            (Encoding::Ascii, _) => ch,
+            (Encoding::Vdg, 0x20..=0x3f) => ch + 0x40,
+            (Encoding::Vdg, 0x40..=0x5f) => ch,
+            (Encoding::Vdg, _) => ch - 0x60,
+        }
+    }
+}
+
+pub struct VirtualTextConsole {
+    ram: Arc<RwLock<Vec<u8>>>,
+    vram_offset: usize,
+    encoding: Encoding,
+    /// the column and row where the next character goes
+    cursor: (usize, usize),
+}
+impl VirtualTextConsole {
+    pub fn new(ram: Arc<RwLock<Vec<u8>>>, vram_offset: usize, encoding: Encoding) -> Self {
+        VirtualTextConsole { ram, vram_offset, encoding, cursor: (0, 0) }
+    }
+    pub fn vram_offset(&self) -> usize { self.vram_offset }
+    /// Moves the console to another part of RAM (what's there is left alone)
+    pub fn set_vram_offset(&mut self, vram_offset: usize) { self.vram_offset = vram_offset }
+    pub fn cursor(&self) -> (usize, usize) { self.cursor }
+    pub fn set_cursor(&mut self, col: usize, row: usize) {
+        self.cursor = (col.min(BLOCK_COLS - 1), row.min(BLOCK_ROWS - 1));
+    }
+    /// Writes a byte of video RAM (index is relative to the start of video RAM)
+    ///
+    /// Panics if the byte is beyond the end of RAM.
+    pub fn write_byte(&self, index: usize, data: u8) {
+        let mut ram = self.ram.write().unwrap();
+        let addr = index + self.vram_offset;
+        assert!(addr < ram.len(), "VRAM write out of bounds ({:04X})", addr);
+        ram[addr] = data;
+    }
+    /// Reads a byte of video RAM (index is relative to the start of video RAM)
+    ///
+    /// Panics if the byte is beyond the end of RAM.
+    pub fn read_byte(&self, index: usize) -> u8 {
+        let ram = self.ram.read().unwrap();
+        let addr = index + self.vram_offset;
+        assert!(addr < ram.len(), "VRAM read out of bounds ({:04X})", addr);
+        ram[addr]
+    }
+    fn space(&self) -> u8 { self.encoding.encode(' ') }
+    /// Fills the screen with spaces and puts the cursor at the top left
+    pub fn clear(&mut self) {
+        let space = self.space();
+        for i in 0..BLOCK_COLS * BLOCK_ROWS {
+            self.write_byte(i, space);
+        }
+        self.cursor = (0, 0);
+    }
+    /// Moves every line up one and blanks the bottom line
+    pub fn scroll(&mut self) {
+        for i in BLOCK_COLS..BLOCK_COLS * BLOCK_ROWS {
+            self.write_byte(i - BLOCK_COLS, self.read_byte(i));
+        }
+        let space = self.space();
+        for i in 0..BLOCK_COLS {
+            self.write_byte(i + BLOCK_COLS * (BLOCK_ROWS - 1), space);
+        }
+    }
+    /// Writes a character at the cursor; a newline moves to the start of the next line
+    pub fn put_char(&mut self, ch: char) {
+        if self.cursor.0 >= BLOCK_COLS || ch == '\n' {
+            self.cursor = (0, self.cursor.1 + 1);
+        }
+        if self.cursor.1 >= BLOCK_ROWS {
+            self.cursor.1 = BLOCK_ROWS - 1;
+            self.scroll();
+        }
+        if ch != '\n' {
+            self.write_byte(self.cursor.0 + self.cursor.1 * BLOCK_COLS, self.encoding.encode(ch));
+            self.cursor.0 += 1;
+        }
+    }
+}
+impl fmt::Write for VirtualTextConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.chars().for_each(|ch| self.put_char(ch));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // (the imports are inside the test because dm-test declares this module without a harness)
+    #[test]
+    fn console() {
+        use super::*;
+        use std::fmt::Write;
+        let ram = Arc::new(RwLock::new(vec![0u8; 0x800]));
+        let mut console = VirtualTextConsole::new(ram.clone(), 0x400, Encoding::Vdg);
+        console.clear();
+        write!(console, "Hi 1@").unwrap();
+        assert_eq!(ram.read().unwrap()[0x400..0x406], [0x48, 0x09, 0x60, 0x71, 0x40, 0x60]);
+        assert_eq!(console.cursor(), (5, 0));
+        // a full line wraps and the 17th line scrolls the first one away
+        for row in 0..BLOCK_ROWS {
+            writeln!(console).unwrap();
+            write!(console, "{}", "X".repeat(BLOCK_COLS)).unwrap();
+            assert_eq!(console.cursor(), (BLOCK_COLS, row.min(BLOCK_ROWS - 2) + 1));
+        }
+        assert_eq!(console.read_byte(0), b'X');
+        assert_eq!(ram.read().unwrap()[0x400 + BLOCK_COLS * BLOCK_ROWS], 0);
+        let mut console = VirtualTextConsole::new(ram.clone(), 0, Encoding::Ascii);
+        writeln!(console, "ok").unwrap();
+        assert_eq!(ram.read().unwrap()[..3], *b"ok\0");
+        assert_eq!(console.cursor(), (0, 1));
+        // moving the console and its cursor
+        console.set_vram_offset(0x200);
+        console.set_cursor(BLOCK_COLS, 1);
+        write!(console, "!").unwrap();
+        assert_eq!((console.vram_offset(), ram.read().unwrap()[0x200 + 2 * BLOCK_COLS - 1]), (0x200, b'!'));
+    }
+}
@@ -0,0 +1,288 @@
+//! Converting machine code between file formats.
+//!
+//! `coco convert <input> <output>` reads machine code in one format and writes it in another.
+//! Each file's format comes from its extension unless --from or --to says otherwise:
+//! ```text
+//! raw    .raw .rom .ccc              the bytes alone (--org says where they go)
+//! hex    .hex                        Intel HEX
+//! srec   .s19 .s28 .s37 .srec .mot   Motorola S-records
+//! decb   .bin                        Disk BASIC's LOADM format: a preamble ($00, length,
+//!                                    address) before each block and a postamble ($FF, 0, 0,
+//!                                    the exec address) at the end
+//! cas    .cas                        a cassette file for CLOADM (see tape.rs)
+//! ```
+//! Raw and cassette files hold one block of memory, so the gaps between blocks are filled
+//! with zeros when they're written. --exec sets (or replaces) the exec address.
+//!
+//! The emulator loads .bin and .cas files given with --load through here too.
+use super::*;
+use hex::{HexRecord, HexRecordCollection, HexRecordType};
+
+/// the most data in a line of a hex or S-record file
+const RECORD_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Raw,
+    Hex,
+    Srec,
+    Decb,
+    Cas,
+}
+impl Format {
+    /// Returns the format of a file with this extension
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let ext = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        Some(match ext.as_str() {
+            "raw" | "rom" | "ccc" => Format::Raw,
+            "hex" => Format::Hex,
+            "s19" | "s28" | "s37" | "srec" | "mot" => Format::Srec,
+            "bin" => Format::Decb,
+            "cas" => Format::Cas,
+            _ => return None,
+        })
+    }
+}
+
+/// Machine code: blocks of bytes and their addresses, and where to start running it
+#[derive(Debug, Default, PartialEq)]
+pub struct Code {
+    pub blocks: Vec<(u16, Vec<u8>)>,
+    pub exec: Option<u16>,
+}
+impl Code {
+    /// Adds bytes at addr (to the end of the last block if they follow on from it)
+    fn add(&mut self, addr: u16, data: &[u8]) {
+        match self.blocks.last_mut() {
+            Some((start, bytes)) if *start as usize + bytes.len() == addr as usize => bytes.extend(data),
+            _ => self.blocks.push((addr, data.to_vec())),
+        }
+    }
+    /// Returns the first address and the bytes from there to the end of the last block (with
+    /// zeros in any gaps)
+    pub fn flatten(&self) -> (u16, Vec<u8>) {
+        let start = self.blocks.iter().map(|b| b.0).min().unwrap_or(0);
+        let mut bytes = Vec::new();
+        for (addr, data) in &self.blocks {
+            let at = (addr - start) as usize;
+            if bytes.len() < at + data.len() {
+                bytes.resize(at + data.len(), 0);
+            }
+            bytes[at..at + data.len()].copy_from_slice(data);
+        }
+        (start, bytes)
+    }
+
+    /// Reads code in a format (org is the address of raw code)
+    pub fn read(format: Format, bytes: &[u8], org: Option<u16>) -> Result<Code, Error> {
+        match format {
+            Format::Raw => {
+                let org = org.ok_or_else(|| general_err!("raw code needs an address (--org)"))?;
+                if org as usize + bytes.len() > 0x10000 {
+                    return Err(general_err!("{} bytes don't fit at {:04X}", bytes.len(), org));
+                }
+                Ok(Code { blocks: vec![(org, bytes.to_vec())], exec: None })
+            }
+            Format::Hex | Format::Srec => Code::read_hex(bytes),
+            Format::Decb => Code::read_decb(bytes),
+            Format::Cas => Code::read_cas(bytes),
+        }
+    }
+    fn read_hex(bytes: &[u8]) -> Result<Code, Error> {
+        let hex = HexRecordCollection::from_str_iter(String::from_utf8_lossy(bytes).lines())?;
+        let (mut code, mut base) = (Code::default(), 0usize);
+        for r in hex.iter() {
+            match r.record_type {
+                HexRecordType::Data => {
+                    let data = r.data.as_deref().unwrap_or_default();
+                    let addr = base + r.address as usize;
+                    check_fits("hex data", addr, data.len())?;
+                    code.add(addr as u16, data);
+                }
+                HexRecordType::ExSegAddr => base = (r.data_value() as usize) << 4,
+                HexRecordType::ExLinAddr => base = (r.data_value() as usize) << 16,
+                HexRecordType::StartSegAddr | HexRecordType::StartLinAddr => {
+                    let v = r.data_value() as usize;
+                    let segmented = r.record_type == HexRecordType::StartSegAddr;
+                    code.exec = u16::try_from(if segmented { (v >> 16 << 4) + (v & 0xffff) } else { v }).ok();
+                }
+                _ => {}
+            }
+        }
+        Ok(code)
+    }
+    fn read_decb(bytes: &[u8]) -> Result<Code, Error> {
+        let (mut code, mut i) = (Code::default(), 0);
+        let word = |i: usize| bytes.get(i..i + 2).map(|w| u16::from_be_bytes([w[0], w[1]]));
+        loop {
+            match (bytes.get(i), word(i + 1), word(i + 3)) {
+                (Some(0), Some(len), Some(addr)) => {
+                    let data = bytes.get(i + 5..i + 5 + len as usize);
+                    let data = data.ok_or_else(|| general_err!("the block at {:04X} is cut off", addr))?;
+                    check_fits("the block", addr as usize, data.len())?;
+                    code.add(addr, data);
+                    i += 5 + len as usize;
+                }
+                (Some(0xff), Some(_), Some(exec)) => {
+                    code.exec = Some(exec);
+                    return Ok(code);
+                }
+                _ => return Err(general_err!("not a Disk BASIC machine language file (at byte {})", i)),
+            }
+        }
+    }
+    fn read_cas(bytes: &[u8]) -> Result<Code, Error> {
+        let blocks = tape::read_cas(bytes);
+        if let Some(bad) = blocks.iter().find(|b| b.error.is_some()) {
+            return Err(general_err!("{}: {}", bad, bad.error.as_ref().unwrap()));
+        }
+        let name = blocks.first().filter(|b| b.kind == 0 && b.data.len() >= 15);
+        let Some(name) = name.filter(|b| b.data[8] == 2) else {
+            return Err(general_err!("not a machine language cassette file"));
+        };
+        let word = |i: usize| u16::from_be_bytes([name.data[i], name.data[i + 1]]);
+        let data: Vec<u8> = blocks.iter().skip(1).take_while(|b| b.kind == 1).flat_map(|b| b.data.clone()).collect();
+        check_fits("the file", word(13) as usize, data.len())?;
+        Ok(Code { blocks: vec![(word(13), data)], exec: Some(word(11)) })
+    }
+
+    /// Writes code in a format (name is the file name on a cassette)
+    pub fn write(&self, format: Format, name: &str) -> Vec<u8> {
+        match format {
+            Format::Raw => self.flatten().1,
+            Format::Hex => {
+                let mut hex = HexRecordCollection::new();
+                for (addr, data) in &self.blocks {
+                    for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+                        _ = hex.add_record(HexRecord::from_data(addr + (i * RECORD_LEN) as u16, chunk));
+                    }
+                }
+                if let Some(exec) = self.exec {
+                    let start = HexRecord::with_type(HexRecordType::StartLinAddr, 0, &(exec as u32).to_be_bytes());
+                    _ = hex.add_record(start);
+                }
+                hex.add_eof();
+                hex.iter().map(|r| r.to_string()).collect::<String>().into_bytes()
+            }
+            Format::Srec => {
+                let mut lines = vec![HexRecord::srec(0, 0, name.as_bytes())];
+                for (addr, data) in &self.blocks {
+                    for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+                        lines.push(HexRecord::srec(1, addr + (i * RECORD_LEN) as u16, chunk));
+                    }
+                }
+                lines.push(HexRecord::srec(9, self.exec.unwrap_or(0), &[]));
+                lines.iter().map(|l| format!("{}\n", l)).collect::<String>().into_bytes()
+            }
+            Format::Decb => {
+                let mut out = Vec::new();
+                for (addr, data) in &self.blocks {
+                    for (i, chunk) in data.chunks(0xffff).enumerate() {
+                        out.push(0);
+                        out.extend((chunk.len() as u16).to_be_bytes());
+                        out.extend((addr + (i * 0xffff) as u16).to_be_bytes());
+                        out.extend(chunk);
+                    }
+                }
+                out.extend([0xff, 0, 0]);
+                out.extend(self.exec.unwrap_or(0).to_be_bytes());
+                out
+            }
+            Format::Cas => {
+                let (load, data) = self.flatten();
+                let mut header = format!("{:<8.8}", name.to_ascii_uppercase()).into_bytes();
+                // machine language, binary, no gaps between blocks
+                header.extend([2, 0, 0]);
+                header.extend(self.exec.unwrap_or(load).to_be_bytes());
+                header.extend(load.to_be_bytes());
+                let mut blocks = vec![tape::Block::new(0, &header)];
+                blocks.extend(data.chunks(255).map(|chunk| tape::Block::new(1, chunk)));
+                blocks.push(tape::Block::new(0xff, &[]));
+                tape::cas(&blocks)
+            }
+        }
+    }
+}
+
+/// Converts machine code from one file format to another (coco convert)
+pub fn convert(
+    input: &Path,
+    output: &Path,
+    formats: (Option<Format>, Option<Format>),
+    org: Option<u16>,
+    exec: Option<u16>,
+) -> Result<(), Error> {
+    let format_of = |path: &Path, format: Option<Format>| {
+        format.or_else(|| Format::from_path(path)).ok_or_else(|| {
+            general_err!("can't tell the format of {} from its extension (use --from or --to)", path.display())
+        })
+    };
+    let (from, to) = (format_of(input, formats.0)?, format_of(output, formats.1)?);
+    let mut code = Code::read(from, &std::fs::read(input)?, org)?;
+    code.exec = exec.or(code.exec);
+    if code.blocks.iter().all(|b| b.1.is_empty()) {
+        return Err(general_err!("there's no code in {}", input.display()));
+    }
+    let name = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    std::fs::write(output, code.write(to, &name))?;
+    let (start, bytes) = code.flatten();
+    let end = start as usize + bytes.len() - 1;
+    let exec = code.exec.map_or(String::new(), |exec| format!(", exec {:04X}", exec));
+    println!("Wrote {:04X}-{:04X}{} to {}", start, end, exec, output.display());
+    Ok(())
+}
+
+impl Core {
+    /// Loads machine code read from a file in one of the formats above
+    pub fn load_code(&mut self, code: &Code, path: &Path) -> Result<(), Error> {
+        let mut ranges = Vec::new();
+        for (addr, data) in &code.blocks {
+            self.raw_ram[*addr as usize..*addr as usize + data.len()].copy_from_slice(data);
+            ranges.push((*addr as usize, data.len()));
+        }
+        self.load_map.add(&path.display().to_string(), &ranges, config::overlap_policy())?;
+        if let Some(exec) = code.exec {
+            self.set_entry_point(exec);
+        }
+        Ok(())
+    }
+}
+
+/// Fails if len bytes at addr run past the end of memory
+fn check_fits(what: &str, addr: usize, len: usize) -> Result<(), Error> {
+    if addr + len > 0x10000 {
+        return Err(general_err!("{} at {:X} is outside the 6809 address space", what, addr));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let blocks = vec![(0x3000, (0..=255).cycle().take(600).collect()), (0x4000, vec![0x39])];
+        let code = Code { blocks, exec: Some(0x3010) };
+        for format in [Format::Hex, Format::Srec, Format::Decb] {
+            assert_eq!(Code::read(format, &code.write(format, "TEST"), None).unwrap(), code);
+        }
+        // raw and cassette files are a single block
+        let (start, bytes) = code.flatten();
+        assert_eq!((start, bytes.len(), bytes[0x1000]), (0x3000, 0x1001, 0x39));
+        let flat = Code { blocks: vec![(start, bytes.clone())], exec: code.exec };
+        assert_eq!(Code::read(Format::Cas, &code.write(Format::Cas, "test"), None).unwrap(), flat);
+        let raw = Code::read(Format::Raw, &code.write(Format::Raw, ""), Some(0x3000)).unwrap();
+        assert_eq!(raw, Code { exec: None, ..flat });
+        assert!(Code::read(Format::Raw, &bytes, None).is_err());
+        assert!(Code::read(Format::Decb, &[0, 0, 9, 0x30, 0, 1], None).is_err());
+        // blocks that run past $FFFF are refused rather than wrapping
+        let mut decb = vec![0, 0x02, 0x00, 0xff, 0x00];
+        decb.extend([0; 0x200]);
+        decb.extend([0xff, 0, 0, 0, 0]);
+        assert!(Code::read(Format::Decb, &decb, None).is_err());
+        let high = Code { blocks: vec![(0xfff0, vec![0x12; 0x20])], exec: None };
+        assert!(Code::read(Format::Cas, &high.write(Format::Cas, "high"), None).is_err());
+        assert_eq!(HexRecord::srec(9, 0x3010, &[]), "S9033010BC");
+    }
+}
@@ -1,12 +1,17 @@
 use super::{test::TestCriterion, *};
 use crate::hex::{HexRecordCollection, HexRecordType};
+use crate::pia::Pia;
 use std::{
     cell::{Cell, RefCell},
     fs::File,
     io::Read,
-    sync::{Arc, Mutex, RwLock},
+    path::PathBuf,
+    rc::Rc,
+    sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
     time::Duration,
 };
+/// The CoCo's CPU clock in Hz (the 14.31818 MHz crystal divided by 16)
+pub const CPU_HZ: u64 = 894_886;
 #[allow(unused)]
 #[derive(Debug, PartialEq, Eq)]
 pub enum InterruptType {
@@ -38,19 +43,47 @@ pub struct Core {
     pub _ram: Arc<RwLock<Vec<u8>>>, // hold on to this object so that it gets properly cleaned up on Drop
     pub raw_ram: &'static mut [u8],    // but the CPU will directly access memory via this slice
     pub ram_top: u16,              // keep track of where the caller wants ram to end
+    pub fast_write_limit: usize,   // writes below this address are plain RAM writes (no ROM or devices)
+    pub write_pool: Vec<instructions::WriteRecord>, // recycled by exec_next to avoid allocating for each instruction
     pub sam: Arc<Mutex<sam::Sam>>,
     pub _vdg: Arc<Mutex<vdg::Vdg>>,
     pub pia0: Arc<Mutex<pia::Pia0>>,
     pub pia1: Arc<Mutex<pia::Pia1>>,
     pub reg: registers::Set,       // the full set of 6809 registers
     pub acia: Option<acia::Acia>,  // ACIA simulator
+    pub disks: Rc<RefCell<disk::Drives>>, // virtual floppy drives (shared with the disk controller)
+    pub becker: Option<becker::Becker>, // Becker port (DriveWire)
+    pub paks: Vec<Box<dyn cart::Cartridge>>, // devices plugged in alongside the slot (see cart.rs)
+    pub cassette: Option<RefCell<cassette::Cassette>>, // cassette recorder (see cassette.rs)
+    pub autotype: Option<autotype::AutoType>, // text waiting to be typed into the keyboard
+    pub basic: Option<basic::Program>, // a BASIC program waiting for BASIC's prompt (see basic.rs)
+    pub basic_watch: Option<basic::Watch>, // the .bas file reloaded when it changes (--watch)
+    pub reference: bool, // take the plain path wherever there's a faster one (see lockstep.rs)
     pub reset_vector: Option<u16>, // overrides the reset vector if set
+    pub stack_guard: Option<stack::StackGuard>, // bounds for the system stack (see stack.rs)
+    pub coverage: Option<coverage::Coverage>,   // instruction coverage (see coverage.rs)
+    pub hang: Option<hang::HangDetector>,       // detects programs stuck in a loop (see hang.rs)
+    pub rewind: Option<rewind::Rewind>,         // snapshots for rewinding with F9 (see rewind.rs)
+    pub host: Option<host::HostServices>,       // services requested by programs with SWI2 (see host.rs)
+    pub exit_status: Option<u8>,                // the status the program exited with (EXIT or host services)
+    pub quit: Option<Arc<AtomicBool>>,          // set by the UI thread when the window is closed
+    pub until: Option<until::Until>,            // conditions that end the run (see until.rs)
+    pub latency: latency::Latency,              // interrupt latencies (see latency.rs)
+    pub trace_file: Option<tracefile::TraceFile>, // exported trace (see tracefile.rs)
+    pub trace_compare: Option<compare::TraceCompare>, // trace from another emulator to check against (see compare.rs)
+    pub rom_calls: Option<romdb::CallLog>, // ROM routines whose calls are logged (see romdb.rs)
+    pub rng: rng::Rng,             // source of all randomness (see rng.rs); devices must not use any other
     /* interrupt processing */
     pub cart_pending: bool,  // true if cart is loaded but hasn't been run yet
+    pub cart: Option<Box<dyn cart::Cartridge>>, // whatever is in the cartridge slot (see cart.rs)
+    pub cart_state_path: Option<PathBuf>, // where the cartridge's state is saved (if it has any)
+    pub cart_inserted: bool, // false once the cartridge has been pulled (see pull_cart)
     pub in_cwai: bool,       // if true, the processor is within a CWAI instruction
     pub in_sync: bool,       // if true, the processor is within a SYNC instruction
-    pub hsync_prev: Instant, // the last time hsync occurred
-    pub vsync_prev: Instant, // the last time vsync occurred
+    pub nmi_pending: bool,   // the NMI line has been asserted and the interrupt hasn't been taken yet
+    pub warp: bool,          // if true, run flat out rather than at the CPU's clock speed (see warp.rs)
+    pub virtual_sync: warp::VirtualSync,
+    pub pacer: Option<warp::Pacer>, // keeps the CPU to its clock speed (None for headless cores, which run flat out)
     /* perf measurement */
     pub start_time: Instant,       // the most recent time at which self.exec() started a program
     pub instruction_count: u64,    // the number of instructions executed since the most recent program started
@@ -61,26 +94,38 @@ pub struct Core {
     pub meta_time: Duration,       // the time spent outside of instruction prep and evaluation
     pub _read_time: Cell<Duration>, // the time spent reading memory (in Cell for interior mutability)
     pub _write_time: Duration,      // the time spent writing to memory
-    pub min_cycle: Option<Duration>, // the minimum duration of a clock cycle
+    pub lock_stats: lockstats::LockStats, // counts of the device locks taken (see lockstats.rs)
     /* fields for debugging */
     pub in_debugger: bool,
     pub breakpoints: Vec<debug::Breakpoint>,    // all current breakpoints
+    pub io_log: Option<RefCell<iolog::IoLog>>,  // recent I/O accesses if --io-log was given (see iolog.rs)
+    pub spies: Option<RefCell<spy::Spies>>,     // actions on accesses of I/O addresses (see spy.rs)
+    pub heartbeat: Option<Arc<watchdog::Heartbeat>>, // signs of life for --watchdog
+    pub title: Option<title::Title>,                  // what the window's title says (see title.rs)
+    pub misuse: RefCell<error::Reported>,       // device misuse already logged (see misuse.rs)
     pub watch_hits: RefCell<Vec<u16>>,          // tracks writes to addresses for which watch breakpoints have been set
     pub addr_to_sym: HashMap<u16, Vec<String>>, // map from address to symbol
     pub sym_to_addr: HashMap<String, u16>,      // map from symbol to address
+    pub regions: region::Regions,               // labeled memory regions (see region.rs)
+    pub cheats: cheat::Cheats,                  // values frozen in RAM and the search for them (see cheat.rs)
+    pub load_map: loadmap::LoadMap,             // what was loaded where (see loadmap.rs)
     pub list_mode: Option<debug::ListMode>,     // equals Some(ListMode) if currently in list (disassemble) mode
     pub program_start: u16,                     // the starting address of the program; should be equal to reset vector
     pub faulted: bool,                          // true if the CPU has faulted (e.g., stack oveflow)
-    pub history: Option<VecDeque<String>>,      // list of instructions that have been recently executed
+    pub history: Option<VecDeque<debug::HistoryEntry>>, // list of instructions that have been recently executed
+    pub history_len: usize,                     // maximum length of history (adjustable from the debugger)
     pub step_mode: debug::StepMode,             // determines current step mode (see debug.rs)
     pub next_linear_step: u16, // tracks the address of the next contiguous instruction (differs from PC when there is a branch or jump)
     pub trace: bool,           // if true then display each instruction as it's executed
+    pub editor: Option<debug::DebugEditor>, // line editor for the debugger (created on first use)
+    pub debug_macros: HashMap<String, String>, // user-defined debugger macros
+    pub debug_pending: VecDeque<String>,       // debugger commands queued by macros and the init script
 }
 impl Core {
     pub fn new(
         ram: Arc<RwLock<Vec<u8>>>, sam: Arc<Mutex<sam::Sam>>, vdg: Arc<Mutex<vdg::Vdg>>, pia0: Arc<Mutex<pia::Pia0>>,
         pia1: Arc<Mutex<pia::Pia1>>, ram_top: u16, acia_addr: Option<u16>,
-    ) -> Core {
+    ) -> Result<Core, Error> {
         instructions::init();
         // The CPU needs fast (non-blocking) access to RAM so we turn the provided memory into a slice
         // that can be directly accessed (without wrappers and locks). 
@@ -91,22 +136,99 @@ impl Core {
             let mut ram = ram.write().unwrap();
             unsafe { std::slice::from_raw_parts_mut(ram.as_mut_ptr(), ram.len()) }
         };
-        Core {
+        // devices can be mapped below the I/O page (and a cartridge can handle writes to its ROM
+        // window) so plain RAM ends at the lowest of them
+        let io_floor = [
+            Some(cart::ROM_START),
+            acia_addr,
+            config::ARGS.becker.then_some(becker::STATUS_ADDR),
+            config::ARGS.ramdisk.map(|_| config::ARGS.ramdisk_addr),
+            config::ARGS.timer.then_some(config::ARGS.timer_addr),
+            config::ARGS.wordpak.map(|_| config::ARGS.wordpak_addr),
+            config::ARGS.midi.as_ref().map(|_| config::ARGS.midi_addr),
+            config::ARGS.tablet.then_some(config::ARGS.tablet_addr),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap();
+        pia0.lock().unwrap().set_strict(config::pia_strict());
+        pia1.lock().unwrap().set_strict(config::pia_strict());
+        let disks = Rc::new(RefCell::new(disk::Drives::default()));
+        let mut paks: Vec<Box<dyn cart::Cartridge>> = Vec::new();
+        if let Some(kb) = config::ARGS.ramdisk {
+            let ramdisk = ramdisk::RamDisk::new(config::ARGS.ramdisk_addr, kb, config::ARGS.ramdisk_file.as_deref());
+            paks.push(Box::new(ramdisk.map_err(|e| general_err!("failed to create the RAM disk: {}", e))?));
+        }
+        if config::ARGS.timer {
+            paks.push(Box::new(timer::Timer::new(config::ARGS.timer_addr)));
+        }
+        if let Some(path) = config::ARGS.midi.as_ref() {
+            let midi = midi::MidiPak::new(config::ARGS.midi_addr, path);
+            paks.push(Box::new(midi.map_err(|e| general_err!("failed to open the MIDI Pak's output: {}", e))?));
+        }
+        if config::ARGS.tablet {
+            // the tablet reads the mouse through pia0's view of the UI
+            let input = pia0.lock().unwrap().input();
+            paks.push(Box::new(tablet::Tablet::new(config::ARGS.tablet_addr, input)));
+        }
+        if let Some(fdc) = diskctl::Fd502::from_args(disks.clone()) {
+            paks.push(Box::new(fdc));
+        }
+        let acia = match acia_addr {
+            Some(a) => Some(acia::Acia::new(a).map_err(|e| general_err!("failed to start the ACIA: {}", e))?),
+            None => None,
+        };
+        let mut rng = rng::Rng::from_args();
+        rng.fill(&mut raw_ram[..=ram_top as usize], config::ARGS.ram_pattern);
+        Ok(Core {
             _ram: ram,
             raw_ram,
             ram_top,
+            fast_write_limit: (ram_top as usize + 1).min(io_floor as usize),
+            write_pool: Vec::new(),
             sam,
             _vdg: vdg,
             pia0,
             pia1,
             reg: { Default::default() },
-            acia: acia_addr.map(|a| acia::Acia::new(a).expect("failed to start ACIA")),
-            reset_vector: None,
+            acia,
+            disks,
+            becker: if config::ARGS.becker {
+                let printer = printer::Printer::new(&config::ARGS.print_dir, config::ARGS.print_format);
+                Some(becker::Becker::new(printer))
+            } else {
+                None
+            },
+            paks,
+            cassette: None,
+            autotype: None,
+            until: None,
+            latency: Default::default(),
+            basic: None,
+            basic_watch: None,
+            reference: false,
+            reset_vector: config::ARGS.reset_vector,
+            stack_guard: stack::StackGuard::from_args(),
+            coverage: config::ARGS.coverage.as_ref().map(|_| coverage::Coverage::new()),
+            hang: hang::HangDetector::from_args(),
+            rewind: rewind::Rewind::from_args(),
+            host: host::HostServices::from_args(),
+            exit_status: None,
+            quit: None,
+            trace_file: None,
+            trace_compare: None,
+            rom_calls: romdb::CallLog::from_args(),
+            rng,
             cart_pending: false,
+            cart: None,
+            cart_state_path: None,
+            cart_inserted: false,
             in_cwai: false,
             in_sync: false,
-            hsync_prev: Instant::now(),
-            vsync_prev: Instant::now(),
+            nmi_pending: false,
+            warp: config::ARGS.warp,
+            virtual_sync: warp::VirtualSync::new(config::ARGS.sync_jitter, config::ARGS.video),
             start_time: Instant::now(),
             instruction_count: 0,
             clock_cycles: 0,
@@ -116,20 +238,47 @@ impl Core {
             meta_time: Duration::ZERO,
             _read_time: Cell::new(Duration::ZERO),
             _write_time: Duration::ZERO,
-            min_cycle: config::ARGS.mhz.map(|m| Duration::from_secs_f32(0.9 / (m * 1e6))),
+            pacer: Some(warp::Pacer::new(config::ARGS.mhz, config::ARGS.accuracy.pace_lines())),
+            lock_stats: lockstats::LockStats::new(config::ARGS.perf),
             in_debugger: false,
             breakpoints: Vec::new(),
+            io_log: config::ARGS.io_log.map(|n| RefCell::new(iolog::IoLog::new(n.max(1)))),
+            spies: None,
+            heartbeat: None,
+            title: None,
+            misuse: Default::default(),
             watch_hits: RefCell::new(Vec::new()),
             addr_to_sym: HashMap::new(),
             sym_to_addr: HashMap::new(),
+            regions: Default::default(),
+            cheats: cheat::Cheats::from_args(),
+            load_map: Default::default(),
             list_mode: None,
             program_start: 0,
             faulted: false,
             history: None,
+            history_len: config::ARGS.history,
             step_mode: debug::StepMode::Off,
             next_linear_step: 0,
             trace: config::ARGS.trace,
-        }
+            editor: None,
+            debug_macros: HashMap::new(),
+            debug_pending: VecDeque::new(),
+        })
+    }
+    /// Creates a core with 32K of RAM backed by devices that aren't connected to the host
+    /// (no window, no audio and no ACIA)
+    pub fn headless() -> Result<Core, Error> { Core::headless_with_ram_top(0x7fff) }
+    pub fn headless_with_ram_top(ram_top: u16) -> Result<Core, Error> {
+        let ram = Arc::new(RwLock::new(vec![0u8; 0x10000]));
+        let (sndr, _) = std::sync::mpsc::channel();
+        let pia1 = Arc::new(Mutex::new(pia::Pia1::new(sndr)));
+        let pia0 = Arc::new(Mutex::new(pia::Pia0::new(pia1.clone())));
+        let vdg = Arc::new(Mutex::new(vdg::Vdg::with_ram(ram.clone(), 0)));
+        let sam = Arc::new(Mutex::new(sam::Sam::new()));
+        let mut core = Core::new(ram, sam, vdg, pia0, pia1, ram_top, None)?;
+        core.pacer = None;
+        Ok(core)
     }
 
     /// Load a program from a file into memory. Hex files are loaded directly. 
@@ -145,12 +294,25 @@ impl Core {
                 let program = asm.assemble_from_file(path)?;
                 self.load_program(&program, Some(path))?;
             }
-            "hex" => {
-                // the file looks like machine code in hex format; read it
+            "hex" | "s19" | "s28" | "s37" | "srec" | "mot" => {
+                // the file looks like machine code in hex (Intel HEX or S-record) format; read it
                 let hex = HexRecordCollection::read_from_file(path)?;
                 info!("Successfully loaded hex file {}", path.display());
                 self.load_hex(&hex, Some(path))?;
             }
+            "bin" | "cas" => {
+                // Disk BASIC (LOADM) or cassette (CLOADM) machine code
+                let format = convert::Format::from_path(path).ok_or_else(|| general_err!("invalid file extension"))?;
+                let code = convert::Code::read(format, &std::fs::read(path)?, None)?;
+                info!("Successfully loaded {}", path.display());
+                self.load_code(&code, path)?;
+            }
+            "bas" => {
+                // the file is a BASIC listing; it goes into memory once BASIC is ready for it
+                let program = basic::Program::from_file(path, config::ARGS.basic_checksum)?;
+                info!("Tokenized {} ({} lines)", path.display(), program.lines.len());
+                self.basic = Some(program);
+            }
             _ => return Err(general_err!("invalid file extension")),
         }
         Ok(())
@@ -160,22 +322,26 @@ impl Core {
         let mut extent = 0u16;
         let mut eof = false;
         let mut rom_write = false;
+        let mut base = 0usize; // set by extended segment/linear address records
+        let mut ranges = Vec::new();
         for r in hex.iter() {
             match r.record_type {
                 HexRecordType::Data => {
                     if let Some(data) = r.data.as_ref() {
-                        if r.address as usize + r.data_size as usize > self.raw_ram.len() {
+                        if base + r.address as usize + r.data_size as usize > self.raw_ram.len() {
                             return Err(Error::new(
                                 ErrorKind::Memory,
                                 None,
                                 format!(
                                     "program overflowed system RAM ({} byte object at {:04X})",
-                                    r.data_size, r.address
+                                    r.data_size,
+                                    base + r.address as usize
                                 )
                                 .as_str(),
                             ));
                         }
-                        let mut addr = r.address as usize;
+                        let mut addr = base + r.address as usize;
+                        ranges.push((addr, data.len()));
                         for &b in data {
                             self.raw_ram[addr] = b;
                             addr += 1;
@@ -190,6 +356,18 @@ impl Core {
                     eof = true;
                     break;
                 }
+                HexRecordType::ExSegAddr => base = (r.data_value() as usize) << 4,
+                HexRecordType::ExLinAddr => base = (r.data_value() as usize) << 16,
+                HexRecordType::StartSegAddr | HexRecordType::StartLinAddr => {
+                    // segment start addresses are CS:IP; linear start addresses are a plain 32-bit address
+                    let v = r.data_value() as usize;
+                    let start = if r.record_type == HexRecordType::StartSegAddr { (v >> 16 << 4) + (v & 0xffff) } else { v };
+                    if start > 0xffff {
+                        warn!("ignoring hex file start address {:X} (outside the 6809 address space)", start);
+                    } else {
+                        self.set_entry_point(start as u16);
+                    }
+                }
                 _ => warn!("ignoring unsupported record type ({}) in hex file.", r.record_type),
             }
         }
@@ -199,6 +377,8 @@ impl Core {
         if rom_write {
             info!("Portions of this program reside in ROM")
         }
+        let source = hex_path.map_or("hex file".to_string(), |p| p.display().to_string());
+        self.load_map.add(&source, &ranges, config::overlap_policy())?;
         verbose_println!("loaded {} bytes from hex file", extent);
         if config::auto_load_syms() {
             if let Some(path) = hex_path {
@@ -211,31 +391,35 @@ impl Core {
         Ok(extent)
     }
 
+    /// Uses a loaded program's entry point as the reset vector unless --reset-vector was given
+    pub fn set_entry_point(&mut self, addr: u16) {
+        if config::ARGS.reset_vector.is_none() {
+            info!("Reset vector set to program entry point {:04X}", addr);
+            self.reset_vector = Some(addr);
+        }
+    }
+
     /// loads binary data from a file into memory at the given address
     pub fn load_bin(&mut self, bin_path: &Path, addr: u16) -> Result<usize, Error> {
         let mut f = File::open(bin_path)?;
         let extent = f.read(&mut self.raw_ram[addr as usize..])?;
+        self.load_map.add(&bin_path.display().to_string(), &[(addr as usize, extent)], config::overlap_policy())?;
         verbose_println!(
-            "loaded {} bytes at 0x{:04x} from binary file \"{}\"",
+            "loaded {} bytes at 0x{:04x} from binary file \"{}\" (CRC32 {:08X})",
             extent,
             addr,
-            bin_path.display()
+            bin_path.display(),
+            romdb::crc32(&self.raw_ram[addr as usize..addr as usize + extent])
         );
         Ok(extent)
     }
 
-    /// simulates the presence of a cartridge (aka "program pak")
-    /// by loading a binary file at address 0xC000 and setting the cart_pending flag.
-    pub fn load_cart(&mut self, cart_path: &Path) -> Result<usize, Error> {
-        let size = self.load_bin(cart_path, 0xc000)?;
-        self.cart_pending = true;
-        Ok(size)
-    }
-
     /// copies the binary representation of the given Program object into simulator memory
     pub fn load_program(&mut self, program: &Program, program_path: Option<&Path>) -> Result<u16, Error> {
         let mut extent = 0u16;
         let mut rom_write = false;
+        let mut code: Option<(u16, u16)> = None;
+        let mut ranges = Vec::new();
         // clean out the reset vector in case it was set by a previous program
         self.force_reset_vector(0)?;
         for line in &program.lines {
@@ -251,7 +435,13 @@ impl Core {
                         .as_str(),
                     ));
                 }
-                extent += bob.to_bytes(&mut self.raw_ram[bob.addr as usize..]);
+                let size = bob.to_bytes(&mut self.raw_ram[bob.addr as usize..]);
+                if size > 0 {
+                    ranges.push((bob.addr as usize, size as usize));
+                    let end = bob.addr.saturating_add(size);
+                    code = Some(code.map_or((bob.addr, end), |(s, e)| (s.min(bob.addr), e.max(end))));
+                }
+                extent += size;
                 if bob.addr as usize + bob.size as usize >= self.ram_top as usize {
                     rom_write = true;
                 }
@@ -260,7 +450,26 @@ impl Core {
         if rom_write {
             info!("Portions of this program reside in ROM")
         }
+        if let Some(entry) = program.entry {
+            self.set_entry_point(entry);
+        }
+        let source = program_path.map_or("program".to_string(), |p| p.display().to_string());
+        self.load_map.add(&source, &ranges, config::overlap_policy())?;
         verbose_println!("loaded {} bytes", extent);
+        if let (Some(coverage), Some(path)) = (self.coverage.as_mut(), program_path) {
+            coverage.add_program(program, path);
+        }
+        // explicit bounds from the command line take precedence over the program's
+        if config::ARGS.stack_low.is_none() {
+            if let Some((low, high)) = program.stack {
+                self.stack_guard = Some(stack::StackGuard::new(stack::StackBounds::Fixed(low, high)));
+            } else if let Some((start, end)) = code.filter(|_| config::ARGS.stack_guard) {
+                self.stack_guard = Some(stack::StackGuard::new(stack::StackBounds::Auto { start, end }));
+            }
+        }
+        if let Some(path) = program_path {
+            self.cheats.open_for(path);
+        }
         if config::auto_load_syms() {
             if let Some(path) = program_path {
                 match self.try_auto_load_symbols(path) {
@@ -0,0 +1,150 @@
+//! Instruction coverage for test runs.
+//!
+//! When --coverage is given, every instruction executed is counted by address. Programs that
+//! are assembled from source register their instruction lines so that, when the emulator exits,
+//! coverage can be reported per source line: a text summary (listing the lines that were never
+//! executed) is printed and an lcov tracefile is written for use with genhtml and friends.
+use super::*;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+/// An instruction line in a source file
+struct SourceLine {
+    line_num: usize,
+    addr: u16,
+    text: String,
+}
+struct SourceFile {
+    path: PathBuf,
+    lines: Vec<SourceLine>,
+}
+
+pub struct Coverage {
+    hits: Vec<u32>, // execution count for each address
+    files: Vec<SourceFile>,
+}
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            hits: vec![0; 0x10000],
+            files: Vec::new(),
+        }
+    }
+    #[inline(always)]
+    pub fn hit(&mut self, addr: u16) { self.hits[addr as usize] = self.hits[addr as usize].saturating_add(1) }
+    /// Remembers the instruction lines of an assembled program so they can be reported on
+    pub fn add_program(&mut self, program: &Program, path: &Path) {
+        let lines = program
+            .lines
+            .iter()
+            .filter(|l| l.obj_size > 0 && instructions::name_to_descriptor(l.get_operation()).is_some())
+            .map(|l| SourceLine {
+                line_num: l.src_line_num,
+                addr: l.addr,
+                text: l.to_string(),
+            })
+            .collect();
+        self.files.push(SourceFile {
+            path: path.to_path_buf(),
+            lines,
+        });
+    }
+    /// Returns the executed address ranges (inclusive) that don't belong to any registered source line
+    fn unattributed_ranges(&self) -> Vec<(u16, u16)> {
+        let mut known = vec![false; 0x10000];
+        self.files.iter().flat_map(|f| &f.lines).for_each(|l| known[l.addr as usize] = true);
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for addr in (0..0x10000usize).filter(|&a| self.hits[a] > 0 && !known[a]) {
+            match ranges.last_mut() {
+                // instructions are at most 5 bytes long so treat nearby addresses as one range
+                Some((_, end)) if addr - (*end as usize) <= 5 => *end = addr as u16,
+                _ => ranges.push((addr as u16, addr as u16)),
+            }
+        }
+        ranges
+    }
+    /// Builds the text report
+    pub fn text_report(&self) -> String {
+        let mut s = String::new();
+        for f in &self.files {
+            let covered = f.lines.iter().filter(|l| self.hits[l.addr as usize] > 0).count();
+            let total = f.lines.len().max(1);
+            _ = writeln!(
+                s,
+                "{}: {} of {} instruction lines executed ({:.1}%)",
+                f.path.display(),
+                covered,
+                f.lines.len(),
+                100.0 * covered as f32 / total as f32
+            );
+            for l in f.lines.iter().filter(|l| self.hits[l.addr as usize] == 0) {
+                _ = writeln!(s, "  not executed: {:5} {:04X}  {}", l.line_num, l.addr, l.text.trim_end());
+            }
+        }
+        let other = self.unattributed_ranges();
+        if !other.is_empty() {
+            _ = writeln!(s, "code executed outside of assembled programs:");
+            for (start, end) in other {
+                _ = writeln!(s, "  {:04X}-{:04X}", start, end);
+            }
+        }
+        s
+    }
+    /// Builds an lcov tracefile
+    pub fn lcov_report(&self) -> String {
+        let mut s = String::from("TN:\n");
+        for f in &self.files {
+            _ = writeln!(s, "SF:{}", f.path.canonicalize().unwrap_or(f.path.clone()).display());
+            // a macro expands to several instructions that share one source line
+            let mut lines: BTreeMap<usize, u32> = BTreeMap::new();
+            for l in &f.lines {
+                let count = lines.entry(l.line_num).or_insert(u32::MAX);
+                *count = (*count).min(self.hits[l.addr as usize]);
+            }
+            for (line_num, count) in &lines {
+                _ = writeln!(s, "DA:{},{}", line_num, count);
+            }
+            _ = writeln!(s, "LF:{}", lines.len());
+            _ = writeln!(s, "LH:{}", lines.values().filter(|&&c| c > 0).count());
+            s.push_str("end_of_record\n");
+        }
+        s
+    }
+    /// Prints the text report and writes the lcov tracefile. Called when the emulator exits.
+    pub fn report(&self, lcov_path: &Path) -> Result<(), Error> {
+        print!("{}", self.text_report());
+        std::fs::write(lcov_path, self.lcov_report())?;
+        info!("wrote coverage data to {}", lcov_path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_reports() {
+        let mut cov = Coverage::new();
+        cov.files.push(SourceFile {
+            path: PathBuf::from("prog.asm"),
+            lines: vec![
+                SourceLine { line_num: 3, addr: 0x4000, text: "LDA #1".into() },
+                SourceLine { line_num: 4, addr: 0x4002, text: "BEQ skip".into() },
+                SourceLine { line_num: 5, addr: 0x4004, text: "CLRA".into() },
+            ],
+        });
+        cov.hit(0x4000);
+        cov.hit(0x4002);
+        cov.hit(0x4002);
+        cov.hit(0xa000);
+        cov.hit(0xa003);
+        let text = cov.text_report();
+        assert!(text.contains("2 of 3 instruction lines executed"));
+        assert!(text.contains("not executed:     5 4004  CLRA"));
+        assert!(text.contains("A000-A003"));
+        let lcov = cov.lcov_report();
+        assert!(lcov.contains("DA:4,2\nDA:5,0\nLF:3\nLH:2\nend_of_record"));
+    }
+}
@@ -1,5 +1,12 @@
 use super::*;
-use std::io::{stdin, stdout, BufRead, Write};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use std::borrow::Cow;
+use std::io::BufRead;
+use std::path::PathBuf;
 
 macro_rules! help {
     ($name:ident,$help:expr) => {
@@ -9,15 +16,39 @@ macro_rules! help {
 }
 macro_rules! show_help {
     ($name:ident) => {
-        println!("{}", $name)
+        println!("{}", tr!($name))
     };
 }
 
 help!(cmd_g, "g - Go; Resume execution at PC");
-help!(cmd_his, "his - Show recent history of executed instructions");
+help!(
+    cmd_his,
+    "his [d] [<num>] - Show recent history of executed instructions (d: show register changes)"
+);
+help!(
+    cmd_hisf,
+    "his find <loc>|<mnemonic>|<reg>=<value> - Search history by address, mnemonic or register value"
+);
+help!(cmd_hiss, "his save <file> - Export history to a file");
+help!(cmd_hisl, "his len <num> - Set the number of instructions kept in history");
 help!(cmd_c, "c - Context; Display the state of all registers");
-help!(cmd_ba, "ba <loc> [<notes>] - Breakpoint Add; add break at <loc>");
-help!(cmd_bw, "bw <loc> [<notes>] - Add Watch Breakpoint on <loc>");
+help!(
+    cmd_set,
+    "set [<reg>=<expr> | cc.<flag>=0|1]... - Set registers/flags, e.g. set a=$41 cc.z=1 (no args: edit each register)"
+);
+help!(cmd_p, "p <expr> - Print the value of an expression, e.g. p x+2 or p [buffer]*256");
+help!(
+    cmd_cc,
+    "cc [+<flag> | -<flag>]... - Show the condition codes or set (+) and clear (-) flags, e.g. cc +z -c"
+);
+help!(
+    cmd_ba,
+    "ba <loc> [if <cond>] [<notes>] - Breakpoint Add; add break at <loc> (when <cond> holds, e.g. if a>=$20)"
+);
+help!(
+    cmd_bw,
+    "bw <loc> [if <cond>] [<notes>] - Add Watch Breakpoint on <loc> (when <cond> holds, e.g. if [loc]=0)"
+);
 help!(cmd_bd, "bd <num> - Breakpoint Delete; delete breakpoint #<num>");
 help!(cmd_bl, "bl - Breakpoint List; list all breakpoints");
 help!(
@@ -26,7 +57,7 @@ help!(
 );
 help!(
     cmd_bi,
-    "bt - Breakpoint Toggle; active/inactive toggle for breakpoint <num>"
+    "bi - Breakpoint Toggle; active/inactive toggle for breakpoint <num>"
 );
 help!(cmd_dm, "dm [<loc>] [<num>] - Dump Memory; show <num> bytes at <loc>");
 help!(cmd_ds, "ds [<num>] - Dump Stack; show <num> bytes of system stack");
@@ -35,18 +66,98 @@ help!(cmd_l, "l [<loc>] [<num>] - List <num> instructions at <loc>");
 help!(cmd_wd, "wd - Working Directory; display the current working directory");
 help!(cmd_q, "q - Quit; terminate this application");
 help!(cmd_r, "r - Restart program at original Program Counter address");
+help!(
+    cmd_reset,
+    "reset [cold] - Press the reset button (warm start) or cycle the power (cold start)"
+);
 help!(cmd_rs, "rs - Restart Step; restart in step mode");
 help!(cmd_s, "s - Step; enter step mode (press esc to exit)");
 help!(cmd_so, "so - Step Over current instruction, then enter step mode");
+help!(cmd_skip, "skip [<num>] - Skip <num> (default 1) instructions at PC without executing them");
+help!(
+    cmd_branch,
+    "branch taken|not - Force the conditional branch at PC to be taken or not (without checking its condition)"
+);
+help!(
+    cmd_wi,
+    "wi - What If; show what the instruction at PC would change and ask before executing it (repeats until 'n')"
+);
 help!(cmd_t, "t - Trace; toggle tracing on/off");
 help!(cmd_load, "load <file> - Load Symbols; load symbols from .sym file");
 help!(cmd_sym, "sym [<loc>] - List all symbols or show symbols at <loc>");
+help!(
+    cmd_disk,
+    "disk [<drive> flush|discard|wp|rw] - Show drives or flush/discard changes or set write protection"
+);
+help!(
+    cmd_dski,
+    "dski <drive> <track> <sector> - Disk Input; dump a raw sector of a mounted disk"
+);
+help!(
+    cmd_dsko,
+    "dsko <drive> <track> <sector> <byte>... - Disk Output; write bytes to a raw sector"
+);
+help!(
+    cmd_define,
+    "define [<name> = <cmd>[; <cmd>...]] - Define a macro ($1-$9, $* are arguments) or list macros"
+);
+help!(cmd_undef, "undef <name> - Remove a macro");
+help!(
+    cmd_asm,
+    "asm <loc> - Assemble lines (end with a blank line) and write them over the same size of code at <loc>"
+);
+help!(cmd_poke, "poke <loc> <byte>... - Write bytes to memory (ROM included)");
+help!(cmd_pokew, "pokew <loc> <word>... - Write 16-bit words to memory (ROM included)");
+help!(
+    cmd_region,
+    "region [<loc> <loc> <kind> [<name>] | del <loc> | clear] - List, add or remove memory regions (kind: code, data, stack, vram, io)"
+);
+help!(cmd_loadmap, "loadmap - List the address ranges loaded from each ROM, cartridge and program");
+help!(
+    cmd_cart,
+    "cart [pull | insert [<file>]] - Show, pull or insert the cartridge (a new <file> replaces the old cartridge)"
+);
+help!(cmd_tape, "tape [rewind] - Show where the cassette is or rewind it");
+help!(cmd_acia, "acia [clear] - Show the ACIA's FIFOs, flow control and traffic (or clear the counts)");
+help!(
+    cmd_state,
+    "state save|load <file> - Save the machine's state to a snapshot file or load it back (or save|restore <file>)"
+);
+help!(cmd_warp, "warp [on | off] - Show, turn on or turn off warp mode (fast-forward)");
+help!(
+    cmd_latency,
+    "latency [<num> | clear] - Show min/avg/max cycles from IRQ/FIRQ to handler over the last <num> interrupts"
+);
+help!(cmd_nmi, "nmi - Take a non-maskable interrupt now (to test an NMI handler)");
+help!(
+    cmd_iolog,
+    "iolog [<num> | clear] - Show the last <num> (default 20) I/O accesses logged with --io-log"
+);
+help!(
+    cmd_cheat,
+    "cheat [add[w] <loc> <value> [<name>] | del <num> | clear | load <file> | save [<file>]] - List, freeze, remove, load or save cheats"
+);
+help!(
+    cmd_cheats,
+    "cheat search [new | =|!=|>|< <value> | changed | same | up | down] - Search RAM for a value or list the candidates"
+);
+help!(
+    cmd_spy,
+    "spy [<loc> r|w|rw [if <cond>] log <message> | shot <file> | break] - List or add spies on I/O addresses"
+);
+help!(cmd_spyd, "spy del <num> | clear - Remove a spy or all of them");
 help!(cmd_h, "h - Help; display this help text");
 
 static COMMAND_HELP: &[&str] = &[
     cmd_g,
     cmd_his,
+    cmd_hisf,
+    cmd_hiss,
+    cmd_hisl,
     cmd_c,
+    cmd_cc,
+    cmd_set,
+    cmd_p,
     cmd_ba,
     cmd_bw,
     cmd_bi,
@@ -58,17 +169,196 @@ static COMMAND_HELP: &[&str] = &[
     cmd_l,
     cmd_q,
     cmd_r,
+    cmd_reset,
     cmd_rs,
     cmd_s,
     cmd_so,
+    cmd_wi,
+    cmd_skip,
+    cmd_branch,
     cmd_t,
     cmd_wd,
     cmd_load,
     cmd_h,
     cmd_sym,
-    "<loc> syntax: Hex address (e.g. FF0A) or '?' followed by symbol (e.g. \"?START\")",
+    cmd_disk,
+    cmd_dski,
+    cmd_dsko,
+    cmd_define,
+    cmd_undef,
+    cmd_asm,
+    cmd_poke,
+    cmd_pokew,
+    cmd_region,
+    cmd_loadmap,
+    cmd_cart,
+    cmd_tape,
+    cmd_acia,
+    cmd_state,
+    cmd_iolog,
+    cmd_warp,
+    cmd_latency,
+    cmd_nmi,
+    cmd_cheat,
+    cmd_cheats,
+    cmd_spy,
+    cmd_spyd,
+    "<loc> syntax: Hex address (e.g. FF0A), '?' followed by symbol (e.g. \"?START\") or an expression",
+    "<expr> syntax: As in assembly source plus registers and [<expr>] for the byte at <expr> (no spaces)",
+    "<cond> syntax: <expr> or <expr> followed by =, !=, <, <=, > or >= and another <expr>",
 ];
 
+/// The relative branches that don't depend on the condition codes
+const UNCONDITIONAL_BRANCHES: &[&str] = &["BRA", "LBRA", "BRN", "LBRN", "BSR", "LBSR"];
+
+/// What a set command assigns a value to
+#[derive(Debug, PartialEq, Eq)]
+enum SetTarget {
+    Register(registers::Name),
+    Flag(registers::CCBit),
+}
+
+/// Splits an assignment like "a=$41" or "cc.z=1" into what it sets and the expression for the value
+fn parse_set(arg: &str) -> Option<(SetTarget, &str)> {
+    let (name, value) = arg.split_once('=')?;
+    let name = name.to_ascii_lowercase();
+    let target = match name.strip_prefix("cc.") {
+        Some(flag) => {
+            let mut chars = flag.chars();
+            match (chars.next().and_then(registers::CCBit::from_char), chars.next()) {
+                (Some(bit), None) => SetTarget::Flag(bit),
+                _ => return None,
+            }
+        }
+        None => match registers::Name::from_str(&name) {
+            registers::Name::Z => return None,
+            reg => SetTarget::Register(reg),
+        },
+    };
+    (!value.is_empty()).then_some((target, value))
+}
+
+/// Limits the number of macro expansions per line of user input (guards against recursive macros)
+const MAX_MACRO_EXPANSIONS: usize = 64;
+
+/// Substitutes arguments into a macro body ($1-$9 for individual arguments, $* for all of them)
+/// and splits the result into individual commands
+fn expand_macro(body: &str, args: &[&str]) -> Vec<String> {
+    let mut s = body.replace("$*", &args.join(" "));
+    for i in (1..=9).rev() {
+        s = s.replace(&format!("${}", i), args.get(i - 1).unwrap_or(&""));
+    }
+    s.split(';').map(str::trim).filter(|c| !c.is_empty()).map(String::from).collect()
+}
+
+pub type DebugEditor = rustyline::Editor<DebugHelper, DefaultHistory>;
+
+/// Provides tab completion for the debugger's line editor:
+/// command names for the first word, symbols for words starting with '?'
+/// and register names otherwise.
+#[derive(Default)]
+pub struct DebugHelper {
+    pub symbols: Vec<String>,
+    pub macros: Vec<String>,
+}
+impl DebugHelper {
+    fn command_names() -> impl Iterator<Item = &'static str> {
+        COMMAND_HELP
+            .iter()
+            .filter_map(|h| h.split_whitespace().next())
+            .filter(|c| !c.starts_with('<'))
+    }
+}
+impl Completer for DebugHelper {
+    type Candidate = String;
+    fn complete(
+        &self, line: &str, pos: usize, _: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let candidates = if start == 0 {
+            let mut names: Vec<String> = Self::command_names()
+                .filter(|c| c.starts_with(word))
+                .map(String::from)
+                .collect();
+            names.dedup();
+            names.extend(self.macros.iter().filter(|m| m.starts_with(word)).cloned());
+            names
+        } else if let Some(prefix) = word.strip_prefix('?') {
+            let mut syms: Vec<String> = self
+                .symbols
+                .iter()
+                .filter(|s| s.starts_with(prefix))
+                .map(|s| format!("?{}", s))
+                .collect();
+            syms.sort();
+            syms
+        } else {
+            ["A", "B", "D", "X", "Y", "U", "S", "PC", "DP", "CC"]
+                .iter()
+                .filter(|r| r.starts_with(&word.to_ascii_uppercase()))
+                .map(|r| r.to_string())
+                .collect()
+        };
+        Ok((start, candidates))
+    }
+}
+impl Hinter for DebugHelper {
+    type Hint = String;
+}
+impl Highlighter for DebugHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _: bool) -> Cow<'b, str> {
+        // the prompt is passed in plain so that the editor can measure it; color it here
+        match prompt.strip_prefix("Debug [FAULT]") {
+            Some(rest) => Cow::Owned(format!(concat!(blue!("Debug ["), red!("FAULT"), blue!("]{}")), rest)),
+            None => Cow::Owned(format!(blue!("{}"), prompt)),
+        }
+    }
+}
+impl Validator for DebugHelper {}
+impl rustyline::Helper for DebugHelper {}
+
+/// Returns the path of the file in which debugger command history is kept between sessions
+fn history_path() -> Option<PathBuf> {
+    config::ARGS
+        .debug_history
+        .clone()
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".coco_history")))
+}
+
+/// One instruction in the execution history
+pub struct HistoryEntry {
+    /// address of the instruction
+    pub pc: u16,
+    /// effective address referenced by the instruction
+    pub ea: u16,
+    /// instruction mnemonic
+    pub name: &'static str,
+    /// registers before the instruction executed
+    pub before: registers::Set,
+    /// registers after the instruction executed
+    pub after: registers::Set,
+    /// the formatted instruction (address, symbol, mnemonic, operand)
+    pub line: String,
+}
+impl HistoryEntry {
+    /// Lists the registers changed by this instruction (other than PC), e.g. "A:00->3F X:0400->0401"
+    pub fn deltas(&self) -> String {
+        self.before
+            .diff(&self.after)
+            .iter()
+            .filter(|c| c.reg != registers::Name::PC)
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]", self.line, self.after.display_changes(&self.before))
+    }
+}
+
 /// Tracks the state of the debugger's list mode.
 pub struct ListMode {
     pub lines_remaining: u16,
@@ -87,6 +377,8 @@ pub struct Breakpoint {
     syms: Option<Vec<String>>,
     /// optional notes added by the user
     notes: Option<String>,
+    /// optional condition that must hold for the breakpoint to break
+    condition: Option<expr::Condition>,
 }
 
 impl PartialEq for Breakpoint {
@@ -94,7 +386,9 @@ impl PartialEq for Breakpoint {
 }
 
 impl Breakpoint {
-    pub fn new(addr: u16, watch: bool, syms: Option<&Vec<String>>, notes: Option<String>) -> Self {
+    pub fn new(
+        addr: u16, watch: bool, syms: Option<&Vec<String>>, notes: Option<String>, condition: Option<expr::Condition>,
+    ) -> Self {
         Breakpoint {
             active: true,
             watch,
@@ -107,16 +401,28 @@ impl Breakpoint {
                 v
             }),
             notes,
+            condition,
         }
     }
+    /// Splits the arguments that follow a breakpoint's <loc> into its condition and notes
+    fn parse_args(args: &[&str]) -> Result<(Option<expr::Condition>, Option<String>), Error> {
+        let (condition, notes) = match args {
+            [kw, cond, notes @ ..] if kw.eq_ignore_ascii_case("if") => {
+                (Some(parse::Parser::new().parse_condition(cond)?), notes)
+            }
+            _ => (None, args),
+        };
+        Ok((condition, if notes.is_empty() { None } else { Some(notes.join(" ")) }))
+    }
 }
 impl std::fmt::Display for Breakpoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s1;
         let s2;
+        let s3;
         write!(
             f,
-            "{:04X}{:1}{:1} {:10}{}",
+            "{:04X}{:1}{:1} {:10}{}{}",
             self.addr,
             if self.watch { "w" } else { "" },
             if !self.active { "*" } else { "" },
@@ -126,6 +432,12 @@ impl std::fmt::Display for Breakpoint {
             } else {
                 ""
             },
+            if let Some(condition) = self.condition.as_ref() {
+                s3 = format!(" if {}", condition);
+                s3.as_str()
+            } else {
+                ""
+            },
             if let Some(notes) = self.notes.as_ref() {
                 s2 = format!("  \"{}\"", notes.as_str());
                 s2.as_str()
@@ -143,11 +455,17 @@ pub enum StepMode {
     Stepping,
     StepOverPending(u16),
     SteppingOverTo(u16),
+    /// an instruction previewed with wi is about to execute
+    WhatIf,
+    /// the previewed instruction executed; preview the next one
+    WhatIfNext,
 }
 impl Core {
     pub fn debug_cli(&mut self) -> Result<(), Error> {
         self.in_debugger = true;
         let save_pc = self.reg.pc;
+        // keep previewing instructions if the last one was previewed with wi
+        let mut what_if = self.step_mode == StepMode::WhatIfNext;
         // clear step mode
         self.step_mode = StepMode::Off;
         // clear watch hits
@@ -157,28 +475,150 @@ impl Core {
             self.reg = lm.saved_ctx;
             self.list_mode = None;
         }
-        println!("Current context: [{} -> ({})]", self.reg, self.reg.cc);
+        println!("Current context: [{}]", self.reg);
+        if self.editor.is_none() {
+            let mut editor = DebugEditor::new().map_err(|e| general_err!("failed to start line editor: {}", e))?;
+            editor.set_helper(Some(DebugHelper::default()));
+            if let Some(path) = history_path() {
+                // it's fine if there is no history yet
+                _ = editor.load_history(&path);
+            }
+            self.editor = Some(editor);
+            // the init script runs the first time the debugger is entered
+            if let Some(path) = config::ARGS.debug_init.as_ref() {
+                match std::fs::read_to_string(path) {
+                    Ok(s) => self.debug_pending.extend(s.lines().map(String::from)),
+                    Err(e) => println!("Failed to read debugger init script {}: {}", path.display(), e),
+                }
+            }
+        }
+        let mut expansions = 0;
         loop {
-            let mut input = String::new();
-            if self.faulted {
-                print!(concat!(blue!("Debug ["), red!("FAULT"), blue!("]> ")));
-            } else {
-                print!(blue!("Debug> "))
+            if what_if {
+                what_if = false;
+                if self.what_if()? {
+                    self.step_mode = StepMode::WhatIf;
+                    break;
+                }
+                continue;
+            }
+            let input = match self.debug_pending.pop_front() {
+                Some(line) => {
+                    println!(blue!("> {}"), line.trim());
+                    line
+                }
+                None => {
+                    expansions = 0;
+                    self.read_debug_input(if self.faulted { "Debug [FAULT]> " } else { "Debug> " })?
+                }
             };
-            _ = stdout().flush();
-            _ = stdin().read_line(&mut input);
             let cmd: Vec<&str> = input.split_whitespace().collect();
-            if cmd.is_empty() {
+            if cmd.is_empty() || cmd[0].starts_with('#') {
+                continue;
+            }
+            if let Some(body) = self.debug_macros.get(cmd[0]) {
+                expansions += 1;
+                if expansions > MAX_MACRO_EXPANSIONS {
+                    println!("Too many macro expansions; does a macro invoke itself?");
+                    self.debug_pending.clear();
+                    continue;
+                }
+                for (i, line) in expand_macro(body, &cmd[1..]).into_iter().enumerate() {
+                    self.debug_pending.insert(i, line);
+                }
                 continue;
             }
             match cmd[0].to_lowercase().as_str() {
+                "define" => {
+                    if cmd.len() == 1 {
+                        let mut names: Vec<_> = self.debug_macros.iter().collect();
+                        names.sort();
+                        for (name, body) in names {
+                            println!("  {} = {}", name, body);
+                        }
+                        continue;
+                    }
+                    match input.trim_start()[6..].split_once('=') {
+                        Some((name, body)) if name.split_whitespace().count() == 1 && !body.trim().is_empty() => {
+                            let name = name.trim().to_string();
+                            println!("Defined {} = {}", name, body.trim());
+                            self.debug_macros.insert(name, body.trim().to_string());
+                        }
+                        _ => show_help!(cmd_define),
+                    }
+                }
+                "asm" => {
+                    let addr = match cmd.get(1).and_then(|a| self.parse_address(a)) {
+                        Some(a) if cmd.len() == 2 => a,
+                        _ => {
+                            show_help!(cmd_asm);
+                            continue;
+                        }
+                    };
+                    println!("Enter assembly language lines; end with a blank line.");
+                    let mut src = Vec::new();
+                    loop {
+                        let line = match self.debug_pending.pop_front() {
+                            Some(line) => line,
+                            None => self.read_debug_input("asm> ")?,
+                        };
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        src.push(line);
+                    }
+                    if let Err(e) = self.hot_patch(addr, &src) {
+                        println!("{}", e);
+                    }
+                }
+                "poke" | "pokew" => {
+                    let word = cmd[0].eq_ignore_ascii_case("pokew");
+                    let addr = cmd.get(1).and_then(|a| self.parse_address(a));
+                    let values: Vec<u8u16> = cmd.iter().skip(2).filter_map(|v| self.parse_number(v)).collect();
+                    match addr {
+                        Some(mut addr) if !values.is_empty() && values.len() == cmd.len() - 2 => {
+                            for v in values {
+                                let v = if word { u8u16::u16(v.u16()) } else { u8u16::u8(v.u8()) };
+                                self._write_u8u16(memory::AccessType::System, addr, v)?;
+                                addr = addr.wrapping_add(v.size());
+                            }
+                        }
+                        _ => println!("{}", if word { cmd_pokew } else { cmd_poke }),
+                    }
+                }
+                "undef" => {
+                    if cmd.len() != 2 {
+                        show_help!(cmd_undef);
+                    } else if self.debug_macros.remove(cmd[1]).is_none() {
+                        println!("No macro named {}", cmd[1]);
+                    }
+                }
                 "g" => {
                     // resume running the program
                     break;
                 }
                 "c" => {
-                    println!("Current context: [{} -> ({})]", self.reg, self.reg.cc);
+                    println!("Current context: [{}]", self.reg);
+                }
+                "p" => match cmd.get(1).map(|e| self.eval_expr(e)) {
+                    Some(Ok(v)) if cmd.len() == 2 => println!("{} = ${:04X} ({})", cmd[1], v.u16(), v.u16()),
+                    Some(Err(e)) => println!("{}", e),
+                    _ => show_help!(cmd_p),
+                },
+                "cc" => {
+                    for arg in &cmd[1..] {
+                        let mut chars = arg.chars();
+                        match (chars.next(), chars.next().and_then(registers::CCBit::from_char), chars.next()) {
+                            (Some(op @ ('+' | '-')), Some(bit), None) => self.reg.cc.set(bit, op == '+'),
+                            _ => {
+                                show_help!(cmd_cc);
+                                break;
+                            }
+                        }
+                    }
+                    println!("CC: {} ({:02X})", self.reg.cc, self.reg.cc.reg);
                 }
+                "set" => self.set_cmd(&cmd[1..])?,
                 "dm" => {
                     // dump memory
                     let mut addr = self.reg.pc;
@@ -292,7 +732,39 @@ impl Core {
                     break;
                 }
                 "his" => {
-                    self.show_history();
+                    match cmd.get(1).copied() {
+                        Some("find") if cmd.len() == 3 => self.find_history(cmd[2]),
+                        Some("save") if cmd.len() == 3 => match self.save_history(cmd[2]) {
+                            Ok(n) => println!("Saved {} instructions to {}", n, cmd[2]),
+                            Err(e) => println!("{}", e),
+                        },
+                        Some("len") if cmd.len() == 3 => {
+                            if let Some(n) = self.parse_number(cmd[2]) {
+                                self.history_len = n.u16() as usize;
+                                if let Some(history) = self.history.as_mut() {
+                                    while history.len() > self.history_len {
+                                        history.pop_front();
+                                    }
+                                }
+                                println!("History length is now {}", self.history_len);
+                            } else {
+                                show_help!(cmd_hisl);
+                            }
+                        }
+                        Some("find") | Some("save") | Some("len") => {
+                            show_help!(cmd_hisf);
+                            show_help!(cmd_hiss);
+                            show_help!(cmd_hisl);
+                        }
+                        _ => {
+                            let deltas = cmd.get(1) == Some(&"d");
+                            let count = cmd
+                                .get(if deltas { 2 } else { 1 })
+                                .and_then(|s| self.parse_number(s))
+                                .map_or(usize::MAX, |n| n.u16() as usize);
+                            self.show_history(count, deltas);
+                        }
+                    }
                     continue;
                 }
                 "ba" => {
@@ -302,12 +774,15 @@ impl Core {
                         continue;
                     }
                     if let Some(addr) = self.parse_address(cmd[1]) {
-                        self.breakpoints.push(Breakpoint::new(
-                            addr,
-                            false,
-                            self.symbol_by_addr(addr),
-                            if cmd.len() > 2 { Some(cmd[2..].join(" ")) } else { None },
-                        ));
+                        let (condition, notes) = match Breakpoint::parse_args(&cmd[2..]) {
+                            Ok(args) => args,
+                            Err(e) => {
+                                println!("Invalid condition: {}", e);
+                                continue;
+                            }
+                        };
+                        self.breakpoints
+                            .push(Breakpoint::new(addr, false, self.symbol_by_addr(addr), notes, condition));
                         println!("Breakpoint {} added at {:04X}", self.breakpoints.len() - 1, addr);
                     } else {
                         println!("Invalid address or symbol.");
@@ -321,12 +796,15 @@ impl Core {
                         continue;
                     }
                     if let Some(addr) = self.parse_address(cmd[1]) {
-                        self.breakpoints.push(Breakpoint::new(
-                            addr,
-                            true,
-                            self.symbol_by_addr(addr),
-                            if cmd.len() > 2 { Some(cmd[2..].join(" ")) } else { None },
-                        ));
+                        let (condition, notes) = match Breakpoint::parse_args(&cmd[2..]) {
+                            Ok(args) => args,
+                            Err(e) => {
+                                println!("Invalid condition: {}", e);
+                                continue;
+                            }
+                        };
+                        self.breakpoints
+                            .push(Breakpoint::new(addr, true, self.symbol_by_addr(addr), notes, condition));
                         println!("Breakpoint {} added watching {:04X}", self.breakpoints.len() - 1, addr);
                     } else {
                         println!("Invalid address or symbol.");
@@ -397,6 +875,17 @@ impl Core {
                     self.reset()?;
                     break;
                 }
+                "reset" => {
+                    match cmd.get(1).copied() {
+                        None | Some("warm") => self.warm_reset()?,
+                        Some("cold") => self.cold_reset()?,
+                        _ => {
+                            show_help!(cmd_reset);
+                            continue;
+                        }
+                    }
+                    break;
+                }
                 "rs" => {
                     self.reset()?;
                     self.step_mode = StepMode::Stepping;
@@ -416,6 +905,17 @@ impl Core {
                     self.step_mode = StepMode::StepOverPending(self.next_linear_step);
                     break;
                 }
+                "wi" => what_if = true,
+                "skip" => match cmd.get(1).map(|n| self.parse_number(n)) {
+                    None => self.skip_instructions(1),
+                    Some(Some(n)) if cmd.len() == 2 => self.skip_instructions(n.u16()),
+                    _ => show_help!(cmd_skip),
+                },
+                "branch" => match cmd.get(1).map(|s| s.to_lowercase()).as_deref() {
+                    Some("t" | "taken") if cmd.len() == 2 => self.force_branch(true),
+                    Some("n" | "not") if cmd.len() == 2 => self.force_branch(false),
+                    _ => show_help!(cmd_branch),
+                },
                 "sym" => {
                     if self.sym_to_addr.is_empty() {
                         println!("No symbols loaded. Use 'load' to load symbols.");
@@ -427,7 +927,7 @@ impl Core {
                             .iter()
                             .map(|(k, v)| (k.as_str(), *v))
                             .collect::<Vec<(&str, u16)>>();
-                        s.sort_by(|(s1, _), (s2, _)| s1.cmp(s2));
+                        s.sort_by_key(|(s, _)| *s);
                         s.iter().for_each(|(sym, addr)| {
                             println!("  {:04X} - {}", addr, sym);
                         });
@@ -461,6 +961,127 @@ impl Core {
                         show_help!(cmd_sym)
                     }
                 }
+                "region" => match cmd.get(1).copied() {
+                    None => {
+                        self.regions.iter().for_each(|r| println!("  {}", r));
+                    }
+                    Some("clear") => self.regions.clear(),
+                    Some("del") => match cmd.get(2).and_then(|a| self.parse_address(a)) {
+                        Some(addr) => println!("Removed {} region(s).", self.regions.remove(addr)),
+                        None => show_help!(cmd_region),
+                    },
+                    Some(_) => {
+                        let start = cmd.get(1).and_then(|a| self.parse_address(a));
+                        let end = cmd.get(2).and_then(|a| self.parse_address(a));
+                        match (start, end, cmd.get(3).map(|k| k.parse::<region::RegionKind>())) {
+                            (Some(start), Some(end), Some(Ok(kind))) if start <= end => self.regions.add(region::Region {
+                                start,
+                                end,
+                                kind,
+                                name: cmd[4..].join(" "),
+                            }),
+                            (_, _, Some(Err(e))) => println!("{}", e),
+                            _ => show_help!(cmd_region),
+                        }
+                    }
+                },
+                "cart" => match (cmd.get(1).copied(), cmd.get(2)) {
+                    (None, _) => {
+                        match self.cart.as_ref() {
+                            Some(cart) => println!(
+                                "{} is {}",
+                                cart.name(),
+                                if self.cart_inserted { "inserted" } else { "pulled" }
+                            ),
+                            None => println!("No cartridge."),
+                        }
+                        for pak in &self.paks {
+                            println!("{} is plugged in", pak.name());
+                        }
+                    }
+                    (Some("pull"), None) => self.pull_cart(),
+                    (Some("insert"), None) => {
+                        if self.insert_cart()? {
+                            break;
+                        }
+                    }
+                    (Some("insert"), Some(path)) => {
+                        self.pull_cart();
+                        if let Err(e) = self.load_cart(Path::new(path)) {
+                            println!("Failed to load cartridge: {}", e);
+                        } else if config::ARGS.cart_reset {
+                            self.warm_reset()?;
+                            break;
+                        }
+                    }
+                    _ => show_help!(cmd_cart),
+                },
+                "tape" => match (self.cassette.as_ref(), cmd.get(1).copied()) {
+                    (None, _) => println!("There's no cassette (use --cassette <file> to put one in)."),
+                    (Some(cassette), None) => println!("{}", cassette.borrow()),
+                    (Some(cassette), Some("rewind")) => {
+                        cassette.borrow_mut().rewind(self.clock_cycles);
+                        println!("{}", cassette.borrow());
+                    }
+                    _ => show_help!(cmd_tape),
+                },
+                "acia" => match (self.acia.as_ref(), cmd.get(1).copied()) {
+                    (None, _) => println!("The ACIA is disabled (use --acia-enable to enable it)."),
+                    (Some(acia), None) => acia.stats().iter().for_each(|s| println!("  {}", s)),
+                    (Some(acia), Some("clear")) => acia.clear_stats(),
+                    _ => show_help!(cmd_acia),
+                },
+                "state" | "save" | "restore" => {
+                    let name = cmd[0].to_lowercase();
+                    let (action, path) = match name.as_str() {
+                        "state" => (cmd.get(1).copied(), cmd.get(2)),
+                        _ => (Some(name.as_str()), cmd.get(1)),
+                    };
+                    match (action, path) {
+                        (Some("save"), Some(path)) => {
+                            if let Err(e) = self.save_snapshot(Path::new(path)) {
+                                println!("Failed to save the state: {}", e);
+                            }
+                        }
+                        (Some("load" | "restore"), Some(path)) => {
+                            if let Err(e) = self.load_snapshot(Path::new(path)) {
+                                println!("Failed to load the state: {}", e);
+                            }
+                        }
+                        _ => show_help!(cmd_state),
+                    }
+                }
+                "iolog" => match (self.io_log.as_ref(), cmd.get(1).copied()) {
+                    (None, _) => println!("The I/O log is disabled (use --io-log <N> to enable it)."),
+                    (Some(log), Some("clear")) => log.borrow_mut().clear(),
+                    (Some(log), n) => match n.map_or(Ok(20), str::parse::<usize>) {
+                        Ok(n) => log.borrow().last(n).for_each(|a| println!("  {}", a)),
+                        Err(_) => show_help!(cmd_iolog),
+                    },
+                },
+                "warp" => match cmd.get(1).copied() {
+                    None => println!("Warp mode is {}.", if self.warp { "on" } else { "off" }),
+                    Some("on") => self.set_warp(true),
+                    Some("off") => self.set_warp(false),
+                    _ => show_help!(cmd_warp),
+                },
+                "latency" => match cmd.get(1).copied() {
+                    Some("clear") => self.latency.clear(),
+                    n => match n.map_or(Ok(usize::MAX), str::parse::<usize>) {
+                        Ok(n) => self.latency.summary(n).iter().for_each(|s| println!("  {}", s)),
+                        Err(_) => show_help!(cmd_latency),
+                    },
+                },
+                "nmi" => {
+                    self.assert_nmi();
+                    match self.take_nmi() {
+                        Ok(_) => println!("NMI taken; the handler is at {:04X}", self.reg.pc),
+                        Err(e) => println!("Failed to take the NMI: {}", e),
+                    }
+                }
+                "loadmap" => self.load_map.iter().for_each(|s| println!("  {}", s)),
+                "cheat" => self.cheat_cmd(&cmd[1..]),
+                "spy" => self.spy_cmd(&cmd[1..], input.trim_start()[3..].trim()),
                 "load" => {
                     // load symbols
                     if cmd.len() != 2 {
@@ -472,6 +1093,69 @@ impl Core {
                         Err(e) => println!("{}", e),
                     }
                 }
+                "disk" => {
+                    if cmd.len() == 1 {
+                        for (i, d) in self.disks.borrow().drive.iter().enumerate() {
+                            match d {
+                                Some(image) => println!("  {}. {}", i, image),
+                                None => println!("  {}. <empty>", i),
+                            }
+                        }
+                        continue;
+                    }
+                    let mut disks = self.disks.borrow_mut();
+                    let image = match cmd[1].parse::<usize>().ok().and_then(|i| disks.drive.get_mut(i)) {
+                        Some(Some(image)) if cmd.len() == 3 => image,
+                        _ => {
+                            println!("Invalid drive.");
+                            show_help!(cmd_disk);
+                            continue;
+                        }
+                    };
+                    match cmd[2] {
+                        "flush" => {
+                            if let Err(e) = image.flush() {
+                                println!("{}", e);
+                            }
+                        }
+                        "discard" => image.discard(),
+                        "wp" => image.write_protect = true,
+                        "rw" => image.write_protect = false,
+                        _ => {
+                            show_help!(cmd_disk);
+                            continue;
+                        }
+                    }
+                    println!("{}", image);
+                }
+                "dski" | "dsko" => {
+                    let input = cmd[0].eq_ignore_ascii_case("dski");
+                    let nums: Vec<usize> = cmd[1..]
+                        .iter()
+                        .filter_map(|s| self.parse_number(s))
+                        .map(|n| n.u16() as usize)
+                        .collect();
+                    if nums.len() != cmd.len() - 1 || nums.len() < 3 || (input && nums.len() != 3) {
+                        println!("{}", if input { cmd_dski } else { cmd_dsko });
+                        continue;
+                    }
+                    let (drive, track, sector) = (nums[0], nums[1], nums[2]);
+                    if !input {
+                        let bytes: Result<Vec<u8>, _> = nums[3..].iter().map(|&b| u8::try_from(b)).collect();
+                        let Ok(bytes) = bytes else {
+                            println!("{}", tr!("bytes must be from 0 to 255"));
+                            continue;
+                        };
+                        if let Err(e) = self.disks.borrow_mut().write_sector(drive, track, sector, &bytes) {
+                            println!("{}", e);
+                            continue;
+                        }
+                    }
+                    match self.disks.borrow().read_sector(drive, track, sector) {
+                        Ok(data) => dump_bytes(0, data, |_| None),
+                        Err(e) => println!("{}", e),
+                    }
+                }
                 "t" | "trace" => {
                     // toggle trace
                     self.trace = !self.trace;
@@ -479,11 +1163,11 @@ impl Core {
                 }
                 "h" => {
                     for help in COMMAND_HELP {
-                        println!("{}", help);
+                        println!("{}", tr!(help));
                     }
                 }
                 _ => {
-                    println!("Unknown command. Try 'h' for help.");
+                    println!("{}", tr!("Unknown command. Try 'h' for help."));
                 }
             }
         }
@@ -491,10 +1175,208 @@ impl Core {
             // whenever we alter the program counter, history must be cleared
             self.clear_history();
         }
+        // commands left over from a macro or script don't carry over to the next session
+        self.debug_pending.clear();
         term::flush_keyboard_input();
         self.in_debugger = false;
         Ok(())
     }
+    /// Lists, adds or removes spies (see spy.rs); spec is the text of the arguments
+    fn spy_cmd(&mut self, args: &[&str], spec: &str) {
+        let spies = self.spies.as_mut().map(|s| &mut s.get_mut().list);
+        match (args, spies) {
+            ([], None) => println!("No spies."),
+            ([], Some(spies)) => {
+                for (i, spy) in spies.iter().enumerate() {
+                    println!("{:>3}: {}", i, spy);
+                }
+            }
+            (["clear"], spies) => {
+                if let Some(spies) = spies {
+                    spies.clear();
+                }
+            }
+            (["del", num], spies) => match (num.parse::<usize>(), spies) {
+                (Ok(i), Some(spies)) if i < spies.len() => println!("Removed spy {}", spies.remove(i)),
+                _ => println!("No spy #{}", num),
+            },
+            _ => match spy::Spy::parse(spec, |loc| self.parse_address(loc)).and_then(|spy| self.add_spy(spy)) {
+                Ok(()) => {}
+                Err(e) => {
+                    println!("{}", e);
+                    show_help!(cmd_spy);
+                }
+            },
+        }
+    }
+    /// Sets registers or condition code flags (or edits all the registers if there are no args)
+    fn set_cmd(&mut self, args: &[&str]) -> Result<(), Error> {
+        if args.is_empty() {
+            return self.edit_registers();
+        }
+        for arg in args {
+            let Some((target, value)) = parse_set(arg) else {
+                show_help!(cmd_set);
+                return Ok(());
+            };
+            if let Err(e) = self.set_value(target, value) {
+                println!("{}", e);
+                return Ok(());
+            }
+        }
+        println!("Current context: [{}]", self.reg);
+        Ok(())
+    }
+    /// Walks through the registers, letting the user edit each one's value
+    fn edit_registers(&mut self) -> Result<(), Error> {
+        use registers::Name::*;
+        println!("Edit each register's value and press enter (or just press enter to keep it).");
+        for reg in [A, B, X, Y, U, S, DP, CC, PC] {
+            let current = format!("${}", self.reg.get_register(reg));
+            loop {
+                let prompt = format!("{:?}=", reg);
+                let input = match self.debug_pending.pop_front() {
+                    Some(line) => line,
+                    None => self.edit_debug_input(&prompt, &current)?,
+                };
+                let input = input.trim();
+                if input.is_empty() || input == current {
+                    break;
+                }
+                match self.set_value(SetTarget::Register(reg), input) {
+                    Ok(()) => break,
+                    Err(e) => println!("{}", e),
+                }
+            }
+        }
+        println!("Current context: [{}]", self.reg);
+        Ok(())
+    }
+    /// Sets a register or flag to the value of an expression. Since the user has fixed things up,
+    /// this also clears a fault so that execution can continue.
+    fn set_value(&mut self, target: SetTarget, value: &str) -> Result<(), Error> {
+        let value = self.eval_expr(value)?.u16();
+        match target {
+            SetTarget::Flag(bit) if value <= 1 => self.reg.cc.set(bit, value == 1),
+            SetTarget::Flag(bit) => return Err(general_err!("flag {} can only be set to 0 or 1", bit.short())),
+            SetTarget::Register(reg) if registers::reg_size(reg) == 1 => match u8::try_from(value) {
+                Ok(v) => self.reg.set_register(reg, u8u16::u8(v)),
+                Err(_) => return Err(general_err!("{:?} is an 8-bit register; ${:04X} doesn't fit", reg, value)),
+            },
+            SetTarget::Register(reg) => self.reg.set_register(reg, u8u16::u16(value)),
+        }
+        if self.faulted {
+            self.faulted = false;
+            println!("The fault is cleared; 'g' resumes at PC={:04X}.", self.reg.pc);
+        }
+        Ok(())
+    }
+    /// Lists, adds or removes cheats or searches RAM (see cheat.rs)
+    fn cheat_cmd(&mut self, args: &[&str]) {
+        use cheat::{Cheat, Filter, Search};
+        let ram = &self.raw_ram[..=self.ram_top as usize];
+        match args {
+            [] => {
+                for (i, cheat) in self.cheats.cheats.iter().enumerate() {
+                    println!("  {}: {}", i, cheat);
+                }
+                if let Some(search) = self.cheats.search.as_ref() {
+                    println!("{} search candidate(s).", search.candidates.len());
+                }
+            }
+            [add @ ("add" | "addw"), loc, val, name @ ..] => match (self.parse_address(loc), self.parse_number(val)) {
+                (Some(addr), Some(val)) => {
+                    let val = if *add == "addw" { u8u16::u16(val.u16()) } else { u8u16::u8(val.u8()) };
+                    let cheat = Cheat { addr, val, name: name.join(" ") };
+                    println!("Freezing {}", cheat);
+                    self.cheats.cheats.push(cheat);
+                }
+                _ => show_help!(cmd_cheat),
+            },
+            ["del", num] => match num.parse::<usize>() {
+                Ok(i) if i < self.cheats.cheats.len() => _ = self.cheats.cheats.remove(i),
+                _ => println!("No cheat #{}. Use \"cheat\" to see the current cheats.", num),
+            },
+            ["clear"] => self.cheats.cheats.clear(),
+            ["load", path] => match self.cheats.load(Path::new(path)) {
+                Ok(n) => println!("Loaded {} cheat(s).", n),
+                Err(e) => println!("{}", e),
+            },
+            ["save"] | ["save", _] => match self.cheats.save(args.get(1).map(Path::new)) {
+                Ok(path) => println!("Saved {} cheat(s) to {}.", self.cheats.cheats.len(), path.display()),
+                Err(e) => println!("{}", e),
+            },
+            ["search", "new"] => {
+                self.cheats.search = Some(Search::new(ram));
+                println!("Searching {} bytes of RAM.", ram.len());
+            }
+            ["search"] | ["search", "list"] => match self.cheats.search.as_ref() {
+                Some(search) if search.candidates.len() <= cheat::MAX_LISTED => {
+                    for &addr in &search.candidates {
+                        println!("  {:04X}: {:02X}", addr, ram[addr as usize]);
+                    }
+                }
+                Some(search) => println!("{} candidates; narrow the search down further.", search.candidates.len()),
+                None => println!("No search in progress."),
+            },
+            ["search", op, val @ ..] => {
+                let val = match val {
+                    [] => None,
+                    [v] => match self.parse_number(v).filter(|v| v.u16() <= 0xff) {
+                        Some(v) => Some(v.u8()),
+                        None => return println!("Invalid byte \"{}\"", v),
+                    },
+                    _ => return show_help!(cmd_cheats),
+                };
+                let Some(filter) = Filter::parse(op, val) else {
+                    return show_help!(cmd_cheats);
+                };
+                let search = match (self.cheats.search.as_mut(), filter) {
+                    (Some(search), _) => search,
+                    // a search for a value can start from scratch
+                    (None, Filter::Eq(_) | Filter::Ne(_) | Filter::Gt(_) | Filter::Lt(_)) => {
+                        self.cheats.search.insert(Search::new(ram))
+                    }
+                    (None, _) => return println!("Start with \"cheat search new\" or a search for a value."),
+                };
+                let n = search.filter(ram, filter);
+                if n <= cheat::MAX_LISTED {
+                    for &addr in &search.candidates {
+                        println!("  {:04X}: {:02X} (was {:02X})", addr, ram[addr as usize], search.prev(addr));
+                    }
+                }
+                println!("{} candidate(s) left.", n);
+            }
+            _ => {
+                show_help!(cmd_cheat);
+                show_help!(cmd_cheats);
+            }
+        }
+    }
+    /// Reads one line of input from the user via the line editor (saving it in the history)
+    fn read_debug_input(&mut self, prompt: &str) -> Result<String, Error> { self.edit_debug_input(prompt, "") }
+    /// Reads a line of input that starts out as initial (which the user can edit)
+    fn edit_debug_input(&mut self, prompt: &str, initial: &str) -> Result<String, Error> {
+        let editor = self.editor.as_mut().unwrap();
+        if let Some(helper) = editor.helper_mut() {
+            helper.symbols = self.sym_to_addr.keys().cloned().collect();
+            helper.macros = self.debug_macros.keys().cloned().collect();
+        }
+        let input = match editor.readline_with_initial(prompt, (initial, "")) {
+            Ok(line) => line,
+            Err(rustyline::error::ReadlineError::Interrupted | rustyline::error::ReadlineError::Eof) => {
+                return Err(Error::new(ErrorKind::Exit, None, "session terminated by user"))
+            }
+            Err(e) => return Err(general_err!("failed to read debugger input: {}", e)),
+        };
+        if !input.trim().is_empty() && initial.is_empty() {
+            _ = editor.add_history_entry(input.trim());
+            if let Some(path) = history_path() {
+                _ = editor.save_history(&path);
+            }
+        }
+        Ok(input)
+    }
     pub fn load_symbols(&mut self, filename: &str) -> Result<usize, Error> {
         let path = std::path::Path::new(filename);
         if let Ok(f) = std::fs::File::open(path) {
@@ -506,6 +1388,11 @@ impl Core {
                     return Err(Error::new(ErrorKind::IO, None, msg.as_str()));
                 }
                 let line = res.unwrap();
+                if line.split(',').next().is_some_and(|c| c.contains('-')) {
+                    // START-END,kind[,name] describes a memory region
+                    self.regions.add(region::Region::parse(&line)?);
+                    continue;
+                }
                 let comps: Vec<&str> = line.split(',').collect();
                 if comps.len() != 2 {
                     return Err(Error::new(ErrorKind::IO, None, "Invalid symbol file format"));
@@ -559,14 +1446,17 @@ impl Core {
     pub fn debug_check_for_watch_hit(&self, addr: u16) {
         for bp in &self.breakpoints {
             if addr == bp.addr && bp.active && bp.watch {
-                println!("Hit at {:04X}", addr);
+                // conditional watches only report the hits that break (see pre_instruction_debug_check)
+                if bp.condition.is_none() {
+                    println!("Hit at {:04X}", addr);
+                }
                 self.watch_hits.borrow_mut().push(addr);
                 return;
             }
         }
     }
     fn clear_symbols(&mut self) { self.addr_to_sym.clear(); }
-    fn add_symbol(&mut self, addr: u16, name: &str) {
+    pub fn add_symbol(&mut self, addr: u16, name: &str) {
         // add symbol to addr_to_sym table
         if let Some(names) = self.addr_to_sym.get_mut(&addr) {
             // address is already in the symbol table
@@ -580,15 +1470,30 @@ impl Core {
     }
     pub fn symbol_by_name(&self, name: &str) -> Option<u16> { self.sym_to_addr.get(name).copied() }
     pub fn symbol_by_addr(&self, addr: u16) -> Option<&Vec<String>> { self.addr_to_sym.get(&addr) }
-    fn parse_address(&self, addr_sym: &str) -> Option<u16> {
+    pub fn parse_address(&self, addr_sym: &str) -> Option<u16> {
         if let Some(name) = addr_sym.strip_prefix('?') {
             self.symbol_by_name(name)
         } else if let Ok(addr) = u16::from_str_radix(addr_sym, 16) {
             Some(addr)
         } else {
-            None
+            self.eval_expr(addr_sym).ok().map(|v| v.u16())
         }
     }
+    /// Evaluates an expression with the debugger's symbols, the registers and memory
+    fn eval_expr(&self, src: &str) -> Result<u8u16, Error> {
+        parse::Parser::new().str_to_value_node(src)?.eval(self, self.reg.pc, false)
+    }
+    /// Returns true if the breakpoint has no condition or its condition holds. A condition that
+    /// can't be evaluated (e.g., it reads I/O space) is reported and counts as holding.
+    fn condition_holds(&self, bp: &Breakpoint) -> bool {
+        let Some(condition) = bp.condition.as_ref() else {
+            return true;
+        };
+        condition.eval(self, self.reg.pc).unwrap_or_else(|e| {
+            println!("Can't evaluate breakpoint condition {}: {}", condition, e);
+            true
+        })
+    }
     fn parse_number(&self, str_num: &str) -> Option<u8u16> {
         let mut number: Option<u8u16> = None;
         let mut negative = false;
@@ -616,22 +1521,158 @@ impl Core {
             }
         })
     }
-    fn show_history(&self) {
-        let mut count = 0;
-        if let Some(history) = self.history.as_ref() {
-            count = history.len();
-            if count > 0 {
-                println!("Showing executed instruction history (length = {})", count);
-                for line in history {
-                    println!("{}", line);
+    /// Returns the size of the instruction at addr (or None if it doesn't decode)
+    pub fn instruction_size_at(&mut self, addr: u16) -> Option<u16> { self.decode_at(addr).map(|inst| inst.size) }
+    /// Decodes the instruction at addr without evaluating it (or returns None if it doesn't decode)
+    fn decode_at(&mut self, addr: u16) -> Option<instructions::Instance> {
+        let (saved_reg, saved_count, saved_cycles) = (self.reg, self.instruction_count, self.clock_cycles);
+        // decode in list mode so that the instruction isn't evaluated
+        let saved_list_mode = self.list_mode.replace(ListMode {
+            lines_remaining: 0,
+            saved_ctx: saved_reg,
+        });
+        self.reg.pc = addr;
+        let inst = self.exec_next(false).ok().map(|o| o.inst);
+        self.list_mode = saved_list_mode;
+        (self.reg, self.instruction_count, self.clock_cycles) = (saved_reg, saved_count, saved_cycles);
+        inst
+    }
+    /// Moves PC past count instructions without executing them
+    fn skip_instructions(&mut self, count: u16) {
+        for _ in 0..count {
+            let Some(inst) = self.decode_at(self.reg.pc) else {
+                println!("Can't decode the instruction at {:04X}.", self.reg.pc);
+                return;
+            };
+            let operand = inst.operand.as_deref().unwrap_or("");
+            println!("Skipped {:04X}: {} {}", self.reg.pc, inst.flavor.desc.name, operand);
+            self.reg.pc = self.reg.pc.wrapping_add(inst.size);
+        }
+    }
+    /// Makes the conditional branch at PC go to its destination (taken) or fall through to the
+    /// next instruction without evaluating its condition
+    fn force_branch(&mut self, taken: bool) {
+        let pc = self.reg.pc;
+        let Some(inst) = self.decode_at(pc) else {
+            println!("Can't decode the instruction at {:04X}.", pc);
+            return;
+        };
+        let name = inst.flavor.desc.name;
+        if inst.flavor.mode != instructions::AddressingMode::Relative || UNCONDITIONAL_BRANCHES.contains(&name) {
+            println!("{} at {:04X} isn't a conditional branch.", name, pc);
+            return;
+        }
+        self.reg.pc = if taken { inst.ea } else { pc.wrapping_add(inst.size) };
+        println!("{} at {:04X} {}; PC={:04X}", name, pc, if taken { "taken" } else { "not taken" }, self.reg.pc);
+    }
+    /// Assembles the given source lines at addr and writes the result into memory. The new code
+    /// has to take exactly the bytes of the instructions it replaces (so that no instruction is
+    /// left half overwritten); otherwise nothing is written and the sizes are reported.
+    fn hot_patch(&mut self, addr: u16, src: &[String]) -> Result<(), Error> {
+        let mut lines = vec![format!(" ORG ${:04X}", addr)];
+        // make debugger symbols referenced by the new code available to the assembler
+        let text = src.join(" ");
+        for (sym, sym_addr) in &self.sym_to_addr {
+            if text.contains(sym.as_str()) {
+                lines.push(format!("{} EQU ${:04X}", sym, sym_addr));
+            }
+        }
+        // lines that don't start with a label need leading whitespace
+        for line in src {
+            let label = line.split_whitespace().next().is_some_and(|w| w.ends_with(':'));
+            lines.push(if label { line.clone() } else { format!(" {}", line.trim()) });
+        }
+        let program = Assembler::new().assemble_lines(lines)?;
+        let mut segments = program.segments();
+        if let Some(stray) = segments.iter().find(|seg| seg.addr != addr) {
+            return Err(general_err!("patch code must be contiguous (found code at {:04X})", stray.addr));
+        }
+        let bytes = segments.pop().map_or(Vec::new(), |seg| seg.bytes);
+        if bytes.is_empty() || addr as usize + bytes.len() > 0x10000 {
+            return Err(general_err!("nothing to patch at {:04X}", addr));
+        }
+        // find the end of the last instruction that the new code overwrites
+        let end = addr as usize + bytes.len();
+        let (mut old_end, mut replaced) = (addr as usize, 0);
+        while old_end < end {
+            let size = self.instruction_size_at(old_end as u16);
+            let size = size.ok_or_else(|| general_err!("can't decode the instruction at {:04X}", old_end))?;
+            old_end += size as usize;
+            replaced += 1;
+        }
+        if old_end != end {
+            return Err(general_err!(
+                "the patch is {} bytes but the {} instruction(s) it overwrites take {} (pad it with NOPs to match)",
+                bytes.len(),
+                replaced,
+                old_end - addr as usize
+            ));
+        }
+        for (i, b) in bytes.iter().enumerate() {
+            self._write_u8(memory::AccessType::System, addr + i as u16, *b)?;
+        }
+        println!("Wrote {} bytes at {:04X} replacing {} instruction(s)", bytes.len(), addr, replaced);
+        Ok(())
+    }
+    /// Shows the most recent count entries of the history, optionally with register changes
+    fn show_history(&self, count: usize, deltas: bool) {
+        let history = match self.history.as_ref() {
+            Some(h) if !h.is_empty() => h,
+            _ => {
+                println!("No history available.");
+                return;
+            }
+        };
+        let count = count.min(history.len());
+        println!("Showing executed instruction history ({} of {})", count, history.len());
+        for entry in history.iter().skip(history.len() - count) {
+            if deltas {
+                println!("{} {}", entry.line, entry.deltas());
+            } else {
+                println!("{}", entry);
+            }
+        }
+    }
+    /// Shows history entries matching term: a mnemonic, an address (matching either the
+    /// instruction's address or its effective address) or <reg>=<value> (register value after execution)
+    fn find_history(&self, term: &str) {
+        let matcher: Box<dyn Fn(&HistoryEntry) -> bool> = if let Some((reg, val)) = term.split_once('=') {
+            let reg = registers::Name::from_str(reg);
+            match self.parse_number(val) {
+                Some(val) if reg != registers::Name::Z => {
+                    Box::new(move |e: &HistoryEntry| e.after.get_register(reg).u16() == val.u16())
+                }
+                _ => {
+                    show_help!(cmd_hisf);
+                    return;
                 }
             }
+        } else if let Some(desc) = instructions::name_to_descriptor(&term.to_ascii_uppercase()) {
+            Box::new(move |e: &HistoryEntry| e.name == desc.name)
+        } else if let Some(addr) = self.parse_address(term) {
+            Box::new(move |e: &HistoryEntry| e.pc == addr || e.ea == addr)
+        } else {
+            show_help!(cmd_hisf);
+            return;
+        };
+        let found: Vec<_> = self.history.iter().flatten().filter(|e| matcher(e)).collect();
+        for entry in &found {
+            println!("{} {}", entry.line, entry.deltas());
         }
-        if count == 0 {
-            println!("No history available.")
+        println!("{} matching instruction(s)", found.len());
+    }
+    /// Writes the history to a file (without terminal color codes)
+    fn save_history(&self, path: &str) -> Result<usize, Error> {
+        let color = regex::Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        let mut out = String::new();
+        for entry in self.history.iter().flatten() {
+            out.push_str(&color.replace_all(&entry.to_string(), ""));
+            out.push('\n');
         }
+        std::fs::write(path, out)?;
+        Ok(self.history.as_ref().map_or(0, |h| h.len()))
     }
-    fn clear_history(&mut self) { self.history = None; }
+    pub fn clear_history(&mut self) { self.history = None; }
     pub fn pre_instruction_debug_check(&mut self, pc: u16) -> bool {
         if let Some(lm) = self.list_mode.as_mut() {
             if lm.lines_remaining == 0 {
@@ -642,8 +1683,8 @@ impl Core {
             // when listing, skip all other considerations
             return false;
         }
-        if self.faulted {
-            // can't run anything if we're faulted
+        if self.faulted || self.step_mode == StepMode::WhatIfNext {
+            // can't run anything if we're faulted (and a previewed instruction only runs by itself)
             return true;
         }
         // if break_start is true then always break into debugger when the instruction at program_start is about to be executed
@@ -672,28 +1713,40 @@ impl Core {
         }
         let hit_breakpoint = || -> bool {
             let mut breakpoint = false;
-            let watch_hits = self.watch_hits.borrow();
+            // the hits of watches whose conditions don't hold are dropped
+            let watch_hits: Vec<u16> = self.watch_hits.borrow_mut().drain(..).collect();
             // if we hit a watch then break into the debugger
-            if !watch_hits.is_empty() {
-                for addr in watch_hits.iter() {
-                    if let Some(bp) = self.get_breakpoint_by_addr(*addr, true) {
+            for addr in watch_hits.iter() {
+                if let Some(bp) = self.get_breakpoint_by_addr(*addr, true) {
+                    if self.condition_holds(bp) {
                         println!("Paused at watch breakpoint: {}", bp);
+                        breakpoint = true;
                     }
                 }
+            }
+            if let Some(spy) = self.spy_stop() {
+                println!("Paused by spy: {}", spy);
                 breakpoint = true;
             }
             // if we're at a breakpoint then break into the debugger
             for bp in &self.breakpoints {
-                if pc == bp.addr && bp.active {
+                if pc == bp.addr && bp.active && self.condition_holds(bp) {
                     println!("Paused at breakpoint: {}", bp);
                     breakpoint = true;
                 }
             }
             breakpoint
         };
-        hit_breakpoint()
+        let hit = hit_breakpoint();
+        if hit {
+            self.title_stop(true);
+        }
+        hit
     }
     pub fn post_instruction_debug_check(&mut self, instruction_pc: u16, outcome: &instructions::Outcome) {
+        if self.list_mode.is_none() {
+            self.regions.check_pc(instruction_pc);
+        }
         if let StepMode::StepOverPending(addr) = self.step_mode {
             // time to start our step-over; remember the address we're stepping to
             self.step_mode = StepMode::SteppingOverTo(addr);
@@ -704,8 +1757,10 @@ impl Core {
                 // we hit our destination address so switch back into stepping mode
                 self.step_mode = StepMode::Stepping;
             }
+        } else if self.step_mode == StepMode::WhatIf {
+            self.step_mode = StepMode::WhatIfNext;
         }
-        if self.trace || self.step_mode == StepMode::Stepping || self.list_mode.is_some() || config::ARGS.history > 0 {
+        if self.trace || self.step_mode == StepMode::Stepping || self.list_mode.is_some() || self.history_len > 0 {
             let mut sym_plus = false;
             let mut sym = String::from(self.symbol_by_addr(instruction_pc).map_or("", |v| {
                 sym_plus = v.len() > 1;
@@ -728,30 +1783,34 @@ impl Core {
                     }
                 }
             }
-            let mut line = format!(
-                "{:04X}: {:10} {:8} {:10} {:10}",
-                instruction_pc,
-                sym,
-                outcome.inst.flavor.desc.name,
-                outcome.inst.operand.as_ref().unwrap_or(&String::from("")),
-                extra_data,
-            );
-            if self.list_mode.is_none() {
-                line.push_str(format!(" [{} -> ({})]", self.reg, self.reg.cc).as_str());
-            }
+            let entry = HistoryEntry {
+                pc: instruction_pc,
+                ea: outcome.inst.ea,
+                name: outcome.inst.flavor.desc.name,
+                before: outcome.inst.ctx,
+                after: self.reg,
+                line: format!(
+                    "{:04X}: {:10} {:8} {:10} {:10}",
+                    instruction_pc,
+                    sym,
+                    outcome.inst.flavor.desc.name,
+                    outcome.inst.operand.as_ref().unwrap_or(&String::from("")),
+                    extra_data,
+                ),
+            };
             if self.trace || self.step_mode == StepMode::Stepping || self.list_mode.is_some() {
-                println!("{}", line);
+                if self.list_mode.is_none() {
+                    println!("{}", self.regions.colorize(instruction_pc, &entry.to_string()));
+                } else {
+                    println!("{}", self.regions.colorize(instruction_pc, &entry.line));
+                }
             }
             // we only push trace lines into history if we're configured for history and we're not in list mode
-            if config::ARGS.history > 0 && self.list_mode.is_none() {
-                if self.history.is_none() {
-                    self.history = Some(VecDeque::new());
-                }
-                if let Some(history) = self.history.as_mut() {
-                    history.push_back(line);
-                    if history.len() > config::ARGS.history {
-                        history.pop_front();
-                    }
+            if self.history_len > 0 && self.list_mode.is_none() {
+                let history = self.history.get_or_insert_with(VecDeque::new);
+                history.push_back(entry);
+                if history.len() > self.history_len {
+                    history.pop_front();
                 }
             }
         }
@@ -761,51 +1820,205 @@ impl Core {
         }
         self.next_linear_step = outcome.inst.ctx.pc + outcome.inst.size;
     }
+    /// Evaluates the instruction at PC without committing the Outcome and shows the registers and
+    /// memory it would change. Returns true if the user wants it executed.
+    fn what_if(&mut self) -> Result<bool, Error> {
+        if self.in_cwai || self.in_sync {
+            println!("The CPU is waiting for an interrupt; there's no instruction to preview.");
+            return Ok(false);
+        }
+        let (instruction_count, clock_cycles) = (self.instruction_count, self.clock_cycles);
+        let outcome = self.exec_next(false);
+        (self.instruction_count, self.clock_cycles) = (instruction_count, clock_cycles);
+        let mut o = match outcome {
+            Ok(o) => o,
+            Err(e) => {
+                println!("{}", e);
+                return Ok(false);
+            }
+        };
+        println!(
+            "What if {:04X}: {} {} ({} cycles)",
+            o.inst.ctx.pc,
+            o.inst.flavor.desc.name,
+            o.inst.operand.as_deref().unwrap_or(""),
+            o.inst.flavor.detail.clk
+        );
+        let changes: Vec<String> = o.inst.ctx.diff(&o.new_ctx).iter().map(|c| c.to_string()).collect();
+        println!("  registers: {}", if changes.is_empty() { "unchanged".to_string() } else { changes.join(" ") });
+        for w in &o.writes {
+            let old: String = (0..w.val.size())
+                .map(|i| w.addr.wrapping_add(i))
+                .map(|a| {
+                    if self.is_io_address(a) {
+                        "??".to_string()
+                    } else {
+                        format!("{:02X}", self.raw_ram[a as usize])
+                    }
+                })
+                .collect();
+            println!("  [{:04X}] {} -> {}", w.addr, old, w.val);
+        }
+        if let Some(meta) = o.meta.as_ref() {
+            println!("  then {:?} (what it does next isn't shown)", meta);
+        }
+        if o.inst.flavor.mode != instructions::AddressingMode::Inherent && self.is_io_address(o.inst.ea) {
+            println!("  (reading {:04X} may already have changed the device's state)", o.inst.ea);
+        }
+        o.writes.clear();
+        self.write_pool = std::mem::take(&mut o.writes);
+        let answer = match self.debug_pending.pop_front() {
+            Some(line) => line,
+            None => self.read_debug_input("Execute it? [Y/n] ")?,
+        };
+        Ok(!matches!(answer.trim().to_lowercase().as_str(), "n" | "no"))
+    }
     pub fn fault(&mut self, addr: u16, e: &Error) {
         println!("{}", e);
         println!("System faulted when executing instruction at {:04X}.", addr);
         self.faulted = true;
     }
     pub fn dump_mem(&mut self, addr: u16, count: u16) {
-        let mut row = 0;
-        const COLS_PER_ROW: u16 = 8;
-        loop {
-            if row * COLS_PER_ROW >= count {
-                break;
-            }
-            print!(blue!("{:04X}:"), addr + row * COLS_PER_ROW);
-            for col in 0..(COLS_PER_ROW * 2) {
-                let i = row * COLS_PER_ROW + col % COLS_PER_ROW;
-                let (index, overflow) = addr.overflowing_add(i);
-                if overflow {
-                    row = count;
-                    break;
-                }
-                let b = self._read_u8(memory::AccessType::System, index, None).unwrap();
-                if col < COLS_PER_ROW {
-                    if i < count {
-                        print!(" {:02X}", b);
-                    } else {
-                        print!("   ");
-                    }
+        let bytes = self.read_mem(addr, count);
+        dump_bytes(addr as usize, &bytes, |a| self.regions.color_at(a as u16));
+    }
+    /// Reads count bytes from addr (fewer if they'd run past the end of the address space)
+    fn read_mem(&mut self, addr: u16, count: u16) -> Vec<u8> {
+        let count = (count as u32).min(0x10000 - addr as u32) as u16;
+        (0..count).map(|i| self._read_u8(memory::AccessType::System, addr + i, None).unwrap()).collect()
+    }
+}
+/// Lets expressions in the debugger use its symbols, the registers and memory
+impl expr::LabelResolver for Core {
+    fn resolve(&self, label: &str) -> Option<u8u16> { self.symbol_by_name(label).map(u8u16::u16) }
+    fn register(&self, name: &str) -> Option<u8u16> {
+        match registers::Name::from_str(name) {
+            registers::Name::Z => None,
+            reg => Some(self.reg.get_register(reg)),
+        }
+    }
+    // reading a device can change its state so I/O space is off limits
+    fn read(&self, addr: u16) -> Option<u8> { (!self.is_io_address(addr)).then(|| self.raw_ram[addr as usize]) }
+}
+/// Displays bytes in rows of hex and ascii, labeling each row with its offset from base.
+/// color returns the ANSI color (if any) in which to show the byte at a given offset.
+pub fn dump_bytes(base: usize, bytes: &[u8], color: impl Fn(usize) -> Option<&'static str>) {
+    const COLS_PER_ROW: usize = 8;
+    for (row, chunk) in bytes.chunks(COLS_PER_ROW).enumerate() {
+        print!(blue!("{:04X}:"), base + row * COLS_PER_ROW);
+        for col in 0..COLS_PER_ROW {
+            match chunk.get(col) {
+                Some(b) => match color(base + row * COLS_PER_ROW + col) {
+                    Some(c) => print!(" \x1b[{}m{:02X}\x1b[0m", c, b),
+                    None => print!(" {:02X}", b),
+                },
+                None => print!("   "),
+            }
+        }
+        print!(" ");
+        for &b in chunk {
+            print!(
+                " {}",
+                if b.is_ascii_alphanumeric() || b.is_ascii_graphic() || b.is_ascii_punctuation() {
+                    b as char
                 } else {
-                    if col % COLS_PER_ROW == 0 {
-                        print!(" ");
-                    }
-                    if i < count {
-                        print!(
-                            " {}",
-                            if b.is_ascii_alphanumeric() || b.is_ascii_graphic() || b.is_ascii_punctuation() {
-                                b as char
-                            } else {
-                                '.'
-                            }
-                        );
-                    }
+                    '.'
                 }
-            }
-            row += 1;
-            println!();
+            );
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_assignments() {
+        use registers::{CCBit, Name};
+        assert_eq!(parse_set("PC=?start"), Some((SetTarget::Register(Name::PC), "?start")));
+        assert_eq!(parse_set("cc.Z=1"), Some((SetTarget::Flag(CCBit::Z), "1")));
+        assert_eq!(parse_set("cc=$50"), Some((SetTarget::Register(Name::CC), "$50")));
+        for bad in ["a", "a=", "q=1", "cc.zz=1", "cc.q=1"] {
+            assert_eq!(parse_set(bad), None, "{}", bad);
         }
+        let mut core = Core::headless().unwrap();
+        core.set_value(SetTarget::Register(Name::A), "$40+1").unwrap();
+        assert_eq!(core.reg.d, 0x4100);
+        assert!(core.set_value(SetTarget::Register(Name::B), "$100").is_err());
+        core.set_value(SetTarget::Flag(CCBit::C), "1").unwrap();
+        assert!(core.reg.cc.is_set(CCBit::C));
+    }
+
+    #[test]
+    fn skip_and_branch() {
+        let mut core = Core::headless().unwrap();
+        // LDA #$41; BNE *+7; LBRA *
+        core.raw_ram[0x2000..0x2007].copy_from_slice(&[0x86, 0x41, 0x26, 0x05, 0x16, 0xff, 0xfd]);
+        core.reg.pc = 0x2000;
+        core.force_branch(true);
+        assert_eq!(core.reg.pc, 0x2000);
+        core.skip_instructions(1);
+        assert_eq!((core.reg.pc, core.reg.a), (0x2002, 0));
+        core.force_branch(true);
+        assert_eq!(core.reg.pc, 0x2009);
+        core.reg.pc = 0x2002;
+        core.force_branch(false);
+        assert_eq!(core.reg.pc, 0x2004);
+        core.force_branch(true);
+        assert_eq!(core.reg.pc, 0x2004);
+        core.skip_instructions(1);
+        assert_eq!(core.reg.pc, 0x2007);
+    }
+
+    #[test]
+    fn nmi() {
+        let mut core = Core::headless().unwrap();
+        // $3000: nop / bra *-1; the handler at $3100 is rti
+        core.raw_ram[0x3000..0x3003].copy_from_slice(&[0x12, 0x20, 0xfd]);
+        core.raw_ram[0x3100] = 0x3b;
+        core.raw_ram[0xbffc..0xbffe].copy_from_slice(&[0x31, 0x00]);
+        (core.reg.pc, core.reg.s) = (0x3000, 0x7000);
+        core.assert_nmi();
+        core.assert_nmi();
+        core.exec_one().unwrap();
+        // taken once, after the NOP, with the entire state stacked and both IRQ and FIRQ masked
+        assert_eq!((core.reg.pc, core.reg.s, core.nmi_pending), (0x3100, 0x7000 - 12, false));
+        assert_eq!(core.reg.cc.reg & 0xd0, 0xd0);
+        assert_eq!(&core.raw_ram[0x6ffe..0x7000], &[0x30, 0x01]);
+        assert!(!core.take_nmi().unwrap());
+    }
+
+    #[test]
+    fn macro_arguments() {
+        assert_eq!(expand_macro("dm $1 $2; c", &["?start", "8"]), vec!["dm ?start 8", "c"]);
+        assert_eq!(expand_macro("ba $*;; g", &["1234", "note"]), vec!["ba 1234 note", "g"]);
+        assert_eq!(expand_macro("dm $1 $2", &[]), vec!["dm"]);
+    }
+
+    #[test]
+    fn hot_patch() {
+        let mut core = Core::headless().unwrap();
+        // $3000: lda #1 / nop
+        core.raw_ram[0x3000..0x3003].copy_from_slice(&[0x86, 0x01, 0x12]);
+        let patch = |core: &mut Core, src: &str| core.hot_patch(0x3000, &[src.to_string()]);
+        // a 1-byte patch would leave half of lda behind
+        let e = patch(&mut core, "nop").unwrap_err();
+        assert!(e.msg.contains("1 bytes but the 1 instruction(s) it overwrites take 2"));
+        assert_eq!(core.raw_ram[0x3000], 0x86);
+        patch(&mut core, "ldb #2").unwrap();
+        patch(&mut core, "lda $1234").unwrap();
+        assert_eq!(core.raw_ram[0x3000..0x3003], [0xb6, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn dump_range() {
+        let mut core = Core::headless().unwrap();
+        core.raw_ram[0..2].copy_from_slice(&[0x12, 0x34]);
+        // dm 0 shows the bytes at 0000; dm at the top of memory stops at FFFF
+        assert_eq!(core.read_mem(0, 16)[..2], [0x12, 0x34]);
+        assert_eq!(core.read_mem(0, 16).len(), 16);
+        assert_eq!(core.read_mem(0xfff8, 16).len(), 8);
     }
 }
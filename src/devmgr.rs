@@ -3,10 +3,167 @@ use crate::sam::*;
 use crate::sound;
 use crate::vdg::*;
 
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU8, Ordering::Relaxed};
 use std::sync::RwLock;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use minifb::{Scale, ScaleMode, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, Scale, ScaleMode, Window, WindowOptions};
+
+const MAX_FRAME_SKIP: u32 = 4;
+/// How long the window's title flashes after the core stops at a breakpoint
+const BADGE_TIME: Duration = Duration::from_secs(5);
+
+const RUNNING: u8 = 0;
+const PAUSED: u8 = 1;
+const AT_BREAKPOINT: u8 = 2;
+
+/// What the window's title says about the core, which keeps it up to date from its own thread
+/// (see title.rs). The title tells instances apart and shows when the debugger has stopped one.
+#[derive(Debug, Default)]
+pub struct TitleStatus {
+    program: Mutex<String>,
+    warp: AtomicBool,
+    khz: AtomicU32,  // the effective clock speed (0 until it's been measured)
+    stopped: AtomicU8, // RUNNING, PAUSED (in the debugger) or AT_BREAKPOINT
+}
+impl TitleStatus {
+    pub fn set_program(&self, name: &str) { *self.program.lock().unwrap() = name.to_string() }
+    pub fn set_warp(&self, on: bool) { self.warp.store(on, Relaxed) }
+    pub fn set_speed(&self, khz: u32) { self.khz.store(khz, Relaxed) }
+    /// Notes that the debugger has stopped the core (at a breakpoint or otherwise)
+    pub fn stop(&self, breakpoint: bool) {
+        if breakpoint {
+            self.stopped.store(AT_BREAKPOINT, Relaxed);
+        } else {
+            let _ = self.stopped.compare_exchange(RUNNING, PAUSED, Relaxed, Relaxed);
+        }
+    }
+    pub fn resume(&self) { self.stopped.store(RUNNING, Relaxed) }
+    /// Returns the title given the frames skipped for each one shown, whether the mouse is
+    /// captured and whether to show the breakpoint badge
+    pub fn text(&self, skip: u32, captured: bool, badge: bool) -> String {
+        let mut title = String::from("Rusty CoCo");
+        let program = self.program.lock().unwrap();
+        if !program.is_empty() {
+            title += &format!(" - {}", program);
+        }
+        match self.stopped.load(Relaxed) {
+            RUNNING => {
+                let (warp, khz) = (self.warp.load(Relaxed), self.khz.load(Relaxed));
+                match (warp, khz) {
+                    (true, 0) => title += " - warp",
+                    (true, _) => title += &format!(" - warp {:.2} MHz", khz as f32 / 1000.0),
+                    (false, 0) => {}
+                    (false, _) => title += &format!(" - {:.2} MHz", khz as f32 / 1000.0),
+                }
+            }
+            PAUSED => title += " - paused in the debugger",
+            _ => {
+                title += " - stopped at a breakpoint";
+                if badge {
+                    title = format!("[!] {} [!]", title);
+                }
+            }
+        }
+        if skip > 0 {
+            title += &format!(" (showing 1 of {} frames)", skip + 1);
+        }
+        if captured {
+            title += " (mouse captured; F7 releases it)";
+        }
+        title
+    }
+}
+
+/// A picture produced by something other than the VDG (e.g., a video cartridge)
+pub trait Screen {
+    /// Returns whether the picture should be shown (in the main window it takes the VDG's place
+    /// while it's active)
+    fn active(&self) -> bool;
+    /// Returns the picture's width and height in pixels
+    fn dimensions(&self) -> (usize, usize);
+    /// Renders the picture into display (resizing it to fit) if it has changed and returns its
+    /// width and height if it did
+    fn render(&mut self, display: &mut Vec<u32>) -> Option<(usize, usize)>;
+}
+
+/// Saves a picture (its pixels, width and height) when the screenshot hotkey is pressed
+pub type SaveScreenshot = Box<dyn FnMut(&[u32], usize, usize)>;
+
+/// A screen along with its latest picture
+struct Picture {
+    screen: Box<dyn Screen>,
+    display: Vec<u32>,
+    size: (usize, usize),
+}
+impl Picture {
+    fn new(screen: Box<dyn Screen>) -> Self { Picture { screen, display: Vec::new(), size: (0, 0) } }
+    /// Renders the picture and shows it in window if it has changed (or if always is set)
+    fn show(&mut self, window: &mut minifb::Window, always: bool) {
+        if let Some(size) = self.screen.render(&mut self.display) {
+            self.size = size;
+        } else if !always || self.display.is_empty() {
+            window.update();
+            return;
+        }
+        window
+            .update_with_buffer(&self.display, self.size.0, self.size.1)
+            .expect("minifb update_with_buffer failed");
+    }
+}
+
+/// Decides when the screen can't be rendered every frame. Frame times are smoothed and when the
+/// host falls behind the refresh rate, VDG renders are skipped (input is still polled every frame)
+/// to keep audio and input responsive. Skipping backs off again once the host keeps up.
+#[derive(Debug, Default)]
+pub struct FramePacer {
+    last: Option<Instant>,
+    avg: f32,       // smoothed frame time in seconds
+    skip: u32,      // the number of frames skipped for every frame rendered
+    no_skip: bool,  // render every frame however far behind the host is
+    countdown: u32, // frames to skip before the next render
+    pub frames: u64,
+    pub skipped: u64,
+    pub render_time: Duration, // total time spent rendering and presenting frames
+}
+impl FramePacer {
+    /// Called at the start of each frame; returns true if this frame should be rendered
+    pub fn start_frame(&mut self) -> bool {
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            let t = (now - last).as_secs_f32();
+            self.avg = if self.avg == 0.0 { t } else { 0.9 * self.avg + 0.1 * t };
+            let period = SCREEN_REFRESH_PERIOD.as_secs_f32();
+            if self.avg > 1.25 * period && self.skip < MAX_FRAME_SKIP && !self.no_skip {
+                self.skip += 1;
+                self.avg = period;
+            } else if self.avg < 1.05 * period && self.skip > 0 && self.frames.is_multiple_of(60) {
+                self.skip -= 1;
+            }
+        }
+        self.last = Some(now);
+        self.frames += 1;
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            self.skipped += 1;
+            return false;
+        }
+        self.countdown = self.skip;
+        true
+    }
+    pub fn skip(&self) -> u32 { self.skip }
+    /// Returns a one line summary of frame pacing
+    pub fn report(&self) -> String {
+        let rendered = (self.frames - self.skipped).max(1);
+        format!(
+            "{} frames, {} skipped; {:.2} ms per rendered frame",
+            self.frames,
+            self.skipped,
+            self.render_time.as_secs_f64() * 1000.0 / rendered as f64
+        )
+    }
+}
 
 // DeviceManager should be instantiated on the main thread and then clones of its
 // member fields can be sent to other threads. DeviceManger methods must only be
@@ -14,12 +171,33 @@ use minifb::{Scale, ScaleMode, Window, WindowOptions};
 pub struct DeviceManager {
     window: minifb::Window,
     display: Vec<u32>,
-    _audio: sound::AudioDevice,
+    _audio: Option<sound::AudioDevice>, // None if the host has no (working) audio output
     ram: Arc<RwLock<Vec<u8>>>,
     sam: Arc<Mutex<Sam>>,
     vdg: Arc<Mutex<Vdg>>,
     pia0: Arc<Mutex<Pia0>>,
     pia1: Arc<Mutex<Pia1>>,
+    // the UI thread only uses these lock-free views of the PIAs and SAM so that the core
+    // thread never has to wait for it
+    input: Arc<PiaInput>,
+    sam_config: Arc<AtomicU16>,
+    pia1_vdg_bits: Arc<AtomicU8>,
+    pacer: FramePacer,
+    // a screen that takes the VDG's place in the main window while it's active
+    main_screen: Option<Picture>,
+    showing_main_screen: bool,
+    // screens shown in windows of their own
+    windows: Vec<(minifb::Window, Picture)>,
+    // saves the main window's picture when F8 is pressed (there's no screenshot hotkey without it)
+    screenshot: Option<SaveScreenshot>,
+    // F7 captures the mouse for the joystick (see allow_mouse_capture)
+    mouse_capture: bool,
+    // what the title says about the core, the title last set and when the core stopped at a breakpoint
+    status: Arc<TitleStatus>,
+    title: String,
+    breakpoint_at: Option<Instant>,
+    // device misuse that has already been logged (see misuse.rs)
+    misuse: crate::error::Reported,
 }
 impl DeviceManager {
     #[allow(clippy::new_without_default)]
@@ -45,54 +223,189 @@ impl DeviceManager {
         window.limit_update_rate(Some(SCREEN_REFRESH_PERIOD));
         // Initialize audio device
         // todo: the AudioDevice should probably live in pia1
-        let mut _audio = sound::AudioDevice::try_new().expect("failed to create audio device");
+        let mut _audio = sound::AudioDevice::try_new()
+            .map_err(|e| warn!("{}; running without sound", e.msg))
+            .ok();
         // Arc<(Mutex<bool>, Condvar)>
         let vdg = Arc::new(Mutex::new(Vdg::with_ram(ram.clone(), vram_offset)));
         // Pia1 needs to communicate directly with the audio output device (which it does via AudioRingBuffer)
-        let pia1 = Arc::new(Mutex::new(Pia1::new(_audio.take_sender())));
+        // without an audio device the DAC's samples go nowhere
+        let sndr = _audio.as_mut().map_or_else(|| std::sync::mpsc::channel().0, |a| a.take_sender());
+        let pia1 = Pia1::new(sndr);
+        let pia1_vdg_bits = pia1.shared_vdg_bits();
+        let pia1 = Arc::new(Mutex::new(pia1));
+        let pia0 = Pia0::new(pia1.clone());
+        let sam = Sam::new();
         DeviceManager {
+            input: pia0.input(),
+            sam_config: sam.shared_config(),
+            pia1_vdg_bits,
             window,
             display: vec![Color::Green.to_rgb(); SCREEN_DIM_X * SCREEN_DIM_Y],
             _audio,
             ram,
-            sam: Arc::new(Mutex::new(Sam::new())),
+            sam: Arc::new(Mutex::new(sam)),
             vdg,
-            pia0: Arc::new(Mutex::new(Pia0::new(pia1.clone()))),
+            pia0: Arc::new(Mutex::new(pia0)),
             pia1,
+            pacer: FramePacer::default(),
+            main_screen: None,
+            showing_main_screen: false,
+            windows: Vec::new(),
+            screenshot: None,
+            mouse_capture: false,
+            status: Default::default(),
+            title: String::new(),
+            breakpoint_at: None,
+            misuse: Default::default(),
         }
     }
+    /// Shows a screen in the main window (in place of the VDG) while it's active
+    pub fn set_main_screen(&mut self, screen: Box<dyn Screen>) { self.main_screen = Some(Picture::new(screen)) }
+    /// Shows a screen in a window of its own
+    pub fn add_screen_window(&mut self, title: &str, screen: Box<dyn Screen>) {
+        let (width, height) = screen.dimensions();
+        let mut window = Window::new(
+            title,
+            width,
+            height,
+            WindowOptions {
+                resize: true,
+                scale_mode: ScaleMode::AspectRatioStretch,
+                scale: Scale::X2,
+                ..WindowOptions::default()
+            },
+        )
+        .expect("Failed to open window");
+        window.limit_update_rate(Some(SCREEN_REFRESH_PERIOD));
+        self.windows.push((window, Picture::new(screen)));
+    }
 
     pub fn get_vdg(&self) -> Arc<Mutex<Vdg>> { self.vdg.clone() }
     pub fn get_pia0(&self) -> Arc<Mutex<Pia0>> { self.pia0.clone() }
     pub fn get_pia1(&self) -> Arc<Mutex<Pia1>> { self.pia1.clone() }
     pub fn get_ram(&self) -> Arc<RwLock<Vec<u8>>> { self.ram.clone() }
     pub fn get_sam(&self) -> Arc<Mutex<Sam>> { self.sam.clone() }
+    pub fn get_title_status(&self) -> Arc<TitleStatus> { self.status.clone() }
     pub fn is_running(&self) -> bool { self.window.is_open() }
+    /// Takes symbols from the characters the host's keyboard layout types (see --typed-symbols)
+    pub fn type_symbols(&mut self) { self.input.type_symbols(&mut self.window) }
+    /// Sets what keys held down on the host do (see --key-repeat)
+    pub fn key_repeat(&self, mode: RepeatMode, delay_ms: u64, rate: u64) {
+        self.input.set_key_repeat(mode, delay_ms, rate)
+    }
+    pub fn frame_pacer(&self) -> &FramePacer { &self.pacer }
+    /// Makes F8 save the main window's picture
+    pub fn on_screenshot(&mut self, save: SaveScreenshot) { self.screenshot = Some(save) }
+    /// Allows (or stops) skipping renders of the screen when the host can't keep up
+    pub fn frame_skip(&mut self, allow: bool) { self.pacer.no_skip = !allow }
+    /// Makes F7 capture the mouse for the joystick (and release it), with the joystick moving by
+    /// sensitivity times its range for a window's width of the mouse's motion
+    pub fn allow_mouse_capture(&mut self, sensitivity: f32) {
+        self.input.set_mouse_sensitivity(sensitivity);
+        self.mouse_capture = true;
+    }
+    /// Captures the mouse (hiding the pointer) or releases it
+    fn capture_mouse(&mut self, on: bool) {
+        self.input.set_capture(on);
+        self.window.set_cursor_visibility(!on);
+        self.windows.iter_mut().for_each(|(window, _)| window.set_cursor_visibility(!on));
+    }
+    /// Runs one tick of the UI: the windows are brought up to date (rendering the screens when
+    /// it's time to) and then input is polled. Neither waits for the core thread, so the windows
+    /// keep responding and F8 keeps working while the core is stopped in the debugger (or holds
+    /// the VDG's lock); the core's hotkeys are picked up once it runs again.
     pub fn update(&mut self) {
+        self.render_tick();
+        self.input_tick();
+    }
+    /// Polls keyboard and mouse input for pia0 (from whichever window has the focus) and handles
+    /// the UI's own hotkeys. The windows' events have to have been processed (by render_tick)
+    /// since the last poll and only once, otherwise minifb loses key presses.
+    fn input_tick(&mut self) {
+        self.windows.retain(|(window, _)| window.is_open());
+        let focused = self.window.is_active() || self.windows.iter_mut().any(|(window, _)| window.is_active());
+        let window = match self.windows.iter_mut().find_map(|(window, _)| window.is_active().then_some(window)) {
+            Some(window) => window,
+            None => &mut self.window,
+        };
+        self.input.update(window);
+        if window.is_key_pressed(Key::F8, KeyRepeat::No) {
+            if let Some(save) = self.screenshot.as_mut() {
+                match self.main_screen.as_ref().filter(|_| self.showing_main_screen) {
+                    Some(picture) => save(&picture.display, picture.size.0, picture.size.1),
+                    None => save(&self.display, SCREEN_DIM_X, SCREEN_DIM_Y),
+                }
+            }
+        }
+        let capture = self.mouse_capture && window.is_key_pressed(Key::F7, KeyRepeat::No);
+        if capture {
+            self.capture_mouse(!self.input.capturing());
+        } else if !focused && self.input.capturing() {
+            // the mouse is let go as soon as the focus goes to another application
+            self.capture_mouse(false);
+        }
+        self.update_title();
+    }
+    /// Sets the window's title if what it says has changed (the breakpoint badge flashes twice a
+    /// second for a while after the core stops at a breakpoint)
+    fn update_title(&mut self) {
+        let at_breakpoint = self.status.stopped.load(Relaxed) == AT_BREAKPOINT;
+        let since = match (at_breakpoint, self.breakpoint_at) {
+            (false, _) => None,
+            (true, None) => Some(Instant::now()),
+            (true, at) => at,
+        };
+        self.breakpoint_at = since;
+        let elapsed = since.map_or(BADGE_TIME, |t| t.elapsed());
+        let badge = elapsed < BADGE_TIME && elapsed.as_millis() / 250 % 2 == 0;
+        let title = self.status.text(self.pacer.skip(), self.input.capturing(), badge);
+        if title != self.title {
+            self.window.set_title(&title);
+            self.title = title;
+        }
+    }
+    /// Processes the windows' events and renders the screens that are due (every window gets
+    /// exactly one update per tick)
+    fn render_tick(&mut self) {
         let mut redraw = false;
-        {
-            // pia0 handles keyboard input
-            let mut pia0 = self.pia0.lock().unwrap();
-            pia0.update(&self.window);
-        }
-        let mode;
-        let css;
-        let vram_offset;
-        {
-            // use SAM and PIA1 to determine current VDG mode
-            let sam = self.sam.lock().unwrap();
-            let pia1 = self.pia1.lock().unwrap();
-            let pia_bits = pia1.get_vdg_bits();
-            mode = VdgMode::try_from_pia_and_sam(pia_bits, sam.get_vdg_bits());
-            css = pia_bits & 1 == 1;
-            // get the starting address of VRAM from the SAM
-            vram_offset = sam.get_vram_start() as usize;
-        }
-        // only try rendering the screen if we have a valid VdgMode
-        if let Some(mode) = mode {
-            let mut vdg = self.vdg.lock().unwrap();
+        if !self.pacer.start_frame() {
+            // keep the windows responsive without spending time on the screens
+            self.window.update();
+            self.windows.iter_mut().for_each(|(window, _)| window.update());
+            return;
+        }
+        let render_start = Instant::now();
+        for (window, picture) in self.windows.iter_mut() {
+            picture.show(window, false);
+        }
+        if let Some(picture) = self.main_screen.as_mut().filter(|p| p.screen.active()) {
+            // the picture has to be shown when it first takes the VDG's place even if it hasn't changed
+            picture.show(&mut self.window, !self.showing_main_screen);
+            self.showing_main_screen = true;
+            self.pacer.render_time += render_start.elapsed();
+            return;
+        }
+        if self.showing_main_screen {
+            // the VDG's picture has to be put back
+            self.showing_main_screen = false;
+            self.vdg.lock().unwrap().set_dirty();
+        }
+        // use SAM and PIA1 to determine current VDG mode
+        let sam_config = self.sam_config.load(Relaxed);
+        let pia_bits = self.pia1_vdg_bits.load(Relaxed);
+        let mode = VdgMode::try_from_pia_and_sam(pia_bits, Sam::vdg_bits(sam_config));
+        let css = pia_bits & 1 == 1;
+        // get the starting address of VRAM from the SAM
+        let vram_offset = Sam::vram_start(sam_config) as usize;
+        // only try rendering the screen if we have a valid VdgMode (and the core isn't busy
+        // with the VDG; it's still dirty next time if this frame is missed)
+        if let (Some(mode), Ok(mut vdg)) = (mode, self.vdg.try_lock()) {
             vdg.set_mode(mode);
-            vdg.set_vram_offset(vram_offset);
+            // a program can point the SAM past the end of RAM; the picture stays where it was
+            if let Err(e) = vdg.set_vram_offset(vram_offset) {
+                self.misuse.warn(&e);
+            }
             // convert contents of VRAM to pixels for display
             redraw = vdg.render(&mut self.display, css);
         }
@@ -103,5 +416,6 @@ impl DeviceManager {
         } else {
             self.window.update();
         }
+        self.pacer.render_time += render_start.elapsed();
     }
 }
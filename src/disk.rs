@@ -0,0 +1,366 @@
+//! Disk image support.
+//!
+//! A DiskImage wraps a floppy image file (JVC/.DSK style: an optional short header followed by
+//! 256-byte sectors). Writes made by the emulated machine are never applied directly to the file.
+//! Instead they are held in an in-memory journal until the user chooses to flush them to the
+//! image or discard them. The first flush of a session copies the original file to "<name>.bak".
+//! Sectors preloaded from the config file (disk_sectors) sit in an overlay of their own between
+//! the journal and the image; they're test fixtures, so they're never flushed to the file.
+//!
+//! A host directory can also be mounted in place of an image file. It appears to the emulated
+//! machine as a Disk BASIC diskette (see rsdos.rs) and flushing writes its files back to the directory.
+use super::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+pub const SECTOR_SIZE: usize = 256;
+pub const SECTORS_PER_TRACK: usize = 18;
+pub const MAX_DRIVES: usize = 4;
+
+/// What to do with journaled (unflushed) disk writes when the emulator exits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExitPolicy {
+    /// ask the user for each drive that has changes
+    Ask,
+    /// write all changes back to the image files
+    Flush,
+    /// throw away all changes
+    Discard,
+}
+
+pub struct DiskImage {
+    pub path: PathBuf,
+    pub write_protect: bool,
+    pub host_dir: bool,                    // true if path is a host directory presented as a Disk BASIC diskette
+    header: Vec<u8>,                       // JVC header bytes (if any); preserved on flush
+    data: Vec<u8>,                         // sector data as originally read from the image file
+    journal: BTreeMap<usize, Vec<u8>>,     // logical sector number -> pending contents
+    fixtures: BTreeMap<usize, Vec<u8>>,    // logical sector number -> contents preloaded from the config file
+    host_names: HashMap<String, String>,   // a host directory's file names by name on the diskette
+    backed_up: bool,                       // true once the .bak file has been written this session
+}
+impl DiskImage {
+    /// Opens the image at path. If the file itself is read-only then the image is
+    /// write protected regardless of the value of write_protect.
+    pub fn open(path: &Path, write_protect: bool) -> Result<Self, Error> {
+        let mut data = fs::read(path)?;
+        let read_only = fs::metadata(path)?.permissions().readonly();
+        if read_only && !write_protect {
+            warn!("disk image {} is read-only; mounting write protected", path.display());
+        }
+        // JVC images may carry a short header; its length is whatever doesn't fit in whole sectors
+        let header: Vec<u8> = data.drain(..data.len() % SECTOR_SIZE).collect();
+        Ok(DiskImage {
+            path: path.to_path_buf(),
+            write_protect: write_protect || read_only,
+            host_dir: false,
+            header,
+            data,
+            journal: BTreeMap::new(),
+            fixtures: BTreeMap::new(),
+            host_names: HashMap::new(),
+            backed_up: false,
+        })
+    }
+    /// Presents the files in the host directory dir as a Disk BASIC diskette
+    pub fn open_dir(dir: &Path, write_protect: bool) -> Result<Self, Error> {
+        let (data, host_names) = rsdos::image_from_dir(dir)?;
+        Ok(DiskImage {
+            path: dir.to_path_buf(),
+            write_protect,
+            host_dir: true,
+            header: Vec::new(),
+            data,
+            journal: BTreeMap::new(),
+            fixtures: BTreeMap::new(),
+            host_names,
+            backed_up: false,
+        })
+    }
+    pub fn sector_count(&self) -> usize { self.data.len() / SECTOR_SIZE }
+    /// Returns the current contents of the given logical sector (including unflushed writes)
+    pub fn read_sector(&self, lsn: usize) -> Result<&[u8], Error> {
+        if lsn >= self.sector_count() {
+            return Err(err!(ErrorKind::IO, None, "sector {} out of range on {}", lsn, self.path.display()));
+        }
+        Ok(match self.journal.get(&lsn) {
+            Some(s) => s.as_slice(),
+            None => self.unchanged_sector(lsn),
+        })
+    }
+    /// Returns the contents of the given logical sector without unflushed writes (the fixture
+    /// if there is one, otherwise the image's)
+    fn unchanged_sector(&self, lsn: usize) -> &[u8] {
+        match self.fixtures.get(&lsn) {
+            Some(s) => s.as_slice(),
+            None => &self.data[lsn * SECTOR_SIZE..(lsn + 1) * SECTOR_SIZE],
+        }
+    }
+    /// Puts a fixture in the given logical sector. Fixtures aren't journaled writes: they don't
+    /// make the image dirty and they're never flushed to the file. Short fixtures are zero filled.
+    pub fn load_fixture(&mut self, lsn: usize, bytes: &[u8]) -> Result<(), Error> {
+        if lsn >= self.sector_count() {
+            return Err(err!(ErrorKind::IO, None, "sector {} out of range on {}", lsn, self.path.display()));
+        }
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        let n = bytes.len().min(SECTOR_SIZE);
+        sector[..n].copy_from_slice(&bytes[..n]);
+        self.journal.remove(&lsn);
+        self.fixtures.insert(lsn, sector);
+        Ok(())
+    }
+    /// Records a write to the given logical sector in the journal.
+    /// Fails if the image is write protected.
+    pub fn write_sector(&mut self, lsn: usize, bytes: &[u8]) -> Result<(), Error> {
+        if self.write_protect {
+            return Err(err!(ErrorKind::IO, None, "{} is write protected", self.path.display()));
+        }
+        if lsn >= self.sector_count() {
+            return Err(err!(ErrorKind::IO, None, "sector {} out of range on {}", lsn, self.path.display()));
+        }
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        let n = bytes.len().min(SECTOR_SIZE);
+        sector[..n].copy_from_slice(&bytes[..n]);
+        if sector[..] == *self.unchanged_sector(lsn) {
+            // writing back the original contents makes the sector clean again
+            self.journal.remove(&lsn);
+        } else {
+            self.journal.insert(lsn, sector);
+        }
+        Ok(())
+    }
+    /// true if there are journaled writes that haven't been flushed to the image file
+    pub fn is_dirty(&self) -> bool { !self.journal.is_empty() }
+    /// the logical sector numbers of all sectors with unflushed changes
+    pub fn dirty_sectors(&self) -> Vec<usize> { self.journal.keys().copied().collect() }
+    /// Writes all journaled changes to the image file.
+    /// The original file is first copied to <path>.bak (once per session).
+    /// For a host directory, the files on the diskette are written back to the directory instead.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if !self.is_dirty() {
+            return Ok(());
+        }
+        if self.host_dir {
+            for (lsn, sector) in std::mem::take(&mut self.journal) {
+                // a write replaces a fixture for good
+                self.fixtures.remove(&lsn);
+                self.data[lsn * SECTOR_SIZE..(lsn + 1) * SECTOR_SIZE].copy_from_slice(&sector);
+            }
+            let count = rsdos::write_to_dir(&self.data, &self.path, &self.host_names)?;
+            info!("wrote {} changed file(s) to {}", count, self.path.display());
+            return Ok(());
+        }
+        if !self.backed_up {
+            let mut bak = self.path.clone().into_os_string();
+            bak.push(".bak");
+            fs::copy(&self.path, &bak)?;
+            self.backed_up = true;
+        }
+        for (lsn, sector) in std::mem::take(&mut self.journal) {
+            self.fixtures.remove(&lsn);
+            self.data[lsn * SECTOR_SIZE..(lsn + 1) * SECTOR_SIZE].copy_from_slice(&sector);
+        }
+        let mut out = self.header.clone();
+        out.extend_from_slice(&self.data);
+        fs::write(&self.path, out)?;
+        info!("flushed changes to {}", self.path.display());
+        Ok(())
+    }
+    /// Throws away all journaled changes
+    pub fn discard(&mut self) { self.journal.clear() }
+}
+
+impl fmt::Display for DiskImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{} ({} sectors{}{})",
+            self.path.display(),
+            if self.host_dir { "/" } else { "" },
+            self.sector_count(),
+            if self.write_protect { ", write protected" } else { "" },
+            if self.is_dirty() { format!(", {} changed", self.journal.len()) } else { String::new() }
+        )
+    }
+}
+
+/// The set of virtual floppy drives
+#[derive(Default)]
+pub struct Drives {
+    pub drive: [Option<DiskImage>; MAX_DRIVES],
+}
+impl Drives {
+    /// Mounts the images given on the command line (see assign)
+    pub fn from_args() -> Result<Self, Error> {
+        let mut drives = Drives::default();
+        for (i, path) in Self::assign()? {
+            drives.mount(i, path, config::ARGS.disk_wp.contains(&i))?;
+        }
+        Ok(drives)
+    }
+    /// Works out which drive each disk on the command line goes in: --disk0 and --disk1 in
+    /// drives 0 and 1 (or the --disk images in drives 0, 1, ...), then the --disk-dir directories
+    /// in the free drives in order
+    pub fn assign() -> Result<Vec<(usize, &'static Path)>, Error> {
+        let args = &config::ARGS;
+        let fixed = [(0, args.disk0.as_deref()), (1, args.disk1.as_deref())];
+        let mut drives: Vec<(usize, &Path)> = fixed.into_iter().filter_map(|(i, p)| Some((i, p?))).collect();
+        let mut free = (0..MAX_DRIVES).filter(|i| drives.iter().all(|(d, _)| d != i)).collect::<Vec<_>>().into_iter();
+        for path in args.disk.iter().chain(args.disk_dir.iter()) {
+            let i = free.next().ok_or_else(|| general_err!("at most {} disk images may be mounted", MAX_DRIVES))?;
+            drives.push((i, path));
+        }
+        drives.sort();
+        Ok(drives)
+    }
+    /// Mounts an image file or (if path is a directory) a host directory in the given drive
+    pub fn mount(&mut self, drive: usize, path: &Path, write_protect: bool) -> Result<(), Error> {
+        if drive >= MAX_DRIVES {
+            return Err(general_err!("invalid drive number {}", drive));
+        }
+        let image = if path.is_dir() {
+            DiskImage::open_dir(path, write_protect)?
+        } else {
+            DiskImage::open(path, write_protect)?
+        };
+        info!("drive {}: {}", drive, image);
+        self.drive[drive] = Some(image);
+        Ok(())
+    }
+    fn image(&self, drive: usize) -> Result<&DiskImage, Error> {
+        self.drive
+            .get(drive)
+            .and_then(|d| d.as_ref())
+            .ok_or_else(|| general_err!("no disk in drive {}", drive))
+    }
+    /// Converts a DSKI$/DSKO$ style track (0-based) and sector (1-based) to a logical sector number
+    fn lsn(track: usize, sector: usize) -> Result<usize, Error> {
+        if sector == 0 || sector > SECTORS_PER_TRACK {
+            return Err(general_err!("invalid sector {} (must be 1-{})", sector, SECTORS_PER_TRACK));
+        }
+        Ok(track * SECTORS_PER_TRACK + sector - 1)
+    }
+    /// Reads the raw contents of a sector (as DSKI$ would)
+    pub fn read_sector(&self, drive: usize, track: usize, sector: usize) -> Result<&[u8], Error> {
+        self.image(drive)?.read_sector(Self::lsn(track, sector)?)
+    }
+    /// Writes raw bytes to a sector (as DSKO$ would). Short writes are zero filled.
+    pub fn write_sector(&mut self, drive: usize, track: usize, sector: usize, bytes: &[u8]) -> Result<(), Error> {
+        let lsn = Self::lsn(track, sector)?;
+        self.image(drive)?;
+        self.drive[drive].as_mut().unwrap().write_sector(lsn, bytes)
+    }
+    /// Pre-populates sectors with the contents of the files listed in the config file.
+    /// Each file goes in consecutive sectors starting at the given track and sector, as fixtures
+    /// (see DiskImage::load_fixture) rather than writes.
+    pub fn load_fixtures(&mut self, fixtures: &[config::SectorSpec]) -> Result<(), Error> {
+        for f in fixtures {
+            let data = std::fs::read(&f.path)?;
+            info!("loading {} into drive {} at track {} sector {}", f.path.display(), f.drive, f.track, f.sector);
+            let first = Self::lsn(f.track, f.sector)?;
+            self.image(f.drive)?;
+            let image = self.drive[f.drive].as_mut().unwrap();
+            for (i, chunk) in data.chunks(SECTOR_SIZE).enumerate() {
+                image.load_fixture(first + i, chunk)?;
+            }
+        }
+        Ok(())
+    }
+    /// Compares sector contents with the files listed in the config file.
+    /// Returns an error describing the first mismatch in each failing expectation.
+    pub fn check_expectations(&self, expected: &[config::SectorSpec]) -> Result<(), Error> {
+        let mut failures = 0;
+        for e in expected {
+            let data = std::fs::read(&e.path)?;
+            let first = Self::lsn(e.track, e.sector)?;
+            print!("\tdrive {} track {} sector {} = {} --> ", e.drive, e.track, e.sector, e.path.display());
+            let image = self.image(e.drive)?;
+            let mut mismatch = None;
+            for (i, b) in data.iter().enumerate() {
+                let actual = image.read_sector(first + i / SECTOR_SIZE)?[i % SECTOR_SIZE];
+                if actual != *b {
+                    mismatch = Some((i, actual, *b));
+                    break;
+                }
+            }
+            match mismatch {
+                None => println!(green!("PASS")),
+                Some((i, actual, expected)) => {
+                    failures += 1;
+                    println!(red!("FAIL at offset {}: {:02X} != {:02X}"), i, actual, expected)
+                }
+            }
+        }
+        if failures == 0 {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::Test, None, format!("Failed {failures} disk check(s)").as_str()))
+        }
+    }
+    /// Resolves any unflushed changes according to policy. Called when the emulator exits.
+    pub fn close(&mut self, policy: ExitPolicy) -> Result<(), Error> {
+        for (i, image) in self.drive.iter_mut().enumerate() {
+            let image = match image.as_mut() {
+                Some(d) if d.is_dirty() => d,
+                _ => continue,
+            };
+            let flush = match policy {
+                ExitPolicy::Flush => true,
+                ExitPolicy::Discard => false,
+                ExitPolicy::Ask => {
+                    println!(
+                        "Drive {} ({}) has {} changed sector(s). Flush to image? (y/n)\r",
+                        i,
+                        image.path.display(),
+                        image.dirty_sectors().len()
+                    );
+                    matches!(term::get_keyboard_input(true, true), Some(b'y') | Some(b'Y'))
+                }
+            };
+            if flush {
+                image.flush()?;
+            } else {
+                info!("discarded changes to {}", image.path.display());
+                image.discard();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_flush_and_backup() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("coco_disk_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("test.dsk");
+        fs::write(&path, vec![0xe5u8; SECTOR_SIZE * 4])?;
+        let mut image = DiskImage::open(&path, false)?;
+        image.write_sector(2, &[1, 2, 3])?;
+        assert_eq!(image.read_sector(2)?[..3], [1, 2, 3]);
+        assert_eq!(image.dirty_sectors(), vec![2]);
+        // nothing touches the file until the journal is flushed
+        assert_eq!(fs::read(&path)?[SECTOR_SIZE * 2], 0xe5);
+        image.flush()?;
+        assert!(!image.is_dirty());
+        assert_eq!(fs::read(&path)?[SECTOR_SIZE * 2], 1);
+        assert_eq!(fs::read(dir.join("test.dsk.bak"))?[SECTOR_SIZE * 2], 0xe5);
+        // fixtures read like writes but aren't flushed, and a write on top of one replaces it
+        image.load_fixture(1, &[7])?;
+        image.load_fixture(3, &[8])?;
+        assert_eq!((image.read_sector(1)?[0], image.is_dirty()), (7, false));
+        image.write_sector(3, &[9])?;
+        image.flush()?;
+        assert_eq!(fs::read(&path)?[SECTOR_SIZE..SECTOR_SIZE + 1], [0xe5]);
+        assert_eq!((fs::read(&path)?[SECTOR_SIZE * 3], image.read_sector(3)?[0]), (9, 9));
+        assert_eq!(image.read_sector(1)?[0], 7);
+        image.write_protect = true;
+        assert!(image.write_sector(0, &[0]).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,648 @@
+//! Floppy disk controller (the FD-502's WD1793).
+//!
+//! Disk Extended Color BASIC drives a WD1793 through these registers (they're there with --fdc
+//! or whenever a disk is mounted):
+//! ```text
+//! FF40  DSKREG (write only, mirrored at FF41-FF47)
+//!         bits 0-2  select drive 0, 1 or 2 (bit 6 selects drive 3)
+//!         bit 3     motor on
+//!         bit 5     double density; also lets the WD1793's INTRQ through as an NMI
+//!         bit 7     halt the CPU whenever the WD1793 has no data ready (DRQ low)
+//! FF48  status (read) / command (write)
+//! FF49  track
+//! FF4A  sector
+//! FF4B  data
+//! ```
+//! FF4C-FF4F mirror FF48-FF4B. To read or write a sector DSKCON gives the command, enables the
+//! halt and then moves bytes through the data register as fast as it can: the halt holds the
+//! CPU until each byte is ready and the NMI at the end of the command breaks it out of the loop.
+//! Here the data is ready as soon as the CPU asks for it, so the CPU is only ever halted between
+//! the last byte and INTRQ, and that time is skipped. As on the real thing the halt takes effect
+//! an instruction after DRQ falls, which is what lets the loop store the last byte.
+//!
+//! Disks are single sided with 18 sectors of 256 bytes a track (JVC .dsk images, see disk.rs)
+//! and have as many tracks as the image holds (35 or 40, usually). Write Track (which DSKINI
+//! uses to format a disk) picks the sectors out of the track the CPU writes and Read Track
+//! builds a track the same way. Seeks take as long as the step rate says; the rest is quick.
+use super::*;
+use crate::core::CPU_HZ;
+use cart::Cartridge;
+use disk::{Drives, MAX_DRIVES, SECTORS_PER_TRACK, SECTOR_SIZE};
+use std::{cell::RefCell, rc::Rc};
+
+/// bytes in a double density track (250 kbit/s at 300 rpm)
+const TRACK_BYTES: usize = 6250;
+/// cycles from the last byte of a sector to INTRQ (while its CRC goes by)
+const CRC_CYCLES: u64 = 64;
+/// cycles spent looking for a sector that isn't there (five revolutions)
+const NOT_FOUND_CYCLES: u64 = CPU_HZ;
+/// cycles for a command to get going
+const COMMAND_CYCLES: u64 = 32;
+/// the step rates (in ms) selected by the low bits of a Type I command
+const STEP_MS: [u64; 4] = [6, 12, 20, 30];
+
+// DSKREG bits
+const DRIVE_3: u8 = 0x40;
+const NMI_ENABLE: u8 = 0x20;
+const HALT_ENABLE: u8 = 0x80;
+
+// status bits
+const BUSY: u8 = 0x01;
+const DRQ: u8 = 0x02;
+const TRACK_0: u8 = 0x04; // Type I
+const NOT_FOUND: u8 = 0x10; // record not found (a seek error after a Type I command)
+const WRITE_PROTECT: u8 = 0x40;
+const NOT_READY: u8 = 0x80;
+
+/// What the data register is moving
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    None,
+    /// the CPU is reading the buffer (a sector, an address or a track)
+    Read { multiple: bool },
+    WriteSector { multiple: bool },
+    WriteTrack,
+}
+
+pub struct DiskController {
+    dskreg: u8,
+    status: u8,     // the status of the last command (the dynamic bits are added when it's read)
+    type_one: bool, // the last command was a Type I (whose status shows where the head is)
+    busy: bool,
+    track: u8,
+    sector: u8,
+    data: u8,
+    cyl: [u8; MAX_DRIVES], // where each drive's head is
+    step_in: bool,         // the direction of the last step
+    transfer: Transfer,
+    buffer: Vec<u8>,
+    pos: usize,
+    intrq_at: Option<u64>, // when the command in progress ends
+    halting: bool,         // an instruction has run since DRQ fell (so the halt has taken effect)
+    nmi: bool,             // INTRQ has started an NMI that hasn't been taken
+}
+impl DiskController {
+    fn new() -> Self {
+        DiskController {
+            dskreg: 0,
+            status: 0,
+            type_one: true,
+            busy: false,
+            track: 0,
+            sector: 1,
+            data: 0,
+            cyl: [0; MAX_DRIVES],
+            step_in: true,
+            transfer: Transfer::None,
+            buffer: Vec::new(),
+            pos: 0,
+            intrq_at: None,
+            halting: false,
+            nmi: false,
+        }
+    }
+    pub fn owns_address(&self, addr: u16) -> bool { (0xff40..0xff50).contains(&addr) }
+    /// Stops whatever the controller is doing (as the reset line does)
+    pub fn reset(&mut self) {
+        let cyl = self.cyl;
+        *self = Self::new();
+        self.cyl = cyl;
+    }
+    /// Returns the controller's registers and where each drive's head is for a snapshot (a
+    /// command in progress isn't saved)
+    pub fn save_state(&self) -> Vec<u8> {
+        let flags = self.type_one as u8 | (self.step_in as u8) << 1 | (self.nmi as u8) << 2;
+        let mut state = vec![self.dskreg, self.status, self.track, self.sector, self.data, flags];
+        state.extend(self.cyl);
+        state
+    }
+    /// Puts the controller in a state saved by save_state, with no command in progress
+    pub fn load_state(&mut self, state: &[u8]) {
+        *self = Self::new();
+        [self.dskreg, self.status, self.track, self.sector, self.data] = state[..5].try_into().unwrap();
+        (self.type_one, self.step_in, self.nmi) = (state[5] & 1 != 0, state[5] & 2 != 0, state[5] & 4 != 0);
+        self.cyl.copy_from_slice(&state[6..6 + MAX_DRIVES]);
+    }
+    /// the selected drive (if any)
+    fn drive(&self) -> Option<usize> {
+        [0x01, 0x02, 0x04, DRIVE_3].iter().position(|bit| self.dskreg & bit != 0)
+    }
+    pub fn read(&mut self, addr: u16, disks: &Drives, cycle: u64) -> u8 {
+        self.update(cycle);
+        match addr & 0x0f {
+            0..=7 => 0xff,
+            n => match n & 3 {
+                0 => self.status(disks),
+                1 => self.track,
+                2 => self.sector,
+                _ => {
+                    if let Transfer::Read { multiple } = self.transfer {
+                        self.data = self.buffer[self.pos];
+                        self.pos += 1;
+                        if self.pos == self.buffer.len() {
+                            self.transfer = Transfer::None;
+                            if multiple {
+                                self.next_sector(disks, cycle);
+                            } else {
+                                self.end(cycle + CRC_CYCLES);
+                            }
+                        }
+                    }
+                    self.data
+                }
+            },
+        }
+    }
+    pub fn write(&mut self, addr: u16, data: u8, disks: &mut Drives, cycle: u64) {
+        self.update(cycle);
+        match addr & 0x0f {
+            0..=7 => self.dskreg = data,
+            n => match n & 3 {
+                0 => self.command(data, disks, cycle),
+                1 => self.track = data,
+                2 => self.sector = data,
+                _ => {
+                    self.data = data;
+                    if matches!(self.transfer, Transfer::WriteSector { .. } | Transfer::WriteTrack) {
+                        self.buffer[self.pos] = data;
+                        self.pos += 1;
+                        if self.pos == self.buffer.len() {
+                            self.written(disks, cycle);
+                        }
+                    }
+                }
+            },
+        }
+    }
+    /// Returns the cycle the CPU is halted until, if the controller is halting it
+    pub fn halted_until(&self) -> Option<u64> {
+        self.intrq_at.filter(|_| self.halting && self.dskreg & HALT_ENABLE != 0 && self.transfer == Transfer::None)
+    }
+    /// Ends the command in progress if it's time; returns true if that starts an NMI
+    pub fn tick(&mut self, cycle: u64) -> bool {
+        self.update(cycle);
+        self.halting = true;
+        std::mem::take(&mut self.nmi)
+    }
+    fn update(&mut self, cycle: u64) {
+        if self.intrq_at.is_some_and(|at| cycle >= at) {
+            self.intrq_at = None;
+            self.busy = false;
+            self.interrupt();
+        }
+    }
+    /// Raises INTRQ, which (if DSKREG lets it through) ends the halt and starts an NMI
+    fn interrupt(&mut self) {
+        if self.dskreg & NMI_ENABLE != 0 {
+            self.dskreg &= !HALT_ENABLE;
+            self.nmi = true;
+        }
+    }
+    /// Ends the command in progress at the given cycle
+    fn end(&mut self, at: u64) {
+        self.intrq_at = Some(at);
+        self.halting = false;
+    }
+    fn fail(&mut self, status: u8, cycle: u64) {
+        self.status = status;
+        self.transfer = Transfer::None;
+        self.end(cycle + if status == NOT_FOUND { NOT_FOUND_CYCLES } else { COMMAND_CYCLES });
+    }
+    fn status(&self, disks: &Drives) -> u8 {
+        let mut status = self.status;
+        if self.type_one {
+            match self.drive().and_then(|d| disks.drive[d].as_ref().map(|image| (d, image))) {
+                Some((d, image)) => {
+                    if image.write_protect {
+                        status |= WRITE_PROTECT;
+                    }
+                    if self.cyl[d] == 0 {
+                        status |= TRACK_0;
+                    }
+                }
+                None => status |= NOT_READY,
+            }
+        }
+        if self.transfer != Transfer::None {
+            status |= DRQ;
+        }
+        if self.busy {
+            status |= BUSY;
+        }
+        status
+    }
+    fn command(&mut self, cmd: u8, disks: &mut Drives, cycle: u64) {
+        if cmd & 0xf0 == 0xd0 {
+            // Force Interrupt (INTRQ right away if bit 3 is set)
+            self.busy = false;
+            self.transfer = Transfer::None;
+            self.intrq_at = None;
+            self.type_one = true;
+            self.status = 0;
+            if cmd & 0x08 != 0 {
+                self.interrupt();
+            }
+            return;
+        }
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+        self.status = 0;
+        self.type_one = cmd & 0x80 == 0;
+        if self.type_one {
+            return self.type_one_command(cmd, disks, cycle);
+        }
+        // the rest need a disk
+        let Some(d) = self.drive().filter(|d| disks.drive[*d].is_some()) else {
+            return self.fail(NOT_READY, cycle);
+        };
+        let image = disks.drive[d].as_ref().unwrap();
+        let writing = matches!(cmd >> 4, 0xa | 0xb | 0xf);
+        if writing && image.write_protect {
+            return self.fail(WRITE_PROTECT, cycle);
+        }
+        let cyl = self.cyl[d];
+        let on_disk = (cyl as usize) < image.sector_count() / SECTORS_PER_TRACK;
+        let multiple = cmd & 0x10 != 0;
+        match cmd >> 4 {
+            0x8 | 0x9 => {
+                self.transfer = Transfer::Read { multiple };
+                self.load_sector(disks, cycle);
+            }
+            0xa | 0xb => {
+                self.transfer = Transfer::WriteSector { multiple };
+                if self.find_sector(disks).is_some() {
+                    self.buffer = vec![0; SECTOR_SIZE];
+                    self.pos = 0;
+                } else {
+                    self.fail(NOT_FOUND, cycle);
+                }
+            }
+            0xc if on_disk => {
+                // Read Address gives the first ID it finds (and copies its track to the sector register)
+                let mut id = vec![cyl, 0, 1, 1];
+                id.extend(crc(&[&[0xa1, 0xa1, 0xa1, 0xfe], &id[..]]).to_be_bytes());
+                self.sector = cyl;
+                self.buffer = id;
+                self.pos = 0;
+                self.transfer = Transfer::Read { multiple: false };
+            }
+            0xe => {
+                let sectors: Vec<&[u8]> = (1..=SECTORS_PER_TRACK)
+                    .map_while(|s| disks.read_sector(d, cyl as usize, s).ok())
+                    .collect();
+                self.buffer = build_track(cyl, &sectors);
+                self.pos = 0;
+                self.transfer = Transfer::Read { multiple: false };
+            }
+            0xf => {
+                self.buffer = vec![0; TRACK_BYTES];
+                self.pos = 0;
+                self.transfer = Transfer::WriteTrack;
+            }
+            _ => self.fail(NOT_FOUND, cycle),
+        }
+    }
+    /// Restore, Seek and the Steps
+    fn type_one_command(&mut self, cmd: u8, disks: &Drives, cycle: u64) {
+        let drive = self.drive();
+        let cyl = drive.map_or(0, |d| self.cyl[d]) as i32;
+        let delta = match cmd >> 4 {
+            0 => {
+                self.track = 0;
+                -cyl
+            }
+            1 => {
+                let delta = self.data as i32 - self.track as i32;
+                self.track = self.data;
+                delta
+            }
+            n => {
+                match n {
+                    4 | 5 => self.step_in = true,
+                    6 | 7 => self.step_in = false,
+                    _ => {}
+                }
+                let delta = if self.step_in { 1 } else { -1 };
+                if cmd & 0x10 != 0 {
+                    self.track = self.track.wrapping_add(delta as u8);
+                }
+                delta
+            }
+        };
+        if delta != 0 {
+            self.step_in = delta > 0;
+        }
+        let new_cyl = (cyl + delta).clamp(0, u8::MAX as i32) as u8;
+        if let Some(d) = drive {
+            self.cyl[d] = new_cyl;
+        }
+        // with the verify bit set the track register must match an ID on the disk
+        if cmd & 0x04 != 0 {
+            let image = drive.and_then(|d| disks.drive[d].as_ref());
+            let tracks = image.map_or(0, |i| i.sector_count() / SECTORS_PER_TRACK);
+            if self.track != new_cyl || new_cyl as usize >= tracks {
+                self.status = NOT_FOUND;
+            }
+        }
+        let steps = (new_cyl as i32 - cyl).unsigned_abs() as u64;
+        self.end(cycle + COMMAND_CYCLES + steps * STEP_MS[(cmd & 3) as usize] * CPU_HZ / 1000);
+    }
+    /// The contents of the sector under the head whose ID matches the track and sector registers
+    fn find_sector<'a>(&self, disks: &'a Drives) -> Option<&'a [u8]> {
+        let d = self.drive()?;
+        if self.track != self.cyl[d] {
+            return None;
+        }
+        disks.read_sector(d, self.cyl[d] as usize, self.sector as usize).ok()
+    }
+    /// Puts the sector in the buffer for a Read Sector
+    fn load_sector(&mut self, disks: &Drives, cycle: u64) {
+        match self.find_sector(disks) {
+            Some(bytes) => {
+                self.buffer = bytes.to_vec();
+                self.pos = 0;
+            }
+            None => self.fail(NOT_FOUND, cycle),
+        }
+    }
+    /// Moves on to the next sector of a multiple sector command (which ends when it runs out)
+    fn next_sector(&mut self, disks: &Drives, cycle: u64) {
+        self.sector = self.sector.wrapping_add(1);
+        match self.transfer {
+            _ if self.find_sector(disks).is_none() => self.fail(NOT_FOUND, cycle),
+            Transfer::None => {
+                self.transfer = Transfer::Read { multiple: true };
+                self.load_sector(disks, cycle);
+            }
+            _ => self.pos = 0,
+        }
+    }
+    /// Called when the CPU has written the last byte of a sector or track
+    fn written(&mut self, disks: &mut Drives, cycle: u64) {
+        let d = self.drive().unwrap_or(0);
+        let cyl = self.cyl[d] as usize;
+        match self.transfer {
+            Transfer::WriteTrack => {
+                let raw = encode_track(&self.buffer);
+                for (sector, data) in sectors_in_track(&raw) {
+                    if let Err(e) = disks.write_sector(d, cyl, sector as usize, data) {
+                        warn!("formatting track {}: {}", cyl, e);
+                    }
+                }
+            }
+            Transfer::WriteSector { multiple } => {
+                if let Err(e) = disks.write_sector(d, cyl, self.sector as usize, &self.buffer) {
+                    warn!("{}", e);
+                }
+                if multiple {
+                    return self.next_sector(disks, cycle);
+                }
+            }
+            _ => {}
+        }
+        self.transfer = Transfer::None;
+        self.end(cycle + CRC_CYCLES);
+    }
+}
+
+/// CRC-16/CCITT of the given pieces (as the WD1793 computes it for ID and data fields)
+fn crc(pieces: &[&[u8]]) -> u16 {
+    let mut crc = 0xffffu16;
+    for b in pieces.iter().flat_map(|p| p.iter()) {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Turns what the CPU wrote with Write Track into what ends up on the disk: F5 writes an A1
+/// sync byte (the first of a run starts the CRC), F6 writes C2 and F7 writes the two CRC bytes
+fn encode_track(written: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(written.len());
+    let mut start = 0;
+    for (i, b) in written.iter().enumerate() {
+        match b {
+            0xf5 => {
+                if i == 0 || written[i - 1] != 0xf5 {
+                    start = raw.len();
+                }
+                raw.push(0xa1);
+            }
+            0xf6 => raw.push(0xc2),
+            0xf7 => {
+                let crc = crc(&[&raw[start..]]);
+                raw.extend(crc.to_be_bytes());
+            }
+            _ => raw.push(*b),
+        }
+    }
+    raw
+}
+
+/// Lays out a track with the given 256-byte sectors (numbered from 1)
+fn build_track(track: u8, sectors: &[&[u8]]) -> Vec<u8> {
+    let mut raw = vec![0x4e; 32];
+    for (i, data) in sectors.iter().enumerate() {
+        let id = [0xa1, 0xa1, 0xa1, 0xfe, track, 0, i as u8 + 1, 1];
+        raw.extend([0; 12]);
+        raw.extend(id);
+        raw.extend(crc(&[&id]).to_be_bytes());
+        raw.extend([0x4e; 22]);
+        raw.extend([0; 12]);
+        raw.extend([0xa1, 0xa1, 0xa1, 0xfb]);
+        raw.extend(*data);
+        raw.extend(crc(&[&[0xa1, 0xa1, 0xa1, 0xfb], data]).to_be_bytes());
+        raw.extend([0x4e; 24]);
+    }
+    raw.resize(TRACK_BYTES, 0x4e);
+    raw
+}
+
+/// Finds the 256-byte sectors in a track: each is an ID field (A1 A1 A1 FE track side sector
+/// size) followed by a data field (A1 A1 A1 FB and the data)
+fn sectors_in_track(raw: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut sectors = Vec::new();
+    let mut id = None;
+    let mut i = 0;
+    while i + 8 <= raw.len() {
+        if raw[i..i + 3] == [0xa1; 3] {
+            match raw[i + 3] {
+                0xfe => {
+                    id = Some((raw[i + 6], raw[i + 7]));
+                    i += 8;
+                    continue;
+                }
+                0xfb | 0xf8 if i + 4 + SECTOR_SIZE <= raw.len() => {
+                    if let Some((sector, 1)) = id.take() {
+                        sectors.push((sector, &raw[i + 4..i + 4 + SECTOR_SIZE]));
+                        i += 4 + SECTOR_SIZE;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    sectors
+}
+
+/// The FD-502 cartridge: the controller and the drives cabled to it
+pub struct Fd502 {
+    fdc: RefCell<DiskController>,
+    disks: Rc<RefCell<Drives>>,
+}
+impl Fd502 {
+    pub fn new(disks: Rc<RefCell<Drives>>) -> Self { Fd502 { fdc: RefCell::new(DiskController::new()), disks } }
+    /// Returns a controller if --fdc was given or a disk is mounted
+    pub fn from_args(disks: Rc<RefCell<Drives>>) -> Option<Self> {
+        let args = &config::ARGS;
+        (args.fdc || args.disk_images().next().is_some() || !args.disk_dir.is_empty()).then(|| {
+            info!("FD-502 disk controller at FF40");
+            Self::new(disks)
+        })
+    }
+}
+impl Cartridge for Fd502 {
+    fn name(&self) -> String { "FD-502 disk controller at FF40".to_string() }
+    fn owns_address(&self, addr: u16) -> bool { self.fdc.borrow().owns_address(addr) }
+    fn read(&self, addr: u16, cycle: u64) -> Option<u8> {
+        let mut fdc = self.fdc.borrow_mut();
+        fdc.owns_address(addr).then(|| fdc.read(addr, &self.disks.borrow(), cycle))
+    }
+    fn write(&mut self, addr: u16, byte: u8, cycle: u64) -> Result<bool, Error> {
+        let fdc = self.fdc.get_mut();
+        let owned = fdc.owns_address(addr);
+        if owned {
+            fdc.write(addr, byte, &mut self.disks.borrow_mut(), cycle);
+        }
+        Ok(owned)
+    }
+    fn reset(&mut self) { self.fdc.get_mut().reset() }
+    fn halted_until(&self) -> Option<u64> { self.fdc.borrow().halted_until() }
+    // INTRQ at the end of a command starts an NMI
+    fn step(&mut self, cycle: u64) -> bool { self.fdc.get_mut().tick(cycle) }
+    fn tag(&self) -> [u8; 4] { *b"FDC " }
+    fn snapshot(&self, _cycle: u64) -> Option<Vec<u8>> { Some(self.fdc.borrow().save_state()) }
+    fn check_snapshot(&self, state: &[u8]) -> Result<(), Error> {
+        snapshot::check_len(&self.tag(), state, 6 + MAX_DRIVES)
+    }
+    fn restore(&mut self, state: &[u8], _cycle: u64) { self.fdc.get_mut().load_state(state) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(name: &str, tracks: usize) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("coco_diskctl_{}_{}.dsk", name, std::process::id()));
+        let data: Vec<u8> = (0..tracks * SECTORS_PER_TRACK * SECTOR_SIZE).map(|i| (i / SECTOR_SIZE) as u8).collect();
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn read_sector_with_nmi() {
+        let path = image("read", 35);
+        let mut core = Core::headless().unwrap();
+        core.disks.borrow_mut().mount(0, &path, false).unwrap();
+        core.paks.push(Box::new(Fd502::new(core.disks.clone())));
+        // as DSKCON does it: select drive 0, read sector 5 of track 0 into $4000 with the halt
+        // enabled and let the NMI (whose handler reads the status) end the loop
+        let program = [
+            0x86, 0x29, 0xb7, 0xff, 0x40, // lda #$29, sta $ff40
+            0x86, 0x05, 0xb7, 0xff, 0x4a, // lda #5, sta $ff4a
+            0x86, 0x80, 0xb7, 0xff, 0x48, // lda #$80, sta $ff48
+            0xc6, 0xa9, 0xf7, 0xff, 0x40, // ldb #$a9, stb $ff40
+            0xb6, 0xff, 0x4b, 0xa7, 0x80, // loop: lda $ff4b, sta ,x+
+            0xf7, 0xff, 0x40, 0x20, 0xf6, // stb $ff40, bra loop
+        ];
+        core.raw_ram[0x3000..0x3000 + program.len()].copy_from_slice(&program);
+        core.raw_ram[0x3100..0x3105].copy_from_slice(&[0xb6, 0xff, 0x48, 0x20, 0xfe]);
+        core.raw_ram[0xbffc..0xbffe].copy_from_slice(&[0x31, 0x00]);
+        core.raw_ram[0x4000..0x4102].fill(0xee);
+        (core.reg.pc, core.reg.x, core.reg.s) = (0x3000, 0x4000, 0x7000);
+        core.warp = true;
+        for _ in 0..5000 {
+            if core.reg.pc == 0x3103 {
+                break;
+            }
+            core.exec_one().unwrap();
+        }
+        assert_eq!(core.reg.pc, 0x3103);
+        assert_eq!(core.reg.a, 0, "status");
+        assert_eq!(core.raw_ram[0x4000..0x4100], [4; SECTOR_SIZE]);
+        assert_eq!(core.raw_ram[0x4100], 0xee);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seek_write_and_format() {
+        let path = image("write", 35);
+        let mut disks = Drives::default();
+        disks.mount(0, &path, false).unwrap();
+        let mut fdc = DiskController::new();
+        let mut cycle = 0;
+        // waits for the last command to end, gives a command and waits for it to end (or to want data)
+        fn run(fdc: &mut DiskController, disks: &mut Drives, cycle: &mut u64, cmd: u8) {
+            while fdc.busy && fdc.transfer == Transfer::None {
+                *cycle += 1000;
+                fdc.tick(*cycle);
+            }
+            fdc.write(0xff48, cmd, disks, *cycle);
+            while fdc.busy && fdc.transfer == Transfer::None {
+                *cycle += 1000;
+                fdc.tick(*cycle);
+            }
+        }
+        fdc.write(0xff40, 0x09, &mut disks, 0);
+        // seek to track 2 (verifying) and write sector 3
+        fdc.write(0xff4b, 2, &mut disks, 0);
+        run(&mut fdc, &mut disks, &mut cycle, 0x14);
+        assert_eq!((fdc.cyl[0], fdc.read(0xff48, &disks, cycle)), (2, 0));
+        fdc.write(0xff4a, 3, &mut disks, cycle);
+        run(&mut fdc, &mut disks, &mut cycle, 0xa0);
+        for i in 0..SECTOR_SIZE {
+            fdc.write(0xff4b, i as u8, &mut disks, cycle);
+        }
+        assert_eq!(disks.read_sector(0, 2, 3).unwrap()[..4], [0, 1, 2, 3]);
+        // a sector that isn't there
+        fdc.write(0xff4a, 19, &mut disks, cycle);
+        run(&mut fdc, &mut disks, &mut cycle, 0x80);
+        assert_eq!(fdc.read(0xff48, &disks, cycle), NOT_FOUND);
+        // Read Track gives what Write Track takes
+        run(&mut fdc, &mut disks, &mut cycle, 0xe0);
+        let track: Vec<u8> = (0..TRACK_BYTES).map(|_| fdc.read(0xff4b, &disks, cycle)).collect();
+        let sectors = sectors_in_track(&track);
+        assert_eq!(sectors.len(), SECTORS_PER_TRACK);
+        assert_eq!((sectors[2].0, sectors[2].1[5], sectors[3].1[5]), (3, 5, 2 * 18 + 3));
+        // format track 2 with every sector filled with $E5 (F5 and F7 as DSKINI writes them)
+        let mut written = Vec::new();
+        for s in 1..=SECTORS_PER_TRACK as u8 {
+            written.extend([0xf5, 0xf5, 0xf5, 0xfe, 2, 0, s, 1, 0xf7]);
+            written.extend([0x4e; 22].iter().chain(&[0; 12]));
+            written.extend([0xf5, 0xf5, 0xf5, 0xfb]);
+            written.extend([0xe5; SECTOR_SIZE]);
+            written.extend([0xf7, 0x4e]);
+        }
+        written.resize(TRACK_BYTES, 0x4e);
+        run(&mut fdc, &mut disks, &mut cycle, 0xf0);
+        for b in written {
+            fdc.write(0xff4b, b, &mut disks, cycle);
+        }
+        assert_eq!(disks.read_sector(0, 2, 18).unwrap(), [0xe5; SECTOR_SIZE]);
+        assert_eq!(disks.read_sector(0, 3, 1).unwrap()[0], 3 * 18);
+        // the CRC of an ID field is the WD1793's
+        assert_eq!(crc(&[&[0xa1, 0xa1, 0xa1, 0xfe, 0, 0, 1, 1]]), 0xfa0c);
+        // a snapshot keeps the registers and the head but not the command in progress
+        run(&mut fdc, &mut disks, &mut cycle, 0x80);
+        let mut restored = DiskController::new();
+        restored.load_state(&fdc.save_state());
+        assert_eq!((restored.cyl, restored.track, restored.sector, restored.busy), ([2, 0, 0, 0], 2, 19, false));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
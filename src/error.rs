@@ -22,6 +22,8 @@ pub enum ErrorKind {
     Test,
     /// error encountered due to the machine code program
     Runtime,
+    /// a device was asked for something it can't do (see misuse.rs)
+    Device,
     /// normal exit (not really an error)
     Exit,
     /// catch-all for other errors
@@ -38,6 +40,22 @@ impl Error {
     }
 }
 
+/// Remembers which device errors have been logged so that a program that keeps making the same
+/// mistake doesn't flood the console
+#[derive(Debug, Default)]
+pub struct Reported(std::collections::HashSet<String>);
+impl Reported {
+    /// Logs the error as a warning unless an error with the same message was already logged;
+    /// returns true if it was logged
+    pub fn warn(&mut self, e: &Error) -> bool {
+        let new = self.0.insert(e.msg.clone());
+        if new {
+            warn!("{}", e.msg)
+        }
+        new
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self { Error::new(ErrorKind::IO, None, e.to_string().as_str()) }
 }
@@ -50,7 +68,7 @@ impl fmt::Display for Error {
         let mut res = write!(f, "{}", self.msg);
         if res.is_ok() {
             if let Some(ctx) = self.ctx {
-                res = write!(f, "\nContext: {} -> ({})", ctx, ctx.cc);
+                res = write!(f, "\nContext: {}", ctx);
             }
         }
         res
@@ -0,0 +1,258 @@
+//! Evaluation of value expressions.
+//!
+//! The assembler, the test criteria in assembly source and the debugger all parse expressions
+//! with the same grammar (see Parser::parse_operand) into a tree of ValueNodes. What the names
+//! in an expression mean depends on who evaluates it, so evaluation goes through a
+//! LabelResolver: the assembler resolves its labels, the debugger resolves the symbols it has
+//! loaded, the registers and `[<expr>]` (the byte at an address). Anything a resolver doesn't
+//! support is an error, e.g., registers can't be used in an assembly-time expression.
+//!
+//! A Condition compares two expressions. The debugger uses them for conditional breakpoints:
+//! ```text
+//! a=$20    x>=buffer+$100    [$ff03]<$80    pc!=loop
+//! ```
+//! A condition without a comparison is true when its value isn't zero.
+use super::parse::{Token, TokenType};
+use super::*;
+
+/// Supplies the values of the names used in an expression
+pub trait LabelResolver {
+    fn resolve(&self, label: &str) -> Option<u8u16>;
+    /// Returns the value of a register (None if registers can't be used)
+    fn register(&self, _name: &str) -> Option<u8u16> { None }
+    /// Returns the byte at addr (None if memory can't be read)
+    fn read(&self, _addr: u16) -> Option<u8> { None }
+}
+
+/// Each value expression is parsed and converted into a tree of ValueNode objects.
+/// The tree could consist of only a root ValueNode containing a value or it may be
+/// arbitrarily deep, in which case the root ValueNode's token will be an operator.
+#[derive(Debug)]
+pub struct ValueNode {
+    /// either the node's value (e.g. label or number) or an operation
+    pub token: Token,
+    /// true if the node's value should be negated after resolution
+    pub negate: bool,
+    /// child nodes
+    left: Option<Box<ValueNode>>,
+    right: Option<Box<ValueNode>>,
+}
+impl ValueNode {
+    pub fn new(val_or_op: Token, negate: bool, left: Option<ValueNode>, right: Option<ValueNode>) -> Self {
+        ValueNode {
+            token: val_or_op,
+            negate,
+            left: left.map(Box::new),
+            right: right.map(Box::new),
+        }
+    }
+    /// Evaluate this ValueNode given an address and LabelResolver.
+    /// If this ValueNode (or its children) require a label that the LabelResolver can't resolve
+    /// then the result will be an Error of ErrorKind::Reference.
+    // Indicate signed = true if the outcome of an evaluation MIGHT be signed.
+    pub fn eval(&self, lr: &dyn LabelResolver, addr: u16, signed: bool) -> Result<u8u16, Error> {
+        let sign = |v: u8u16| {
+            if signed || self.negate {
+                let (u, _) = v.force_signed(self.negate);
+                u
+            } else {
+                v
+            }
+        };
+        match self.token.ttype {
+            TokenType::Number => Ok(sign(self.token.value.unwrap())),
+            TokenType::Label => {
+                if let Some(v) = lr.resolve(self.token.clean().as_str()) {
+                    Ok(sign(v))
+                } else {
+                    Err(Error::new(
+                        ErrorKind::Reference,
+                        None,
+                        format!("unresolved label \"{}\"", self.token.clean()).as_str(),
+                    ))
+                }
+            }
+            TokenType::Register => lr
+                .register(&self.token.clean())
+                .map(sign)
+                .ok_or_else(|| syntax_err!(format!("register {} can't be used here", self.token.clean()))),
+            TokenType::LBracket => {
+                // memory dereference; the child node is the address
+                let at = self
+                    .left
+                    .as_ref()
+                    .ok_or_else(|| syntax_err!("missing address in []"))?
+                    .eval(lr, addr, false)?
+                    .u16();
+                lr.read(at)
+                    .map(|b| sign(u8u16::u8(b)))
+                    .ok_or_else(|| syntax_err!(format!("can't read ${:04X} here", at)))
+            }
+            TokenType::Add | TokenType::Sub | TokenType::Star | TokenType::Div | TokenType::Mod | TokenType::Pow => {
+                if let Some(left) = &self.left {
+                    if let Some(right) = &self.right {
+                        return self._eval_binary(lr, addr, signed, left, right);
+                    }
+                } else if self.token.ttype == TokenType::Star {
+                    // this is a location reference; it has no child nodes
+                    // use the supplied addr as the value for the special label "*"
+                    return Ok(u8u16::u16(addr));
+                }
+                Err(Error::new(
+                    ErrorKind::Syntax,
+                    None,
+                    format!("missing operand(s) for binary operation \"{}\"", self.token.clean()).as_str(),
+                ))
+            }
+            _ => Err(Error::new(
+                ErrorKind::Syntax,
+                None,
+                format!("can't evaluate \"{}\"", self.token).as_str(),
+            )),
+        }
+    }
+    fn _eval_binary(
+        &self, lr: &dyn LabelResolver, addr: u16, signed: bool, left: &ValueNode, right: &ValueNode,
+    ) -> Result<u8u16, Error> {
+        let lhs = left.eval(lr, addr, signed)?;
+        let rhs = right.eval(lr, addr, signed)?;
+        // TODO: warn about overflow in these operations
+        match self.token.ttype {
+            TokenType::Add => u8u16::u16(lhs.u16())
+                .checked_add(rhs)
+                .map(|u| u8u16::from_u16_shrink(u.u16()))
+                .ok_or_else(|| syntax_err!("addition overflow")),
+            TokenType::Sub => u8u16::u16(lhs.u16())
+                .checked_sub(rhs)
+                .map(|u| u8u16::from_u16_shrink(u.u16()))
+                .ok_or_else(|| syntax_err!("subtraction overflow")),
+            TokenType::Star => {
+                let (u, f) = lhs.u16().overflowing_mul(rhs.u16());
+                if f {
+                    Err(syntax_err!("multiplication overflow"))
+                } else {
+                    Ok(u8u16::from_u16_shrink(u))
+                }
+            }
+            TokenType::Div => Ok(lhs.div(rhs)),
+            TokenType::Mod => Ok(lhs.modulo(rhs)),
+            TokenType::Pow => {
+                let (u, f) = lhs.u16().overflowing_pow(rhs.u16() as u32);
+                if f {
+                    Err(syntax_err!("exponential overflow"))
+                } else {
+                    Ok(u8u16::from_u16_shrink(u))
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+impl fmt::Display for ValueNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.left.as_ref(), self.right.as_ref()) {
+            (Some(left), None) if self.token.ttype == TokenType::LBracket => {
+                write!(f, "{}[{}]", if self.negate { "-" } else { "" }, left)
+            }
+            (Some(left), Some(right)) => write!(
+                f,
+                "({}{} {} {})",
+                if self.negate { "-" } else { "" },
+                left,
+                self.token.clean(),
+                right
+            ),
+            (Some(left), None) => write!(f, "ERROR? {} LEFT: {} ", self.token.clean(), left),
+            _ if self.negate => write!(f, "-{}", self.token.clean()),
+            _ => write!(f, "{}", self.token.clean()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+impl fmt::Display for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            Compare::Eq => "=",
+            Compare::Ne => "!=",
+            Compare::Lt => "<",
+            Compare::Le => "<=",
+            Compare::Gt => ">",
+            Compare::Ge => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// A comparison of two expressions (or a single expression that's true when it isn't zero)
+#[derive(Debug)]
+pub struct Condition {
+    pub lhs: ValueNode,
+    pub cmp: Compare,
+    pub rhs: Option<ValueNode>,
+}
+impl Condition {
+    /// Evaluates the condition. Values are compared as unsigned 16-bit numbers.
+    pub fn eval(&self, lr: &dyn LabelResolver, addr: u16) -> Result<bool, Error> {
+        let lhs = self.lhs.eval(lr, addr, false)?.u16();
+        let rhs = match self.rhs.as_ref() {
+            Some(rhs) => rhs.eval(lr, addr, false)?.u16(),
+            None => return Ok(lhs != 0),
+        };
+        Ok(match self.cmp {
+            Compare::Eq => lhs == rhs,
+            Compare::Ne => lhs != rhs,
+            Compare::Lt => lhs < rhs,
+            Compare::Le => lhs <= rhs,
+            Compare::Gt => lhs > rhs,
+            Compare::Ge => lhs >= rhs,
+        })
+    }
+}
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.rhs.as_ref() {
+            Some(rhs) => write!(f, "{} {} {}", self.lhs, self.cmp, rhs),
+            None => write!(f, "{}", self.lhs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Machine;
+    impl LabelResolver for Machine {
+        fn resolve(&self, label: &str) -> Option<u8u16> { (label == "buf").then_some(u8u16::u16(0x1000)) }
+        fn register(&self, name: &str) -> Option<u8u16> { (name == "A").then_some(u8u16::u8(0x20)) }
+        fn read(&self, addr: u16) -> Option<u8> { (addr < 0xff00).then_some(addr as u8) }
+    }
+    struct Labels;
+    impl LabelResolver for Labels {
+        fn resolve(&self, _: &str) -> Option<u8u16> { Some(u8u16::u8(1)) }
+    }
+
+    #[test]
+    fn conditions() {
+        let parser = parse::Parser::new();
+        let holds = |src: &str| parser.parse_condition(src).unwrap().eval(&Machine, 0).unwrap();
+        assert!(holds("a=$20") && holds("a==32") && holds("a!=#$21"));
+        assert!(holds("[buf+5]=5") && holds("[buf+5]<6") && holds("[buf+5]<=5") && !holds("[buf+5]>5"));
+        assert!(holds("a*2>=$40") && holds("buf") && !holds("a-32"));
+        assert!(parser.parse_condition("a=").is_err() && parser.parse_condition("a=1)").is_err());
+        assert!(parser.parse_condition("[$ff00]=0").unwrap().eval(&Machine, 0).is_err());
+        // the assembler's labels don't include registers or memory
+        assert!(parser.str_to_value_node("x+1").unwrap().eval(&Labels, 0, false).is_err());
+        assert!(parser.str_to_value_node("[x]").unwrap().eval(&Labels, 0, false).is_err());
+        assert_eq!(parser.parse_condition("-[a+1]>=x").unwrap().to_string(), "-[(A + 01)] >= X");
+    }
+}
@@ -0,0 +1,36 @@
+//! Entry points for the fuzz targets in fuzz/.
+//!
+//! Each function accepts arbitrary bytes and may return an error but must never panic.
+//! The core they run against has no window or audio device attached.
+use super::*;
+use crate::hex::HexRecordCollection;
+
+/// Parses the input as an Intel hex file and loads it into memory
+pub fn hex_loader(data: &[u8]) -> Result<(), Error> {
+    let text = String::from_utf8_lossy(data);
+    let hex = HexRecordCollection::from_str_iter(text.lines())?;
+    Core::headless()?.load_hex(&hex, None)?;
+    Ok(())
+}
+
+/// Assembles the input as source code and loads the resulting program into memory (INCLUDEs
+/// find no files, so the host's files are never read)
+pub fn assembler(data: &[u8]) -> Result<(), Error> {
+    let text = String::from_utf8_lossy(data);
+    let asm = Assembler::with_fs(Box::new(assembler::MemoryFs::new()));
+    let program = asm.assemble_str(&text)?;
+    Core::headless()?.load_program(&program, None)?;
+    Ok(())
+}
+
+/// Decodes the input as a sequence of instructions (without executing them)
+pub fn decoder(data: &[u8]) -> Result<(), Error> {
+    let mut core = Core::headless()?;
+    let len = data.len().min(0x8000);
+    core.raw_ram[..len].copy_from_slice(&data[..len]);
+    let mut addr = 0usize;
+    while addr < len {
+        addr += core.instruction_size_at(addr as u16).unwrap_or(1).max(1) as usize;
+    }
+    Ok(())
+}
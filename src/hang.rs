@@ -0,0 +1,179 @@
+//! Detection of programs that appear to be hung.
+//!
+//! With --hang-detect the emulator watches for a program that keeps running the same few
+//! instructions without making any progress that's visible in memory. Interrupt handlers are
+//! ignored (a program that's stuck still gets its timer interrupts) and so are writes to the
+//! stack and to I/O space, so a loop that does nothing but poll a device or count down a delay
+//! counts as stuck. When that's gone on for the given number of cycles the loop is reported
+//! (and, with --hang-break, the program stops or the debugger is entered).
+//!
+//! Plenty of programs wait in a loop on purpose (e.g., for a key by calling POLCAT), so loops
+//! that include an address given with --hang-allow are never reported. Waiting with CWAI or
+//! SYNC is never reported either.
+use super::*;
+use std::collections::HashSet;
+
+/// a loop that runs more distinct instructions than this is doing real work
+const MAX_LOOP: usize = 128;
+/// writes this close above S are to the stack (return addresses, saved registers and locals)
+const STACK_SPAN: u16 = 256;
+/// the start of I/O space
+const IO_START: u16 = 0xff00;
+
+#[derive(Debug)]
+pub struct HangDetector {
+    /// the number of cycles without progress before a loop is reported
+    limit: u64,
+    /// the addresses (or symbols) of intentional wait loops
+    allow: Vec<String>,
+    /// the cycle at which the last progress was made
+    start: u64,
+    /// the instructions run since then
+    pcs: HashSet<u16>,
+    /// true once the current loop has been reported
+    reported: bool,
+    /// the values of S above the state stacked for each interrupt being serviced
+    interrupts: Vec<u16>,
+}
+impl HangDetector {
+    pub fn new(mcycles: f64, allow: Vec<String>) -> Self {
+        info!("reporting loops that make no progress for {} million cycles", mcycles);
+        HangDetector {
+            limit: (mcycles * 1e6) as u64,
+            allow,
+            start: 0,
+            pcs: HashSet::new(),
+            reported: false,
+            interrupts: Vec::new(),
+        }
+    }
+    /// Creates the detector from the --hang-detect and --hang-allow options
+    pub fn from_args() -> Option<Self> {
+        config::ARGS.hang_detect.map(|mcycles| HangDetector::new(mcycles, config::ARGS.hang_allow.clone()))
+    }
+    /// Starts looking for a new loop
+    pub fn progress(&mut self, cycle: u64) {
+        self.start = cycle;
+        self.pcs.clear();
+        self.reported = false;
+    }
+    /// Forgets everything (e.g., after a reset)
+    pub fn reset(&mut self, cycle: u64) {
+        self.interrupts.clear();
+        self.progress(cycle);
+    }
+    /// Notes that an interrupt is starting with S (before anything is stacked) at s
+    pub fn interrupt(&mut self, s: u16) { self.interrupts.push(s); }
+    /// Returns true if an interrupt handler is running with S at s
+    fn in_interrupt(&mut self, s: u16) -> bool {
+        // the handler has returned once S is back where it was
+        while self.interrupts.last().is_some_and(|&top| s >= top) {
+            self.interrupts.pop();
+        }
+        !self.interrupts.is_empty()
+    }
+    /// Checks the writes made by an instruction (with S at s afterwards) for progress
+    pub fn writes(&mut self, writes: &[instructions::WriteRecord], s: u16, cycle: u64) {
+        if self.in_interrupt(s) {
+            return;
+        }
+        let visible = |addr: u16| addr < IO_START && !(s..s.saturating_add(STACK_SPAN)).contains(&addr);
+        if writes.iter().any(|w| visible(w.addr)) {
+            self.progress(cycle);
+        }
+    }
+    /// Notes the instruction that ran at pc (with S at s afterwards). Returns true if the program
+    /// has just been found to be stuck in a loop.
+    pub fn instruction(&mut self, pc: u16, s: u16, cycle: u64) -> bool {
+        if self.in_interrupt(s) {
+            return false;
+        }
+        if self.pcs.insert(pc) && self.pcs.len() > MAX_LOOP {
+            self.progress(cycle);
+        }
+        if self.reported || cycle.saturating_sub(self.start) < self.limit {
+            return false;
+        }
+        self.reported = true;
+        true
+    }
+    /// Returns the instructions in the loop
+    pub fn pcs(&self) -> Vec<u16> {
+        let mut pcs: Vec<u16> = self.pcs.iter().copied().collect();
+        pcs.sort_unstable();
+        pcs
+    }
+}
+
+impl Core {
+    /// Reports the loop the program is stuck in (unless it's allowed). Returns an error if the
+    /// program should stop.
+    pub fn report_hang(&mut self, pc: u16) -> Result<(), Error> {
+        let Some(hang) = self.hang.as_ref() else {
+            return Ok(());
+        };
+        let pcs = hang.pcs();
+        let allowed = hang.allow.iter().filter_map(|a| self.hang_allowed_addr(a)).any(|a| pcs.contains(&a));
+        if allowed {
+            return Ok(());
+        }
+        let symbol = |addr: u16| match self.symbol_by_addr(addr).and_then(|s| s.first()) {
+            Some(sym) => format!("${:04X} ({})", addr, sym),
+            None => format!("${:04X}", addr),
+        };
+        let (first, last) = (pcs[0], pcs[pcs.len() - 1]);
+        let msg = format!(
+            "program appears hung at {}: {} instructions in {}-{} have run for {} cycles without progress",
+            symbol(pc),
+            pcs.len(),
+            symbol(first),
+            symbol(last),
+            hang.limit
+        );
+        if config::ARGS.hang_break {
+            return Err(runtime_err!(Some(self.reg), "{}", msg));
+        }
+        warn!("{}", msg);
+        Ok(())
+    }
+    /// Returns the address named by a --hang-allow entry (hex or a symbol)
+    fn hang_allowed_addr(&self, allow: &str) -> Option<u16> {
+        let hex = allow.trim_start_matches('$').trim_start_matches("0x");
+        u16::from_str_radix(hex, 16).ok().or_else(|| self.symbol_by_name(allow))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use instructions::WriteRecord;
+    use memory::AccessType;
+
+    #[test]
+    fn loops() {
+        let write = |addr| WriteRecord { addr, at: AccessType::Generic, val: u8u16::u8(0) };
+        let mut hang = HangDetector::new(0.001, vec![]);
+        // a delay loop
+        for cycle in (0..999).step_by(3) {
+            assert!(!hang.instruction(0x3f00 + cycle as u16 % 2, 0x7f00, cycle));
+        }
+        // its timer interrupt doesn't count as progress
+        hang.interrupt(0x7f00);
+        hang.writes(&[write(0x0112)], 0x7ef4, 1000);
+        assert!(!hang.instruction(0xa9de, 0x7ef4, 1000));
+        assert!(hang.instruction(0x3f00, 0x7f00, 1001));
+        assert_eq!(hang.pcs(), vec![0x3f00, 0x3f01]);
+        assert!(!hang.instruction(0x3f01, 0x7f00, 5000));
+        // pushes and device writes aren't progress either but other writes are
+        hang.writes(&[write(0x7efe), write(0xff20)], 0x7efe, 5001);
+        assert!(hang.reported);
+        hang.writes(&[write(0x0400)], 0x7f00, 5002);
+        assert!(!hang.reported && hang.pcs.is_empty());
+        // so is running lots of different instructions
+        for pc in 0..=MAX_LOOP as u16 {
+            hang.instruction(0x4000 + pc, 0x7f00, 5500);
+        }
+        assert!(!hang.instruction(0x3f00, 0x7f00, 6001));
+        assert!(hang.instruction(0x3f00, 0x7f00, 6502));
+    }
+}
@@ -5,8 +5,9 @@
 //!   
 //! This implementation is based on the specification of I8HEX described in
 //! [this wikipedia article](https://en.wikipedia.org/wiki/Intel_HEX).
+//! Segment and linear address records (I16HEX and I32HEX) can also be read, as can
+//! Motorola S-record files, which are converted into equivalent Intel HEX records as they're read.
 
-use regex::Regex;
 use std::fmt::{self, Display};
 use std::fs::File;
 use std::io;
@@ -44,6 +45,20 @@ impl Display for HexRecord {
     }
 }
 use super::Error;
+
+/// Converts a string of hex digit pairs into bytes
+fn hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    if !s.is_ascii() {
+        return Err("invalid characters in record".to_string());
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".to_string());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("invalid hex digits \"{}\"", &s[i..i + 2])))
+        .collect()
+}
 impl HexRecord {
     pub fn from_data(address: u16, data: &[u8]) -> Self {
         let mut h = HexRecord {
@@ -56,37 +71,110 @@ impl HexRecord {
         h.checksum = h.calc_checksum().expect("should be impossible");
         h
     }
-    pub fn from_str<S: AsRef<str>>(s: S) -> Result<Option<Self>, ()> {
-        let re = Regex::new(r"(?i)^.*:([0-9a-f]{2})([0-9a-f]{4})([0-9a-f]{2})((?:[0-9a-f]{2})*)([0-9a-f]{2})")
-            .map_err(|_| ())?;
-        if let Some(c) = re.captures(s.as_ref()) {
-            Ok(Some(Self::from_captures(&c).ok_or(())?))
-        } else {
-            Ok(None)
+    /// Parses one line of an Intel HEX file, verifying its length and checksum
+    pub fn from_intel(line: &str) -> Result<Self, String> {
+        let bytes = hex_bytes(line.strip_prefix(':').ok_or("record does not start with ':'")?)?;
+        if bytes.len() < 5 {
+            return Err("record is too short".to_string());
+        }
+        let data_size = bytes[0];
+        if bytes.len() != data_size as usize + 5 {
+            return Err(format!(
+                "record length {} does not match its byte count ({})",
+                bytes.len() - 5,
+                data_size
+            ));
         }
-    }
-    pub fn from_captures(c: &regex::Captures) -> Option<Self> {
-        let data_size = u8::from_str_radix(c.get(1)?.as_str(), 16).ok()?;
         let h = HexRecord {
             data_size,
-            address: u16::from_str_radix(c.get(2)?.as_str(), 16).ok()?,
-            record_type: u8::from_str_radix(c.get(3)?.as_str(), 16).ok()?,
-            data: HexRecord::data_from_str(c.get(4)?.as_str(), data_size),
-            checksum: u8::from_str_radix(c.get(5)?.as_str(), 16).ok()?,
+            address: (bytes[1] as u16) << 8 | bytes[2] as u16,
+            record_type: bytes[3],
+            data: if data_size > 0 { Some(bytes[4..bytes.len() - 1].to_vec()) } else { None },
+            checksum: bytes[bytes.len() - 1],
         };
-        h.calc_checksum().filter(|&c| c == h.checksum).map(|_| h)
+        let expected = h.calc_checksum().ok_or("bad record")?;
+        if expected != h.checksum {
+            return Err(format!("checksum mismatch (record has {:02X}; computed {:02X})", h.checksum, expected));
+        }
+        if h.record_type > HexRecordType::StartLinAddr {
+            return Err(format!("unknown record type {:02X}", h.record_type));
+        }
+        Ok(h)
     }
-    fn data_from_str(s: &str, byte_count: u8) -> Option<Vec<u8>> {
-        if byte_count == 0 || s.len() < (2 * byte_count) as usize {
-            return None;
+    /// Parses one line of a Motorola S-record file and converts it into the equivalent Intel HEX
+    /// record(s): S1-S3 become data records (preceded by an extended linear address record when
+    /// the upper 16 bits of the address differ from the previous data record's, which upper holds,
+    /// so a record below 64K after one above it goes back to base 0) and S7-S9 become a start
+    /// address record followed by EOF. Header (S0) and count (S5/S6) records produce no records.
+    pub fn from_srec(line: &str, upper: &mut u16) -> Result<Vec<Self>, String> {
+        let mut chars = line.chars();
+        if chars.next() != Some('S') {
+            return Err("record does not start with 'S'".to_string());
+        }
+        let kind = chars.next().and_then(|c| c.to_digit(10)).ok_or("missing S-record type")?;
+        let bytes = hex_bytes(chars.as_str())?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err("S-record length does not match its byte count".to_string());
         }
-        let mut data: Vec<u8> = Vec::with_capacity(byte_count as usize);
-        let mut i = 0u8;
-        while i < byte_count {
-            data.push(u8::from_str_radix(&s[(i * 2) as usize..((i + 1) * 2) as usize], 16).ok()?);
-            i += 1;
+        let sum = bytes[..bytes.len() - 1].iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        let checksum = bytes[bytes.len() - 1];
+        if !sum != checksum {
+            return Err(format!("checksum mismatch (record has {:02X}; computed {:02X})", checksum, !sum));
+        }
+        let addr_size = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(format!("unknown S-record type S{}", kind)),
+        };
+        if bytes.len() < addr_size + 2 {
+            return Err("S-record is too short".to_string());
+        }
+        let addr = bytes[1..=addr_size].iter().fold(0u32, |acc, &b| acc << 8 | b as u32);
+        let data = &bytes[addr_size + 1..bytes.len() - 1];
+        let mut records = Vec::new();
+        match kind {
+            1..=3 => {
+                if (addr >> 16) as u16 != *upper {
+                    *upper = (addr >> 16) as u16;
+                    records.push(HexRecord::with_type(HexRecordType::ExLinAddr, 0, &upper.to_be_bytes()));
+                }
+                if !data.is_empty() {
+                    records.push(HexRecord::from_data(addr as u16, data));
+                }
+            }
+            7..=9 => {
+                records.push(HexRecord::with_type(HexRecordType::StartLinAddr, 0, &addr.to_be_bytes()));
+                records.push(HexRecord::with_type(HexRecordType::EndOfFile, 0, &[]));
+            }
+            _ => {}
         }
-        Some(data)
+        Ok(records)
+    }
+    pub fn with_type(record_type: u8, address: u16, data: &[u8]) -> Self {
+        let mut h = HexRecord {
+            data_size: data.len() as u8,
+            address,
+            record_type,
+            data: if data.is_empty() { None } else { Some(data.to_vec()) },
+            checksum: 0,
+        };
+        h.checksum = h.calc_checksum().expect("should be impossible");
+        h
+    }
+    /// Returns the record's data interpreted as a big-endian number
+    pub fn data_value(&self) -> u32 {
+        self.data.iter().flatten().fold(0u32, |acc, &b| acc << 8 | b as u32)
+    }
+    /// Returns an S-record line (S1 data or S9 start address) with a 16-bit address
+    pub fn srec(kind: u8, address: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8 + 3];
+        bytes.extend(address.to_be_bytes());
+        bytes.extend(data);
+        let sum = bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        let mut line = format!("S{}", kind);
+        bytes.iter().chain([!sum].iter()).for_each(|b| line.push_str(&format!("{:02X}", b)));
+        line
     }
     fn calc_checksum(&self) -> Option<u8> {
         let mut sum = 0u16;
@@ -116,6 +204,7 @@ impl HexRecordCollection {
             eof: false,
         }
     }
+    /// Builds a collection from the lines of an Intel HEX or Motorola S-record file
     pub fn from_str_iter<I, T>(iter: I) -> Result<Self, Error>
     where
         I: IntoIterator<Item = T>,
@@ -125,10 +214,19 @@ impl HexRecordCollection {
             records: Vec::new(),
             eof: false,
         };
-        for s in iter {
-            let hr = HexRecord::from_str(s.into()).map_err(|_| general_err!("failed to parse hex file"))?;
-            if let Some(hr) = hr {
-                hf.add_record(hr)?
+        let mut upper = 0u16;
+        for (i, s) in iter.into_iter().enumerate() {
+            let s = s.into();
+            let line = s.trim();
+            let records = match line.chars().next() {
+                None => continue,
+                Some(':') => HexRecord::from_intel(line).map(|r| vec![r]),
+                Some('S') => HexRecord::from_srec(line, &mut upper),
+                Some(_) => Err("not an Intel HEX or S-record line".to_string()),
+            };
+            let records = records.map_err(|e| general_err!("hex file line {}: {}", i + 1, e))?;
+            for r in records {
+                hf.add_record(r).map_err(|e| general_err!("hex file line {}: {}", i + 1, e.msg))?;
             }
         }
         if hf.eof {
@@ -178,3 +276,29 @@ impl Deref for HexRecordCollection {
     type Target = Vec<HexRecord>;
     fn deref(&self) -> &Self::Target { &self.records }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_and_srec() -> Result<(), Error> {
+        let intel = HexRecordCollection::from_str_iter([":0300300002337A1E", ":00000001FF"])?;
+        assert_eq!(intel[0].address, 0x0030);
+        assert_eq!(intel[0].data, Some(vec![0x02, 0x33, 0x7a]));
+        let e = HexRecordCollection::from_str_iter([":0300300002337A1F", ":00000001FF"]).err().unwrap();
+        assert!(e.msg.contains("line 1: checksum mismatch"));
+        let srec = HexRecordCollection::from_str_iter(["S00600004844521B", "S1070E008601123918", "S9030E00EE"])?;
+        assert_eq!(srec[0].address, 0x0e00);
+        assert_eq!(srec[0].data, Some(vec![0x86, 0x01, 0x12, 0x39]));
+        assert_eq!(srec[1].record_type, HexRecordType::StartLinAddr);
+        assert_eq!(srec[1].data_value(), 0x0e00);
+        assert_eq!(srec[2].record_type, HexRecordType::EndOfFile);
+        // an S1 record after one above 64K goes back to base 0
+        let srec = HexRecordCollection::from_str_iter(["S205010000AA4F", "S1042000BB20", "S9030000FC"])?;
+        let types: Vec<_> = srec.iter().map(|r| (r.record_type, r.data_value())).collect();
+        use HexRecordType::{Data, ExLinAddr};
+        assert_eq!(types[..4], [(ExLinAddr, 1), (Data, 0xaa), (ExLinAddr, 0), (Data, 0xbb)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! Host services for programs running in the emulator.
+//!
+//! With --host-services a software interrupt (SWI2 unless another one is named) no longer
+//! goes through its vector. Instead the emulator performs the service whose code is in A and
+//! execution continues with the next instruction. Carry is clear if the service succeeded and
+//! set if it failed (the reason is shown as a warning).
+//! ```text
+//! A    service  arguments and results
+//! $01  PUTS     prints the NUL-terminated string at X ($0D is printed as a newline)
+//! $02  PUTC     prints the character in B
+//! $03  TIME     returns the seconds since 1970 in D (high word) and X, milliseconds in Y
+//! $04  EXIT     ends the emulation; B is the exit status of coco
+//! $05  WRITE    writes U bytes starting at Y to the file named by the string at X
+//! $06  FOPEN    opens the file named by the string at X for reading (B=0), writing (B=1, the
+//!               file is created or truncated) or appending (B=2); returns a handle in B
+//! $07  FREAD    reads up to U bytes from handle B to Y; returns the number read in U (0 at EOF)
+//! $08  FWRITE   writes U bytes starting at Y to handle B
+//! $09  FCLOSE   closes handle B
+//! ```
+//! Files are sandboxed in the --host-dir directory (the current directory by default): their
+//! names can't include a path. Up to 8 files can be open at once and they're all closed when
+//! the machine's power is cycled. Kiosk mode never writes files.
+use super::*;
+use instructions::Meta;
+use registers::CCBit;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const PUTS: u8 = 0x01;
+pub const PUTC: u8 = 0x02;
+pub const TIME: u8 = 0x03;
+pub const EXIT: u8 = 0x04;
+pub const WRITE: u8 = 0x05;
+pub const FOPEN: u8 = 0x06;
+pub const FREAD: u8 = 0x07;
+pub const FWRITE: u8 = 0x08;
+pub const FCLOSE: u8 = 0x09;
+
+/// the most files a program can have open at once
+const MAX_FILES: usize = 8;
+
+/// The software interrupt that requests host services
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Trap {
+    Swi,
+    Swi2,
+    Swi3,
+}
+
+#[derive(Debug)]
+pub struct HostServices {
+    trap: Trap,
+    dir: PathBuf,
+    /// open files (a handle is the index plus one)
+    files: [Option<File>; MAX_FILES],
+}
+impl HostServices {
+    pub fn new(trap: Trap, dir: PathBuf) -> Self {
+        info!("host services requested with {:?} (files in {})", trap, dir.display());
+        HostServices { trap, dir, files: Default::default() }
+    }
+    /// Creates the host services from the --host-services and --host-dir options
+    pub fn from_args() -> Option<Self> {
+        config::ARGS.host_services.map(|trap| HostServices::new(trap, config::ARGS.host_dir.clone()))
+    }
+    /// Returns true if the meta-instruction requests a host service
+    pub fn traps(&self, meta: &Meta) -> bool {
+        matches!(
+            (self.trap, meta),
+            (Trap::Swi, Meta::SWI) | (Trap::Swi2, Meta::SWI2) | (Trap::Swi3, Meta::SWI3)
+        )
+    }
+    /// Returns the path of a file in the host directory
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', ':']) {
+            return Err(general_err!("invalid file name \"{}\"", name));
+        }
+        Ok(self.dir.join(name))
+    }
+    /// Opens a file in the host directory and returns its handle
+    fn open(&mut self, name: &str, mode: u8) -> Result<u8, Error> {
+        let mut options = OpenOptions::new();
+        match mode {
+            0 => options.read(true),
+            1 | 2 if config::kiosk() => return Err(general_err!("files can't be written in kiosk mode")),
+            1 => options.write(true).create(true).truncate(true),
+            2 => options.append(true).create(true),
+            _ => return Err(general_err!("invalid mode {}", mode)),
+        };
+        let slot = self.files.iter().position(Option::is_none).ok_or_else(|| general_err!("too many open files"))?;
+        let path = self.path(name)?;
+        self.files[slot] = Some(options.open(&path)?);
+        verbose_println!("opened {} as handle {}", path.display(), slot + 1);
+        Ok(slot as u8 + 1)
+    }
+    /// Returns the open file with the given handle
+    fn file(&mut self, handle: u8) -> Result<&mut File, Error> {
+        (handle as usize)
+            .checked_sub(1)
+            .and_then(|i| self.files.get_mut(i))
+            .and_then(Option::as_mut)
+            .ok_or_else(|| general_err!("handle {} isn't open", handle))
+    }
+    /// Closes the file with the given handle
+    fn close(&mut self, handle: u8) -> Result<(), Error> {
+        self.file(handle)?;
+        self.files[handle as usize - 1] = None;
+        Ok(())
+    }
+    /// Closes every open file
+    pub fn close_all(&mut self) { self.files = Default::default(); }
+}
+
+impl Core {
+    /// Performs the host service requested by the program
+    pub fn host_call(&mut self) -> Result<(), Error> {
+        let pc = self.reg.pc;
+        let result = match self.reg.a {
+            PUTS => self.host_string(self.reg.x).map(|s| {
+                print!("{}", s.replace('\r', "\n"));
+                _ = io::stdout().flush();
+            }),
+            PUTC => {
+                let c = self.reg.b as char;
+                print!("{}", if c == '\r' { '\n' } else { c });
+                _ = io::stdout().flush();
+                Ok(())
+            }
+            TIME => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                let secs = now.as_secs() as u32;
+                self.reg.set_register(registers::Name::D, u8u16::u16((secs >> 16) as u16));
+                self.reg.x = secs as u16;
+                self.reg.y = now.subsec_millis() as u16;
+                Ok(())
+            }
+            EXIT => {
+                info!("program exited with status {} at PC={:04X}", self.reg.b, pc);
+                self.exit_status = Some(self.reg.b);
+                return Err(Error::new(ErrorKind::Exit, None, "program terminated by EXIT service"));
+            }
+            WRITE => self.host_write(),
+            FOPEN => self.host_open(),
+            FREAD => self.host_read(),
+            FWRITE => self.host_fwrite(),
+            FCLOSE => {
+                let handle = self.reg.b;
+                self.host_services().and_then(|h| h.close(handle))
+            }
+            code => Err(general_err!("unknown service ${:02X}", code)),
+        };
+        if let Err(e) = &result {
+            warn!("host service ${:02X} at {:04X} failed: {}", self.reg.a, pc, e);
+        }
+        self.reg.cc.set(CCBit::C, result.is_err());
+        Ok(())
+    }
+    /// Reads the NUL-terminated string at addr
+    fn host_string(&self, addr: u16) -> Result<String, Error> {
+        let mut bytes = Vec::new();
+        for offset in 0..=0xffffu16 {
+            match self._read_u8(memory::AccessType::Generic, addr.wrapping_add(offset), None)? {
+                0 => return Ok(String::from_utf8_lossy(&bytes).into_owned()),
+                b => bytes.push(b),
+            }
+        }
+        Err(general_err!("string at {:04X} isn't terminated", addr))
+    }
+    /// Reads the U bytes starting at Y
+    fn host_data(&self) -> Result<Vec<u8>, Error> {
+        let (start, len) = (self.reg.y, self.reg.u);
+        (0..len)
+            .map(|i| self._read_u8(memory::AccessType::Generic, start.wrapping_add(i), None))
+            .collect()
+    }
+    fn host_services(&mut self) -> Result<&mut HostServices, Error> {
+        self.host.as_mut().ok_or_else(|| general_err!("host services are disabled"))
+    }
+    fn host_write(&mut self) -> Result<(), Error> {
+        if config::kiosk() {
+            return Err(general_err!("files can't be written in kiosk mode"));
+        }
+        let name = self.host_string(self.reg.x)?;
+        let data = self.host_data()?;
+        let path = self.host_services()?.path(&name)?;
+        std::fs::write(&path, &data)?;
+        verbose_println!("wrote {} bytes to {}", data.len(), path.display());
+        Ok(())
+    }
+    fn host_open(&mut self) -> Result<(), Error> {
+        let name = self.host_string(self.reg.x)?;
+        let mode = self.reg.b;
+        self.reg.b = self.host_services()?.open(&name, mode)?;
+        Ok(())
+    }
+    fn host_read(&mut self) -> Result<(), Error> {
+        let mut data = vec![0u8; self.reg.u as usize];
+        let (handle, start) = (self.reg.b, self.reg.y);
+        let file = self.host_services()?.file(handle)?;
+        // keep reading until the buffer is full or the end of the file is reached
+        let mut len = 0;
+        while len < data.len() {
+            match file.read(&mut data[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        for (i, b) in data[..len].iter().enumerate() {
+            self._write_u8(memory::AccessType::Generic, start.wrapping_add(i as u16), *b)?;
+        }
+        self.reg.u = len as u16;
+        Ok(())
+    }
+    fn host_fwrite(&mut self) -> Result<(), Error> {
+        let data = self.host_data()?;
+        let handle = self.reg.b;
+        self.host_services()?.file(handle)?.write_all(&data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let host = HostServices::new(Trap::Swi2, PathBuf::from("out"));
+        assert_eq!(host.path("RESULT.TXT").unwrap(), PathBuf::from("out").join("RESULT.TXT"));
+        for name in ["", ".", "..", "../x", "/etc/passwd", "a\\b", "C:X"] {
+            assert!(host.path(name).is_err(), "{}", name);
+        }
+        assert!(host.traps(&Meta::SWI2) && !host.traps(&Meta::SWI) && !host.traps(&Meta::SWI3));
+    }
+
+    #[test]
+    fn files() {
+        let dir = std::env::temp_dir().join(format!("coco-host-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut host = HostServices::new(Trap::Swi2, dir.clone());
+        let out = host.open("DATA.BIN", 1).unwrap();
+        host.file(out).unwrap().write_all(b"6809").unwrap();
+        assert_eq!(host.open("DATA.BIN", 2).unwrap(), 2);
+        host.file(2).unwrap().write_all(b"!").unwrap();
+        host.close(out).unwrap();
+        host.close(2).unwrap();
+        assert!(host.close(out).is_err() && host.file(0).is_err() && host.file(9).is_err());
+        let input = host.open("DATA.BIN", 0).unwrap();
+        let mut text = String::new();
+        host.file(input).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "6809!");
+        assert!(host.open("MISSING", 0).is_err() && host.open("DATA.BIN", 3).is_err());
+        for _ in 1..MAX_FILES {
+            host.open("DATA.BIN", 0).unwrap();
+        }
+        assert!(host.open("DATA.BIN", 0).is_err());
+        host.close_all();
+        assert!(host.file(input).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! Importing machine state saved by other emulators.
+//!
+//! `state load` (and --state) also take XRoar snapshots (.sna): they're converted into one of
+//! coco's own snapshots (see snapshot.rs) and loaded the same way. An XRoar snapshot is
+//! "XRoar snapshot.\n\0" followed by chunks of an ID byte, a 2-byte (big-endian) length and the
+//! data. The chunks that are converted:
+//! ```text
+//! 0  register dump (older snapshots): CC, A, B, DP, X, Y, U, S and PC
+//! 1  RAM page 0: RAM from $0000 (up to 32K)
+//! 2  PIA registers: DDR, output register and control register of PIA0-A, PIA0-B, PIA1-A, PIA1-B
+//! 3  SAM register bits (the same as coco's SAM config)
+//! 4  6809 state: the registers as in chunk 0 (the interrupt lines and the rest after them are ignored)
+//! 7  RAM page 1: RAM from $8000
+//! ```
+//! Everything else (the machine's configuration, cartridges, disks, the 6309's extra registers)
+//! is skipped with a warning, so the snapshot should be loaded with the same ROMs, cartridge and
+//! disks it was saved with.
+//!
+//! VCC has no snapshot file to convert: it saves its configuration but not the machine's state.
+use super::*;
+
+pub const XROAR_MAGIC: &[u8] = b"XRoar snapshot.\n\0";
+
+/// Returns a coco snapshot converted from an XRoar snapshot and a warning for each part of it
+/// that was skipped
+pub fn xroar(bytes: &[u8]) -> Result<(Vec<u8>, Vec<String>), Error> {
+    let mut rest = bytes.strip_prefix(XROAR_MAGIC).ok_or_else(|| general_err!("not an XRoar snapshot"))?;
+    let (mut ram, mut warnings) = (vec![], vec![]);
+    let mut out = snapshot::MAGIC.to_vec();
+    while !rest.is_empty() {
+        let (Some(&id), Some(len)) = (rest.first(), rest.get(1..3)) else {
+            return Err(general_err!("XRoar snapshot is truncated"));
+        };
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        let data = rest.get(3..3 + len).ok_or_else(|| general_err!("XRoar snapshot is truncated"))?;
+        let too_short = |needed: usize| {
+            if data.len() < needed {
+                return Err(general_err!("XRoar snapshot chunk {} is too short", id));
+            }
+            Ok(())
+        };
+        match id {
+            1 | 7 => {
+                let start = if id == 1 { 0 } else { 0x8000 };
+                if ram.len() < start + data.len() {
+                    ram.resize(start + data.len(), 0);
+                }
+                ram[start..start + data.len()].copy_from_slice(data);
+            }
+            2 => {
+                too_short(12)?;
+                for (tag, pia) in [(b"PIA0", &data[..6]), (b"PIA1", &data[6..12])] {
+                    let mut state = Vec::new();
+                    for side in pia.chunks(3) {
+                        let (ddr, or, cr) = (side[0], side[1], side[2]);
+                        // the pins read back what's being output until something else drives them
+                        state.extend([cr, or, or, ddr, 0, or]);
+                    }
+                    snapshot::put_chunk(&mut out, tag, &state);
+                }
+            }
+            0 | 4 => {
+                too_short(14)?;
+                let mut core = vec![data[1], data[2]];
+                core.extend(&data[4..14]);
+                core.extend([data[3], data[0], 0]);
+                snapshot::put_chunk(&mut out, b"CORE", &core);
+            }
+            3 => {
+                too_short(2)?;
+                snapshot::put_chunk(&mut out, b"SAM ", &data[..2]);
+            }
+            _ => warnings.push(format!("skipped XRoar snapshot chunk {}", id)),
+        }
+        rest = &rest[3 + len..];
+    }
+    if !ram.is_empty() {
+        let mut chunk = ((ram.len() - 1) as u16).to_be_bytes().to_vec();
+        chunk.extend(rewind::pack(&ram));
+        snapshot::put_chunk(&mut out, b"RAM ", &chunk);
+    }
+    Ok((out, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pia::Pia;
+
+    /// A snapshot laid out as XRoar writes one for a 64K CoCo: its version and the machine's
+    /// configuration (which are skipped), the 6809's state, the PIAs, both pages of RAM and the SAM
+    #[test]
+    fn xroar_snapshot() {
+        let mut sna = XROAR_MAGIC.to_vec();
+        sna.extend([10, 0, 3, 1, 0, 8]);
+        sna.extend([8, 0, 4, 1, 2, 3, 4]);
+        // the registers and then the interrupt lines and the CPU's state
+        sna.extend([4, 0, 20, 0x50, 0x12, 0x34, 0x00, 0x11, 0x11, 0x22, 0x22, 0x33, 0x33, 0x7f, 0x00, 0x30, 0x00]);
+        sna.extend([0; 6]);
+        sna.extend([2, 0, 12, 0, 0, 0x34, 0xff, 0, 0x35, 0xf8, 0x08, 0x34, 0x00, 0, 0x37]);
+        let mut page0 = vec![0; 0x8000];
+        page0[..4].copy_from_slice(b"coco");
+        sna.extend([1, 0x80, 0].iter().chain(&page0));
+        sna.extend([7, 0x80, 0].iter().chain(&[0x39; 0x8000]));
+        sna.extend([3, 0, 2, 0x02, 0x09]);
+        let (snapshot, warnings) = xroar(&sna).unwrap();
+        assert_eq!(warnings, ["skipped XRoar snapshot chunk 10", "skipped XRoar snapshot chunk 8"]);
+        let mut core = Core::headless().unwrap();
+        assert!(core.restore(&snapshot).unwrap().iter().any(|w| w.contains("65536 bytes of RAM")));
+        assert_eq!((core.reg.get_register(registers::Name::D).u16(), core.reg.pc), (0x1234, 0x3000));
+        assert_eq!((core.reg.x, core.reg.s, core.reg.dp), (0x1111, 0x7f00, 0));
+        assert_eq!(&core.raw_ram[..4], b"coco");
+        assert_eq!(core.sam.lock().unwrap().get_raw_config(), 0x0209);
+        assert_eq!(core.pia1.lock().unwrap().state().to_bytes()[..4], [0x34, 0x08, 0x08, 0xf8]);
+        assert!(xroar(&sna[..sna.len() - 1]).is_err());
+    }
+}
@@ -21,6 +21,7 @@ pub fn opcode_to_flavor(op: u16) -> Option<&'static Flavor> {
     // SAFETY: FLAVOR_TABLE is a static mut that is initialized once by init()
     unsafe { instructions::FLAVOR_TABLE[ft_index(op)?].as_ref() }
 }
+#[allow(static_mut_refs)]
 pub fn name_to_descriptor(name: &str) -> Option<&'static Descriptor> {
     // SAFETY: DESC_BY_NAME is a static mut that is initialized once by init()
     unsafe { DESC_BY_NAME.as_ref()?.get(name).copied() }
@@ -254,7 +255,7 @@ impl Meta {
     pub fn from_opcode(i: u16) -> Option<Self> {
         match i {
             0x3c => Some(Meta::CWAI),
-            0x1111 => Some(Meta::EXIT),
+            0x1111 | 0x1112 => Some(Meta::EXIT),
             0x3f => Some(Meta::SWI),
             0x103f => Some(Meta::SWI2),
             0x113f => Some(Meta::SWI3),
@@ -288,28 +289,23 @@ pub struct Outcome {
     pub new_ctx: registers::Set,
     /// indicates if this is a meta-instruction (and what type)
     pub meta: Option<Meta>,
-    /// all the writes that result from this instruction
-    pub writes: Option<Vec<WriteRecord>>,
+    /// all the writes that result from this instruction (the Vec is recycled by Core::exec_next)
+    pub writes: Vec<WriteRecord>,
     /// helpful debug info string (address and 16 bit value at address)
     pub dbgstr: Option<String>,
 }
 impl Outcome {
-    pub fn new(inst: Instance, new_ctx: registers::Set) -> Outcome {
+    pub fn new(inst: Instance, new_ctx: registers::Set, writes: Vec<WriteRecord>) -> Outcome {
         Outcome {
             inst,
             new_ctx,
             meta: None,
-            writes: None,
+            writes,
             dbgstr: None,
         }
     }
 
-    pub fn write(&mut self, addr: u16, at: AccessType, val: u8u16) {
-        if self.writes.is_none() {
-            self.writes = Some(Vec::new());
-        }
-        self.writes.as_mut().unwrap().push(WriteRecord { addr, at, val });
-    }
+    pub fn write(&mut self, addr: u16, at: AccessType, val: u8u16) { self.writes.push(WriteRecord { addr, at, val }); }
 }
 pub fn is_high_byte_of_16bit_instruction(op: u8) -> bool { op == 0x10 || op == 0x11 }
 /// Information about a specific instance of an instruction in the context of a running program.
@@ -416,6 +412,8 @@ pub struct Descriptor {
     pub md: &'static [ModeDetail],
     /// the post-byte type expected for this instruction
     pub pbt: PBT,
+    /// how the instruction affects H, N, Z, V and C, as the datasheet shows it (see instructions.tbl)
+    pub flags: &'static str,
 }
 // Can't use default impl of Debug because it doesn't know what to do with EvalFn.
 // Also can't seem to implement Debug for EvalFn.
@@ -427,6 +425,7 @@ impl Debug for Descriptor {
             .field("ot", &self.ot)
             .field("md", &self.md)
             .field("pbt", &self.pbt)
+            .field("flags", &self.flags)
             .finish()
     }
 }
@@ -1067,148 +1066,7 @@ fn __meta(c: &Core, o: &mut Outcome) -> Result<(), Error> {
 
 use registers::Name;
 //
-// instruction table
+// instruction table (generated by build.rs from instructions.tbl)
 //
 #[rustfmt::skip]
-pub const DESCRIPTORS: &[Descriptor] = &[
- Descriptor{name:"ABX", 	eval:__abx,	reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x3A,clk:1,sz:1,am:4},]},
- Descriptor{name:"ADCA",	eval:__adc,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x89,clk:2,sz:2,am:0},M{op:0x99,clk:3,sz:2,am:1},M{op:0xA9,clk:4,sz:2,am:2},M{op:0xB9,clk:4,sz:3,am:3},]},
- Descriptor{name:"ADCB",	eval:__adc,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC9,clk:2,sz:2,am:0},M{op:0xD9,clk:3,sz:2,am:1},M{op:0xE9,clk:4,sz:2,am:2},M{op:0xF9,clk:4,sz:3,am:3},]},
- Descriptor{name:"ADDA",	eval:__add,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x8B,clk:2,sz:2,am:0},M{op:0x9B,clk:3,sz:2,am:1},M{op:0xAB,clk:4,sz:2,am:2},M{op:0xBB,clk:4,sz:3,am:3},]},
- Descriptor{name:"ADDB",	eval:__add,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xCB,clk:2,sz:2,am:0},M{op:0xDB,clk:3,sz:2,am:1},M{op:0xEB,clk:4,sz:2,am:2},M{op:0xFB,clk:4,sz:3,am:3},]},
- Descriptor{name:"ADDD",	eval:__add,	reg: Name::D, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC3,clk:3,sz:3,am:0},M{op:0xD3,clk:4,sz:2,am:1},M{op:0xE3,clk:5,sz:2,am:2},M{op:0xF3,clk:5,sz:3,am:3},]},
- Descriptor{name:"ANDA",	eval:__and,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x84,clk:2,sz:2,am:0},M{op:0x94,clk:3,sz:2,am:1},M{op:0xA4,clk:4,sz:2,am:2},M{op:0xB4,clk:4,sz:3,am:3},]},
- Descriptor{name:"ANDB",	eval:__and,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC4,clk:2,sz:2,am:0},M{op:0xD4,clk:3,sz:2,am:1},M{op:0xE4,clk:4,sz:2,am:2},M{op:0xF4,clk:4,sz:3,am:3},]},
- Descriptor{name:"ANDCC",	eval:__and, reg: Name::CC, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1C,clk:3,sz:2,am:0},]},
- Descriptor{name:"ASL",	    eval:__asl,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x08,clk:5,sz:2,am:1},M{op:0x68,clk:6,sz:2,am:2},M{op:0x78,clk:6,sz:3,am:3},]},
- Descriptor{name:"ASLA",	eval:__asl,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x48,clk:1,sz:1,am:4},]},
- Descriptor{name:"ASLB",	eval:__asl,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x58,clk:1,sz:1,am:4},]},
- Descriptor{name:"ASR",	    eval:__asr,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x07,clk:5,sz:2,am:1},M{op:0x67,clk:6,sz:2,am:2},M{op:0x77,clk:6,sz:3,am:3},]},
- Descriptor{name:"ASRA",	eval:__asr,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x47,clk:1,sz:1,am:4},]},
- Descriptor{name:"ASRB",	eval:__asr,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x57,clk:1,sz:1,am:4},]},
- Descriptor{name:"BEQ",	    eval:__beq,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x27,clk:3,sz:2,am:5},]},
- Descriptor{name:"BGE",	    eval:__bge,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x2C,clk:3,sz:2,am:5},]},
- Descriptor{name:"BGT",	    eval:__bgt,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x2E,clk:3,sz:2,am:5},]},
- Descriptor{name:"BCC",	    eval:__bcc,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x24,clk:3,sz:2,am:5},]},
- Descriptor{name:"BHI",	    eval:__bhi,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x22,clk:3,sz:2,am:5},]},
- Descriptor{name:"BHS",	    eval:__bhs,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x24,clk:3,sz:2,am:5},]},
- Descriptor{name:"BITA",	eval:__bit,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x85,clk:2,sz:2,am:0},M{op:0x95,clk:3,sz:2,am:1},M{op:0xA5,clk:4,sz:2,am:2},M{op:0xB5,clk:4,sz:3,am:3},]},
- Descriptor{name:"BITB",	eval:__bit,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC5,clk:2,sz:2,am:0},M{op:0xD5,clk:3,sz:2,am:1},M{op:0xE5,clk:4,sz:2,am:2},M{op:0xF5,clk:4,sz:3,am:3},]},
- Descriptor{name:"BLE",	    eval:__ble,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x2F,clk:3,sz:2,am:5},]},
- Descriptor{name:"BCS",	    eval:__err,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x25,clk:3,sz:2,am:5},]},
- Descriptor{name:"BLO",	    eval:__blo,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x25,clk:3,sz:2,am:5},]},
- Descriptor{name:"BLS",	    eval:__bls,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x23,clk:3,sz:2,am:5},]},
- Descriptor{name:"BLT",	    eval:__blt,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x2D,clk:3,sz:2,am:5},]},
- Descriptor{name:"BMI",	    eval:__bmi,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x2B,clk:3,sz:2,am:5},]},
- Descriptor{name:"BNE",	    eval:__bne,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x26,clk:3,sz:2,am:5},]},
- Descriptor{name:"BPL",	    eval:__bpl,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x2A,clk:3,sz:2,am:5},]},
- Descriptor{name:"BRA",	    eval:__bra,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x20,clk:3,sz:2,am:5},]},
- Descriptor{name:"BRN",	    eval:__nop,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x21,clk:3,sz:2,am:5},]},
- Descriptor{name:"BSR",	    eval:__bsr,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x8D,clk:6,sz:2,am:5},]},
- Descriptor{name:"BVC",	    eval:__bvc,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x28,clk:3,sz:2,am:5},]},
- Descriptor{name:"BVS",	    eval:__bvs,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x29,clk:3,sz:2,am:5},]},
- Descriptor{name:"CLR",	    eval:__clr,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x0F,clk:5,sz:2,am:1},M{op:0x6F,clk:6,sz:2,am:2},M{op:0x7F,clk:6,sz:3,am:3},]},
- Descriptor{name:"CLRA",	eval:__clr,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x4F,clk:1,sz:1,am:4},]},
- Descriptor{name:"CLRB",	eval:__clr,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x5F,clk:1,sz:1,am:4},]},
- Descriptor{name:"CMPA",	eval:__cmp,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x81,clk:2,sz:2,am:0},M{op:0x91,clk:3,sz:2,am:1},M{op:0xA1,clk:4,sz:2,am:2},M{op:0xB1,clk:4,sz:3,am:3},]},
- Descriptor{name:"CMPB",	eval:__cmp,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC1,clk:2,sz:2,am:0},M{op:0xD1,clk:3,sz:2,am:1},M{op:0xE1,clk:4,sz:2,am:2},M{op:0xF1,clk:4,sz:3,am:3},]},
- Descriptor{name:"CMPD",	eval:__cmp,	reg: Name::D, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1083,clk:4,sz:4,am:0},M{op:0x1093,clk:5,sz:3,am:1},M{op:0x10A3,clk:6,sz:3,am:2},M{op:0x10B3,clk:6,sz:4,am:3},]},
- Descriptor{name:"CMPS",	eval:__cmp,	reg: Name::S, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x118C,clk:4,sz:4,am:0},M{op:0x119C,clk:5,sz:3,am:1},M{op:0x11AC,clk:7,sz:3,am:2},M{op:0x11BC,clk:6,sz:4,am:3},]},
- Descriptor{name:"CMPU",	eval:__cmp,	reg: Name::U, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1183,clk:4,sz:4,am:0},M{op:0x1193,clk:5,sz:3,am:1},M{op:0x11A3,clk:6,sz:3,am:2},M{op:0x11B3,clk:6,sz:4,am:3},]},
- Descriptor{name:"CMPX",	eval:__cmp,	reg: Name::X, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x8C,clk:3,sz:3,am:0},M{op:0x9C,clk:4,sz:2,am:1},M{op:0xAC,clk:5,sz:2,am:2},M{op:0xBC,clk:5,sz:3,am:3},]},
- Descriptor{name:"CMPY",	eval:__cmp,	reg: Name::Y, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x108C,clk:4,sz:4,am:0},M{op:0x109C,clk:5,sz:3,am:1},M{op:0x10AC,clk:6,sz:3,am:2},M{op:0x10BC,clk:6,sz:4,am:3},]},
- Descriptor{name:"COM",	    eval:__com,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x03,clk:5,sz:2,am:1},M{op:0x63,clk:6,sz:2,am:2},M{op:0x73,clk:6,sz:3,am:3},]},
- Descriptor{name:"COMA",	eval:__com,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x43,clk:1,sz:1,am:4},]},
- Descriptor{name:"COMB",	eval:__com,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x53,clk:1,sz:1,am:4},]},
- Descriptor{name:"CWAI",	eval:__meta,reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x3C,clk:20,sz:2,am:0},]},
- Descriptor{name:"DAA",	    eval:__daa,	reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x19,clk:1,sz:1,am:4},]},
- Descriptor{name:"DEC",	    eval:__dec,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x0A,clk:5,sz:2,am:1},M{op:0x6A,clk:6,sz:2,am:2},M{op:0x7A,clk:6,sz:3,am:3},]},
- Descriptor{name:"DECA",	eval:__dec,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x4A,clk:1,sz:1,am:4},]},
- Descriptor{name:"DECB",	eval:__dec,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x5A,clk:1,sz:1,am:4},]},
- Descriptor{name:"EORA",	eval:__xor,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x88,clk:2,sz:2,am:0},M{op:0x98,clk:3,sz:2,am:1},M{op:0xA8,clk:4,sz:2,am:2},M{op:0xB8,clk:4,sz:3,am:3},]},
- Descriptor{name:"EORB",	eval:__xor,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC8,clk:2,sz:2,am:0},M{op:0xD8,clk:3,sz:2,am:1},M{op:0xE8,clk:4,sz:2,am:2},M{op:0xF8,clk:4,sz:3,am:3},]},
- Descriptor{name:"EXG",	    eval:__exg,	reg: Name::Z, pbt: PBT::TransferExchange,  ot:OT::Exch,md:&[M{op:0x1E,clk:5,sz:2,am:0},]},
- Descriptor{name:"EXIT",	eval:__meta,reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x1111,clk:99,sz:2,am:4},]},
- Descriptor{name:"INC",	    eval:__inc,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x0C,clk:5,sz:2,am:1},M{op:0x6C,clk:6,sz:2,am:2},M{op:0x7C,clk:6,sz:3,am:3},]},
- Descriptor{name:"INCA",	eval:__inc,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x4C,clk:1,sz:1,am:4},]},
- Descriptor{name:"INCB",	eval:__inc,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x5C,clk:1,sz:1,am:4},]},
- Descriptor{name:"JMP",	    eval:__jmp,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x0E,clk:2,sz:2,am:1},M{op:0x6E,clk:3,sz:2,am:2},M{op:0x7E,clk:3,sz:3,am:3},]},
- Descriptor{name:"JSR",	    eval:__jsr,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x9D,clk:6,sz:2,am:1},M{op:0xAD,clk:6,sz:2,am:2},M{op:0xBD,clk:7,sz:3,am:3},]},
- Descriptor{name:"LBCS",	eval:__bcs,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1025,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBLO",	eval:__bcs,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1025,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBEQ",	eval:__beq,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1027,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBGE",	eval:__bge,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x102C,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBGT",	eval:__bgt,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x102E,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBHI",	eval:__bhi,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1022,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBCC",	eval:__bhs,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1024,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBHS",	eval:__bhs,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1024,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBLE",	eval:__ble,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x102F,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBLS",	eval:__bls,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1023,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBLT",	eval:__blt,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x102D,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBMI",	eval:__bmi,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x102B,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBNE",	eval:__bne,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1026,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBPL",	eval:__bpl,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x102A,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBRA",	eval:__bra,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x16,clk:4,sz:3,am:5},]},
- Descriptor{name:"LBRN",	eval:__nop,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1021,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBSR",	eval:__bsr,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x17,clk:7,sz:3,am:5},]},
- Descriptor{name:"LBVC",	eval:__bvc,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1028,clk:5,sz:4,am:5},]},
- Descriptor{name:"LBVS",	eval:__bvs,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1029,clk:5,sz:4,am:5},]},
- Descriptor{name:"LDA",	    eval:__ld,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x86,clk:2,sz:2,am:0},M{op:0x96,clk:3,sz:2,am:1},M{op:0xA6,clk:4,sz:2,am:2},M{op:0xB6,clk:4,sz:3,am:3},]},
- Descriptor{name:"LDB",	    eval:__ld,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC6,clk:2,sz:2,am:0},M{op:0xD6,clk:3,sz:2,am:1},M{op:0xE6,clk:4,sz:2,am:2},M{op:0xF6,clk:4,sz:3,am:3},]},
- Descriptor{name:"LDD",	    eval:__ld,	reg: Name::D, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xCC,clk:3,sz:3,am:0},M{op:0xDC,clk:4,sz:2,am:1},M{op:0xEC,clk:5,sz:2,am:2},M{op:0xFC,clk:5,sz:3,am:3},]},
- Descriptor{name:"LDS",	    eval:__ld,	reg: Name::S, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x10CE,clk:4,sz:4,am:0},M{op:0x10DE,clk:5,sz:3,am:1},M{op:0x10EE,clk:6,sz:3,am:2},M{op:0x10FE,clk:6,sz:4,am:3},]},
- Descriptor{name:"LDU",	    eval:__ld,	reg: Name::U, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xCE,clk:3,sz:3,am:0},M{op:0xDE,clk:4,sz:2,am:1},M{op:0xEE,clk:5,sz:2,am:2},M{op:0xFE,clk:5,sz:3,am:3},]},
- Descriptor{name:"LDX",	    eval:__ld,	reg: Name::X, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x8E,clk:3,sz:3,am:0},M{op:0x9E,clk:4,sz:2,am:1},M{op:0xAE,clk:5,sz:2,am:2},M{op:0xBE,clk:5,sz:3,am:3},]},
- Descriptor{name:"LDY",	    eval:__ld,	reg: Name::Y, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x108E,clk:4,sz:4,am:0},M{op:0x109E,clk:5,sz:3,am:1},M{op:0x10AE,clk:6,sz:3,am:2},M{op:0x10BE,clk:6,sz:4,am:3},]},
- Descriptor{name:"LEAS",	eval:__lea,	reg: Name::S, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x32,clk:4,sz:2,am:2},]},
- Descriptor{name:"LEAU",	eval:__lea,	reg: Name::U, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x33,clk:4,sz:2,am:2},]},
- Descriptor{name:"LEAX",	eval:__lea,	reg: Name::X, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x30,clk:4,sz:2,am:2},]},
- Descriptor{name:"LEAY",	eval:__lea,	reg: Name::Y, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x31,clk:4,sz:2,am:2},]},
- Descriptor{name:"LSL",	    eval:__asl,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x08,clk:5,sz:2,am:1},M{op:0x68,clk:6,sz:2,am:2},M{op:0x78,clk:6,sz:3,am:3},]},
- Descriptor{name:"LSLA",	eval:__asl,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x48,clk:1,sz:1,am:4},]},
- Descriptor{name:"LSLB",	eval:__asl,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x58,clk:1,sz:1,am:4},]},
- Descriptor{name:"LSR",	    eval:__lsr,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x04,clk:5,sz:2,am:1},M{op:0x64,clk:6,sz:2,am:2},M{op:0x74,clk:6,sz:3,am:3},]},
- Descriptor{name:"LSRA",	eval:__lsr,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x44,clk:1,sz:1,am:4},]},
- Descriptor{name:"LSRB",	eval:__lsr,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x54,clk:1,sz:1,am:4},]},
- Descriptor{name:"MUL",	    eval:__mul,	reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x3D,clk:10,sz:1,am:4},]},
- Descriptor{name:"NEG",	    eval:__neg,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x00,clk:5,sz:2,am:1},M{op:0x60,clk:6,sz:2,am:2},M{op:0x70,clk:6,sz:3,am:3},]},
- Descriptor{name:"NEGA",	eval:__neg,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x40,clk:1,sz:1,am:4},]},
- Descriptor{name:"NEGB",	eval:__neg,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x50,clk:1,sz:1,am:4},]},
- Descriptor{name:"NOP",	    eval:__nop,	reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x12,clk:1,sz:1,am:4},]},
- Descriptor{name:"ORA",	    eval:__or,  reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x8A,clk:2,sz:2,am:0},M{op:0x9A,clk:3,sz:2,am:1},M{op:0xAA,clk:4,sz:2,am:2},M{op:0xBA,clk:4,sz:3,am:3},]},
- Descriptor{name:"ORB",	    eval:__or,  reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xCA,clk:2,sz:2,am:0},M{op:0xDA,clk:3,sz:2,am:1},M{op:0xEA,clk:4,sz:2,am:2},M{op:0xFA,clk:4,sz:3,am:3},]},
- Descriptor{name:"ORCC",	eval:__or,  reg: Name::CC, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x1A,clk:2,sz:2,am:0},]},
- Descriptor{name:"PSHS",	eval:__psh,	reg: Name::S, pbt: PBT::PushPull,  ot:OT::Push,md:&[M{op:0x34,clk:4,sz:2,am:0},]},
- Descriptor{name:"PSHU",	eval:__psh,	reg: Name::U, pbt: PBT::PushPull,  ot:OT::Push,md:&[M{op:0x36,clk:4,sz:2,am:0},]},
- Descriptor{name:"PULS",	eval:__pul,	reg: Name::S, pbt: PBT::PushPull,  ot:OT::Push,md:&[M{op:0x35,clk:4,sz:2,am:0},]},
- Descriptor{name:"PULU",	eval:__pul,	reg: Name::U, pbt: PBT::PushPull,  ot:OT::Push,md:&[M{op:0x37,clk:4,sz:2,am:0},]},
- Descriptor{name:"ROL",	    eval:__rol,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x09,clk:5,sz:2,am:1},M{op:0x69,clk:6,sz:2,am:2},M{op:0x79,clk:6,sz:3,am:3},]},
- Descriptor{name:"ROLA",	eval:__rol,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x49,clk:1,sz:1,am:4},]},
- Descriptor{name:"ROLB",	eval:__rol,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x59,clk:1,sz:1,am:4},]},
- Descriptor{name:"ROR",	    eval:__ror,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x06,clk:5,sz:2,am:1},M{op:0x66,clk:6,sz:2,am:2},M{op:0x76,clk:6,sz:3,am:3},]},
- Descriptor{name:"RORA",	eval:__ror,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x46,clk:1,sz:1,am:4},]},
- Descriptor{name:"RORB",	eval:__ror,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x56,clk:1,sz:1,am:4},]},
- Descriptor{name:"RTI",	    eval:__rti,	reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x3B,clk:6,sz:1,am:4},]},
- Descriptor{name:"RTS",	    eval:__rts,	reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x39,clk:1,sz:1,am:4},]},
- Descriptor{name:"SBCA",	eval:__sbc,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x82,clk:2,sz:2,am:0},M{op:0x92,clk:3,sz:2,am:1},M{op:0xA2,clk:4,sz:2,am:2},M{op:0xB2,clk:4,sz:3,am:3},]},
- Descriptor{name:"SBCB",	eval:__sbc,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC2,clk:2,sz:2,am:0},M{op:0xD2,clk:3,sz:2,am:1},M{op:0xE2,clk:4,sz:2,am:2},M{op:0xF2,clk:4,sz:3,am:3},]},
- Descriptor{name:"SEX",	    eval:__sex,	reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x1D,clk:1,sz:1,am:4},]},
- Descriptor{name:"STA",	    eval:__st,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x97,clk:3,sz:2,am:1},M{op:0xA7,clk:4,sz:2,am:2},M{op:0xB7,clk:4,sz:3,am:3},]},
- Descriptor{name:"STB",	    eval:__st,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xD7,clk:3,sz:2,am:1},M{op:0xE7,clk:4,sz:2,am:2},M{op:0xF7,clk:4,sz:3,am:3},]},
- Descriptor{name:"STD",	    eval:__st,	reg: Name::D, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xDD,clk:4,sz:2,am:1},M{op:0xED,clk:5,sz:2,am:2},M{op:0xFD,clk:5,sz:3,am:3},]},
- Descriptor{name:"STS",	    eval:__st,	reg: Name::S, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x10DF,clk:5,sz:3,am:1},M{op:0x10EF,clk:6,sz:3,am:2},M{op:0x10FF,clk:6,sz:4,am:3},]},
- Descriptor{name:"STU",	    eval:__st,	reg: Name::U, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xDF,clk:4,sz:2,am:1},M{op:0xEF,clk:5,sz:2,am:2},M{op:0xFF,clk:5,sz:3,am:3},]},
- Descriptor{name:"STX",	    eval:__st,	reg: Name::X, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x9F,clk:4,sz:2,am:1},M{op:0xAF,clk:5,sz:2,am:2},M{op:0xBF,clk:5,sz:3,am:3},]},
- Descriptor{name:"STY",	    eval:__st,	reg: Name::Y, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x109F,clk:5,sz:3,am:1},M{op:0x10AF,clk:6,sz:3,am:2},M{op:0x10BF,clk:6,sz:4,am:3},]},
- Descriptor{name:"SUBA",	eval:__sub,	reg: Name::A, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x80,clk:2,sz:2,am:0},M{op:0x90,clk:3,sz:2,am:1},M{op:0xA0,clk:4,sz:2,am:2},M{op:0xB0,clk:4,sz:3,am:3},]},
- Descriptor{name:"SUBB",	eval:__sub,	reg: Name::B, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0xC0,clk:2,sz:2,am:0},M{op:0xD0,clk:3,sz:2,am:1},M{op:0xE0,clk:4,sz:2,am:2},M{op:0xF0,clk:4,sz:3,am:3},]},
- Descriptor{name:"SUBD",	eval:__sub,	reg: Name::D, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x83,clk:3,sz:3,am:0},M{op:0x93,clk:4,sz:2,am:1},M{op:0xA3,clk:5,sz:2,am:2},M{op:0xB3,clk:5,sz:3,am:3},]},
- Descriptor{name:"SWI",	    eval:__meta,reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x3F,clk:19,sz:1,am:4},]},
- Descriptor{name:"SWI2",	eval:__meta,reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x103F,clk:20,sz:2,am:4},]},
- Descriptor{name:"SWI3",	eval:__meta,reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x113F,clk:20,sz:2,am:4},]},
- Descriptor{name:"SYNC",	eval:__meta,reg: Name::Z, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x13,clk:1,sz:1,am:4},]},
- Descriptor{name:"TFR",	    eval:__tfr,	reg: Name::Z, pbt: PBT::TransferExchange,  ot:OT::Exch,md:&[M{op:0x1F,clk:4,sz:2,am:0},]},
- Descriptor{name:"TST",	    eval:__tst,	reg: Name::Z, pbt: PBT::NA,  ot:OT::Mode,md:&[M{op:0x0D,clk:4,sz:2,am:1},M{op:0x6D,clk:5,sz:2,am:2},M{op:0x7D,clk:5,sz:3,am:3},]},
- Descriptor{name:"TSTA",	eval:__tst,	reg: Name::A, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x4D,clk:1,sz:1,am:4},]},
- Descriptor{name:"TSTB",	eval:__tst,	reg: Name::B, pbt: PBT::NA,  ot:OT::None,md:&[M{op:0x5D,clk:1,sz:1,am:4},]},
-];
+include!(concat!(env!("OUT_DIR"), "/descriptors.rs"));
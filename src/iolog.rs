@@ -0,0 +1,96 @@
+//! A log of the most recent I/O accesses.
+//!
+//! With --io-log N the last N reads and writes of I/O space (the PIAs, SAM and any devices such
+//! as the ACIA, Becker port or RAM disk) are kept in a ring buffer along with the value, the
+//! clock cycle and the PC of the instruction that made the access. The debugger's `iolog`
+//! command dumps it, which helps when working out what a driver did to a device. When the log
+//! is disabled the only cost is checking for it.
+use super::*;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoAccess {
+    pub addr: u16,
+    pub val: u8,
+    pub write: bool,
+    pub cycle: u64,
+    pub pc: u16,
+}
+impl fmt::Display for IoAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>12} PC={:04X} {} {:04X} {} {:02X}",
+            self.cycle,
+            self.pc,
+            if self.write { "W" } else { "R" },
+            self.addr,
+            if self.write { "<-" } else { "->" },
+            self.val
+        )
+    }
+}
+
+pub struct IoLog {
+    ring: VecDeque<IoAccess>,
+    capacity: usize,
+}
+impl IoLog {
+    pub fn new(capacity: usize) -> Self {
+        IoLog {
+            ring: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+    pub fn record(&mut self, access: IoAccess) {
+        if self.ring.len() == self.capacity {
+            self.ring.pop_front();
+        }
+        self.ring.push_back(access);
+    }
+    pub fn clear(&mut self) { self.ring.clear() }
+    /// Returns the last count accesses (oldest first)
+    pub fn last(&self, count: usize) -> impl Iterator<Item = &IoAccess> {
+        self.ring.iter().skip(self.ring.len().saturating_sub(count))
+    }
+}
+
+impl Core {
+    /// Returns true if addr belongs to a device rather than to RAM or ROM
+    pub fn is_io_address(&self, addr: u16) -> bool {
+        (0xff00..0xffe0).contains(&addr)
+            || self.acia.as_ref().is_some_and(|a| a.owns_address(addr))
+            || self.becker.as_ref().is_some_and(|b| b.owns_address(addr))
+            || self.cartridges().any(|c| c.owns_address(addr))
+    }
+    /// Adds an access to the I/O log (if it's enabled and addr is in I/O space)
+    #[inline(always)]
+    pub fn log_io(&self, addr: u16, val: u8, write: bool) {
+        if let Some(log) = self.io_log.as_ref() {
+            if self.is_io_address(addr) {
+                log.borrow_mut().record(IoAccess {
+                    addr,
+                    val,
+                    write,
+                    cycle: self.clock_cycles,
+                    pc: self.reg.pc,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let mut log = IoLog::new(3);
+        for i in 0..5u16 {
+            log.record(IoAccess { addr: 0xff00 + i, val: i as u8, write: i % 2 == 1, cycle: i as u64, pc: 0x3f00 });
+        }
+        let addrs: Vec<u16> = log.last(10).map(|a| a.addr).collect();
+        assert_eq!(addrs, vec![0xff02, 0xff03, 0xff04]);
+        assert_eq!(log.last(1).next().unwrap().to_string(), "           4 PC=3F00 R FF04 -> 04");
+    }
+}
@@ -0,0 +1,106 @@
+//! Interrupt latency (the debugger's latency command).
+//!
+//! For IRQ and FIRQ this keeps the cycles from the interrupt being asserted to the first
+//! instruction of its handler, for the last 1000 interrupts taken. Interrupt sources are sampled
+//! at hsync, so an interrupt is asserted at the cycle of the hsync where it's first seen and
+//! stays asserted until it's taken or its source lets go.
+//! The latency is then how far the CPU had got into an instruction plus however long the
+//! interrupt was masked, which is what a handler with a timing budget has to allow for.
+use std::collections::VecDeque;
+
+/// how many latencies are kept for each line
+const KEEP: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    Irq,
+    Firq,
+}
+const NAMES: [&str; 2] = ["IRQ", "FIRQ"];
+
+#[derive(Default)]
+pub struct Latency {
+    asserted: [Option<u64>; 2],  // when each line was asserted (while it's waiting to be taken)
+    samples: [VecDeque<u64>; 2], // the latencies of the interrupts taken, oldest first
+}
+impl Latency {
+    /// Notes whether a line is asserted at an hsync (at the given cycle)
+    pub fn sample(&mut self, line: Line, asserted: bool, cycle: u64) {
+        let pending = &mut self.asserted[line as usize];
+        if !asserted {
+            *pending = None;
+        } else if pending.is_none() {
+            *pending = Some(cycle);
+        }
+    }
+    /// Records the latency of an interrupt whose handler starts at the given cycle
+    pub fn taken(&mut self, line: Line, cycle: u64) {
+        if let Some(at) = self.asserted[line as usize].take() {
+            let samples = &mut self.samples[line as usize];
+            if samples.len() == KEEP {
+                samples.pop_front();
+            }
+            samples.push_back(cycle.saturating_sub(at));
+        }
+    }
+    pub fn clear(&mut self) { self.samples.iter_mut().for_each(VecDeque::clear) }
+    /// Describes the latencies of the last n interrupts on each line
+    pub fn summary(&self, n: usize) -> Vec<String> {
+        NAMES
+            .iter()
+            .zip(&self.samples)
+            .map(|(name, samples)| {
+                let last: Vec<u64> = samples.iter().rev().take(n).copied().collect();
+                match (last.iter().min(), last.iter().max()) {
+                    (Some(min), Some(max)) => {
+                        let avg = last.iter().sum::<u64>() as f64 / last.len() as f64;
+                        format!("{:<4} last {}: min {}, avg {:.1}, max {} cycles", name, last.len(), min, avg, max)
+                    }
+                    _ => format!("{:<4} none taken", name),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Core;
+    use crate::pia::Pia;
+
+    #[test]
+    fn latencies() {
+        let mut latency = Latency::default();
+        // masked for two hsyncs, then taken 3 cycles into the third
+        latency.sample(Line::Irq, true, 100);
+        latency.sample(Line::Irq, true, 157);
+        latency.sample(Line::Irq, true, 214);
+        latency.taken(Line::Irq, 217);
+        // let go of before it was taken
+        latency.sample(Line::Irq, true, 271);
+        latency.sample(Line::Irq, false, 328);
+        latency.taken(Line::Irq, 330);
+        latency.sample(Line::Irq, true, 385);
+        latency.taken(Line::Irq, 390);
+        assert_eq!(latency.summary(10)[0], "IRQ  last 2: min 5, avg 61.0, max 117 cycles");
+        assert_eq!(latency.summary(1)[0], "IRQ  last 1: min 5, avg 5.0, max 5 cycles");
+        assert_eq!(latency.summary(10)[1], "FIRQ none taken");
+
+        // hsync IRQs taken by a loop of NOPs are only late by (at most) the instruction they interrupt
+        let mut core = Core::headless().unwrap();
+        core.lock_pia0().write(1, 0x01);
+        // $3000: andcc #$ef / nop / bra *-1; the handler is rti
+        core.raw_ram[0x3000..0x3005].copy_from_slice(&[0x1c, 0xef, 0x12, 0x20, 0xfd]);
+        core.raw_ram[0x3100] = 0x3b;
+        core.raw_ram[0xbff8..0xbffa].copy_from_slice(&[0x31, 0x00]);
+        (core.reg.pc, core.reg.s) = (0x3000, 0x7000);
+        core.warp = true;
+        while core.clock_cycles < 10_000 {
+            core.exec_one().unwrap();
+        }
+        let samples = &core.latency.samples[Line::Irq as usize];
+        assert!(samples.len() > 100);
+        assert!(samples.iter().all(|l| *l <= 3), "{:?}", samples);
+    }
+}
@@ -0,0 +1,380 @@
+//! # A TRS-80 Color Computer simulator
+//!
+//! The emulator is built as a library that main.rs runs (see run). Besides the emulator, the
+//! library gives the fuzz targets in fuzz/ the loaders, the assembler and the instruction decoder
+//! (through the entry points in fuzz.rs) without going through run() or opening a window. The
+//! assembler and the programs it builds (segments, symbols and the listing) are public for other
+//! tools, and so is Core, whose screen methods (screen_text, screen_shows and wait_for_text in
+//! screen.rs) let a program built on the library run the machine until it shows some text.
+#[macro_use]
+mod macros;
+#[macro_use]
+mod term;
+mod acia;
+pub mod assembler;
+mod autotype;
+mod basic;
+mod becker;
+mod bench;
+mod cart;
+mod cassette;
+mod charmap;
+mod cheat;
+mod compare;
+#[cfg(test)]
+mod audio_test;
+mod config;
+#[cfg(test)]
+mod console;
+mod convert;
+mod core;
+mod coverage;
+mod debug;
+mod devmgr;
+mod disk;
+mod diskctl;
+mod error;
+mod expr;
+pub mod fuzz;
+mod hang;
+mod hex;
+mod host;
+mod import;
+mod instructions;
+mod iolog;
+mod latency;
+mod loadmap;
+mod lockstats;
+mod lockstep;
+mod lsp;
+mod machine;
+mod memory;
+mod messages;
+mod midi;
+mod misuse;
+mod monitor;
+mod obj;
+mod pager;
+mod parse;
+mod patch;
+mod pia;
+mod printer;
+pub mod program;
+mod ramdisk;
+mod recent;
+mod region;
+mod registers;
+mod rewind;
+mod rng;
+mod romdb;
+mod rsdos;
+mod runtime;
+mod sam;
+mod screen;
+mod snapshot;
+mod sound;
+mod spy;
+mod stack;
+mod tablet;
+mod tape;
+mod test;
+mod timer;
+mod title;
+mod tracefile;
+mod u8oru16;
+mod until;
+mod vdg;
+mod warp;
+mod watchdog;
+mod wordpak;
+use crate::assembler::Assembler;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::result::Result;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::*;
+use std::sync::Arc;
+use std::time::Instant;
+use std::{fmt, io, thread};
+pub(crate) use u8oru16::u8u16;
+pub(crate) use {devmgr::*, error::*, program::*};
+pub use crate::core::Core;
+
+pub use error::Error;
+
+/// Runs one of the tools that don't need the emulator (see config::Utility)
+fn run_utility(utility: &config::Utility) -> Result<(), Error> {
+    match utility {
+        config::Utility::Tapeconv { input, output, rate, threshold, hysteresis } => {
+            tape::convert(input, output, *rate, *threshold, *hysteresis)
+        }
+        config::Utility::Convert { input, output, from, to, org, exec } => {
+            convert::convert(input, output, (*from, *to), *org, *exec)
+        }
+        config::Utility::Dsk { command } => match command {
+            config::DskCommand::Ls { image } => rsdos::ls(image),
+            config::DskCommand::Get { image, name, output } => rsdos::get(image, name, output.as_deref()),
+            config::DskCommand::Put { image, file, name } => rsdos::put(image, file, name.as_deref()),
+        },
+        config::Utility::Lsp => lsp::run(),
+    }
+}
+
+/// Runs the emulator (or one of its tools) as the command line asks; this is all main.rs does
+pub fn run() {
+    config::init();
+    term::init();
+    if let Some(utility) = config::ARGS.utility.as_ref() {
+        if let Err(e) = run_utility(utility) {
+            println!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(which) = config::ARGS.bench_opcode.as_deref() {
+        if let Err(e) = bench::run(which) {
+            println!("BENCHMARK ERROR: {}", e);
+        }
+        return;
+    }
+    if let Some(path) = config::ARGS.dump_machine_info.as_deref() {
+        if let Err(e) = machine::dump(Some(Path::new(path)).filter(|_| !path.is_empty())) {
+            println!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(count) = config::ARGS.lockstep {
+        if let Err(e) = lockstep::run(count, load) {
+            println!("LOCKSTEP ERROR: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // The device manager has to live on the main thread
+    // because it opens a window via minifb (must be done on main thread on some OS's)
+    // but SAM, PIA and VDG are all accessed from another thread (the "core" thread)
+    // Ideally, this would be the other way around (main thread == core thread and window on another thread).
+    let mut dm = DeviceManager::new();
+    if config::ARGS.typed_symbols {
+        dm.type_symbols();
+    }
+    dm.frame_skip(config::ARGS.accuracy.frame_skip());
+    dm.key_repeat(config::ARGS.key_repeat, config::ARGS.key_repeat_delay, config::ARGS.key_repeat_rate);
+    if !config::kiosk() {
+        dm.on_screenshot(Box::new(save_screenshot));
+        dm.allow_mouse_capture(config::ARGS.mouse_sensitivity);
+    }
+    // Get threadsafe clones of peripherals for use on the "core" thread.
+    let ram = dm.get_ram();
+    let vdg = dm.get_vdg();
+    let pia0 = dm.get_pia0();
+    let pia1 = dm.get_pia1();
+    let sam = dm.get_sam();
+    // the WordPak's CRTC and video RAM are shared by the card (on the core thread) and its display
+    let wordpak = config::ARGS.wordpak.map(|output| {
+        let crtc = wordpak::Crtc::shared();
+        let display = Box::new(wordpak::Display::new(crtc.clone(), output));
+        match output {
+            wordpak::Output::Window => dm.add_screen_window("Rusty CoCo WordPak", display),
+            wordpak::Output::Main => dm.set_main_screen(display),
+        }
+        crtc
+    });
+    // with --watchdog both threads beat a heartbeat that the watchdog keeps an eye on
+    let heartbeats = config::ARGS.watchdog.map(|limit| {
+        let (core, ui) = (watchdog::Heartbeat::new("core"), watchdog::Heartbeat::new("UI"));
+        let locks = watchdog::Locks { pia0: pia0.clone(), pia1: pia1.clone(), sam: sam.clone(), vdg: vdg.clone() };
+        watchdog::start(limit, core.clone(), ui.clone(), locks);
+        (core, ui)
+    });
+    let core_heartbeat = heartbeats.as_ref().map(|(core, _)| core.clone());
+    let title_status = dm.get_title_status();
+    let simulation_complete = Arc::new(AtomicBool::new(false));
+    let complete = simulation_complete.clone();
+    let quit = Arc::new(AtomicBool::new(false));
+    let core_quit = quit.clone();
+    // the simulated computer runs on a separate thread (aka "core" thread)
+    let core_thread = thread::spawn(move || {
+        let acia_addr = if !config::ARGS.acia_enable {
+            None
+        } else {
+            Some(config::ARGS.acia_addr)
+        };
+        //  create a CPU simulator
+        let result = Core::new(ram, sam, vdg, pia0, pia1, config::ARGS.ram_top, acia_addr).and_then(|mut core| {
+            if let Some(crtc) = wordpak {
+                core.paks.push(Box::new(wordpak::WordPak::new(config::ARGS.wordpak_addr, crtc)));
+            }
+            core.heartbeat = core_heartbeat;
+            core.quit = Some(core_quit);
+            core.title = Some(title::Title::new(title_status));
+            compute_thread(&mut core).map(|()| core.exit_status.unwrap_or(0))
+        });
+        let status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                println!("SIMULATOR ERROR: {}", e);
+                1
+            }
+        };
+        complete.store(true, Release);
+        status
+    });
+    while dm.is_running() && !simulation_complete.load(Acquire) {
+        dm.update();
+        if let Some((_, ui)) = heartbeats.as_ref() {
+            ui.beat();
+        }
+    }
+    if config::ARGS.perf {
+        info!("Display: {}", dm.frame_pacer().report());
+    }
+    // the core thread has to finish up (resolving disk changes, saving files, etc.) before coco exits
+    quit.store(true, Release);
+    drop(dm);
+    // the program's exit status becomes coco's (so that scripts can tell whether a test program passed)
+    match core_thread.join() {
+        Ok(0) => {}
+        Ok(status) => std::process::exit(status as i32),
+        Err(_) => std::process::exit(1),
+    }
+}
+/// Saves the window's picture (F8) as the first of screenshot-1.ppm, screenshot-2.ppm, ... that
+/// doesn't exist yet
+fn save_screenshot(pixels: &[u32], width: usize, height: usize) {
+    let path = (1..).map(|n| std::path::PathBuf::from(format!("screenshot-{}.ppm", n))).find(|p| !p.exists()).unwrap();
+    match std::fs::write(&path, vdg::ppm(pixels, width, height)) {
+        Ok(()) => info!("{}", tr!("saved a screenshot to {}", path.display())),
+        Err(e) => warn!("failed to save a screenshot to {}: {}", path.display(), e),
+    }
+}
+/// The emulator's CPU runs on this thread.
+/// Load up everything the user has requested (see load) and then start the CPU running.
+fn compute_thread(core: &mut Core) -> Result<(), Error> {
+    load(core)?;
+    info!("{}", tr!("Press <ctrl-c> to exit."));
+    if !config::kiosk() {
+        let keys = "Keys: F12 reset, shift+F12 power cycle, F11 cartridge, F10 warp, F9 rewind, F8 screenshot, \
+                    F7 mouse capture";
+        info!("{}", tr!(keys));
+    }
+    // put the simulator in a clean reset state and start running
+    *core.disks.borrow_mut() = disk::Drives::from_args()?;
+    core.cassette = cassette::Cassette::from_args()?.map(std::cell::RefCell::new);
+    core.autotype = autotype::AutoType::from_args()?;
+    core.basic_watch = basic::Watch::from_args();
+    recent::remember();
+    if let Some(fixtures) = config::ARGS.config_file.as_ref().and_then(|c| c.disk_sectors.as_ref()) {
+        core.disks.borrow_mut().load_fixtures(fixtures)?;
+    }
+    // name the routines of any ROM we recognize (and log calls to them)
+    if !config::ARGS.no_auto_sym || core.rom_calls.is_some() {
+        core.annotate_roms()?;
+    }
+    // remember what was loaded into RAM so that a cold start can put it back
+    core.load_map.snapshot(core.raw_ram, core.ram_top);
+    core.trace_file = tracefile::TraceFile::from_args()?;
+    core.trace_compare = compare::TraceCompare::from_args()?;
+    core.spies_from_args()?;
+    core.until_from_args()?;
+    core.reset()?;
+    if let Some(path) = config::ARGS.state.as_ref() {
+        core.load_snapshot(path)?;
+    }
+    let mut result = core.exec();
+    if let Some(expected) = config::ARGS.config_file.as_ref().and_then(|c| c.disk_expect.as_ref()) {
+        if result.is_ok() {
+            info!("Validating {} disk check(s)", expected.len());
+            result = core.disks.borrow().check_expectations(expected);
+        }
+    }
+    // resolve any unsaved disk changes and print jobs even if the program faulted
+    if config::kiosk() {
+        // kiosk mode never writes files
+        core.disks.borrow_mut().close(disk::ExitPolicy::Discard)?;
+        return result;
+    }
+    if let Some(path) = config::ARGS.save_state.as_ref() {
+        core.save_snapshot(path)?;
+    }
+    core.disks.borrow_mut().close(config::ARGS.disk_exit)?;
+    if let Some(becker) = core.becker.as_mut() {
+        becker.close()?;
+    }
+    for pak in core.paks.iter_mut() {
+        pak.close()?;
+    }
+    if let Some(cassette) = core.cassette.as_ref() {
+        cassette.borrow_mut().save()?;
+    }
+    core.save_cart_state()?;
+    if let (Some(coverage), Some(path)) = (core.coverage.as_ref(), config::ARGS.coverage.as_ref()) {
+        coverage.report(path)?;
+    }
+    if let Some(trace) = core.trace_file.as_mut() {
+        trace.finish()?;
+    }
+    result
+}
+/// Loads everything the user has requested. The load order is as follows:
+/// - load the cartridge if one is specified on the command line
+/// - load any ROM(s) specified in the config file
+/// - load any code (asm or hex) specified in the config file
+/// - load code specified on the command line
+/// - apply any patches specified in the config file
+///
+/// This load order allows the user to replace segments of the code in
+/// ROM or cartridge programs with their own custom code.
+fn load(core: &mut Core) -> Result<(), Error> {
+    // try to load a cartridge
+    if let Some(cart) = config::ARGS.cart.as_ref() {
+        core.load_cart(cart)?;
+    }
+    // try to load contents of ROM (--rom and --monitor take the place of the config file's ROMs)
+    if config::ARGS.monitor {
+        info!("loading the built-in monitor at a000");
+        core.load_monitor()?;
+    } else if let Some(path) = config::ARGS.rom.as_ref() {
+        info!("loading ROM at a000 from: {}", path.display());
+        core.load_bin(path, 0xa000)?;
+    }
+    if let Some(c) = config::ARGS.config_file.as_ref() {
+        if config::roms_replaced() {
+            // the config file's ROMs have been replaced
+        } else if let Some(roms) = &c.load_rom {
+            for r in roms {
+                info!("loading ROM at {:04x} from: {}", r.addr, r.path.display());
+                core.load_bin(&r.path, r.addr)?;
+            }
+        } else {
+            warn!("No ROMs specified in config file.");
+        }
+        if let Some(code) = &c.load_code {
+            for h in code {
+                info!("loading code from: {}", h.path.display());
+                core.load_program_from_file(&h.path)?;
+            }
+        } else {
+            info!("No code specified in config file.");
+        }
+    }
+    // try to load other code provided by user
+    for path in &config::ARGS.load {
+        // load programs in the order given
+        info!("Loading {}", path.display());
+        core.load_program_from_file(path)?;
+    }
+    // with nothing to run there's the monitor
+    if monitor::wanted() {
+        info!("nothing to run; starting the built-in monitor");
+        core.load_monitor()?;
+    }
+    // apply any patches on top of the loaded ROMs and programs
+    if let Some(patches) = config::ARGS.config_file.as_ref().and_then(|c| c.patches.as_ref()) {
+        info!("applying {} patch(es)", patches.len());
+        core.apply_patches(patches)?;
+    }
+    Ok(())
+}
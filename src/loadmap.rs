@@ -0,0 +1,129 @@
+//! A record of what was loaded where.
+//!
+//! Every cartridge, ROM and program that's loaded adds its address ranges to the load map.
+//! Loading over something that was loaded earlier replaces those bytes (which is how code can
+//! be used to patch a ROM) but doing so by accident is an easy way to clobber a ROM, so each
+//! overlap is handled according to the --load-overlap policy. Patches (see patch.rs) are meant
+//! to overwrite what's already loaded and aren't checked.
+use super::*;
+use serde::Deserialize;
+
+/// What to do when something is loaded on top of something that was loaded earlier
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapPolicy {
+    /// refuse to start
+    Error,
+    /// print a warning and replace the earlier bytes
+    Warn,
+    /// silently replace the earlier bytes
+    Allow,
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub start: u16,
+    pub end: u16, // inclusive
+    pub source: String,
+}
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}-{:04X} {}", self.start, self.end, self.source)
+    }
+}
+
+#[derive(Default)]
+pub struct LoadMap {
+    segments: Vec<Segment>,
+    image: Vec<(usize, Vec<u8>)>, // contents of the loaded segments in RAM as they were at boot
+}
+impl LoadMap {
+    /// Saves the loaded contents of RAM (below ram_top) so they can be restored by a cold start
+    pub fn snapshot(&mut self, ram: &[u8], ram_top: u16) {
+        self.image = self
+            .segments
+            .iter()
+            .filter(|s| s.start <= ram_top)
+            .map(|s| {
+                let (start, end) = (s.start as usize, s.end.min(ram_top) as usize);
+                (start, ram[start..=end].to_vec())
+            })
+            .collect();
+    }
+    /// Writes the contents saved by snapshot back into ram
+    pub fn restore(&self, ram: &mut [u8]) {
+        for (start, bytes) in &self.image {
+            ram[*start..*start + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Segment> { self.segments.iter() }
+    /// Records the (start, length) ranges loaded from source. Adjacent ranges are merged and
+    /// any overlap with a range loaded from a different source is handled according to policy.
+    pub fn add(&mut self, source: &str, ranges: &[(usize, usize)], policy: OverlapPolicy) -> Result<(), Error> {
+        let mut ranges: Vec<(usize, usize)> = ranges
+            .iter()
+            .filter(|(_, len)| *len > 0)
+            .map(|&(start, len)| (start, (start + len - 1).min(0xffff)))
+            .filter(|(start, _)| *start <= 0xffff)
+            .collect();
+        ranges.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, e)) if start <= *e + 1 => *e = (*e).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        let new: Vec<Segment> = merged
+            .into_iter()
+            .map(|(start, end)| Segment {
+                start: start as u16,
+                end: end as u16,
+                source: source.to_string(),
+            })
+            .collect();
+        if policy != OverlapPolicy::Allow {
+            for n in &new {
+                for s in self.segments.iter().filter(|s| s.source != n.source) {
+                    if n.start > s.end || n.end < s.start {
+                        continue;
+                    }
+                    let msg = format!(
+                        "{} overwrites {:04X}-{:04X} which was loaded from {}",
+                        n.source,
+                        n.start.max(s.start),
+                        n.end.min(s.end),
+                        s.source
+                    );
+                    if policy == OverlapPolicy::Error {
+                        return Err(Error::new(
+                            ErrorKind::Memory,
+                            None,
+                            format!("{} (use --load-overlap to allow this)", msg).as_str(),
+                        ));
+                    }
+                    warn!("{}", msg);
+                }
+            }
+        }
+        self.segments.extend(new);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlaps() {
+        let mut map = LoadMap::default();
+        assert!(map.add("rom", &[(0xa000, 0x2000)], OverlapPolicy::Error).is_ok());
+        assert!(map.add("prog", &[(0x3f00, 0x10), (0x3f10, 0x10), (0x4000, 2)], OverlapPolicy::Error).is_ok());
+        let segs: Vec<String> = map.iter().map(|s| s.to_string()).collect();
+        assert_eq!(segs, vec!["A000-BFFF rom", "3F00-3F1F prog", "4000-4001 prog"]);
+        assert!(map.add("patch", &[(0xbffe, 4)], OverlapPolicy::Error).is_err());
+        assert!(map.add("patch", &[(0xbffe, 4)], OverlapPolicy::Allow).is_ok());
+        assert!(map.add("prog", &[(0x3f08, 1)], OverlapPolicy::Error).is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+//! Counts of the locks the core takes, for --perf.
+//!
+//! PIA0, PIA1, the SAM and the VDG are shared with the UI thread, each behind a mutex, so every
+//! access the core makes to one of them takes a lock. With --perf each acquisition is counted,
+//! along with the ones that found the lock held (by the UI thread) and how long the core waited
+//! for those. The counts are reported with the rest of the perf data so that contention added
+//! by a new device or a change in the UI thread shows up. Without --perf the locks are just
+//! taken.
+use super::*;
+use std::cell::Cell;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Device {
+    Pia0,
+    Pia1,
+    Sam,
+    Vdg,
+}
+const NAMES: [&str; 4] = ["pia0", "pia1", "sam", "vdg"];
+
+#[derive(Default, Clone, Copy)]
+struct Counts {
+    taken: u64,
+    contended: u64,
+    wait: Duration,
+}
+
+#[derive(Default)]
+pub struct LockStats {
+    enabled: bool,
+    counts: [Cell<Counts>; 4],
+}
+impl LockStats {
+    pub fn new(enabled: bool) -> Self { LockStats { enabled, ..Default::default() } }
+    /// Takes the lock of a device's mutex, counting it (and timing the wait if it's held)
+    pub fn lock<'a, T>(&self, device: Device, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        if !self.enabled {
+            return mutex.lock().unwrap();
+        }
+        let cell = &self.counts[device as usize];
+        let mut counts = cell.get();
+        counts.taken += 1;
+        let guard = match mutex.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                let start = Instant::now();
+                let guard = mutex.lock().unwrap();
+                counts.contended += 1;
+                counts.wait += start.elapsed();
+                guard
+            }
+        };
+        cell.set(counts);
+        guard
+    }
+    /// Displays the counts (see report_perf)
+    pub fn report(&self) {
+        info!("\t{:<6} {:>12} {:>10} {:>9}", "Lock", "Taken", "Contended", "Wait");
+        info!("\t---------------------------------------");
+        for (name, cell) in NAMES.iter().zip(&self.counts) {
+            let counts = cell.get();
+            info!("\t{:<6} {:>12} {:>10} {:>9.3}", name, counts.taken, counts.contended, counts.wait.as_secs_f32());
+        }
+    }
+}
+
+impl Core {
+    pub fn lock_pia0(&self) -> MutexGuard<'_, pia::Pia0> { self.lock_stats.lock(Device::Pia0, &self.pia0) }
+    pub fn lock_pia1(&self) -> MutexGuard<'_, pia::Pia1> { self.lock_stats.lock(Device::Pia1, &self.pia1) }
+    pub fn lock_sam(&self) -> MutexGuard<'_, sam::Sam> { self.lock_stats.lock(Device::Sam, &self.sam) }
+    pub fn lock_vdg(&self) -> MutexGuard<'_, vdg::Vdg> { self.lock_stats.lock(Device::Vdg, &self._vdg) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_counts() {
+        let stats = LockStats::new(true);
+        let mutex = Mutex::new(0);
+        *stats.lock(Device::Sam, &mutex) += 1;
+        // another thread (the UI, say) holds the lock for a while
+        let held = std::sync::Barrier::new(2);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let _guard = mutex.lock().unwrap();
+                held.wait();
+                std::thread::sleep(Duration::from_millis(20));
+            });
+            held.wait();
+            *stats.lock(Device::Sam, &mutex) += 1;
+        });
+        let counts = stats.counts[Device::Sam as usize].get();
+        assert_eq!((counts.taken, counts.contended, *mutex.lock().unwrap()), (2, 1, 2));
+        assert!(counts.wait >= Duration::from_millis(10));
+        assert_eq!(stats.counts[Device::Pia0 as usize].get().taken, 0);
+        // without --perf nothing is counted
+        let stats = LockStats::new(false);
+        drop(stats.lock(Device::Sam, &mutex));
+        assert_eq!(stats.counts[Device::Sam as usize].get().taken, 0);
+    }
+}
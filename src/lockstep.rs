@@ -0,0 +1,103 @@
+//! Lockstep checking for refactoring safety.
+//!
+//! --lockstep runs the loaded program on two headless cores side by side: one as usual and one
+//! with `reference` set, which makes it take the plain, obviously correct path wherever the
+//! emulator has a faster one (today that's committing writes to RAM straight to memory instead
+//! of through the device dispatch). After every instruction the two must agree on registers,
+//! cycle count and all of memory; the first difference is reported along with the instruction
+//! that caused it and coco exits with status 1.
+//!
+//! Both cores run in warp mode so that their interrupts come at the same emulated cycles, and
+//! they start from the same power-on RAM. When adding an optimization, have `reference` turn it
+//! off and run programs under --lockstep to see whether it changes anything.
+use super::*;
+
+/// the most differing memory locations that are listed
+const MAX_LISTED: usize = 8;
+
+/// Describes how two cores differ (if they do)
+fn compare(a: &Core, b: &Core) -> Option<String> {
+    let regs = a.reg.diff(&b.reg);
+    let mut diffs: Vec<String> = regs.iter().map(|c| format!("{:?} {} vs {}", c.reg, c.old, c.new)).collect();
+    if a.clock_cycles != b.clock_cycles {
+        diffs.push(format!("cycles {} vs {}", a.clock_cycles, b.clock_cycles));
+    }
+    if (a.in_cwai, a.in_sync) != (b.in_cwai, b.in_sync) {
+        diffs.push("waiting for an interrupt".to_string());
+    }
+    if a.raw_ram != b.raw_ram {
+        let addrs = (0..a.raw_ram.len()).filter(|&i| a.raw_ram[i] != b.raw_ram[i]);
+        let addrs: Vec<usize> = addrs.collect();
+        for &i in addrs.iter().take(MAX_LISTED) {
+            diffs.push(format!("[{:04X}] {:02X} vs {:02X}", i, a.raw_ram[i], b.raw_ram[i]));
+        }
+        if addrs.len() > MAX_LISTED {
+            diffs.push(format!("and {} more bytes", addrs.len() - MAX_LISTED));
+        }
+    }
+    (!diffs.is_empty()).then(|| diffs.join(", "))
+}
+
+/// Runs two cores (loaded by load) in lockstep for up to count instructions or until the
+/// program exits. Returns an error if they diverge.
+pub fn run(count: u64, load: impl Fn(&mut Core) -> Result<(), Error>) -> Result<(), Error> {
+    let ram_top = config::ARGS.ram_top;
+    let mut cores = [Core::headless_with_ram_top(ram_top)?, Core::headless_with_ram_top(ram_top)?];
+    let [a, b] = &mut cores;
+    b.reference = true;
+    b.raw_ram.copy_from_slice(a.raw_ram);
+    b.rng = a.rng.clone();
+    for core in cores.iter_mut() {
+        load(core)?;
+        core.load_map.snapshot(core.raw_ram, core.ram_top);
+        core.warp = true;
+        core.reset()?;
+    }
+    info!("running two cores in lockstep for up to {} instructions", count);
+    let [a, b] = &mut cores;
+    for n in 1..=count {
+        let (pc, op) = (a.reg.pc, a._read_u8(memory::AccessType::System, a.reg.pc, None)?);
+        match (a.exec_one(), b.exec_one()) {
+            (Err(e), Err(_)) if e.kind == ErrorKind::Exit => {
+                info!("the program exited after {} instructions without the cores diverging", n);
+                return Ok(());
+            }
+            (Err(e), Err(f)) if e.to_string() == f.to_string() => return Err(e),
+            (Ok(()), Ok(())) => {}
+            (ra, rb) => {
+                let describe = |r: Result<(), Error>| r.err().map_or("ok".to_string(), |e| e.to_string());
+                let (ra, rb) = (describe(ra), describe(rb));
+                return Err(general_err!("instruction {} at {:04X} ({:02X}...): {} vs {}", n, pc, op, ra, rb));
+            }
+        }
+        if let Some(diffs) = compare(a, b) {
+            return Err(general_err!(
+                "the cores diverged after instruction {} at {:04X} ({:02X}...): {}",
+                n,
+                pc,
+                op,
+                diffs
+            ));
+        }
+    }
+    info!("no divergence in {} instructions", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divergence() {
+        let (a, b) = (Core::headless().unwrap(), Core::headless().unwrap());
+        b.raw_ram.copy_from_slice(a.raw_ram);
+        assert_eq!(compare(&a, &b), None);
+        let (mut a, mut b) = (a, b);
+        a.reg.set_register(registers::Name::X, u8u16::u16(0x1234));
+        a.raw_ram[0x400] = b.raw_ram[0x400].wrapping_add(1);
+        b.clock_cycles = 10;
+        let diffs = compare(&a, &b).unwrap();
+        assert!(diffs.starts_with("X 1234 vs 0000, cycles 0 vs 10, [0400]"), "{}", diffs);
+    }
+}
@@ -0,0 +1,405 @@
+//! A Language Server Protocol server for 6809 assembly (`coco lsp`).
+//!
+//! The editor starts `coco lsp` and talks JSON-RPC to it over stdin and stdout. The server
+//! keeps the text of the open documents and answers with the assembler's own parser:
+//! ```text
+//! diagnostics   the file is assembled when it's opened and saved; the first error (if any)
+//!               is reported on its line
+//! definition    where a label is defined, in the file or in the files it INCLUDEs
+//! hover         an instruction's addressing modes, opcodes, cycle counts, sizes and flags,
+//!               or a label's value
+//! rename        renames a label everywhere it appears in the file and the files it INCLUDEs
+//! ```
+//! Files that are open in the editor are read as the editor has them (saved or not); other
+//! included files are read from disk. Documents are synced in full on each change.
+use super::*;
+use assembler::SourceFs;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+/// the deepest the include tree is searched for labels
+const MAX_DEPTH: usize = 16;
+
+/// Source files as the editor has them, falling back to the host's filesystem
+struct EditorFs(HashMap<PathBuf, String>);
+impl SourceFs for EditorFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.0.get(path).map_or_else(|| std::fs::read(path), |text| Ok(text.clone().into_bytes()))
+    }
+}
+
+struct Server<W: Write> {
+    docs: HashMap<PathBuf, String>, // the text of each open document
+    out: W,
+    re_label: Regex,
+    re_line_num: Regex,
+}
+impl<W: Write> Server<W> {
+    fn new(out: W) -> Self {
+        Server {
+            docs: HashMap::new(),
+            out,
+            re_label: Regex::new(r"(?i)^[$._a-z0-9]{1,15}$").unwrap(),
+            re_line_num: Regex::new(r"line (\d+)").unwrap(),
+        }
+    }
+    fn send(&mut self, msg: Value) -> Result<(), Error> {
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()?;
+        Ok(())
+    }
+    /// An assembler that reads the open documents (and keeps quiet, since stdout is ours)
+    fn assembler(&self) -> Assembler { Assembler::with_fs(Box::new(EditorFs(self.docs.clone()))).quiet() }
+    fn text_of(&self, path: &Path) -> Option<String> {
+        self.docs.get(path).cloned().or_else(|| std::fs::read_to_string(path).ok())
+    }
+
+    /// Handles a message from the editor; returns false when it's time to exit
+    fn handle(&mut self, msg: &Value) -> Result<bool, Error> {
+        let method = msg["method"].as_str().unwrap_or("");
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let path = uri_to_path(uri);
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": {"openClose": true, "change": 1, "save": {"includeText": true}},
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "renameProvider": true,
+                },
+                "serverInfo": {"name": "coco", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "shutdown" => Ok(Value::Null),
+            "exit" => return Ok(false),
+            "textDocument/didOpen" | "textDocument/didSave" => {
+                let text = params["textDocument"]["text"].as_str().or(params["text"].as_str());
+                if let Some(text) = text {
+                    self.docs.insert(path.clone(), text.to_string());
+                }
+                let diagnostics = self.diagnostics(&path);
+                self.notify_diagnostics(uri, diagnostics)?;
+                return Ok(true);
+            }
+            "textDocument/didChange" => {
+                let change = params["contentChanges"].as_array().and_then(|c| c.last());
+                if let Some(text) = change.and_then(|c| c["text"].as_str()) {
+                    self.docs.insert(path, text.to_string());
+                }
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&path);
+                self.notify_diagnostics(uri, Vec::new())?;
+                return Ok(true);
+            }
+            "textDocument/definition" => Ok(self.definition(&path, &params["position"])),
+            "textDocument/hover" => Ok(self.hover(&path, &params["position"])),
+            "textDocument/rename" => self.rename(&path, &params["position"], params["newName"].as_str().unwrap_or("")),
+            _ => Err((-32601, format!("unsupported method {}", method))),
+        };
+        // notifications (which have no id) get no response
+        let Some(id) = msg.get("id") else {
+            return Ok(true);
+        };
+        let response = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}),
+        };
+        self.send(response)?;
+        Ok(true)
+    }
+    fn notify_diagnostics(&mut self, uri: &str, diagnostics: Vec<Value>) -> Result<(), Error> {
+        let params = json!({"uri": uri, "diagnostics": diagnostics});
+        self.send(json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": params}))
+    }
+
+    /// Assembles a document and returns its first error as a diagnostic
+    fn diagnostics(&self, path: &Path) -> Vec<Value> {
+        let asm = self.assembler();
+        let Err(e) = asm.assemble_from_file(path) else {
+            return Vec::new();
+        };
+        let msg = pager::strip_colors(&e.msg);
+        let text = self.text_of(path).unwrap_or_default();
+        let line = self.re_line_num.captures(&msg).and_then(|c| c[1].parse::<usize>().ok());
+        let line = line.map_or(0, |line| source_line(&asm, &text, path, line));
+        let len = text.lines().nth(line).map_or(0, |l| l.chars().count());
+        vec![json!({"range": range(line, 0, len), "severity": 1, "source": "coco", "message": msg})]
+    }
+
+    /// Returns the label or mnemonic at a position in a document
+    fn word_at(&self, path: &Path, position: &Value) -> Option<String> {
+        let line_num = position["line"].as_u64()? as usize;
+        let chars: Vec<char> = self.docs.get(path)?.lines().nth(line_num)?.chars().collect();
+        let col = (position["character"].as_u64()? as usize).min(chars.len());
+        let start = col - chars[..col].iter().rev().take_while(|&&c| is_label_char(c)).count();
+        let end = col + chars[col..].iter().take_while(|&&c| is_label_char(c)).count();
+        Some(chars[start..end].iter().collect()).filter(|w: &String| !w.is_empty())
+    }
+    /// Collects a file and the files it includes (and so on) with their text
+    fn include_tree(&self, asm: &Assembler, path: &Path, depth: usize, files: &mut Vec<(PathBuf, String)>) {
+        if depth > MAX_DEPTH || files.iter().any(|(p, _)| p == path) {
+            return;
+        }
+        let Some(text) = self.text_of(path) else {
+            return;
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let includes: Vec<PathBuf> =
+            text.lines().filter_map(|l| asm.include_name(l)).filter(|n| !n.is_empty()).map(|n| dir.join(n)).collect();
+        files.push((path.to_path_buf(), text));
+        for include in includes {
+            self.include_tree(asm, &include, depth + 1, files);
+        }
+    }
+    /// Returns the file and line where a label is defined
+    fn find_definition(&self, asm: &Assembler, files: &[(PathBuf, String)], label: &str) -> Option<(PathBuf, usize)> {
+        files.iter().find_map(|(path, text)| {
+            let line = text.lines().position(|l| asm.split_statement(l).0 == Some(label))?;
+            Some((path.clone(), line))
+        })
+    }
+
+    fn definition(&self, path: &Path, position: &Value) -> Value {
+        let Some(word) = self.word_at(path, position) else {
+            return Value::Null;
+        };
+        let asm = self.assembler();
+        let mut files = Vec::new();
+        self.include_tree(&asm, path, 0, &mut files);
+        self.find_definition(&asm, &files, &word).map_or(Value::Null, |(path, line)| {
+            json!({"uri": path_to_uri(&path), "range": range(line, 0, word.chars().count())})
+        })
+    }
+
+    fn hover(&self, path: &Path, position: &Value) -> Value {
+        let Some(word) = self.word_at(path, position) else {
+            return Value::Null;
+        };
+        let text = if let Some(desc) = instructions::name_to_descriptor(&word.to_ascii_uppercase()) {
+            let mut text = format!("**{}**\n\nflags (HNZVC): `{}`\n\n", desc.name, desc.flags);
+            text.push_str("| mode | opcode | cycles | bytes |\n|---|---|---|---|\n");
+            for md in desc.md {
+                let mode = format!("{:?}", md.addressing_mode()).to_lowercase();
+                text.push_str(&format!("| {} | {:02X} | {} | {} |\n", mode, md.op, md.clk, md.sz));
+            }
+            text.push_str("\nCycles and bytes are the least the mode takes (indexed modes can take more).");
+            text
+        } else {
+            let asm = self.assembler();
+            let mut files = Vec::new();
+            self.include_tree(&asm, path, 0, &mut files);
+            let Some((def_path, line)) = self.find_definition(&asm, &files, &word) else {
+                return Value::Null;
+            };
+            let mut text = format!("**{}** is defined on line {}", word, line + 1);
+            if def_path != path {
+                text.push_str(&format!(" of {}", def_path.display()));
+            }
+            if let Ok(program) = asm.assemble_from_file(path) {
+                if let Some(symbol) = program.symbols().iter().find(|s| s.name == word) {
+                    let kind = if symbol.equ { "EQU" } else { "address" };
+                    text.push_str(&format!("\n\n{} ${:04X} ({})", kind, symbol.value, symbol.value));
+                }
+            }
+            text
+        };
+        json!({"contents": {"kind": "markdown", "value": text}})
+    }
+
+    fn rename(&self, path: &Path, position: &Value, new_name: &str) -> Result<Value, (i64, String)> {
+        let Some(word) = self.word_at(path, position) else {
+            return Err((-32602, "there's no label here".to_string()));
+        };
+        let asm = self.assembler();
+        let mut files = Vec::new();
+        self.include_tree(&asm, path, 0, &mut files);
+        if self.find_definition(&asm, &files, &word).is_none() {
+            return Err((-32602, format!("{} isn't a label", word)));
+        }
+        if !self.re_label.is_match(new_name) {
+            return Err((-32602, format!("{} isn't a valid label", new_name)));
+        }
+        if self.find_definition(&asm, &files, new_name).is_some() {
+            return Err((-32602, format!("{} is already defined", new_name)));
+        }
+        let mut changes = serde_json::Map::new();
+        for (file, text) in &files {
+            let edits: Vec<Value> = text
+                .lines()
+                .enumerate()
+                .flat_map(|(i, line)| occurrences(&asm, line, &word).into_iter().map(move |col| (i, col)))
+                .map(|(i, col)| json!({"range": range(i, col, col + word.chars().count()), "newText": new_name}))
+                .collect();
+            if !edits.is_empty() {
+                changes.insert(path_to_uri(file), Value::Array(edits));
+            }
+        }
+        Ok(json!({ "changes": changes }))
+    }
+}
+
+fn is_label_char(c: char) -> bool { c.is_ascii_alphanumeric() || "$._".contains(c) }
+
+/// Returns the columns (in characters) where a label appears in a line: as the line's label
+/// or in its operand (but not in a comment, a string or a character constant)
+fn occurrences(asm: &Assembler, line: &str, label: &str) -> Vec<usize> {
+    let (found, operation, operand) = asm.split_statement(line);
+    let mut cols = Vec::new();
+    if found == Some(label) {
+        cols.push(0);
+    }
+    let Some(operand) = operand.filter(|_| !operation.is_some_and(|op| op.eq_ignore_ascii_case("FCC"))) else {
+        return cols;
+    };
+    let operand = operand.split(';').next().unwrap_or("");
+    let offset = operand.as_ptr() as usize - line.as_ptr() as usize;
+    let mut start = None;
+    for (i, c) in operand.char_indices().chain([(operand.len(), ' ')]) {
+        match (start, is_label_char(c)) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                let quoted = operand[..s].ends_with('\'');
+                if &operand[s..i] == label && !quoted {
+                    cols.push(line[..offset + s].chars().count());
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    cols
+}
+
+/// Returns the line in the file (counting from 0) that produced a line of the assembled
+/// program (counting from 1, with the lines of included files counted in)
+fn source_line(asm: &Assembler, text: &str, path: &Path, assembled: usize) -> usize {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut count = 0;
+    for (i, line) in text.lines().enumerate() {
+        count += asm.expand_includes(vec![line.to_string()], dir, 0).map_or(1, |lines| lines.len());
+        if count >= assembled {
+            return i;
+        }
+    }
+    text.lines().count().saturating_sub(1)
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({"start": {"line": line, "character": start}, "end": {"line": line, "character": end}})
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::new();
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        let hex = |c: Option<u8>| c.and_then(|c| (c as char).to_digit(16));
+        match b {
+            b'%' => match (hex(iter.next()), hex(iter.next())) {
+                (Some(h), Some(l)) => bytes.push((h * 16 + l) as u8),
+                _ => bytes.push(b),
+            },
+            _ => bytes.push(b),
+        }
+    }
+    let path = String::from_utf8_lossy(&bytes).into_owned();
+    // file:///C:/dir on Windows
+    PathBuf::from(if path.get(2..3) == Some(":") { &path[1..] } else { &path })
+}
+fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from(if path.starts_with('/') { "file://" } else { "file:///" });
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' | b':' => uri.push(b as char),
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+/// Reads a message (a Content-Length header, a blank line and a JSON body) or returns None at
+/// the end of the input
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let len = len.ok_or_else(|| general_err!("message without a Content-Length"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| general_err!("bad message: {}", e))
+}
+
+/// Runs the server on stdin and stdout until the editor says to exit (coco lsp)
+pub fn run() -> Result<(), Error> {
+    let mut input = io::stdin().lock();
+    let mut server = Server::new(io::stdout().lock());
+    while let Some(msg) = read_message(&mut input)? {
+        if !server.handle(&msg)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(server: &mut Server<Vec<u8>>, method: &str, params: Value) -> Value {
+        server.out.clear();
+        server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params})).unwrap();
+        let mut out = io::Cursor::new(std::mem::take(&mut server.out));
+        read_message(&mut out).unwrap().unwrap()
+    }
+
+    #[test]
+    fn language_server() {
+        let mut server = Server::new(Vec::new());
+        let (main, defs) = ("file:///src/main.asm", "file:///src/defs.asm");
+        let open = |uri: &str, text: &str| json!({"textDocument": {"uri": uri, "text": text}});
+        request(&mut server, "textDocument/didOpen", open(defs, "value equ $41\n"));
+        let src = " include defs.asm\n org $3000\nstart lda #value\n bra start ; start\n fcc /start/\n lda ,q\n";
+        let diagnostics = request(&mut server, "textDocument/didOpen", open(main, src));
+        let diagnostic = &diagnostics["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["range"]["start"]["line"], 5, "{}", diagnostic);
+
+        let at = |line: usize, character: usize| {
+            json!({"textDocument": {"uri": main}, "position": {"line": line, "character": character}})
+        };
+        let definition = request(&mut server, "textDocument/definition", at(2, 13));
+        assert_eq!(definition["result"], json!({"uri": defs, "range": range(0, 0, 5)}));
+        let hover = request(&mut server, "textDocument/hover", at(2, 7));
+        assert!(hover["result"]["contents"]["value"].as_str().unwrap().contains("| immediate | 86 | 2 | 2 |"));
+
+        let mut params = at(3, 6);
+        params["newName"] = json!("begin");
+        let edits = &request(&mut server, "textDocument/rename", params.clone())["result"]["changes"][main];
+        let cols: Vec<_> = edits.as_array().unwrap().iter().map(|e| e["range"]["start"].clone()).collect();
+        assert_eq!(cols, [json!({"line": 2, "character": 0}), json!({"line": 3, "character": 5})]);
+        params["newName"] = json!("value");
+        let error = request(&mut server, "textDocument/rename", params);
+        assert!(error["error"]["message"].as_str().unwrap().contains("already"));
+
+        assert_eq!(uri_to_path("file:///home/my%20code/a.asm"), PathBuf::from("/home/my code/a.asm"));
+        assert_eq!(path_to_uri(Path::new("/home/my code/a.asm")), "file:///home/my%20code/a.asm");
+    }
+}
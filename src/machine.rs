@@ -0,0 +1,207 @@
+//! A description of the emulated machine for bug reports (--dump-machine-info).
+//!
+//! It gathers what a maintainer usually has to ask for: coco's version and the host it runs on,
+//! the machine's settings, the ROMs (with their CRC32s and names if the ROM database recognizes
+//! them), the devices that are plugged in and the audio and video backends. It's shown as text or
+//! written to a file as JSON (an object for each section whose members are strings).
+use super::*;
+use crate::core::CPU_HZ;
+use cpal::traits::{DeviceTrait, HostTrait};
+
+/// Sections of name/value pairs (in the order they're shown)
+#[derive(Debug, Default)]
+pub struct Info {
+    sections: Vec<(&'static str, Vec<(String, String)>)>,
+}
+impl Info {
+    fn section(&mut self, name: &'static str) { self.sections.push((name, Vec::new())) }
+    fn add(&mut self, name: &str, value: impl fmt::Display) {
+        self.sections.last_mut().unwrap().1.push((name.to_string(), value.to_string()));
+    }
+    /// Gathers everything from the command line, the config file and the host
+    pub fn gather() -> Self {
+        let mut info = Info::default();
+        info.section("coco");
+        info.add("version", env!("CARGO_PKG_VERSION"));
+        info.add("build", if cfg!(debug_assertions) { "debug" } else { "release" });
+        info.add("host", format!("{} {}", std::env::consts::OS, std::env::consts::ARCH));
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        info.add("arguments", args.join(" "));
+        let loaded = if config::ARGS.config_file.is_some() { "" } else { " (not loaded)" };
+        info.add("config file", format!("{}{}", config::ARGS.config_file_path.display(), loaded));
+
+        info.section("machine");
+        let mhz = config::ARGS.mhz.map_or(format!("{} (normal)", CPU_HZ as f64 / 1e6), |mhz| mhz.to_string());
+        info.add("cpu MHz", mhz);
+        info.add("RAM", format!("{}K (top {:04X})", (config::ARGS.ram_top as usize + 1) / 1024, config::ARGS.ram_top));
+        info.add("RAM pattern", format!("{:?}", config::ARGS.ram_pattern));
+        if let Some(seed) = config::ARGS.seed {
+            info.add("seed", seed);
+        }
+        if let Some(vector) = config::ARGS.reset_vector {
+            info.add("reset vector", format!("{:04X}", vector));
+        }
+        info.add("accuracy", format!("{:?}", config::ARGS.accuracy).to_lowercase());
+        info.add("strict PIAs", config::pia_strict());
+        info.add("warp", config::ARGS.warp);
+        info.add("video", config::ARGS.video);
+
+        info.section("roms");
+        if config::ARGS.monitor {
+            info.add("A000", "built-in monitor");
+        } else if let Some(path) = config::ARGS.rom.as_ref() {
+            info.add("A000", describe_rom(path));
+        }
+        // --rom and --monitor take the place of the config file's ROMs
+        let config_file = config::ARGS.config_file.as_ref().filter(|_| !config::roms_replaced());
+        let roms = config_file.and_then(|c| c.load_rom.as_ref());
+        let cart = config::ARGS.cart.iter().map(|p| (p, cart::ROM_START));
+        for (path, addr) in roms.into_iter().flatten().map(|r| (&r.path, r.addr)).chain(cart) {
+            info.add(&format!("{:04X}", addr), describe_rom(path));
+        }
+
+        info.section("devices");
+        // (too many disks is reported when they're mounted)
+        let drives = disk::Drives::assign().unwrap_or_default();
+        if config::ARGS.fdc || !drives.is_empty() {
+            info.add("disk controller", "FD-502 (FF40-FF4F)");
+        }
+        for (i, disk) in drives {
+            let protected = if config::ARGS.disk_wp.contains(&i) { " (write protected)" } else { "" };
+            info.add(&format!("drive {}", i), format!("{}{}", disk.display(), protected));
+        }
+        if config::ARGS.acia_enable {
+            let port = config::ARGS.acia_port.map(|p| format!("port {}", p));
+            let port = port.unwrap_or_else(|| "first free port from 6809".to_string());
+            let to = config::ARGS.acia_connect.clone().unwrap_or(port);
+            info.add("ACIA", format!("{:04X}, {}", config::ARGS.acia_addr, to));
+        }
+        if let Some(path) = config::ARGS.cassette.as_ref() {
+            info.add("cassette", path.display().to_string());
+        }
+        if config::ARGS.becker {
+            info.add("Becker port", format!("{:04X}", becker::STATUS_ADDR));
+        }
+        if let Some(kb) = config::ARGS.ramdisk {
+            info.add("RAM disk", format!("{:04X}, {}K", config::ARGS.ramdisk_addr, kb));
+        }
+        if config::ARGS.timer {
+            info.add("timer", format!("{:04X}", config::ARGS.timer_addr));
+        }
+        if let Some(path) = config::ARGS.midi.as_ref() {
+            info.add("MIDI Pak", format!("{:04X}, {}", config::ARGS.midi_addr, path.display()));
+        }
+        if config::ARGS.tablet {
+            info.add("tablet", format!("{:04X}", config::ARGS.tablet_addr));
+        }
+        if let Some(output) = config::ARGS.wordpak {
+            info.add("WordPak", format!("{:04X}, {:?}", config::ARGS.wordpak_addr, output));
+        }
+        if let Some(trap) = config::ARGS.host_services {
+            info.add("host services", format!("{:?}", trap));
+        }
+
+        info.section("audio");
+        let host = cpal::default_host();
+        info.add("host", host.id().name());
+        match host.default_output_device() {
+            Some(device) => {
+                info.add("device", device.name().unwrap_or("<unknown>".to_string()));
+                match device.default_output_config() {
+                    Ok(c) => info.add(
+                        "config",
+                        format!("{} channels, {} Hz, {:?}", c.channels(), c.sample_rate().0, c.sample_format()),
+                    ),
+                    Err(e) => info.add("config", format!("<none: {}>", e)),
+                }
+            }
+            None => info.add("device", "<none>"),
+        }
+
+        info.section("video");
+        info.add("backend", "minifb");
+        info.add("screen", format!("{}x{}", vdg::SCREEN_DIM_X, vdg::SCREEN_DIM_Y));
+        info.add("refresh period", format!("{:?}", vdg::SCREEN_REFRESH_PERIOD));
+        info
+    }
+    /// Returns the sections as JSON
+    pub fn to_json(&self) -> String {
+        let members = |pairs: &Vec<(String, String)>| {
+            let pairs: Vec<String> = pairs.iter().map(|(n, v)| format!("    {}: {}", json_string(n), json_string(v))).collect();
+            pairs.join(",\n")
+        };
+        let sections: Vec<String> =
+            self.sections.iter().map(|(name, pairs)| format!("  {}: {{\n{}\n  }}", json_string(name), members(pairs))).collect();
+        format!("{{\n{}\n}}\n", sections.join(",\n"))
+    }
+}
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, pairs) in &self.sections {
+            writeln!(f, "{}:", name)?;
+            for (n, v) in pairs {
+                writeln!(f, "  {}: {}", n, v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes a ROM image: its size, CRC32 and name (if the ROM database knows it)
+fn describe_rom(path: &Path) -> String {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => return format!("{} (unreadable: {})", path.display(), e),
+    };
+    let crc = romdb::crc32(&data);
+    let name = romdb::identify(crc, data.len()).map_or(String::new(), |name| format!(", {}", name));
+    format!("{} ({} bytes, CRC32 {:08X}{})", path.display(), data.len(), crc, name)
+}
+
+/// Quotes and escapes a string for JSON
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            c if (c as u32) < 0x20 => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Prints the machine's description (or writes it to a file as JSON)
+pub fn dump(path: Option<&Path>) -> Result<(), Error> {
+    let info = Info::gather();
+    match path {
+        Some(path) => {
+            std::fs::write(path, info.to_json())?;
+            info!("machine info written to {}", path.display());
+        }
+        None => print!("{}", info),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let mut info = Info::default();
+        info.section("coco");
+        info.add("version", "1.0");
+        info.add("path", "C:\\roms\\\"bas\"");
+        info.section("video");
+        info.add("backend", "minifb");
+        let expected = "{\n  \"coco\": {\n    \"version\": \"1.0\",\n    \"path\": \"C:\\\\roms\\\\\\\"bas\\\"\"\n  },\n  \
+                        \"video\": {\n    \"backend\": \"minifb\"\n  }\n}\n";
+        assert_eq!(info.to_json(), expected);
+        assert_eq!(info.to_string(), "coco:\n  version: 1.0\n  path: C:\\roms\\\"bas\"\nvideo:\n  backend: minifb\n");
+    }
+}
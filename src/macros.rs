@@ -85,6 +85,15 @@ macro_rules! err {
         )
     };
 }
+// translates a message shown to the user (see messages.rs) and fills in its {}s
+macro_rules! tr {
+    ($msg:expr) => {
+        crate::messages::text($msg)
+    };
+    ($msg:expr,$($arg:expr),+) => {
+        crate::messages::fill(crate::messages::text($msg), &[$(&$arg as &dyn std::fmt::Display),+])
+    };
+}
 macro_rules! within_usize_bound {
     ($val:expr,$bound:expr) => {
         ((($val) as usize) < (($bound) as usize))
@@ -149,3 +158,8 @@ macro_rules! blue {
         color!(94, $msg)
     };
 }
+macro_rules! gray {
+    ($msg:expr) => {
+        color!(90, $msg)
+    };
+}
@@ -15,11 +15,45 @@ pub enum AccessType {
 impl Core {
     // reads one byte from RAM
     #[inline(always)]
-    pub fn _read_u8(&self, _: AccessType, addr: u16, data: Option<&mut u8>) -> Result<u8, Error> {
+    pub fn _read_u8(&self, at: AccessType, addr: u16, data: Option<&mut u8>) -> Result<u8, Error> {
+        if self.heartbeat.is_some() {
+            self.heartbeat_io(addr, false);
+        }
+        let byte = self.read_u8_dispatch(at, addr)?;
+        if self.io_log.is_some() {
+            self.log_io(addr, byte, false);
+        }
+        if self.spies.is_some() {
+            self.spy(addr, byte, false);
+        }
+        if let Some(data) = data {
+            *data = byte;
+        }
+        Ok(byte)
+    }
+    // routes a read to RAM, ROM or the device that owns addr
+    #[inline(always)]
+    fn read_u8_dispatch(&self, _: AccessType, addr: u16) -> Result<u8, Error> {
         // first check to see if this address is overridden by the ACIA
         if let Some(acia) = self.acia.as_ref() {
             if acia.owns_address(addr) {
-                return acia.read(addr);
+                return acia.read(addr, self.clock_cycles);
+            }
+        }
+        if let Some(becker) = self.becker.as_ref() {
+            if becker.owns_address(addr) {
+                return Ok(becker.read(addr));
+            }
+        }
+        // the devices plugged in alongside the slot see every address and the slot only its windows
+        for pak in &self.paks {
+            if let Some(byte) = pak.read(addr, self.clock_cycles) {
+                return Ok(byte);
+            }
+        }
+        if self.cart_inserted && cart::in_slot(addr) {
+            if let Some(byte) = self.cart.as_ref().and_then(|c| c.read(addr, self.clock_cycles)) {
+                return Ok(byte);
             }
         }
         // if the debugger is enabled then check to see if this read should trigger a breakpoint
@@ -34,12 +68,15 @@ impl Core {
             }
             0xff00..=0xff1f => {
                 // pia0
-                let mut pia = self.pia0.lock().unwrap();
+                let mut pia = self.lock_pia0();
                 pia.read((addr - 0xff00) as usize)
             }
             0xff20..=0xff3f => {
                 // pia1
-                let mut pia = self.pia1.lock().unwrap();
+                let mut pia = self.lock_pia1();
+                if let Some(cassette) = self.cassette.as_ref().filter(|_| addr & 3 == 0) {
+                    pia.set_cassette_input(cassette.borrow_mut().input(self.clock_cycles));
+                }
                 pia.read((addr - 0xff20) as usize)
             }
             0xffc0..=0xffdf => {
@@ -55,9 +92,6 @@ impl Core {
                 0
             }
         };
-        if let Some(data) = data {
-            *data = byte;
-        }
         Ok(byte)
     }
     // helper version of _read_u8 that reads a byte into a u16
@@ -76,7 +110,7 @@ impl Core {
     pub fn _read_u16(&self, atype: AccessType, addr: u16, data: Option<&mut u16>) -> Result<u16, Error> {
         let mut b: [u8; 2] = [0, 0];
         self._read_u8(atype, addr, Some(&mut b[0]))?;
-        self._read_u8(atype, addr + 1, Some(&mut b[1]))?;
+        self._read_u8(atype, addr.wrapping_add(1), Some(&mut b[1]))?;
         let word = (b[0] as u16) << 8 | (b[1] as u16);
         if let Some(data) = data {
             *data = word;
@@ -103,10 +137,44 @@ impl Core {
     //
     #[inline(always)]
     pub fn _write_u8(&mut self, at: AccessType, addr: u16, data: u8) -> Result<(), Error> {
+        if self.io_log.is_some() {
+            self.log_io(addr, data, true);
+        }
+        if self.spies.is_some() {
+            self.spy(addr, data, true);
+        }
+        if self.trace_file.is_some() {
+            self.trace_io_write(addr, data)?;
+        }
+        if self.heartbeat.is_some() {
+            self.heartbeat_io(addr, true);
+        }
+        self.write_u8_dispatch(at, addr, data)
+    }
+    // routes a write to RAM or the device that owns addr (writes to ROM are ignored unless at is System)
+    #[inline(always)]
+    fn write_u8_dispatch(&mut self, at: AccessType, addr: u16, data: u8) -> Result<(), Error> {
         // first check to see if this address is overridden by the ACIA
         if let Some(acia) = self.acia.as_mut() {
             if acia.owns_address(addr) {
-                return acia.write(addr, data);
+                return acia.write(addr, data, self.clock_cycles);
+            }
+        }
+        if let Some(becker) = self.becker.as_mut() {
+            if becker.owns_address(addr) {
+                return becker.write(addr, data);
+            }
+        }
+        for pak in self.paks.iter_mut() {
+            if pak.write(addr, data, self.clock_cycles)? {
+                return Ok(());
+            }
+        }
+        if self.cart_inserted && cart::in_slot(addr) {
+            if let Some(cart) = self.cart.as_mut() {
+                if cart.write(addr, data, self.clock_cycles)? {
+                    return Ok(());
+                }
             }
         }
         // if the debugger is enabled then check to see if this write should trigger a breakpoint
@@ -124,18 +192,23 @@ impl Core {
             }
             0xff00..=0xff1f => {
                 // pia0
-                let mut pia = self.pia0.lock().unwrap();
+                let mut pia = self.lock_pia0();
                 pia.write((addr - 0xff00) as usize, data);
             }
             0xff20..=0xff3f => {
                 // pia1
-                let mut pia = self.pia1.lock().unwrap();
-                pia.write((addr - 0xff20) as usize, data);
+                self.lock_pia1().write((addr - 0xff20) as usize, data);
+                // side A has the cassette relay and the DAC
+                if addr & 2 == 0 {
+                    self.cassette_update()?;
+                }
             }
             0xffc0..=0xffdf => {
                 // sam
-                let mut sam = self.sam.lock().unwrap();
-                sam.write((addr - 0xffc0) as usize);
+                let res = self.lock_sam().write((addr - 0xffc0) as usize);
+                if let Err(e) = res {
+                    self.misuse(e)?;
+                }
             }
             0xffe0..=0xffff => {
                 if addr > self.ram_top && at != AccessType::System {
@@ -156,6 +229,6 @@ impl Core {
             self._write_u8(atype, addr, msb)?;
             offset += 1;
         }
-        self._write_u8(atype, addr + offset, data.lsb())
+        self._write_u8(atype, addr.wrapping_add(offset), data.lsb())
     }
 }
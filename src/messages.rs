@@ -0,0 +1,58 @@
+//! Translations of the messages shown to the user.
+//!
+//! The keyboard help, hotkey notices, the recent-files chooser and the debugger's help are
+//! looked up by their English text in a catalog that can be filled in under `messages` in
+//! coco.yaml:
+//! ```yaml
+//! messages:
+//!   "reset (warm start)": "Reset (Warmstart)"
+//!   "rewound {} seconds to PC={}": "{} Sekunden zurück zu PC={}"
+//! ```
+//! Each {} stands for a value that's filled in (in order). Messages without a translation are
+//! shown in English.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// Translations keyed by their English text
+pub struct Catalog(HashMap<String, String>);
+impl Catalog {
+    /// Returns the translation of msg (or msg itself if there isn't one)
+    pub fn text<'a>(&'a self, msg: &'a str) -> &'a str { self.0.get(msg).map_or(msg, String::as_str) }
+}
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// Sets the translations (only the first call has any effect)
+pub fn init(catalog: HashMap<String, String>) { _ = CATALOG.set(Catalog(catalog)); }
+
+/// Returns the translation of msg from the catalog set by init (or msg itself if there isn't one)
+pub fn text(msg: &str) -> &str { CATALOG.get().map_or(msg, |c| c.text(msg)) }
+
+/// Fills in the {}s in a message with args
+pub fn fill(msg: &str, args: &[&dyn Display]) -> String {
+    let mut parts = msg.split("{}");
+    let mut s = parts.next().unwrap_or_default().to_string();
+    for (i, part) in parts.enumerate() {
+        if let Some(arg) = args.get(i) {
+            s.push_str(&arg.to_string());
+        }
+        s.push_str(part);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog() {
+        assert_eq!(fill("{} of {}", &[&1, &"two"]), "1 of two");
+        assert_eq!(fill("{}-{}!", &[&'x']), "x-!");
+        // (a catalog of its own so that the global one is left to config)
+        let catalog = Catalog(HashMap::from([("warp mode on".to_string(), "Warp-Modus an".to_string())]));
+        assert_eq!(catalog.text("warp mode on"), "Warp-Modus an");
+        assert_eq!(catalog.text("warp mode off"), "warp mode off");
+    }
+}
@@ -0,0 +1,274 @@
+//! MIDI Pak cartridge.
+//!
+//! The MIDI Pak is a 6850 ACIA running at MIDI's 31250 baud, enabled with --midi and mapped at
+//! --midi-addr ($FF6E by default):
+//! ```text
+//! base+0  write: control (master reset with bits 0-1 set; bits 5-6 = 01 enable the transmit
+//!           interrupt)
+//!         read: status (bit 1 when the transmit data register is empty, bit 7 interrupt request)
+//! base+1  write: transmit data
+//! ```
+//! Whatever a program sends goes to the file given with --midi. A name ending in .mid captures
+//! it as a standard MIDI file (timed by the emulated clock, so it plays back at the right speed
+//! even with --warp) and anything else is written to as a raw byte stream, which is how a host
+//! MIDI port such as /dev/snd/midiC1D0 or /dev/midi1 is driven. Nothing is ever received.
+use super::*;
+use crate::core::CPU_HZ;
+use cart::{Cartridge, Line};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+// status register bits
+const TDRE: u8 = 0b00000010; // transmit data register empty
+const IRQ: u8 = 0b10000000; // interrupt request
+// control register bits
+const MASTER_RESET: u8 = 0b00000011;
+const TX_CONTROL: u8 = 0b01100000;
+const TX_INTERRUPT: u8 = 0b00100000;
+// 10 bits per byte at 31250 baud
+const CYCLES_PER_BYTE: u64 = CPU_HZ * 10 / 31250;
+// a standard MIDI file's timing: 480 ticks per quarter note at 120 BPM (the default tempo)
+const TICKS_PER_QUARTER: u64 = 480;
+const TICKS_PER_SECOND: u64 = TICKS_PER_QUARTER * 2;
+/// where the events start in a standard MIDI file (after the header and the track's header)
+const EVENTS_START: u64 = 22;
+const END_OF_TRACK: [u8; 4] = [0x00, 0xff, 0x2f, 0x00];
+
+/// Splits a MIDI byte stream into messages
+#[derive(Default)]
+struct Parser {
+    /// the current status (kept for running status)
+    status: Option<u8>,
+    message: Vec<u8>,
+}
+impl Parser {
+    /// the number of data bytes that follow a status byte
+    fn data_len(status: u8) -> usize {
+        match status {
+            0xc0..=0xdf | 0xf1 | 0xf3 => 1,
+            0x80..=0xef | 0xf2 => 2,
+            _ => 0,
+        }
+    }
+    /// Adds a byte to the message being put together; returns the message once it's complete
+    fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match byte {
+            // real-time messages can come anywhere (even in the middle of another message)
+            0xf8..=0xff => Some(vec![byte]),
+            0xf7 if self.status == Some(0xf0) => {
+                self.status = None;
+                self.message.push(byte);
+                Some(std::mem::take(&mut self.message))
+            }
+            // an end of exclusive without a start
+            0xf7 => None,
+            0x80..=0xf6 => {
+                self.message = vec![byte];
+                // system common messages cancel running status
+                self.status = (byte <= 0xf0).then_some(byte);
+                self.complete()
+            }
+            _ => match self.status {
+                Some(0xf0) => {
+                    self.message.push(byte);
+                    None
+                }
+                Some(status) => {
+                    if self.message.is_empty() {
+                        self.message.push(status);
+                    }
+                    self.message.push(byte);
+                    self.complete()
+                }
+                None => {
+                    // a system common message's data or a stray data byte
+                    if self.message.first().is_some_and(|&s| (0xf1..=0xf3).contains(&s)) {
+                        self.message.push(byte);
+                        return self.complete();
+                    }
+                    None
+                }
+            },
+        }
+    }
+    fn complete(&mut self) -> Option<Vec<u8>> {
+        let &status = self.message.first()?;
+        if status == 0xf0 || self.message.len() <= Parser::data_len(status) {
+            return None;
+        }
+        Some(std::mem::take(&mut self.message))
+    }
+}
+
+/// Appends n to bytes as a variable-length quantity
+fn push_vlq(bytes: &mut Vec<u8>, n: u64) {
+    let mut groups = vec![(n & 0x7f) as u8];
+    let mut n = n >> 7;
+    while n > 0 {
+        groups.push((n & 0x7f) as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+/// A standard MIDI file (format 0) that's kept complete after each message
+struct Capture {
+    file: File,
+    parser: Parser,
+    /// the offset of the end of the events (where the end of the track goes)
+    end: u64,
+    last_tick: u64,
+}
+impl Capture {
+    fn new(mut file: File) -> Result<Self, Error> {
+        let mut header = b"MThd\0\0\0\x06\0\0\0\x01".to_vec();
+        header.extend((TICKS_PER_QUARTER as u16).to_be_bytes());
+        header.extend(b"MTrk");
+        header.extend((END_OF_TRACK.len() as u32).to_be_bytes());
+        header.extend(END_OF_TRACK);
+        file.write_all(&header)?;
+        Ok(Capture { file, parser: Parser::default(), end: EVENTS_START, last_tick: 0 })
+    }
+    /// Turns a message into a track event
+    fn event(delta: u64, message: &[u8]) -> Vec<u8> {
+        let mut event = Vec::new();
+        push_vlq(&mut event, delta);
+        if message[0] == 0xf0 {
+            // a system exclusive message's length follows the F0
+            event.push(0xf0);
+            push_vlq(&mut event, message.len() as u64 - 1);
+            event.extend(&message[1..]);
+        } else {
+            event.extend(message);
+        }
+        event
+    }
+    fn write(&mut self, byte: u8, cycle: u64) -> Result<(), Error> {
+        let Some(message) = self.parser.push(byte) else {
+            return Ok(());
+        };
+        // real-time messages (and resets, which look like meta events) don't belong in a file
+        if message[0] >= 0xf8 {
+            return Ok(());
+        }
+        let tick = cycle * TICKS_PER_SECOND / CPU_HZ;
+        let mut event = Capture::event(tick.saturating_sub(self.last_tick), &message);
+        self.last_tick = tick.max(self.last_tick);
+        let track_len = self.end + event.len() as u64 + END_OF_TRACK.len() as u64 - EVENTS_START;
+        event.extend(END_OF_TRACK);
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&event)?;
+        self.end += (event.len() - END_OF_TRACK.len()) as u64;
+        self.file.seek(SeekFrom::Start(EVENTS_START - 4))?;
+        self.file.write_all(&(track_len as u32).to_be_bytes())?;
+        Ok(())
+    }
+}
+
+enum Output {
+    /// a MIDI port (or any other file) that gets the bytes as they're sent
+    Raw(File),
+    Capture(Capture),
+}
+
+pub struct MidiPak {
+    pub addr: u16,
+    control: u8,
+    /// the cycle at which the transmitter will have sent everything it's been given
+    busy_until: u64,
+    output: Output,
+}
+impl MidiPak {
+    pub fn new(addr: u16, path: &Path) -> Result<Self, Error> {
+        let capture = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("mid"));
+        let output = if capture {
+            Output::Capture(Capture::new(File::create(path)?)?)
+        } else {
+            Output::Raw(File::options().write(true).create(true).truncate(true).open(path)?)
+        };
+        info!("MIDI Pak enabled at {:04X}, sending to {}", addr, path.display());
+        Ok(MidiPak { addr, control: 0, busy_until: 0, output })
+    }
+    /// the transmit data register is empty once the byte before it is being shifted out
+    fn ready(&self, cycle: u64) -> bool { self.busy_until <= cycle + CYCLES_PER_BYTE }
+    /// Returns true if the MIDI Pak is requesting an interrupt (it's ready to send another byte
+    /// and the program has enabled the transmit interrupt)
+    pub fn irq(&self, cycle: u64) -> bool { self.control & TX_CONTROL == TX_INTERRUPT && self.ready(cycle) }
+    fn status(&self, addr: u16, cycle: u64) -> u8 {
+        if addr != self.addr {
+            // nothing is ever received
+            return 0;
+        }
+        let mut status = 0;
+        if self.ready(cycle) {
+            status |= TDRE;
+        }
+        if self.irq(cycle) {
+            status |= IRQ;
+        }
+        status
+    }
+    fn send(&mut self, addr: u16, byte: u8, cycle: u64) -> Result<(), Error> {
+        if addr == self.addr {
+            self.control = byte;
+            if byte & MASTER_RESET == MASTER_RESET {
+                self.busy_until = 0;
+            }
+            return Ok(());
+        }
+        self.busy_until = self.busy_until.max(cycle) + CYCLES_PER_BYTE;
+        match &mut self.output {
+            Output::Raw(file) => file.write_all(&[byte])?,
+            Output::Capture(capture) => capture.write(byte, cycle)?,
+        }
+        Ok(())
+    }
+}
+impl Cartridge for MidiPak {
+    fn name(&self) -> String { format!("MIDI Pak at {:04X}", self.addr) }
+    fn owns_address(&self, addr: u16) -> bool { addr == self.addr || addr == self.addr + 1 }
+    fn read(&self, addr: u16, cycle: u64) -> Option<u8> { self.owns_address(addr).then(|| self.status(addr, cycle)) }
+    fn write(&mut self, addr: u16, byte: u8, cycle: u64) -> Result<bool, Error> {
+        let owned = self.owns_address(addr);
+        if owned {
+            self.send(addr, byte, cycle)?;
+        }
+        Ok(owned)
+    }
+    // the MIDI Pak's interrupt pulls the CART line
+    fn tick(&mut self, cycle: u64) -> Option<Line> { self.irq(cycle).then_some(Line::Cart) }
+    fn tag(&self) -> [u8; 4] { *b"MIDI" }
+    /// The control register and the cycles until the transmitter is idle (what was sent stays sent)
+    fn snapshot(&self, cycle: u64) -> Option<Vec<u8>> {
+        let mut state = vec![self.control];
+        state.extend(self.busy_until.saturating_sub(cycle).to_be_bytes());
+        Some(state)
+    }
+    fn check_snapshot(&self, state: &[u8]) -> Result<(), Error> { snapshot::check_len(&self.tag(), state, 9) }
+    fn restore(&mut self, state: &[u8], cycle: u64) {
+        self.control = state[0];
+        self.busy_until = cycle + u64::from_be_bytes(state[1..9].try_into().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let mut parser = Parser::default();
+        // a note on, another with running status, a clock in the middle of a program change
+        // and a system exclusive message
+        let stream = [0x90, 0x3c, 0x40, 0x3e, 0x40, 0xc1, 0xf8, 0x05, 0xf0, 0x43, 0x12, 0xf7, 0x7f];
+        let messages: Vec<Vec<u8>> = stream.iter().filter_map(|&b| parser.push(b)).collect();
+        let expected: Vec<Vec<u8>> =
+            vec![vec![0x90, 0x3c, 0x40], vec![0x90, 0x3e, 0x40], vec![0xf8], vec![0xc1, 0x05], vec![0xf0, 0x43, 0x12, 0xf7]];
+        assert_eq!(messages, expected);
+        let mut bytes = Vec::new();
+        push_vlq(&mut bytes, 0x3fff);
+        push_vlq(&mut bytes, 0x40);
+        assert_eq!(bytes, [0xff, 0x7f, 0x40]);
+        assert_eq!(Capture::event(200, &[0xf0, 0x43, 0xf7]), [0x81, 0x48, 0xf0, 0x02, 0x43, 0xf7]);
+    }
+}
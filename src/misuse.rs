@@ -0,0 +1,73 @@
+//! What happens when a program asks a device for something it can't do.
+//!
+//! The devices return an `Error` of kind `ErrorKind::Device` (a SAM register that doesn't exist,
+//! VRAM that would run past the end of RAM) instead of panicking; panics are left for the
+//! emulator's own bugs. When the debugger is enabled the error is passed on so that the CPU
+//! faults and the debugger stops at the instruction that did it. Otherwise the error is logged
+//! (once per message so a program stuck in a loop doesn't flood the console) and the access is
+//! ignored.
+//!
+//! The UI thread can't stop the core so it only logs (see `DeviceManager::update`).
+use super::*;
+
+impl Core {
+    /// Applies the device misuse policy to an error returned by a device
+    pub fn misuse(&self, e: Error) -> Result<(), Error> {
+        if config::debug() {
+            return Err(Error { ctx: e.ctx.or(Some(self.reg)), ..e });
+        }
+        self.misuse.borrow_mut().warn(&e);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::AccessType;
+    use std::sync::{Arc, RwLock};
+
+    #[test]
+    fn sam_register() {
+        let mut sam = sam::Sam::new();
+        assert!(sam.write(31).is_ok());
+        assert_eq!(sam.write(32).unwrap_err().kind, ErrorKind::Device);
+    }
+
+    #[test]
+    fn vram_offset() {
+        let ram = Arc::new(RwLock::new(vec![0u8; 0x10000]));
+        let mut vdg = vdg::Vdg::with_ram(ram, 0x400);
+        // the SAM's F bits can put VRAM at $FE00 but there isn't room for it there
+        assert_eq!(vdg.set_vram_offset(0xfe00).unwrap_err().kind, ErrorKind::Device);
+        assert!(vdg.set_vram_offset(0x10000 - 6144).is_ok());
+    }
+
+    #[test]
+    fn zero_vector() {
+        let mut core = Core::headless().unwrap();
+        core.raw_ram[0xbffc..0xbffe].copy_from_slice(&[0x31, 0x00]);
+        core.reg.s = 0x7000;
+        let e = core.start_interrupt(core::InterruptType::Nmi).unwrap_err();
+        assert_eq!(e.kind, ErrorKind::Runtime);
+    }
+
+    #[test]
+    fn dac_without_audio() {
+        // a headless core has no audio device listening for the DAC
+        let mut core = Core::headless().unwrap();
+        for (addr, data) in [(0xff23, 0x3c), (0xff21, 0x04), (0xff20, 0xfc), (0xff22, 0x02)] {
+            core._write_u8(AccessType::System, addr, data).unwrap();
+        }
+    }
+
+    #[test]
+    fn logged_once() {
+        let core = Core::headless().unwrap();
+        let e = || Error::new(ErrorKind::Device, None, "no such register");
+        assert!(core.misuse(e()).is_ok());
+        assert!(core.misuse(e()).is_ok());
+        assert!(!core.misuse.borrow_mut().warn(&e()));
+        assert!(core.misuse.borrow_mut().warn(&Error::new(ErrorKind::Device, None, "another")));
+    }
+}
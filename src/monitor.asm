@@ -0,0 +1,371 @@
+* coco's built-in machine language monitor
+*
+* This is what coco runs when it's given no ROMs, cartridge or program. It lives where Color
+* BASIC would be and takes commands typed on the CoCo's keyboard:
+*   E [addr]            examine 32 bytes (from where the last E or M left off without addr)
+*   M addr byte...      put bytes into memory
+*   G [addr]            call a program (RTS comes back to the monitor)
+*   H                   list the commands
+* Numbers are hex. LEFT ARROW (or backspace) rubs out a character.
+
+sam     equ $ffc0
+pia0    equ $ff00
+vram    equ $400
+vramend equ $600
+
+* the monitor's variables are kept below its stack (which is just below video RAM)
+cur     equ $100            ; where the next character goes on the screen
+lastkey equ $102            ; the key that was down the last time the keyboard was read
+col     equ $103            ; the keyboard column being scanned
+val     equ $104            ; the number being read by gethex
+digits  equ $106            ; the number of hex digits gethex has read
+addr    equ $107            ; the address E and M left off at (or G last called)
+lines   equ $109            ; lines left to show for E
+linebuf equ $110            ; the line being typed
+linemax equ 28              ; the longest line that can be typed
+
+        org $a000
+start:
+        orcc #$50           ; no interrupts (there are no vectors to handle them)
+        lds #vram
+* show video RAM at $400 (as BASIC does)
+        sta sam+9
+* set pia0-b as outputs to strobe the keyboard's columns
+        lda #$ff
+        sta pia0+2
+        ldb #$34
+        stb pia0+1
+        stb pia0+3
+        clr lastkey
+        clr addr
+        clr addr+1
+* clear the screen
+        ldx #vram
+        lda #$60
+cls:
+        sta ,x+
+        cmpx #vramend
+        blo cls
+        ldx #vram
+        stx cur
+        ldy #banner
+        jsr puts
+        jsr help
+
+* read a command and carry it out
+prompt:
+        lda #$3e
+        jsr putc
+        jsr getline
+        ldy #linebuf
+        jsr skipsp
+        lda ,y+
+        beq prompt
+        cmpa #'E
+        lbeq cmd_e
+        cmpa #'M
+        lbeq cmd_m
+        cmpa #'G
+        lbeq cmd_g
+        cmpa #'H
+        beq cmd_h
+what:
+        ldy #msgwhat
+        jsr puts
+        bra prompt
+
+cmd_h:
+        jsr help
+        bra prompt
+
+cmd_e:
+        jsr gethex
+        bcs e_from
+        std addr
+e_from:
+        lda #4
+        sta lines
+e_line:
+        ldd addr
+        jsr outword
+        lda #$3a
+        jsr putc
+        ldx addr
+        ldb #8
+e_byte:
+        lda #$20
+        jsr putc
+        lda ,x+
+        jsr outbyte
+        decb
+        bne e_byte
+        stx addr
+        lda #$0d
+        jsr putc
+        dec lines
+        bne e_line
+        jmp prompt
+
+cmd_m:
+        jsr gethex
+        bcs what
+        std addr
+        tfr d,x
+m_byte:
+        jsr gethex
+        lbcs prompt
+        stb ,x+
+        bra m_byte
+
+cmd_g:
+        jsr gethex
+        bcc g_call
+        ldd addr
+g_call:
+        std addr
+        tfr d,x
+        jsr ,x
+        jmp prompt
+
+* lists the commands
+help:
+        ldy #msghelp
+        jmp puts
+
+* reads a line into linebuf (ending with 0) and echoes it on the screen
+getline:
+        ldx #linebuf
+gl_show:
+        ldy cur
+        lda #$20            ; an inverse space is the cursor
+        sta ,y
+gl_wait:
+        jsr getkey
+        tsta
+        beq gl_wait
+        cmpa #$0d
+        beq gl_end
+        cmpa #$08
+        beq gl_rubout
+        cmpx #linebuf+linemax
+        bhs gl_wait
+        sta ,x+
+        jsr putc
+        bra gl_show
+gl_rubout:
+        cmpx #linebuf
+        beq gl_wait
+        leax -1,x
+        ldy cur
+        lda #$60
+        sta ,y
+        leay -1,y
+        sty cur
+        bra gl_show
+gl_end:
+        clr ,x
+        jmp putc
+
+* returns the key that's just been pressed in A (as ASCII) or 0 if there isn't one
+getkey:
+        pshs b,x
+        ldb #$fe
+        clr col
+gk_col:
+        stb pia0+2
+        lda pia0
+        coma
+        anda #$7f           ; bit 7 is the joystick comparator
+        beq gk_next
+        pshs b
+        ldx #keytab
+        ldb col
+        abx
+gk_row:
+        lsra
+        bcc gk_skip
+        ldb ,x
+        bne gk_found
+gk_skip:
+        leax 8,x
+        tsta
+        bne gk_row
+        puls b
+gk_next:
+        comb
+        aslb
+        comb
+        inc col
+        lda col
+        cmpa #8
+        blo gk_col
+        clra
+        bra gk_new
+gk_found:
+        leas 1,s
+        tfr b,a
+* only a key that wasn't down the last time counts
+gk_new:
+        cmpa lastkey
+        beq gk_same
+        sta lastkey
+        puls b,x,pc
+gk_same:
+        clra
+        puls b,x,pc
+
+* skips the spaces at Y
+skipsp:
+        lda ,y
+        cmpa #$20
+        bne ss_end
+        leay 1,y
+        bra skipsp
+ss_end:
+        rts
+
+* reads a hex number at Y into D (carry is set if there isn't one)
+gethex:
+        bsr skipsp
+        clr val
+        clr val+1
+        clr digits
+gh_digit:
+        lda ,y
+        cmpa #'0
+        blo gh_end
+        cmpa #'9
+        bls gh_dec
+        cmpa #'A
+        blo gh_end
+        cmpa #'F
+        bhi gh_end
+        suba #7
+gh_dec:
+        suba #'0
+        leay 1,y
+        ldb #4
+gh_shift:
+        asl val+1
+        rol val
+        decb
+        bne gh_shift
+        ora val+1
+        sta val+1
+        inc digits
+        bra gh_digit
+gh_end:
+        ldd val
+        andcc #$fe
+        tst digits
+        bne gh_ok
+        orcc #1
+gh_ok:
+        rts
+
+* shows D in hex
+outword:
+        pshs b
+        bsr outbyte
+        puls a
+* shows A in hex
+outbyte:
+        pshs a
+        lsra
+        lsra
+        lsra
+        lsra
+        bsr outnib
+        puls a
+        anda #$0f
+outnib:
+        adda #'0
+        cmpa #'9
+        bls putc
+        adda #7
+* shows the character in A (ASCII; CR starts a new line) and scrolls at the bottom
+putc:
+        pshs a,b,x
+        cmpa #$0d
+        beq pc_cr
+        cmpa #$40
+        bhs pc_store
+        adda #$40           ; the VDG shows $60-$7F as space to '?'
+pc_store:
+        ldx cur
+        sta ,x+
+        cmpx #vramend
+        blo pc_done
+pc_scroll:
+        bsr scroll
+        ldx #vramend-32
+pc_done:
+        stx cur
+        puls a,b,x,pc
+pc_cr:
+        ldx cur
+        lda #$60
+        sta ,x              ; rub out the cursor
+        tfr x,d
+        andb #$e0
+        addd #32
+        tfr d,x
+        cmpx #vramend
+        blo pc_done
+        bra pc_scroll
+
+* moves the screen up a line
+scroll:
+        pshs a,x
+        ldx #vram
+sc_move:
+        lda 32,x
+        sta ,x+
+        cmpx #vramend-32
+        blo sc_move
+        lda #$60
+sc_clear:
+        sta ,x+
+        cmpx #vramend
+        blo sc_clear
+        puls a,x,pc
+
+* shows the string at Y (ending with 0)
+puts:
+        lda ,y+
+        beq ps_end
+        bsr putc
+        bra puts
+ps_end:
+        rts
+
+* ASCII for each key (by row and then column); 0 for the keys that are ignored
+keytab:
+        fcc "@ABCDEFG"
+        fcc "HIJKLMNO"
+        fcc "PQRSTUVW"
+        fcb $58,$59,$5a,0,0,$08,0,$20
+        fcc "01234567"
+        fcb $38,$39,$3a,$3b,$2c,$2d,$2e,$2f
+        fcb $0d,0,0,0,0,0,0,0
+banner:
+        fcc "COCO MONITOR"
+        fcb $0d,0
+msghelp:
+        fcc "E [ADDR]  EXAMINE MEMORY"
+        fcb $0d
+        fcc "M ADDR BYTE...  CHANGE MEMORY"
+        fcb $0d
+        fcc "G [ADDR]  GO (RTS RETURNS)"
+        fcb $0d
+        fcc "H  HELP"
+        fcb $0d,0
+msgwhat:
+        fcc "WHAT?"
+        fcb $0d,0
+
+* point the reset vector at the monitor (and make it the entry point, so that it still boots
+* with --monitor when a program without an entry point of its own is loaded after it)
+        org $bffe
+        fdb start
+        end start
@@ -0,0 +1,63 @@
+//! The built-in machine language monitor.
+//!
+//! Without ROMs, a cartridge or a program there's nothing for the CPU to run, so coco boots
+//! into a small monitor instead (see monitor.asm for its commands). It's assembled from the
+//! source that's built into coco, so it needs no ROM images. --monitor loads it even when there's
+//! something else to run. It isn't a Color BASIC replacement: programs that call into BASIC's
+//! ROM still need the real ROMs.
+use super::*;
+
+// The source is assembled when the monitor is loaded rather than by build.rs: the assembler is
+// part of this crate (and needs the instruction table that build.rs generates), so it isn't
+// available to the build script. The test below catches any error in monitor.asm instead.
+const SOURCE: &str = include_str!("monitor.asm");
+
+/// Returns true if coco has been given nothing to run (so the monitor should be loaded)
+pub fn wanted() -> bool {
+    let config = config::ARGS.config_file.as_ref();
+    let roms = config.and_then(|c| c.load_rom.as_ref()).is_some_and(|r| !r.is_empty());
+    let code = config.and_then(|c| c.load_code.as_ref()).is_some_and(|c| !c.is_empty());
+    let given = config::roms_replaced() || config::ARGS.reset_vector.is_some();
+    !roms && !code && !given && config::ARGS.cart.is_none() && config::ARGS.load.is_empty()
+}
+
+fn assemble() -> Result<program::Program, Error> { Assembler::new().assemble_str(SOURCE) }
+
+impl Core {
+    /// Loads the monitor (it sets the reset vector to itself) and gives its labels to the debugger
+    pub fn load_monitor(&mut self) -> Result<(), Error> {
+        let program = assemble()?;
+        self.load_program(&program, None)?;
+        if config::auto_load_syms() {
+            let labels = program.code_labels();
+            labels.iter().for_each(|&(addr, name)| self.add_symbol(addr, name));
+            info!("Auto-loaded {} symbols.", labels.len());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn boots() {
+        let program = assemble().unwrap();
+        assert!(program.code_labels().contains(&(0xa000, "start")));
+        let mut core = Core::headless().unwrap();
+        core.load_program(&program, None).unwrap();
+        core.reset().unwrap();
+        assert!(core.wait_for_text("H  HELP", Duration::from_secs(1)).unwrap());
+        // the banner, then the help and the prompt (with the cursor after it) below it
+        assert!(core.screen_shows("COCO MONITOR"));
+        assert_eq!(core.raw_ram[0x4a0..0x4a2], [0x7e, 0x20]);
+        // a program loaded after it (as with --monitor --load) doesn't take the reset vector away
+        let mut core = Core::headless().unwrap();
+        core.load_monitor().unwrap();
+        core.load_program(&Assembler::new().assemble_str(" org $3000\n nop\n").unwrap(), None).unwrap();
+        core.reset().unwrap();
+        assert_eq!(core.reg.pc, 0xa000);
+    }
+}
@@ -1,5 +1,6 @@
 use super::instructions::*;
-use super::parse::{IncDecType, LabelResolver, OperandDescriptor, ValueNode};
+use super::expr::{LabelResolver, ValueNode};
+use super::parse::{IncDecType, OperandDescriptor};
 use super::*;
 
 /// The assembler translates each assembly language statement into a BinaryObject.
@@ -72,6 +73,7 @@ pub struct Instruction {
     trying_direct: bool,
 }
 impl Instruction {
+    #[allow(clippy::unnecessary_map_or)]
     pub fn try_new(
         id: &'static instructions::Descriptor, od: OperandDescriptor, addr: u16, lr: &dyn LabelResolver, dp_dirty: bool,
     ) -> Result<Self, Error> {
@@ -0,0 +1,140 @@
+//! Pages long output (such as the program listing) when stdout is a terminal.
+//!
+//! Colors in the text are only kept for the terminal (they're removed when stdout isn't a
+//! terminal and for $PAGER). If $PAGER is set then the text is piped to it. Otherwise a simple
+//! built-in pager is used:
+//! ```text
+//! <space>        next page            /text   search forward (case insensitive)
+//! <enter>        next line            n       next match
+//! b              previous page        g       go to a symbol or line number
+//! q              quit
+//! ```
+use super::*;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+const DEFAULT_HEIGHT: usize = 24;
+
+/// Removes the escape sequences that color text
+pub fn strip_colors(text: &str) -> String {
+    lazy_static::lazy_static! {
+        static ref RE_COLOR: regex::Regex = regex::Regex::new("\x1b\\[[0-9;]*m").unwrap();
+    }
+    RE_COLOR.replace_all(text, "").into_owned()
+}
+
+/// Returns the index of the first line at or after from that contains pattern (ignoring case)
+fn find(lines: &[&str], from: usize, pattern: &str) -> Option<usize> {
+    let pattern = pattern.to_ascii_lowercase();
+    (from..lines.len()).find(|&i| strip_colors(lines[i]).to_ascii_lowercase().contains(&pattern))
+}
+
+/// Resolves the target of a "go to" command: a symbol (optionally prefixed with '?') or a line number
+fn jump_target(target: &str, symbols: &HashMap<String, usize>) -> Option<usize> {
+    let target = target.trim();
+    let sym = target.strip_prefix('?').unwrap_or(target);
+    symbols
+        .get(sym)
+        .or_else(|| symbols.iter().find(|(s, _)| s.eq_ignore_ascii_case(sym)).map(|(_, i)| i))
+        .copied()
+        .or_else(|| target.parse::<usize>().ok().map(|n| n.saturating_sub(1)))
+}
+
+fn prompt(text: &str) -> Option<String> {
+    print!("{}", text);
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).ok()?;
+    Some(line.trim_end().to_string())
+}
+
+/// Writes text to stdout, one page at a time if stdout is a terminal. Symbols maps names to
+/// line indices for the built-in pager's go to command.
+pub fn page(text: &str, symbols: &HashMap<String, usize>) -> Result<(), Error> {
+    let height = std::env::var("LINES")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_HEIGHT)
+        .max(4)
+        - 1;
+    let lines: Vec<&str> = text.lines().collect();
+    if !io::stdout().is_terminal() {
+        print!("{}", strip_colors(text));
+        return Ok(());
+    }
+    if config::ARGS.no_pager || lines.len() <= height {
+        print!("{}", text);
+        return Ok(());
+    }
+    if let Some(pager) = std::env::var("PAGER").ok().filter(|p| !p.trim().is_empty()) {
+        let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+        let mut child = Command::new(shell).args([flag, &pager]).stdin(Stdio::piped()).spawn()?;
+        // the pager may quit before reading everything
+        _ = child.stdin.take().unwrap().write_all(strip_colors(text).as_bytes());
+        child.wait()?;
+        return Ok(());
+    }
+    let mut top = 0usize;
+    let mut count = height;
+    let mut pattern: Option<String> = None;
+    loop {
+        for line in lines.iter().skip(top).take(count) {
+            println!("{}", line);
+        }
+        top = (top + count).min(lines.len());
+        if top >= lines.len() {
+            break;
+        }
+        print!("\x1b[7m-- {}/{} (space, enter, b, /, n, g, q) --\x1b[0m\r", top, lines.len());
+        io::stdout().flush()?;
+        let key = term::get_keyboard_input(true, true);
+        print!("\x1b[2K");
+        count = height;
+        match key {
+            Some(b'q') | Some(b'Q') | Some(3) | None => break,
+            Some(b'\r') | Some(b'\n') => count = 1,
+            Some(b'b') | Some(b'B') => top = top.saturating_sub(2 * height),
+            Some(b'/') | Some(b'n') => {
+                if key == Some(b'/') {
+                    pattern = prompt("/").filter(|p| !p.is_empty()).or(pattern);
+                }
+                // a search starts on the line after the top of the current page
+                let from = top.saturating_sub(height) + 1;
+                match pattern.as_deref().and_then(|p| find(&lines, from, p)) {
+                    Some(i) => top = i,
+                    None => {
+                        println!("Pattern not found");
+                        top = top.saturating_sub(height);
+                    }
+                }
+            }
+            Some(b'g') | Some(b'G') => match prompt("go to symbol or line: ").and_then(|t| jump_target(&t, symbols)) {
+                Some(i) => top = i.min(lines.len() - 1),
+                None => {
+                    println!("Not found");
+                    top = top.saturating_sub(height);
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_and_jump() {
+        let lines = ["  1 3F00 START LDA #1", "  2 3F02 LOOP DECA", "  3 3F03 BNE LOOP"];
+        assert_eq!(find(&lines, 0, "loop"), Some(1));
+        assert_eq!(find(&lines, 2, "loop"), Some(2));
+        assert_eq!(find(&lines, 0, "rts"), None);
+        let symbols = HashMap::from([("START".to_string(), 0), ("LOOP".to_string(), 1)]);
+        assert_eq!(jump_target("?loop", &symbols), Some(1));
+        assert_eq!(jump_target("START", &symbols), Some(0));
+        assert_eq!(jump_target("3", &symbols), Some(2));
+        assert_eq!(jump_target("nowhere", &symbols), None);
+    }
+}
@@ -1,3 +1,4 @@
+use super::expr::{Compare, Condition, LabelResolver, ValueNode};
 use super::instructions::AddressingMode;
 use super::test::{AddrOrVal, RegOrAddr, TestCriterion};
 
@@ -27,6 +28,8 @@ pub enum TokenType {
     RParen,
     LAngle,
     RAngle,
+    Equal,    // = or == (only in conditions)
+    NotEqual, // != (only in conditions)
 }
 impl From<Token> for TokenType {
     fn from(item: Token) -> Self { item.ttype }
@@ -37,11 +40,11 @@ impl From<Token> for TokenType {
 #[derive(Debug)]
 pub struct Token {
     /// the type of this token
-    ttype: TokenType,
+    pub ttype: TokenType,
     /// raw source string that resulted in creation of this token
-    raw: String,
+    pub raw: String,
     /// if token is numeric then this holds the value
-    value: Option<u8u16>,
+    pub value: Option<u8u16>,
 }
 impl Token {
     pub fn clean(&self) -> String {
@@ -66,158 +69,6 @@ impl Display for Token {
         write!(f, "{:?}\"{}\"<--\"{}\"", self.ttype, self.clean(), self.raw)
     }
 }
-pub trait LabelResolver {
-    fn resolve(&self, label: &str) -> Option<u8u16>;
-}
-
-/// Each value expression is parsed and converted into a tree of ValueNode objects.
-/// The tree could consist of only a root ValueNode containing a value or it may be
-/// arbitrarily deep, in which case the root ValueNode's token will be an operator.
-#[derive(Debug)]
-pub struct ValueNode {
-    /// either the node's value (e.g. label or number) or an operation
-    pub token: Token,
-    /// true if the node's value should be negated after resolution
-    pub negate: bool,
-    /// child nodes
-    left: Option<Box<ValueNode>>,
-    right: Option<Box<ValueNode>>,
-}
-impl ValueNode {
-    pub fn new(val_or_op: Token, negate: bool, left: Option<ValueNode>, right: Option<ValueNode>) -> Self {
-        ValueNode {
-            token: val_or_op,
-            negate,
-            left: left.map(Box::new),
-            right: right.map(Box::new),
-        }
-    }
-    /// Evaluate this ValueNode given an address and LabelResolver.
-    /// If this ValueNode (or its children) require a label that the LabelResolver can't resolve
-    /// then the result will be an Error of ErrorKind::Reference.
-    // Indicate signed = true if the outcome of an evaluation MIGHT be signed.
-    pub fn eval(&self, lr: &dyn LabelResolver, addr: u16, signed: bool) -> Result<u8u16, Error> {
-        match self.token.ttype {
-            TokenType::Number => {
-                let v = self.token.value.unwrap();
-                if signed || self.negate {
-                    let (u, _) = v.force_signed(self.negate);
-                    Ok(u)
-                } else {
-                    Ok(v)
-                }
-            }
-            TokenType::Label => {
-                if let Some(v) = lr.resolve(self.token.clean().as_str()) {
-                    if signed || self.negate {
-                        let (u, _) = v.force_signed(self.negate);
-                        Ok(u)
-                    } else {
-                        Ok(v)
-                    }
-                } else {
-                    Err(Error::new(
-                        ErrorKind::Reference,
-                        None,
-                        format!("unresolved label \"{}\"", self.token.clean()).as_str(),
-                    ))
-                }
-            }
-            TokenType::Add | TokenType::Sub | TokenType::Star | TokenType::Div | TokenType::Mod | TokenType::Pow => {
-                if let Some(left) = &self.left {
-                    if let Some(right) = &self.right {
-                        return self._eval_binary(lr, addr, signed, left, right);
-                    }
-                } else if self.token.ttype == TokenType::Star {
-                    // this is a location reference; it has no child nodes
-                    // use the supplied addr as the value for the special label "*"
-                    return Ok(u8u16::u16(addr));
-                }
-                Err(Error::new(
-                    ErrorKind::Syntax,
-                    None,
-                    format!("missing operand(s) for binary operation \"{}\"", self.token.clean()).as_str(),
-                ))
-            }
-            _ => Err(Error::new(
-                ErrorKind::Syntax,
-                None,
-                format!("can't evaluate \"{}\"", self.token).as_str(),
-            )),
-        }
-    }
-    fn _eval_binary(
-        &self, lr: &dyn LabelResolver, addr: u16, signed: bool, left: &ValueNode, right: &ValueNode,
-    ) -> Result<u8u16, Error> {
-        let lhs = left.eval(lr, addr, signed)?;
-        let rhs = right.eval(lr, addr, signed)?;
-        // TODO: warn about overflow in these operations
-        match self.token.ttype {
-            TokenType::Add => {
-                let (u, f) = lhs.u16().overflowing_add(rhs.u16());
-                if f {
-                    Err(syntax_err!("addition overflow"))
-                } else {
-                    Ok(u8u16::from_u16_shrink(u))
-                }
-            }
-            TokenType::Sub => {
-                let (u, f) = lhs.u16().overflowing_sub(rhs.u16());
-                if f {
-                    Err(syntax_err!("subtraction overflow"))
-                } else {
-                    Ok(u8u16::from_u16_shrink(u))
-                }
-            }
-            TokenType::Star => {
-                let (u, f) = lhs.u16().overflowing_mul(rhs.u16());
-                if f {
-                    Err(syntax_err!("multiplication overflow"))
-                } else {
-                    Ok(u8u16::from_u16_shrink(u))
-                }
-            }
-            TokenType::Div => Ok(lhs.div(rhs)),
-            TokenType::Mod => Ok(lhs.modulo(rhs)),
-            TokenType::Pow => {
-                let (u, f) = lhs.u16().overflowing_pow(rhs.u16() as u32);
-                if f {
-                    Err(syntax_err!("exponential overflow"))
-                } else {
-                    Ok(u8u16::from_u16_shrink(u))
-                }
-            }
-            _ => unreachable!(),
-        }
-    }
-}
-impl Display for ValueNode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let r;
-        if self.left.is_some() && self.right.is_some() {
-            r = write!(
-                f,
-                "({}{} {} {})",
-                if self.negate { "-" } else { "" },
-                self.left.as_ref().unwrap(),
-                self.token.clean(),
-                self.right.as_ref().unwrap()
-            );
-        } else if self.left.is_some() {
-            r = write!(
-                f,
-                "ERROR? {} LEFT: {} ",
-                self.token.clean(),
-                self.left.as_ref().unwrap()
-            );
-        } else if self.negate {
-            r = write!(f, "-{}", self.token.clean());
-        } else {
-            r = write!(f, "{}", self.token.clean());
-        }
-        r
-    }
-}
 #[derive(Debug)]
 pub enum IncDecType {
     Dec = 1,
@@ -255,8 +106,8 @@ impl OperandDescriptor {
 impl Display for OperandDescriptor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<{:?}> ", self.mode,)?;
-        let value = if self.value.is_some() {
-            format!("{}", self.value.as_ref().unwrap())
+        let value = if let Some(v) = self.value.as_ref() {
+            format!("{}", v)
         } else {
             "".to_string()
         };
@@ -320,7 +171,7 @@ impl Parser {
     ///  valexpr (l-to-r) = powexpr [<addop|mulop> powexpr]
     ///  mulexpr = powexpr [mulop powexpr]
     ///  powexpr = "-" powexpr | "+" powexpr | atom ["^" powexpr]
-    ///  atom = label | number | "*" | "(" valexpr ")"
+    ///  atom = label | number | "*" | "(" valexpr ")" | reg | "[" valexpr "]"
     ///  number = /\d{1,5}/ | /$\x{1,4}/ | /%{1,16}/
     ///  label = /[a-zA-Z][$_a-zA-Z0-9]+/
     ///  incdec = reg "+" | reg "++" | "-" reg | "--" reg
@@ -637,14 +488,22 @@ impl Parser {
     }
     /// Parse an atom.
     /// ```text
-    ///     atom ::= label | number | '*' | '(' valexpr ')'
+    ///     atom ::= label | number | '*' | '(' valexpr ')' | reg | '[' valexpr ']'
     /// ```
+    /// Registers and memory ("[" valexpr "]") can only be evaluated by the debugger (see expr.rs).
     fn parse_atom(&self, token_iter: &mut TokenIter) -> Result<ValueNode, Error> {
         if let Some(token) = token_iter.next() {
             match token.ttype {
-                TokenType::Number | TokenType::Label | TokenType::Star => {
+                TokenType::Number | TokenType::Label | TokenType::Star | TokenType::Register => {
                     return Ok(ValueNode::new(token, false, None, None));
                 }
+                TokenType::LBracket => {
+                    let node = self.parse_valexpr(token_iter)?;
+                    if token_iter.next().filter(|t| t.ttype == TokenType::RBracket).is_some() {
+                        return Ok(ValueNode::new(token, false, Some(node), None));
+                    }
+                    return Err(syntax_err!("missing closing bracket"));
+                }
                 TokenType::LParen => {
                     let node = self.parse_valexpr(token_iter)?;
                     if let Some(rparen) = token_iter.next() {
@@ -663,14 +522,50 @@ impl Parser {
         let tokens = self.tokenize(expr)?;
         self.parse_valexpr(&mut tokens.into_iter().peekable())
     }
+    /// Parses a condition (see expr.rs). A '#' before the right-hand side is allowed (and ignored)
+    /// so conditions can be written like test criteria.
+    /// ```text
+    ///     condition ::= valexpr [cmp ['#'] valexpr]
+    ///     cmp ::= "=" | "==" | "!=" | "<" | "<=" | ">" | ">="
+    /// ```
+    pub fn parse_condition(&self, src: &str) -> Result<Condition, Error> {
+        let tokens = self.tokenize(src)?;
+        let mut token_iter = tokens.into_iter().peekable();
+        let lhs = self.parse_valexpr(&mut token_iter)?;
+        let or_equal = |token_iter: &mut TokenIter| token_iter.next_if(|t| t.ttype == TokenType::Equal).is_some();
+        let cmp = match token_iter.next() {
+            None => return Ok(Condition { lhs, cmp: Compare::Ne, rhs: None }),
+            Some(t) => match t.ttype {
+                TokenType::Equal => Compare::Eq,
+                TokenType::NotEqual => Compare::Ne,
+                TokenType::LAngle if or_equal(&mut token_iter) => Compare::Le,
+                TokenType::LAngle => Compare::Lt,
+                TokenType::RAngle if or_equal(&mut token_iter) => Compare::Ge,
+                TokenType::RAngle => Compare::Gt,
+                _ => return Err(syntax_err!(format!("expected a comparison but found \"{}\"", t.raw))),
+            },
+        };
+        token_iter.next_if(|t| t.ttype == TokenType::Hash);
+        let rhs = self.parse_valexpr(&mut token_iter)?;
+        if let Some(t) = token_iter.next() {
+            return Err(syntax_err!(format!("unexpected token \"{}\"", t.raw)));
+        }
+        Ok(Condition { lhs, cmp, rhs: Some(rhs) })
+    }
     /// Parse a string for a test criterion and populate the given TestCriterion object.
     /// ErrorKind::Reference is returned when unresolved labels are encountered
     ///
     pub fn parse_test_criterion(&self, tc: &mut TestCriterion, lr: &dyn LabelResolver) -> Result<(), Error> {
-        let mut tokens = self.tokenize(&tc.lhs_src)?;
+        // try to get the lhs; start by looking for a condition code flag (e.g., cc.z)
+        let flag = tc.lhs_src.to_ascii_lowercase().strip_prefix("cc.").and_then(|f| {
+            let mut chars = f.chars();
+            chars.next().filter(|_| chars.next().is_none()).and_then(registers::CCBit::from_char)
+        });
+        let mut tokens = if flag.is_some() { Vec::new() } else { self.tokenize(&tc.lhs_src)? };
         let mut token_iter = tokens.into_iter().peekable();
-        // try to get the lhs; start by looking for a register
-        if token_iter.peek().filter(|t| t.ttype == TokenType::Register).is_some() {
+        if let Some(bit) = flag {
+            tc.lhs = Some(RegOrAddr::Flag(bit));
+        } else if token_iter.peek().filter(|t| t.ttype == TokenType::Register).is_some() {
             // consume the register token
             let reg = token_iter.next().unwrap();
             tc.lhs = Some(RegOrAddr::Reg(registers::Name::from_str(&reg.clean())));
@@ -830,6 +725,19 @@ impl Parser {
                     output.push(Token::new(TokenType::RAngle, ">".to_string(), None));
                     current = chars.next();
                 }
+                '=' => {
+                    output.push(Token::new(TokenType::Equal, "=".to_string(), None));
+                    current = chars.next();
+                    // == is the same as =
+                    if current == Some('=') {
+                        current = chars.next();
+                    }
+                }
+                '!' if chars.clone().next() == Some('=') => {
+                    output.push(Token::new(TokenType::NotEqual, "!=".to_string(), None));
+                    chars.next();
+                    current = chars.next();
+                }
                 '(' => {
                     output.push(Token::new(TokenType::LParen, "(".to_string(), None));
                     current = chars.next();
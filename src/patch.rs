@@ -0,0 +1,82 @@
+//! Memory patches applied after ROMs, cartridges and programs have been loaded.
+//!
+//! Patches are listed in the config file. Each one is either a list of bytes to write at an
+//! address or an IPS file whose offsets are relative to the given address (typically the
+//! address at which the patched ROM was loaded).
+use super::*;
+
+/// Returns the (offset, bytes) records contained in an IPS patch
+pub fn parse_ips(ips: &[u8]) -> Result<Vec<(usize, Vec<u8>)>, Error> {
+    if !ips.starts_with(b"PATCH") {
+        return Err(general_err!("missing IPS header"));
+    }
+    let mut records = Vec::new();
+    let mut i = 5;
+    let field = |i: usize, len: usize| -> Result<usize, Error> {
+        match ips.get(i..i + len) {
+            Some(b) => Ok(b.iter().fold(0, |acc, &b| acc << 8 | b as usize)),
+            None => Err(general_err!("IPS patch is truncated at offset {}", i)),
+        }
+    };
+    loop {
+        if ips.get(i..i + 3) == Some(b"EOF") {
+            break;
+        }
+        let offset = field(i, 3)?;
+        let size = field(i + 3, 2)?;
+        i += 5;
+        if size == 0 {
+            // run-length encoded record
+            let count = field(i, 2)?;
+            let value = field(i + 2, 1)? as u8;
+            records.push((offset, vec![value; count]));
+            i += 3;
+        } else {
+            field(i + size - 1, 1)?;
+            records.push((offset, ips[i..i + size].to_vec()));
+            i += size;
+        }
+    }
+    Ok(records)
+}
+
+impl Core {
+    /// Applies the patches listed in the config file. Patches may write to ROM.
+    pub fn apply_patches(&mut self, patches: &[config::PatchSpec]) -> Result<(), Error> {
+        for p in patches {
+            let records = match (&p.bytes, &p.ips) {
+                (Some(bytes), None) => vec![(0, bytes.clone())],
+                (None, Some(path)) => {
+                    info!("applying IPS patch {} at {:04X}", path.display(), p.addr);
+                    parse_ips(&std::fs::read(path)?)
+                        .map_err(|e| general_err!("{}: {}", path.display(), e))?
+                }
+                _ => return Err(general_err!("patch at {:04X} must have either bytes or ips", p.addr)),
+            };
+            for (offset, bytes) in records {
+                let start = p.addr as usize + offset;
+                if start + bytes.len() > self.raw_ram.len() {
+                    return Err(general_err!("patch at {:04X} extends past the end of memory", start));
+                }
+                self.raw_ram[start..start + bytes.len()].copy_from_slice(&bytes);
+                verbose_println!("patched {} bytes at {:04X}", bytes.len(), start);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ips_records() -> Result<(), Error> {
+        let ips = b"PATCH\x00\x00\x10\x00\x02\xAB\xCD\x00\x01\x00\x00\x00\x00\x03\x12EOF";
+        let records = parse_ips(ips)?;
+        assert_eq!(records, vec![(0x10, vec![0xab, 0xcd]), (0x100, vec![0x12; 3])]);
+        assert!(parse_ips(b"PATCH\x00\x00\x10\x00\x05\x01").is_err());
+        assert!(parse_ips(b"NOTIPS").is_err());
+        Ok(())
+    }
+}
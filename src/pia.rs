@@ -1,11 +1,56 @@
 pub trait Pia {
     fn read(&mut self, reg_num: usize) -> u8;
     fn write(&mut self, reg_num: usize, data: u8);
+    /// Switches between the fast model and the strict model (see PiaSide)
+    fn set_strict(&mut self, strict: bool);
+    /// Returns the state of both sides (for rewinding and snapshots)
+    fn state(&self) -> PiaState;
+    /// Puts both sides back in a state returned by state
+    fn set_state(&mut self, state: &PiaState);
 }
 
-/// Implements one "side" of a PIA chip
-#[derive(Debug, Default)]
+/// The registers and control lines of both sides of a PIA
+#[derive(Debug, Clone, Default)]
+pub struct PiaState([PiaSide; 2]);
+impl PiaState {
+    /// the number of bytes to_bytes returns
+    pub const LEN: usize = 12;
+    /// Returns each side's control, peripheral, output and data direction registers, its
+    /// control lines and stale flag (bits 0-2) and what was last written to its output register
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        for side in &self.0 {
+            let flags = side.c1 as u8 | (side.c2 as u8) << 1 | (side.stale_flags as u8) << 2;
+            bytes.extend([side.cr, side.ir, side.or, side.ddr, flags, side.written]);
+        }
+        bytes
+    }
+    /// Returns this state with its registers and control lines replaced by bytes from to_bytes
+    /// (or None if there aren't enough of them)
+    pub fn with_bytes(&self, bytes: &[u8]) -> Option<PiaState> {
+        let mut state = self.clone();
+        for (side, b) in state.0.iter_mut().zip(bytes.get(..Self::LEN)?.chunks(Self::LEN / 2)) {
+            (side.cr, side.ir, side.or, side.ddr, side.written) = (b[0], b[1], b[2], b[3], b[5]);
+            (side.c1, side.c2, side.stale_flags) = (b[4] & 1 != 0, b[4] & 2 != 0, b[4] & 4 != 0);
+        }
+        Some(state)
+    }
+}
+
+/// Implements one "side" of a PIA chip.
+///
+/// By default this is a fast model of the 6821 that's good enough for everything the CoCo's
+/// ROMs do. A few corners are approximated: interrupt flags are cleared by reading the control
+/// register (rather than the data register), only the first C1 edge after an interrupt sets the
+/// flag when that interrupt is disabled, C2's handshake and pulse output modes aren't modeled and
+/// output register bits written while they're inputs are lost. When software depends on one of
+/// these (as some copy protection does) a warning is printed once. The strict model (see
+/// set_strict) handles all of them the way the data sheet describes.
+#[derive(Debug, Default, Clone)]
 struct PiaSide {
+    // which side of which PIA this is (for warnings)
+    name: &'static str,
+    side_b: bool,
     // control register
     cr: u8,
     // peripheral register
@@ -17,11 +62,39 @@ struct PiaSide {
     // control lines
     c1: bool,
     c2: bool,
+    // model every documented corner case
+    strict: bool,
+    // everything last written to the output register (the fast model only keeps the output bits)
+    written: u8,
+    // the fast model cleared interrupt flags that a real PIA would still be showing
+    stale_flags: bool,
+    // compatibility warnings that have already been printed
+    warned: u8,
 }
 
+// compatibility warnings
+const WARN_FLAGS: u8 = 1;
+const WARN_C2_OUTPUT: u8 = 2;
+const WARN_DDR: u8 = 4;
+
 #[allow(unused)]
 impl PiaSide {
+    fn named(name: &'static str, side_b: bool) -> Self {
+        PiaSide {
+            name,
+            side_b,
+            ..Default::default()
+        }
+    }
+    fn compat_warning(&mut self, which: u8, msg: &str) {
+        if self.warned & which == 0 {
+            self.warned |= which;
+            warn!("{}: {} (this is approximated; try --pia-strict)", self.name, msg);
+        }
+    }
     fn manual_c2_trigger(&self) -> bool { self.cr & 0x30 == 0x30 }
+    // C2 is an output in handshake (bit 3 clear) or pulse (bit 3 set) mode
+    fn strobed_c2(&self) -> bool { self.cr & 0x30 == 0x20 }
     fn write_control(&mut self, b: u8) {
         // bits 6 & 7 are read-only
         self.cr = (b & 0x3f) | (self.cr & 0xc0);
@@ -29,12 +102,26 @@ impl PiaSide {
         if self.manual_c2_trigger() {
             // bit 3 controls c2
             self.set_c2(self.cr & 8 == 8);
+        } else if self.strobed_c2() {
+            if self.strict {
+                // c2 idles high until it's strobed
+                self.c2 = true;
+            } else {
+                self.compat_warning(WARN_C2_OUTPUT, "C2 handshake/pulse output mode selected");
+            }
         }
     }
     fn read_control(&mut self) -> u8 {
         let b = self.cr;
-        // control line flags are cleared on read
-        self.cr &= 0x3f;
+        if !self.strict {
+            if self.stale_flags {
+                // a real PIA would still show the flags that the previous read cleared
+                self.compat_warning(WARN_FLAGS, "control register read twice without reading the data register");
+            }
+            // control line flags are cleared on read
+            self.stale_flags = b & 0xc0 != 0;
+            self.cr &= 0x3f;
+        }
         b
     }
     fn pr_selected(&self) -> bool { self.cr & 4 == 4 }
@@ -49,19 +136,47 @@ impl PiaSide {
         if index & 1 == 1 {
             self.read_control()
         } else {
-            self.read_data()
+            let b = self.read_data();
+            if self.pr_selected() {
+                // reading the peripheral register is what clears the flags on a real PIA
+                self.stale_flags = false;
+                if self.strict {
+                    self.cr &= 0x3f;
+                    // on side A a read strobes c2 (side B strobes on writes)
+                    if self.strobed_c2() && !self.side_b {
+                        self.strobe_c2();
+                    }
+                }
+            }
+            b
         }
     }
     fn write_data(&mut self, b: u8) {
         // bit 2 in CR determines which register receives the write
         if self.pr_selected() {
             // write to peripheral register
-            self.or = b & self.ddr;
+            self.written = b;
+            if self.strict {
+                // every bit is stored; the DDR decides which ones are seen
+                self.or = b;
+                if self.strobed_c2() && self.side_b {
+                    self.strobe_c2();
+                }
+            } else {
+                self.or = b & self.ddr;
+            }
         } else {
             // write to DDR
+            let new_outputs = b & !self.ddr;
+            if !self.strict && (self.written ^ self.or) & new_outputs != 0 {
+                self.compat_warning(WARN_DDR, "output register bits written while they were inputs");
+            }
             self.ddr = b;
         }
     }
+    // c2 goes low when strobed. In pulse mode it goes back high on the next cycle and in
+    // handshake mode it stays low until the next active transition of c1.
+    fn strobe_c2(&mut self) { self.c2 = self.cr & 8 == 8; }
     fn read_data(&self) -> u8 {
         if self.pr_selected() {
             // bits marked as outputs source from output register
@@ -78,6 +193,18 @@ impl PiaSide {
         (self.or & self.ddr) | !self.ddr
     }
     fn set_c1(&mut self, c1: bool) {
+        if self.strict {
+            // each call is a pulse on c1 (like hsync and vsync) so both edges happen and the
+            // flag is set whichever one is selected
+            if c1 {
+                self.cr |= 0x80;
+                if self.strobed_c2() && self.cr & 8 == 0 {
+                    // handshake complete
+                    self.c2 = true;
+                }
+            }
+            return;
+        }
         // Note! only supporting low-high transitions
         if c1 && !self.c1 {
             // set c1 flag; (bit 7)
@@ -87,7 +214,9 @@ impl PiaSide {
     }
     fn set_c2(&mut self, c2: bool) {
         // Note only supporting low-high transitions
-        if c2 && !self.c2 && !self.manual_c2_trigger() {
+        // (strictly, the flag is only set when c2 is an input)
+        let input = if self.strict { self.cr & 0x20 == 0 } else { !self.manual_c2_trigger() };
+        if c2 && !self.c2 && input {
             // set c2 flag; (bit 6)
             self.cr |= 0x40;
             // remember this transition
@@ -97,6 +226,10 @@ impl PiaSide {
     // returns true if an interrupt signal is active
     // and resets the interrupt to inactive
     fn consume_interrupt(&mut self) -> bool {
+        if self.strict {
+            // the interrupt output follows the flags (and stays active until the data register is read)
+            return self.cr & 0x81 == 0x81 || self.cr & 0x68 == 0x48;
+        }
         let mut interrupt = false;
         // if control line 1 transitioned and interrupt from c1 is enabled in cr...
         if self.c1 && (self.cr & 1 == 1) {
@@ -115,7 +248,10 @@ impl PiaSide {
 
 use std::{
     collections::HashMap,
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering::Relaxed},
     sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 /// Keyboard map for coco (from [worldofdragon.org](https://worldofdragon.org/index.php?title=Keyboard))
@@ -152,7 +288,7 @@ use std::{
 ///    ')' (shift-'0') --> shift-'9' == [(6,7),(5,1)]
 ///    '+' (shift-'=') --> shift-';' == [(6,7),(5,3)]
 ///
-use minifb::{Key, MouseButton, MouseMode};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode};
 
 use crate::{sound::AudioSample, vdg};
 #[derive(Debug)]
@@ -194,16 +330,339 @@ const KEY_MATRIX: &[[minifb::Key;8];8] = &[
     [Key::Enter, Key::Home /* CLR */, Key::Escape /* BRK */, Key::Unknown, Key::Unknown, Key::Unknown, Key::Unknown, Key::RightShift],
     [Key::Unknown, Key::Unknown, Key::Unknown, Key::Unknown, Key::Unknown, Key::Unknown, Key::Unknown, Key::Unknown],
 ];
+/// the keys on a modern keyboard whose symbols depend on the host's keyboard layout
+#[rustfmt::skip]
+static SYMBOL_KEYS: &[Key] = &[
+    Key::Apostrophe, Key::Backquote, Key::Backslash, Key::Comma, Key::Equal, Key::LeftBracket, Key::Minus,
+    Key::Period, Key::RightBracket, Key::Semicolon, Key::Slash,
+];
+#[rustfmt::skip]
+static DIGIT_KEYS: &[Key] = &[
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+];
+pub const SHIFT: (usize, usize) = (6, 7);
+// frames for which a typed symbol is held down and then released
+const SYMBOL_DOWN_FRAMES: u32 = 3;
+const SYMBOL_UP_FRAMES: u32 = 2;
+// PiaInput::pointer while the mouse is outside the window
+const NO_POINTER: u32 = u32::MAX;
+
+/// Returns the key matrix coordinates (row, col) that must be pressed to type c
+pub fn char_keys(c: char) -> Option<Vec<(usize, usize)>> {
+    let c = c.to_ascii_uppercase();
+    let key = |i: usize| (i / 8, i % 8);
+    let plain = "@ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let digits = "0123456789:;,-./";
+    let shifted = " !\"#$%&'()*+<=>?";
+    Some(if let Some(i) = plain.find(c) {
+        vec![key(i)]
+    } else if let Some(i) = digits.find(c) {
+        vec![key(32 + i)]
+    } else if let Some(i) = shifted.find(c).filter(|&i| i > 0) {
+        vec![SHIFT, key(32 + i)]
+    } else {
+        match c {
+            ' ' => vec![(3, 7)],
+            '\r' | '\n' => vec![(6, 0)],
+            _ => return None,
+        }
+    })
+}
+
+/// Symbols typed with the host's keyboard layout (see --typed-symbols), which are pressed on
+/// the CoCo's keyboard one at a time
+#[derive(Debug, Default)]
+struct TypedSymbols {
+    queue: VecDeque<char>,
+    keys: Vec<(usize, usize)>,
+    down: u32,
+    up: u32,
+}
+/// Receives the characters typed in the window
+struct SymbolInput(Arc<PiaInput>);
+impl minifb::InputCallback for SymbolInput {
+    fn add_char(&mut self, c: u32) {
+        if let Some(c) = char::from_u32(c).filter(char::is_ascii_punctuation) {
+            self.0.symbols.lock().unwrap().queue.push_back(c);
+        }
+    }
+}
+/// What a key held down on the host does to the keyboard matrix (see --key-repeat)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RepeatMode {
+    /// the matrix follows the host's keys, including any flicker from its auto-repeat
+    #[default]
+    Host,
+    /// a held key stays down on the matrix through the host's auto-repeat
+    Off,
+    /// a held key is released and pressed again on the matrix after a delay and then at a rate
+    Synth,
+}
+/// Keys held down on the host, for --key-repeat off and synth
+#[derive(Debug, Default)]
+struct Typematic {
+    mode: RepeatMode,
+    delay_ms: u64,
+    period_ms: u64,
+    held: HashMap<Key, (u64, u64)>, // when each key went down and when it was last seen down
+    newest: Option<Key>,            // the key that repeats (modifiers never do)
+}
+impl Typematic {
+    /// Returns the keys to report on the matrix given the host's keys at time now (in ms)
+    fn filter(&mut self, keys: Vec<Key>, now: u64) -> Vec<Key> {
+        if self.mode == RepeatMode::Host {
+            return keys;
+        }
+        for &k in &keys {
+            let seen = self.held.entry(k).or_insert_with(|| {
+                if !MODIFIER_KEYS.contains(&k) {
+                    self.newest = Some(k);
+                }
+                (now, now)
+            });
+            seen.1 = now;
+        }
+        // the host's auto-repeat can release a key for a moment; it's held through that
+        self.held.retain(|_, (_, seen)| now - *seen <= RELEASE_GRACE_MS);
+        let mut out = keys;
+        out.extend(self.held.keys().filter(|k| !out.contains(k)).copied().collect::<Vec<_>>());
+        if let Some(since) = self.newest.and_then(|k| self.held.get(&k)).map(|&(since, _)| since) {
+            // the repeating key is up for the half period before each repeat
+            let (t, gap) = (now - since + self.period_ms / 2, self.period_ms / 2);
+            if self.mode == RepeatMode::Synth && t >= self.delay_ms && (t - self.delay_ms) % self.period_ms < gap {
+                out.retain(|&k| Some(k) != self.newest);
+            }
+        }
+        out
+    }
+}
+/// How long a key can be released on the host and still be held on the matrix
+const RELEASE_GRACE_MS: u64 = 60;
+const MODIFIER_KEYS: [Key; 6] =
+    [Key::LeftShift, Key::RightShift, Key::LeftCtrl, Key::RightCtrl, Key::LeftAlt, Key::RightAlt];
+
+/// The joystick's position while the mouse is captured (F7), which the mouse moves by how far it
+/// goes rather than by where it is in the window
 #[derive(Debug)]
-pub struct Pia0 {
-    ab: [PiaSide; 2],
-    col: [u8; 8],
+struct MouseCapture {
+    on: bool,
+    sensitivity: f32,         // 1.0 moves the joystick across its range for a window's width of motion
+    last: Option<(f32, f32)>, // where the mouse was at the last frame
+    joy: (f32, f32),          // the joystick's position (0-63)
+}
+impl MouseCapture {
+    /// Moves the joystick by the mouse's motion since the last frame and returns its position
+    fn track(&mut self, mouse: Option<(f32, f32)>) -> (u8, u8) {
+        if let (Some((x, y)), Some((last_x, last_y))) = (mouse, self.last) {
+            let scale = 64.0 * self.sensitivity;
+            self.joy.0 = (self.joy.0 + (x - last_x) * scale / vdg::SCREEN_DIM_X as f32).clamp(0.0, 63.0);
+            self.joy.1 = (self.joy.1 + (y - last_y) * scale / vdg::SCREEN_DIM_Y as f32).clamp(0.0, 63.0);
+        }
+        self.last = mouse;
+        (self.joy.0.round() as u8, self.joy.1.round() as u8)
+    }
+}
+
+/// Emulator hotkeys handled by the core: F12 is the reset button (a warm start that
+/// preserves memory), shift+F12 is a power cycle (a cold start), F11 pulls or inserts
+/// the cartridge, F10 turns warp mode on or off and F9 rewinds (see rewind.rs)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Hotkey {
+    WarmReset = 1,
+    ColdReset,
+    ToggleCart,
+    ToggleWarp,
+    Rewind,
+}
+
+/// Keyboard, mouse and hotkey state. The UI thread updates it every frame and Pia0 reads it on
+/// the core thread. Everything in it is atomic so the UI thread never has to lock Pia0.
+#[derive(Debug)]
+pub struct PiaInput {
+    col: [AtomicU8; 8],         // keyboard matrix (a bit is set for each key that's down)
+    joy_x: AtomicU8,
+    joy_y: AtomicU8,
+    joy_sw: AtomicU8,           // bit 0: left mouse button; bit 1: right mouse button
+    pointer: AtomicU32,         // the mouse's position in the window (x << 16 | y) or NO_POINTER
+    hotkey: AtomicU8,           // 0 or a Hotkey waiting for the core to pick it up
+    last_input_ms: AtomicU64,   // the last time (ms since epoch) a key, mouse button or the mouse was moved
+    epoch: Instant,
     direct_map: HashMap<minifb::Key, Vec<(usize, usize)>>,
     shift_map: HashMap<minifb::Key, Vec<(usize, usize)>>,
-    joy_x: u8,
-    joy_y: u8,
-    joy_sw_1: bool,
-    joy_sw_2: bool,
+    typed: AtomicBool,          // symbols come from typed characters rather than key positions
+    symbols: Mutex<TypedSymbols>,
+    typematic: Mutex<Typematic>,
+    capture: Mutex<MouseCapture>,
+}
+impl PiaInput {
+    fn new() -> Self {
+        let mut direct_map: HashMap<minifb::Key, Vec<(usize, usize)>> = HashMap::new();
+        // add our KEY_MATRIX entries to the direct_map
+        #[allow(clippy::needless_range_loop)]
+        for row in 0..8usize {
+            for col in 0..8usize {
+                direct_map.insert(KEY_MATRIX[row][col], vec![(row, col); 1]);
+            }
+        }
+        // add our ONE_TO_N entries to the direct_map
+        ONE_TO_N.iter().for_each(|m| {
+            direct_map.insert(m.from, m.to.to_vec());
+        });
+        // now populate the shift_map with entries from SHIFT_ONE_TO_N
+        let mut shift_map: HashMap<minifb::Key, Vec<(usize, usize)>> = HashMap::new();
+        SHIFT_ONE_TO_N.iter().for_each(|m| {
+            shift_map.insert(m.from, m.to.to_vec());
+        });
+        PiaInput {
+            col: Default::default(),
+            joy_x: AtomicU8::new(0x1f),
+            joy_y: AtomicU8::new(0x1f),
+            joy_sw: AtomicU8::new(0),
+            pointer: AtomicU32::new(NO_POINTER),
+            hotkey: AtomicU8::new(0),
+            last_input_ms: AtomicU64::new(0),
+            epoch: Instant::now(),
+            direct_map,
+            shift_map,
+            typed: AtomicBool::new(false),
+            symbols: Default::default(),
+            typematic: Default::default(),
+            capture: Mutex::new(MouseCapture { on: false, sensitivity: 1.0, last: None, joy: (0.0, 0.0) }),
+        }
+    }
+    /// Takes symbols from the characters typed in the window from now on (for keyboard layouts
+    /// other than US)
+    pub fn type_symbols(self: &Arc<Self>, w: &mut minifb::Window) {
+        self.typed.store(true, Relaxed);
+        w.set_input_callback(Box::new(SymbolInput(self.clone())));
+    }
+    /// Sets what keys held down on the host do (delay_ms and rate only matter for RepeatMode::Synth)
+    pub fn set_key_repeat(&self, mode: RepeatMode, delay_ms: u64, rate: u64) {
+        let mut typematic = self.typematic.lock().unwrap();
+        (typematic.mode, typematic.delay_ms, typematic.period_ms) = (mode, delay_ms, 1000 / rate.clamp(1, 50));
+    }
+    /// Sets how far the joystick moves for the mouse's motion while it's captured (1.0 moves it
+    /// across its range for the width of the window)
+    pub fn set_mouse_sensitivity(&self, sensitivity: f32) { self.capture.lock().unwrap().sensitivity = sensitivity }
+    /// Captures the mouse (the joystick then follows its motion rather than its position) or
+    /// releases it
+    pub fn set_capture(&self, on: bool) {
+        let mut capture = self.capture.lock().unwrap();
+        // the joystick carries on from where it is
+        (capture.on, capture.last) = (on, None);
+        capture.joy = (self.joy_x.load(Relaxed) as f32, self.joy_y.load(Relaxed) as f32);
+    }
+    pub fn capturing(&self) -> bool { self.capture.lock().unwrap().on }
+    /// Returns the keys for the typed symbol that's being pressed or released (if any)
+    fn typed_symbol(&self) -> Option<Vec<(usize, usize)>> {
+        let mut symbols = self.symbols.lock().unwrap();
+        while symbols.down == 0 && symbols.up == 0 {
+            let c = symbols.queue.pop_front()?;
+            if let Some(keys) = char_keys(c) {
+                (symbols.keys, symbols.down, symbols.up) = (keys, SYMBOL_DOWN_FRAMES, SYMBOL_UP_FRAMES);
+            }
+        }
+        if symbols.down > 0 {
+            symbols.down -= 1;
+            return Some(symbols.keys.clone());
+        }
+        symbols.up -= 1;
+        Some(vec![])
+    }
+    // update is called periodically (on the UI thread) to allow for updates of keyboard and joystick state
+    pub fn update(&self, w: &minifb::Window) {
+        self.update_keyboard(w);
+        self.update_joystick(w);
+    }
+    fn touch(&self) { self.last_input_ms.store(self.epoch.elapsed().as_millis() as u64, Relaxed) }
+    fn update_joystick(&self, w: &minifb::Window) {
+        let mut capture = self.capture.lock().unwrap();
+        let joy = if capture.on {
+            Some(capture.track(w.get_mouse_pos(MouseMode::Pass)))
+        } else {
+            // translate mouse position into 6-bit integers
+            w.get_mouse_pos(MouseMode::Clamp).map(|mouse| {
+                let x = ((255.0 * (mouse.0 / vdg::SCREEN_DIM_X as f32)).round() as u8) >> 2;
+                let y = ((255.0 * (mouse.1 / vdg::SCREEN_DIM_Y as f32)).round() as u8) >> 2;
+                (x, y)
+            })
+        };
+        drop(capture);
+        if let Some((x, y)) = joy {
+            let sw = w.get_mouse_down(MouseButton::Left) as u8 | (w.get_mouse_down(MouseButton::Right) as u8) << 1;
+            if self.joy_x.swap(x, Relaxed) != x || self.joy_y.swap(y, Relaxed) != y || sw != 0 {
+                self.touch();
+            }
+            self.joy_sw.store(sw, Relaxed);
+        } 
+        // the unclamped position is for a tablet (which can tell when the pen is out of range)
+        let pointer = w.get_mouse_pos(MouseMode::Discard).map_or(NO_POINTER, |(x, y)| (x as u32) << 16 | y as u32);
+        self.pointer.store(pointer, Relaxed);
+    }
+    /// Returns the mouse's position in the window's pixels (if it's in the window)
+    pub fn pointer(&self) -> Option<(u16, u16)> {
+        let pointer = self.pointer.load(Relaxed);
+        (pointer != NO_POINTER).then_some(((pointer >> 16) as u16, pointer as u16))
+    }
+    /// Returns the mouse buttons that are down (bit 0: left; bit 1: right)
+    pub fn buttons(&self) -> u8 { self.joy_sw.load(Relaxed) }
+    fn update_keyboard(&self, w: &minifb::Window) {
+        let mut coords: Vec<(usize, usize)> = Vec::new();
+        let now = self.epoch.elapsed().as_millis() as u64;
+        let keys = self.typematic.lock().unwrap().filter(w.get_keys(), now);
+        if w.is_key_pressed(Key::F12, KeyRepeat::No) {
+            let shift = keys.iter().any(|&k| k == Key::LeftShift || k == Key::RightShift);
+            let hotkey = if shift { Hotkey::ColdReset } else { Hotkey::WarmReset };
+            self.hotkey.store(hotkey as u8, Relaxed);
+        } else if w.is_key_pressed(Key::F11, KeyRepeat::No) {
+            self.hotkey.store(Hotkey::ToggleCart as u8, Relaxed);
+        } else if w.is_key_pressed(Key::F10, KeyRepeat::No) {
+            self.hotkey.store(Hotkey::ToggleWarp as u8, Relaxed);
+        } else if w.is_key_pressed(Key::F9, KeyRepeat::No) {
+            self.hotkey.store(Hotkey::Rewind as u8, Relaxed);
+        }
+        let mut col = [0u8; 8];
+        let typed = self.typed.load(Relaxed);
+        if let Some(symbol) = typed.then(|| self.typed_symbol()).flatten() {
+            // a typed symbol replaces whatever keys it took to type it
+            self.touch();
+            coords = symbol;
+        } else if !keys.is_empty() {
+            self.touch();
+            let shift = keys.iter().any(|&k| k == Key::LeftShift || k == Key::RightShift);
+            // with typed symbols the keys that type them (including shifted digits) are ignored
+            let ignored = |k: &Key| typed && (SYMBOL_KEYS.contains(k) || shift && DIGIT_KEYS.contains(k));
+            if shift && !typed {
+                // shift key is down; check shift_map to see if there are any matches
+                // if so then the 1st match will be the only key press we report (any other keys will be ignored)
+                if let Some(v) = keys.iter().find_map(|k| self.shift_map.get(k)) {
+                    v.iter().for_each(|&c| coords.push(c));
+                }
+            }
+            if coords.is_empty() {
+                // shift key is not down or we didn't find a shift+key mapping
+                // so now we just try to use a direct mapping of each of the keypresses
+                keys.iter().filter(|k| !ignored(k)).for_each(|k| {
+                    if let Some(v) = self.direct_map.get(k) {
+                        v.iter().for_each(|&c| coords.push(c));
+                    }
+                });
+            }
+        }
+        // now set each column in the matrix based on the new (row,col) coords
+        coords.iter().for_each(|&(r, c)| col[c] |= 1 << r as u8);
+        // Pia0 picks up the new matrix the next time the program reads the keyboard
+        col.iter().zip(&self.col).for_each(|(&c, a)| a.store(c, Relaxed));
+    }
+}
+
+#[derive(Debug)]
+pub struct Pia0 {
+    ab: [PiaSide; 2],
+    typed_col: [u8; 8], // keys pressed by autotype (see autotype.rs)
+    scanned: bool,      // true once the keyboard has been strobed by the program
+    input: Arc<PiaInput>, // keyboard and mouse state from the UI thread
     // Deadlock risk! but Pia0 needs to read Pia1.
     // In real life, they are wired together.
     // I'm sure there's a better way to do this
@@ -211,6 +670,12 @@ pub struct Pia0 {
     pia1: Arc<Mutex<Pia1>>,
 }
 impl Pia for Pia0 {
+    fn set_strict(&mut self, strict: bool) { self.ab.iter_mut().for_each(|s| s.strict = strict) }
+    fn state(&self) -> PiaState { PiaState(self.ab.clone()) }
+    fn set_state(&mut self, state: &PiaState) {
+        self.ab = state.0.clone();
+        self.strobe_keyboard();
+    }
     fn read(&mut self, reg_num: usize) -> u8 {
         let i = reg_num % 4;
         if i == 0 {
@@ -218,11 +683,13 @@ impl Pia for Pia0 {
             // In order to set bit 7 appropriately we need to
             // compare the value of the DAC with the selected joystick.
             // Note: we route the mouse to BOTH joysticks
+            // First pick up the latest keyboard state from the UI thread.
+            self.strobe_keyboard();
             let joy_val = match self.ab[0].c2 {
                 // horizontal axis
-                false => self.joy_x,
+                false => self.input.joy_x.load(Relaxed),
                 // vertical axis
-                true => self.joy_y,
+                true => self.input.joy_y.load(Relaxed),
             };
             // DAC val is in the top 6 bits of A side data register of pia1
             // This is the only reason we need a reference to pia1 here.
@@ -248,7 +715,10 @@ impl Pia for Pia0 {
             // if write is to one of the control registers then check DAC mux bits
             1 | 3 => self.pia1.lock().unwrap().set_dac_mux(self.ab[0].c2, self.ab[1].c2),
             // if write is to the b-side data register, then it's related to keyboard
-            2 => self.strobe_keyboard(),
+            2 => {
+                self.scanned = true;
+                self.strobe_keyboard()
+            }
             _ => (),
         }
     }
@@ -256,79 +726,39 @@ impl Pia for Pia0 {
 impl Pia0 {
     #[allow(clippy::new_without_default)]
     pub fn new(pia1: Arc<Mutex<Pia1>>) -> Self {
-        let mut direct_map: HashMap<minifb::Key, Vec<(usize, usize)>> = HashMap::new();
-        // add our KEY_MATRIX entries to the direct_map
-        #[allow(clippy::needless_range_loop)]
-        for row in 0..8usize {
-            for col in 0..8usize {
-                direct_map.insert(KEY_MATRIX[row][col], vec![(row, col); 1]);
-            }
-        }
-        // add our ONE_TO_N entries to the direct_map
-        ONE_TO_N.iter().for_each(|m| {
-            direct_map.insert(m.from, m.to.to_vec());
-        });
-        // now populate the shift_map with entries from SHIFT_ONE_TO_N
-        let mut shift_map: HashMap<minifb::Key, Vec<(usize, usize)>> = HashMap::new();
-        SHIFT_ONE_TO_N.iter().for_each(|m| {
-            shift_map.insert(m.from, m.to.to_vec());
-        });
         Pia0 {
-            ab: [PiaSide::default(), PiaSide::default()],
-            col: [0xff; 8],
-            direct_map,
-            shift_map,
-            joy_x: 0x1f,
-            joy_y: 0x1f,
-            joy_sw_1: false,
-            joy_sw_2: false,
+            ab: [PiaSide::named("PIA0 A", false), PiaSide::named("PIA0 B", true)],
+            typed_col: [0; 8],
+            scanned: false,
+            input: Arc::new(PiaInput::new()),
             pia1,
         }
     }
-    // update is called periodically to allow for updates of keyboard and joystick state
-    pub fn update(&mut self, w: &minifb::Window) {
-        self.update_keyboard(w);
-        self.update_joystick(w);
-    }
-    fn update_joystick(&mut self, w: &minifb::Window) {
-        if let Some(mouse) = w.get_mouse_pos(MouseMode::Clamp) {
-            // translate mouse position into 6-bit integers
-            self.joy_x = ((255.0 * (mouse.0 / vdg::SCREEN_DIM_X as f32)).round() as u8) >> 2;
-            self.joy_y = ((255.0 * (mouse.1 / vdg::SCREEN_DIM_Y as f32)).round() as u8) >> 2;
-            self.joy_sw_1 = w.get_mouse_down(MouseButton::Left);
-            self.joy_sw_2 = w.get_mouse_down(MouseButton::Right);
-        } 
+    /// Returns the input state that the UI thread updates
+    pub fn input(&self) -> Arc<PiaInput> { self.input.clone() }
+    /// Replaces the set of keys (row, col) that are held down on behalf of autotype
+    pub fn set_typed_keys(&mut self, keys: &[(usize, usize)]) {
+        self.typed_col = [0; 8];
+        keys.iter().for_each(|&(r, c)| self.typed_col[c] |= 1 << r as u8);
+        self.strobe_keyboard()
     }
-    fn update_keyboard(&mut self, w: &minifb::Window) {
-        let mut coords: Vec<(usize, usize)> = Vec::new();
-        let keys = w.get_keys();
-        // clear out our internal keyboard matrix
-        for c in self.col.iter_mut() {
-            *c = 0
+    pub fn keyboard_scanned(&self) -> bool { self.scanned }
+    pub fn take_hotkey(&mut self) -> Option<Hotkey> {
+        match self.input.hotkey.swap(0, Relaxed) {
+            1 => Some(Hotkey::WarmReset),
+            2 => Some(Hotkey::ColdReset),
+            3 => Some(Hotkey::ToggleCart),
+            4 => Some(Hotkey::ToggleWarp),
+            5 => Some(Hotkey::Rewind),
+            _ => None,
         }
-        if !keys.is_empty() {
-            let shift = keys.iter().any(|&k| k == Key::LeftShift || k == Key::RightShift);
-            if shift {
-                // shift key is down; check shift_map to see if there are any matches
-                // if so then the 1st match will be the only key press we report (any other keys will be ignored)
-                if let Some(v) = keys.iter().find_map(|k| self.shift_map.get(k)) {
-                    v.iter().for_each(|&c| coords.push(c));
-                }
-            }
-            if coords.is_empty() {
-                // shift key is not down or we didn't find a shift+key mapping
-                // so now we just try to use a direct mapping of each of the keypresses
-                keys.iter().for_each(|k| {
-                    if let Some(v) = self.direct_map.get(k) {
-                        v.iter().for_each(|&c| coords.push(c));
-                    }
-                });
-            }
-            // now set each column in the matrix based on the new (row,col) coords
-            coords.iter().for_each(|&(r, c)| self.col[c] |= 1 << r as u8);
-        }
-        self.strobe_keyboard()
     }
+    /// Returns the time since the user last touched the keyboard or mouse
+    pub fn idle_time(&self) -> Duration {
+        let now = self.input.epoch.elapsed();
+        now.saturating_sub(Duration::from_millis(self.input.last_input_ms.load(Relaxed)))
+    }
+    pub fn reset_idle_time(&mut self) { self.input.touch() }
     pub fn strobe_keyboard(&mut self) {
         // strobe the keyboard based on side B output
         let mut com = 0u8;
@@ -339,17 +769,18 @@ impl Pia0 {
             for i in 0..8 {
                 if cols & 1 == 1 {
                     // strobing column i
-                    com |= self.col[i];
+                    com |= self.input.col[i].load(Relaxed) | self.typed_col[i];
                 }
                 cols >>= 1;
             }
         }
         // handle joystick switches -- both joysticks mapped to the mouse
-        if self.joy_sw_1 {
+        let joy_sw = self.input.joy_sw.load(Relaxed);
+        if joy_sw & 1 != 0 {
             // only provide joystick switch if caller didn't strobe associated col(s)
             com |= 0x3 & !cols
         }
-        if self.joy_sw_2 {
+        if joy_sw & 2 != 0 {
             // only provide joystick switch if caller didn't strobe associated col(s)
             com |= 0xc & !cols
         }
@@ -375,9 +806,16 @@ pub struct Pia1 {
     dac_sel_a: bool,
     dac_sel_b: bool,
     last_bit_sound: bool,
+    vdg_bits: Arc<AtomicU8>, // published copy of get_vdg_bits() for the UI thread
 }
 impl Pia for Pia1 {
     fn read(&mut self, reg_num: usize) -> u8 { self.ab[(reg_num >> 1) & 1].read(reg_num) }
+    fn set_strict(&mut self, strict: bool) { self.ab.iter_mut().for_each(|s| s.strict = strict) }
+    fn state(&self) -> PiaState { PiaState(self.ab.clone()) }
+    fn set_state(&mut self, state: &PiaState) {
+        self.ab = state.0.clone();
+        self.vdg_bits.store(self.get_vdg_bits(), Relaxed);
+    }
     fn write(&mut self, reg_num: usize, data: u8) {
         let i = reg_num % 4;
         self.ab[(i >> 1) & 1].write(reg_num, data);
@@ -388,37 +826,38 @@ impl Pia for Pia1 {
                 // this is a write to the DAC and sound is enabled so send the data to the audio device
                 // convert 6-bit amplitude into f32 value between -1.0 and +1.0
                 let fdata = ((self.ab[0].read_output() >> 2) as f32 - 31.0) / 32.0;
-                self.sndr
-                    .send(AudioSample::new(fdata))
-                    .expect("error sending audio sample to channel");
+                // nobody is listening if there's no audio device (or the core is headless)
+                let _ = self.sndr.send(AudioSample::new(fdata));
             }
             2 => {
                 // check for single-bit sound in pia1-b data register
                 let bit = self.ab[1].read_output() & 2 == 2;
                 if bit != self.last_bit_sound {
                     let fdata = if bit { 0.5 } else { -0.5 };
-                    self.sndr
-                        .send(AudioSample::new(fdata))
-                        .expect("error sending single bit audio to channel")
+                    let _ = self.sndr.send(AudioSample::new(fdata));
                 }
                 self.last_bit_sound = bit;
             }
             3 => self.sound_enabled = data & 8 == 8,
             _ => (),
         }
+        self.vdg_bits.store(self.get_vdg_bits(), Relaxed);
     }
 }
 impl Pia1 {
     pub fn new(sndr: mpsc::Sender<AudioSample>) -> Self {
         Pia1 {
-            ab: [PiaSide::default(), PiaSide::default()],
+            ab: [PiaSide::named("PIA1 A", false), PiaSide::named("PIA1 B", true)],
             sndr,
             sound_enabled: false,
             dac_sel_a: false,
             dac_sel_b: false,
             last_bit_sound: false,
+            vdg_bits: Arc::new(AtomicU8::new(0)),
         }
     }
+    /// Returns the VDG bits as last written, for reading without locking PIA1
+    pub fn shared_vdg_bits(&self) -> Arc<AtomicU8> { self.vdg_bits.clone() }
     /// Returns the following bits as a byte: 0, 0, 0, G/!A, GM2, GM1, GM0, CSS
     pub fn get_vdg_bits(&self) -> u8 { (self.ab[1].read_data() >> 3) & 0x1f }
     /// Lets PIA1 know that a cartridge was inserted.
@@ -427,8 +866,94 @@ impl Pia1 {
         self.ab[1].set_c1(true);
         self.ab[1].consume_interrupt()
     }
+    /// Returns true if the cassette relay is closed (CA2 is an output and it's set)
+    pub fn cassette_motor(&self) -> bool { self.ab[0].cr & 0x38 == 0x38 }
+    /// Returns the DAC's 6-bit value (which is also what goes out to the cassette)
+    pub fn dac(&self) -> u8 { self.ab[0].read_output() >> 2 }
+    /// Sets the bit that comes in from the cassette (PA0)
+    pub fn set_cassette_input(&mut self, bit: bool) { self.ab[0].ir = self.ab[0].ir & 0xfe | bit as u8 }
     pub fn set_dac_mux(&mut self, a: bool, b: bool) {
         self.dac_sel_a = a;
         self.dac_sel_b = b;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    // (no imports because dm_test compiles this module without running its tests)
+    #[test]
+    fn strict_side() {
+        let mut side = super::PiaSide::named("PIA0 B", true);
+        side.strict = true;
+        // flags are cleared by reading the data register, not the control register
+        side.write(1, 0x05);
+        side.set_c1(true);
+        assert!(side.consume_interrupt() && side.consume_interrupt());
+        assert_eq!(side.read(1) & 0x80, 0x80);
+        assert_eq!(side.read(1) & 0x80, 0x80);
+        side.read(0);
+        assert!(!side.consume_interrupt());
+        // output bits written while they're inputs appear when they become outputs
+        side.write(1, 0x00);
+        side.write(0, 0x00);
+        side.write(1, 0x04);
+        side.write(0, 0xff);
+        side.write(1, 0x00);
+        side.write(0, 0xff);
+        assert_eq!(side.read_output(), 0xff);
+        side.write(1, 0x04);
+        assert_eq!(side.read(0), 0xff);
+        // handshake mode: c2 goes low on a side B write and back high on c1
+        side.write(1, 0x24);
+        assert!(side.c2);
+        side.write(0, 0x55);
+        assert!(!side.c2);
+        side.set_c1(true);
+        assert!(side.c2);
+    }
+
+    #[test]
+    fn typed_symbols() {
+        let input = super::PiaInput::new();
+        input.symbols.lock().unwrap().queue.extend([':', '€', '"']);
+        // each symbol is held down and then released; ones the CoCo doesn't have are skipped
+        let frames: Vec<_> = std::iter::from_fn(|| input.typed_symbol()).collect();
+        assert_eq!(frames.len(), 10);
+        assert_eq!(frames[0], vec![(5, 2)]);
+        assert!(frames[3].is_empty());
+        assert_eq!(frames[5], vec![super::SHIFT, (4, 2)]);
+    }
+
+    #[test]
+    fn mouse_capture() {
+        let mut capture = super::MouseCapture { on: true, sensitivity: 0.5, last: None, joy: (32.0, 32.0) };
+        // the first frame only notes where the mouse is
+        assert_eq!(capture.track(Some((100.0, 100.0))), (32, 32));
+        // half a window's width at half sensitivity moves a quarter of the range
+        let half_width = super::vdg::SCREEN_DIM_X as f32 / 2.0;
+        assert_eq!(capture.track(Some((100.0 + half_width, 100.0))), (48, 32));
+        assert_eq!(capture.track(Some((100.0 + 4.0 * half_width, 100.0))), (63, 32));
+        assert_eq!(capture.track(None), (63, 32));
+        assert_eq!(capture.track(Some((0.0, 0.0))), (63, 32));
+    }
+
+    #[test]
+    fn key_repeat() {
+        use minifb::Key;
+        let mut typematic = super::Typematic { delay_ms: 500, period_ms: 100, ..Default::default() };
+        // the host's keys go straight through
+        assert!(typematic.filter(vec![], 0).is_empty());
+        typematic.mode = super::RepeatMode::Off;
+        // a moment's release (the host's auto-repeat) doesn't reach the matrix but a real release does
+        assert_eq!(typematic.filter(vec![Key::A], 0), vec![Key::A]);
+        assert_eq!(typematic.filter(vec![], 30), vec![Key::A]);
+        assert_eq!(typematic.filter(vec![Key::A], 40), vec![Key::A]);
+        assert_eq!(typematic.filter(vec![], 600), vec![]);
+        // a held key repeats at 500ms and then every 100ms; shift stays down
+        typematic.mode = super::RepeatMode::Synth;
+        let mut down = |ms| typematic.filter(vec![Key::LeftShift, Key::A], 1000 + ms).contains(&Key::A);
+        let pattern: Vec<bool> = (0..12u64).map(|i| down(i * 50)).collect();
+        assert_eq!(pattern, [true, true, true, true, true, true, true, true, true, false, true, false]);
+        assert!(typematic.filter(vec![Key::LeftShift, Key::A], 1300).contains(&Key::LeftShift));
+    }
+}
@@ -0,0 +1,155 @@
+//! Virtual printer that spools print jobs to text or PDF files.
+use super::*;
+use std::fs;
+use std::path::PathBuf;
+
+const LINES_PER_PAGE: usize = 66;
+const FORM_FEED: char = '\x0c';
+
+/// Output format for spooled print jobs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrintFormat {
+    Text,
+    Pdf,
+}
+
+pub struct Printer {
+    dir: PathBuf,
+    format: PrintFormat,
+    buf: Vec<u8>,
+    jobs: usize,
+}
+impl Printer {
+    pub fn new(dir: &Path, format: PrintFormat) -> Self {
+        Printer {
+            dir: dir.to_path_buf(),
+            format,
+            buf: Vec::new(),
+            jobs: 0,
+        }
+    }
+    /// Adds one byte to the current print job
+    pub fn print(&mut self, byte: u8) { self.buf.push(byte) }
+    /// Ends the current print job (if any) and writes it to a new file in the spool directory
+    pub fn flush(&mut self) -> Result<Option<PathBuf>, Error> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        if config::kiosk() {
+            // nothing gets written in kiosk mode
+            self.buf.clear();
+            return Ok(None);
+        }
+        let text = to_text(&std::mem::take(&mut self.buf));
+        fs::create_dir_all(&self.dir)?;
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.jobs += 1;
+        let mut path = self.dir.join(format!("print_{}_{}", stamp, self.jobs));
+        match self.format {
+            PrintFormat::Text => {
+                path.set_extension("txt");
+                fs::write(&path, text)?;
+            }
+            PrintFormat::Pdf => {
+                path.set_extension("pdf");
+                fs::write(&path, to_pdf(&text))?;
+            }
+        }
+        info!("print job written to {}", path.display());
+        Ok(Some(path))
+    }
+}
+
+/// Converts raw printer output to host text: CR and CR/LF become newlines,
+/// form feeds are kept and other control characters are dropped.
+fn to_text(raw: &[u8]) -> String {
+    let mut s = String::with_capacity(raw.len());
+    let mut prev_cr = false;
+    for &b in raw {
+        match b {
+            b'\r' => s.push('\n'),
+            b'\n' if prev_cr => {}
+            b'\n' => s.push('\n'),
+            0x0c => s.push(FORM_FEED),
+            b'\t' | 0x20..=0x7e => s.push(b as char),
+            _ => {}
+        }
+        prev_cr = b == b'\r';
+    }
+    s
+}
+
+/// Renders text as a minimal PDF (US letter, 10pt Courier, 66 lines per page)
+fn to_pdf(text: &str) -> Vec<u8> {
+    // split into pages on form feeds and every LINES_PER_PAGE lines
+    let mut pages: Vec<Vec<&str>> = Vec::new();
+    for chunk in text.split(FORM_FEED) {
+        let lines: Vec<&str> = chunk.lines().collect();
+        if lines.is_empty() {
+            pages.push(Vec::new());
+        }
+        pages.extend(lines.chunks(LINES_PER_PAGE).map(|c| c.to_vec()));
+    }
+    let page_count = pages.len();
+    // objects: 1 catalog, 2 pages, 3 font, then a page and a content stream per page
+    let mut objects: Vec<String> = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..page_count).map(|i| format!("{} 0 R", 4 + i * 2)).collect::<Vec<_>>().join(" "),
+            page_count
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (i, lines) in pages.iter().enumerate() {
+        let mut stream = String::from("BT /F1 10 Tf 11 TL 36 756 Td\n");
+        for line in lines {
+            let escaped = line.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)");
+            stream.push_str(&format!("({}) Tj T*\n", escaped));
+        }
+        stream.push_str("ET");
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            5 + i * 2
+        ));
+        objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", stream.len(), stream));
+    }
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, obj) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, obj));
+    }
+    let xref = out.len();
+    out.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+    for o in offsets {
+        out.push_str(&format!("{:010} 00000 n \n", o));
+    }
+    out.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_conversion() {
+        assert_eq!(to_text(b"HELLO\r\nWORLD\r\x0c\x01!"), "HELLO\nWORLD\n\x0c!");
+    }
+    #[test]
+    fn pdf_pages() {
+        let text = (0..70).map(|i| format!("LINE {}\n", i)).collect::<String>() + "\x0cLAST";
+        let pdf = String::from_utf8(to_pdf(&text)).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains("/Count 3"));
+        assert!(pdf.ends_with("%%EOF\n"));
+    }
+}
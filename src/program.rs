@@ -1,16 +1,17 @@
 use crate::hex::{HexRecord, HexRecordCollection};
 
 use super::obj::*;
-use super::parse::{LabelResolver, ValueNode};
+use super::expr::{LabelResolver, ValueNode};
 use super::test::TestCriterion;
 use super::*;
 
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
+use std::rc::Rc;
 lazy_static! {
     static ref RE_PARAM: Regex = Regex::new(r"[@](\d+)").unwrap();
 }
@@ -103,16 +104,16 @@ impl Macro {
 pub struct ProgramLine {
     pub src_line_num: usize,       // corresponding line number in source
     pub src: String,               // verbatim line from source
-    pub label: Option<String>,     // label defined on this line
-    pub operation: Option<String>, // operation (mnemonic or directive) used on this line
+    pub label: Option<Rc<str>>,     // label defined on this line
+    pub operation: Option<Rc<str>>, // operation (mnemonic or directive) used on this line
     pub operand: Option<String>,   // operand used on this line
     pub obj: Option<Box<dyn ObjectProducer>>,
     pub obj_size: u16, // keep track of object size between passes
     pub addr: u16,     // the program address corresponding to this line (whether the line produces an object or not)
 }
 impl ProgramLine {
-    pub fn get_label(&self) -> &str { self.label.as_ref().map_or("", String::as_str) }
-    pub fn get_operation(&self) -> &str { self.operation.as_ref().map_or("", String::as_str) }
+    pub fn get_label(&self) -> &str { self.label.as_deref().unwrap_or("") }
+    pub fn get_operation(&self) -> &str { self.operation.as_deref().unwrap_or("") }
     pub fn get_operand(&self) -> &str { self.operand.as_ref().map_or("", String::as_str) }
     pub fn is_inert(&self) -> bool { self.label.is_none() && self.operation.is_none() }
 }
@@ -131,9 +132,24 @@ impl fmt::Display for ProgramLine {
         }
     }
 }
+/// Keeps a single copy of each label and operation name; the lines that use a name (and the
+/// label table) share it rather than each holding a string of their own
+#[derive(Default)]
+pub struct Interner(HashSet<Rc<str>>);
+impl Interner {
+    pub fn intern(&mut self, s: &str) -> Rc<str> {
+        if let Some(name) = self.0.get(s) {
+            return name.clone();
+        }
+        let name: Rc<str> = Rc::from(s);
+        self.0.insert(name.clone());
+        name
+    }
+}
+
 #[derive(Debug)]
 pub struct Label {
-    pub name: String,         // name of label; Note: these are case sensitive!
+    pub name: Rc<str>,        // name of label; Note: these are case sensitive!
     pub line: usize,          // line number where the label is defined
     addr: u16,                // address (location) of this label
     node: Option<ValueNode>,  // if this label is defined by EQU then it has a ValueNode
@@ -148,13 +164,15 @@ impl fmt::Display for Label {
 
 #[derive(Debug)]
 pub struct ProgramLabels {
-    map: HashMap<String, Label>,
+    map: HashMap<Rc<str>, Label>,
 }
 impl LabelResolver for ProgramLabels {
     fn resolve(&self, label: &str) -> Option<u8u16> { self.get_value(label) }
 }
 impl ProgramLabels {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> ProgramLabels { ProgramLabels { map: HashMap::new() } }
+    #[allow(clippy::unnecessary_sort_by)]
     pub fn dump(&self) {
         if self.map.is_empty() {
             println!("No symbols.")
@@ -168,7 +186,9 @@ impl ProgramLabels {
             }
         }
     }
-    pub fn new_definition(&mut self, name: &str, line: usize, addr: u16, node: Option<ValueNode>) -> Result<(), Error> {
+    pub fn new_definition(
+        &mut self, name: &Rc<str>, line: usize, addr: u16, node: Option<ValueNode>,
+    ) -> Result<(), Error> {
         if self.map.contains_key(name) {
             return Err(Error::new(
                 ErrorKind::Syntax,
@@ -177,7 +197,7 @@ impl ProgramLabels {
             ));
         }
         let label = Label {
-            name: name.to_string(),
+            name: name.clone(),
             line,
             addr,
             node,
@@ -282,6 +302,7 @@ pub struct ProgramSegments {
     pub map: BTreeMap<u16, ProgramSegment>,
 }
 impl ProgramSegments {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let mut segs = ProgramSegments { map: BTreeMap::new() };
         // we default to having a segment starting at 0
@@ -314,6 +335,59 @@ impl ProgramSegments {
     }
 }
 
+/// Reads the bytes in a listing written by write_listing (a .lst file) by address. After the
+/// line number, each line has an address and then each byte (or word) in a 5-character column.
+pub fn read_listing(text: &str) -> BTreeMap<u16, u8> {
+    let mut bytes = BTreeMap::new();
+    for line in text.lines() {
+        let Some((_, obj)) = line.trim_start().split_once(' ') else {
+            continue;
+        };
+        let Some(mut addr) = obj.get(..4).and_then(|a| u16::from_str_radix(a, 16).ok()) else {
+            continue;
+        };
+        let mut rest = obj.get(5..).unwrap_or("");
+        while let Some(item) = rest.get(..5) {
+            let digits = item.trim_end();
+            let is_item = matches!(digits.len(), 2 | 4) && digits.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_item {
+                break;
+            }
+            for i in (0..digits.len()).step_by(2) {
+                bytes.insert(addr, u8::from_str_radix(&digits[i..i + 2], 16).unwrap());
+                addr = addr.wrapping_add(1);
+            }
+            rest = &rest[5..];
+        }
+    }
+    bytes
+}
+
+/// A run of bytes that a program puts in memory
+#[derive(Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// A symbol defined by a program (the emulator doesn't need these but tools built on the library do)
+#[derive(Debug, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub value: u16,  // its address, or its value if it's defined with EQU
+    pub line: usize, // the source line that defines it
+    pub equ: bool,   // true if it's defined with EQU
+}
+
+/// A line of the program's listing: a source line, where it was assembled and what it produced
+#[derive(Debug, PartialEq, Eq)]
+pub struct ListingLine<'a> {
+    pub line: usize, // line number in the source (lines expanded from a macro share their number)
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub src: &'a str,
+}
+
 #[derive(Debug)]
 pub struct Program {
     pub addr: u16,                      // current address
@@ -323,6 +397,10 @@ pub struct Program {
     pub results: Vec<TestCriterion>,    // expected results for test criteria
     pub segs: ProgramSegments,          // program segments (defined by ORG directive)
     pub dp_dirty: bool,                 // true if DP register has been written to
+    pub stack_nodes: Option<(ValueNode, ValueNode)>, // operands of the STACK directive (evaluated after build)
+    pub stack: Option<(u16, u16)>,      // bounds for the system stack pointer (low, high)
+    pub entry_node: Option<ValueNode>,  // operand of the END directive (evaluated after build)
+    pub entry: Option<u16>,             // the program's entry point (from END <start>)
 }
 impl LabelResolver for Program {
     fn resolve(&self, label: &str) -> Option<u8u16> { self.labels.get_value(label) }
@@ -348,25 +426,170 @@ impl Program {
             results: Vec::new(),
             segs: ProgramSegments::new(),
             dp_dirty: false,
+            stack_nodes: None,
+            stack: None,
+            entry_node: None,
+            entry: None,
         }
     }
-    pub fn write_listing(&self, f: &mut dyn io::Write) -> Result<(), io::Error> {
+    /// Writes the listing: the source line number, the address and bytes, and the source line
+    /// (in color if color is true: addresses, bytes, operations and comments each get one)
+    pub fn write_listing(&self, f: &mut dyn io::Write, color: bool) -> Result<(), io::Error> {
         for line in &self.lines {
             if config::ARGS.code_only && line.is_inert() {
                 continue;
             }
             write!(f, "{:4} ", line.src_line_num)?;
-            if let Some(bob) = line.obj.as_ref().and_then(|op| op.bob_ref()) {
-                write!(f, "{:28} ", bob)?;
-            } else if let Some(op) = line.obj.as_ref() {
-                write!(f, "{:28} ", op)?;
+            let bob = line.obj.as_ref().and_then(|op| op.bob_ref());
+            let obj = match (bob, line.obj.as_ref()) {
+                (Some(bob), _) => format!("{:28}", bob),
+                (None, Some(op)) => format!("{:28}", op),
+                (None, None) => format!("{:28}", format!("{:04X}", line.addr)),
+            };
+            if !color {
+                writeln!(f, "{}  {line}", obj)?;
+            } else if line.is_inert() {
+                writeln!(f, concat!(yellow!("{}"), green!("{}"), "  ", gray!("{}")), &obj[..4], &obj[4..], line.src)?;
             } else {
-                write!(f, "{:28} ", format!("{:04X}", line.addr),)?;
+                let (addr, bytes) = if bob.is_some() || line.obj.is_none() { obj.split_at(4) } else { ("", &obj[..]) };
+                let operand = line.get_operand();
+                let (operand, comment) = operand.find(';').map_or((operand, ""), |i| operand.split_at(i));
+                writeln!(
+                    f,
+                    concat!(yellow!("{}"), green!("{}"), "  {:8} ", blue!("{:8}"), " {}", gray!("{}")),
+                    addr,
+                    bytes,
+                    line.get_label(),
+                    line.get_operation(),
+                    operand,
+                    comment
+                )?;
             }
-            writeln!(f, " {line}")?;
         }
         Ok(())
     }
+    /// Shows the listing a page at a time (see pager.rs); symbols can be used to jump around
+    pub fn page_listing(&self) -> Result<(), Error> {
+        let mut text = Vec::new();
+        self.write_listing(&mut text, true)?;
+        let symbols = self
+            .lines
+            .iter()
+            .filter(|line| !(config::ARGS.code_only && line.is_inert()))
+            .enumerate()
+            .filter_map(|(i, line)| line.label.as_ref().map(|l| (l.to_string(), i)))
+            .collect();
+        pager::page(&String::from_utf8_lossy(&text), &symbols)
+    }
+    /// Compares the program with the listing (.lst file) of an earlier assembly and shows the
+    /// lines whose bytes changed (with the old bytes) and the addresses that no longer hold code
+    pub fn diff_listing(&self, old_path: &Path) -> Result<(), Error> {
+        let mut old = read_listing(&std::fs::read_to_string(old_path)?);
+        let (mut changed, mut added) = (0, 0);
+        println!("Differences from {}:", old_path.display());
+        for line in self.listing().filter(|l| !l.bytes.is_empty()) {
+            let mut new_bytes = String::new();
+            let mut old_bytes = String::new();
+            let mut differs = false;
+            for (i, &b) in line.bytes.iter().enumerate() {
+                let was = old.remove(&line.addr.wrapping_add(i as u16));
+                match was {
+                    Some(was) if was == b => new_bytes.push_str(&format!("{:02X} ", b)),
+                    Some(_) => new_bytes.push_str(&format!(concat!(red!("{:02X}"), " "), b)),
+                    None => new_bytes.push_str(&format!(concat!(green!("{:02X}"), " "), b)),
+                }
+                match was {
+                    Some(was) => old_bytes.push_str(&format!("{:02X} ", was)),
+                    None => old_bytes.push_str("-- "),
+                }
+                differs |= was != Some(b);
+                changed += matches!(was, Some(was) if was != b) as usize;
+                added += was.is_none() as usize;
+            }
+            if differs {
+                let pad = " ".repeat(24usize.saturating_sub(3 * line.bytes.len()));
+                println!(
+                    "{:4} {:04X} {}{}(was {}) {}",
+                    line.line,
+                    line.addr,
+                    new_bytes,
+                    pad,
+                    old_bytes.trim_end(),
+                    line.src.trim()
+                );
+            }
+        }
+        // whatever is left of the old listing is gone from the new one
+        let mut removed: Vec<(u16, u16)> = Vec::new();
+        for &addr in old.keys() {
+            match removed.last_mut() {
+                Some((_, end)) if end.wrapping_add(1) == addr => *end = addr,
+                _ => removed.push((addr, addr)),
+            }
+        }
+        for (start, end) in &removed {
+            println!(red!("     {:04X}-{:04X} removed"), start, end);
+        }
+        if changed + added + old.len() == 0 {
+            println!("No differences.");
+        } else {
+            println!("{} bytes changed, {} added, {} removed", changed, added, old.len());
+        }
+        Ok(())
+    }
+    /// Returns the labels that stand for locations in the program (not those defined with EQU)
+    /// in order of address
+    pub fn code_labels(&self) -> Vec<(u16, &str)> {
+        let mut labels: Vec<(u16, &str)> =
+            self.labels.map.values().filter(|l| l.node.is_none()).map(|l| (l.addr, &*l.name)).collect();
+        labels.sort();
+        labels
+    }
+    /// Returns the bytes the program puts in memory, as runs of consecutive addresses in the
+    /// order they were assembled (space reserved with RMB isn't included)
+    pub fn segments(&self) -> Vec<Segment> {
+        let mut segments: Vec<Segment> = Vec::new();
+        for line in self.listing().filter(|l| !l.bytes.is_empty()) {
+            match segments.last_mut() {
+                Some(seg) if seg.addr as usize + seg.bytes.len() == line.addr as usize => seg.bytes.extend(line.bytes),
+                _ => segments.push(Segment { addr: line.addr, bytes: line.bytes }),
+            }
+        }
+        segments
+    }
+    /// Returns the program's symbols in the order they're defined
+    pub fn symbols(&self) -> Vec<Symbol<'_>> {
+        let mut symbols: Vec<Symbol> = self
+            .labels
+            .map
+            .values()
+            .map(|l| Symbol {
+                name: &l.name,
+                value: self.labels.get_value(&l.name).map_or(l.addr, |v| v.u16()),
+                line: l.line,
+                equ: l.node.is_some(),
+            })
+            .collect();
+        symbols.sort_by_key(|s| (s.line, s.name));
+        symbols
+    }
+    /// Iterates over the program's lines with their addresses and the bytes each one produced
+    /// (the same information as the .lst file)
+    pub fn listing(&self) -> impl Iterator<Item = ListingLine<'_>> {
+        self.lines.iter().map(|line| {
+            let bob = line.obj.as_ref().and_then(|o| o.bob_ref());
+            let data = bob.and_then(|b| b.data.as_ref());
+            let mut bytes = vec![0; data.map_or(0, |d| d.iter().map(|u| u.size() as usize).sum())];
+            bob.map(|b| b.to_bytes(&mut bytes));
+            ListingLine { line: line.src_line_num, addr: bob.map_or(line.addr, |b| b.addr), bytes, src: &line.src }
+        })
+    }
+    /// Returns the number of the source line that produced the byte at addr
+    pub fn line_at(&self, addr: u16) -> Option<usize> {
+        let contains = |l: &ListingLine| (l.addr as usize..l.addr as usize + l.bytes.len()).contains(&(addr as usize));
+        self.listing().find(contains).map(|l| l.line)
+    }
+    #[allow(clippy::unnecessary_sort_by)]
     pub fn write_output_files(&self, path: &Path) -> Result<(), Error> {
         let basename = path
             .file_stem()
@@ -377,11 +600,11 @@ impl Program {
         // write out the listing file
         pb.set_extension("lst");
         let mut file = File::create(&pb)?;
-        self.write_listing(&mut file)?;
+        self.write_listing(&mut file, false)?;
         println!("wrote listing file: {}", pb.display());
         // now symbols...
         // first create a collection of (name,addr) label tuples
-        let mut labels: Vec<(&String, u16)> = self.labels.map.iter().map(|(s, l)| (s, l.addr)).collect();
+        let mut labels: Vec<(&Rc<str>, u16)> = self.labels.map.iter().map(|(s, l)| (s, l.addr)).collect();
         // sort them by address
         labels.sort_by(|a, b| a.1.cmp(&b.1));
         // now try to write them out to a *.sym file
@@ -394,47 +617,11 @@ impl Program {
         // now the binary...
         let mut hf = HexRecordCollection::new();
         const MAX_DATA: usize = 32;
-        let mut addr = 16;
-        let mut buf = [0u8; MAX_DATA + 1];
-        let mut i = 0;
-        for line in &self.lines {
-            if let Some(bob) = line.obj.as_ref().and_then(|o| o.bob_ref()) {
-                if bob.data.is_some() && bob.addr as usize != addr as usize + i {
-                    // discontinguous object; write previous record (if any)
-                    if i > 0 {
-                        hf.add_record(HexRecord::from_data(addr, &buf[0..i]))?;
-                        i = 0;
-                    }
-                    addr = bob.addr;
-                }
-                if let Some(data) = &bob.data {
-                    // this object contains some data; write it into our buffer
-                    for u in data.iter() {
-                        if let Some(b) = u.msb() {
-                            buf[i] = b;
-                            i += 1;
-                        }
-                        buf[i] = u.lsb();
-                        i += 1;
-                        if i >= MAX_DATA {
-                            // we have accumulated a full record's worth of data; fill a record and add it to our collection
-                            hf.add_record(HexRecord::from_data(addr, &buf[0..MAX_DATA]))?;
-                            (addr, _) = addr.overflowing_add(MAX_DATA as u16);
-                            if i > MAX_DATA {
-                                buf[0] = buf[MAX_DATA];
-                                i = 1
-                            } else {
-                                i = 0
-                            }
-                        }
-                    }
-                }
+        for seg in self.segments() {
+            for (i, chunk) in seg.bytes.chunks(MAX_DATA).enumerate() {
+                hf.add_record(HexRecord::from_data(seg.addr.wrapping_add((i * MAX_DATA) as u16), chunk))?;
             }
         }
-        if i > 0 {
-            // we have some data remaining in the buffer; write it to a record
-            hf.add_record(HexRecord::from_data(addr, &buf[0..i]))?;
-        }
         // add an EOF record to the collection
         hf.add_eof();
         // write out the *.hex file
@@ -445,3 +632,41 @@ impl Program {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_symbols_and_listing() {
+        let src = " org $3000\nstart lda #value\n rmb 2\n nop\nvalue equ $41\n org $4000\n fcb 1,2\n end start";
+        let program = Assembler::new().assemble_str(src).unwrap();
+        let segments = [
+            Segment { addr: 0x3000, bytes: vec![0x86, 0x41] },
+            Segment { addr: 0x3004, bytes: vec![0x12] },
+            Segment { addr: 0x4000, bytes: vec![1, 2] },
+        ];
+        assert_eq!(program.segments(), segments);
+        let symbols: Vec<_> = program.symbols().iter().map(|s| (s.name, s.value, s.line, s.equ)).collect();
+        assert_eq!(symbols, [("start", 0x3000, 2, false), ("value", 0x41, 5, true)]);
+        let nop = program.listing().find(|l| l.src == " nop").unwrap();
+        assert_eq!((nop.line, nop.addr, nop.bytes), (4, 0x3004, vec![0x12]));
+        let lines = [0x3001, 0x4001, 0x3002].map(|addr| program.line_at(addr));
+        assert_eq!(lines, [Some(2), Some(7), None]);
+    }
+
+    #[test]
+    fn listing_round_trip() {
+        let src = " org $3000\nbeef ldx #$1234 ; comment\n fcb 1,2,3,4,5,6,7,8\n* comment\n rmb 4\n fdb beef";
+        let program = Assembler::new().assemble_str(src).unwrap();
+        let mut lst = Vec::new();
+        program.write_listing(&mut lst, false).unwrap();
+        let bytes: BTreeMap<u16, u8> =
+            program.segments().iter().flat_map(|s| (s.addr..).zip(s.bytes.iter().copied())).collect();
+        assert_eq!(bytes.len(), 13);
+        assert_eq!(read_listing(&String::from_utf8_lossy(&lst)), bytes);
+        let mut colored = Vec::new();
+        program.write_listing(&mut colored, true).unwrap();
+        assert_eq!(pager::strip_colors(&String::from_utf8_lossy(&colored)), String::from_utf8_lossy(&lst));
+    }
+}
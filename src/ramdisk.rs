@@ -0,0 +1,111 @@
+//! Bank-switched RAM disk cartridge (in the spirit of the Disto RAM disk).
+//!
+//! The RAM disk is accessed through four registers starting at its base address:
+//! ```text
+//! base+0  address bits 16-23
+//! base+1  address bits 8-15
+//! base+2  address bits 0-7
+//! base+3  data; reads and writes auto-increment the 24-bit address
+//! ```
+//! Addresses beyond the size of the RAM disk wrap around. If a backing file is given then the
+//! contents are loaded from it at startup (when it exists) and saved to it at exit.
+use super::*;
+use cart::Cartridge;
+use std::cell::Cell;
+use std::path::PathBuf;
+
+pub struct RamDisk {
+    pub base: u16,
+    data: Vec<u8>,
+    addr: Cell<usize>,
+    path: Option<PathBuf>,
+}
+impl RamDisk {
+    pub fn new(base: u16, size_kb: usize, path: Option<&Path>) -> Result<Self, Error> {
+        let mut data = vec![0u8; size_kb * 1024];
+        if data.is_empty() || data.len() > 0x1000000 {
+            return Err(general_err!("RAM disk size must be between 1K and 16384K"));
+        }
+        if let Some(p) = path.filter(|p| p.exists()) {
+            let saved = std::fs::read(p)?;
+            let n = saved.len().min(data.len());
+            data[..n].copy_from_slice(&saved[..n]);
+            info!("RAM disk loaded {} bytes from {}", n, p.display());
+        }
+        info!("RAM disk ({}K) enabled at {:04X}", size_kb, base);
+        Ok(RamDisk {
+            base,
+            data,
+            addr: Cell::new(0),
+            path: path.map(|p| p.to_path_buf()),
+        })
+    }
+    fn get(&self, addr: u16) -> u8 {
+        let a = self.addr.get();
+        match addr - self.base {
+            0 => (a >> 16) as u8,
+            1 => (a >> 8) as u8,
+            2 => a as u8,
+            _ => {
+                self.addr.set((a + 1) & 0xffffff);
+                self.data[a % self.data.len()]
+            }
+        }
+    }
+    fn put(&mut self, addr: u16, byte: u8) {
+        let a = self.addr.get();
+        let new_addr = match addr - self.base {
+            0 => (a & 0x00ffff) | (byte as usize) << 16,
+            1 => (a & 0xff00ff) | (byte as usize) << 8,
+            2 => (a & 0xffff00) | byte as usize,
+            _ => {
+                let len = self.data.len();
+                self.data[a % len] = byte;
+                (a + 1) & 0xffffff
+            }
+        };
+        self.addr.set(new_addr);
+    }
+}
+impl Cartridge for RamDisk {
+    fn name(&self) -> String { format!("{}K RAM disk at {:04X}", self.data.len() / 1024, self.base) }
+    fn owns_address(&self, addr: u16) -> bool { addr >= self.base && addr - self.base < 4 }
+    fn read(&self, addr: u16, _cycle: u64) -> Option<u8> { self.owns_address(addr).then(|| self.get(addr)) }
+    fn write(&mut self, addr: u16, byte: u8, _cycle: u64) -> Result<bool, Error> {
+        let owned = self.owns_address(addr);
+        if owned {
+            self.put(addr, byte);
+        }
+        Ok(owned)
+    }
+    fn tag(&self) -> [u8; 4] { *b"RDSK" }
+    /// The address (3 bytes) and the contents packed with PackBits
+    fn snapshot(&self, _cycle: u64) -> Option<Vec<u8>> {
+        let mut state = (self.addr.get() as u32).to_be_bytes()[1..].to_vec();
+        state.extend(rewind::pack(&self.data));
+        Some(state)
+    }
+    fn check_snapshot(&self, state: &[u8]) -> Result<(), Error> {
+        snapshot::check_len(&self.tag(), state, 3)?;
+        let len = rewind::unpack(&state[3..])?.len();
+        if len != self.data.len() {
+            let (saved, size) = (len / 1024, self.data.len() / 1024);
+            return Err(general_err!("the snapshot's RAM disk is {}K but this one is {}K", saved, size));
+        }
+        Ok(())
+    }
+    fn restore(&mut self, state: &[u8], _cycle: u64) {
+        self.addr.set(u32::from_be_bytes([0, state[0], state[1], state[2]]) as usize);
+        if let Ok(data) = rewind::unpack(&state[3..]) {
+            self.data.copy_from_slice(&data);
+        }
+    }
+    /// Saves the contents to the backing file (if any). Called when the emulator exits.
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(p) = self.path.as_ref() {
+            std::fs::write(p, &self.data)?;
+            info!("RAM disk saved to {}", p.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+//! The list of recently used programs, cartridges and disks.
+//!
+//! Every file loaded with --load, --cart or --disk is remembered in ~/.coco_recent (or the file
+//! given with --recent-file), most recent first. When coco is started from a terminal with
+//! nothing to load (no --cassette or --state either) it lists the recent files and loads the
+//! one that's chosen, as if it had been given on the command line:
+//! ```text
+//! Recently used:
+//!   1. cart  /home/me/games/dungeon.ccc
+//!   2. disk  /home/me/disks/tools.dsk
+//! Load which (1-2, or enter for none)?
+//! ```
+//! The file is plain text with one "<kind> <path>" line per entry.
+use super::*;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// the most files that are remembered
+const MAX_RECENT: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Load,
+    Cart,
+    Disk,
+}
+impl Kind {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "load" => Some(Kind::Load),
+            "cart" => Some(Kind::Cart),
+            "disk" => Some(Kind::Disk),
+            _ => None,
+        }
+    }
+    /// Returns the command line option that loads this kind of file
+    pub fn option(&self) -> &'static str {
+        match self {
+            Kind::Load => "--load",
+            Kind::Cart => "--cart",
+            Kind::Disk => "--disk",
+        }
+    }
+}
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", &self.option()[2..]) }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub kind: Kind,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct Recent {
+    pub entries: Vec<Entry>,
+}
+impl Recent {
+    /// Returns the path of the file in which the list is kept
+    pub fn path(recent_file: Option<&PathBuf>) -> Option<PathBuf> {
+        recent_file.cloned().or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".coco_recent")))
+    }
+    /// Reads the list (which is empty if there's no file yet). Lines that can't be parsed are
+    /// skipped.
+    pub fn open(path: &Path) -> Self {
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        let entries = text
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter_map(|(kind, path)| Some(Entry { kind: Kind::parse(kind)?, path: PathBuf::from(path) }))
+            .collect();
+        Recent { entries }
+    }
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let text: String = self.entries.iter().map(|e| format!("{} {}\n", e.kind, e.path.display())).collect();
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+    /// Moves a file to the top of the list (adding it if it's new)
+    pub fn add(&mut self, kind: Kind, path: &Path) {
+        // keep absolute paths so that the list works from any directory
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let entry = Entry { kind, path };
+        self.entries.retain(|e| *e != entry);
+        self.entries.insert(0, entry);
+        self.entries.truncate(MAX_RECENT);
+    }
+    /// Lists the files that still exist on the terminal and returns the one the user chooses
+    /// (if any)
+    pub fn choose(&self) -> Option<Entry> {
+        let entries: Vec<&Entry> = self.entries.iter().filter(|e| e.path.exists()).collect();
+        if entries.is_empty() || !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return None;
+        }
+        println!("{}", tr!("Recently used:"));
+        for (i, entry) in entries.iter().enumerate() {
+            println!("{:>3}. {:<5} {}", i + 1, entry.kind, entry.path.display());
+        }
+        loop {
+            print!("{}", tr!("Load which (1-{}, or enter for none)? ", entries.len()));
+            io::stdout().flush().ok()?;
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).ok()?;
+            let line = line.trim();
+            if line.is_empty() {
+                return None;
+            }
+            match line.parse::<usize>() {
+                Ok(n @ 1..) if n <= entries.len() => return Some(entries[n - 1].clone()),
+                _ => println!("{}", tr!("Enter a number from 1 to {}.", entries.len())),
+            }
+        }
+    }
+}
+
+/// Adds the files given on the command line to the list (unless it's turned off)
+pub fn remember() {
+    if config::kiosk() || config::ARGS.no_recent {
+        return;
+    }
+    let Some(path) = Recent::path(config::ARGS.recent_file.as_ref()) else {
+        return;
+    };
+    let mut recent = Recent::open(&path);
+    for disk in config::ARGS.disk_images().rev() {
+        recent.add(Kind::Disk, disk);
+    }
+    if let Some(cart) = config::ARGS.cart.as_ref() {
+        recent.add(Kind::Cart, cart);
+    }
+    for load in config::ARGS.load.iter().rev() {
+        recent.add(Kind::Load, load);
+    }
+    if let Err(e) = recent.save(&path) {
+        warn!("failed to save the recent files to {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list() {
+        let path = std::env::temp_dir().join(format!("coco-recent-{}", std::process::id()));
+        std::fs::write(&path, "cart /games/a.ccc\nbogus line\ndisk /disks/my disk.dsk\n").unwrap();
+        let mut recent = Recent::open(&path);
+        assert_eq!(recent.entries.len(), 2);
+        assert_eq!(recent.entries[1], Entry { kind: Kind::Disk, path: PathBuf::from("/disks/my disk.dsk") });
+        recent.add(Kind::Disk, Path::new("/disks/my disk.dsk"));
+        for i in 0..MAX_RECENT - 1 {
+            recent.add(Kind::Load, Path::new(&format!("/code/{}.asm", i)));
+        }
+        recent.save(&path).unwrap();
+        let recent = Recent::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recent.entries.len(), MAX_RECENT);
+        assert_eq!(recent.entries[0].path, PathBuf::from("/code/8.asm"));
+        assert_eq!(recent.entries[MAX_RECENT - 1].kind, Kind::Disk);
+        assert_eq!(Kind::Cart.to_string(), "cart");
+    }
+}
@@ -0,0 +1,136 @@
+//! Labeled memory regions used by the debugger.
+//!
+//! Regions come from the symbol file (lines of the form `START-END,kind[,name]`) or from the
+//! debugger's `region` command. They're used to colorize hexdumps and disassembly and to warn
+//! when execution wanders into an area that has been labeled as data.
+use super::*;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    Data,
+    Stack,
+    Vram,
+    Io,
+}
+impl RegionKind {
+    /// ANSI color used when displaying bytes in a region of this kind
+    pub fn color(&self) -> &'static str {
+        match self {
+            RegionKind::Code => "92",
+            RegionKind::Data => "93",
+            RegionKind::Stack => "95",
+            RegionKind::Vram => "96",
+            RegionKind::Io => "91",
+        }
+    }
+}
+impl FromStr for RegionKind {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "code" => Ok(RegionKind::Code),
+            "data" => Ok(RegionKind::Data),
+            "stack" => Ok(RegionKind::Stack),
+            "vram" => Ok(RegionKind::Vram),
+            "io" => Ok(RegionKind::Io),
+            _ => Err(general_err!("unknown region kind \"{}\" (expected code, data, stack, vram or io)", s)),
+        }
+    }
+}
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = format!("{:?}", self).to_lowercase();
+        write!(f, "\x1b[{}m{:5}\x1b[0m", self.color(), s)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16, // inclusive
+    pub kind: RegionKind,
+    pub name: String,
+}
+impl Region {
+    /// Parses a symbol file line of the form START-END,kind[,name] (addresses in hex)
+    pub fn parse(line: &str) -> Result<Self, Error> {
+        let comps: Vec<&str> = line.split(',').map(str::trim).collect();
+        let (start, end) = comps[0].split_once('-').ok_or_else(|| general_err!("bad region range: {}", comps[0]))?;
+        let start = u16::from_str_radix(start, 16).map_err(|_| general_err!("bad region start: {}", start))?;
+        let end = u16::from_str_radix(end, 16).map_err(|_| general_err!("bad region end: {}", end))?;
+        if end < start || comps.len() < 2 || comps.len() > 3 {
+            return Err(general_err!("invalid region: {}", line));
+        }
+        Ok(Region {
+            start,
+            end,
+            kind: comps[1].parse()?,
+            name: comps.get(2).unwrap_or(&"").to_string(),
+        })
+    }
+    pub fn contains(&self, addr: u16) -> bool { (self.start..=self.end).contains(&addr) }
+}
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}-{:04X} {} {}", self.start, self.end, self.kind, self.name)
+    }
+}
+
+/// The table of regions. When regions overlap, the most recently added one wins.
+#[derive(Default)]
+pub struct Regions {
+    list: Vec<Region>,
+    last_pc_data: Option<u16>, // start of the data region in which execution was last seen
+}
+impl Regions {
+    pub fn add(&mut self, region: Region) { self.list.push(region) }
+    /// Removes regions that start at addr; returns the number removed
+    pub fn remove(&mut self, addr: u16) -> usize {
+        let len = self.list.len();
+        self.list.retain(|r| r.start != addr);
+        len - self.list.len()
+    }
+    pub fn clear(&mut self) { self.list.clear() }
+    pub fn iter(&self) -> impl Iterator<Item = &Region> { self.list.iter() }
+    pub fn at(&self, addr: u16) -> Option<&Region> { self.list.iter().rev().find(|r| r.contains(addr)) }
+    pub fn color_at(&self, addr: u16) -> Option<&'static str> { self.at(addr).map(|r| r.kind.color()) }
+    /// Wraps text in the color of the region containing addr (if any)
+    pub fn colorize(&self, addr: u16, text: &str) -> String {
+        match self.color_at(addr) {
+            Some(c) => format!("\x1b[{}m{}\x1b[0m", c, text),
+            None => text.to_string(),
+        }
+    }
+    /// Warns (once per entry) when an instruction is executed from a region labeled as data
+    pub fn check_pc(&mut self, pc: u16) {
+        let data = self.at(pc).filter(|r| r.kind == RegionKind::Data);
+        let start = data.map(|r| r.start);
+        if start.is_some() && start != self.last_pc_data {
+            let r = data.unwrap();
+            warn!("execution entered data region {:04X}-{:04X} {} at {:04X}", r.start, r.end, r.name, pc);
+        }
+        self.last_pc_data = start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_lookup() -> Result<(), Error> {
+        let mut regions = Regions::default();
+        regions.add(Region::parse("0400-05FF,vram,screen")?);
+        regions.add(Region::parse("0500-0507,data")?);
+        assert_eq!(regions.at(0x0400).map(|r| r.kind), Some(RegionKind::Vram));
+        assert_eq!(regions.at(0x0501).map(|r| r.kind), Some(RegionKind::Data));
+        assert!(regions.at(0x0600).is_none());
+        assert_eq!(regions.remove(0x0500), 1);
+        assert_eq!(regions.at(0x0501).map(|r| r.name.as_str()), Some("screen"));
+        assert!(Region::parse("0600-0500,code").is_err());
+        assert!(Region::parse("0400-05FF,bogus").is_err());
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@
 use super::*;
 
 /// Enumeration of the condition code register bits
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CCBit {
     C = 0,
     V = 1,
@@ -35,30 +35,6 @@ macro_rules! sign_bit_8 {
         (($b & 0x80) == 0x80)
     };
 }
-macro_rules! sign_bit_16 {
-    ($w:ident) => {
-        (($w & 0x8000) == 0x8000)
-    };
-}
-macro_rules! signed_sub_overflow {
-    ($a:ident,$b:ident, $t:ty) => {{
-        let (_, _o) = ($a as $t).overflowing_sub(($b as $t));
-        _o
-    }};
-}
-macro_rules! signed_add_overflow {
-    ($a:ident,$b:ident, $t:ty) => {{
-        let (_, _o) = ($a as $t).overflowing_add(($b as $t));
-        _o
-    }};
-}
-
-macro_rules! twos_comp {
-    ($a:ident) => {{
-        let (z, _) = $a.overflowing_neg();
-        z
-    }};
-}
 /// Metadata for each condition code register bit.
 #[rustfmt::skip]
 static CC_TABLE: [CCInfo;8] = [
@@ -74,6 +50,11 @@ static CC_TABLE: [CCInfo;8] = [
 
 impl CCBit {
     pub fn info(&self) -> &CCInfo { &CC_TABLE[*self as usize] }
+    /// Returns the bit with the given letter (e.g., 'z' or 'Z')
+    pub fn from_char(c: char) -> Option<CCBit> {
+        CC_TABLE.iter().find(|t| t.short == c.to_ascii_uppercase()).map(|t| t.bit)
+    }
+    pub fn short(&self) -> char { self.info().short }
 }
 
 impl CCBits {
@@ -101,66 +82,49 @@ impl CCBits {
         }
         v
     }
+    /// Sets N, Z, V and C (and H if half is true) to the flags an operation produced
+    pub fn set_flags(&mut self, flags: u8oru16::Flags, half: bool) {
+        self.set(CCBit::N, flags.n);
+        self.set(CCBit::Z, flags.z);
+        self.set(CCBit::V, flags.v);
+        self.set(CCBit::C, flags.c);
+        if half {
+            self.set(CCBit::H, flags.h);
+        }
+    }
     // condition code struct doubles as ALU
     pub fn add_u8(&mut self, a: u8, b: u8, with_carry: bool) -> u8 {
-        let carry_in = u8::from(with_carry && self.is_set(CCBit::C));
-        let (a1, c1) = a.overflowing_add(carry_in);
-        let (result, c2) = a1.overflowing_add(b);
-        self.set(CCBit::C, c1 || c2);
-        self.set(
-            CCBit::V,
-            signed_add_overflow!(a, carry_in, i8) || signed_add_overflow!(a1, b, i8),
-        );
-        self.set(CCBit::Z, result == 0);
-        self.set(CCBit::N, sign_bit_8!(result));
-        self.set(CCBit::H, ((a & 0xf) + (b & 0xf) + carry_in) & 0x10 == 0x10);
-        result
+        let (result, flags) = u8u16::u8(a).overflowing_add(u8u16::u8(b), with_carry && self.is_set(CCBit::C));
+        self.set_flags(flags, true);
+        result.u8()
     }
     pub fn add_u16(&mut self, a: u16, b: u16) -> u16 {
-        let (result, c) = a.overflowing_add(b);
-        self.set(CCBit::C, c);
-        self.set(CCBit::V, signed_add_overflow!(a, b, i16));
-        self.set(CCBit::Z, result == 0);
-        self.set(CCBit::N, sign_bit_16!(result));
-        result
+        let (result, flags) = u8u16::u16(a).overflowing_add(u8u16::u16(b), false);
+        self.set_flags(flags, false);
+        result.u16()
     }
 
     pub fn sub_u16(&mut self, a: u16, b: u16) -> u16 {
-        let (result, c) = a.overflowing_sub(b);
-        self.set(CCBit::C, c);
-        self.set(CCBit::V, signed_sub_overflow!(a, b, i16));
-        self.set(CCBit::Z, result == 0);
-        self.set(CCBit::N, sign_bit_16!(result));
-        result
+        let (result, flags) = u8u16::u16(a).overflowing_sub(u8u16::u16(b), false);
+        self.set_flags(flags, false);
+        result.u16()
     }
     pub fn sub_u8(&mut self, a: u8, b: u8, with_carry: bool) -> u8 {
-        let borrow = u8::from(with_carry && self.is_set(CCBit::C));
-        let (a1, c1) = a.overflowing_sub(borrow);
-        let (result, c2) = a1.overflowing_sub(b);
-        self.set(CCBit::C, c1 || c2);
-        self.set(
-            CCBit::V,
-            signed_sub_overflow!(a, borrow, i8) || signed_sub_overflow!(a1, b, i8),
-        );
-        self.set(CCBit::Z, result == 0);
-        self.set(CCBit::N, sign_bit_8!(result));
-        result
+        let (result, flags) = u8u16::u8(a).overflowing_sub(u8u16::u8(b), with_carry && self.is_set(CCBit::C));
+        self.set_flags(flags, false);
+        result.u8()
     }
     // note: DEC does not affect the carry flag
     pub fn dec_u8(&mut self, val: u8) -> u8 {
-        let (result, v) = val.overflowing_sub(1);
-        self.set(CCBit::V, v);
-        self.set(CCBit::Z, result == 0);
-        self.set(CCBit::N, result & 0x80 != 0);
-        result
+        let (result, flags) = u8u16::u8(val).overflowing_sub(u8u16::u8(1), false);
+        self.set_flags(u8oru16::Flags { c: self.is_set(CCBit::C), ..flags }, false);
+        result.u8()
     }
     // note INC does not affect the carry flag
     pub fn inc_u8(&mut self, val: u8) -> u8 {
-        let (result, v) = val.overflowing_add(1);
-        self.set(CCBit::V, v);
-        self.set(CCBit::Z, result == 0);
-        self.set(CCBit::N, result & 0x80 != 0);
-        result
+        let (result, flags) = u8u16::u8(val).overflowing_add(u8u16::u8(1), false);
+        self.set_flags(u8oru16::Flags { c: self.is_set(CCBit::C), ..flags }, false);
+        result.u8()
     }
 
     pub fn and_u8(&mut self, val1: u8, val2: u8) -> u8 {
@@ -200,15 +164,14 @@ impl CCBits {
         result
     }
     pub fn neg_u8(&mut self, val: u8) -> u8 {
-        let result = twos_comp!(val);
-        self.set(CCBit::C, val != 0);
-        self.set(CCBit::V, val == 0x80);
-        self.set(CCBit::N, 0x80 & result != 0);
-        self.set(CCBit::Z, result == 0);
-        result
+        let (result, flags) = u8u16::u8(0).overflowing_sub(u8u16::u8(val), false);
+        self.set_flags(flags, false);
+        result.u8()
+    }
+    pub fn cmp_u8(&mut self, val1: u8, val2: u8) { self.set_flags(u8u16::u8(val1).compare(u8u16::u8(val2)), false) }
+    pub fn cmp_u16(&mut self, val1: u16, val2: u16) {
+        self.set_flags(u8u16::u16(val1).compare(u8u16::u16(val2)), false)
     }
-    pub fn cmp_u8(&mut self, val1: u8, val2: u8) { self.sub_u8(val1, val2, false); }
-    pub fn cmp_u16(&mut self, val1: u16, val2: u16) { self.sub_u16(val1, val2); }
     pub fn shl_u8(&mut self, val: u8) -> u8 {
         let c = sign_bit_8!(val);
         let result = val << 1;
@@ -263,45 +226,15 @@ impl CCBits {
     }
 }
 use std::fmt;
+/// Shows the flags as "EFHINZVC" with the set bits in upper case and the clear bits in lower case
 impl fmt::Display for CCBits {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            concat!(
-                green!("{}:"),
-                "{} ",
-                green!("{}:"),
-                "{} ",
-                green!("{}:"),
-                "{} ",
-                green!("{}:"),
-                "{} ",
-                green!("{}:"),
-                "{} ",
-                green!("{}:"),
-                "{} ",
-                green!("{}:"),
-                "{} ",
-                green!("{}:"),
-                "{}"
-            ),
-            CC_TABLE[0].short,
-            self.is_set(CC_TABLE[0].bit) as usize,
-            CC_TABLE[1].short,
-            self.is_set(CC_TABLE[1].bit) as usize,
-            CC_TABLE[2].short,
-            self.is_set(CC_TABLE[2].bit) as usize,
-            CC_TABLE[3].short,
-            self.is_set(CC_TABLE[3].bit) as usize,
-            CC_TABLE[4].short,
-            self.is_set(CC_TABLE[4].bit) as usize,
-            CC_TABLE[5].short,
-            self.is_set(CC_TABLE[5].bit) as usize,
-            CC_TABLE[6].short,
-            self.is_set(CC_TABLE[6].bit) as usize,
-            CC_TABLE[7].short,
-            self.is_set(CC_TABLE[7].bit) as usize,
-        )
+        let flags: String = CC_TABLE
+            .iter()
+            .rev()
+            .map(|t| if self.is_set(t.bit) { t.short } else { t.short.to_ascii_lowercase() })
+            .collect();
+        write!(f, "{}", flags)
     }
 }
 
@@ -348,6 +281,17 @@ pub fn reg_size(reg: Name) -> u16 {
     }
 }
 
+/// A register whose value differs between two register sets (see Set::diff)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegChange {
+    pub reg: Name,
+    pub old: u8u16,
+    pub new: u8u16,
+}
+impl fmt::Display for RegChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{:?}:{}->{}", self.reg, self.old, self.new) }
+}
+
 /// Provides storage and helpers for the full set of 6809 registers.
 #[derive(Clone, Copy, Default)]
 pub struct Set {
@@ -414,6 +358,39 @@ impl Set {
             Name::Z => panic!("invalid register"),
         }
     }
+    /// Lists the registers whose values differ in other (D is covered by A and B)
+    pub fn diff(&self, other: &Set) -> Vec<RegChange> {
+        use Name::*;
+        [A, B, X, Y, U, S, PC, DP, CC]
+            .iter()
+            .map(|&reg| RegChange {
+                reg,
+                old: self.get_register(reg),
+                new: other.get_register(reg),
+            })
+            .filter(|c| c.old != c.new)
+            .collect()
+    }
+    /// Formats the registers like Display but with the values that differ from before
+    /// highlighted (except PC, which nearly always changes)
+    pub fn display_changes(&self, before: &Set) -> String {
+        use Name::*;
+        [X, Y, U, S, PC, A, B, D, DP, CC]
+            .iter()
+            .map(|&reg| {
+                let val = match reg {
+                    CC => self.cc.to_string(),
+                    _ => format!("{:0w$x}", self.get_register(reg).u16(), w = 2 * reg_size(reg) as usize),
+                };
+                if reg != PC && before.get_register(reg) != self.get_register(reg) {
+                    format!(concat!(blue!("{}:"), yellow!("{}")), reg.to_str(), val)
+                } else {
+                    format!(concat!(blue!("{}:"), "{}"), reg.to_str(), val)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
     fn sync_d(&mut self) { self.d = ((self.a as u16) << 8) | (self.b as u16); }
     fn sync_ab(&mut self) {
         self.a = (self.d >> 8) as u8;
@@ -447,9 +424,28 @@ impl fmt::Display for Set {
                 blue!("DP:"),
                 "{:02x} ",
                 blue!("CC:"),
-                "{:02x}"
+                "{}"
             ),
-            self.x, self.y, self.u, self.s, self.pc, self.a, self.b, self.d, self.dp, self.cc.reg
+            self.x, self.y, self.u, self.s, self.pc, self.a, self.b, self.d, self.dp, self.cc
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn diff() {
+        use super::*;
+        let before = Set::default();
+        let mut after = before;
+        after.set_register(Name::A, u8u16::u8(0x3f));
+        after.cc.set(CCBit::Z, true);
+        let changes = before.diff(&after);
+        // only A and CC changed
+        assert_eq!(changes.iter().map(|c| c.reg).collect::<Vec<_>>(), vec![Name::A, Name::CC]);
+        assert_eq!(changes[0].to_string(), "A:00->3F");
+        assert!(after.diff(&after).is_empty());
+        assert_eq!(after.cc.to_string(), "efhinZvc");
+        assert_eq!(CCBit::from_char('v'), Some(CCBit::V));
+    }
+}
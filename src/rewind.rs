@@ -0,0 +1,160 @@
+//! Gameplay rewind.
+//!
+//! With --rewind the machine's state is snapshotted every --rewind-interval seconds of emulated
+//! time (1 by default) and the most recent snapshots are kept (30 by default). Pressing F9 puts
+//! the machine back the way it was at the last snapshot that's at least half an interval old,
+//! so a quick press goes back a second or two and each further press goes back another snapshot.
+//!
+//! The snapshots are the ones `state save` writes (see snapshot.rs), so everything a snapshot
+//! file holds is rewound, with RAM packed with PackBits since most of it is usually runs of the
+//! same byte. Anything outside the machine (disk images, printer output, serial connections)
+//! isn't rewound.
+use super::*;
+use crate::core::CPU_HZ;
+
+/// Packs data with PackBits: a header byte n followed by n+1 literal bytes (n < 128) or by one
+/// byte that's repeated 257-n times (n > 128)
+pub fn pack(data: &[u8]) -> Vec<u8> {
+    let mut packed = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take(128).take_while(|&&b| b == data[i]).count();
+        if run > 1 {
+            packed.extend([(257 - run) as u8, data[i]]);
+            i += run;
+            continue;
+        }
+        // literal bytes continue up to the next run of 3 or more
+        let run_at = |j: usize| j + 2 < data.len() && data[j] == data[j + 1] && data[j] == data[j + 2];
+        let mut end = i + 1;
+        while end < data.len() && end - i < 128 && !run_at(end) {
+            end += 1;
+        }
+        packed.push((end - i - 1) as u8);
+        packed.extend_from_slice(&data[i..end]);
+        i = end;
+    }
+    packed
+}
+
+/// Unpacks data packed by pack (or returns an error if it's cut short)
+pub fn unpack(packed: &[u8]) -> Result<Vec<u8>, Error> {
+    let truncated = || general_err!("packed data is truncated");
+    let mut data = Vec::new();
+    let mut i = 0;
+    while i < packed.len() {
+        let n = packed[i] as usize;
+        if n < 128 {
+            data.extend_from_slice(packed.get(i + 1..i + 2 + n).ok_or_else(truncated)?);
+            i += n + 2;
+        } else {
+            data.extend(std::iter::repeat_n(*packed.get(i + 1).ok_or_else(truncated)?, 257 - n));
+            i += 2;
+        }
+    }
+    Ok(data)
+}
+
+#[derive(Debug)]
+pub struct Rewind {
+    /// the snapshots and when they were taken
+    snapshots: VecDeque<(u64, Vec<u8>)>,
+    depth: usize,
+    /// cycles between snapshots
+    interval: u64,
+    /// when the next snapshot is due
+    next: u64,
+}
+impl Rewind {
+    pub fn new(depth: usize, secs: f64) -> Self {
+        info!("keeping {} snapshots {} second(s) apart for rewinding (press F9)", depth, secs);
+        let interval = (secs * CPU_HZ as f64) as u64;
+        Rewind { snapshots: VecDeque::with_capacity(depth), depth, interval, next: 0 }
+    }
+    /// Creates the rewind buffer from the --rewind and --rewind-interval options
+    pub fn from_args() -> Option<Self> {
+        let depth = config::ARGS.rewind.filter(|&depth| depth > 0)?;
+        Some(Rewind::new(depth, config::ARGS.rewind_interval))
+    }
+    /// Returns true if a snapshot should be taken
+    pub fn due(&self, cycle: u64) -> bool { cycle >= self.next }
+    /// Keeps a snapshot taken at cycle (dropping the oldest if the buffer is full)
+    fn push(&mut self, cycle: u64, snapshot: Vec<u8>) {
+        self.next = cycle + self.interval;
+        if self.snapshots.len() == self.depth {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((cycle, snapshot));
+    }
+    /// Removes and returns the latest snapshot that's at least half an interval old (or the
+    /// oldest one if they're all newer)
+    fn pop(&mut self, cycle: u64) -> Option<(u64, Vec<u8>)> {
+        while let Some(snapshot) = self.snapshots.pop_back() {
+            if cycle.saturating_sub(snapshot.0) >= self.interval / 2 || self.snapshots.is_empty() {
+                self.next = cycle + self.interval;
+                return Some(snapshot);
+            }
+        }
+        None
+    }
+}
+
+impl Core {
+    /// Snapshots the machine for rewinding (if a snapshot is due)
+    pub fn rewind_snapshot(&mut self) {
+        if !self.rewind.as_ref().is_some_and(|r| r.due(self.clock_cycles)) {
+            return;
+        }
+        let snapshot = self.snapshot();
+        self.rewind.as_mut().unwrap().push(self.clock_cycles, snapshot);
+    }
+    /// Puts the machine back the way it was at an earlier snapshot. Returns false if there
+    /// isn't one.
+    pub fn rewind(&mut self) -> Result<bool, Error> {
+        let Some((cycle, snapshot)) = self.rewind.as_mut().and_then(|r| r.pop(self.clock_cycles)) else {
+            info!("{}", tr!("nothing to rewind to"));
+            return Ok(false);
+        };
+        for warning in self.restore(&snapshot)? {
+            warn!("{}", warning);
+        }
+        let secs = (self.clock_cycles - cycle) as f64 / CPU_HZ as f64;
+        let (secs, pc) = (format!("{:.1}", secs), format!("{:04X}", self.reg.pc));
+        info!("{}", tr!("rewound {} seconds to PC={}", secs, pc));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packing() {
+        let mut data = vec![0u8; 300];
+        data.extend([1, 2, 3, 3, 4, 5, 5, 5, 5, 6]);
+        data.extend((0..=255).cycle().take(700).map(|b: u32| b as u8));
+        data.push(9);
+        let packed = pack(&data);
+        assert!(packed.len() < data.len() - 250);
+        assert_eq!(unpack(&packed).unwrap(), data);
+        assert!(unpack(&packed[..packed.len() - 1]).is_err() && unpack(&[0x80]).is_err());
+        assert_eq!(pack(&[7, 7]), vec![255, 7]);
+        assert_eq!(pack(&[1, 2]), vec![1, 1, 2]);
+        assert!(pack(&[]).is_empty());
+    }
+
+    #[test]
+    fn buffer() {
+        let mut rewind = Rewind::new(3, 1.0);
+        assert!(rewind.due(0));
+        for secs in 0..5 {
+            rewind.push(secs * CPU_HZ, Vec::new());
+        }
+        assert!(!rewind.due(4 * CPU_HZ + 1) && rewind.snapshots.len() == 3);
+        // the snapshot at 4s is too recent
+        assert_eq!(rewind.pop(4 * CPU_HZ + 100).unwrap().0, 3 * CPU_HZ);
+        assert_eq!(rewind.pop(4 * CPU_HZ + 200).unwrap().0, 2 * CPU_HZ);
+        assert!(rewind.pop(5 * CPU_HZ).is_none());
+    }
+}
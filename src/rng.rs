@@ -0,0 +1,81 @@
+//! The emulator's only source of randomness.
+//!
+//! Anything nondeterministic (such as the contents of RAM at power-on) draws from one generator
+//! seeded by --seed so that a run can be repeated exactly. Without --seed a seed is picked from
+//! the clock and printed (with --verbose) so that an interesting run can still be reproduced.
+use super::*;
+
+/// How RAM is filled at power-on. Some programs accidentally depend on what's typically
+/// found in RAM when a real machine is switched on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RamPattern {
+    /// all bytes are 0x00
+    Zeros,
+    /// all bytes are 0xFF
+    Ones,
+    /// 0x00 and 0xFF in alternating bytes
+    Alternating,
+    /// random bytes (see --seed)
+    Random,
+}
+
+/// A small xorshift64* generator; plenty for emulating noise and not worth a dependency
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // the state must never be zero so mix the seed first (splitmix64)
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng { state: (z ^ (z >> 31)) | 1 }
+    }
+    /// Creates the generator from --seed (or from the clock if no seed was given)
+    pub fn from_args() -> Self {
+        let seed = config::ARGS.seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        verbose_println!("random seed is {} (use --seed to repeat this run)", seed);
+        Rng::new(seed)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    pub fn next_u8(&mut self) -> u8 { (self.next_u64() >> 56) as u8 }
+    /// Fills ram with the given power-on pattern
+    pub fn fill(&mut self, ram: &mut [u8], pattern: RamPattern) {
+        for (i, b) in ram.iter_mut().enumerate() {
+            *b = match pattern {
+                RamPattern::Zeros => 0,
+                RamPattern::Ones => 0xff,
+                RamPattern::Alternating => if i % 2 == 0 { 0 } else { 0xff },
+                RamPattern::Random => self.next_u8(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_patterns() {
+        let mut a = [0u8; 16];
+        let mut b = [0u8; 16];
+        Rng::new(42).fill(&mut a, RamPattern::Random);
+        Rng::new(42).fill(&mut b, RamPattern::Random);
+        assert_eq!(a, b);
+        Rng::new(43).fill(&mut b, RamPattern::Random);
+        assert_ne!(a, b);
+        Rng::new(0).fill(&mut a, RamPattern::Alternating);
+        assert_eq!(a[..4], [0, 0xff, 0, 0xff]);
+    }
+}
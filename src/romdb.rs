@@ -0,0 +1,315 @@
+//! A database of ROM entry points.
+//!
+//! The entry points of the standard ROMs (Color BASIC's POLCAT, CHROUT, CSRDON and so on) are
+//! listed in romdb.txt, which is built into coco, keyed by the CRC32 of each ROM image. Once
+//! everything is loaded the ROM windows are checksummed and the entry points of any ROM that's
+//! recognized are added to the symbol table, so traces, the debugger's disassembly and anything
+//! else that shows symbols name the ROM routines a program calls. More ROMs (or more entry
+//! points) can be described in a file of the same format given with --rom-db.
+//!
+//! An entry point can also describe its arguments, so that --rom-calls can log each call into
+//! a ROM routine (e.g., "CHROUT 'A' dev=0") as a readable narrative of what a program does.
+use super::*;
+use registers::Name;
+
+const BUILT_IN: &str = include_str!("romdb.txt");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// the routine is at this address
+    Fixed(u16),
+    /// the address of the routine is stored here
+    Vector(u16),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rom {
+    pub crc: u32,
+    pub start: u16,
+    pub len: usize,
+    pub name: String,
+}
+
+/// Where an argument of a routine comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Reg(Name),
+    /// a variable in memory
+    Mem(u16),
+}
+
+/// How an argument is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Hex,
+    Dec,
+    Char,
+    /// a 16-bit value (in hex)
+    Word,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arg {
+    pub label: Option<String>,
+    pub source: Source,
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub loc: Location,
+    pub args: Vec<Arg>,
+}
+
+/// ROMs that share a set of entry points (e.g., the versions of Color BASIC)
+#[derive(Debug, Default)]
+pub struct Group {
+    pub roms: Vec<Rom>,
+    pub entries: Vec<Entry>,
+}
+
+fn hex(s: &str, what: &str) -> Result<u32, Error> {
+    u32::from_str_radix(s, 16).map_err(|_| general_err!("bad {} \"{}\"", what, s))
+}
+
+/// Parses the fields of a rom line (after "rom")
+fn parse_rom(crc: &str, start: &str, len: &str, name: &[&str]) -> Result<Rom, Error> {
+    let rom = Rom {
+        crc: hex(crc, "checksum")?,
+        start: hex(start, "address")? as u16,
+        len: hex(len, "length")? as usize,
+        name: name.join(" "),
+    };
+    if rom.start as usize + rom.len > 0x10000 {
+        return Err(general_err!("{} doesn't fit in memory", rom.name));
+    }
+    Ok(rom)
+}
+
+/// Parses the address of an entry point
+fn parse_location(addr: &str) -> Result<Location, Error> {
+    Ok(match addr.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+        Some(vector) => Location::Vector(hex(vector, "address")? as u16),
+        None => Location::Fixed(hex(addr, "address")? as u16),
+    })
+}
+
+/// Parses an argument: [<label>=]<register or [addr]>[:hex|dec|char|word]
+fn parse_arg(arg: &str) -> Result<Arg, Error> {
+    let (label, rest) = match arg.split_once('=') {
+        Some((label, rest)) => (Some(label.to_string()), rest),
+        None => (None, arg),
+    };
+    let (source, format) = rest.split_once(':').unwrap_or((rest, "hex"));
+    let source = match (source.strip_prefix('[').and_then(|a| a.strip_suffix(']')), Name::from_str(source)) {
+        (Some(addr), _) => Source::Mem(hex(addr, "address")? as u16),
+        (None, Name::Z) => return Err(general_err!("bad argument \"{}\"", arg)),
+        (None, reg) => Source::Reg(reg),
+    };
+    let format = match format {
+        "hex" => Format::Hex,
+        "dec" => Format::Dec,
+        "char" => Format::Char,
+        "word" => Format::Word,
+        _ => return Err(general_err!("bad format \"{}\"", format)),
+    };
+    Ok(Arg { label, source, format })
+}
+
+/// Parses a ROM database
+pub fn parse(text: &str) -> Result<Vec<Group>, Error> {
+    let mut groups: Vec<Group> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let at_line = |e: Error| general_err!("line {}: {}", i + 1, e.msg);
+        match fields.as_slice() {
+            [] => {}
+            ["rom", crc, start, len, name @ ..] => {
+                let rom = parse_rom(crc, start, len, name).map_err(at_line)?;
+                // a rom line after entry points starts a new group
+                if groups.last().is_none_or(|g| !g.entries.is_empty()) {
+                    groups.push(Group::default());
+                }
+                groups.last_mut().unwrap().roms.push(rom);
+            }
+            [name, addr, args @ ..] => {
+                let loc = parse_location(addr).map_err(at_line)?;
+                let args = args.iter().map(|a| parse_arg(a)).collect::<Result<_, _>>().map_err(at_line)?;
+                let group = groups.last_mut().ok_or_else(|| at_line(general_err!("entry point before any rom")))?;
+                group.entries.push(Entry { name: name.to_string(), loc, args });
+            }
+            _ => {
+                let expected = "expected \"rom <crc32> <start> <length> <name>\" or \"<name> <addr> [<args>]\"";
+                return Err(at_line(general_err!("{}", expected)));
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Parses the built-in ROM database along with the one given with --rom-db (if any)
+fn database() -> Result<Vec<Group>, Error> {
+    let mut groups = parse(BUILT_IN)?;
+    if let Some(path) = config::ARGS.rom_db.as_ref() {
+        let text = std::fs::read_to_string(path)?;
+        groups.extend(parse(&text).map_err(|e| general_err!("{}: {}", path.display(), e.msg))?);
+    }
+    Ok(groups)
+}
+
+/// Returns the name of the ROM image with the given CRC32 and length (if it's in the database)
+pub fn identify(crc: u32, len: usize) -> Option<String> {
+    let groups = database().ok()?;
+    let mut roms = groups.into_iter().flat_map(|g| g.roms);
+    roms.find(|rom| rom.crc == crc && rom.len == len).map(|rom| rom.name)
+}
+
+/// Computes the CRC32 (as used by zip and MAME) of data
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// The entry points whose calls are logged (--rom-calls)
+#[derive(Debug, Default)]
+pub struct CallLog {
+    entries: HashMap<u16, Entry>,
+    /// the only routines to log (all of them if empty)
+    only: Vec<String>,
+}
+impl CallLog {
+    pub fn new(only: &str) -> Self {
+        let only = only.split(',').map(|n| n.trim().to_ascii_uppercase()).filter(|n| !n.is_empty()).collect();
+        CallLog { entries: HashMap::new(), only }
+    }
+    /// Creates the log from the --rom-calls option
+    pub fn from_args() -> Option<Self> { config::ARGS.rom_calls.as_deref().map(CallLog::new) }
+    /// Logs calls to the entry point at addr
+    pub fn add(&mut self, addr: u16, entry: &Entry) {
+        if self.only.is_empty() || self.only.iter().any(|n| n.eq_ignore_ascii_case(&entry.name)) {
+            self.entries.insert(addr, entry.clone());
+        }
+    }
+}
+
+/// Shows a value as an argument in the given format
+fn format_value(val: u16, size: u16, format: Format) -> String {
+    match format {
+        Format::Char if (0x20..0x7f).contains(&val) => format!("'{}'", val as u8 as char),
+        Format::Hex | Format::Char if size == 1 => format!("${:02X}", val),
+        Format::Dec => format!("{}", val),
+        _ => format!("${:04X}", val),
+    }
+}
+
+impl Core {
+    /// Describes the call into a ROM routine (if any) made by the instruction that just ran
+    fn describe_rom_call(&self) -> Option<String> {
+        let entry = self.rom_calls.as_ref()?.entries.get(&self.reg.pc)?;
+        let mut text = entry.name.clone();
+        for arg in &entry.args {
+            let (val, size, label) = match arg.source {
+                Source::Reg(reg) => {
+                    (self.reg.get_register(reg).u16(), registers::reg_size(reg), reg.to_str().to_string())
+                }
+                Source::Mem(addr) => {
+                    let byte = |a: u16| self.raw_ram[a as usize] as u16;
+                    match arg.format {
+                        Format::Word => (byte(addr) << 8 | byte(addr.wrapping_add(1)), 2, format!("[{:04X}]", addr)),
+                        _ => (byte(addr), 1, format!("[{:04X}]", addr)),
+                    }
+                }
+            };
+            let val = format_value(val, size, arg.format);
+            match (arg.label.as_ref(), arg.format) {
+                (Some(label), _) => text += &format!(" {}={}", label, val),
+                (None, Format::Char) => text += &format!(" {}", val),
+                (None, _) => text += &format!(" {}={}", label, val),
+            }
+        }
+        Some(text)
+    }
+    /// Logs the instruction at pc if it called (or jumped to) a ROM routine
+    pub fn log_rom_call(&self, pc: u16, mnemonic: &str) {
+        if matches!(mnemonic, "JSR" | "BSR" | "LBSR" | "JMP") {
+            if let Some(text) = self.describe_rom_call() {
+                println!("{:04X}: {}", pc, text);
+            }
+        }
+    }
+    /// Adds the entry points of every recognized ROM to the symbol table (unless --no-auto-sym)
+    /// and to the call log (with --rom-calls). Returns the number of entry points found.
+    pub fn annotate_roms(&mut self) -> Result<usize, Error> {
+        let mut count = 0;
+        for group in database()? {
+            let found = group.roms.iter().find(|rom| {
+                let start = rom.start as usize;
+                crc32(&self.raw_ram[start..start + rom.len]) == rom.crc
+            });
+            let Some(rom) = found else {
+                continue;
+            };
+            for entry in &group.entries {
+                let addr = match entry.loc {
+                    Location::Fixed(addr) => addr,
+                    Location::Vector(v) => {
+                        u16::from_be_bytes([self.raw_ram[v as usize], self.raw_ram[v.wrapping_add(1) as usize]])
+                    }
+                };
+                if !config::ARGS.no_auto_sym {
+                    self.add_symbol(addr, &entry.name);
+                }
+                if let Some(log) = self.rom_calls.as_mut() {
+                    log.add(addr, entry);
+                }
+            }
+            info!("recognized {} ({} entry points)", rom.name, group.entries.len());
+            count += group.entries.len();
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        let groups = parse(BUILT_IN).unwrap();
+        assert!(groups.iter().all(|g| !g.roms.is_empty() && !g.entries.is_empty()));
+        let basic = &groups[0];
+        assert_eq!(basic.roms[2].name, "Color BASIC 1.2");
+        assert_eq!((&basic.entries[1].name, basic.entries[1].loc), (&"CHROUT".to_string(), Location::Vector(0xa002)));
+        let dev = Arg { label: Some("dev".to_string()), source: Source::Mem(0x6f), format: Format::Dec };
+        assert_eq!(basic.entries[1].args[1], dev);
+        let extended = &groups[1];
+        assert_eq!((extended.roms.len(), extended.roms[2].len), (3, 0x8000));
+        assert_eq!(extended.entries[0].loc, Location::Fixed(0x8002));
+        assert_eq!(parse_arg("x").unwrap(), Arg { label: None, source: Source::Reg(Name::X), format: Format::Hex });
+        let text = "rom 1 8000 4000 Mine\nrom 2 8000 4000 Mine 2\nHELLO 8010\n\nrom 3 c000 10 X\nY [c000]";
+        let groups = parse(text).unwrap();
+        assert_eq!((groups.len(), groups[0].roms.len(), groups[1].roms[0].crc), (2, 2, 3));
+        assert_eq!(groups[0].entries[0].loc, Location::Fixed(0x8010));
+        let bad_args = ["rom 1 8000 1 X\nY 8000 Q", "rom 1 8000 1 X\nY 8000 A:oct", "rom 1 8000 1 X\nY 8000 n=[zz]"];
+        for bad in ["HELLO 8010", "rom 1 ffff 2 X", "rom x 8000 1 X", "rom 1 8000 1 X\nY [zz]", "rom 1 8000"] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+        for bad in bad_args {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(format_value(0x41, 1, Format::Char), "'A'");
+        assert_eq!(format_value(0x0d, 1, Format::Char), "$0D");
+        assert_eq!(format_value(0x2a, 1, Format::Dec), "42");
+        assert_eq!(format_value(0x600, 2, Format::Hex), "$0600");
+    }
+}
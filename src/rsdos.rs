@@ -0,0 +1,376 @@
+//! Disk BASIC (RS-DOS) filesystem support.
+//!
+//! This is used to present a host directory to the emulated machine as a standard 35 track
+//! Disk BASIC diskette. The image is synthesized from the directory's files when it is mounted
+//! and, when changes are flushed, the files on the diskette are written back to the directory.
+use super::*;
+use crate::disk::{SECTORS_PER_TRACK, SECTOR_SIZE};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, PathBuf};
+
+pub const TRACKS: usize = 35;
+pub const DIR_TRACK: usize = 17;
+pub const GRANULES: usize = 68;
+const SECTORS_PER_GRANULE: usize = 9;
+const GRANULE_SIZE: usize = SECTORS_PER_GRANULE * SECTOR_SIZE;
+const FAT_LSN: usize = DIR_TRACK * SECTORS_PER_TRACK + 1;
+const DIR_LSN: usize = DIR_TRACK * SECTORS_PER_TRACK + 2;
+const DIR_SECTORS: usize = 9;
+const DIR_ENTRY_SIZE: usize = 32;
+const FREE: u8 = 0xff;
+
+/// A file stored on an RS-DOS diskette
+pub struct DirEntry {
+    pub name: String, // host style name, e.g. "HELLO.BAS"
+    pub file_type: u8,
+    pub ascii: bool,
+    pub data: Vec<u8>,
+}
+
+/// Returns the logical sector number of the first sector in granule g
+fn granule_lsn(g: usize) -> usize {
+    let track = g / 2 + if g >= DIR_TRACK * 2 { 1 } else { 0 };
+    track * SECTORS_PER_TRACK + (g % 2) * SECTORS_PER_GRANULE
+}
+
+/// Converts a host file name to the space-padded 8.3 form used in the directory
+fn guest_name(host: &str) -> Option<([u8; 8], [u8; 3])> {
+    let upper = host.to_ascii_uppercase();
+    let (stem, ext) = upper.rsplit_once('.').unwrap_or((upper.as_str(), ""));
+    if stem.is_empty() || stem.len() > 8 || ext.len() > 3 || !upper.is_ascii() {
+        return None;
+    }
+    let mut name = [b' '; 8];
+    let mut extension = [b' '; 3];
+    name[..stem.len()].copy_from_slice(stem.as_bytes());
+    extension[..ext.len()].copy_from_slice(ext.as_bytes());
+    Some((name, extension))
+}
+
+/// Guesses the Disk BASIC file type (0=BASIC, 1=data, 2=ML, 3=text) and ASCII flag of a host file
+fn guess_type(ext: &[u8; 3], data: &[u8]) -> (u8, bool) {
+    let text = data.iter().all(|&b| b == b'\r' || b == b'\n' || b == b'\t' || (0x20..0x7f).contains(&b));
+    match ext {
+        b"BAS" => (0, text),
+        b"BIN" => (2, false),
+        b"TXT" | b"ASM" => (3, true),
+        _ => (1, text),
+    }
+}
+
+/// Returns a blank, formatted RS-DOS image
+pub fn formatted() -> Vec<u8> {
+    let mut image = vec![0xffu8; TRACKS * SECTORS_PER_TRACK * SECTOR_SIZE];
+    let fat_start = FAT_LSN * SECTOR_SIZE;
+    image[fat_start..fat_start + SECTOR_SIZE].fill(0);
+    image[fat_start..fat_start + GRANULES].fill(FREE);
+    image
+}
+
+/// Checks that a name read from a diskette's directory is a plain file name that can't reach
+/// outside the directory it's written to on the host
+pub fn safe_name(name: &str) -> Result<&str, Error> {
+    let mut components = Path::new(name).components();
+    let plain = matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+    if !plain || name.contains(['/', '\\', ':']) || name.contains("..") {
+        return Err(general_err!("\"{}\" isn't a safe file name", name.escape_default()));
+    }
+    Ok(name)
+}
+
+/// Builds a formatted RS-DOS image containing every regular file in dir that fits. Also returns
+/// the host's name for each file on the image (e.g. "HELLO.BAS" -> "hello.bas") so that changes
+/// can be written back to the file they came from.
+pub fn image_from_dir(dir: &Path) -> Result<(Vec<u8>, HashMap<String, String>), Error> {
+    let mut image = formatted();
+    let mut names = HashMap::new();
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    for path in paths {
+        let file_name = path.file_name().and_then(OsStr::to_str).unwrap_or("");
+        match add_file(&mut image, file_name, &fs::read(&path)?) {
+            Ok(()) => _ = names.insert(file_name.to_ascii_uppercase(), file_name.to_string()),
+            Err(e) => warn!("skipping {}: {}", file_name, e),
+        }
+    }
+    Ok((image, names))
+}
+
+/// Returns the offset of the directory entry for the file called name (e.g. "HELLO.BAS")
+fn find_entry(image: &[u8], name: &str) -> Option<usize> {
+    let (stem, ext) = guest_name(name)?;
+    (0..DIR_SECTORS * SECTOR_SIZE / DIR_ENTRY_SIZE)
+        .map(|entry| DIR_LSN * SECTOR_SIZE + entry * DIR_ENTRY_SIZE)
+        .take_while(|&de| image[de] != 0xff)
+        .find(|&de| image[de] != 0 && image[de..de + 8] == stem && image[de + 8..de + 11] == ext)
+}
+
+/// Removes the file called name from an RS-DOS image and frees its granules. Returns false if
+/// there's no such file.
+pub fn remove_file(image: &mut [u8], name: &str) -> bool {
+    let Some(de) = find_entry(image, name) else { return false };
+    let fat = FAT_LSN * SECTOR_SIZE;
+    let mut g = image[de + 13] as usize;
+    // (bounded, in case the FAT contains a loop)
+    for _ in 0..GRANULES {
+        if g >= GRANULES {
+            break;
+        }
+        let next = image[fat + g];
+        image[fat + g] = FREE;
+        if next & 0xc0 == 0xc0 {
+            break;
+        }
+        g = next as usize;
+    }
+    image[de] = 0;
+    true
+}
+
+/// Adds a file to an RS-DOS image (replacing any file with the same name) in the first free
+/// granules. Its type is guessed from its name and contents (see guess_type).
+pub fn add_file(image: &mut [u8], name: &str, data: &[u8]) -> Result<(), Error> {
+    let (stem, ext) = guest_name(name).ok_or_else(|| general_err!("not a valid Disk BASIC file name"))?;
+    if image.len() < TRACKS * SECTORS_PER_TRACK * SECTOR_SIZE {
+        return Err(general_err!("image is too small to be a Disk BASIC diskette"));
+    }
+    let mut new = image.to_vec();
+    remove_file(&mut new, name);
+    let fat = FAT_LSN * SECTOR_SIZE;
+    let granules = data.len().div_ceil(GRANULE_SIZE).max(1);
+    let free: Vec<usize> = (0..GRANULES).filter(|&g| new[fat + g] == FREE).take(granules).collect();
+    let entry = (0..DIR_SECTORS * SECTOR_SIZE / DIR_ENTRY_SIZE)
+        .map(|entry| DIR_LSN * SECTOR_SIZE + entry * DIR_ENTRY_SIZE)
+        .find(|&de| new[de] == 0 || new[de] == 0xff);
+    let (Some(de), true) = (entry, free.len() == granules) else {
+        return Err(general_err!("diskette is full"));
+    };
+    // copy the data into the granules and chain them together in the FAT
+    for (&g, chunk) in free.iter().zip(data.chunks(GRANULE_SIZE)) {
+        let lsn = granule_lsn(g);
+        new[lsn * SECTOR_SIZE..lsn * SECTOR_SIZE + chunk.len()].copy_from_slice(chunk);
+    }
+    for pair in free.windows(2) {
+        new[fat + pair[0]] = pair[1] as u8;
+    }
+    let last_len = data.len() - (granules - 1) * GRANULE_SIZE;
+    let sectors_in_last = last_len.div_ceil(SECTOR_SIZE).max(1);
+    new[fat + free[granules - 1]] = 0xc0 | sectors_in_last as u8;
+    let bytes_in_last_sector = last_len - (sectors_in_last - 1) * SECTOR_SIZE;
+    // and add the directory entry
+    let (file_type, ascii) = guess_type(&ext, data);
+    new[de..de + DIR_ENTRY_SIZE].fill(0);
+    new[de..de + 8].copy_from_slice(&stem);
+    new[de + 8..de + 11].copy_from_slice(&ext);
+    new[de + 11] = file_type;
+    new[de + 12] = if ascii { 0xff } else { 0 };
+    new[de + 13] = free[0] as u8;
+    new[de + 14..de + 16].copy_from_slice(&(bytes_in_last_sector as u16).to_be_bytes());
+    image.copy_from_slice(&new);
+    Ok(())
+}
+
+/// Returns the number of free granules on an RS-DOS image
+pub fn free_granules(image: &[u8]) -> usize {
+    image[FAT_LSN * SECTOR_SIZE..FAT_LSN * SECTOR_SIZE + GRANULES].iter().filter(|&&g| g == FREE).count()
+}
+
+/// Reads the directory of an RS-DOS image and returns all of the files on it
+pub fn read_dir(image: &[u8]) -> Result<Vec<DirEntry>, Error> {
+    if image.len() < TRACKS * SECTORS_PER_TRACK * SECTOR_SIZE {
+        return Err(general_err!("image is too small to be a Disk BASIC diskette"));
+    }
+    let fat = &image[FAT_LSN * SECTOR_SIZE..FAT_LSN * SECTOR_SIZE + GRANULES];
+    let mut files = Vec::new();
+    for de in image[DIR_LSN * SECTOR_SIZE..(DIR_LSN + DIR_SECTORS) * SECTOR_SIZE].chunks(DIR_ENTRY_SIZE) {
+        match de[0] {
+            0 => continue, // deleted
+            0xff => break, // end of directory
+            _ => {}
+        }
+        let stem = String::from_utf8_lossy(&de[..8]).trim_end().to_string();
+        let ext = String::from_utf8_lossy(&de[8..11]).trim_end().to_string();
+        let name = if ext.is_empty() { stem } else { format!("{}.{}", stem, ext) };
+        let bytes_in_last_sector = u16::from_be_bytes([de[14], de[15]]) as usize;
+        let mut data = Vec::new();
+        let mut g = de[13] as usize;
+        // follow the granule chain (bounded, in case the FAT contains a loop)
+        for _ in 0..GRANULES {
+            if g >= GRANULES {
+                return Err(general_err!("{}: bad granule {} in FAT", name, g));
+            }
+            let start = granule_lsn(g) * SECTOR_SIZE;
+            let next = fat[g];
+            if next & 0xc0 == 0xc0 {
+                let sectors = ((next & 0x3f) as usize).clamp(1, SECTORS_PER_GRANULE);
+                let len = (sectors - 1) * SECTOR_SIZE + bytes_in_last_sector.min(SECTOR_SIZE);
+                data.extend_from_slice(&image[start..start + len]);
+                break;
+            }
+            data.extend_from_slice(&image[start..start + GRANULE_SIZE]);
+            g = next as usize;
+        }
+        files.push(DirEntry {
+            name,
+            file_type: de[11],
+            ascii: de[12] != 0,
+            data,
+        });
+    }
+    Ok(files)
+}
+
+/// Writes every file on the image into dir, each to the host file it came from (see
+/// image_from_dir) or, for a new file, to its name on the diskette. Existing host files that would
+/// change are first copied to <name>.bak. Files deleted on the diskette are left alone on the host
+/// and files with names that aren't safe (see safe_name) are skipped.
+pub fn write_to_dir(image: &[u8], dir: &Path, names: &HashMap<String, String>) -> Result<usize, Error> {
+    let mut count = 0;
+    for file in read_dir(image)? {
+        let name = names.get(&file.name).map_or(safe_name(&file.name), |host| Ok(host));
+        let path = match name {
+            Ok(name) => dir.join(name),
+            Err(e) => {
+                warn!("not writing {} to {}: {}", file.name.escape_default(), dir.display(), e.msg);
+                continue;
+            }
+        };
+        match fs::read(&path) {
+            Ok(old) if old == file.data => continue,
+            Ok(_) => {
+                let mut bak = path.clone().into_os_string();
+                bak.push(".bak");
+                fs::copy(&path, bak)?;
+            }
+            Err(_) => {}
+        }
+        fs::write(&path, &file.data)?;
+        verbose_println!(
+            "wrote {} (type {}{}, {} bytes)",
+            path.display(),
+            file.file_type,
+            if file.ascii { " ascii" } else { "" },
+            file.data.len()
+        );
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Returns everything on a mounted image (with any unflushed changes)
+fn contents(disk: &disk::DiskImage) -> Result<Vec<u8>, Error> {
+    let mut image = Vec::with_capacity(disk.sector_count() * SECTOR_SIZE);
+    for lsn in 0..disk.sector_count() {
+        image.extend_from_slice(disk.read_sector(lsn)?);
+    }
+    Ok(image)
+}
+
+/// Lists the files on an RS-DOS image (coco dsk ls)
+pub fn ls(path: &Path) -> Result<(), Error> {
+    let image = contents(&disk::DiskImage::open(path, true)?)?;
+    for file in read_dir(&image)? {
+        let kind = ["BASIC", "data", "ML", "text"].get(file.file_type as usize).copied().unwrap_or("?");
+        let format = if file.ascii { "ascii" } else { "binary" };
+        println!("{:<12} {:<5} {:<6} {:>6} bytes", file.name, kind, format, file.data.len());
+    }
+    let free = free_granules(&image);
+    println!("{} granules ({} bytes) free", free, free * GRANULE_SIZE);
+    Ok(())
+}
+
+/// Copies the file called name from an RS-DOS image to output (or to name, if it's safe; see
+/// safe_name) (coco dsk get)
+pub fn get(path: &Path, name: &str, output: Option<&Path>) -> Result<(), Error> {
+    let image = contents(&disk::DiskImage::open(path, true)?)?;
+    let file = read_dir(&image)?
+        .into_iter()
+        .find(|f| f.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| general_err!("there's no {} on {}", name, path.display()))?;
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => {
+            let name = safe_name(&file.name).map_err(|e| general_err!("{}; give a file to write it to", e.msg))?;
+            PathBuf::from(name)
+        }
+    };
+    fs::write(&output, &file.data)?;
+    println!("Wrote {} ({} bytes) to {}", file.name, file.data.len(), output.display());
+    Ok(())
+}
+
+/// Copies a host file onto an RS-DOS image as name (or with its own name), replacing any file
+/// that's already there (coco dsk put). The image is first copied to <image>.bak.
+pub fn put(path: &Path, file: &Path, name: Option<&str>) -> Result<(), Error> {
+    let name = name.or_else(|| file.file_name().and_then(OsStr::to_str)).unwrap_or_default().to_ascii_uppercase();
+    let data = fs::read(file)?;
+    let mut disk = disk::DiskImage::open(path, false)?;
+    let old = contents(&disk)?;
+    read_dir(&old)?;
+    let mut image = old.clone();
+    add_file(&mut image, &name, &data).map_err(|e| general_err!("can't put {} on {}: {}", name, path.display(), e))?;
+    for (lsn, (new, old)) in image.chunks(SECTOR_SIZE).zip(old.chunks(SECTOR_SIZE)).enumerate() {
+        if new != old {
+            disk.write_sector(lsn, new)?;
+        }
+    }
+    disk.flush()?;
+    println!("Put {} ({} bytes) on {}", name, data.len(), path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_host_dir() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("coco_rsdos_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let big: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        fs::write(dir.join("hello.bas"), b"10 PRINT \"HELLO\"\r")?;
+        fs::write(dir.join("big.bin"), &big)?;
+        let (image, names) = image_from_dir(&dir)?;
+        let files = read_dir(&image)?;
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "BIG.BIN");
+        assert_eq!(files[0].data, big);
+        assert_eq!(files[1].name, "HELLO.BAS");
+        assert!(files[1].ascii);
+        // replace a file and add one in the granules it freed
+        let mut image = image;
+        add_file(&mut image, "BIG.BIN", b"small")?;
+        add_file(&mut image, "new.txt", &big[..2400])?;
+        let files = read_dir(&image)?;
+        assert_eq!(files.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["BIG.BIN", "HELLO.BAS", "NEW.TXT"]);
+        assert_eq!((files[0].data.as_slice(), files[2].data.as_slice()), (&b"small"[..], &big[..2400]));
+        assert_eq!(image[FAT_LSN * SECTOR_SIZE..FAT_LSN * SECTOR_SIZE + 4], [0xc1, 2, 0xc1, 0xc1]);
+        assert!(remove_file(&mut image, "hello.bas") && !remove_file(&mut image, "HELLO.BAS"));
+        assert_eq!(free_granules(&image), GRANULES - 3);
+        assert!(add_file(&mut image, "HUGE.BIN", &vec![0; GRANULES * GRANULE_SIZE]).is_err());
+        // changes go back to the host's file (not a new upper case one) and new files get their own
+        // (and a name that climbs out of the directory is never written)
+        add_file(&mut image, "HELLO.BAS", b"20 END\r")?;
+        add_file(&mut image, "EVIL.BIN", b"x")?;
+        let de = find_entry(&image, "EVIL.BIN").unwrap();
+        image[de..de + 8].copy_from_slice(b"../EVIL ");
+        assert_eq!(write_to_dir(&image, &dir, &names)?, 3);
+        let mut listing: Vec<_> = fs::read_dir(&dir)?.map(|e| e.unwrap().file_name()).collect();
+        listing.sort();
+        assert_eq!(listing, ["NEW.TXT", "big.bin", "big.bin.bak", "hello.bas", "hello.bas.bak"]);
+        assert!(["../EVIL.BIN", "/ETC", "A\\B", "C:X", ".."].iter().all(|name| safe_name(name).is_err()));
+        // coco dsk get needs to be told where to put it too
+        let dsk = dir.join("evil.dsk");
+        fs::write(&dsk, &image)?;
+        assert!(get(&dsk, "../evil.bin", None).is_err());
+        get(&dsk, "../evil.bin", Some(&dir.join("evil.bin")))?;
+        assert_eq!(fs::read(dir.join("evil.bin"))?, b"x");
+        assert_eq!(fs::read(dir.join("hello.bas"))?, b"20 END\r");
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use std::sync::atomic::Ordering::Relaxed;
 use std::time::Duration;
 
 /// Implements the runtime engine of the simulator.
@@ -9,8 +10,8 @@ use crate::{
 use super::*;
 use memory::AccessType;
 
-pub const HSYNC_PERIOD: Duration = Duration::from_nanos(63_500);
-pub const VSYNC_PERIOD: Duration = Duration::from_micros(16_667);
+/// Color BASIC does a warm start on reset if this byte is 0x55 (and RSTVEC points to a NOP)
+const BASIC_RSTFLG: usize = 0x71;
 
 impl Core {
     /// Resets the 6809 by clearing the registers and
@@ -18,6 +19,9 @@ impl Core {
     /// (or using the override value if one has been set)
     pub fn reset(&mut self) -> Result<(), Error> {
         self.reg.reset();
+        for cart in self.cartridges_mut() {
+            cart.reset();
+        }
         if let Some(addr) = self.reset_vector {
             self.force_reset_vector(addr)?
         }
@@ -26,8 +30,39 @@ impl Core {
         self.reg.pc = self._read_u16(memory::AccessType::System, 0xfffe, None)?;
         self.program_start = self.reg.pc;
         self.faulted = false;
+        if let Some(hang) = self.hang.as_mut() {
+            hang.reset(self.clock_cycles);
+        }
         Ok(())
     }
+    /// Simulates a power cycle: RAM is refilled with the power-on pattern (see rng.rs) and
+    /// whatever was loaded into RAM is put back before the CPU is reset. BASIC's warm start
+    /// flag is cleared so that BASIC does a cold start.
+    pub fn cold_reset(&mut self) -> Result<(), Error> {
+        let top = self.ram_top as usize;
+        self.rng.fill(&mut self.raw_ram[..=top], config::ARGS.ram_pattern);
+        self.load_map.restore(self.raw_ram);
+        self.raw_ram[BASIC_RSTFLG] = 0;
+        self.cart_pending = self.cart_inserted && self.cart.as_ref().is_some_and(|c| c.autostart());
+        if let Some(host) = self.host.as_mut() {
+            host.close_all();
+        }
+        self.reset()
+    }
+    /// Simulates the reset button. Memory is left alone so BASIC does a warm start (if its
+    /// warm start flag is set) and keeps any program in memory.
+    pub fn warm_reset(&mut self) -> Result<(), Error> {
+        verbose_println!("warm start flag is {:02X}", self.raw_ram[BASIC_RSTFLG]);
+        self.reset()
+    }
+    /// Turns warp mode (see warp.rs) on or off
+    pub fn set_warp(&mut self, on: bool) {
+        self.warp = on;
+        if let Some(pacer) = self.pacer.as_mut() {
+            pacer.resync();
+        }
+        info!("{}", if on { tr!("warp mode on") } else { tr!("warp mode off") });
+    }
     pub fn force_reset_vector(&mut self, addr: u16) -> Result<(), Error> {
         self._write_u8u16(memory::AccessType::System, 0xfffe, u8u16::u16(addr))
     }
@@ -62,6 +97,7 @@ impl Core {
         perf_row!("eval", self.eval_time);
         perf_row!("commit", self.commit_time);
         perf_row!("total", total_time);
+        self.lock_stats.report();
     }
     /// Starts executing instructions at the current program counter.  
     /// Does not set or read any registers before attempting to execute.  
@@ -72,6 +108,12 @@ impl Core {
         loop {
             let temp_pc = self.reg.pc;
             if let Err(e) = self.exec_one() {
+                if config::kiosk() {
+                    // in kiosk mode the show must go on
+                    warn!("{}; restarting", e);
+                    self.cold_reset()?;
+                    continue;
+                }
                 if e.kind == ErrorKind::Exit {
                     // this is a normal exit
                     break;
@@ -90,6 +132,14 @@ impl Core {
                     break;
                 }
             }
+            if self.quit.as_ref().is_some_and(|quit| quit.load(Relaxed)) {
+                info!("Terminating because the window was closed.");
+                break;
+            }
+            if let Some(condition) = self.until_check() {
+                info!("Terminating because {} (after {} cycles).", condition, self.clock_cycles);
+                break;
+            }
         }
         if config::ARGS.perf {
             self.report_perf()
@@ -98,27 +148,56 @@ impl Core {
     }
     /// Helper function for exec.  
     /// Wraps calls to exec_next and adds debug checks and interrupt processing.
-    fn exec_one(&mut self) -> Result<(), Error> {
-        let function_start = Instant::now();
+    pub fn exec_one(&mut self) -> Result<(), Error> {
         let mut meta_start: Option<Instant> = None;
-        let mut expected_duration: Option<Duration> = None;
         if config::debug() && self.pre_instruction_debug_check(self.reg.pc) {
-            self.debug_cli()?;
+            // the watchdog doesn't expect progress while the debugger waits for commands
+            self.heartbeat.as_ref().inspect(|h| h.set_waiting(true));
+            self.title_stop(false);
+            let result = self.debug_cli();
+            self.title_resume();
+            self.heartbeat.as_ref().inspect(|h| h.set_waiting(false));
+            result?;
         }
         let temp_pc = self.reg.pc;
         if !self.in_cwai && !self.in_sync {
+            let start_cycle = self.clock_cycles;
+            let before = self.reg;
             let outcome = self.exec_next(self.list_mode.is_none())?;
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.hit(temp_pc);
+            }
+            if let Some(trace) = self.trace_file.as_mut() {
+                trace.instruction(start_cycle, self.clock_cycles, temp_pc, outcome.inst.flavor.desc.name)?;
+            }
+            if self.trace_compare.is_some() {
+                self.compare_trace(temp_pc, &before)?;
+            }
+            if self.rom_calls.is_some() {
+                self.log_rom_call(temp_pc, outcome.inst.flavor.desc.name);
+            }
+            if self.hang.as_mut().is_some_and(|h| h.instruction(temp_pc, self.reg.s, self.clock_cycles)) {
+                self.report_hang(temp_pc)?;
+            }
             meta_start = Some(Instant::now());
-            // if paying attention to timing then track how long this instruction should have taken
-            expected_duration = self
-                .min_cycle
-                .and_then(|min| min.checked_mul(outcome.inst.flavor.detail.clk as u32));
             // check for meta instructions (interrupts, SYNC, CWAI, EXIT)
             if let Some(meta) = outcome.meta.as_ref() {
                 let it = meta.to_interrupt_type();
+                // waiting for an interrupt is never a busy-wait
+                if let (Some(hang), instructions::Meta::CWAI | instructions::Meta::SYNC) = (self.hang.as_mut(), meta) {
+                    hang.progress(self.clock_cycles);
+                }
                 match meta {
                     instructions::Meta::EXIT => {
-                        info!("EXIT instruction at PC={:0x}", self.reg.pc);
+                        // EXIT #<status> sets the status coco exits with (plain EXIT is a success)
+                        let status = match outcome.inst.flavor.mode {
+                            instructions::AddressingMode::Immediate => {
+                                self._read_u8(memory::AccessType::Generic, outcome.inst.ea, None)?
+                            }
+                            _ => 0,
+                        };
+                        info!("EXIT instruction at PC={:0x} (status {})", self.reg.pc, status);
+                        self.exit_status = Some(status);
                         return Err(Error::new(
                             ErrorKind::Exit,
                             None,
@@ -134,6 +213,7 @@ impl Core {
                         self.in_sync = true;
                         verbose_println!("SYNC at PC={:0x}: waiting for interrupt...", self.reg.pc);
                     }
+                    _ if self.host.as_ref().is_some_and(|h| h.traps(meta)) => self.host_call()?,
                     _ if it.is_some() => {
                         self.start_interrupt(it.unwrap())?;
                     }
@@ -145,7 +225,13 @@ impl Core {
             if config::help_humans() {
                 self.post_instruction_debug_check(temp_pc, &outcome);
             }
+            if self.spies.is_some() {
+                self.spy_shots();
+            }
         }
+        // a cartridge (the disk controller) can halt the CPU and end a command with an NMI
+        self.cartridge_step();
+        self.take_nmi()?;
         if meta_start.is_none() {
             meta_start = Some(Instant::now());
         }
@@ -154,28 +240,83 @@ impl Core {
         // check for work that needs to be done on hsync
         // (using hsync as the period at which to poll for pending interrupts
         // rather than checking between every instruction)
-        if self.hsync_prev.elapsed() >= HSYNC_PERIOD {
-            self.hsync_prev = Instant::now();
+        // (hsync and vsync are timed by the emulated clock; see warp.rs)
+        if self.in_cwai || self.in_sync {
+            // nothing happens while waiting for an interrupt so skip ahead to the next hsync
+            self.clock_cycles = self.clock_cycles.max(self.virtual_sync.next_hsync());
+        }
+        // the cycle at which interrupts sampled at this hsync were asserted
+        let hsync_at = self.virtual_sync.next_hsync();
+        let (hsync, vsync) = self.virtual_sync.poll(self.clock_cycles, &mut self.rng);
+        if hsync {
+            // the host's clock only holds the CPU back to its speed (unless we're warping)
+            if let Some(pacer) = self.pacer.as_mut().filter(|p| !self.warp && (p.lines || vsync)) {
+                pacer.pace(self.clock_cycles);
+            }
+            if let Some(heartbeat) = self.heartbeat.as_ref() {
+                heartbeat.beat_at(self.reg.pc);
+            }
             // check for hardware firq
+            // (the ACIA sits in the cartridge slot like an RS-232 pak so its interrupt pulls the CART line)
+            if self.acia.as_ref().is_some_and(|a| a.irq(self.clock_cycles)) {
+                self.cart_pending = true;
+            }
+            // the cartridges can pull either line
+            let cart_irq = self.cartridge_tick();
             {
-                let mut pia1 = self.pia1.lock().unwrap();
+                let mut pia1 = self.lock_pia1();
                 if self.cart_pending {
                     firq = pia1.cart_firq();
                 }
             }
             // check for hardware irq
             {
-                let mut pia0 = self.pia0.lock().unwrap();
-                irq = pia0.hsync_irq();
+                let mut pia0 = self.lock_pia0();
+                irq = pia0.hsync_irq() || cart_irq;
             }
             // if it's vsync time, then also check for vsync irq
-            if self.vsync_prev.elapsed() >= VSYNC_PERIOD {
-                self.vsync_prev = Instant::now();
-                {
-                    let mut pia0 = self.pia0.lock().unwrap();
+            if vsync {
+                let hotkey = {
+                    let mut pia0 = self.lock_pia0();
                     irq = irq || pia0.vsync_irq();
+                    let idle = config::ARGS.kiosk_idle;
+                    if config::kiosk() && idle > 0 && pia0.idle_time() >= Duration::from_secs(idle) {
+                        pia0.reset_idle_time();
+                        info!("{}", tr!("no input for {} seconds; restarting", idle));
+                        Some(pia::Hotkey::ColdReset)
+                    } else {
+                        pia0.take_hotkey()
+                    }
+                };
+                self.basic_vsync()?;
+                self.autotype_vsync()?;
+                self.title_vsync();
+                self.until_vsync();
+                self.apply_cheats()?;
+                self.rewind_snapshot();
+                match hotkey {
+                    Some(pia::Hotkey::WarmReset) => {
+                        info!("{}", tr!("reset (warm start)"));
+                        return self.warm_reset();
+                    }
+                    Some(pia::Hotkey::ColdReset) => {
+                        info!("{}", tr!("power cycle (cold start)"));
+                        return self.cold_reset();
+                    }
+                    Some(pia::Hotkey::ToggleCart | pia::Hotkey::ToggleWarp | pia::Hotkey::Rewind)
+                        if config::kiosk() => {}
+                    Some(pia::Hotkey::ToggleWarp) => self.set_warp(!self.warp),
+                    Some(pia::Hotkey::ToggleCart) if self.cart_inserted => self.pull_cart(),
+                    Some(pia::Hotkey::ToggleCart) => _ = self.insert_cart()?,
+                    Some(pia::Hotkey::Rewind) if self.rewind()? => return Ok(()),
+                    Some(pia::Hotkey::Rewind) | None => {}
                 }
             }
+            if let Some(trace) = self.trace_file.as_mut() {
+                trace.sync(self.clock_cycles, vsync, irq, firq)?;
+            }
+            self.latency.sample(latency::Line::Irq, irq, hsync_at);
+            self.latency.sample(latency::Line::Firq, firq, hsync_at);
             if irq {
                 // hardware issued an hsync irq
                 // sync completes whether or not we service the interrupt
@@ -183,6 +324,7 @@ impl Core {
                 // if irq is not masked then service it
                 if !self.reg.cc.is_set(registers::CCBit::I) {
                     self.start_interrupt(InterruptType::Irq)?;
+                    self.latency.taken(latency::Line::Irq, self.clock_cycles);
                 }
             }
             if firq {
@@ -192,19 +334,33 @@ impl Core {
                 // if FIRQ is not masked then service it
                 if !self.reg.cc.is_set(registers::CCBit::F) {
                     self.start_interrupt(InterruptType::Firq)?;
+                    self.latency.taken(latency::Line::Firq, self.clock_cycles);
                     self.cart_pending = false;
                 }
             }
         }
-        // finally, if we're limiting CPU speed, then check to make sure we didn't execute this instruction too quickly
-        if let Some(remaining_time) = expected_duration.and_then(|m| m.checked_sub(function_start.elapsed())) {
-            let time = Instant::now();
-            while Instant::now() - time < remaining_time { /* spin because other sleep options are inconsistent */ }
+        // make sure the stack pointer hasn't strayed out of bounds
+        if let Some(guard) = self.stack_guard.as_mut() {
+            guard.check(&self.reg, self.ram_top)?;
         }
         self.meta_time += meta_start.unwrap().elapsed();
         Ok(())
     }
 
+    /// Asserts the NMI line. The interrupt is taken at the next instruction boundary (the 6809's
+    /// NMI is edge-triggered, so asserting it again before then has no further effect).
+    pub fn assert_nmi(&mut self) { self.nmi_pending = true }
+    /// Takes the NMI if one is pending and returns true if it did
+    pub fn take_nmi(&mut self) -> Result<bool, Error> {
+        if !std::mem::take(&mut self.nmi_pending) {
+            return Ok(false);
+        }
+        // an NMI ends SYNC and CWAI like any other interrupt
+        self.in_sync = false;
+        self.start_interrupt(InterruptType::Nmi)?;
+        Ok(true)
+    }
+
     // helper function for interrupt handling
     // simply pushes the named register on the system stack
     pub fn system_psh(&mut self, reg: registers::Name) -> Result<(), Error> {
@@ -239,6 +395,10 @@ impl Core {
     /// then sets PC to the vector for the given interrupt.
     pub fn start_interrupt(&mut self, it: core::InterruptType) -> Result<(), Error> {
         assert!(!self.in_sync);
+        if let Some(hang) = self.hang.as_mut() {
+            // CWAI has already stacked the entire state
+            hang.interrupt(if self.in_cwai { self.reg.s.wrapping_add(12) } else { self.reg.s });
+        }
         // info!("start_interrupt {:?}, vector {:04x}", it, it.vector());
         // if this is an IRQ then we need to push (almost) everything on the stack
         let mut entire = false;
@@ -270,10 +430,11 @@ impl Core {
         self.reg.cc.or_with_byte(if_mask_flags);
         // get the vector for the ISR
         let addr = self._read_u16(AccessType::System, it.vector(), None)?;
-        // check to see if the vector points to a zero byte; if so then panic
+        // check to see if the vector points to a zero byte; if so then the vector was never set
         let b = self._read_u8(AccessType::System, addr, None)?;
         if b == 0 {
-            panic!("interrupt {:?} vector points to zero instruction", it)
+            let reg = Some(self.reg);
+            return Err(runtime_err!(reg, "interrupt {:?} vector points to zero instruction at {:04X}", it, addr));
         }
         // set the program counter
         self.reg.set_register(registers::Name::PC, u8u16::u16(addr));
@@ -294,7 +455,7 @@ impl Core {
 
         // get the base op code
         loop {
-            inst.buf[inst.size as usize] = self._read_u8(AccessType::Program, live_ctx.pc + inst.size, None)?;
+            inst.buf[inst.size as usize] = self._read_u8(AccessType::Program, live_ctx.pc.wrapping_add(inst.size), None)?;
             op16 |= inst.buf[inst.size as usize] as u16;
             inst.size += 1;
             if inst.size == 1 && instructions::is_high_byte_of_16bit_instruction(inst.buf[0]) {
@@ -322,7 +483,8 @@ impl Core {
         assert!(inst.size >= inst.flavor.detail.sz);
         // adjust the program counter before evaluating instructions
         live_ctx.pc = self.checked_pc_add(live_ctx.pc, inst.size, &inst)?;
-        let mut o = instructions::Outcome::new(inst, live_ctx);
+        // reuse the write list from the previous instruction to avoid an allocation
+        let mut o = instructions::Outcome::new(inst, live_ctx, std::mem::take(&mut self.write_pool));
         // track how long all this preparation took
         self.prep_time += start.elapsed();
         start = Instant::now();
@@ -338,11 +500,24 @@ impl Core {
         if commit && self.list_mode.is_none() {
             self.reg = o.new_ctx;
             // and complete any writes to the address space
-            if let Some(v) = o.writes.as_ref() {
-                for w in v {
+            // plain RAM writes go straight to memory; anything else goes through the device dispatch
+            let fast = !config::debug() && !self.reference;
+            if let Some(hang) = self.hang.as_mut() {
+                hang.writes(&o.writes, self.reg.s, self.clock_cycles);
+            }
+            for w in &o.writes {
+                let addr = w.addr as usize;
+                if fast && addr + (w.val.size() as usize) <= self.fast_write_limit {
+                    match w.val.msb() {
+                        Some(msb) => self.raw_ram[addr..addr + 2].copy_from_slice(&[msb, w.val.lsb()]),
+                        None => self.raw_ram[addr] = w.val.lsb(),
+                    }
+                } else {
                     self._write_u8u16(w.at, w.addr, w.val)?;
                 }
             }
+            o.writes.clear();
+            self.write_pool = std::mem::take(&mut o.writes);
         }
         self.commit_time += start.elapsed();
 
@@ -546,7 +721,7 @@ impl Core {
                     // 0b10000111 => {} invalid
                     0b10001000 => {
                         // EA = ,R + 8 bit offset
-                        let offset = self._read_u8(AccessType::Program, live_ctx.pc + inst.size, None)? as i8;
+                        let offset = self._read_u8(AccessType::Program, live_ctx.pc.wrapping_add(inst.size), None)? as i8;
                         inst.size += 1;
                         let (addr, _) = u16::overflowing_add(*ir_ptr, offset as u16);
                         inst.ea = addr;
@@ -556,7 +731,7 @@ impl Core {
                     }
                     0b10001001 => {
                         // ,R + 16 bit offset
-                        let offset = self._read_u16(AccessType::Program, live_ctx.pc + inst.size, None)? as i16;
+                        let offset = self._read_u16(AccessType::Program, live_ctx.pc.wrapping_add(inst.size), None)? as i16;
                         inst.size += 2;
                         let (addr, _) = u16::overflowing_add(*ir_ptr, offset as u16);
                         inst.ea = addr;
@@ -575,7 +750,7 @@ impl Core {
                     }
                     0b10001100 => {
                         // ,PC + 8 bit offset
-                        let offset = self._read_u8(AccessType::Program, live_ctx.pc + inst.size, None)? as i8;
+                        let offset = self._read_u8(AccessType::Program, live_ctx.pc.wrapping_add(inst.size), None)? as i8;
                         inst.size += 1;
                         // Note: effective address is relative to the program counter's NEW value (the address of the next instruction)
                         let (pc, _) = u16::overflowing_add(live_ctx.pc, inst.size);
@@ -587,7 +762,7 @@ impl Core {
                     }
                     0b10001101 => {
                         // ,PC + 16 bit offset
-                        let offset = self._read_u16(AccessType::Program, live_ctx.pc + inst.size, None)? as i16;
+                        let offset = self._read_u16(AccessType::Program, live_ctx.pc.wrapping_add(inst.size), None)? as i16;
                         inst.size += 2;
                         // Note: effective address is relative to the program counter's NEW value (the address of the next instruction)
                         let (pc, _) = u16::overflowing_add(live_ctx.pc, inst.size);
@@ -599,7 +774,7 @@ impl Core {
                     }
                     0b10001111 => {
                         // EA = [,address]
-                        inst.ea = self._read_u16(AccessType::Program, live_ctx.pc + inst.size, None)?;
+                        inst.ea = self._read_u16(AccessType::Program, live_ctx.pc.wrapping_add(inst.size), None)?;
                         if config::help_humans() {
                             inst.operand = Some(format!("[{:04X}]", inst.ea));
                         }
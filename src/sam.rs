@@ -9,23 +9,38 @@
 /// 13-14 | Memory Size
 /// 15    | Map Type (ROM+RAM or RAM-only; coco uses ROM+RAM)
 ///
+use crate::error::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU16, Ordering::Relaxed};
+use std::sync::Arc;
+
 #[derive(Debug)]
 pub struct Sam {
     config: u16,
+    shared: Arc<AtomicU16>, // published copy of config for reading without locking the SAM
 }
 
 impl Sam {
     #[allow(clippy::new_without_default)]
-    pub fn new() -> Self { Sam { config: 0 } }
+    pub fn new() -> Self { Sam { config: 0, shared: Arc::new(AtomicU16::new(0)) } }
     pub fn get_raw_config(&self) -> u16 { self.config }
-    pub fn get_vdg_bits(&self) -> u8 { VDG_MODE.from_config(self.config) as u8 }
-    pub fn get_vram_start(&self) -> u16 { 512 * VRAM_START.from_config(self.config) }
+    /// Puts back a config returned by get_raw_config (e.g., when rewinding)
+    pub fn set_raw_config(&mut self, config: u16) {
+        self.config = config;
+        self.shared.store(config, Relaxed);
+    }
+    /// Returns the config as last written, for reading without locking the SAM (see vdg_bits and vram_start)
+    pub fn shared_config(&self) -> Arc<AtomicU16> { self.shared.clone() }
+    pub fn vdg_bits(config: u16) -> u8 { VDG_MODE.from_config(config) as u8 }
+    pub fn vram_start(config: u16) -> u16 { 512 * VRAM_START.from_config(config) }
+    pub fn get_vdg_bits(&self) -> u8 { Sam::vdg_bits(self.config) }
+    pub fn get_vram_start(&self) -> u16 { Sam::vram_start(self.config) }
     pub fn get_page_switch(&self) -> bool { (PAGE_SWITCH.from_config(self.config)) != 0 }
     pub fn get_mpu_rate(&self) -> u8 { MPU_RATE.from_config(self.config)as u8 }
     pub fn get_map_type(&self) -> bool { MAP_TYPE.from_config(self.config) != 0 }
-    pub fn write(&mut self, index: usize) {
+    /// Sets (odd index) or clears (even index) one of the SAM's bits; index is the offset from $FFC0
+    pub fn write(&mut self, index: usize) -> Result<(), Error> {
         if index >= 32 {
-            panic!()
+            return Err(Error::new(ErrorKind::Device, None, &format!("the SAM has no register at offset {}", index)));
         }
         let mut val = 1u16 << (index / 2);
         if index & 1 == 0 {
@@ -33,7 +48,9 @@ impl Sam {
         } else {
             self.config |= val;
         }
+        self.shared.store(self.config, Relaxed);
         verbose_println!("SAM config={:016b}",self.config);
+        Ok(())
     }
 }
 
@@ -0,0 +1,76 @@
+//! Reading the text screen.
+//!
+//! These read the 32x16 text screen at the address the SAM is displaying (see vdg::text_grid
+//! for how bytes become characters), so scripts, tests and programs built on the library can
+//! check what's shown without looking at pixels. wait_for_text runs the machine until some text
+//! appears or a timeout (in emulated time) passes. It runs in warp mode so the wait is
+//! deterministic: the same program always stops after the same number of cycles.
+use super::*;
+use crate::core::CPU_HZ;
+use std::time::Duration;
+use vdg::{BLOCK_COLS, BLOCK_ROWS};
+
+const CYCLES_PER_FRAME: u64 = CPU_HZ / 60;
+
+impl Core {
+    /// Returns the characters on the text screen
+    pub fn screen_text(&self) -> [[char; BLOCK_COLS]; BLOCK_ROWS] {
+        let start = self.lock_sam().get_vram_start() as usize;
+        vdg::text_grid(&self.raw_ram[start..])
+    }
+    /// Returns the lines of the text screen (without trailing spaces)
+    pub fn screen_lines(&self) -> Vec<String> {
+        self.screen_text().iter().map(|row| row.iter().collect::<String>().trim_end().to_string()).collect()
+    }
+    /// Returns true if a line of the text screen contains text
+    pub fn screen_shows(&self, text: &str) -> bool { self.screen_lines().iter().any(|line| line.contains(text)) }
+    /// Runs until a line of the text screen contains text (checking once a frame) and returns
+    /// true, or returns false once timeout has passed in emulated time
+    pub fn wait_for_text(&mut self, text: &str, timeout: Duration) -> Result<bool, Error> {
+        let limit = self.clock_cycles + (timeout.as_secs_f64() * CPU_HZ as f64) as u64;
+        let warp = self.warp;
+        self.warp = true;
+        let result = loop {
+            if self.screen_shows(text) {
+                break Ok(true);
+            }
+            if self.clock_cycles >= limit {
+                break Ok(false);
+            }
+            let frame_end = self.clock_cycles + CYCLES_PER_FRAME;
+            while self.clock_cycles < frame_end {
+                if let Err(e) = self.exec_one() {
+                    self.warp = warp;
+                    return Err(e);
+                }
+            }
+        };
+        self.warp = warp;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen_text() {
+        let mut core = Core::headless().unwrap();
+        // the screen is at $400 and cleared, as Color BASIC sets it up
+        core.sam.lock().unwrap().set_raw_config(2 << 3);
+        core.raw_ram[0x400..0x600].fill(0x60);
+        core.raw_ram[0x400..0x408].copy_from_slice(b"HI\x60\x08\x09\x20\x8f\x80");
+        assert_eq!(core.screen_lines()[0], "HI hi █");
+        assert!(core.screen_shows("HI hi") && !core.screen_shows("HO"));
+        // $3000: sta $400,x / leax 1,x / bra $3000 fills the screen from the second line with A
+        core.raw_ram[0x3000..0x3006].copy_from_slice(&[0xa7, 0x89, 0x04, 0x00, 0x30, 0x01]);
+        core.raw_ram[0x3006..0x3008].copy_from_slice(&[0x20, 0xf8]);
+        (core.reg.a, core.reg.pc, core.reg.x) = (b'!', 0x3000, 0x20);
+        assert!(core.wait_for_text("!!!!", Duration::from_secs(1)).unwrap());
+        assert_eq!(core.screen_lines()[1], "!".repeat(32));
+        let start = core.clock_cycles;
+        assert!(!core.wait_for_text("?", Duration::from_millis(50)).unwrap());
+        assert!(core.clock_cycles - start >= CPU_HZ / 20);
+    }
+}
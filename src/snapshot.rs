@@ -0,0 +1,350 @@
+//! Machine state files (snapshots).
+//!
+//! `state save <file>` (or `save <file>`) in the debugger writes the whole machine to a file and
+//! `state load <file>` (or `restore <file>`, or --load-state <file> at startup) puts it back;
+//! --save-state <file> saves it when coco exits. XRoar's snapshots load too (see import.rs). A
+//! snapshot file is "COCOSNAP" followed by one chunk for each part of the machine:
+//! ```text
+//! tag      4 bytes, e.g. "PIA0"
+//! version  2 bytes (big-endian)
+//! length   4 bytes (big-endian): the number of bytes of data that follow
+//! data
+//! ```
+//! Each chunk stands alone, so a new device only has to add a chunk of its own. When a snapshot
+//! is loaded a chunk with an unknown tag (or a newer version than this coco understands) is
+//! skipped with a warning and that part of the machine is left as it is. Bytes after the end
+//! of the fields a chunk's version defines are ignored, so a newer coco can also add fields to
+//! the end of a chunk without changing its version.
+//!
+//! The chunks (all version 1):
+//! ```text
+//! CORE  D, X, Y, U, S, PC, DP and CC, then flags (bit 0: in CWAI, bit 1: in SYNC)
+//! RAM   the last address of RAM (2 bytes) and RAM packed with PackBits (see rewind.rs)
+//! SAM   the SAM's configuration bits (2 bytes)
+//! PIA0  both sides' registers and control lines (see PiaState::to_bytes)
+//! PIA1  the same for PIA1
+//! ACIA  the control register (only if there's an ACIA)
+//! CART  1 if the cartridge is inserted, then the cartridge's own state (only with a cartridge)
+//! DISK  for each drive the length (2 bytes) and path of its image (0 if nothing is mounted)
+//! TAPE  the cassette recorder's motor, DAC and tape position (see Cassette::save_state)
+//! FDC   the disk controller's registers and head positions (see DiskController::save_state)
+//! TIMR  the timer cartridge's registers and count
+//! WPAK  the WordPak's CRTC registers and video RAM
+//! MIDI  the MIDI Pak's control register and how long the transmitter is busy for
+//! TBLT  the tablet's latched position
+//! RDSK  the RAM disk's address and its contents packed with PackBits
+//! ```
+//! The last six are written by the devices themselves (see Cartridge::snapshot in cart.rs).
+//! Disk images, tapes and cartridge ROMs aren't copied into the snapshot: loading one only warns
+//! when a different disk or tape is in. A disk command in progress isn't saved either, so a
+//! snapshot taken in the middle of one leaves the controller idle. The VDG has no chunk because
+//! what it shows follows from the SAM, PIA1 and RAM. Pending interrupts are covered by the PIAs'
+//! flags (IRQ and FIRQ), the CWAI and SYNC flags and the controller's NMI flag.
+use super::*;
+use pia::{Pia, PiaState};
+use registers::Name::{self, CC, D, PC, S, U, X, Y};
+
+pub const MAGIC: &[u8; 8] = b"COCOSNAP";
+/// the version of every chunk this coco writes (and the newest it reads)
+const VERSION: u16 = 1;
+/// the chunks in the order they're restored (PIA1 goes before PIA0, which reads it)
+const TAGS: [&[u8; 4]; 15] = [
+    b"CORE", b"RAM ", b"SAM ", b"PIA1", b"PIA0", b"ACIA", b"CART", b"DISK", b"TAPE", b"FDC ", b"TIMR", b"WPAK",
+    b"MIDI", b"TBLT", b"RDSK",
+];
+/// the 16-bit registers in the CORE chunk
+const REGISTERS: [Name; 6] = [D, X, Y, U, S, PC];
+
+#[derive(Debug)]
+struct Chunk<'a> {
+    tag: [u8; 4],
+    version: u16,
+    data: &'a [u8],
+}
+impl Chunk<'_> {
+    fn name(&self) -> String { String::from_utf8_lossy(&self.tag).trim_end().to_string() }
+    /// Returns the data from start to end or an error if the chunk is too short
+    fn get(&self, start: usize, end: usize) -> Result<&[u8], Error> {
+        self.data.get(start..end).ok_or_else(|| general_err!("snapshot chunk {} is too short", self.name()))
+    }
+}
+
+/// Returns an error unless a chunk's data is at least len bytes long
+pub fn check_len(tag: &[u8; 4], data: &[u8], len: usize) -> Result<(), Error> {
+    let chunk = Chunk { tag: *tag, version: VERSION, data };
+    chunk.get(0, len).map(|_| ())
+}
+
+/// Appends a chunk to a snapshot
+pub fn put_chunk(out: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(tag);
+    out.extend(VERSION.to_be_bytes());
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Splits a snapshot into its chunks
+fn chunks(bytes: &[u8]) -> Result<Vec<Chunk<'_>>, Error> {
+    let mut rest = bytes.strip_prefix(MAGIC).ok_or_else(|| general_err!("not a coco snapshot"))?;
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        let (Some(header), Some(len)) = (rest.get(..10), rest.get(6..10)) else {
+            return Err(general_err!("snapshot is truncated"));
+        };
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let data = rest.get(10..10 + len).ok_or_else(|| general_err!("snapshot is truncated"))?;
+        let tag = header[..4].try_into().unwrap();
+        chunks.push(Chunk { tag, version: u16::from_be_bytes([header[4], header[5]]), data });
+        rest = &rest[10 + len..];
+    }
+    Ok(chunks)
+}
+
+impl Core {
+    /// Returns the machine's state as a snapshot
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        let mut core: Vec<u8> = REGISTERS.iter().flat_map(|&r| self.reg.get_register(r).u16().to_be_bytes()).collect();
+        core.extend([self.reg.dp, self.reg.get_register(CC).u8(), self.in_cwai as u8 | (self.in_sync as u8) << 1]);
+        put_chunk(&mut out, b"CORE", &core);
+        let mut ram = self.ram_top.to_be_bytes().to_vec();
+        ram.extend(rewind::pack(&self.raw_ram[..=self.ram_top as usize]));
+        put_chunk(&mut out, b"RAM ", &ram);
+        put_chunk(&mut out, b"SAM ", &self.lock_sam().get_raw_config().to_be_bytes());
+        put_chunk(&mut out, b"PIA0", &self.lock_pia0().state().to_bytes());
+        put_chunk(&mut out, b"PIA1", &self.lock_pia1().state().to_bytes());
+        if let Some(acia) = self.acia.as_ref() {
+            put_chunk(&mut out, b"ACIA", &[acia.control()]);
+        }
+        if let Some(cart) = self.cart.as_ref() {
+            let mut data = vec![self.cart_inserted as u8];
+            data.extend(cart.save_state().unwrap_or_default());
+            put_chunk(&mut out, b"CART", &data);
+        }
+        let mut disks = Vec::new();
+        for drive in &self.disks.borrow().drive {
+            let path = drive.as_ref().map(|d| d.path.to_string_lossy().into_owned()).unwrap_or_default();
+            disks.extend((path.len() as u16).to_be_bytes());
+            disks.extend(path.as_bytes());
+        }
+        put_chunk(&mut out, b"DISK", &disks);
+        if let Some(cassette) = self.cassette.as_ref() {
+            put_chunk(&mut out, b"TAPE", &cassette.borrow_mut().save_state(self.clock_cycles));
+        }
+        for pak in &self.paks {
+            if let Some(state) = pak.snapshot(self.clock_cycles) {
+                put_chunk(&mut out, &pak.tag(), &state);
+            }
+        }
+        out
+    }
+    /// Puts the machine in the state saved in a snapshot. Returns a warning for each chunk that
+    /// was skipped.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<Vec<String>, Error> {
+        let chunks = chunks(bytes)?;
+        let mut warnings = Vec::new();
+        for chunk in &chunks {
+            if !TAGS.contains(&&chunk.tag) {
+                let name = chunk.name();
+                warnings.push(format!("skipped snapshot chunk {} (unknown to this version of coco)", name));
+            } else if chunk.version > VERSION {
+                let (name, version) = (chunk.name(), chunk.version);
+                warnings.push(format!("skipped snapshot chunk {} (version {} is too new)", name, version));
+            }
+        }
+        // check every chunk before changing anything so a bad snapshot leaves the machine alone
+        let mut ram = Vec::new();
+        for chunk in chunks.iter().filter(|c| c.version <= VERSION) {
+            let len = match &chunk.tag {
+                b"CORE" => 15,
+                b"RAM " | b"SAM " => 2,
+                b"PIA0" | b"PIA1" => PiaState::LEN,
+                b"ACIA" | b"CART" => 1,
+                b"TAPE" => 6,
+                _ => 0,
+            };
+            chunk.get(0, len)?;
+            match &chunk.tag {
+                b"RAM " => ram = rewind::unpack(&chunk.data[2..])?,
+                b"CART" => {
+                    if let Some(cart) = self.cart.as_ref().filter(|_| chunk.data.len() > 1) {
+                        cart.check_state(&chunk.data[1..])?;
+                    }
+                }
+                tag => {
+                    if let Some(pak) = self.paks.iter().find(|p| &p.tag() == tag) {
+                        pak.check_snapshot(chunk.data)?;
+                    }
+                }
+            }
+        }
+        for tag in TAGS {
+            let Some(chunk) = chunks.iter().rev().find(|c| &c.tag == tag && c.version <= VERSION) else {
+                continue;
+            };
+            match tag {
+                b"CORE" => {
+                    let data = chunk.get(0, 15)?;
+                    for (&r, word) in REGISTERS.iter().zip(data.chunks(2)) {
+                        self.reg.set_register(r, u8u16::u16(u16::from_be_bytes([word[0], word[1]])));
+                    }
+                    self.reg.dp = data[12];
+                    self.reg.set_register(CC, u8u16::u8(data[13]));
+                    (self.in_cwai, self.in_sync) = (data[14] & 1 != 0, data[14] & 2 != 0);
+                }
+                b"RAM " => {
+                    let top = u16::from_be_bytes(chunk.get(0, 2)?.try_into().unwrap());
+                    if top != self.ram_top {
+                        let (saved, now) = (top as usize + 1, self.ram_top as usize + 1);
+                        warnings.push(format!("the snapshot has {} bytes of RAM but the machine has {}", saved, now));
+                    }
+                    let len = ram.len().min(self.ram_top as usize + 1);
+                    self.raw_ram[..len].copy_from_slice(&ram[..len]);
+                }
+                b"SAM " => {
+                    let config = u16::from_be_bytes(chunk.get(0, 2)?.try_into().unwrap());
+                    self.lock_sam().set_raw_config(config);
+                }
+                b"PIA1" => {
+                    let mut pia1 = self.lock_pia1();
+                    let state = pia1.state().with_bytes(chunk.data).unwrap();
+                    pia1.set_state(&state);
+                }
+                b"PIA0" => {
+                    let mut pia0 = self.lock_pia0();
+                    let state = pia0.state().with_bytes(chunk.data).unwrap();
+                    pia0.set_state(&state);
+                }
+                b"ACIA" => match self.acia.as_mut() {
+                    Some(acia) => acia.set_control(chunk.data[0]),
+                    None => warnings.push("skipped snapshot chunk ACIA (there's no ACIA)".to_string()),
+                },
+                b"CART" => match self.cart.as_mut() {
+                    Some(cart) => {
+                        self.cart_inserted = chunk.data[0] != 0;
+                        if chunk.data.len() > 1 {
+                            cart.load_state(&chunk.data[1..])?;
+                        }
+                    }
+                    None => warnings.push("skipped snapshot chunk CART (there's no cartridge)".to_string()),
+                },
+                b"DISK" => {
+                    let mut data = chunk.data;
+                    for (n, drive) in self.disks.borrow().drive.iter().enumerate() {
+                        let Some(len) = data.get(..2).map(|b| u16::from_be_bytes([b[0], b[1]]) as usize) else { break };
+                        let Some(path) = data.get(2..2 + len) else { break };
+                        let path = String::from_utf8_lossy(path);
+                        let mounted = drive.as_ref().map(|d| d.path.to_string_lossy().into_owned()).unwrap_or_default();
+                        if path != mounted {
+                            warnings.push(format!("drive {} had {:?} mounted when the snapshot was saved", n, path));
+                        }
+                        data = &data[2 + len..];
+                    }
+                }
+                b"TAPE" => match self.cassette.as_ref() {
+                    Some(cassette) => warnings.extend(cassette.borrow_mut().load_state(chunk.data, self.clock_cycles)),
+                    None => warnings.push("skipped snapshot chunk TAPE (there's no cassette)".to_string()),
+                },
+                tag => match self.paks.iter_mut().find(|p| p.tag() == *tag) {
+                    Some(pak) => pak.restore(chunk.data, self.clock_cycles),
+                    None => {
+                        let name = chunk.name();
+                        warnings.push(format!("skipped snapshot chunk {} (that device isn't plugged in)", name));
+                    }
+                },
+            }
+        }
+        self.lock_vdg().set_dirty();
+        if let Some(hang) = self.hang.as_mut() {
+            hang.reset(self.clock_cycles);
+        }
+        self.clear_history();
+        Ok(warnings)
+    }
+    /// Saves the machine's state to a file
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.snapshot())?;
+        info!("{}", tr!("saved the machine's state to {}", path.display()));
+        Ok(())
+    }
+    /// Loads the machine's state from a file saved by save_snapshot (or by XRoar; see import.rs)
+    pub fn load_snapshot(&mut self, path: &Path) -> Result<(), Error> {
+        let mut bytes = std::fs::read(path)?;
+        let mut warnings = Vec::new();
+        if bytes.starts_with(import::XROAR_MAGIC) {
+            (bytes, warnings) = import::xroar(&bytes)?;
+        }
+        warnings.extend(self.restore(&bytes)?);
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        info!("{}", tr!("loaded the machine's state from {} (PC={})", path.display(), format!("{:04X}", self.reg.pc)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::AccessType::Generic;
+
+    #[test]
+    fn round_trip() {
+        let mut core = Core::headless().unwrap();
+        core.reg.set_register(X, u8u16::u16(0x1234));
+        core.reg.set_register(CC, u8u16::u8(0x50));
+        (core.reg.pc, core.in_sync) = (0x3000, true);
+        core.raw_ram[0x2000..0x2004].copy_from_slice(b"coco");
+        core.sam.lock().unwrap().set_raw_config(0x0209);
+        core.pia1.lock().unwrap().write(3, 0x37);
+        let mut saved = core.snapshot();
+        // chunks from a newer coco are skipped
+        put_chunk(&mut saved, b"SPCH", &[1, 2, 3]);
+        saved.extend(b"SAM \x00\x02\x00\x00\x00\x02\xff\xff");
+
+        let mut restored = Core::headless().unwrap();
+        let warnings = restored.restore(&saved).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("SPCH") && warnings[1].contains("version 2"));
+        assert_eq!(restored.reg.get_register(X).u16(), 0x1234);
+        assert_eq!((restored.reg.pc, restored.in_sync), (0x3000, true));
+        assert_eq!(restored.reg.get_register(CC).u8(), 0x50);
+        assert_eq!(&restored.raw_ram[0x2000..0x2004], b"coco");
+        assert_eq!(restored.sam.lock().unwrap().get_raw_config(), 0x0209);
+        assert_eq!(restored.pia1.lock().unwrap().state().to_bytes(), core.pia1.lock().unwrap().state().to_bytes());
+
+        assert!(restored.restore(b"COCOSNAPCORE\x00\x01\x00\x00\x00\x01\x00").is_err());
+        assert!(restored.restore(b"COCOSNAPCORE\x00").is_err());
+        assert!(restored.restore(b"not a snapshot").is_err());
+    }
+
+    #[test]
+    fn devices() {
+        let mut core = Core::headless().unwrap();
+        core.paks.push(Box::new(timer::Timer::new(0xff54)));
+        core.raw_ram[0x2000] = 1;
+        core._write_u8(Generic, 0xff56, 100).unwrap();
+        core._write_u8(Generic, 0xff54, 1).unwrap();
+        core.clock_cycles = 40;
+        let saved = core.snapshot();
+
+        // a bad chunk is found before anything is changed
+        let mut restored = Core::headless().unwrap();
+        restored.paks.push(Box::new(timer::Timer::new(0xff54)));
+        restored.raw_ram[0x2000] = 2;
+        let mut bad = saved.clone();
+        put_chunk(&mut bad, b"RAM ", &[0x7f, 0xff, 5, 1]);
+        assert!(restored.restore(&bad).is_err());
+        assert_eq!((restored.raw_ram[0x2000], restored._read_u8(Generic, 0xff56, None).unwrap()), (2, 0));
+
+        // the timer carries on counting from where it was
+        restored.clock_cycles = 1000;
+        assert!(restored.restore(&saved).unwrap().is_empty());
+        restored.clock_cycles = 1010;
+        let count = [0xff55, 0xff56].map(|addr| restored._read_u8(Generic, addr, None).unwrap());
+        assert_eq!(count, [0, 50]);
+        // without the timer its chunk is skipped
+        let warnings = Core::headless().unwrap().restore(&saved).unwrap();
+        assert!(warnings.len() == 1 && warnings[0].contains("TIMR"));
+    }
+}
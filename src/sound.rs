@@ -51,7 +51,8 @@ impl AudioDevice {
         let sample_rate = dc.sample_rate().0 as usize;
         let buffer_frames = match *dc.buffer_size() {
             cpal::SupportedBufferSize::Range { min, max } => max.min(2048).max(min) as usize,
-            _ => panic!(),
+            // the device doesn't say so use the most we'd ask for anyway
+            cpal::SupportedBufferSize::Unknown => 2048,
         };
         info!(
             "audio output stream config: channels={channels}, sample_rate={sample_rate}, buffer_frames={buffer_frames}"
@@ -0,0 +1,261 @@
+//! I/O spies: actions triggered by reads and writes of I/O addresses.
+//!
+//! A spy watches one address in I/O space (the PIAs, SAM and any devices) and does something
+//! whenever the program reads or writes it and an optional condition holds: it logs a message,
+//! saves a screenshot or breaks into the debugger. Spies are given with --spy (or listed under
+//! `spies` in coco.yaml) and added in the debugger with the `spy` command, all in the same form:
+//! ```text
+//! <loc> r|w|rw [if <cond>] log <message> | shot <file> | break
+//! ff22 w if value/8!=old/8 log VDG mode bits {old:b} -> {value:b} at PC={pc}
+//! ff02 r if value=$fe shot title-{cycle}.ppm
+//! ```
+//! Conditions are written like those of conditional breakpoints (see expr.rs) and can also use
+//! `value` (the byte read or written) and `old` (the byte at the previous access of the address,
+//! which is the same as value at the first one). Messages and file names can include {value},
+//! {old}, {addr}, {pc} and {cycle}; bytes are shown in hex, or in binary as {value:b} and
+//! {old:b}. Screenshots are saved as PPM images once the instruction that triggered them is done.
+use super::*;
+use expr::LabelResolver;
+use std::path::PathBuf;
+
+/// Which accesses of its address trigger a spy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Both,
+}
+impl Access {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "r" => Some(Access::Read),
+            "w" => Some(Access::Write),
+            "rw" => Some(Access::Both),
+            _ => None,
+        }
+    }
+    fn includes(&self, write: bool) -> bool { *self == Access::Both || (*self == Access::Write) == write }
+}
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Access::Read => "r",
+            Access::Write => "w",
+            Access::Both => "rw",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Log(String),
+    Shot(String),
+    Break,
+}
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Log(msg) => write!(f, "log {}", msg),
+            Action::Shot(file) => write!(f, "shot {}", file),
+            Action::Break => write!(f, "break"),
+        }
+    }
+}
+
+pub struct Spy {
+    pub addr: u16,
+    pub access: Access,
+    condition: Option<expr::Condition>,
+    pub action: Action,
+}
+impl Spy {
+    /// Parses a spy (see above), using addr_of to work out its address
+    pub fn parse(spec: &str, addr_of: impl Fn(&str) -> Option<u16>) -> Result<Self, Error> {
+        let words: Vec<&str> = spec.split_whitespace().collect();
+        let [loc, access, rest @ ..] = words.as_slice() else {
+            return Err(general_err!("expected <loc> r|w|rw followed by an action"));
+        };
+        let addr = addr_of(loc).ok_or_else(|| general_err!("invalid address \"{}\"", loc))?;
+        let access = Access::parse(access).ok_or_else(|| general_err!("expected r, w or rw but found \"{}\"", access))?;
+        let (condition, rest) = match rest {
+            [kw, cond, rest @ ..] if kw.eq_ignore_ascii_case("if") => {
+                (Some(parse::Parser::new().parse_condition(cond)?), rest)
+            }
+            _ => (None, rest),
+        };
+        let action = match rest {
+            ["log", msg @ ..] if !msg.is_empty() => Action::Log(msg.join(" ")),
+            ["shot", file] => Action::Shot(file.to_string()),
+            ["break"] => Action::Break,
+            _ => return Err(general_err!("expected log <message>, shot <file> or break")),
+        };
+        Ok(Spy { addr, access, condition, action })
+    }
+}
+impl fmt::Display for Spy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X} {}", self.addr, self.access)?;
+        if let Some(condition) = self.condition.as_ref() {
+            write!(f, " if {}", condition)?;
+        }
+        write!(f, " {}", self.action)
+    }
+}
+
+#[derive(Default)]
+pub struct Spies {
+    pub list: Vec<Spy>,
+    /// the byte at each watched address at its last access
+    last: HashMap<u16, u8>,
+    /// screenshots to save once the current instruction is done
+    shots: Vec<PathBuf>,
+    /// the spy that wants to break into the debugger (if any)
+    stop: Option<String>,
+}
+
+/// An access that a spy is looking at. Resolves `value` and `old` for the spy's condition and
+/// leaves everything else to the debugger.
+struct Trigger<'a> {
+    core: &'a Core,
+    addr: u16,
+    value: u8,
+    old: u8,
+}
+impl Trigger<'_> {
+    /// Fills in the placeholders in a message or file name
+    fn fill(&self, text: &str) -> String {
+        text.replace("{value:b}", &format!("{:08b}", self.value))
+            .replace("{old:b}", &format!("{:08b}", self.old))
+            .replace("{value}", &format!("{:02X}", self.value))
+            .replace("{old}", &format!("{:02X}", self.old))
+            .replace("{addr}", &format!("{:04X}", self.addr))
+            .replace("{pc}", &format!("{:04X}", self.core.reg.pc))
+            .replace("{cycle}", &self.core.clock_cycles.to_string())
+    }
+}
+impl LabelResolver for Trigger<'_> {
+    fn resolve(&self, label: &str) -> Option<u8u16> {
+        match label {
+            "value" => Some(u8u16::u8(self.value)),
+            "old" => Some(u8u16::u8(self.old)),
+            _ => self.core.resolve(label),
+        }
+    }
+    fn register(&self, name: &str) -> Option<u8u16> { self.core.register(name) }
+    fn read(&self, addr: u16) -> Option<u8> { self.core.read(addr) }
+}
+
+impl Core {
+    /// Adds a spy (which has to watch an I/O address)
+    pub fn add_spy(&mut self, spy: Spy) -> Result<(), Error> {
+        if !self.is_io_address(spy.addr) {
+            return Err(general_err!("{:04X} isn't an I/O address", spy.addr));
+        }
+        if spy.action == Action::Break && !config::debug() {
+            return Err(general_err!("a spy can only break into the debugger with --debug"));
+        }
+        self.spies.get_or_insert_with(Default::default).get_mut().list.push(spy);
+        Ok(())
+    }
+    /// Adds the spies given with --spy and in the config file
+    pub fn spies_from_args(&mut self) -> Result<(), Error> {
+        let file = config::ARGS.config_file.as_ref().and_then(|c| c.spies.as_ref());
+        for spec in file.into_iter().flatten().chain(&config::ARGS.spy) {
+            let spy = Spy::parse(spec, |loc| self.parse_address(loc)).map_err(|e| general_err!("spy {}: {}", spec, e))?;
+            self.add_spy(spy)?;
+        }
+        Ok(())
+    }
+    /// Runs the spies that watch addr (value was just read from it or is being written to it)
+    pub fn spy(&self, addr: u16, value: u8, write: bool) {
+        let Some(spies) = self.spies.as_ref() else {
+            return;
+        };
+        // the debugger's own reads (e.g., dumping memory) don't count
+        if self.in_debugger || !spies.borrow().list.iter().any(|s| s.addr == addr) {
+            return;
+        }
+        let Spies { list, last, shots, stop } = &mut *spies.borrow_mut();
+        let old = last.insert(addr, value).unwrap_or(value);
+        let trigger = Trigger { core: self, addr, value, old };
+        for spy in list.iter().filter(|s| s.addr == addr && s.access.includes(write)) {
+            // a condition that can't be evaluated is reported and counts as holding
+            let holds = match spy.condition.as_ref().map(|c| c.eval(&trigger, self.reg.pc)) {
+                Some(Ok(holds)) => holds,
+                Some(Err(e)) => {
+                    warn!("spy {}: {}", spy, e);
+                    true
+                }
+                None => true,
+            };
+            if holds {
+                match &spy.action {
+                    Action::Log(msg) => info!("{}", trigger.fill(msg)),
+                    Action::Shot(file) => shots.push(PathBuf::from(trigger.fill(file))),
+                    Action::Break => *stop = Some(spy.to_string()),
+                }
+            }
+        }
+    }
+    /// Saves the screenshots the spies have asked for since the last instruction
+    pub fn spy_shots(&mut self) {
+        let Some(spies) = self.spies.as_mut() else {
+            return;
+        };
+        let shots = std::mem::take(&mut spies.get_mut().shots);
+        for path in shots {
+            match self.save_screenshot(&path) {
+                Ok(()) => info!("saved a screenshot to {}", path.display()),
+                Err(e) => warn!("failed to save a screenshot to {}: {}", path.display(), e),
+            }
+        }
+    }
+    /// Returns the spy that wants to break into the debugger (if any)
+    pub fn spy_stop(&self) -> Option<String> { self.spies.as_ref().and_then(|s| s.borrow_mut().stop.take()) }
+    /// Saves the screen as a PPM image
+    pub fn save_screenshot(&self, path: &std::path::Path) -> Result<(), Error> {
+        let mut pixels = vec![0u32; vdg::SCREEN_DIM_X * vdg::SCREEN_DIM_Y];
+        let pia_bits = self.lock_pia1().get_vdg_bits();
+        let sam_config = self.lock_sam().get_raw_config();
+        if let Some(mode) = vdg::VdgMode::try_from_pia_and_sam(pia_bits, sam::Sam::vdg_bits(sam_config)) {
+            let mut vdg = self.lock_vdg();
+            vdg.set_mode(mode);
+            vdg.set_vram_offset(sam::Sam::vram_start(sam_config) as usize)?;
+            vdg.set_dirty();
+            vdg.render(&mut pixels, pia_bits & 1 == 1);
+            // the display still has to be brought up to date
+            vdg.set_dirty();
+        }
+        std::fs::write(path, vdg::ppm(&pixels, vdg::SCREEN_DIM_X, vdg::SCREEN_DIM_Y))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triggers() {
+        let hex = |loc: &str| u16::from_str_radix(loc, 16).ok();
+        let spy = Spy::parse("ff02 w if value/8!=old/8 log keys {old:b} -> {value:b} at {pc}", hex).unwrap();
+        let log = Action::Log("keys {old:b} -> {value:b} at {pc}".into());
+        assert_eq!((spy.addr, spy.access, spy.action.clone()), (0xff02, Access::Write, log));
+        assert!(Spy::parse("ff22 x log hi", hex).is_err());
+        assert!(Spy::parse("ff22 r", hex).is_err());
+        assert!(Spy::parse("ff22 r shot a b", hex).is_err());
+        let mut core = Core::headless().unwrap();
+        assert!(core.add_spy(Spy::parse("0400 rw log ram", hex).unwrap()).is_err());
+        core.add_spy(spy).unwrap();
+        core.add_spy(Spy::parse("ff02 rw if value=$f8 shot {addr}-{value}.ppm", hex).unwrap()).unwrap();
+        for value in [0x08, 0x0f, 0xf8, 0x10] {
+            core._write_u8(memory::AccessType::Generic, 0xff02, value).unwrap();
+        }
+        let spies = core.spies.as_ref().unwrap().borrow();
+        assert_eq!(spies.shots, vec![PathBuf::from("FF02-F8.ppm")]);
+        assert_eq!(spies.last[&0xff02], 0x10);
+        let trigger = Trigger { core: &core, addr: 0xff02, value: 0x0f, old: 0xf8 };
+        assert_eq!(trigger.fill("{old:b}->{value} {addr}"), "11111000->0F FF02");
+    }
+}
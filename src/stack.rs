@@ -0,0 +1,109 @@
+//! Bounds checking for the system stack.
+//!
+//! Pushes already fail when S would wrap below zero, but the far more common mistake is a
+//! stack that quietly grows down into the program or its data. When a stack guard is set up
+//! the system stack pointer is checked after every instruction (whether or not the debugger is
+//! enabled) and the program faults as soon as S leaves the allowed range.
+//!
+//! The bounds come from the --stack-low/--stack-high options, from a `STACK low,high`
+//! directive in the program or, with --stack-guard, they're derived from the loaded program:
+//! a stack that starts above the program may not grow into it and a stack that starts
+//! below the program may not be pulled up into it.
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackBounds {
+    Fixed(u16, u16),             // lowest and highest allowed values of S
+    Auto { start: u16, end: u16 }, // extent of the program (end is exclusive)
+}
+
+#[derive(Debug)]
+pub struct StackGuard {
+    bounds: StackBounds,
+    armed: bool, // the guard is armed once S is first set up by the program
+}
+impl StackGuard {
+    pub fn new(bounds: StackBounds) -> Self {
+        match bounds {
+            StackBounds::Fixed(low, high) => info!("stack limited to {:04X}-{:04X}", low, high),
+            StackBounds::Auto { start, end } => {
+                info!("stack will be kept clear of the program at {:04X}-{:04X}", start, end.wrapping_sub(1))
+            }
+        }
+        StackGuard { bounds, armed: false }
+    }
+    /// Creates a guard from the --stack-low and --stack-high options (if both were given)
+    pub fn from_args() -> Option<Self> {
+        match (config::ARGS.stack_low, config::ARGS.stack_high) {
+            (Some(low), Some(high)) => Some(StackGuard::new(StackBounds::Fixed(low, high))),
+            _ => None,
+        }
+    }
+    /// Checks the system stack pointer in reg. Returns a runtime error if it's out of bounds.
+    #[inline(always)]
+    pub fn check(&mut self, reg: &registers::Set, ram_top: u16) -> Result<(), Error> {
+        let s = reg.s;
+        if !self.armed {
+            match self.bounds {
+                StackBounds::Fixed(low, high) => self.armed = (low..=high).contains(&s),
+                StackBounds::Auto { start, end } if s != 0 => {
+                    self.armed = true;
+                    self.bounds = if s >= end {
+                        StackBounds::Fixed(end, ram_top.saturating_add(1))
+                    } else if s <= start {
+                        StackBounds::Fixed(0, start)
+                    } else {
+                        warn!("stack pointer {:04X} is inside the program; not checking stack bounds", s);
+                        StackBounds::Fixed(0, 0xffff)
+                    };
+                    if let StackBounds::Fixed(low, high) = self.bounds {
+                        verbose_println!("stack bounds set to {:04X}-{:04X}", low, high);
+                    }
+                }
+                _ => {}
+            }
+            return Ok(());
+        }
+        if let StackBounds::Fixed(low, high) = self.bounds {
+            if s < low {
+                return Err(runtime_err!(
+                    Some(*reg),
+                    "stack overflow: S={:04X} is below the stack's lower bound ({:04X}); too many bytes were pushed or a subroutine recursed too deeply",
+                    s,
+                    low
+                ));
+            }
+            if s > high {
+                return Err(runtime_err!(
+                    Some(*reg),
+                    "stack underflow: S={:04X} is above the stack's upper bound ({:04X}); more bytes were pulled than were pushed",
+                    s,
+                    high
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_bounds() {
+        let mut guard = StackGuard {
+            bounds: StackBounds::Auto { start: 0x1000, end: 0x1200 },
+            armed: false,
+        };
+        let mut reg = registers::Set::default();
+        assert!(guard.check(&reg, 0x7fff).is_ok());
+        reg.s = 0x2000;
+        assert!(guard.check(&reg, 0x7fff).is_ok());
+        assert_eq!(guard.bounds, StackBounds::Fixed(0x1200, 0x8000));
+        reg.s = 0x1200;
+        assert!(guard.check(&reg, 0x7fff).is_ok());
+        reg.s = 0x11fe;
+        assert!(guard.check(&reg, 0x7fff).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Graphics tablet (in the spirit of the X-Pad).
+//!
+//! The mouse stands in for the tablet's pen: its position in the window is the pen's absolute
+//! position and the left button presses the pen down on the pad. It's enabled with --tablet and
+//! its registers start at --tablet-addr ($FF60 by default):
+//! ```text
+//! base+0  X (0-255 from left to right)
+//! base+1  Y (0-191 from top to bottom)
+//! base+2  status
+//!           bit 0  the pen is pressed down (the left mouse button)
+//!           bit 1  the pen is over the pad (the mouse is in the window)
+//!           bit 2  the pen's side button (the right mouse button)
+//! ```
+//! Reading the status latches the position so that X and Y always go together. The position
+//! is that of the last time the pen was over the pad.
+use super::*;
+use cart::Cartridge;
+use pia::PiaInput;
+use std::cell::Cell;
+use std::sync::Arc;
+
+const PEN_DOWN: u8 = 0x01;
+const IN_RANGE: u8 = 0x02;
+const SIDE_BUTTON: u8 = 0x04;
+
+pub struct Tablet {
+    pub base: u16,
+    input: Arc<PiaInput>,
+    /// the position latched by reading the status
+    x: Cell<u8>,
+    y: Cell<u8>,
+}
+impl Tablet {
+    pub fn new(base: u16, input: Arc<PiaInput>) -> Self {
+        info!("tablet enabled at {:04X}", base);
+        Tablet { base, input, x: Cell::new(0), y: Cell::new(0) }
+    }
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr - self.base {
+            0 => self.x.get(),
+            1 => self.y.get(),
+            _ => {
+                let Some((x, y)) = self.input.pointer() else {
+                    return 0;
+                };
+                self.x.set(x.min(vdg::SCREEN_DIM_X as u16 - 1) as u8);
+                self.y.set(y.min(vdg::SCREEN_DIM_Y as u16 - 1) as u8);
+                let buttons = self.input.buttons();
+                let mut status = IN_RANGE;
+                if buttons & 1 != 0 {
+                    status |= PEN_DOWN;
+                }
+                if buttons & 2 != 0 {
+                    status |= SIDE_BUTTON;
+                }
+                status
+            }
+        }
+    }
+}
+impl Cartridge for Tablet {
+    fn name(&self) -> String { format!("tablet at {:04X}", self.base) }
+    fn owns_address(&self, addr: u16) -> bool { addr >= self.base && addr - self.base < 3 }
+    fn read(&self, addr: u16, _cycle: u64) -> Option<u8> { self.owns_address(addr).then(|| Tablet::read(self, addr)) }
+    // the registers are read-only
+    fn write(&mut self, addr: u16, _byte: u8, _cycle: u64) -> Result<bool, Error> { Ok(self.owns_address(addr)) }
+    fn tag(&self) -> [u8; 4] { *b"TBLT" }
+    /// The latched position (the pen itself is wherever the mouse is)
+    fn snapshot(&self, _cycle: u64) -> Option<Vec<u8>> { Some(vec![self.x.get(), self.y.get()]) }
+    fn check_snapshot(&self, state: &[u8]) -> Result<(), Error> { snapshot::check_len(&self.tag(), state, 2) }
+    fn restore(&mut self, state: &[u8], _cycle: u64) {
+        self.x.set(state[0]);
+        self.y.set(state[1]);
+    }
+}
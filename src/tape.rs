@@ -0,0 +1,340 @@
+//! Cassette tapes: converting between .CAS files and audio.
+//!
+//! `coco tapeconv <input> <output>` turns a .CAS file (the bytes on a tape) into a .WAV file a
+//! real CoCo can CLOAD, or decodes a .WAV file (a tape digitized from a real cassette, say) into
+//! a .CAS file, listing each block it finds and any that are damaged.
+//!
+//! The CoCo records a 0 bit as one cycle of 1200 Hz and a 1 bit as one cycle of 2400 Hz, each
+//! byte least significant bit first. A file is a leader of $55 bytes, a name block, a gap (while
+//! BASIC restarts the motor), another leader and the data blocks, ending with an EOF block. A
+//! block is $55 $3C, its type (0 name, 1 data, $FF end of file), the length of its data, the
+//! data and a checksum (the sum of the type, length and data bytes), followed by another $55.
+//!
+//! Decoding measures the time between rising zero crossings and takes cycles above the
+//! --threshold frequency to be 1s. A crossing only counts once the signal has gone past
+//! --hysteresis (a fraction of the recording's peak) on each side, so noise near zero is ignored.
+use super::*;
+use std::f64::consts::TAU;
+
+const ZERO_HZ: f64 = 1200.0;
+const ONE_HZ: f64 = 2400.0;
+const LEADER: u8 = 0x55;
+const SYNC: u8 = 0x3c;
+/// the number of $55s in a leader
+const LEADER_LEN: usize = 128;
+/// the silence after a name block (in seconds)
+const GAP_SECS: f64 = 0.5;
+
+/// A block read from a tape
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub kind: u8,
+    pub data: Vec<u8>,
+    /// the checksum as it was read
+    pub checksum: u8,
+    /// where the block starts (in seconds)
+    pub at: f64,
+    /// what's wrong with the block (if anything)
+    pub error: Option<String>,
+}
+impl Block {
+    /// Returns a block (with the right checksum) of a kind: 0 name, 1 data or $FF end of file
+    pub fn new(kind: u8, data: &[u8]) -> Self {
+        let mut block = Block { kind, data: data.to_vec(), checksum: 0, at: 0.0, error: None };
+        block.checksum = block.sum();
+        block
+    }
+    /// Returns what the block's checksum should be
+    fn sum(&self) -> u8 {
+        let sum = self.kind.wrapping_add(self.data.len() as u8);
+        self.data.iter().fold(sum, |sum, &b| sum.wrapping_add(b))
+    }
+}
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            0 => {
+                let name = String::from_utf8_lossy(self.data.get(..8).unwrap_or(&self.data));
+                write!(f, "name block \"{}\"", name.trim_end())
+            }
+            1 => write!(f, "data block ({} bytes)", self.data.len()),
+            0xff => write!(f, "end of file block"),
+            kind => write!(f, "block of type ${:02X} ({} bytes)", kind, self.data.len()),
+        }
+    }
+}
+
+/// Returns where each name block in a .CAS file ends (after its trailing $55)
+fn name_block_ends(cas: &[u8]) -> Vec<usize> {
+    let mut ends = Vec::new();
+    for i in 0..cas.len().saturating_sub(3) {
+        if cas[i..i + 3] == [LEADER, SYNC, 0] {
+            let mut end = i + 5 + cas[i + 3] as usize;
+            if cas.get(end) == Some(&LEADER) {
+                end += 1;
+            }
+            ends.push(end);
+        }
+    }
+    ends
+}
+
+/// Returns the sound (from -1 to 1) of a tape holding the bytes of a .CAS file
+pub fn encode(cas: &[u8], rate: u32) -> Vec<f32> {
+    let rate = rate as f64;
+    let gaps = name_block_ends(cas);
+    let mut samples = Vec::new();
+    // where the next cycle starts (in samples)
+    let mut t = 0.0;
+    for (i, &byte) in cas.iter().enumerate() {
+        for bit in 0..8 {
+            let len = rate / if byte >> bit & 1 == 1 { ONE_HZ } else { ZERO_HZ };
+            while (samples.len() as f64) < t + len {
+                let phase = (samples.len() as f64 - t) / len;
+                samples.push((phase * TAU).sin() as f32);
+            }
+            t += len;
+        }
+        if gaps.contains(&(i + 1)) {
+            samples.resize((t + GAP_SECS * rate).ceil() as usize, 0.0);
+            t = samples.len() as f64;
+        }
+    }
+    samples
+}
+
+/// Turns bits into blocks
+#[derive(Default)]
+struct Decoder {
+    blocks: Vec<Block>,
+    /// the last 8 bits (the latest in bit 7)
+    shift: u8,
+    /// the block being read: where it started, its bytes (type, length, data and checksum) and
+    /// the number of bits of the next byte
+    block: Option<(f64, Vec<u8>, u8)>,
+}
+impl Decoder {
+    fn bit(&mut self, bit: bool, at: f64) {
+        self.shift = self.shift >> 1 | (bit as u8) << 7;
+        let Some((start, bytes, bits)) = self.block.as_mut() else {
+            if self.shift == SYNC {
+                self.block = Some((at, Vec::new(), 0));
+            }
+            return;
+        };
+        *bits += 1;
+        if *bits < 8 {
+            return;
+        }
+        *bits = 0;
+        bytes.push(self.shift);
+        if bytes.len() >= 2 && bytes.len() == bytes[1] as usize + 3 {
+            let (kind, checksum) = (bytes[0], bytes[bytes.len() - 1]);
+            let data = bytes[2..bytes.len() - 1].to_vec();
+            let mut block = Block { kind, data, checksum, at: *start, error: None };
+            if block.sum() != checksum {
+                block.error = Some(format!("bad checksum (${:02X}, should be ${:02X})", checksum, block.sum()));
+            }
+            self.blocks.push(block);
+            (self.block, self.shift) = (None, 0);
+        }
+    }
+    /// Ends the block being read (if any) at a gap or the end of the recording
+    fn cut_off(&mut self) {
+        if let Some((at, bytes, _)) = self.block.take() {
+            let (kind, data) = (bytes.first().copied().unwrap_or(0), bytes.get(2..).unwrap_or_default().to_vec());
+            let error = Some(format!("cut off after {} bytes", bytes.len()));
+            self.blocks.push(Block { kind, data, checksum: 0, at, error });
+        }
+    }
+}
+
+/// Returns the blocks on a tape. Cycles above threshold Hz are 1s and hysteresis is the
+/// fraction of the peak level the signal has to pass on each side of a zero crossing.
+pub fn decode(samples: &[f32], rate: u32, threshold: f64, hysteresis: f32) -> Vec<Block> {
+    let peak = samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+    let level = peak * hysteresis;
+    let (rate, mut decoder) = (rate as f64, Decoder::default());
+    // a cycle longer than this is a gap or a dropout
+    let gap = 3.0 * rate / ZERO_HZ;
+    let (mut high, mut last_rise) = (false, None);
+    for (i, &sample) in samples.iter().enumerate() {
+        if high && sample < -level {
+            high = false;
+        } else if !high && sample > level {
+            high = true;
+            if let Some(last) = last_rise {
+                let len = (i - last) as f64;
+                if len > gap {
+                    decoder.cut_off();
+                } else {
+                    decoder.bit(len < rate / threshold, i as f64 / rate);
+                }
+            }
+            last_rise = Some(i);
+        }
+    }
+    decoder.cut_off();
+    decoder.blocks
+}
+
+/// Returns the blocks in a .CAS file
+pub fn read_cas(cas: &[u8]) -> Vec<Block> {
+    let (mut blocks, mut i) = (Vec::new(), 0);
+    while i + 1 < cas.len() {
+        if cas[i] != SYNC {
+            i += 1;
+            continue;
+        }
+        let (kind, len) = (cas[i + 1], cas.get(i + 2).copied().unwrap_or(0) as usize);
+        let Some(bytes) = cas.get(i + 3..i + 4 + len) else {
+            let data = cas.get(i + 3..).unwrap_or_default().to_vec();
+            let error = Some(format!("cut off after {} bytes", cas.len() - i - 1));
+            blocks.push(Block { kind, data, checksum: 0, at: 0.0, error });
+            break;
+        };
+        let mut block = Block::new(kind, &bytes[..len]);
+        if block.checksum != bytes[len] {
+            block.error = Some(format!("bad checksum (${:02X}, should be ${:02X})", bytes[len], block.checksum));
+            block.checksum = bytes[len];
+        }
+        blocks.push(block);
+        i += 4 + len;
+    }
+    blocks
+}
+
+/// Returns a .CAS file holding blocks, with a leader before the first block, each name block
+/// and the block after a name block
+pub fn cas(blocks: &[Block]) -> Vec<u8> {
+    let (mut out, mut leader) = (Vec::new(), true);
+    for block in blocks {
+        if leader || block.kind == 0 {
+            out.extend([LEADER; LEADER_LEN]);
+        }
+        leader = block.kind == 0;
+        out.extend([LEADER, SYNC, block.kind, block.data.len() as u8]);
+        out.extend(&block.data);
+        out.extend([block.checksum, LEADER]);
+    }
+    out
+}
+
+/// Returns a WAV file (8-bit mono PCM) holding samples
+pub fn wav(samples: &[f32], rate: u32) -> Vec<u8> {
+    let len = samples.len() as u32;
+    let mut out = b"RIFF".to_vec();
+    out.extend((36 + len).to_le_bytes());
+    out.extend(b"WAVEfmt ");
+    out.extend(16u32.to_le_bytes());
+    // PCM, 1 channel, the sample rate, bytes per second, bytes per sample and bits per sample
+    out.extend([1, 0, 1, 0]);
+    out.extend(rate.to_le_bytes());
+    out.extend(rate.to_le_bytes());
+    out.extend([1, 0, 8, 0]);
+    out.extend(b"data");
+    out.extend(len.to_le_bytes());
+    out.extend(samples.iter().map(|s| (128.0 + s * 127.0).round() as u8));
+    out
+}
+
+/// Returns the samples (of the first channel) and the sample rate of an 8 or 16-bit PCM WAV file
+pub fn read_wav(bytes: &[u8]) -> Result<(Vec<f32>, u32), Error> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(general_err!("not a WAV file"));
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let (mut format, mut i) = (None, 12);
+    while i + 8 <= bytes.len() {
+        let len = u32::from_le_bytes(bytes[i + 4..i + 8].try_into().unwrap()) as usize;
+        let data = &bytes[i + 8..(i + 8 + len).min(bytes.len())];
+        match &bytes[i..i + 4] {
+            b"fmt " if data.len() >= 16 => {
+                let rate = u32::from_le_bytes(data[4..8].try_into().unwrap());
+                format = Some((u16_at(i + 8), rate, u16_at(i + 20) as usize, u16_at(i + 22)));
+            }
+            b"data" => {
+                let Some((1, rate, frame, bits @ (8 | 16))) = format.filter(|f| f.2 > 0) else {
+                    return Err(general_err!("only 8 and 16-bit PCM WAV files can be decoded"));
+                };
+                let samples = data.chunks_exact(frame).map(|s| match bits {
+                    8 => (s[0] as f32 - 128.0) / 128.0,
+                    _ => i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0,
+                });
+                return Ok((samples.collect(), rate));
+            }
+            _ => {}
+        }
+        i += 8 + len + (len & 1);
+    }
+    Err(general_err!("the WAV file has no audio"))
+}
+
+/// Converts a .CAS file to a .WAV file or the other way around (coco tapeconv)
+pub fn convert(input: &Path, output: &Path, rate: u32, threshold: f64, hysteresis: f32) -> Result<(), Error> {
+    let ext = |path: &Path| path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    match (ext(input).as_deref(), ext(output).as_deref()) {
+        (Some("cas"), Some("wav")) => {
+            let samples = encode(&std::fs::read(input)?, rate);
+            std::fs::write(output, wav(&samples, rate))?;
+            let secs = samples.len() as f64 / rate as f64;
+            println!("Wrote {:.1} seconds of audio to {}", secs, output.display());
+        }
+        (Some("wav"), Some("cas")) => {
+            let (samples, rate) = read_wav(&std::fs::read(input)?)?;
+            let blocks = decode(&samples, rate, threshold, hysteresis);
+            for (n, block) in blocks.iter().enumerate() {
+                match &block.error {
+                    None => println!("{:7.2}s  block {}: {}", block.at, n, block),
+                    Some(e) => println!("{:7.2}s  block {}: {}: {}", block.at, n, block, e),
+                }
+            }
+            std::fs::write(output, cas(&blocks))?;
+            let bad = blocks.iter().filter(|b| b.error.is_some()).count();
+            println!("Wrote {} blocks ({} with errors) to {}", blocks.len(), bad, output.display());
+        }
+        _ => return Err(general_err!("tapeconv converts a .cas file to a .wav file or a .wav file to a .cas file")),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let blocks = [
+            Block::new(0, b"GAME    \x02\x00\x00\x30\x00\x30\x00"),
+            Block::new(1, &[0x39; 255]),
+            Block::new(0xff, &[]),
+        ];
+        let cas = cas(&blocks);
+        assert_eq!(read_cas(&cas), blocks);
+        for rate in [11025, 44100] {
+            let (samples, noise) = (encode(&cas, rate), [0.05, -0.05].iter().cycle());
+            // a leader and 0.5s gap before the data
+            assert!(samples.len() as f64 > (cas.len() * 8) as f64 * rate as f64 / ONE_HZ + 0.5 * rate as f64);
+            let noisy: Vec<f32> = samples.iter().zip(noise).map(|(s, n)| s * 0.5 + n).collect();
+            let wav = wav(&noisy, rate);
+            let (read, read_rate) = read_wav(&wav).unwrap();
+            assert_eq!((read.len(), read_rate), (samples.len(), rate));
+            let decoded = decode(&read, rate, 1800.0, 0.2);
+            assert_eq!(decoded.len(), 3);
+            for (decoded, block) in decoded.iter().zip(&blocks) {
+                assert_eq!((decoded.kind, &decoded.data, &decoded.error), (block.kind, &block.data, &None));
+            }
+            assert!(decoded[1].at > decoded[0].at + GAP_SECS);
+            assert_eq!(decoded[0].to_string(), "name block \"GAME\"");
+        }
+        // a damaged byte and a recording that stops in the middle of a block
+        let mut bad = cas.clone();
+        bad[LEADER_LEN + 10] ^= 1;
+        let samples = encode(&bad[..bad.len() - 10], 22050);
+        let decoded = decode(&samples, 22050, 1800.0, 0.1);
+        assert!(decoded[0].error.as_ref().unwrap().starts_with("bad checksum"));
+        assert_eq!(decoded.len(), 2);
+        // (the last byte is lost too because its last cycle is never followed by another)
+        assert_eq!(decoded[1].error.as_deref(), Some("cut off after 254 bytes"));
+    }
+}
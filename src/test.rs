@@ -10,7 +10,7 @@
 //! ```text
 //! identifier-expression evaluates to an ident
 //! value-expression evaluates to a value
-//! ident := register | address
+//! ident := register | cc.flag | address
 //! value := constant | address
 //! constant := '#' valexpr
 //! address := valexpr
@@ -32,17 +32,20 @@
 //! - `;! label+1 = #10` Passes if byte at address _label+1_ equals value 10 (decimal)
 //! - `;! label = a` Passes if byte at address _label_ equals value of register A
 //! - `;! b = #'C` Passes if register B holds the value of ascii char 'C' (0x43)
+//! - `;! cc.z = #1` Passes if the zero flag is set (any of the flags E, F, H, I, N, Z, V and C can be checked)
 //!
 use super::*;
 #[derive(Debug)]
 pub enum RegOrAddr {
     Reg(registers::Name),
+    Flag(registers::CCBit),
     Addr(u16),
 }
 impl fmt::Display for RegOrAddr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             RegOrAddr::Reg(r) => write!(f, "{:?}", r),
+            RegOrAddr::Flag(b) => write!(f, "CC.{}", b.short()),
             RegOrAddr::Addr(a) => write!(f, "${:04X}", a),
         }
     }
@@ -96,6 +99,7 @@ impl TestCriterion {
                 lhs_size = registers::reg_size(*reg);
                 core.reg.get_register(*reg)
             }
+            RegOrAddr::Flag(bit) => u8u16::u8(core.reg.cc.is_set(*bit) as u8),
             RegOrAddr::Addr(addr) => {
                 if let AddrOrVal::Val(val) = rhs {
                     lhs_size = val.size();
@@ -0,0 +1,231 @@
+//! Programmable timer cartridge.
+//!
+//! A synthetic peripheral (there was never such a cartridge) that gives programs a periodic
+//! interrupt that doesn't depend on video timing, e.g. for music players or for learning how
+//! interrupt handlers work. It's enabled with --timer and has four registers starting at its
+//! base address (--timer-addr, $FF54 by default):
+//! ```text
+//! base+0  write: control
+//!           bit 0  run
+//!           bit 1  interrupt when the count reaches zero
+//!           bit 2  interrupt with FIRQ (through the cartridge's CART line) instead of IRQ
+//!           bit 3  one-shot: stop at zero instead of reloading the period and counting again
+//!         read: status (the control bits, plus bit 7 set if the count has reached zero since
+//!           the status was last read); reading the status acknowledges the interrupt
+//! base+1  period, high byte
+//! base+2  period, low byte; writing it loads the counter (a period of 0 means 65536)
+//! base+3  prescaler: the counter counts down once every n+1 CPU cycles
+//! ```
+//! Reading base+1 returns the high byte of the current count and latches the low byte, which
+//! base+2 then returns. The timer's interrupt is only noticed at the next hsync (like the
+//! other interrupt sources), so it's as precise as a scan line.
+use super::*;
+use cart::{Cartridge, Line};
+use std::cell::Cell;
+
+const RUN: u8 = 0x01;
+const INTERRUPT: u8 = 0x02;
+const FIRQ: u8 = 0x04;
+const ONE_SHOT: u8 = 0x08;
+/// status bit that's set when the count has reached zero
+const REACHED: u8 = 0x80;
+
+pub struct Timer {
+    pub base: u16,
+    control: Cell<u8>,
+    period: Cell<u16>,
+    /// the high byte of the period until the low byte is written
+    period_high: Cell<u8>,
+    prescale: Cell<u8>,
+    /// the cycle at which the counter was last loaded with the period
+    start: Cell<u64>,
+    /// the count while the timer isn't running
+    held: Cell<u32>,
+    reached: Cell<bool>,
+    /// the low byte of the count latched by reading the high byte
+    latch: Cell<u8>,
+}
+impl Timer {
+    pub fn new(base: u16) -> Self {
+        info!("timer cartridge enabled at {:04X}", base);
+        Timer {
+            base,
+            control: Cell::new(0),
+            period: Cell::new(0),
+            period_high: Cell::new(0),
+            prescale: Cell::new(0),
+            start: Cell::new(0),
+            held: Cell::new(0x10000),
+            reached: Cell::new(false),
+            latch: Cell::new(0),
+        }
+    }
+    /// the number of counts in a period
+    fn ticks(&self) -> u32 {
+        match self.period.get() {
+            0 => 0x10000,
+            period => period as u32,
+        }
+    }
+    /// the number of cycles per count
+    fn cycles_per_tick(&self) -> u64 { self.prescale.get() as u64 + 1 }
+    /// Brings the counter up to date with the clock
+    fn update(&self, cycle: u64) {
+        if self.control.get() & RUN == 0 {
+            return;
+        }
+        let span = self.ticks() as u64 * self.cycles_per_tick();
+        let elapsed = cycle.saturating_sub(self.start.get());
+        if elapsed < span {
+            return;
+        }
+        self.reached.set(true);
+        if self.control.get() & ONE_SHOT != 0 {
+            self.control.set(self.control.get() & !RUN);
+            self.held.set(0);
+        } else {
+            self.start.set(self.start.get() + elapsed / span * span);
+        }
+    }
+    /// Returns the current count
+    fn count(&self, cycle: u64) -> u32 {
+        self.update(cycle);
+        if self.control.get() & RUN == 0 {
+            return self.held.get();
+        }
+        let elapsed = cycle.saturating_sub(self.start.get()) / self.cycles_per_tick();
+        self.ticks() - elapsed as u32
+    }
+    /// Returns the interrupt line the timer is pulling (if any)
+    pub fn irq(&self, cycle: u64) -> Option<Line> {
+        self.update(cycle);
+        let control = self.control.get();
+        if !self.reached.get() || control & INTERRUPT == 0 {
+            None
+        } else if control & FIRQ != 0 {
+            Some(Line::Cart)
+        } else {
+            Some(Line::Irq)
+        }
+    }
+    pub fn read(&self, addr: u16, cycle: u64) -> u8 {
+        match addr - self.base {
+            0 => {
+                self.update(cycle);
+                let status = self.control.get() | if self.reached.get() { REACHED } else { 0 };
+                self.reached.set(false);
+                status
+            }
+            1 => {
+                let count = self.count(cycle);
+                self.latch.set(count as u8);
+                (count >> 8) as u8
+            }
+            2 => self.latch.get(),
+            _ => self.prescale.get(),
+        }
+    }
+    pub fn write(&mut self, addr: u16, byte: u8, cycle: u64) {
+        match addr - self.base {
+            0 => {
+                let count = self.count(cycle);
+                let was_running = self.control.get() & RUN != 0;
+                self.control.set(byte & (RUN | INTERRUPT | FIRQ | ONE_SHOT));
+                match (was_running, byte & RUN != 0) {
+                    // counting carries on from where it was held (or starts over if it reached zero)
+                    (false, true) if count == 0 => self.start.set(cycle),
+                    (false, true) => self.start.set(cycle - (self.ticks() - count.min(self.ticks())) as u64),
+                    (true, false) => self.held.set(count),
+                    _ => {}
+                }
+            }
+            1 => self.period_high.set(byte),
+            2 => {
+                self.update(cycle);
+                self.period.set(u16::from_be_bytes([self.period_high.get(), byte]));
+                self.start.set(cycle);
+                self.held.set(self.ticks());
+            }
+            _ => {
+                self.update(cycle);
+                self.prescale.set(byte);
+            }
+        }
+    }
+}
+
+impl Cartridge for Timer {
+    fn name(&self) -> String { format!("timer cartridge at {:04X}", self.base) }
+    fn owns_address(&self, addr: u16) -> bool { addr >= self.base && addr - self.base < 4 }
+    fn read(&self, addr: u16, cycle: u64) -> Option<u8> {
+        self.owns_address(addr).then(|| Timer::read(self, addr, cycle))
+    }
+    fn write(&mut self, addr: u16, byte: u8, cycle: u64) -> Result<bool, Error> {
+        let owned = self.owns_address(addr);
+        if owned {
+            Timer::write(self, addr, byte, cycle);
+        }
+        Ok(owned)
+    }
+    /// Stops the timer (as the reset line does)
+    fn reset(&mut self) {
+        self.control.set(0);
+        self.reached.set(false);
+        self.held.set(self.ticks());
+    }
+    fn tick(&mut self, cycle: u64) -> Option<Line> { self.irq(cycle) }
+    fn tag(&self) -> [u8; 4] { *b"TIMR" }
+    /// The registers, the cycles since the counter was loaded and the held count
+    fn snapshot(&self, cycle: u64) -> Option<Vec<u8>> {
+        let mut state = vec![self.control.get(), self.period_high.get(), self.prescale.get(), self.latch.get()];
+        state.extend(self.period.get().to_be_bytes());
+        state.push(self.reached.get() as u8);
+        state.extend(cycle.saturating_sub(self.start.get()).to_be_bytes());
+        state.extend(self.held.get().to_be_bytes());
+        Some(state)
+    }
+    fn check_snapshot(&self, state: &[u8]) -> Result<(), Error> { snapshot::check_len(&self.tag(), state, 19) }
+    fn restore(&mut self, state: &[u8], cycle: u64) {
+        self.control.set(state[0]);
+        self.period_high.set(state[1]);
+        self.prescale.set(state[2]);
+        self.latch.set(state[3]);
+        self.period.set(u16::from_be_bytes([state[4], state[5]]));
+        self.reached.set(state[6] != 0);
+        self.start.set(cycle.saturating_sub(u64::from_be_bytes(state[7..15].try_into().unwrap())));
+        self.held.set(u32::from_be_bytes(state[15..19].try_into().unwrap()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countdown() {
+        let mut timer = Timer::new(0xff54);
+        // a period of 100 counts, 4 cycles each
+        timer.write(0xff55, 0, 0);
+        timer.write(0xff56, 100, 0);
+        timer.write(0xff57, 3, 0);
+        assert_eq!((timer.read(0xff55, 10), timer.read(0xff56, 10)), (0, 100));
+        timer.write(0xff54, RUN | INTERRUPT | FIRQ, 1000);
+        assert_eq!(timer.read(0xff55, 1040), 0);
+        assert_eq!(timer.read(0xff56, 1040), 90);
+        assert_eq!(timer.irq(1399), None);
+        assert_eq!(timer.irq(1400), Some(Line::Cart));
+        // reading the status acknowledges the interrupt; the counter reloaded and keeps going
+        assert_eq!(timer.read(0xff54, 1404), REACHED | RUN | INTERRUPT | FIRQ);
+        assert_eq!(timer.irq(1404), None);
+        assert_eq!((timer.read(0xff55, 1404), timer.read(0xff56, 1404)), (0, 99));
+        // a one-shot stops at zero and holds its count
+        timer.write(0xff54, RUN | INTERRUPT | ONE_SHOT, 1500);
+        assert_eq!(timer.irq(2000), Some(Line::Irq));
+        assert_eq!(timer.read(0xff54, 3000), REACHED | INTERRUPT | ONE_SHOT);
+        assert_eq!((timer.read(0xff55, 3000), timer.read(0xff56, 3000)), (0, 0));
+        timer.write(0xff54, RUN, 3100);
+        assert_eq!((timer.read(0xff55, 3140), timer.read(0xff56, 3140)), (0, 90));
+        timer.reset();
+        assert_eq!((timer.read(0xff55, 3200), timer.read(0xff56, 3200)), (0, 100));
+    }
+}
@@ -0,0 +1,96 @@
+//! What the window's title says about the core.
+//!
+//! The title names the program that was loaded (the cartridge, the first --load file, disk or
+//! cassette), says whether warp mode is on and shows the effective clock speed, measured about
+//! twice a second of emulated time. While the debugger has the core stopped the title says so,
+//! and a breakpoint hit makes it flash for a few seconds (see devmgr::TitleStatus), so that with
+//! several instances running it's easy to tell them apart and to notice which one stopped.
+use super::*;
+use devmgr::TitleStatus;
+use std::sync::Arc;
+
+/// the vsyncs between measurements of the clock speed
+const SPEED_FIELDS: u32 = 30;
+
+pub struct Title {
+    status: Arc<TitleStatus>,
+    fields: u32,
+    since: Option<(Instant, u64)>, // when (and at which cycle) the speed was last measured
+}
+impl Title {
+    pub fn new(status: Arc<TitleStatus>) -> Self {
+        let args = &config::ARGS;
+        let program = args.cart.iter().chain(args.load.iter().take(1)).chain(args.disk_images()).chain(&args.cassette);
+        if let Some(name) = program.filter_map(|p| p.file_name()).next() {
+            status.set_program(&name.to_string_lossy());
+        }
+        Title { status, fields: 0, since: None }
+    }
+    /// Measures the clock speed every SPEED_FIELDS vsyncs
+    fn vsync(&mut self, cycle: u64, warp: bool) {
+        self.status.set_warp(warp);
+        self.fields += 1;
+        if self.fields < SPEED_FIELDS && self.since.is_some() {
+            return;
+        }
+        let now = Instant::now();
+        if let Some((then, start)) = self.since {
+            let secs = now.duration_since(then).as_secs_f64();
+            self.status.set_speed(((cycle - start) as f64 / secs / 1000.0).round() as u32);
+        }
+        (self.fields, self.since) = (0, Some((now, cycle)));
+    }
+}
+
+impl Core {
+    /// Updates the title at vsync
+    pub fn title_vsync(&mut self) {
+        let (cycle, warp) = (self.clock_cycles, self.warp);
+        if let Some(title) = self.title.as_mut() {
+            title.vsync(cycle, warp);
+        }
+    }
+    /// Notes that the debugger has stopped the core (breakpoint is true if it hit a breakpoint)
+    pub fn title_stop(&self, breakpoint: bool) {
+        if let Some(title) = self.title.as_ref() {
+            title.status.stop(breakpoint);
+        }
+    }
+    /// Notes that the core is running again (the time it was stopped doesn't count toward its speed)
+    pub fn title_resume(&mut self) {
+        if let Some(title) = self.title.as_mut() {
+            title.status.resume();
+            title.since = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title() {
+        let status = Arc::new(TitleStatus::default());
+        let mut core = Core::headless().unwrap();
+        core.title = Some(Title::new(status.clone()));
+        status.set_program("GAME.CCC");
+        assert_eq!(status.text(0, false, false), "Rusty CoCo - GAME.CCC");
+        // the speed is measured after a while (here flat out, with warp on)
+        core.raw_ram[0x3000..0x3002].copy_from_slice(&[0x20, 0xfe]);
+        (core.reg.pc, core.warp) = (0x3000, true);
+        while core.clock_cycles < 40 * 57 * 262 {
+            core.exec_one().unwrap();
+        }
+        let text = status.text(0, false, false);
+        assert!(text.starts_with("Rusty CoCo - GAME.CCC - warp ") && text.ends_with(" MHz"), "{}", text);
+        assert!(status.text(2, true, false).ends_with(" (showing 1 of 3 frames) (mouse captured; F7 releases it)"));
+        // stops in the debugger
+        core.title_stop(false);
+        assert_eq!(status.text(0, false, true), "Rusty CoCo - GAME.CCC - paused in the debugger");
+        core.title_stop(true);
+        assert_eq!(status.text(0, false, true), "[!] Rusty CoCo - GAME.CCC - stopped at a breakpoint [!]");
+        core.title_resume();
+        assert!(status.text(0, false, false).contains("MHz"));
+    }
+}
@@ -0,0 +1,289 @@
+//! Trace export for waveform and trace viewers.
+//!
+//! With --trace-export the emulator streams what it does to a file that can be explored in
+//! standard tools, which makes timing relationships (e.g., an IRQ vs. the scan line vs. writes
+//! to the DAC) easy to see. The file records:
+//! - every instruction (its address and mnemonic)
+//! - hsync and vsync
+//! - the IRQ and FIRQ lines when they're asserted
+//! - every write to a device register (and the value of the 6-bit DAC)
+//!
+//! Time comes from the emulated clock (core::CPU_HZ) rather than the host's. Two formats are
+//! supported: VCD (Value Change Dump) for waveform viewers like GTKWave and Surfer, and the
+//! JSON trace event format, which Perfetto (ui.perfetto.dev) and chrome://tracing open.
+use super::*;
+use crate::core::CPU_HZ;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// the address of PIA1's data register A (the top six bits drive the DAC)
+const DAC_ADDR: u16 = 0xff20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceFormat {
+    /// Value Change Dump (for waveform viewers)
+    Vcd,
+    /// JSON trace events (for Perfetto and chrome://tracing)
+    Perfetto,
+}
+impl TraceFormat {
+    /// Picks the format from the file's extension (.vcd or anything else for Perfetto)
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(OsStr::to_str) {
+            Some(ext) if ext.eq_ignore_ascii_case("vcd") => TraceFormat::Vcd,
+            _ => TraceFormat::Perfetto,
+        }
+    }
+}
+
+/// VCD identifiers of the signals
+mod sig {
+    pub const PC: char = '!';
+    pub const HSYNC: char = '"';
+    pub const VSYNC: char = '#';
+    pub const IRQ: char = '$';
+    pub const FIRQ: char = '%';
+    pub const DAC: char = '&';
+    pub const IO_ADDR: char = '\'';
+    pub const IO_DATA: char = '(';
+}
+/// Perfetto thread ids (one track for each)
+mod track {
+    pub const CPU: u32 = 1;
+    pub const VIDEO: u32 = 2;
+    pub const INTERRUPTS: u32 = 3;
+    pub const IO: u32 = 4;
+}
+
+pub struct TraceFile {
+    format: TraceFormat,
+    out: BufWriter<File>,
+    /// the time (in ns) of the last VCD timestamp written
+    time: Option<u64>,
+    /// single-bit VCD signals that were raised and have to be lowered at the next timestamp
+    pulses: Vec<char>,
+    /// the number of Perfetto events written
+    events: u64,
+}
+impl TraceFile {
+    pub fn create(path: &Path, format: TraceFormat) -> Result<Self, Error> {
+        let mut trace = TraceFile {
+            format,
+            out: BufWriter::new(File::create(path)?),
+            time: None,
+            pulses: Vec::new(),
+            events: 0,
+        };
+        trace.header()?;
+        info!("writing a {:?} trace to {}", format, path.display());
+        Ok(trace)
+    }
+    /// Creates the trace file named by --trace-export (if any; kiosk mode never writes files)
+    pub fn from_args() -> Result<Option<Self>, Error> {
+        let Some(path) = config::ARGS.trace_export.as_ref().filter(|_| !config::kiosk()) else {
+            return Ok(None);
+        };
+        let format = config::ARGS.trace_format.unwrap_or_else(|| TraceFormat::from_path(path));
+        Ok(Some(TraceFile::create(path, format)?))
+    }
+    fn header(&mut self) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Vcd => {
+                writeln!(self.out, "$version coco {} $end", env!("CARGO_PKG_VERSION"))?;
+                writeln!(self.out, "$timescale 1 ns $end")?;
+                writeln!(self.out, "$scope module coco $end")?;
+                for (bits, id, name) in [
+                    (16, sig::PC, "pc"),
+                    (1, sig::HSYNC, "hsync"),
+                    (1, sig::VSYNC, "vsync"),
+                    (1, sig::IRQ, "irq"),
+                    (1, sig::FIRQ, "firq"),
+                    (6, sig::DAC, "dac"),
+                    (16, sig::IO_ADDR, "io_addr"),
+                    (8, sig::IO_DATA, "io_data"),
+                ] {
+                    writeln!(self.out, "$var wire {} {} {} $end", bits, id, name)?;
+                }
+                writeln!(self.out, "$upscope $end\n$enddefinitions $end")?;
+                // the single-bit signals start low; the others are unknown until they're first set
+                self.time = Some(0);
+                writeln!(self.out, "#0\n0\"\n0#\n0$\n0%")
+            }
+            TraceFormat::Perfetto => {
+                write!(self.out, "{{\"traceEvents\":[")?;
+                self.event(r#""name":"process_name","ph":"M","pid":1,"args":{"name":"coco"}"#)?;
+                for (tid, name) in [
+                    (track::CPU, "6809"),
+                    (track::VIDEO, "video"),
+                    (track::INTERRUPTS, "interrupts"),
+                    (track::IO, "I/O"),
+                ] {
+                    self.event(&format!(
+                        r#""name":"thread_name","ph":"M","pid":1,"tid":{},"args":{{"name":"{}"}}"#,
+                        tid, name
+                    ))?;
+                }
+                Ok(())
+            }
+        }
+    }
+    /// Converts a clock cycle to ns (VCD)
+    fn ns(cycle: u64) -> u64 { (cycle as u128 * 1_000_000_000 / CPU_HZ as u128) as u64 }
+    /// Converts a clock cycle to µs (Perfetto)
+    fn us(cycle: u64) -> f64 { cycle as f64 * 1e6 / CPU_HZ as f64 }
+    /// Starts a VCD timestamp (lowering the pulses raised at the previous one)
+    fn at(&mut self, cycle: u64) -> io::Result<()> {
+        let ns = Self::ns(cycle);
+        if self.time.is_some_and(|t| t >= ns) {
+            return Ok(());
+        }
+        writeln!(self.out, "#{}", ns)?;
+        self.time = Some(ns);
+        for id in std::mem::take(&mut self.pulses) {
+            writeln!(self.out, "0{}", id)?;
+        }
+        Ok(())
+    }
+    /// Raises a single-bit VCD signal until the next timestamp
+    fn pulse(&mut self, id: char) -> io::Result<()> {
+        if !self.pulses.contains(&id) {
+            writeln!(self.out, "1{}", id)?;
+            self.pulses.push(id);
+        }
+        Ok(())
+    }
+    /// Writes the body of a Perfetto event
+    fn event(&mut self, body: &str) -> io::Result<()> {
+        let sep = if self.events == 0 { "\n" } else { ",\n" };
+        self.events += 1;
+        write!(self.out, "{}{{{}}}", sep, body)
+    }
+    /// Records an instruction that ran from clock cycle start to end
+    pub fn instruction(&mut self, start: u64, end: u64, pc: u16, name: &str) -> Result<(), Error> {
+        match self.format {
+            TraceFormat::Vcd => {
+                self.at(start)?;
+                writeln!(self.out, "b{:b} {}", pc, sig::PC)?;
+            }
+            TraceFormat::Perfetto => self.event(&format!(
+                r#""name":"{}","ph":"X","ts":{:.3},"dur":{:.3},"pid":1,"tid":{},"args":{{"pc":"{:04X}"}}"#,
+                name,
+                Self::us(start),
+                Self::us(end) - Self::us(start),
+                track::CPU,
+                pc
+            ))?,
+        }
+        Ok(())
+    }
+    /// Records hsync (and vsync) along with the interrupt lines asserted at that time
+    pub fn sync(&mut self, cycle: u64, vsync: bool, irq: bool, firq: bool) -> Result<(), Error> {
+        let signals = [
+            (true, sig::HSYNC, "hsync", track::VIDEO),
+            (vsync, sig::VSYNC, "vsync", track::VIDEO),
+            (irq, sig::IRQ, "IRQ", track::INTERRUPTS),
+            (firq, sig::FIRQ, "FIRQ", track::INTERRUPTS),
+        ];
+        for (_, id, name, tid) in signals.into_iter().filter(|s| s.0) {
+            match self.format {
+                TraceFormat::Vcd => {
+                    self.at(cycle)?;
+                    self.pulse(id)?;
+                }
+                TraceFormat::Perfetto => self.event(&format!(
+                    r#""name":"{}","ph":"i","s":"t","ts":{:.3},"pid":1,"tid":{}"#,
+                    name,
+                    Self::us(cycle),
+                    tid
+                ))?,
+            }
+        }
+        Ok(())
+    }
+    /// Records a write to a device register
+    pub fn io_write(&mut self, cycle: u64, pc: u16, addr: u16, val: u8) -> Result<(), Error> {
+        let ts = Self::us(cycle);
+        match self.format {
+            TraceFormat::Vcd => {
+                self.at(cycle)?;
+                writeln!(self.out, "b{:b} {}\nb{:b} {}", addr, sig::IO_ADDR, val, sig::IO_DATA)?;
+                if addr == DAC_ADDR {
+                    writeln!(self.out, "b{:b} {}", val >> 2, sig::DAC)?;
+                }
+            }
+            TraceFormat::Perfetto => {
+                let args = format!(r#"{{"val":"{:02X}","pc":"{:04X}"}}"#, val, pc);
+                self.event(&format!(
+                    r#""name":"{:04X}","ph":"i","s":"t","ts":{:.3},"pid":1,"tid":{},"args":{}"#,
+                    addr,
+                    ts,
+                    track::IO,
+                    args
+                ))?;
+                if addr == DAC_ADDR {
+                    let dac = val >> 2;
+                    self.event(&format!(r#""name":"DAC","ph":"C","ts":{:.3},"pid":1,"args":{{"dac":{}}}"#, ts, dac))?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Completes the file
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if self.format == TraceFormat::Perfetto {
+            write!(self.out, "\n]}}")?;
+        }
+        self.out.flush()?;
+        info!("trace complete");
+        Ok(())
+    }
+}
+
+impl Core {
+    /// Adds a write to the trace if it's a write to a device register
+    pub fn trace_io_write(&mut self, addr: u16, val: u8) -> Result<(), Error> {
+        if !self.is_io_address(addr) {
+            return Ok(());
+        }
+        let (cycle, pc) = (self.clock_cycles, self.reg.pc);
+        match self.trace_file.as_mut() {
+            Some(trace) => trace.io_write(cycle, pc, addr, val),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let dir = std::env::temp_dir();
+        let read = |name: &str, format: TraceFormat| {
+            let path = dir.join(format!("coco-trace-{}-{}", std::process::id(), name));
+            let mut trace = TraceFile::create(&path, format).unwrap();
+            trace.instruction(0, 2, 0x1000, "LDA").unwrap();
+            trace.io_write(2, 0x1000, DAC_ADDR, 0xfc).unwrap();
+            trace.sync(57, true, true, false).unwrap();
+            trace.instruction(57, 60, 0x1002, "NOP").unwrap();
+            trace.finish().unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            text
+        };
+        let vcd = read("a.vcd", TraceFormat::Vcd);
+        assert!(vcd.contains("$var wire 6 & dac $end") && vcd.contains("$enddefinitions $end"));
+        assert!(vcd.contains("#0\n0\"\n0#\n0$\n0%\nb1000000000000 !\n#2234\n"), "{}", vcd);
+        assert!(vcd.contains("#2234\nb1111111100100000 '\nb11111100 (\nb111111 &\n"), "{}", vcd);
+        assert!(vcd.contains("#63695\n1\"\n1#\n1$\nb1000000000010 !\n"), "{}", vcd);
+        let json = read("a.json", TraceFormat::Perfetto);
+        assert!(json.starts_with("{\"traceEvents\":[\n{") && json.ends_with("}\n]}"));
+        assert!(json.contains(r#""name":"LDA","ph":"X","ts":0.000,"dur":2.235,"pid":1,"tid":1,"args":{"pc":"1000"}"#));
+        assert!(json.contains(r#""name":"DAC","ph":"C","ts":2.235,"pid":1,"args":{"dac":63}"#));
+        assert_eq!(json.matches("\"ph\":\"i\"").count(), 4);
+        assert_eq!(TraceFormat::from_path(Path::new("x.VCD")), TraceFormat::Vcd);
+        assert_eq!(TraceFormat::from_path(Path::new("x.json")), TraceFormat::Perfetto);
+    }
+}
@@ -1,5 +1,15 @@
 use std::fmt;
 
+/// The condition codes that an arithmetic operation on a u8u16 produces (as the 6809 sets them)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub n: bool, // the sign bit of the result is set
+    pub z: bool, // the result is zero
+    pub v: bool, // the result doesn't fit in a signed value of this size
+    pub c: bool, // carry out of the top bit (or borrow into it when subtracting)
+    pub h: bool, // carry out of bit 3 (only meaningful when adding 8-bit values)
+}
+
 /// u8u16 is a helper enum for dealing with values that may be either u8 or u16.
 /// It allows code that cares only about the value to focus on the value while
 /// code that cares about the size of the type can also get clear size information.
@@ -143,4 +153,118 @@ impl u8u16 {
             _ => u8u16::u16(self.u16() % rhs.u16()),
         }
     }
+    /// Returns a value the same size as self holding the low bits of val
+    fn same_size(&self, val: u32) -> Self {
+        match self {
+            u8u16::u8(_) => u8u16::u8(val as u8),
+            u8u16::u16(_) => u8u16::u16(val as u16),
+        }
+    }
+    fn sign_mask(&self) -> u32 { if self.is_u8() { 0x80 } else { 0x8000 } }
+    /// Returns the flags for result where raw is the result before it was cut down to size
+    fn flags_for(&self, raw: u32, v: bool) -> Flags {
+        let sign = self.sign_mask();
+        Flags {
+            n: raw & sign != 0,
+            z: raw & (sign * 2 - 1) == 0,
+            v,
+            c: raw & (sign * 2) != 0,
+            h: false,
+        }
+    }
+    /// Adds rhs (and the carry) and returns the result, the same size as self, and its flags
+    pub fn overflowing_add(self, rhs: Self, carry: bool) -> (Self, Flags) {
+        let (a, b) = (self.u16() as u32, rhs.u16() as u32 & (self.sign_mask() * 2 - 1));
+        let raw = a + b + carry as u32;
+        let mut flags = self.flags_for(raw, (a ^ raw) & (b ^ raw) & self.sign_mask() != 0);
+        flags.h = (a ^ b ^ raw) & 0x10 != 0;
+        (self.same_size(raw), flags)
+    }
+    /// Subtracts rhs (and the borrow) and returns the result, the same size as self, and its flags
+    pub fn overflowing_sub(self, rhs: Self, borrow: bool) -> (Self, Flags) {
+        let (a, b) = (self.u16() as u32, rhs.u16() as u32 & (self.sign_mask() * 2 - 1));
+        let raw = a.wrapping_sub(b).wrapping_sub(borrow as u32);
+        let flags = self.flags_for(raw, (a ^ b) & (a ^ raw) & self.sign_mask() != 0);
+        (self.same_size(raw), flags)
+    }
+    /// Returns the flags for self - rhs (as CMP sets them)
+    pub fn compare(self, rhs: Self) -> Flags { self.overflowing_sub(rhs, false).1 }
+    /// Adds rhs; returns None if the unsigned result doesn't fit in self's size
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        let (result, flags) = self.overflowing_add(rhs, false);
+        (!flags.c).then_some(result)
+    }
+    /// Subtracts rhs; returns None if the unsigned result would be negative
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        let (result, flags) = self.overflowing_sub(rhs, false);
+        (!flags.c).then_some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn flags() {
+        use super::*;
+        // the flags as the 6809 datasheet defines them, worked out with signed and unsigned arithmetic
+        fn expected(a: i32, b: i32, carry: i32, add: bool, bits: u32) -> (u32, [bool; 5]) {
+            let (size, half) = (1i32 << bits, 1i32 << (bits - 1));
+            let signed = |x: i32| if x >= half { x - size } else { x };
+            let (unsigned, signed_result) = if add {
+                (a + b + carry, signed(a) + signed(b) + carry)
+            } else {
+                (a - b - carry, signed(a) - signed(b) - carry)
+            };
+            let result = unsigned.rem_euclid(size) as u32;
+            let n = result & half as u32 != 0;
+            let z = result == 0;
+            let v = !(-half..half).contains(&signed_result);
+            let c = !(0..size).contains(&unsigned);
+            let h = add && (a & 0xf) + (b & 0xf) + carry > 0xf;
+            (result, [n, z, v, c, h])
+        }
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                for carry in [false, true] {
+                    for add in [true, false] {
+                        let (result, f) = if add {
+                            u8u16::u8(a).overflowing_add(u8u16::u8(b), carry)
+                        } else {
+                            u8u16::u8(a).overflowing_sub(u8u16::u8(b), carry)
+                        };
+                        let (want, flags) = expected(a as i32, b as i32, carry as i32, add, 8);
+                        assert_eq!(result, u8u16::u8(want as u8));
+                        assert_eq!([f.n, f.z, f.v, f.c, f.h && add], flags, "{:02X} {} {:02X} {}", a, add, b, carry);
+                    }
+                }
+            }
+        }
+        let edges: Vec<u16> = [0u16, 1, 0x7f, 0x80, 0xff, 0x100, 0x7ffe, 0x7fff, 0x8000, 0x8001, 0xfffe, 0xffff]
+            .into_iter()
+            .chain((0..=0xffffu32).step_by(0x1235).map(|w| w as u16))
+            .collect();
+        for &a in &edges {
+            for &b in &edges {
+                for (carry, add) in [(false, true), (true, true), (false, false), (true, false)] {
+                    let (result, f) = if add {
+                        u8u16::u16(a).overflowing_add(u8u16::u16(b), carry)
+                    } else {
+                        u8u16::u16(a).overflowing_sub(u8u16::u16(b), carry)
+                    };
+                    let (want, flags) = expected(a as i32, b as i32, carry as i32, add, 16);
+                    assert_eq!(result, u8u16::u16(want as u16));
+                    assert_eq!([f.n, f.z, f.v, f.c], flags[..4], "{:04X} {} {:04X} {}", a, add, b, carry);
+                }
+            }
+        }
+        // a few rows from the datasheet's point of view
+        let f = u8u16::u8(0x7f).overflowing_add(u8u16::u8(0xff), true).1;
+        assert!(!f.v && f.c && f.h);
+        let f = u8u16::u8(0x80).overflowing_sub(u8u16::u8(0x01), false).1;
+        assert!(f.v && !f.c && !f.n);
+        assert_eq!(u8u16::u8(0x10).compare(u8u16::u8(0x20)), Flags { n: true, c: true, ..Default::default() });
+        assert_eq!(u8u16::u8(0xff).checked_add(u8u16::u8(1)), None);
+        assert_eq!(u8u16::u16(0x00ff).checked_add(u8u16::u8(1)), Some(u8u16::u16(0x100)));
+        assert_eq!(u8u16::u8(1).checked_sub(u8u16::u8(2)), None);
+    }
 }
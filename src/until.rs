@@ -0,0 +1,144 @@
+//! Conditions that end a run, for running programs unattended (e.g., in CI).
+//!
+//! Besides --time, a run can be ended when the program counter reaches an address or symbol
+//! (--until-pc), when a byte of memory holds a value (--until-mem), when the text screen shows
+//! some text (--until-screen) or after a number of frames (--until-frames). Each option can be
+//! given more than once and the run ends as soon as any of the conditions holds; coco reports
+//! which one it was. The PC and memory are checked after every instruction and the screen and
+//! frame count once a frame (at vsync).
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Pc(u16),
+    Memory(u16, u8),
+    Screen(String),
+    Frames(u64),
+}
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::Pc(addr) => write!(f, "PC reached {:04X}", addr),
+            Condition::Memory(addr, value) => write!(f, "{:04X} holds {:02X}", addr, value),
+            Condition::Screen(text) => write!(f, "the screen shows \"{}\"", text),
+            Condition::Frames(frames) => write!(f, "{} frames have passed", frames),
+        }
+    }
+}
+
+pub struct Until {
+    conditions: Vec<Condition>,
+    frames: u64,              // vsyncs since the run started
+    fired: Option<Condition>, // the condition that ended the run
+}
+impl Until {
+    pub fn new(conditions: Vec<Condition>) -> Self { Until { conditions, frames: 0, fired: None } }
+    /// Returns the condition that ended the run (if one has)
+    #[allow(unused)]
+    pub fn fired(&self) -> Option<&Condition> { self.fired.as_ref() }
+}
+
+/// Parses an --until-mem condition (<address>=<value>), using addr_of to work out the address
+/// and the value
+fn parse_memory(spec: &str, addr_of: impl Fn(&str) -> Option<u16>) -> Result<Condition, Error> {
+    let (loc, value) = spec.split_once('=').ok_or_else(|| general_err!("expected <address>=<value>"))?;
+    let addr = addr_of(loc.trim()).ok_or_else(|| general_err!("invalid address \"{}\"", loc.trim()))?;
+    match addr_of(value.trim()) {
+        Some(value @ 0..=0xff) => Ok(Condition::Memory(addr, value as u8)),
+        _ => Err(general_err!("invalid byte \"{}\"", value.trim())),
+    }
+}
+
+impl Core {
+    /// Sets up the conditions given on the command line (if any)
+    pub fn until_from_args(&mut self) -> Result<(), Error> {
+        let args = &config::ARGS;
+        let mut conditions = Vec::new();
+        for loc in &args.until_pc {
+            let addr = self.parse_address(loc).ok_or_else(|| general_err!("--until-pc: invalid address \"{}\"", loc))?;
+            conditions.push(Condition::Pc(addr));
+        }
+        for spec in &args.until_mem {
+            let condition = parse_memory(spec, |s| self.parse_address(s));
+            conditions.push(condition.map_err(|e| general_err!("--until-mem {}: {}", spec, e))?);
+        }
+        conditions.extend(args.until_screen.iter().map(|text| Condition::Screen(text.clone())));
+        conditions.extend(args.until_frames.map(Condition::Frames));
+        self.until = (!conditions.is_empty()).then(|| Until::new(conditions));
+        Ok(())
+    }
+    /// Counts a frame and checks the conditions that are checked once a frame
+    pub fn until_vsync(&mut self) {
+        let Some(until) = self.until.as_mut() else {
+            return;
+        };
+        until.frames += 1;
+        if until.fired.is_some() {
+            return;
+        }
+        let until = self.until.as_ref().unwrap();
+        let fired = until
+            .conditions
+            .iter()
+            .find(|c| match c {
+                Condition::Screen(text) => self.screen_shows(text),
+                Condition::Frames(n) => until.frames >= *n,
+                _ => false,
+            })
+            .cloned();
+        self.until.as_mut().unwrap().fired = fired;
+    }
+    /// Returns the condition that should end the run now (if any); called after each instruction
+    pub fn until_check(&mut self) -> Option<Condition> {
+        let until = self.until.as_mut()?;
+        if until.fired.is_none() {
+            let (pc, ram) = (self.reg.pc, &self.raw_ram);
+            until.fired = until
+                .conditions
+                .iter()
+                .find(|c| match c {
+                    Condition::Pc(addr) => pc == *addr,
+                    Condition::Memory(addr, value) => ram[*addr as usize] == *value,
+                    _ => false,
+                })
+                .cloned();
+        }
+        until.fired.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_until() {
+        let addr_of = |s: &str| if s == "FLAG" { Some(0x3100) } else { u16::from_str_radix(s, 16).ok() };
+        assert_eq!(parse_memory("FLAG = ff", addr_of).unwrap(), Condition::Memory(0x3100, 0xff));
+        assert!(parse_memory("FLAG=100", addr_of).is_err());
+        assert!(parse_memory("FLAG", addr_of).is_err());
+
+        let mut core = Core::headless().unwrap();
+        // $3000: inc $3100 / bra $3000
+        core.raw_ram[0x3100] = 0;
+        core.raw_ram[0x3000..0x3005].copy_from_slice(&[0x7c, 0x31, 0x00, 0x20, 0xfb]);
+        core.reg.pc = 0x3000;
+        core.warp = true;
+        core.until = Some(Until::new(vec![Condition::Frames(2), Condition::Memory(0x3100, 3)]));
+        let fired = loop {
+            core.exec_one().unwrap();
+            if let Some(condition) = core.until_check() {
+                break condition;
+            }
+        };
+        assert_eq!(fired, Condition::Memory(0x3100, 3));
+        assert_eq!(fired.to_string(), "3100 holds 03");
+        assert_eq!(core.reg.pc, 0x3003);
+
+        core.until = Some(Until::new(vec![Condition::Frames(2), Condition::Pc(0x4000)]));
+        while core.until_check().is_none() {
+            core.exec_one().unwrap();
+        }
+        assert_eq!(core.until.as_ref().unwrap().fired(), Some(&Condition::Frames(2)));
+    }
+}
@@ -117,6 +117,7 @@ impl VdgMode {
         }
     }
 }
+use crate::error::{Error, ErrorKind};
 use std::{
     sync::{Arc, RwLock},
     time::Duration,
@@ -178,6 +179,38 @@ pub const BLOCK_ROWS: usize = SCREEN_DIM_Y / BLOCK_DIM_Y;
 pub const VRAM_SIZE: usize = (SCREEN_DIM_X * SCREEN_DIM_Y) / 8;
 pub const ALWAYS_RENDER: bool = true;
 
+/// Returns a picture (pixels as 0RGB) as a PPM image
+pub fn ppm(pixels: &[u32], width: usize, height: usize) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for p in pixels {
+        data.extend([(p >> 16) as u8, (p >> 8) as u8, *p as u8]);
+    }
+    data
+}
+
+/// Returns the character a byte of text screen memory shows: $40-$7F are the normal
+/// characters (as Color BASIC writes them), $00-$3F the inverted ones (BASIC's lowercase, which
+/// are returned as lowercase letters) and $80-$FF semigraphics blocks (a space if the block is
+/// dark and '█' otherwise)
+pub fn screen_char(byte: u8) -> char {
+    match byte {
+        0x00..=0x1f => (byte + 0x40).to_ascii_lowercase() as char,
+        0x20..=0x3f => byte as char,
+        0x40..=0x5f => byte as char,
+        0x60..=0x7f => (byte - 0x40) as char,
+        _ if byte & 0x0f == 0 => ' ',
+        _ => '█',
+    }
+}
+/// Returns the characters on a 32x16 text screen from its 512 bytes of memory
+pub fn text_grid(vram: &[u8]) -> [[char; BLOCK_COLS]; BLOCK_ROWS] {
+    let mut grid = [[' '; BLOCK_COLS]; BLOCK_ROWS];
+    for (i, &byte) in vram.iter().take(BLOCK_COLS * BLOCK_ROWS).enumerate() {
+        grid[i / BLOCK_COLS][i % BLOCK_COLS] = screen_char(byte);
+    }
+    grid
+}
+
 pub struct Char {
     font_index: usize,
     inverted: bool,
@@ -242,14 +275,12 @@ impl Vdg {
     }
     #[allow(unused)]
     pub fn interpret_chars_as_ascii(&mut self, ascii: bool) { self.ascii = ascii; }
-    pub fn set_vram_offset(&mut self, vram_offset: usize) {
+    /// Sets where VRAM starts; an offset that would put the end of VRAM past the end of RAM is
+    /// refused (and the old offset kept)
+    pub fn set_vram_offset(&mut self, vram_offset: usize) -> Result<(), Error> {
         if (vram_offset + VRAM_SIZE) > self.ram.len() {
-            panic!(
-                "invalid vram_offset {:x} (ram.len={:x}, VRAM_SIZE={:x}",
-                vram_offset,
-                self.ram.len(),
-                VRAM_SIZE
-            )
+            let msg = format!("VRAM can't start at {:04X} (RAM ends at {:04X})", vram_offset, self.ram.len());
+            return Err(Error::new(ErrorKind::Device, None, &msg));
         }
         if vram_offset != self.vram_offset {
             info!(
@@ -259,9 +290,13 @@ impl Vdg {
             self.vram_offset = vram_offset;
             self.dirty = true;
         }
+        Ok(())
     }
     #[allow(unused)]
     pub fn get_mode(&self) -> VdgMode { self.mode }
+    /// Returns the characters on the screen (as it would look in a text mode; see text_grid)
+    #[allow(unused)]
+    pub fn text(&self) -> [[char; BLOCK_COLS]; BLOCK_ROWS] { text_grid(&self.ram[self.vram_offset..]) }
 
     #[allow(unused)]
     pub fn set_dirty(&mut self) { self.dirty = true }
@@ -456,7 +491,7 @@ impl Vdg {
         }
     }
 }
-const FONT_MAP: &[u8] = &[
+pub(crate) const FONT_MAP: &[u8] = &[
     0x00, 0x00, 0x00, 0x1C, 0x22, 0x2A, 0x2A, 0x2C, 0x20, 0x1E, 0x00, 0x00, // @
     0x00, 0x00, 0x00, 0x08, 0x14, 0x22, 0x22, 0x3E, 0x22, 0x22, 0x00, 0x00, // A
     0x00, 0x00, 0x00, 0x3C, 0x22, 0x22, 0x3C, 0x22, 0x22, 0x3C, 0x00, 0x00, //
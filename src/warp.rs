@@ -0,0 +1,219 @@
+//! Timing hsync and vsync by the emulated clock.
+//!
+//! hsync and vsync are scheduled by counting cycles: one hsync every 57 cycles and one vsync
+//! every 262 lines, just like the real hardware. Each hsync is asserted at a precise cycle within
+//! its scan line and is taken at the first instruction boundary after that, so a run is the same
+//! every time and timing loops, interrupt-driven timekeeping (BASIC's TIMER, clocks in games,
+//! etc.) and the program all stay in step however fast the host is. Time spent waiting in SYNC
+//! or CWAI is skipped. On a real machine the latency varies from line to line, which
+//! --sync-jitter models by asserting each hsync a random number of cycles (up to the given
+//! limit) into its line.
+//!
+//! The host's clock is only used to hold the CPU to its speed (0.89 MHz or --mhz): at each hsync
+//! the Pacer waits until the host has caught up with the emulated clock. Pacing every scan line
+//! rather than every field keeps the DAC's writes (which the sound thread times by the host's
+//! clock) evenly spread; `--accuracy fast` paces every field instead, which is cheaper but bunches
+//! the sound up. In warp mode (fast-forward) there's no pacing and the CPU runs as fast
+//! as it can.
+//!
+//! vsync comes from counting scan lines so that every field has the video standard's number of
+//! hsyncs: 262 for NTSC (about 60 fields a second) and 312 for PAL machines (about 50).
+use super::*;
+use crate::core::CPU_HZ;
+use std::ops::Range;
+use std::time::Duration;
+
+/// CPU cycles per scan line (hsync)
+pub const HSYNC_CYCLES: u64 = 57;
+/// The number of lines the VDG displays
+const ACTIVE_LINES: u64 = 192;
+/// How far the host can fall behind (in the debugger, say) before the Pacer gives up catching up
+const MAX_LAG: Duration = Duration::from_millis(50);
+/// Waits shorter than this are spun rather than slept (sleeping isn't that precise)
+const MIN_SLEEP: Duration = Duration::from_millis(2);
+
+/// The video standard the machine was built for (--video)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum VideoStandard {
+    #[default]
+    Ntsc,
+    Pal,
+}
+impl VideoStandard {
+    /// Returns the number of scan lines per field (one vsync)
+    pub fn lines_per_field(&self) -> u64 {
+        match self {
+            VideoStandard::Ntsc => 262,
+            VideoStandard::Pal => 312,
+        }
+    }
+    /// Returns the scan lines (counting from vsync) on which the VDG displays its active area.
+    /// The VDG's field sync (vsync) comes at the end of the active area so these are the last
+    /// lines of each field; PAL machines add their extra lines to the borders and blanking.
+    pub fn active_lines(&self) -> Range<u64> { self.lines_per_field() - ACTIVE_LINES..self.lines_per_field() }
+}
+impl fmt::Display for VideoStandard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            VideoStandard::Ntsc => "NTSC",
+            VideoStandard::Pal => "PAL",
+        };
+        let (lines, active) = (self.lines_per_field(), self.active_lines());
+        write!(f, "{}, {} lines per field, active lines {}-{}", name, lines, active.start, active.end - 1)
+    }
+}
+
+/// How much speed is traded for fidelity (--accuracy)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Accuracy {
+    Fast,
+    #[default]
+    Balanced,
+    Accurate,
+}
+impl Accuracy {
+    /// Returns true if the CPU is held to its speed every scan line (rather than every field)
+    pub fn pace_lines(&self) -> bool { *self != Accuracy::Fast }
+    /// Returns true if frames can go unrendered when the host falls behind
+    pub fn frame_skip(&self) -> bool { *self != Accuracy::Accurate }
+    /// Returns true if the PIAs follow the data sheet (see --pia-strict)
+    pub fn strict_pias(&self) -> bool { *self == Accuracy::Accurate }
+}
+
+/// Times hsync and vsync on the emulated clock
+#[derive(Debug, Default)]
+pub struct VirtualSync {
+    line_start: u64, // the clock cycle at which the current scan line started
+    next_hsync: u64, // the clock cycle at which the next hsync is asserted
+    line: u64,       // the current scan line
+    lines: u64,      // scan lines per field
+    jitter: u64,     // the largest offset into a line at which hsync is asserted
+}
+impl VirtualSync {
+    pub fn new(jitter: u64, standard: VideoStandard) -> Self {
+        VirtualSync {
+            // anything later would be indistinguishable from falling behind by a line
+            jitter: jitter.min(HSYNC_CYCLES / 2),
+            lines: standard.lines_per_field(),
+            ..Default::default()
+        }
+    }
+    /// Moves on to the next scan line (at hsync) and returns true if it starts a new field (vsync)
+    pub fn next_line(&mut self) -> bool {
+        self.line = (self.line + 1) % self.lines;
+        self.line == 0
+    }
+    /// Returns the cycle at which the next hsync is due
+    pub fn next_hsync(&self) -> u64 { self.next_hsync }
+    /// Returns (hsync, vsync) to say which syncs are due at the given cycle
+    pub fn poll(&mut self, cycle: u64, rng: &mut rng::Rng) -> (bool, bool) {
+        if cycle < self.next_hsync {
+            return (false, false);
+        }
+        // stay on the grid unless we've fallen a whole line behind (e.g., warp was just turned on)
+        let base = if cycle - self.line_start < HSYNC_CYCLES { self.line_start } else { cycle };
+        self.line_start = base + HSYNC_CYCLES;
+        self.next_hsync = self.line_start;
+        if self.jitter > 0 {
+            self.next_hsync += rng.next_u64() % (self.jitter + 1);
+        }
+        (true, self.next_line())
+    }
+}
+
+/// Holds the emulated clock to the host's
+#[derive(Debug)]
+pub struct Pacer {
+    hz: f64,
+    start: Option<(Instant, u64)>, // the host's time at a cycle that's on schedule
+    pub lines: bool,               // pace every scan line (rather than every field)
+}
+impl Pacer {
+    pub fn new(mhz: Option<f32>, lines: bool) -> Self {
+        Pacer { hz: mhz.map_or(CPU_HZ as f64, |m| m as f64 * 1e6), start: None, lines }
+    }
+    /// Returns how long to wait at the given cycle (and time) for the host to catch up
+    fn wait(&mut self, cycle: u64, now: Instant) -> Duration {
+        let (start, start_cycle) = *self.start.get_or_insert((now, cycle));
+        let due = start + Duration::from_secs_f64(cycle.saturating_sub(start_cycle) as f64 / self.hz);
+        if now > due + MAX_LAG {
+            // rather than racing to catch up, carry on at the right speed from here
+            self.start = Some((now, cycle));
+        }
+        due.saturating_duration_since(now)
+    }
+    /// Waits until the host's clock has caught up with cycle
+    pub fn pace(&mut self, cycle: u64) {
+        let wait = self.wait(cycle, Instant::now());
+        if wait.is_zero() {
+            return;
+        }
+        let due = Instant::now() + wait;
+        if wait > MIN_SLEEP {
+            std::thread::sleep(wait - MIN_SLEEP);
+        }
+        while Instant::now() < due { /* spin because other sleep options are inconsistent */ }
+    }
+    /// Starts the schedule over (when warp mode is turned off, say)
+    pub fn resync(&mut self) { self.start = None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_syncs(sync: &mut VirtualSync, cycles: u64) -> (u64, u64) {
+        let mut rng = rng::Rng::new(1);
+        let (mut hsyncs, mut vsyncs, mut cycle) = (0u64, 0u64, 0u64);
+        for step in [2u64, 3, 5, 7, 11, 4].iter().cycle() {
+            if cycle >= cycles {
+                break;
+            }
+            let (h, v) = sync.poll(cycle, &mut rng);
+            hsyncs += h as u64;
+            vsyncs += v as u64;
+            cycle += step;
+        }
+        (hsyncs, vsyncs)
+    }
+
+    #[test]
+    fn syncs_follow_the_emulated_clock() {
+        // a minute of emulated time, advancing by instruction-sized steps
+        let minute = CPU_HZ * 60;
+        for jitter in [0, 20] {
+            let (hsyncs, vsyncs) = count_syncs(&mut VirtualSync::new(jitter, VideoStandard::Ntsc), minute);
+            assert!(hsyncs.abs_diff(minute / HSYNC_CYCLES) <= 1);
+            // the TIMER ticks about 60 times a second
+            assert!(vsyncs.abs_diff(minute / (HSYNC_CYCLES * 262)) <= 1);
+            assert!((3590..=3600).contains(&vsyncs));
+        }
+        // and about 50 times a second on a PAL machine, with 312 hsyncs to each vsync
+        let (hsyncs, vsyncs) = count_syncs(&mut VirtualSync::new(0, VideoStandard::Pal), minute);
+        assert_eq!(hsyncs / 312, vsyncs);
+        assert!((3010..=3020).contains(&vsyncs));
+        assert_eq!(VideoStandard::Pal.to_string(), "PAL, 312 lines per field, active lines 120-311");
+    }
+
+    #[test]
+    fn pacer() {
+        let mut pacer = Pacer::new(Some(1.0), true);
+        let start = Instant::now();
+        let ms = |n| Duration::from_millis(n);
+        assert!(pacer.wait(5000, start).is_zero());
+        // a million cycles a second: 1000 cycles later the host should be 1ms later
+        assert_eq!(pacer.wait(6000, start), ms(1));
+        assert_eq!(pacer.wait(16_000, start + ms(4)), ms(7));
+        assert!(pacer.wait(16_000, start + ms(20)).is_zero());
+        // a long stop (in the debugger, say) starts the schedule over rather than racing to catch up
+        assert!(pacer.wait(17_000, start + ms(500)).is_zero());
+        assert_eq!(pacer.wait(27_000, start + ms(505)), ms(5));
+    }
+
+    #[test]
+    fn accuracy() {
+        use Accuracy::*;
+        let presets = [Fast, Balanced, Accurate].map(|a| (a.pace_lines(), a.frame_skip(), a.strict_pias()));
+        assert_eq!(presets, [(false, true, false), (true, true, false), (true, false, true)]);
+    }
+}
@@ -0,0 +1,163 @@
+//! A supervisor that notices when the emulator stops making progress (--watchdog).
+//!
+//! The core thread and the UI thread each beat a heartbeat: the core at every hsync and the UI
+//! every frame. A watchdog thread checks them and if either one hasn't beaten for the given
+//! number of seconds (while it isn't legitimately waiting, e.g. for a debugger command) it
+//! reports what each thread was last doing and which of the shared devices' locks are held, and
+//! then exits with status 1 instead of leaving a frozen window behind.
+//!
+//! Only the core thread takes the PIA, SAM and VDG locks to emulate the hardware (Pia0 also
+//! takes Pia1's lock while it holds its own) and the UI thread only takes the VDG's to render,
+//! so a lock that's held while the core is stuck tells where it's stuck.
+use super::*;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Core::heartbeat's last I/O access when there hasn't been one
+const NO_ACCESS: u32 = u32::MAX;
+
+/// The latest signs of life from a thread
+#[derive(Debug)]
+pub struct Heartbeat {
+    name: &'static str,
+    epoch: Instant,
+    /// milliseconds since epoch at the last beat
+    last: AtomicU64,
+    /// set while the thread is legitimately waiting (so it isn't expected to beat)
+    waiting: AtomicBool,
+    /// the core's program counter at the last beat
+    pc: AtomicU16,
+    /// the core's last I/O access (the address, plus bit 16 for a write)
+    io: AtomicU32,
+}
+impl Heartbeat {
+    pub fn new(name: &'static str) -> Arc<Self> {
+        Arc::new(Heartbeat {
+            name,
+            epoch: Instant::now(),
+            last: AtomicU64::new(0),
+            waiting: AtomicBool::new(false),
+            pc: AtomicU16::new(0),
+            io: AtomicU32::new(NO_ACCESS),
+        })
+    }
+    pub fn beat(&self) { self.last.store(self.epoch.elapsed().as_millis() as u64, Relaxed) }
+    /// Notes where the core is running
+    pub fn beat_at(&self, pc: u16) {
+        self.pc.store(pc, Relaxed);
+        self.beat();
+    }
+    /// Marks the thread as waiting (or not); it's given a fresh start either way
+    pub fn set_waiting(&self, waiting: bool) {
+        self.waiting.store(waiting, Relaxed);
+        self.beat();
+    }
+    /// Notes an I/O access that's about to happen
+    pub fn io(&self, addr: u16, write: bool) { self.io.store(addr as u32 | (write as u32) << 16, Relaxed) }
+    /// Returns the time since the last beat (None while the thread is waiting)
+    fn silence(&self) -> Option<Duration> {
+        let since = self.epoch.elapsed().saturating_sub(Duration::from_millis(self.last.load(Relaxed)));
+        (!self.waiting.load(Relaxed)).then_some(since)
+    }
+    /// Describes the thread's last known activity
+    fn describe(&self, with_core_state: bool) -> String {
+        let mut text = match self.silence() {
+            Some(silence) => format!("{} thread: last sign of life {:.1}s ago", self.name, silence.as_secs_f64()),
+            None => format!("{} thread: waiting (e.g., for a debugger command)", self.name),
+        };
+        if with_core_state {
+            text += &format!(", PC={:04X}", self.pc.load(Relaxed));
+            match self.io.load(Relaxed) {
+                NO_ACCESS => text += ", no I/O accesses",
+                io => {
+                    let kind = if io & 0x10000 != 0 { "write" } else { "read" };
+                    text += &format!(", last I/O access: {} of {:04X}", kind, io as u16);
+                }
+            }
+        }
+        text
+    }
+}
+
+/// Returns the state of a lock
+fn lock_state<T>(lock: &Mutex<T>) -> &'static str {
+    match lock.try_lock() {
+        Ok(_) => "free",
+        Err(TryLockError::WouldBlock) => "held",
+        Err(TryLockError::Poisoned(_)) => "poisoned (a thread panicked while holding it)",
+    }
+}
+
+/// The locks the watchdog reports on
+pub struct Locks {
+    pub pia0: Arc<Mutex<pia::Pia0>>,
+    pub pia1: Arc<Mutex<pia::Pia1>>,
+    pub sam: Arc<Mutex<sam::Sam>>,
+    pub vdg: Arc<Mutex<vdg::Vdg>>,
+}
+impl Locks {
+    fn describe(&self) -> Vec<String> {
+        vec![
+            format!("PIA0 lock: {} (the core's; Pia0 takes PIA1's lock while holding it)", lock_state(&self.pia0)),
+            format!("PIA1 lock: {} (the core's)", lock_state(&self.pia1)),
+            format!("SAM lock: {} (the core's)", lock_state(&self.sam)),
+            format!("VDG lock: {} (the core's or the UI's while rendering)", lock_state(&self.vdg)),
+        ]
+    }
+}
+
+/// Starts the watchdog thread, which exits the process if the core or the UI stops beating for
+/// limit seconds
+pub fn start(limit: f64, core: Arc<Heartbeat>, ui: Arc<Heartbeat>, locks: Locks) {
+    info!("watchdog started: exiting if the core or the UI stops for {} seconds", limit);
+    let limit = Duration::from_secs_f64(limit);
+    core.beat();
+    ui.beat();
+    thread::spawn(move || loop {
+        thread::sleep((limit / 4).min(Duration::from_secs(1)));
+        let stuck = [&core, &ui].into_iter().find(|h| h.silence().is_some_and(|s| s >= limit));
+        if let Some(stuck) = stuck {
+            println!(concat!(red!("WATCHDOG"), ": the {} thread has stopped making progress"), stuck.name);
+            println!("  {}", core.describe(true));
+            println!("  {}", ui.describe(false));
+            for lock in locks.describe() {
+                println!("  {}", lock);
+            }
+            std::process::exit(1);
+        }
+    });
+}
+
+impl Core {
+    /// Notes an I/O access for the watchdog (if there is one)
+    #[inline(always)]
+    pub fn heartbeat_io(&self, addr: u16, write: bool) {
+        if let Some(heartbeat) = self.heartbeat.as_ref().filter(|_| self.is_io_address(addr)) {
+            heartbeat.io(addr, write);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat() {
+        let heartbeat = Heartbeat::new("core");
+        heartbeat.beat_at(0xc0de);
+        assert!(heartbeat.silence().is_some_and(|s| s < Duration::from_secs(1)));
+        assert!(heartbeat.describe(true).ends_with("PC=C0DE, no I/O accesses"));
+        heartbeat.io(0xff22, true);
+        assert!(heartbeat.describe(true).ends_with("last I/O access: write of FF22"));
+        heartbeat.set_waiting(true);
+        assert_eq!(heartbeat.silence(), None);
+        let lock = Mutex::new(0);
+        let guard = lock.lock().unwrap();
+        assert_eq!(lock_state(&lock), "held");
+        drop(guard);
+        assert_eq!(lock_state(&lock), "free");
+    }
+}
@@ -0,0 +1,313 @@
+//! WordPak-style 80-column video card.
+//!
+//! The WordPak was a cartridge for the CoCo 1 and 2 with a 6545 CRTC and 2K of its own video
+//! RAM that drove a separate monitor with 80 columns of text. This is modelled on it rather than
+//! on its exact register map: the CRTC's registers are reached through an address/data pair and
+//! video RAM through a data port at the CRTC's update address (which advances after each access),
+//! all starting at the card's base address (--wordpak-addr, $FF98 by default):
+//! ```text
+//! base+0  write: selects a CRTC register
+//!         read: status (bit 7, update ready, is always set)
+//! base+1  the selected register (R14-R19 can be read back)
+//! base+2  video RAM at the update address (R18/R19)
+//! ```
+//! The screen is R1 columns by R6 rows of 8x12 characters starting at the address in R12/R13,
+//! with the cursor at R14/R15 on the scan lines from R10 to R11 (bits 5 and 6 of R10 select
+//! steady, hidden or blinking at one of two rates). Characters are ASCII; bit 7 shows them in
+//! inverse video. Upper case, digits and punctuation use the VDG's glyphs (so ^ and _ are arrows).
+//!
+//! The picture is shown in its own window (like the card's monitor) or with --wordpak main in
+//! the main window in place of the VDG once a program has set the card up.
+use crate::cart::Cartridge;
+use crate::error::Error;
+use crate::snapshot;
+use crate::devmgr::Screen;
+use crate::vdg::{Color, BLOCK_DIM_X, BLOCK_DIM_Y, FONT_MAP};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const VRAM_SIZE: usize = 0x800;
+const MAX_COLS: usize = 128;
+const MAX_ROWS: usize = 48;
+/// blink periods in milliseconds (1/16 and 1/32 of the field rate)
+const BLINK_FAST: u128 = 267;
+const BLINK_SLOW: u128 = 533;
+
+/// Where the card's picture is shown
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Output {
+    /// in a window of its own
+    Window,
+    /// in the main window (in place of the VDG) once a program has set the card up
+    Main,
+}
+
+/// The CRTC and video RAM (shared by the card and its display)
+pub struct Crtc {
+    regs: [u8; 32],
+    /// the register selected by writing base+0
+    selected: u8,
+    vram: Vec<u8>,
+    /// set once a program writes any of the registers
+    programmed: bool,
+    dirty: bool,
+}
+impl Crtc {
+    pub fn shared() -> Arc<Mutex<Self>> {
+        let mut regs = [0u8; 32];
+        // an 80x24 screen with a blinking block cursor
+        regs[1] = 80;
+        regs[6] = 24;
+        regs[10] = 0x40;
+        regs[11] = BLOCK_DIM_Y as u8 - 1;
+        Arc::new(Mutex::new(Crtc {
+            regs,
+            selected: 0,
+            vram: vec![0x20; VRAM_SIZE],
+            programmed: false,
+            dirty: true,
+        }))
+    }
+    fn pair(&self, high: usize) -> usize { (self.regs[high] as usize & 0x3f) << 8 | self.regs[high + 1] as usize }
+    fn dimensions(&self) -> (usize, usize) {
+        let cols = (self.regs[1] as usize).clamp(1, MAX_COLS);
+        let rows = (self.regs[6] as usize & 0x7f).clamp(1, MAX_ROWS);
+        (cols, rows)
+    }
+    /// Returns the address of the next video RAM access and advances the update address
+    fn next_update_address(&mut self) -> usize {
+        let addr = self.pair(18);
+        let next = (addr + 1) & 0x3fff;
+        self.regs[18] = (next >> 8) as u8;
+        self.regs[19] = next as u8;
+        addr % VRAM_SIZE
+    }
+}
+
+/// The card as the CPU sees it
+pub struct WordPak {
+    pub base: u16,
+    crtc: Arc<Mutex<Crtc>>,
+}
+impl WordPak {
+    pub fn new(base: u16, crtc: Arc<Mutex<Crtc>>) -> Self {
+        info!("WordPak enabled at {:04X}", base);
+        WordPak { base, crtc }
+    }
+    pub fn read(&self, addr: u16) -> u8 {
+        let mut crtc = self.crtc.lock().unwrap();
+        match addr - self.base {
+            0 => 0x80,
+            1 => match crtc.selected {
+                n @ 14..=19 => crtc.regs[n as usize],
+                _ => 0,
+            },
+            _ => {
+                let addr = crtc.next_update_address();
+                crtc.vram[addr]
+            }
+        }
+    }
+    pub fn write(&self, addr: u16, byte: u8) {
+        let mut crtc = self.crtc.lock().unwrap();
+        match addr - self.base {
+            0 => crtc.selected = byte & 0x1f,
+            1 => {
+                let selected = crtc.selected as usize;
+                crtc.regs[selected] = byte;
+                crtc.programmed = true;
+            }
+            _ => {
+                let addr = crtc.next_update_address();
+                crtc.vram[addr] = byte;
+            }
+        }
+        crtc.dirty = true;
+    }
+}
+impl Cartridge for WordPak {
+    fn name(&self) -> String { format!("WordPak at {:04X}", self.base) }
+    fn owns_address(&self, addr: u16) -> bool { addr >= self.base && addr - self.base < 3 }
+    fn read(&self, addr: u16, _cycle: u64) -> Option<u8> { self.owns_address(addr).then(|| WordPak::read(self, addr)) }
+    fn write(&mut self, addr: u16, byte: u8, _cycle: u64) -> Result<bool, Error> {
+        let owned = self.owns_address(addr);
+        if owned {
+            WordPak::write(self, addr, byte);
+        }
+        Ok(owned)
+    }
+    fn tag(&self) -> [u8; 4] { *b"WPAK" }
+    /// The selected register, whether the card has been programmed, the registers and video RAM
+    fn snapshot(&self, _cycle: u64) -> Option<Vec<u8>> {
+        let crtc = self.crtc.lock().unwrap();
+        let mut state = vec![crtc.selected, crtc.programmed as u8];
+        state.extend(crtc.regs);
+        state.extend(&crtc.vram);
+        Some(state)
+    }
+    fn check_snapshot(&self, state: &[u8]) -> Result<(), Error> {
+        snapshot::check_len(&self.tag(), state, 2 + 32 + VRAM_SIZE)
+    }
+    fn restore(&mut self, state: &[u8], _cycle: u64) {
+        let mut crtc = self.crtc.lock().unwrap();
+        (crtc.selected, crtc.programmed) = (state[0], state[1] != 0);
+        crtc.regs.copy_from_slice(&state[2..34]);
+        crtc.vram.copy_from_slice(&state[34..34 + VRAM_SIZE]);
+        crtc.dirty = true;
+    }
+}
+
+/// The card's picture
+pub struct Display {
+    crtc: Arc<Mutex<Crtc>>,
+    output: Output,
+    epoch: Instant,
+    /// whether the cursor was showing when the picture was last rendered
+    cursor_shown: bool,
+}
+impl Display {
+    pub fn new(crtc: Arc<Mutex<Crtc>>, output: Output) -> Self {
+        Display { crtc, output, epoch: Instant::now(), cursor_shown: false }
+    }
+    /// Returns whether the cursor is showing at the moment
+    fn cursor_showing(&self, crtc: &Crtc) -> bool {
+        let millis = self.epoch.elapsed().as_millis();
+        match crtc.regs[10] & 0x60 {
+            0x00 => true,
+            0x20 => false,
+            0x40 => millis % BLINK_FAST < BLINK_FAST / 2,
+            _ => millis % BLINK_SLOW < BLINK_SLOW / 2,
+        }
+    }
+}
+impl Screen for Display {
+    fn active(&self) -> bool { self.output == Output::Window || self.crtc.lock().unwrap().programmed }
+    fn dimensions(&self) -> (usize, usize) {
+        let (cols, rows) = self.crtc.lock().unwrap().dimensions();
+        (cols * BLOCK_DIM_X, rows * BLOCK_DIM_Y)
+    }
+    fn render(&mut self, display: &mut Vec<u32>) -> Option<(usize, usize)> {
+        let mut crtc = self.crtc.lock().unwrap();
+        let cursor_shown = self.cursor_showing(&crtc);
+        if !crtc.dirty && cursor_shown == self.cursor_shown {
+            return None;
+        }
+        crtc.dirty = false;
+        self.cursor_shown = cursor_shown;
+        let (cols, rows) = crtc.dimensions();
+        let width = cols * BLOCK_DIM_X;
+        display.resize(width * rows * BLOCK_DIM_Y, 0);
+        let start = crtc.pair(12);
+        let cursor = crtc.pair(14) % VRAM_SIZE;
+        let cursor_lines = (crtc.regs[10] & 0x1f) as usize..=(crtc.regs[11] & 0x1f) as usize;
+        for row in 0..rows {
+            for col in 0..cols {
+                let addr = (start + row * cols + col) % VRAM_SIZE;
+                let ch = crtc.vram[addr];
+                let glyph = glyph(ch & 0x7f);
+                let mut index = row * BLOCK_DIM_Y * width + col * BLOCK_DIM_X;
+                for (line, &bits) in glyph.iter().enumerate() {
+                    let inverse = ch & 0x80 != 0;
+                    let in_cursor = cursor_shown && addr == cursor && cursor_lines.contains(&line);
+                    let bits = if inverse != in_cursor { !bits } else { bits };
+                    for (i, pixel) in display[index..index + BLOCK_DIM_X].iter_mut().enumerate() {
+                        let color = if bits & (0x80 >> i) != 0 { Color::Buff } else { Color::Black };
+                        *pixel = color.to_rgb();
+                    }
+                    index += width;
+                }
+            }
+        }
+        Some((width, rows * BLOCK_DIM_Y))
+    }
+}
+
+/// Returns the 12 lines of an ASCII character's glyph
+fn glyph(ch: u8) -> &'static [u8] {
+    let start = match ch {
+        // control characters are blank
+        0x00..=0x1f => 0x20 * BLOCK_DIM_Y,
+        0x20..=0x3f => ch as usize * BLOCK_DIM_Y,
+        0x40..=0x5f => (ch - 0x40) as usize * BLOCK_DIM_Y,
+        _ => return &LOWER_CASE[(ch - 0x60) as usize * BLOCK_DIM_Y..][..BLOCK_DIM_Y],
+    };
+    &FONT_MAP[start..start + BLOCK_DIM_Y]
+}
+
+/// glyphs for $60-$7F (which the VDG doesn't have) in the style of the VDG's
+const LOWER_CASE: &[u8] = &[
+    0x00, 0x00, 0x00, 0x10, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // `
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x1C, 0x02, 0x1E, 0x22, 0x1E, 0x00, 0x00, // a
+    0x00, 0x00, 0x00, 0x20, 0x20, 0x3C, 0x22, 0x22, 0x22, 0x3C, 0x00, 0x00, // b
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x1C, 0x20, 0x20, 0x20, 0x1C, 0x00, 0x00, // c
+    0x00, 0x00, 0x00, 0x02, 0x02, 0x1E, 0x22, 0x22, 0x22, 0x1E, 0x00, 0x00, // d
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x1C, 0x22, 0x3E, 0x20, 0x1C, 0x00, 0x00, // e
+    0x00, 0x00, 0x00, 0x0C, 0x10, 0x3C, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00, // f
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x1E, 0x22, 0x22, 0x1E, 0x02, 0x1C, 0x00, // g
+    0x00, 0x00, 0x00, 0x20, 0x20, 0x3C, 0x22, 0x22, 0x22, 0x22, 0x00, 0x00, // h
+    0x00, 0x00, 0x00, 0x08, 0x00, 0x18, 0x08, 0x08, 0x08, 0x1C, 0x00, 0x00, // i
+    0x00, 0x00, 0x00, 0x04, 0x00, 0x0C, 0x04, 0x04, 0x04, 0x24, 0x18, 0x00, // j
+    0x00, 0x00, 0x00, 0x20, 0x20, 0x24, 0x28, 0x30, 0x28, 0x24, 0x00, 0x00, // k
+    0x00, 0x00, 0x00, 0x18, 0x08, 0x08, 0x08, 0x08, 0x08, 0x1C, 0x00, 0x00, // l
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x34, 0x2A, 0x2A, 0x2A, 0x22, 0x00, 0x00, // m
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x3C, 0x22, 0x22, 0x22, 0x22, 0x00, 0x00, // n
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x1C, 0x22, 0x22, 0x22, 0x1C, 0x00, 0x00, // o
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x3C, 0x22, 0x22, 0x3C, 0x20, 0x20, 0x00, // p
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x1E, 0x22, 0x22, 0x1E, 0x02, 0x02, 0x00, // q
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x2C, 0x32, 0x20, 0x20, 0x20, 0x00, 0x00, // r
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x1E, 0x20, 0x1C, 0x02, 0x3C, 0x00, 0x00, // s
+    0x00, 0x00, 0x00, 0x10, 0x10, 0x3C, 0x10, 0x10, 0x12, 0x0C, 0x00, 0x00, // t
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x22, 0x22, 0x26, 0x1A, 0x00, 0x00, // u
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x22, 0x22, 0x14, 0x08, 0x00, 0x00, // v
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x22, 0x2A, 0x2A, 0x14, 0x00, 0x00, // w
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x14, 0x08, 0x14, 0x22, 0x00, 0x00, // x
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x22, 0x22, 0x1E, 0x02, 0x1C, 0x00, // y
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x3E, 0x04, 0x08, 0x10, 0x3E, 0x00, 0x00, // z
+    0x00, 0x00, 0x00, 0x06, 0x08, 0x08, 0x30, 0x08, 0x08, 0x06, 0x00, 0x00, // {
+    0x00, 0x00, 0x00, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x00, 0x00, // |
+    0x00, 0x00, 0x00, 0x30, 0x08, 0x08, 0x06, 0x08, 0x08, 0x30, 0x00, 0x00, // }
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x2A, 0x04, 0x00, 0x00, 0x00, 0x00, // ~
+    0x00, 0x00, 0x00, 0x3E, 0x3E, 0x3E, 0x3E, 0x3E, 0x3E, 0x3E, 0x00, 0x00, // DEL
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screen() {
+        let crtc = Crtc::shared();
+        let card = WordPak::new(0xff98, crtc.clone());
+        // a 4x2 screen starting at $0100 with a steady cursor on its last line at $0105
+        for (reg, value) in [(1, 4), (6, 2), (10, 11), (11, 11), (12, 1), (13, 0), (14, 1), (15, 5), (18, 1), (19, 0)] {
+            card.write(0xff98, reg);
+            card.write(0xff99, value);
+        }
+        for &ch in b"Hi!\x80ab" {
+            card.write(0xff9a, ch);
+        }
+        card.write(0xff98, 19);
+        assert_eq!(card.read(0xff99), 6);
+        let mut display = Display::new(crtc.clone(), Output::Main);
+        assert!(display.active());
+        assert_eq!(display.dimensions(), (32, 24));
+        let mut pixels = Vec::new();
+        assert_eq!(display.render(&mut pixels), Some((32, 24)));
+        assert_eq!(display.render(&mut pixels), None);
+        assert_eq!(pixels.len(), 32 * 24);
+        let on = Color::Buff.to_rgb();
+        let line = |row: usize, line: usize, col: usize| {
+            let start = (row * BLOCK_DIM_Y + line) * 32 + col * BLOCK_DIM_X;
+            pixels[start..start + 8].iter().fold(0u8, |bits, &p| bits << 1 | (p == on) as u8)
+        };
+        // 'i' (line 5 of its glyph), the inverse space and the cursor under 'b'
+        assert_eq!(line(0, 5, 1), 0x18);
+        assert_eq!(line(0, 4, 3), 0xff);
+        assert_eq!((line(1, 10, 1), line(1, 11, 1)), (0x00, 0xff));
+        // reading video RAM back goes through the update address too
+        card.write(0xff98, 19);
+        card.write(0xff99, 1);
+        assert_eq!(card.read(0xff9a), b'i');
+        assert!(crtc.lock().unwrap().dirty);
+    }
+}